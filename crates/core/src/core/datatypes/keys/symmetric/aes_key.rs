@@ -14,7 +14,7 @@ pub struct AES256CTRKey {
 }
 impl SymmetricKey for AES256CTRKey {
     fn get_key_length() -> usize {
-        256
+        32
     }
     
     fn init(raw_key: &[u8]) -> Result<Self, KeyError> where Self: Sized {
@@ -22,7 +22,7 @@ impl AuthEncKey for AES256CTRHMACSHA256Key {}
 
 impl SymmetricKey for AES256CTRHMACSHA256Key {
     fn get_key_length() -> usize {
-        256
+        64
     }
 
     fn init(b: &[u8]) -> Result<Self, KeyError> where Self: Sized {
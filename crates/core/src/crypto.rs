@@ -5,9 +5,9 @@ mod aes;
 pub mod prng;
 mod mac;
 mod kdf;
-mod commitment;
-mod hash;
-mod auth_encryption;
+pub mod commitment;
+pub mod hash;
+pub mod auth_encryption;
 mod elliptic_curves;
 mod utils;
 mod signature;
@@ -16,6 +16,8 @@ pub enum AuthEncError {
     MACError(#[from] MACError),
     #[error("MAC verification failed")]
     MACVerificationFailed,
+    #[error("Cipher text too short")]
+    CipherTextTooShort,
 }
 pub trait AuthEnc<K: AuthEncKey> {
     fn encrypt(m: &[u8], key: &K, prng: &mut impl PRNG) -> Result<Vec<u8>, AuthEncError>; 
@@ -45,6 +47,10 @@ impl AuthEnc<AES256CTRHMACSHA256Key> for AES256CTRHMACSHA256 {
         let mac_key = &key.mac_key;
         let enc_key = &key.enc_key;
 
+        if c.len() < AES256_CTR_IV_BYTE_LENGTH + HMAC_SHA256_OUTPUT_LENGTH {
+            return Err(AuthEncError::CipherTextTooShort)
+        }
+
         let encrypted_bytes_length = c.len() - HMAC_SHA256_OUTPUT_LENGTH;
         let hash = &c[encrypted_bytes_length..];
         let encrypted_bytes = &c[..encrypted_bytes_length];
@@ -41,7 +41,11 @@ impl AuthenticationOverEC {
     }
 
     fn solve(&mut self, challenge: &[u8], prefix: &[u8], prng: &mut impl PRNG) -> Result<Vec<u8>, AuthenticationError> {
-        if self.key_pair.0.public_key_over_ec.curve != self.key_pair.1.private_key_over_ec.curve {
+        Self::solve_with_keys(&self.key_pair.0, &self.key_pair.1, challenge, prefix, prng)
+    }
+
+    pub fn solve_with_keys(pk: &AuthenticationPublicKeyOverEC, sk: &AuthenticationPrivateKeyOverEC, challenge: &[u8], prefix: &[u8], prng: &mut dyn PRNG) -> Result<Vec<u8>, AuthenticationError> {
+        if pk.public_key_over_ec.curve != sk.private_key_over_ec.curve {
             return Err(AuthenticationError::DifferentCurve)
         }
 
@@ -51,8 +55,8 @@ impl AuthenticationOverEC {
         formatted_challenge.extend_from_slice(challenge);
         formatted_challenge.append(&mut suffix.clone());
 
-        let pk_sigma = self.key_pair.0.to_signature_public_key_over_ec();
-        let sk_sigma = self.key_pair.1.to_signature_private_key_over_ec();
+        let pk_sigma = pk.to_signature_public_key_over_ec();
+        let sk_sigma = sk.to_signature_private_key_over_ec();
 
         let mut sigma = SignatureOverEc::sign(&sk_sigma, &formatted_challenge, &pk_sigma, prng)?;
 
@@ -63,15 +67,30 @@ impl AuthenticationOverEC {
         Ok(result)
     }
 
-    // fn check()
+    pub fn check_with_key(pk: &AuthenticationPublicKeyOverEC, challenge: &[u8], prefix: &[u8], response: &[u8]) -> Result<bool, AuthenticationError> {
+        if response.len() < 16 {
+            return Ok(false);
+        }
+
+        let (suffix, sigma) = response.split_at(16);
+        let mut formatted_challenge = Vec::<u8>::new();
+        formatted_challenge.extend_from_slice(prefix);
+        formatted_challenge.extend_from_slice(challenge);
+        formatted_challenge.extend_from_slice(suffix);
+
+        Ok(SignatureOverEc::verify(&pk.to_signature_public_key_over_ec(), &formatted_challenge, sigma)?)
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+
+    use rand::random;
     use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
     use serde::Deserialize;
 
-    use crate::{core::asymmetric::authentication_key::{AuthenticationOverECKeyPair, AuthenticationPrivateKeyOverEC, AuthenticationPublicKeyOverEC}, crypto::{authentication::AuthenticationOverEC, prng::{PRNGHmacSHA256, PRNG}, utils::tests::get_test_vectors}, encoding::{Decoder, Encoder}};
+    use crate::{core::{asymmetric::authentication_key::{AuthenticationOverECKeyPair, AuthenticationPrivateKeyOverEC, AuthenticationPublicKeyOverEC}, edwards_curve::EdwardsCurve}, crypto::{authentication::AuthenticationOverEC, prng::{PRNGHmacSHA256, PRNG}, utils::tests::get_test_vectors}, encoding::{Decoder, Encoder}};
 
     #[derive(Deserialize)]
     struct TestServerAuthentication {
@@ -104,5 +123,21 @@ mod tests {
             assert_eq!(computed_response, response);
         });
     }
+
+    #[test]
+    fn solve_check() {
+        let seed: [u8; 32] = random();
+        let mut prng = PRNGHmacSHA256::init(&seed).unwrap();
+
+        let curve = EdwardsCurve::new_mdc().unwrap();
+        let key_pair = AuthenticationOverEC::generate_key_pair(&mut prng, Arc::new(curve)).unwrap();
+        let compact_pk = AuthenticationPublicKeyOverEC::expand_compact_key(&key_pair.0.public_key_over_ec.get_compact_key().unwrap()).unwrap();
+
+        let challenge = prng.bytes(32).unwrap();
+        let response = AuthenticationOverEC::solve_with_keys(&key_pair.0, &key_pair.1, &challenge, b"authentChallenge", &mut prng).unwrap();
+
+        assert!(AuthenticationOverEC::check_with_key(&compact_pk, &challenge, b"authentChallenge", &response).unwrap());
+        assert!(!AuthenticationOverEC::check_with_key(&compact_pk, &prng.bytes(32).unwrap(), b"authentChallenge", &response).unwrap());
+    }
 }
 
@@ -1,9 +1,15 @@
 use std::{rc::Rc, str::FromStr, sync::Arc};
 
+use num::{bigint::Sign, BigInt};
 use thiserror::Error;
 use url::Url;
 
-use crate::{core::{asymmetric::{authentication_key::{AuthenticationPrivateKeyOverEC, AuthenticationPublicKeyOverEC}, edwards_key::PublicKeyOverEC, kem_key::{KEMPrivateKeyOverEc, KEMPublicKeyOverEC}}, edwards_curve::EdwardsCurve, symmetric::mac_key::HMACWithSHA256Key}, crypto::{authentication::AuthenticationOverEC, kem::KEMOverEC, mac::HMACWithSHA256, prng::{self, PRNG}}};
+use crate::{core::{asymmetric::{authentication_key::{AuthenticationPrivateKeyOverEC, AuthenticationPublicKeyOverEC}, edwards_key::PublicKeyOverEC, kem_key::{KEMPrivateKeyOverEc, KEMPublicKeyOverEC}}, bytes_from_biguint, edwards_curve::EdwardsCurve, symmetric::{auth_enc_key::AES256CTRHMACSHA256Key, mac_key::HMACWithSHA256Key}}, crypto::{auth_encryption::{AuthEnc, AES256CTRHMACSHA256}, authentication::AuthenticationOverEC, kem::KEMOverEC, mac::HMACWithSHA256, prng::{self, PRNG}}, encoding::{BytesArray, Decoder, Encoder}};
+
+pub const AUTHENTICATION_CHALLENGE_PREFIX: &[u8] = b"authentChallenge";
+
+/// Length of the KEM ciphertext prepended to the messages encrypted for an identity
+const KEM_CIPHERTEXT_LENGTH: usize = 32;
 
 #[derive(Error, Debug)]
 pub enum CryptoIdentityError {
@@ -74,6 +80,29 @@ impl CryptographicIdentity {
         return Ok(Self::new(&server_url, pk_a, pk_e));
     }
 
+    pub fn get_server_url(&self) -> &str {
+        &self.server_url
+    }
+
+    /// Checks the response computed by `OwnedCryptographicIdentity::solve_challenge`
+    pub fn check_challenge_response(&self, challenge: &[u8], response: &[u8]) -> Result<bool, CryptoIdentityError> {
+        AuthenticationOverEC::check_with_key(&self.public_key_for_authentication, challenge, AUTHENTICATION_CHALLENGE_PREFIX, response)
+            .map_err(|_| CryptoIdentityError::TechnicalError)
+    }
+
+    /// Checks a signature computed by `OwnedCryptographicIdentity::sign` with the same `prefix`
+    pub fn verify_signature(&self, prefix: &[u8], data: &[u8], signature: &[u8]) -> Result<bool, CryptoIdentityError> {
+        AuthenticationOverEC::check_with_key(&self.public_key_for_authentication, data, prefix, signature)
+            .map_err(|_| CryptoIdentityError::TechnicalError)
+    }
+
+    /// Encrypts a message that only the owner of this identity can decrypt, see `OwnedCryptographicIdentity::decrypt`
+    pub fn encrypt(&self, plaintext: &[u8], prng: &mut impl PRNG) -> Result<Vec<u8>, CryptoIdentityError> {
+        let (mut ciphertext, key) = KEMOverEC::encrypt::<AES256CTRHMACSHA256Key>(&self.public_key_for_kem, prng).map_err(|_| CryptoIdentityError::TechnicalError)?;
+        ciphertext.append(&mut AES256CTRHMACSHA256::encrypt(plaintext, &key, prng).map_err(|_| CryptoIdentityError::TechnicalError)?);
+        Ok(ciphertext)
+    }
+
     pub fn get_identity(&self) -> Vec<u8> {
         let mut identity = Vec::<u8>::new();
         identity.extend_from_slice(self.server_url.as_bytes());
@@ -119,15 +148,130 @@ impl OwnedCryptographicIdentity {
     pub fn get_crypto_identity(&self) -> CryptographicIdentity {
         return CryptographicIdentity::new(&self.server_url, self.public_key_for_authentication.clone(), self.public_key_for_kem.clone());
     }
+
+    pub fn get_server_url(&self) -> &str {
+        &self.server_url
+    }
+
+    /// Answers a server authentication challenge, the returned bytes are sent back to the server to get a session token
+    pub fn solve_challenge(&self, challenge: &[u8], prng: &mut dyn PRNG) -> Result<Vec<u8>, CryptoIdentityError> {
+        AuthenticationOverEC::solve_with_keys(&self.public_key_for_authentication, &self.private_key_for_authentication, challenge, AUTHENTICATION_CHALLENGE_PREFIX, prng)
+            .map_err(|_| CryptoIdentityError::TechnicalError)
+    }
+
+    /// Signs `data` with the authentication key, `prefix` separates the purposes so that a signature is never valid for another one
+    pub fn sign(&self, prefix: &[u8], data: &[u8], prng: &mut dyn PRNG) -> Result<Vec<u8>, CryptoIdentityError> {
+        AuthenticationOverEC::solve_with_keys(&self.public_key_for_authentication, &self.private_key_for_authentication, data, prefix, prng)
+            .map_err(|_| CryptoIdentityError::TechnicalError)
+    }
+
+    pub fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, CryptoIdentityError> {
+        if ciphertext.len() < KEM_CIPHERTEXT_LENGTH {
+            return Err(CryptoIdentityError::TechnicalError);
+        }
+
+        let key = KEMOverEC::decrypt::<AES256CTRHMACSHA256Key>(&ciphertext[..KEM_CIPHERTEXT_LENGTH], &self.private_key_for_kem).map_err(|_| CryptoIdentityError::TechnicalError)?;
+        AES256CTRHMACSHA256::decrypt(&ciphertext[KEM_CIPHERTEXT_LENGTH..], &key).map_err(|_| CryptoIdentityError::TechnicalError)
+    }
+
+    /// Serializes the whole identity, private keys included, so that it can be persisted
+    pub fn serialize(&self) -> Result<Vec<u8>, CryptoIdentityError> {
+        let sk_a = &self.private_key_for_authentication.private_key_over_ec.scalar;
+        let sk_e = &self.private_key_for_kem.private_key_over_ec.scalar;
+        let mac_key = self.secret_mac_key.cryptographic_key_details.get_key("mackey").map_err(|_| CryptoIdentityError::TechnicalError)?;
+
+        let encoded_values: Vec<BytesArray> = vec![
+            self.server_url.encode(),
+            self.public_key_for_authentication.public_key_over_ec.get_compact_key().map_err(|_| CryptoIdentityError::TechnicalError)?.encode(),
+            bytes_from_biguint(&sk_a.to_biguint().ok_or(CryptoIdentityError::TechnicalError)?, 32).encode(),
+            self.public_key_for_kem.public_key_over_ec.get_compact_key().map_err(|_| CryptoIdentityError::TechnicalError)?.encode(),
+            bytes_from_biguint(&sk_e.to_biguint().ok_or(CryptoIdentityError::TechnicalError)?, 32).encode(),
+            mac_key.encode(),
+        ].into_iter().collect::<Result<Vec<BytesArray>, _>>().map_err(|_| CryptoIdentityError::TechnicalError)?;
+
+        encoded_values.encode().map_err(|_| CryptoIdentityError::TechnicalError)
+    }
+
+    pub fn deserialize(serialized: &[u8]) -> Result<Self, CryptoIdentityError> {
+        let encoded_values = Vec::<BytesArray>::decode(serialized).map_err(|_| CryptoIdentityError::TechnicalError)?;
+        if encoded_values.len() != 6 {
+            return Err(CryptoIdentityError::TechnicalError);
+        }
+
+        let decoded_values = encoded_values.iter()
+            .map(|encoded_value| BytesArray::decode(encoded_value))
+            .collect::<Result<Vec<BytesArray>, _>>()
+            .map_err(|_| CryptoIdentityError::TechnicalError)?;
+
+        let server_url = String::from_utf8(decoded_values[0].clone()).map_err(|_| CryptoIdentityError::TechnicalError)?;
+
+        let pk_a = AuthenticationPublicKeyOverEC::expand_compact_key(&decoded_values[1]).map_err(|_| CryptoIdentityError::TechnicalError)?;
+        let sk_a = AuthenticationPrivateKeyOverEC::new(Arc::clone(&pk_a.public_key_over_ec.curve), BigInt::from_bytes_be(Sign::Plus, &decoded_values[2]))
+            .map_err(|_| CryptoIdentityError::TechnicalError)?;
+
+        let pk_e = KEMPublicKeyOverEC::expand_compact_key(&decoded_values[3]).map_err(|_| CryptoIdentityError::TechnicalError)?;
+        let sk_e = KEMPrivateKeyOverEc::init(Arc::clone(&pk_e.public_key_over_ec.curve), BigInt::from_bytes_be(Sign::Plus, &decoded_values[4]))
+            .map_err(|_| CryptoIdentityError::TechnicalError)?;
+
+        let key = HMACWithSHA256Key::init(&decoded_values[5]).map_err(|_| CryptoIdentityError::TechnicalError)?;
+
+        Ok(Self::new(&server_url, pk_a, sk_a, pk_e, sk_e, key))
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::CryptographicIdentity;
+    use rand::random;
+
+    use crate::crypto::prng::{PRNGHmacSHA256, PRNG};
+
+    use super::{CryptographicIdentity, OwnedCryptographicIdentity};
 
     #[test]
     fn from_raw() {
         let raw_identity: Vec<u8> = vec![104, 116, 116, 112, 115, 58, 47, 47, 115, 101, 114, 118, 101, 114, 46, 111, 108, 118, 105, 100, 46, 105, 111, 0, 0, 128, 178, 251, 83, 58, 169, 15, 14, 109, 14, 121, 83, 239, 187, 68, 154, 87, 165, 201, 202, 125, 25, 239, 195, 157, 100, 188, 34, 68, 138, 139, 150, 1, 26, 192, 145, 222, 142, 29, 88, 17, 30, 6, 129, 235, 60, 12, 180, 149, 198, 201, 98, 26, 75, 127, 0, 83, 41, 209, 105, 58, 75, 68, 39, 9];
         let test = CryptographicIdentity::from_raw(&raw_identity).unwrap();
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn serialize_deserialize() {
+        let seed: [u8; 32] = random();
+        let mut prng = PRNGHmacSHA256::init(&seed).unwrap();
+
+        let owned_identity = OwnedCryptographicIdentity::generate_owned_cryptographic_identity("https://server.olvid.io", &mut prng).unwrap();
+        let serialized = owned_identity.serialize().unwrap();
+        let deserialized = OwnedCryptographicIdentity::deserialize(&serialized).unwrap();
+
+        assert_eq!(owned_identity.get_server_url(), deserialized.get_server_url());
+        assert_eq!(owned_identity.get_crypto_identity().get_identity(), deserialized.get_crypto_identity().get_identity());
+        assert_eq!(serialized, deserialized.serialize().unwrap());
+    }
+
+    #[test]
+    fn encrypt_decrypt() {
+        let seed: [u8; 32] = random();
+        let mut prng = PRNGHmacSHA256::init(&seed).unwrap();
+
+        let owned_identity = OwnedCryptographicIdentity::generate_owned_cryptographic_identity("https://server.olvid.io", &mut prng).unwrap();
+        let other_identity = OwnedCryptographicIdentity::generate_owned_cryptographic_identity("https://server.olvid.io", &mut prng).unwrap();
+
+        let ciphertext = owned_identity.get_crypto_identity().encrypt(b"hello", &mut prng).unwrap();
+        assert_eq!(owned_identity.decrypt(&ciphertext).unwrap(), b"hello");
+        assert!(other_identity.decrypt(&ciphertext).is_err());
+        assert!(owned_identity.decrypt(&ciphertext[..16]).is_err());
+    }
+
+    #[test]
+    fn sign_verify() {
+        let seed: [u8; 32] = random();
+        let mut prng = PRNGHmacSHA256::init(&seed).unwrap();
+
+        let owned_identity = OwnedCryptographicIdentity::generate_owned_cryptographic_identity("https://server.olvid.io", &mut prng).unwrap();
+        let signature = owned_identity.sign(b"prefix", b"data", &mut prng).unwrap();
+
+        let identity = owned_identity.get_crypto_identity();
+        assert!(identity.verify_signature(b"prefix", b"data", &signature).unwrap());
+        assert!(!identity.verify_signature(b"other", b"data", &signature).unwrap());
+        assert!(!identity.verify_signature(b"prefix", b"other", &signature).unwrap());
+    }
+}
@@ -66,7 +66,7 @@ impl SignatureOverEc {
     //     Ok(sigma)
     // }
 
-    pub fn sign(sk: &SignaturePrivateKeyOverEc, m: &[u8], pk: &SignaturePublicKeyOverEC, prng: &mut impl PRNG) -> Result<Vec<u8>, SignatureError> {
+    pub fn sign(sk: &SignaturePrivateKeyOverEc, m: &[u8], pk: &SignaturePublicKeyOverEC, prng: &mut dyn PRNG) -> Result<Vec<u8>, SignatureError> {
         if &sk.private_key_over_ec.curve != &pk.public_key_over_ec.curve {
             return Err(SignatureError::DifferentCurve)
         }
@@ -108,44 +108,40 @@ impl SignatureOverEc {
 
     pub fn verify(pk: &SignaturePublicKeyOverEC, m: &[u8], sigma: &[u8]) -> Result<bool, SignatureError> {
         let public_curve = &pk.public_key_over_ec.curve;
-        let p_len = public_curve.p.bits().to_usize().ok_or(SignatureError::Technical)?;
+        let p_len = ((public_curve.p.bits() + 7) / 8).to_usize().ok_or(SignatureError::Technical)?;
 
         if sigma.len() != 32 + p_len {
             return Ok(false);
         }
 
-        let encoded_sigma = BytesArray::parse(sigma)?;
-        let h = &encoded_sigma.content[0..32];
-        let z = &encoded_sigma.content[32..];
+        let h = &sigma[0..32];
+        let z = &sigma[32..];
 
-        let e = BigUint::decode(h)?;
-        let y = BigUint::decode(z)?;
+        let e = BigInt::from_bytes_be(Sign::Plus, h);
+        let y = BigInt::from_bytes_be(Sign::Plus, z);
 
         let p: (Option<&BigInt>, &BigInt) = match &pk.public_key_over_ec.point.is_none() {
             true =>  (None, &pk.public_key_over_ec.y),
             false => (Some(&pk.public_key_over_ec.point.as_ref().unwrap().x), &pk.public_key_over_ec.point.as_ref().unwrap().y)
         };
         
-        let (a1, a2) = public_curve.mul_add(&BigInt::from_biguint(Sign::Plus, y), &public_curve.G, &BigInt::from_biguint(Sign::Plus, e), p)?;
+        // With only the y coordinate of the public key, y.G + e.A has two candidates, one of them must match
+        let (a1, a2) = public_curve.mul_add(&y, &public_curve.G, &e, p)?;
 
-        let ay = pk.public_key_over_ec.y.to_biguint().ok_or(SignatureError::Technical)?.encode()?;
-        let mut a1_y = a1.y.to_biguint().ok_or(SignatureError::Technical)?.encode()?;
-        let mut a2_y = a2.y.to_biguint().ok_or(SignatureError::Technical)?.encode()?;
+        let ay = bytes_from_biguint(&pk.public_key_over_ec.y.to_biguint().ok_or(SignatureError::Technical)?, p_len);
 
-        let mut data1 = Vec::<u8>::new();
-        data1.append(&mut a1_y);
-        data1.append(&mut ay.clone());
-        data1.append(&mut m.to_vec());
+        for candidate in [a1, a2] {
+            let mut data = Vec::<u8>::new();
+            data.append(&mut bytes_from_biguint(&candidate.y.to_biguint().ok_or(SignatureError::Technical)?, p_len));
+            data.extend_from_slice(&ay);
+            data.extend_from_slice(m);
 
-        let mut data2 = Vec::<u8>::new();
-        data2.append(&mut a2_y);
-        data2.append(&mut ay.clone());
-        data2.append(&mut m.to_vec());
+            if SHA256::digest(&data) == h {
+                return Ok(true);
+            }
+        }
 
-        let h1 = SHA256::digest(&data1);
-        let h2 = SHA256::digest(&data2);
-        
-        Ok(h == h1 || h == h2)
+        Ok(false)
     }
 
     pub fn generate_key_pair_mdc(prng: &mut impl PRNG) -> Result<(SignaturePublicKeyOverEC, SignaturePrivateKeyOverEc), KeyError> {
@@ -162,7 +158,25 @@ impl SignatureOverEc {
 
 #[cfg(test)]
 mod tests {
-    use crate::crypto::utils::tests::get_test_vectors;
+    use rand::random;
+
+    use crate::crypto::{prng::{PRNGHmacSHA256, PRNG}, utils::tests::get_test_vectors};
+
+    use super::SignatureOverEc;
+
+    #[test]
+    fn sign_verify() {
+        let seed: [u8; 32] = random();
+        let mut prng = PRNGHmacSHA256::init(&seed).unwrap();
+
+        for (pk, sk) in [SignatureOverEc::generate_key_pair_mdc(&mut prng).unwrap(), SignatureOverEc::generate_key_pair_curve25519(&mut prng).unwrap()] {
+            let message = prng.bytes(50).unwrap();
+            let sigma = SignatureOverEc::sign(&sk, &message, &pk, &mut prng).unwrap();
+
+            assert!(SignatureOverEc::verify(&pk, &message, &sigma).unwrap());
+            assert!(!SignatureOverEc::verify(&pk, &prng.bytes(50).unwrap(), &sigma).unwrap());
+        }
+    }
 
     // #[derive(Deserialize)]
     // struct TestServerAuthentication {
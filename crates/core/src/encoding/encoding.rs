@@ -113,6 +113,10 @@ pub trait Encoder {
         let inner_data = encoded.content;
         let mut encoded_values: Vec<BytesArray> = Vec::new();
 
+        if inner_data.is_empty() {
+            return Ok((encoded.identifier, encoded_values));
+        }
+
         let (mut encoded_value, mut remaining) = Self::extract_first_encoded_value(&inner_data)?;
         encoded_values.push(encoded_value);
 
@@ -204,4 +208,13 @@ mod tests {
         assert_eq!(encoded_values.get(0).unwrap(), &encoded_val1);
         assert_eq!(encoded_values.get(1).unwrap(), &encoded_val2);
     }
+
+    #[test]
+    fn unpack_empty() {
+        let input = vec![0x03, 0x00, 0x00, 0x00, 0x00];
+
+        let (byteId, encoded_values) = String::unpack(&input).unwrap();
+        assert_eq!(byteId, 0x03);
+        assert!(encoded_values.is_empty());
+    }
 }
\ No newline at end of file
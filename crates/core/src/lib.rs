@@ -2,6 +2,7 @@
 
 mod core;
 pub mod crypto;
-mod encoding;
+pub mod encoding;
 
-pub use crypto::identity::*;
\ No newline at end of file
+pub use crypto::identity::*;
+pub use crate::core::symmetric::{auth_enc_key::AES256CTRHMACSHA256Key, symmetric_key::SymmetricKey};
\ No newline at end of file
@@ -0,0 +1,2 @@
+ALTER TABLE identities ADD COLUMN private_identity BLOB;
+ALTER TABLE identities ADD COLUMN current_device_uid BLOB;
@@ -0,0 +1,31 @@
+CREATE TABLE IF NOT EXISTS outbox_messages
+(
+    id INTEGER PRIMARY KEY NOT NULL,
+    from_identity BLOB NOT NULL,
+    to_identity BLOB NOT NULL,
+    device_uids BLOB NOT NULL,
+    wrapped_key BLOB NOT NULL,
+    encrypted_content BLOB NOT NULL,
+    is_application_message BOOLEAN NOT NULL,
+    delivery_state INTEGER NOT NULL,
+    attempt_count INTEGER NOT NULL,
+    next_attempt_timestamp INTEGER NOT NULL,
+    message_uid BLOB,
+    creation_timestamp INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS outbox_messages_delivery_state_index ON outbox_messages (delivery_state, next_attempt_timestamp);
+
+CREATE TABLE IF NOT EXISTS inbox_messages
+(
+    id INTEGER PRIMARY KEY NOT NULL,
+    owned_identity BLOB NOT NULL,
+    message_uid BLOB NOT NULL,
+    server_timestamp INTEGER NOT NULL,
+    wrapped_key BLOB NOT NULL,
+    encrypted_content BLOB NOT NULL,
+    attachment_count INTEGER NOT NULL,
+    processed BOOLEAN NOT NULL,
+    reception_timestamp INTEGER NOT NULL,
+    UNIQUE (owned_identity, message_uid)
+);
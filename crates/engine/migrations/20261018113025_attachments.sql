@@ -0,0 +1,25 @@
+ALTER TABLE outbox_messages ADD COLUMN attachment_chunk_counts BLOB;
+
+CREATE TABLE IF NOT EXISTS attachments
+(
+    id INTEGER PRIMARY KEY NOT NULL,
+    owned_identity BLOB NOT NULL,
+    direction INTEGER NOT NULL,
+    outbox_message_id INTEGER,
+    message_uid BLOB,
+    attachment_number INTEGER NOT NULL,
+    key BLOB NOT NULL,
+    size INTEGER NOT NULL,
+    chunk_length INTEGER NOT NULL,
+    chunk_count INTEGER NOT NULL,
+    transferred_chunk_count INTEGER NOT NULL,
+    upload_urls TEXT,
+    file_path TEXT NOT NULL,
+    file_name TEXT NOT NULL,
+    mime_type TEXT NOT NULL,
+    status INTEGER NOT NULL,
+    attempt_count INTEGER NOT NULL,
+    next_attempt_timestamp INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS attachments_status_index ON attachments (status, next_attempt_timestamp);
@@ -0,0 +1,47 @@
+CREATE TABLE IF NOT EXISTS contacts
+(
+    id INTEGER PRIMARY KEY NOT NULL,
+    owned_identity BLOB NOT NULL,
+    contact_identity BLOB NOT NULL,
+    identity_details TEXT NOT NULL,
+    display_name TEXT NOT NULL,
+    creation_timestamp INTEGER NOT NULL,
+    UNIQUE (owned_identity, contact_identity)
+);
+
+CREATE TABLE IF NOT EXISTS discussions
+(
+    id INTEGER PRIMARY KEY NOT NULL,
+    owned_identity BLOB NOT NULL,
+    discussion_type INTEGER NOT NULL,
+    contact_identity BLOB,
+    group_identifier BLOB,
+    title TEXT NOT NULL,
+    sender_thread_identifier BLOB NOT NULL,
+    last_message_timestamp INTEGER NOT NULL,
+    creation_timestamp INTEGER NOT NULL,
+    UNIQUE (owned_identity, contact_identity),
+    UNIQUE (owned_identity, group_identifier)
+);
+
+CREATE TABLE IF NOT EXISTS messages
+(
+    id INTEGER PRIMARY KEY NOT NULL,
+    discussion_id INTEGER NOT NULL,
+    direction INTEGER NOT NULL,
+    sender_identity BLOB NOT NULL,
+    sender_thread_identifier BLOB NOT NULL,
+    sender_sequence_number INTEGER NOT NULL,
+    body TEXT,
+    server_timestamp INTEGER,
+    local_timestamp INTEGER NOT NULL,
+    sort_index REAL NOT NULL,
+    reply_to_message_id INTEGER,
+    status INTEGER NOT NULL,
+    edited BOOLEAN NOT NULL,
+    outbox_message_id INTEGER
+);
+
+CREATE INDEX IF NOT EXISTS messages_discussion_sort_index ON messages (discussion_id, sort_index);
+CREATE UNIQUE INDEX IF NOT EXISTS messages_sender_index ON messages (discussion_id, sender_identity, sender_thread_identifier, sender_sequence_number);
+CREATE INDEX IF NOT EXISTS messages_outbox_message_index ON messages (outbox_message_id);
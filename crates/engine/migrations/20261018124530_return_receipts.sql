@@ -0,0 +1,18 @@
+ALTER TABLE messages ADD COLUMN return_receipt_nonce BLOB;
+ALTER TABLE messages ADD COLUMN return_receipt_key BLOB;
+
+CREATE INDEX IF NOT EXISTS messages_return_receipt_nonce_index ON messages (return_receipt_nonce);
+
+ALTER TABLE identities ADD COLUMN pref_send_read_receipt BOOLEAN NOT NULL DEFAULT FALSE;
+-- NULL when the discussion follows the setting of its owned identity
+ALTER TABLE discussions ADD COLUMN pref_send_read_receipt BOOLEAN;
+
+CREATE TABLE IF NOT EXISTS outbox_return_receipts
+(
+    id INTEGER PRIMARY KEY NOT NULL,
+    to_identity BLOB NOT NULL,
+    nonce BLOB NOT NULL,
+    encrypted_payload BLOB NOT NULL,
+    attempt_count INTEGER NOT NULL,
+    next_attempt_timestamp INTEGER NOT NULL
+);
@@ -0,0 +1,23 @@
+-- Shared settings of a discussion, the one with the highest version wins
+ALTER TABLE discussions ADD COLUMN settings_version INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE discussions ADD COLUMN settings_read_once BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE discussions ADD COLUMN settings_visibility_duration INTEGER;
+ALTER TABLE discussions ADD COLUMN settings_existence_duration INTEGER;
+
+-- Server uid of inbound messages, their attachments are stored under it
+ALTER TABLE messages ADD COLUMN message_uid BLOB;
+ALTER TABLE messages ADD COLUMN read_once BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE messages ADD COLUMN visibility_duration INTEGER;
+ALTER TABLE messages ADD COLUMN existence_duration INTEGER;
+ALTER TABLE messages ADD COLUMN wiped BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE TABLE IF NOT EXISTS message_expirations
+(
+    id INTEGER PRIMARY KEY NOT NULL,
+    message_id INTEGER NOT NULL,
+    expiration_timestamp INTEGER NOT NULL,
+    wipe_only BOOLEAN NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS message_expirations_timestamp_index ON message_expirations (expiration_timestamp);
+CREATE INDEX IF NOT EXISTS message_expirations_message_index ON message_expirations (message_id);
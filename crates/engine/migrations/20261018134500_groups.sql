@@ -0,0 +1,23 @@
+-- Groups v2, the group blob stored on the server is the reference, these tables mirror its last version
+CREATE TABLE IF NOT EXISTS groups_v2
+(
+    id INTEGER PRIMARY KEY NOT NULL,
+    owned_identity BLOB NOT NULL,
+    group_uid BLOB NOT NULL,
+    name TEXT NOT NULL,
+    version INTEGER NOT NULL,
+    blob_key BLOB NOT NULL,
+    status INTEGER NOT NULL,
+    creation_timestamp INTEGER NOT NULL,
+    UNIQUE (owned_identity, group_uid)
+);
+
+CREATE TABLE IF NOT EXISTS group_v2_members
+(
+    id INTEGER PRIMARY KEY NOT NULL,
+    group_id INTEGER NOT NULL,
+    member_identity BLOB NOT NULL,
+    is_admin BOOLEAN NOT NULL,
+    is_pending BOOLEAN NOT NULL,
+    UNIQUE (group_id, member_identity)
+);
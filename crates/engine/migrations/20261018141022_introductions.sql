@@ -0,0 +1,17 @@
+-- How much the owned identity trusts a contact, only raised by introductions and verifications
+ALTER TABLE contacts ADD COLUMN trust_level INTEGER NOT NULL DEFAULT 0;
+
+-- Introductions received from contacts, both introduced identities must accept before becoming contacts
+CREATE TABLE IF NOT EXISTS introductions
+(
+    id INTEGER PRIMARY KEY NOT NULL,
+    owned_identity BLOB NOT NULL,
+    introduction_uid BLOB NOT NULL,
+    introducer_identity BLOB NOT NULL,
+    contact_identity BLOB NOT NULL,
+    identity_details TEXT NOT NULL,
+    status INTEGER NOT NULL,
+    contact_signature BLOB,
+    creation_timestamp INTEGER NOT NULL,
+    UNIQUE (owned_identity, introduction_uid)
+);
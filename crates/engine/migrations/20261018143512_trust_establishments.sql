@@ -0,0 +1,18 @@
+-- Trust establishments with short authentication strings, on the inviter and on the invited side
+CREATE TABLE IF NOT EXISTS trust_establishments
+(
+    id INTEGER PRIMARY KEY NOT NULL,
+    owned_identity BLOB NOT NULL,
+    protocol_uid BLOB NOT NULL,
+    contact_identity BLOB NOT NULL,
+    identity_details TEXT,
+    is_inviter INTEGER NOT NULL,
+    status INTEGER NOT NULL,
+    own_seed BLOB,
+    decommitment BLOB,
+    contact_commitment BLOB,
+    contact_seed BLOB,
+    contact_confirmed INTEGER NOT NULL DEFAULT 0,
+    creation_timestamp INTEGER NOT NULL,
+    UNIQUE (owned_identity, protocol_uid)
+);
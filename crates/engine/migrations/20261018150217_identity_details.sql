@@ -0,0 +1,10 @@
+-- Details edited by the owned identity but not sent to its contacts yet, and the version of the published ones
+ALTER TABLE identities ADD COLUMN unpublished_identity_details TEXT;
+ALTER TABLE identities ADD COLUMN unpublished_photo_url TEXT;
+ALTER TABLE identities ADD COLUMN details_version INTEGER NOT NULL DEFAULT 0;
+
+-- Details published by a contact, kept apart from the trusted ones until the owned identity accepts them
+ALTER TABLE contacts ADD COLUMN photo_url TEXT;
+ALTER TABLE contacts ADD COLUMN published_identity_details TEXT;
+ALTER TABLE contacts ADD COLUMN published_photo_url TEXT;
+ALTER TABLE contacts ADD COLUMN published_details_version INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,12 @@
+-- How the display names of owned identities and contacts are formatted from their details, a single row
+CREATE TABLE IF NOT EXISTS display_name_format
+(
+    id INTEGER PRIMARY KEY NOT NULL CHECK (id = 0),
+    name_order INTEGER NOT NULL,
+    show_company_and_position BOOLEAN NOT NULL
+);
+
+-- Nickname chosen by the owned identity, and the key contact lists are sorted by
+ALTER TABLE contacts ADD COLUMN custom_display_name TEXT;
+ALTER TABLE contacts ADD COLUMN sort_key TEXT NOT NULL DEFAULT '';
+UPDATE contacts SET sort_key = lower(display_name);
@@ -0,0 +1,2 @@
+-- The current profile is the visible one selected last
+ALTER TABLE identities ADD COLUMN last_selection_timestamp INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,7 @@
+-- Seed of the key backups are encrypted with, a single row
+CREATE TABLE IF NOT EXISTS backup_key
+(
+    id INTEGER PRIMARY KEY NOT NULL CHECK (id = 0),
+    seed BLOB NOT NULL,
+    creation_timestamp INTEGER NOT NULL
+);
@@ -0,0 +1,9 @@
+-- Other devices of the owned identities, the current one is `identities.current_device_uid`
+CREATE TABLE IF NOT EXISTS owned_devices
+(
+    id INTEGER PRIMARY KEY NOT NULL,
+    owned_identity BLOB NOT NULL,
+    device_uid BLOB NOT NULL,
+    creation_timestamp INTEGER NOT NULL,
+    UNIQUE (owned_identity, device_uid)
+);
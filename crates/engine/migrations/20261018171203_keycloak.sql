@@ -0,0 +1,17 @@
+-- Keycloak server managing an owned identity, at most one per owned identity
+CREATE TABLE IF NOT EXISTS keycloak_servers
+(
+    owned_identity BLOB PRIMARY KEY NOT NULL,
+    server_url TEXT NOT NULL,
+    client_id TEXT NOT NULL,
+    client_secret TEXT,
+    jwks TEXT NOT NULL,
+    signature_key TEXT,
+    serialized_auth_state TEXT,
+    keycloak_user_id TEXT,
+    transfer_restricted BOOLEAN NOT NULL,
+    latest_revocation_list_timestamp INTEGER NOT NULL
+);
+
+-- Contacts whose details are signed by the keycloak server of their owned identity
+ALTER TABLE contacts ADD COLUMN keycloak_managed BOOLEAN NOT NULL DEFAULT FALSE;
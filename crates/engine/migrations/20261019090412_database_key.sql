@@ -0,0 +1,8 @@
+-- Key the sensitive columns are encrypted with, wrapped by a key derived from a passphrase or a key file, a single row
+CREATE TABLE IF NOT EXISTS database_key
+(
+    id INTEGER PRIMARY KEY NOT NULL CHECK (id = 0),
+    wrapped_key BLOB NOT NULL,
+    kdf_salt BLOB NOT NULL,
+    kdf_iterations INTEGER NOT NULL
+);
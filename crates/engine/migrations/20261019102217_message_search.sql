@@ -0,0 +1,3 @@
+-- Words of the body and of the attachment file names of each message, the rowid is the id of the message.
+-- The words are normalized by the engine, or replaced by keyed hashes when the sensitive columns are encrypted.
+CREATE VIRTUAL TABLE IF NOT EXISTS message_search USING fts5(body, file_names, tokenize = 'ascii');
@@ -0,0 +1,43 @@
+-- Time of the current body of an edited message, edits older than it only go to the history
+ALTER TABLE messages ADD COLUMN edit_timestamp INTEGER;
+-- Deleted for everyone by its sender or a group administrator, the message stays as a tombstone
+ALTER TABLE messages ADD COLUMN remotely_deleted BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Previous bodies of the edited messages
+CREATE TABLE IF NOT EXISTS message_edits
+(
+    id INTEGER PRIMARY KEY NOT NULL,
+    message_id INTEGER NOT NULL,
+    body TEXT NOT NULL,
+    timestamp INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS message_edits_message_index ON message_edits (message_id, timestamp);
+
+-- Last reaction of each participant to a message, a removed reaction is kept without emoji so that older ones
+-- arriving late don't bring it back
+CREATE TABLE IF NOT EXISTS message_reactions
+(
+    id INTEGER PRIMARY KEY NOT NULL,
+    message_id INTEGER NOT NULL,
+    reactor_identity BLOB NOT NULL,
+    reaction TEXT,
+    timestamp INTEGER NOT NULL,
+    UNIQUE (message_id, reactor_identity)
+);
+
+-- Edits, deletions and reactions received before the message they apply to
+CREATE TABLE IF NOT EXISTS pending_message_operations
+(
+    id INTEGER PRIMARY KEY NOT NULL,
+    discussion_id INTEGER NOT NULL,
+    operation_type INTEGER NOT NULL,
+    author_identity BLOB NOT NULL,
+    sender_identity BLOB NOT NULL,
+    sender_thread_identifier BLOB NOT NULL,
+    sender_sequence_number INTEGER NOT NULL,
+    body TEXT,
+    server_timestamp INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS pending_message_operations_reference_index ON pending_message_operations (discussion_id, sender_identity, sender_thread_identifier, sender_sequence_number);
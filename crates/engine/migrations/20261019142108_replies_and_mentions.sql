@@ -0,0 +1,20 @@
+-- Message a reply references, kept to link the reply once a late original arrives
+ALTER TABLE messages ADD COLUMN reply_to_sender_identity BLOB;
+ALTER TABLE messages ADD COLUMN reply_to_sender_thread_identifier BLOB;
+ALTER TABLE messages ADD COLUMN reply_to_sender_sequence_number INTEGER;
+-- The message mentions the owned identity or replies to one of its messages
+ALTER TABLE messages ADD COLUMN mentioned BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE INDEX IF NOT EXISTS messages_reply_to_index ON messages (discussion_id, reply_to_sender_identity, reply_to_sender_thread_identifier, reply_to_sender_sequence_number);
+
+-- Identities mentioned in the body of the messages, at byte ranges
+CREATE TABLE IF NOT EXISTS message_mentions
+(
+    id INTEGER PRIMARY KEY NOT NULL,
+    message_id INTEGER NOT NULL,
+    mentioned_identity BLOB NOT NULL,
+    range_start INTEGER NOT NULL,
+    range_end INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS message_mentions_message_index ON message_mentions (message_id);
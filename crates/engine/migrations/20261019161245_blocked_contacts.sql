@@ -0,0 +1,9 @@
+-- Identities an owned identity blocked, kept when the contact is deleted so that it can't come back unnoticed
+CREATE TABLE IF NOT EXISTS blocked_contacts
+(
+    id INTEGER PRIMARY KEY NOT NULL,
+    owned_identity BLOB NOT NULL,
+    contact_identity BLOB NOT NULL,
+    blocked_timestamp INTEGER NOT NULL,
+    UNIQUE (owned_identity, contact_identity)
+);
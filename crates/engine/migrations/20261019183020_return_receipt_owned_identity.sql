@@ -0,0 +1,14 @@
+-- Receipts are deleted with the owned identity that queued them
+ALTER TABLE outbox_return_receipts ADD COLUMN owned_identity BLOB NOT NULL DEFAULT x'';
+
+-- Receipts already queued belong to the owned identity that received the message they acknowledge
+UPDATE outbox_return_receipts SET owned_identity = COALESCE(
+    (
+        SELECT discussions.owned_identity FROM messages
+        INNER JOIN discussions ON discussions.id = messages.discussion_id
+        WHERE messages.return_receipt_nonce = outbox_return_receipts.nonce AND messages.direction = 1
+        LIMIT 1
+    ),
+    x''
+);
+DELETE FROM outbox_return_receipts WHERE owned_identity = x'';
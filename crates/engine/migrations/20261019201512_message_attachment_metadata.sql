@@ -0,0 +1,2 @@
+-- Metadata of the attachments of inbound messages, as received in their payload
+ALTER TABLE messages ADD COLUMN attachment_metadata TEXT;
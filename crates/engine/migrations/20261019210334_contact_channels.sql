@@ -0,0 +1,10 @@
+-- Channel keys the owned identities encrypt their messages to a contact with, until the channel is torn down
+CREATE TABLE IF NOT EXISTS contact_channels
+(
+    id INTEGER PRIMARY KEY NOT NULL,
+    owned_identity BLOB NOT NULL,
+    contact_identity BLOB NOT NULL,
+    channel_key BLOB NOT NULL,
+    creation_timestamp INTEGER NOT NULL,
+    UNIQUE (owned_identity, contact_identity)
+);
@@ -0,0 +1,2 @@
+-- Sent again with every upload attempt of a message, so that the server stores it once
+ALTER TABLE outbox_messages ADD COLUMN upload_nonce BLOB;
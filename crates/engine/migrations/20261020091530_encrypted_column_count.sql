@@ -0,0 +1,2 @@
+-- How many of the sensitive columns are encrypted, the ones added since are encrypted when the database is opened
+ALTER TABLE database_key ADD COLUMN encrypted_column_count INTEGER NOT NULL DEFAULT 14;
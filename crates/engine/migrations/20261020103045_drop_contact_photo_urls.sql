@@ -0,0 +1,3 @@
+-- Photos are not uploaded for contacts to download, contacts never have one
+ALTER TABLE contacts DROP COLUMN photo_url;
+ALTER TABLE contacts DROP COLUMN published_photo_url;
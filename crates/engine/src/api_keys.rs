@@ -0,0 +1,153 @@
+//! Registration of the API key of the owned identities and tracking of its status.
+//!
+//! The server answers the status of the registered key with each new session, whichever part of the engine opened
+//! it. The `ApiKeyMonitor` stores it on the owned identity and marks the key expired once its expiration timestamp
+//! passed, an event is published when the key expires or its license is exhausted.
+
+use std::{sync::Arc, time::Duration};
+
+use tokio::{sync::broadcast::{self, error::RecvError}, task::JoinHandle};
+use uuid::Uuid;
+
+use crate::{current_timestamp, entities::identity::{API_KEY_STATUS_EXPIRED, API_KEY_STATUS_FREE_TRIAL_KEY_EXPIRED, API_KEY_STATUS_LICENSE_EXHAUSTED}, events::{publish_event, EngineEvent}, server::{ApiKeyStatus, ServerSession}, store::Store, Engine, EngineError, Result};
+
+const API_KEY_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+fn is_expired(status: i64) -> bool {
+    status == i64::from(API_KEY_STATUS_EXPIRED) || status == i64::from(API_KEY_STATUS_FREE_TRIAL_KEY_EXPIRED)
+}
+
+/// The key is expired once its expiration timestamp passed, even if the server still answers another status
+fn apply_expiration(mut api_key_status: ApiKeyStatus, now: i64) -> ApiKeyStatus {
+    let has_passed = api_key_status.expiration_timestamp.is_some_and(|expiration_timestamp| expiration_timestamp <= now);
+    if has_passed && !is_expired(api_key_status.status) {
+        api_key_status.status = i64::from(API_KEY_STATUS_EXPIRED);
+    }
+
+    api_key_status
+}
+
+fn session_api_key_status(session: &ServerSession) -> ApiKeyStatus {
+    ApiKeyStatus {
+        status: session.api_key_status,
+        permissions: session.api_key_permissions,
+        expiration_timestamp: session.api_key_expiration_timestamp,
+    }
+}
+
+/// Stores the status of the API key of an owned identity, publishing events when it changed
+pub(crate) async fn save_api_key_status(store: &dyn Store, events: &broadcast::Sender<EngineEvent>, bytes_owned_identity: &[u8], api_key_status: ApiKeyStatus) -> Result<()> {
+    let api_key_status = apply_expiration(api_key_status, current_timestamp());
+
+    // Within a transaction, so that the same change saved twice at once is published once
+    let mut transaction = store.begin().await?;
+    let Some(owned_identity) = transaction.owned_identities().get_by_identity(bytes_owned_identity).await? else {
+        return Ok(());
+    };
+    let previous_status = owned_identity.get_api_key_status();
+    if api_key_status == previous_status {
+        return Ok(());
+    }
+    transaction.owned_identities().set_api_key_status(bytes_owned_identity, &api_key_status).await?;
+    transaction.commit().await?;
+
+    publish_event(events, EngineEvent::OwnedIdentityUpdated { owned_identity: bytes_owned_identity.to_vec() });
+    if is_expired(api_key_status.status) && !is_expired(previous_status.status) {
+        publish_event(events, EngineEvent::ApiKeyExpired { owned_identity: bytes_owned_identity.to_vec() });
+    }
+    if api_key_status.status == i64::from(API_KEY_STATUS_LICENSE_EXHAUSTED) && previous_status.status != api_key_status.status {
+        publish_event(events, EngineEvent::ApiKeyLicenseExhausted { owned_identity: bytes_owned_identity.to_vec() });
+    }
+
+    Ok(())
+}
+
+/// Background task saving the API key status of the sessions opened by the server client, and expiring the keys
+pub(crate) struct ApiKeyMonitor {
+    pub store: Arc<dyn Store>,
+    pub events: broadcast::Sender<EngineEvent>,
+    pub new_sessions: broadcast::Receiver<(Vec<u8>, ServerSession)>,
+}
+
+impl ApiKeyMonitor {
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(self.run())
+    }
+
+    async fn run(mut self) {
+        loop {
+            let wait_duration = match self.expire_due_api_keys().await {
+                Ok(wait_duration) => wait_duration,
+                Err(_) => Some(API_KEY_RETRY_DELAY),
+            };
+
+            let new_session = match wait_duration {
+                Some(wait_duration) => {
+                    tokio::select! {
+                        new_session = self.new_sessions.recv() => new_session,
+                        _ = tokio::time::sleep(wait_duration) => continue,
+                    }
+                }
+                None => self.new_sessions.recv().await,
+            };
+
+            match new_session {
+                Ok((bytes_owned_identity, session)) => {
+                    // The status comes again with the next session when it can't be saved
+                    let _ = save_api_key_status(self.store.as_ref(), &self.events, &bytes_owned_identity, session_api_key_status(&session)).await;
+                }
+                Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => return,
+            }
+        }
+    }
+
+    /// Expires the keys whose expiration timestamp passed, returns the time until the next one expires
+    async fn expire_due_api_keys(&self) -> Result<Option<Duration>> {
+        let now = current_timestamp();
+        let mut next_expiration_timestamp: Option<i64> = None;
+        for owned_identity in self.store.owned_identities().get_all().await? {
+            let api_key_status = owned_identity.get_api_key_status();
+            let Some(expiration_timestamp) = api_key_status.expiration_timestamp.filter(|_| !is_expired(api_key_status.status)) else {
+                continue;
+            };
+
+            if expiration_timestamp <= now {
+                save_api_key_status(self.store.as_ref(), &self.events, owned_identity.get_bytes_owned_identity(), api_key_status).await?;
+            } else {
+                next_expiration_timestamp = Some(next_expiration_timestamp.map_or(expiration_timestamp, |timestamp| timestamp.min(expiration_timestamp)));
+            }
+        }
+
+        Ok(next_expiration_timestamp.map(|timestamp| Duration::from_millis(timestamp.saturating_sub(now) as u64)))
+    }
+}
+
+impl Engine {
+    /// Registers an API key for an owned identity, the one the engine was initialized with when `api_key` is `None`
+    pub async fn register_api_key(&mut self, bytes_owned_identity: &[u8], api_key: Option<Uuid>) -> Result<ApiKeyStatus> {
+        let api_key = api_key.or(self.api_key).ok_or(EngineError::NoApiKey)?;
+        let owned_identity = self.get_owned_identity(bytes_owned_identity).await?;
+        self.server_client.register_api_key(&owned_identity.get_private_identity()?, &api_key.to_string(), &mut *self.prng).await?;
+
+        self.refresh_api_key_status(bytes_owned_identity).await
+    }
+
+    /// Authenticates again with the server to get the current status of the registered API key
+    pub async fn refresh_api_key_status(&mut self, bytes_owned_identity: &[u8]) -> Result<ApiKeyStatus> {
+        self.server_client.invalidate_session(bytes_owned_identity);
+        let session = self.authenticate_owned_identity(bytes_owned_identity).await?;
+
+        Ok(apply_expiration(session_api_key_status(&session), current_timestamp()))
+    }
+
+    /// Status of an API key before registering it, nothing is stored
+    pub async fn query_api_key_status(&self, bytes_owned_identity: &[u8], api_key: Uuid) -> Result<ApiKeyStatus> {
+        self.server_client.query_api_key_status(bytes_owned_identity, &api_key.to_string()).await
+    }
+
+    /// Saves the status of a session right away, the `ApiKeyMonitor` may save it only later
+    pub(crate) async fn save_session_api_key_status(&self, bytes_owned_identity: &[u8], session: &ServerSession) -> Result<()> {
+        save_api_key_status(self.store.as_ref(), &self.events, bytes_owned_identity, session_api_key_status(session)).await
+    }
+}
@@ -0,0 +1,130 @@
+use std::path::{Path, PathBuf};
+
+use crate::{entities::{attachment::{Attachment, AttachmentDirection, ATTACHMENT_KEY_LENGTH}, message::Message}, messages::payload::JsonAttachment, Engine, EngineError, Result};
+
+const OUTBOUND_ATTACHMENTS_DIRECTORY: &str = "attachments/outbound";
+const INBOUND_ATTACHMENTS_DIRECTORY: &str = "attachments/inbound";
+
+/// What the sender of an attachment shares with its recipients, inside the encrypted message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttachmentMetadata {
+    pub key: Vec<u8>,
+    pub size: i64,
+    pub chunk_length: i64,
+    pub file_name: String,
+    pub mime_type: String,
+}
+
+impl Attachment {
+    pub fn get_metadata(&self) -> AttachmentMetadata {
+        AttachmentMetadata {
+            key: self.get_key().to_vec(),
+            size: self.get_size(),
+            chunk_length: self.get_chunk_length(),
+            file_name: self.get_file_name().to_owned(),
+            mime_type: self.get_mime_type().to_owned(),
+        }
+    }
+}
+
+impl Message {
+    /// Metadata of the attachments of an inbound message, numbered by their position, empty once the message is wiped
+    pub fn get_attachment_metadata(&self) -> Result<Vec<AttachmentMetadata>> {
+        let Some(attachment_metadata) = &self.attachment_metadata else {
+            return Ok(vec![]);
+        };
+        let attachments = serde_json::from_str::<Vec<JsonAttachment>>(attachment_metadata)?;
+
+        Ok(attachments.into_iter().map(AttachmentMetadata::from).collect())
+    }
+}
+
+/// File names come from other users, they must not escape the attachments directory
+fn sanitize_file_name(file_name: &str) -> String {
+    let sanitized = file_name.replace(['/', '\\'], "_");
+    sanitized.trim_start_matches('.').to_owned()
+}
+
+impl Engine {
+    pub fn get_data_directory(&self) -> &Path {
+        &self.data_directory
+    }
+
+    /// Copies a file to the data directory and creates its encryption key.
+    ///
+    /// The returned attachment is uploaded once given to `queue_message_with_attachments`.
+    pub async fn prepare_outbound_attachment(&self, bytes_owned_identity: &[u8], source_path: &Path, mime_type: &str) -> Result<Attachment> {
+        let file_name = source_path.file_name().and_then(|file_name| file_name.to_str()).ok_or(EngineError::Technical)?;
+
+        let mut key = vec![0; ATTACHMENT_KEY_LENGTH];
+        getrandom::fill(&mut key).map_err(|_| EngineError::PRNG)?;
+        let mut file_uid = [0u8; 16];
+        getrandom::fill(&mut file_uid).map_err(|_| EngineError::PRNG)?;
+
+        let directory = self.data_directory.join(OUTBOUND_ATTACHMENTS_DIRECTORY);
+        tokio::fs::create_dir_all(&directory).await?;
+        let file_path = directory.join(format!("{}-{}", hex::encode(file_uid), sanitize_file_name(file_name)));
+        let size = tokio::fs::copy(source_path, &file_path).await? as i64;
+
+        let attachment = Attachment::builder()
+            .owned_identity(bytes_owned_identity.to_vec())
+            .direction(AttachmentDirection::Outbound)
+            .key(key)
+            .size(size)
+            .chunk_length(self.attachment_chunk_length)
+            .file_path(file_path.to_string_lossy().into_owned())
+            .file_name(file_name.to_owned())
+            .mime_type(mime_type.to_owned())
+            .build()?;
+
+        let id = self.store.attachments().insert(attachment).await?;
+        self.store.attachments().get_by_id(id).await?.ok_or(EngineError::UnknownAttachment)
+    }
+
+    /// Downloads an attachment of a message received by an owned identity, returns the id of the attachment.
+    ///
+    /// Starting a download twice returns the attachment created the first time.
+    pub async fn start_attachment_download(&self, bytes_owned_identity: &[u8], message_uid: &[u8], attachment_number: i64, metadata: AttachmentMetadata) -> Result<i64> {
+        let existing_attachments = self.store.attachments().get_inbound_by_message(bytes_owned_identity, message_uid).await?;
+        if let Some(existing_attachment) = existing_attachments.iter().find(|attachment| attachment.get_attachment_number() == attachment_number) {
+            return existing_attachment.get_id().ok_or(EngineError::Technical);
+        }
+
+        let directory = self.data_directory.join(INBOUND_ATTACHMENTS_DIRECTORY);
+        tokio::fs::create_dir_all(&directory).await?;
+        let file_path: PathBuf = directory.join(format!("{}-{}-{}", hex::encode(message_uid), attachment_number, sanitize_file_name(&metadata.file_name)));
+
+        let attachment = Attachment::builder()
+            .owned_identity(bytes_owned_identity.to_vec())
+            .direction(AttachmentDirection::Inbound)
+            .message_uid(message_uid.to_vec())
+            .attachment_number(attachment_number)
+            .key(metadata.key)
+            .size(metadata.size)
+            .chunk_length(metadata.chunk_length)
+            .file_path(file_path.to_string_lossy().into_owned())
+            .file_name(metadata.file_name)
+            .mime_type(metadata.mime_type)
+            .build()?;
+
+        let id = self.store.attachments().insert(attachment).await?;
+        self.attachment_wake_up.notify_one();
+
+        Ok(id)
+    }
+
+    pub async fn get_attachment(&self, attachment_id: i64) -> Result<Option<Attachment>> {
+        self.store.attachments().get_by_id(attachment_id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::sanitize_file_name;
+
+    #[test]
+    fn file_name_cannot_escape_directory() {
+        assert_eq!(sanitize_file_name("../../etc/passwd"), "_.._etc_passwd");
+        assert_eq!(sanitize_file_name("photo.jpg"), "photo.jpg");
+    }
+}
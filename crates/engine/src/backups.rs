@@ -0,0 +1,213 @@
+//! Encrypted backups of the owned identities with their private keys, their contacts and groups, and the settings.
+//!
+//! A backup is the JSON `JsonBackup` encrypted with a key derived from the seed of the backup key, which the user
+//! writes down as base32. The IV is derived from the seed and the content, so backing up the same content with the
+//! same key always gives the same backup.
+
+use data_encoding::BASE32_NOPAD;
+use olvid_core::{crypto::{auth_encryption::{AuthEnc, AES256CTRHMACSHA256}, hash::{Hash, SHA256}, prng::{PRNGHmacSHA256, PRNG}}, AES256CTRHMACSHA256Key};
+use serde::{Deserialize, Serialize};
+
+use crate::{entities::{backup_key::BackupKey, contact::Contact, group::{Group, GroupMember}, identity::{DisplayNameFormat, OwnedIdentity}, keycloak_server::KeycloakServer, owned_device::OwnedDevice}, events::EngineEvent, store::{Store, StoreTransaction}, Engine, EngineError, Result};
+
+/// Prepended to the encrypted backups
+const BACKUP_FORMAT_VERSION: u8 = 0;
+const BACKUP_KEY_SEED_LENGTH: usize = 20;
+const BACKUP_KEY_GROUP_LENGTH: usize = 4;
+
+#[derive(Serialize, Deserialize, Debug)]
+struct JsonBackup {
+    display_name_format: DisplayNameFormat,
+    /// Sorted by identity, as everything else, for the backups to be deterministic
+    owned_identities: Vec<JsonOwnedIdentityBackup>,
+}
+
+/// Also what a profile transfer sends to the new device
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) struct JsonOwnedIdentityBackup {
+    pub owned_identity: OwnedIdentity,
+    contacts: Vec<Contact>,
+    groups: Vec<JsonGroupBackup>,
+    #[serde(default)]
+    keycloak_server: Option<KeycloakServer>,
+    /// Other devices of the owned identity
+    #[serde(default)]
+    owned_devices: Vec<OwnedDevice>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct JsonGroupBackup {
+    group: Group,
+    members: Vec<GroupMember>,
+}
+
+/// Base32 seed in groups of four characters, as shown to the user
+fn format_backup_key(seed: &[u8]) -> String {
+    let characters: Vec<char> = BASE32_NOPAD.encode(seed).chars().collect();
+    characters.chunks(BACKUP_KEY_GROUP_LENGTH).map(String::from_iter).collect::<Vec<_>>().join(" ")
+}
+
+/// Accepts the backup key as typed by the user, in any case and with any separator between the groups
+fn parse_backup_key(backup_key: &str) -> Result<Vec<u8>> {
+    let encoded: String = backup_key.chars().filter(char::is_ascii_alphanumeric).map(|character| character.to_ascii_uppercase()).collect();
+    match BASE32_NOPAD.decode(encoded.as_bytes()) {
+        Ok(seed) if seed.len() == BACKUP_KEY_SEED_LENGTH => Ok(seed),
+        _ => Err(EngineError::InvalidBackupKey),
+    }
+}
+
+/// The KDF needs a longer seed than the one written down by the user
+fn derive_backup_key(seed: &[u8]) -> Result<AES256CTRHMACSHA256Key> {
+    AES256CTRHMACSHA256::generate_key(&SHA256::digest(seed)).map_err(|_| EngineError::Technical)
+}
+
+fn seal(backup: &JsonBackup, seed: &[u8]) -> Result<Vec<u8>> {
+    let content = serde_json::to_vec(backup)?;
+    let mut prng = PRNGHmacSHA256::init(&SHA256::digest(&[seed, &content].concat())).map_err(|_| EngineError::PRNG)?;
+    let encrypted = AES256CTRHMACSHA256::encrypt(&content, &derive_backup_key(seed)?, &mut prng).map_err(|_| EngineError::Technical)?;
+
+    Ok([&[BACKUP_FORMAT_VERSION], encrypted.as_slice()].concat())
+}
+
+/// Fails with `InvalidBackupKey` when the backup does not decrypt with the key
+fn open(backup: &[u8], seed: &[u8]) -> Result<JsonBackup> {
+    let Some((&BACKUP_FORMAT_VERSION, encrypted)) = backup.split_first() else {
+        return Err(EngineError::InvalidBackup);
+    };
+    let content = AES256CTRHMACSHA256::decrypt(encrypted, &derive_backup_key(seed)?).map_err(|_| EngineError::InvalidBackupKey)?;
+
+    serde_json::from_slice(&content).map_err(|_| EngineError::InvalidBackup)
+}
+
+pub(crate) async fn collect_owned_identity_backup(store: &dyn Store, owned_identity: OwnedIdentity) -> Result<JsonOwnedIdentityBackup> {
+    let bytes_owned_identity = owned_identity.get_bytes_owned_identity();
+    let mut contacts = store.contacts().get_all(bytes_owned_identity).await?;
+    contacts.sort_by(|a, b| a.get_contact_identity().cmp(b.get_contact_identity()));
+
+    let mut groups = store.groups().get_all(bytes_owned_identity).await?;
+    groups.sort_by(|a, b| a.get_group_uid().cmp(b.get_group_uid()));
+    let mut group_backups = Vec::new();
+    for group in groups {
+        let mut members = store.group_members().get_all(group.get_id().ok_or(EngineError::Technical)?).await?;
+        members.sort_by(|a, b| a.get_member_identity().cmp(b.get_member_identity()));
+        group_backups.push(JsonGroupBackup { group, members });
+    }
+
+    let keycloak_server = store.keycloak_servers().get(bytes_owned_identity).await?;
+
+    let mut owned_devices = store.owned_devices().get_all(bytes_owned_identity).await?;
+    owned_devices.sort_by(|a, b| a.get_device_uid().cmp(b.get_device_uid()));
+
+    Ok(JsonOwnedIdentityBackup { owned_identity, contacts, groups: group_backups, keycloak_server, owned_devices })
+}
+
+async fn collect_backup(store: &dyn Store) -> Result<JsonBackup> {
+    let mut owned_identities = store.owned_identities().get_all().await?;
+    owned_identities.sort_by(|a, b| a.get_bytes_owned_identity().cmp(b.get_bytes_owned_identity()));
+
+    let mut owned_identity_backups = Vec::new();
+    for owned_identity in owned_identities {
+        owned_identity_backups.push(collect_owned_identity_backup(store, owned_identity).await?);
+    }
+
+    Ok(JsonBackup { display_name_format: store.settings().get_display_name_format().await?, owned_identities: owned_identity_backups })
+}
+
+/// The current device of the owned identity is not one of its other devices, as when it was transferred back to one of them
+pub(crate) async fn restore_owned_identity_backup(transaction: &mut dyn StoreTransaction, owned_identity_backup: JsonOwnedIdentityBackup) -> Result<()> {
+    let current_device_uid = owned_identity_backup.owned_identity.current_device_uid.clone();
+    transaction.owned_identities().insert(owned_identity_backup.owned_identity).await?;
+    for contact in owned_identity_backup.contacts {
+        transaction.contacts().insert_if_absent(contact).await?;
+    }
+    for group_backup in owned_identity_backup.groups {
+        let group_id = transaction.groups().upsert(group_backup.group).await?;
+        transaction.group_members().replace_all(group_id, group_backup.members).await?;
+    }
+    if let Some(keycloak_server) = owned_identity_backup.keycloak_server {
+        transaction.keycloak_servers().upsert(keycloak_server).await?;
+    }
+    for owned_device in owned_identity_backup.owned_devices {
+        if current_device_uid.as_deref() != Some(owned_device.get_device_uid()) {
+            transaction.owned_devices().insert_if_absent(owned_device).await?;
+        }
+    }
+
+    Ok(())
+}
+
+impl Engine {
+    /// Generates the key the next backups are encrypted with, to be written down by the user.
+    ///
+    /// The previous backups can only be restored with the previous key.
+    pub async fn generate_backup_key(&self) -> Result<String> {
+        let mut seed = vec![0; BACKUP_KEY_SEED_LENGTH];
+        getrandom::fill(&mut seed).map_err(|_| EngineError::PRNG)?;
+        self.store.settings().set_backup_key(BackupKey::new(&seed)).await?;
+
+        Ok(format_backup_key(&seed))
+    }
+
+    /// Backs up everything needed to restore the owned identities on a new device, except the discussions
+    pub async fn export_backup(&self) -> Result<Vec<u8>> {
+        let backup_key = self.store.settings().get_backup_key().await?.ok_or(EngineError::NoBackupKey)?;
+        seal(&collect_backup(&*self.store).await?, backup_key.get_seed())
+    }
+
+    /// Restores a backup in a database without owned identity, the backup key becomes the current one
+    pub async fn restore_backup(&self, backup_key: &str, backup: &[u8]) -> Result<()> {
+        let seed = parse_backup_key(backup_key)?;
+        let backup = open(backup, &seed)?;
+        if !self.store.owned_identities().get_all().await?.is_empty() {
+            return Err(EngineError::BackupRestoreNotEmpty);
+        }
+
+        let mut transaction = self.store.begin().await?;
+        transaction.settings().set_display_name_format(&backup.display_name_format).await?;
+        transaction.settings().set_backup_key(BackupKey::new(&seed)).await?;
+        let mut restored_identities = Vec::new();
+        for owned_identity_backup in backup.owned_identities {
+            restored_identities.push(owned_identity_backup.owned_identity.get_bytes_owned_identity().to_vec());
+            restore_owned_identity_backup(&mut *transaction, owned_identity_backup).await?;
+        }
+        transaction.commit().await?;
+
+        for owned_identity in restored_identities {
+            self.publish_event(EngineEvent::OwnedIdentityCreated { owned_identity });
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{entities::identity::{DisplayNameFormat, NameOrder}, EngineError};
+
+    use super::{format_backup_key, open, parse_backup_key, seal, JsonBackup, BACKUP_KEY_SEED_LENGTH};
+
+    #[test]
+    fn backup_key_is_grouped_base32() {
+        let seed: Vec<u8> = (0..BACKUP_KEY_SEED_LENGTH as u8).collect();
+        let backup_key = format_backup_key(&seed);
+        assert_eq!(backup_key.len(), 8 * 4 + 7);
+        assert!(backup_key.split(' ').all(|group| group.len() == 4));
+
+        assert_eq!(parse_backup_key(&backup_key).unwrap(), seed);
+        assert_eq!(parse_backup_key(&backup_key.to_lowercase().replace(' ', "-")).unwrap(), seed);
+        assert!(matches!(parse_backup_key(&backup_key[..20]), Err(EngineError::InvalidBackupKey)));
+    }
+
+    #[test]
+    fn backup_is_deterministic_and_opens_only_with_its_key() {
+        let seed = vec![1; BACKUP_KEY_SEED_LENGTH];
+        let format = DisplayNameFormat::builder().name_order(NameOrder::LastFirst).build();
+        let backup = JsonBackup { display_name_format: format, owned_identities: Vec::new() };
+
+        let sealed = seal(&backup, &seed).unwrap();
+        assert_eq!(sealed, seal(&backup, &seed).unwrap());
+        assert_eq!(open(&sealed, &seed).unwrap().display_name_format, format);
+        assert!(matches!(open(&sealed, &[2; BACKUP_KEY_SEED_LENGTH]), Err(EngineError::InvalidBackupKey)));
+        assert!(matches!(open(&[&[9], &sealed[1..]].concat(), &seed), Err(EngineError::InvalidBackup)));
+    }
+}
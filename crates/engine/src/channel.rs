@@ -0,0 +1,127 @@
+//! Encryption of the messages exchanged between identities.
+//!
+//! An owned identity encrypts its messages to an identity with the `AES256CTRHMACSHA256Key` of their channel, see
+//! `ContactChannel`. Each message carries the key encrypted with the public key of the recipient as its wrapped key,
+//! so that every device of the recipient can read it, and the content, made of the sender identity, the payload and
+//! the signature of the sender, encrypted with the key. Anyone can encrypt for the recipient, the signature is what
+//! authenticates the sender.
+//!
+//! Tearing a channel down makes the next messages use a new key, the messages sent before can't be read with it.
+
+use olvid_core::{crypto::{auth_encryption::{AuthEnc, AES256CTRHMACSHA256}, prng::{PRNGHmacSHA256, PRNG}}, cryptographic_identity::{CryptographicIdentity, OwnedCryptographicIdentity}, encoding::{BytesArray, Decoder, Encoder}, AES256CTRHMACSHA256Key, SymmetricKey};
+
+use crate::{EngineError, Result};
+
+const CHANNEL_KEY_LENGTH: usize = 64;
+const SENDER_SIGNATURE_PREFIX: &[u8] = b"channelMessage";
+
+/// Decrypted content of a message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ChannelMessage {
+    pub sender_identity: Vec<u8>,
+    pub payload: Vec<u8>,
+}
+
+/// The signature covers the recipient, so that a recipient can't forward a signed message to someone else
+fn sender_statement(to_identity: &[u8], payload: &[u8]) -> Result<Vec<u8>> {
+    Ok(vec![to_identity.to_vec().encode()?, payload.to_vec().encode()?].encode()?)
+}
+
+/// Key of a new channel
+pub(crate) fn generate_channel_key(prng: &mut PRNGHmacSHA256) -> Result<Vec<u8>> {
+    prng.bytes(CHANNEL_KEY_LENGTH).map_err(|_| EngineError::PRNG)
+}
+
+/// Returns the wrapped key and the encrypted content of a payload sent by `from_identity` to `to_identity` on the channel
+/// with `channel_key`
+pub(crate) fn seal(from_identity: &OwnedCryptographicIdentity, channel_key: &[u8], payload: &[u8], to_identity: &CryptographicIdentity, prng: &mut PRNGHmacSHA256) -> Result<(Vec<u8>, Vec<u8>)> {
+    let key = AES256CTRHMACSHA256Key::init(channel_key).map_err(|_| EngineError::Technical)?;
+
+    let signature = from_identity.sign(SENDER_SIGNATURE_PREFIX, &sender_statement(&to_identity.get_identity(), payload)?, prng).map_err(|_| EngineError::Technical)?;
+    let sender_identity = from_identity.get_crypto_identity().get_identity();
+    let wrapped_key = to_identity.encrypt(channel_key, prng).map_err(|_| EngineError::Technical)?;
+    let content = vec![sender_identity.encode()?, payload.to_vec().encode()?, signature.encode()?].encode()?;
+    let encrypted_content = AES256CTRHMACSHA256::encrypt(&content, &key, prng).map_err(|_| EngineError::Technical)?;
+
+    Ok((wrapped_key, encrypted_content))
+}
+
+/// Fails with `InvalidSenderSignature` when the content was not signed by the identity it claims to come from
+pub(crate) fn open(owned_identity: &OwnedCryptographicIdentity, wrapped_key: &[u8], encrypted_content: &[u8]) -> Result<ChannelMessage> {
+    let raw_key = owned_identity.decrypt(wrapped_key).map_err(|_| EngineError::ChannelDecryption)?;
+    let key = AES256CTRHMACSHA256Key::init(&raw_key).map_err(|_| EngineError::ChannelDecryption)?;
+    let content = AES256CTRHMACSHA256::decrypt(encrypted_content, &key).map_err(|_| EngineError::ChannelDecryption)?;
+
+    let values = Vec::<BytesArray>::decode(&content)?;
+    let [sender_identity, payload, signature] = values.as_slice() else {
+        return Err(EngineError::ChannelDecryption);
+    };
+    let message = ChannelMessage { sender_identity: BytesArray::decode(sender_identity)?, payload: BytesArray::decode(payload)? };
+    let signature = BytesArray::decode(signature)?;
+
+    let statement = sender_statement(&owned_identity.get_crypto_identity().get_identity(), &message.payload)?;
+    let is_signed = CryptographicIdentity::from_raw(&message.sender_identity)
+        .and_then(|identity| identity.verify_signature(SENDER_SIGNATURE_PREFIX, &statement, &signature))
+        .unwrap_or(false);
+    if !is_signed {
+        return Err(EngineError::InvalidSenderSignature);
+    }
+
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use olvid_core::{crypto::auth_encryption::{AuthEnc, AES256CTRHMACSHA256}, cryptographic_identity::OwnedCryptographicIdentity, encoding::Encoder, AES256CTRHMACSHA256Key, SymmetricKey};
+
+    use crate::{Engine, EngineError};
+
+    use super::{generate_channel_key, open, seal, sender_statement, ChannelMessage, SENDER_SIGNATURE_PREFIX};
+
+    #[test]
+    fn sealed_message_opens_for_recipient_only() {
+        let mut prng = Engine::get_default_hmac_prng().unwrap();
+        let sender = OwnedCryptographicIdentity::generate_owned_cryptographic_identity("https://server.olvid.io", &mut prng).unwrap();
+        let recipient = OwnedCryptographicIdentity::generate_owned_cryptographic_identity("https://server.olvid.io", &mut prng).unwrap();
+        let other = OwnedCryptographicIdentity::generate_owned_cryptographic_identity("https://server.olvid.io", &mut prng).unwrap();
+        let payload = br#"{"message":{}}"#.to_vec();
+        let channel_key = generate_channel_key(&mut prng).unwrap();
+
+        let (wrapped_key, encrypted_content) = seal(&sender, &channel_key, &payload, &recipient.get_crypto_identity(), &mut prng).unwrap();
+
+        let message = ChannelMessage { sender_identity: sender.get_crypto_identity().get_identity(), payload: payload.clone() };
+        assert_eq!(open(&recipient, &wrapped_key, &encrypted_content).unwrap(), message);
+        assert!(matches!(open(&other, &wrapped_key, &encrypted_content), Err(EngineError::ChannelDecryption)));
+
+        // Messages on the same channel share the key, not the ciphertext
+        let (next_wrapped_key, next_encrypted_content) = seal(&sender, &channel_key, &payload, &recipient.get_crypto_identity(), &mut prng).unwrap();
+        assert_eq!(recipient.decrypt(&next_wrapped_key).unwrap(), channel_key);
+        assert_ne!(next_encrypted_content, encrypted_content);
+        assert_eq!(open(&recipient, &next_wrapped_key, &next_encrypted_content).unwrap(), message);
+    }
+
+    #[test]
+    fn forged_sender_is_rejected() {
+        let mut prng = Engine::get_default_hmac_prng().unwrap();
+        let sender = OwnedCryptographicIdentity::generate_owned_cryptographic_identity("https://server.olvid.io", &mut prng).unwrap();
+        let impersonated = OwnedCryptographicIdentity::generate_owned_cryptographic_identity("https://server.olvid.io", &mut prng).unwrap();
+        let recipient = OwnedCryptographicIdentity::generate_owned_cryptographic_identity("https://server.olvid.io", &mut prng).unwrap();
+        let other = OwnedCryptographicIdentity::generate_owned_cryptographic_identity("https://server.olvid.io", &mut prng).unwrap();
+        let payload = b"{}".to_vec();
+
+        // Content claiming to come from another identity, with the signature of the actual sender
+        let raw_key = generate_channel_key(&mut prng).unwrap();
+        let key = AES256CTRHMACSHA256Key::init(&raw_key).unwrap();
+        let signature = sender.sign(SENDER_SIGNATURE_PREFIX, &sender_statement(&recipient.get_crypto_identity().get_identity(), &payload).unwrap(), &mut prng).unwrap();
+        let content = vec![impersonated.get_crypto_identity().get_identity().encode().unwrap(), payload.encode().unwrap(), signature.encode().unwrap()].encode().unwrap();
+        let wrapped_key = recipient.get_crypto_identity().encrypt(&raw_key, &mut prng).unwrap();
+        let encrypted_content = AES256CTRHMACSHA256::encrypt(&content, &key, &mut prng).unwrap();
+        assert!(matches!(open(&recipient, &wrapped_key, &encrypted_content), Err(EngineError::InvalidSenderSignature)));
+
+        // A signed message forwarded to another recipient
+        let (wrapped_key, encrypted_content) = seal(&sender, &raw_key, &payload, &recipient.get_crypto_identity(), &mut prng).unwrap();
+        let raw_key = recipient.decrypt(&wrapped_key).unwrap();
+        let forwarded_wrapped_key = other.get_crypto_identity().encrypt(&raw_key, &mut prng).unwrap();
+        assert!(matches!(open(&other, &forwarded_wrapped_key, &encrypted_content), Err(EngineError::InvalidSenderSignature)));
+    }
+}
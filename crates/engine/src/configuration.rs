@@ -0,0 +1,26 @@
+use std::path::PathBuf;
+
+use bon::Builder;
+use uuid::Uuid;
+
+use crate::{network::RetryPolicy, store::DatabaseSecret, DB_URL};
+
+/// Plain text bytes per attachment chunk, each chunk being encrypted and uploaded on its own
+pub const DEFAULT_ATTACHMENT_CHUNK_LENGTH: i64 = 512 * 1024;
+
+#[derive(Builder, Debug, Clone)]
+pub struct EngineConfiguration {
+    pub server_url: String,
+    pub api_key: Option<Uuid>,
+    #[builder(default = DB_URL.to_owned())]
+    pub database_url: String,
+    /// Encrypts the sensitive columns of the database, needed again to open it once given
+    pub database_secret: Option<DatabaseSecret>,
+    /// Where attachments are stored, decrypted
+    #[builder(default = PathBuf::from("olvid_data"))]
+    pub data_directory: PathBuf,
+    #[builder(default = DEFAULT_ATTACHMENT_CHUNK_LENGTH)]
+    pub attachment_chunk_length: i64,
+    #[builder(default)]
+    pub retry_policy: RetryPolicy,
+}
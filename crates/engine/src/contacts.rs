@@ -0,0 +1,216 @@
+//! Contacts of the owned identities, and the identities they blocked.
+//!
+//! Messages from a blocked identity are dropped, protocol messages included, and it can't become a contact again
+//! through an introduction or a trust establishment until the user unblocks it. Revoking the trust in a contact sets it
+//! back to unverified and tears down the channel with it. The contact is sent a revocation signed by the owned
+//! identity, which reaches all of its devices, so that it does the same on its side.
+
+use olvid_core::{cryptographic_identity::CryptographicIdentity, encoding::Encoder};
+
+use crate::{
+    entities::{blocked_contact::BlockedContact, contact::Contact, identity::JsonIdentityDetails, introduction::IntroductionStatus, trust_establishment::TrustEstablishmentStatus},
+    events::EngineEvent,
+    messages::{payload::{JsonPayload, JsonTrustRevocation}, queue_payload},
+    store::{Store, StoreTransaction},
+    Engine, EngineError, Result,
+};
+
+const REVOCATION_SIGNATURE_PREFIX: &[u8] = b"contactTrustRevocation";
+
+fn revocation_statement(revoker_identity: &[u8], contact_identity: &[u8]) -> Result<Vec<u8>> {
+    Ok(vec![revoker_identity.to_vec().encode()?, contact_identity.to_vec().encode()?].encode()?)
+}
+
+/// Fails with `ContactBlocked` when the owned identity blocked `identity`
+pub(crate) async fn check_not_blocked(store: &dyn Store, owned_identity: &[u8], identity: &[u8]) -> Result<()> {
+    match store.blocked_contacts().get(owned_identity, identity).await? {
+        Some(_) => Err(EngineError::ContactBlocked),
+        None => Ok(()),
+    }
+}
+
+/// Lowers the trust level of a contact that revoked its trust in the owned identity and tears down the channel with it,
+/// ignores revocations not signed by it
+pub(crate) async fn process_trust_revocation(transaction: &mut dyn StoreTransaction, events: &mut Vec<EngineEvent>, owned_identity: &[u8], sender_identity: &[u8], revocation: JsonTrustRevocation) -> Result<()> {
+    if revocation.contact_identity != owned_identity {
+        return Ok(());
+    }
+    let statement = revocation_statement(sender_identity, owned_identity)?;
+    let is_signed = CryptographicIdentity::from_raw(sender_identity)
+        .and_then(|identity| identity.verify_signature(REVOCATION_SIGNATURE_PREFIX, &statement, &revocation.signature))
+        .unwrap_or(false);
+    if !is_signed {
+        return Ok(());
+    }
+
+    if transaction.contacts().revoke_trust(owned_identity, sender_identity).await? {
+        events.push(EngineEvent::ContactUpdated { owned_identity: owned_identity.to_vec(), contact_identity: sender_identity.to_vec() });
+    }
+    transaction.contact_channels().delete(owned_identity, sender_identity).await?;
+
+    Ok(())
+}
+
+impl Engine {
+    /// Adds a contact to an owned identity, adding it again returns the existing contact
+    pub async fn add_contact(&self, bytes_owned_identity: &[u8], contact_identity: &[u8], identity_details: &JsonIdentityDetails) -> Result<Contact> {
+        self.get_owned_identity(bytes_owned_identity).await?;
+        check_not_blocked(&*self.store, bytes_owned_identity, contact_identity).await?;
+
+        let contact = Contact::new(bytes_owned_identity, contact_identity, identity_details, &self.store.settings().get_display_name_format().await?)?;
+        // Messages are encrypted for the contact, its identity must contain valid public keys
+        contact.get_cryptographic_identity()?;
+
+        if self.store.contacts().insert_if_absent(contact).await? {
+            self.publish_event(EngineEvent::ContactAdded { owned_identity: bytes_owned_identity.to_vec(), contact_identity: contact_identity.to_vec() });
+        }
+
+        self.store.contacts().get(bytes_owned_identity, contact_identity).await?.ok_or(EngineError::Technical)
+    }
+
+    pub async fn get_contacts(&self, bytes_owned_identity: &[u8]) -> Result<Vec<Contact>> {
+        self.store.contacts().get_all(bytes_owned_identity).await
+    }
+
+    /// Revokes the trust in a contact, it goes back to unverified and the channel with it is torn down on both sides
+    pub async fn revoke_contact_trust(&self, bytes_owned_identity: &[u8], contact_identity: &[u8]) -> Result<()> {
+        let owned_identity = self.get_owned_identity(bytes_owned_identity).await?.get_private_identity()?;
+        self.store.contacts().get(bytes_owned_identity, contact_identity).await?.ok_or(EngineError::UnknownContact)?;
+
+        let mut prng = Self::get_default_hmac_prng()?;
+        let signature = owned_identity.sign(REVOCATION_SIGNATURE_PREFIX, &revocation_statement(bytes_owned_identity, contact_identity)?, &mut prng)
+            .map_err(|_| EngineError::Technical)?;
+        let payload = JsonPayload {
+            trust_revocation: Some(JsonTrustRevocation { contact_identity: contact_identity.to_vec(), signature }),
+            ..Default::default()
+        };
+
+        // The contact is told even when it was not trusted here, it may still trust the owned identity. The revocation is
+        // the last message sent on the channel, the next ones use a new key.
+        let mut transaction = self.store.begin().await?;
+        let revoked = transaction.contacts().revoke_trust(bytes_owned_identity, contact_identity).await?;
+        queue_payload(&mut *transaction, &owned_identity, contact_identity, &payload, &mut prng).await?;
+        transaction.contact_channels().delete(bytes_owned_identity, contact_identity).await?;
+        transaction.commit().await?;
+        self.outbox_wake_up.notify_one();
+
+        if revoked {
+            self.publish_event(EngineEvent::ContactUpdated { owned_identity: bytes_owned_identity.to_vec(), contact_identity: contact_identity.to_vec() });
+        }
+
+        Ok(())
+    }
+
+    /// Blocks an identity, contact or not, until `unblock_contact`.
+    ///
+    /// Its pending introductions and trust establishments are cancelled, and it is removed from the groups the owned
+    /// identity administers. It is not told.
+    pub async fn block_contact(&self, bytes_owned_identity: &[u8], contact_identity: &[u8]) -> Result<()> {
+        self.get_owned_identity(bytes_owned_identity).await?;
+        if !self.store.blocked_contacts().insert_if_absent(BlockedContact::new(bytes_owned_identity, contact_identity)).await? {
+            return Ok(());
+        }
+        self.publish_event(EngineEvent::ContactBlocked { owned_identity: bytes_owned_identity.to_vec(), contact_identity: contact_identity.to_vec() });
+
+        for introduction in self.store.introductions().get_all(bytes_owned_identity).await? {
+            let is_involved = introduction.get_introducer_identity() == contact_identity || introduction.get_contact_identity() == contact_identity;
+            if is_involved && matches!(introduction.get_status()?, IntroductionStatus::Pending | IntroductionStatus::Accepted) {
+                let introduction_id = introduction.get_id().ok_or(EngineError::Technical)?;
+                self.store.introductions().set_status(introduction_id, IntroductionStatus::Rejected).await?;
+                self.publish_event(EngineEvent::IntroductionUpdated { owned_identity: bytes_owned_identity.to_vec(), introduction_id });
+            }
+        }
+        for trust_establishment in self.store.trust_establishments().get_all(bytes_owned_identity).await? {
+            let is_finished = matches!(trust_establishment.get_status()?, TrustEstablishmentStatus::Finished | TrustEstablishmentStatus::Cancelled);
+            if trust_establishment.get_contact_identity() == contact_identity && !is_finished {
+                let trust_establishment_id = trust_establishment.get_id().ok_or(EngineError::Technical)?;
+                self.store.trust_establishments().set_status(trust_establishment_id, TrustEstablishmentStatus::Cancelled).await?;
+                self.publish_event(EngineEvent::TrustEstablishmentUpdated { owned_identity: bytes_owned_identity.to_vec(), trust_establishment_id });
+            }
+        }
+
+        self.remove_from_administered_groups(bytes_owned_identity, contact_identity).await
+    }
+
+    /// Lets the identity send messages again, and become a contact again if it was deleted
+    pub async fn unblock_contact(&self, bytes_owned_identity: &[u8], contact_identity: &[u8]) -> Result<()> {
+        if self.store.blocked_contacts().delete(bytes_owned_identity, contact_identity).await? {
+            self.publish_event(EngineEvent::ContactUnblocked { owned_identity: bytes_owned_identity.to_vec(), contact_identity: contact_identity.to_vec() });
+        }
+
+        Ok(())
+    }
+
+    /// Identities blocked by an owned identity, earliest blocked first
+    pub async fn get_blocked_contacts(&self, bytes_owned_identity: &[u8]) -> Result<Vec<BlockedContact>> {
+        self.store.blocked_contacts().get_all(bytes_owned_identity).await
+    }
+
+    /// Deletes a contact with its discussion, messages, attachments and channel, after removing it from the groups the
+    /// owned identity administers.
+    ///
+    /// The contact is not told, and stays blocked if it was.
+    pub async fn delete_contact(&self, bytes_owned_identity: &[u8], contact_identity: &[u8]) -> Result<()> {
+        self.store.contacts().get(bytes_owned_identity, contact_identity).await?.ok_or(EngineError::UnknownContact)?;
+        self.remove_from_administered_groups(bytes_owned_identity, contact_identity).await?;
+
+        let discussion_id = match self.store.discussions().get_one_to_one(bytes_owned_identity, contact_identity).await? {
+            Some(discussion) => Some(discussion.get_id().ok_or(EngineError::Technical)?),
+            None => None,
+        };
+        let attachments = match discussion_id {
+            Some(discussion_id) => self.store.attachments().get_by_discussion(discussion_id).await?,
+            None => vec![],
+        };
+
+        let mut transaction = self.store.begin().await?;
+        if let Some(discussion_id) = discussion_id {
+            for attachment in &attachments {
+                transaction.attachments().delete(attachment.get_id().ok_or(EngineError::Technical)?).await?;
+            }
+            transaction.message_expirations().delete_by_discussion(discussion_id).await?;
+            transaction.message_edits().delete_by_discussion(discussion_id).await?;
+            transaction.message_mentions().delete_by_discussion(discussion_id).await?;
+            transaction.message_reactions().delete_by_discussion(discussion_id).await?;
+            transaction.pending_message_operations().delete_by_discussion(discussion_id).await?;
+            transaction.messages().delete_by_discussion(discussion_id).await?;
+            transaction.discussions().delete(discussion_id).await?;
+        }
+        transaction.contact_channels().delete(bytes_owned_identity, contact_identity).await?;
+        transaction.contacts().delete(bytes_owned_identity, contact_identity).await?;
+        transaction.commit().await?;
+
+        for attachment in &attachments {
+            // The file may not be downloaded yet
+            let _ = tokio::fs::remove_file(attachment.get_file_path()).await;
+        }
+        self.publish_event(EngineEvent::ContactDeleted { owned_identity: bytes_owned_identity.to_vec(), contact_identity: contact_identity.to_vec() });
+        if let Some(discussion_id) = discussion_id {
+            self.publish_event(EngineEvent::DiscussionDeleted { discussion_id });
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use olvid_core::cryptographic_identity::OwnedCryptographicIdentity;
+
+    use crate::Engine;
+
+    use super::{revocation_statement, REVOCATION_SIGNATURE_PREFIX};
+
+    #[test]
+    fn revocation_is_bound_to_both_identities() {
+        let mut prng = Engine::get_default_hmac_prng().unwrap();
+        let revoker = OwnedCryptographicIdentity::generate_owned_cryptographic_identity("https://server.olvid.io", &mut prng).unwrap();
+        let revoker_identity = revoker.get_crypto_identity().get_identity();
+
+        let signature = revoker.sign(REVOCATION_SIGNATURE_PREFIX, &revocation_statement(&revoker_identity, &[2]).unwrap(), &mut prng).unwrap();
+        let verify = |statement: &[u8]| revoker.get_crypto_identity().verify_signature(REVOCATION_SIGNATURE_PREFIX, statement, &signature).unwrap_or(false);
+        assert!(verify(&revocation_statement(&revoker_identity, &[2]).unwrap()));
+        assert!(!verify(&revocation_statement(&revoker_identity, &[3]).unwrap()));
+        assert!(!verify(&revocation_statement(&[2], &revoker_identity).unwrap()));
+    }
+}
@@ -0,0 +1,16 @@
+//! Changing the secret the database key is wrapped with.
+//!
+//! Only the wrapping of the database key is replaced, the encrypted columns are left as they are.
+
+use crate::{store::{encryption, DatabaseSecret}, Engine, EngineError, Result};
+
+impl Engine {
+    /// Wraps the database key with a new passphrase or key file, the current one being needed to unwrap it
+    pub async fn change_database_secret(&self, current_secret: &DatabaseSecret, new_secret: &DatabaseSecret) -> Result<()> {
+        let database_key = self.store.settings().get_database_key().await?.ok_or(EngineError::DatabaseNotEncrypted)?;
+        let seed = encryption::unwrap_database_key(&database_key, current_secret).await?;
+        let database_key = encryption::wrap_database_key(&seed, new_secret).await?;
+
+        self.store.settings().set_database_key(database_key).await
+    }
+}
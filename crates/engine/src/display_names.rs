@@ -0,0 +1,57 @@
+use crate::{entities::identity::DisplayNameFormat, events::{publish_events, EngineEvent}, Engine, EngineError, Result};
+
+impl Engine {
+    pub async fn get_display_name_format(&self) -> Result<DisplayNameFormat> {
+        self.store.settings().get_display_name_format().await
+    }
+
+    /// Changes how display names are formatted, the ones of every owned identity and contact are updated
+    pub async fn set_display_name_format(&self, format: &DisplayNameFormat) -> Result<()> {
+        let mut events = Vec::new();
+        let mut transaction = self.store.begin().await?;
+        transaction.settings().set_display_name_format(format).await?;
+
+        let owned_identities = transaction.owned_identities().get_all().await?;
+        for owned_identity in owned_identities {
+            let bytes_owned_identity = owned_identity.get_bytes_owned_identity();
+            transaction.owned_identities().refresh_display_name(bytes_owned_identity, format).await?;
+            events.push(EngineEvent::OwnedIdentityUpdated { owned_identity: bytes_owned_identity.to_vec() });
+
+            let contacts = transaction.contacts().get_all(bytes_owned_identity).await?;
+            for contact in contacts {
+                transaction.contacts().refresh_display_name(bytes_owned_identity, contact.get_contact_identity(), format).await?;
+                events.push(EngineEvent::ContactUpdated { owned_identity: bytes_owned_identity.to_vec(), contact_identity: contact.get_contact_identity().to_vec() });
+            }
+        }
+        transaction.commit().await?;
+        publish_events(&self.events, events);
+
+        Ok(())
+    }
+
+    /// Shows a nickname instead of the details of an owned identity, `None` to go back to the details
+    pub async fn set_owned_identity_custom_display_name(&self, bytes_owned_identity: &[u8], custom_display_name: Option<&str>) -> Result<()> {
+        self.get_owned_identity(bytes_owned_identity).await?;
+        let mut transaction = self.store.begin().await?;
+        let format = transaction.settings().get_display_name_format().await?;
+        transaction.owned_identities().set_custom_display_name(bytes_owned_identity, custom_display_name).await?;
+        transaction.owned_identities().refresh_display_name(bytes_owned_identity, &format).await?;
+        transaction.commit().await?;
+        self.publish_event(EngineEvent::OwnedIdentityUpdated { owned_identity: bytes_owned_identity.to_vec() });
+
+        Ok(())
+    }
+
+    /// Shows a nickname instead of the details of a contact, `None` to go back to the details
+    pub async fn set_contact_custom_display_name(&self, bytes_owned_identity: &[u8], contact_identity: &[u8], custom_display_name: Option<&str>) -> Result<()> {
+        self.store.contacts().get(bytes_owned_identity, contact_identity).await?.ok_or(EngineError::UnknownContact)?;
+        let mut transaction = self.store.begin().await?;
+        let format = transaction.settings().get_display_name_format().await?;
+        transaction.contacts().set_custom_display_name(bytes_owned_identity, contact_identity, custom_display_name).await?;
+        transaction.contacts().refresh_display_name(bytes_owned_identity, contact_identity, &format).await?;
+        transaction.commit().await?;
+        self.publish_event(EngineEvent::ContactUpdated { owned_identity: bytes_owned_identity.to_vec(), contact_identity: contact_identity.to_vec() });
+
+        Ok(())
+    }
+}
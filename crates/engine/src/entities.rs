@@ -1 +1,23 @@
-pub mod identity;
\ No newline at end of file
+pub mod attachment;
+pub mod backup_key;
+pub mod blocked_contact;
+pub mod contact;
+pub mod contact_channel;
+pub mod database_key;
+pub mod discussion;
+pub mod group;
+pub mod identity;
+pub mod inbox_message;
+pub mod introduction;
+pub mod keycloak_server;
+pub mod message;
+pub mod message_edit;
+pub mod message_expiration;
+pub mod message_mention;
+pub mod message_reaction;
+pub mod message_search;
+pub mod outbox_message;
+pub mod owned_device;
+pub mod pending_message_operation;
+pub mod return_receipt;
+pub mod trust_establishment;
@@ -0,0 +1,453 @@
+use bon::bon;
+use sqlx::{FromRow, SqliteExecutor};
+
+use crate::{current_timestamp, EngineError, Result};
+
+/// Length of the raw `AES256CTRHMACSHA256Key` of an attachment
+pub const ATTACHMENT_KEY_LENGTH: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttachmentDirection {
+    Outbound,
+    Inbound,
+}
+
+impl From<AttachmentDirection> for i64 {
+    fn from(direction: AttachmentDirection) -> Self {
+        match direction {
+            AttachmentDirection::Outbound => 0,
+            AttachmentDirection::Inbound => 1,
+        }
+    }
+}
+
+impl TryFrom<i64> for AttachmentDirection {
+    type Error = EngineError;
+
+    fn try_from(value: i64) -> std::result::Result<Self, Self::Error> {
+        match value {
+            0 => Ok(AttachmentDirection::Outbound),
+            1 => Ok(AttachmentDirection::Inbound),
+            _ => Err(EngineError::Technical),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttachmentStatus {
+    /// Outbound attachment not attached to a queued message yet
+    Pending,
+    InProgress,
+    Complete,
+    Failed,
+}
+
+impl From<AttachmentStatus> for i64 {
+    fn from(status: AttachmentStatus) -> Self {
+        match status {
+            AttachmentStatus::Pending => 0,
+            AttachmentStatus::InProgress => 1,
+            AttachmentStatus::Complete => 2,
+            AttachmentStatus::Failed => 3,
+        }
+    }
+}
+
+impl TryFrom<i64> for AttachmentStatus {
+    type Error = EngineError;
+
+    fn try_from(value: i64) -> std::result::Result<Self, Self::Error> {
+        match value {
+            0 => Ok(AttachmentStatus::Pending),
+            1 => Ok(AttachmentStatus::InProgress),
+            2 => Ok(AttachmentStatus::Complete),
+            3 => Ok(AttachmentStatus::Failed),
+            _ => Err(EngineError::Technical),
+        }
+    }
+}
+
+/// Encrypted file transferred in chunks through the signed URLs given by the server.
+///
+/// Chunks are transferred in order and `transferred_chunk_count` is saved after each of them, so a transfer
+/// interrupted by a restart resumes from the first missing chunk.
+#[derive(Clone, FromRow, Debug)]
+pub struct Attachment {
+    pub(crate) id: Option<i64>,
+    pub(crate) owned_identity: Vec<u8>,
+    pub(crate) direction: i64,
+    pub(crate) outbox_message_id: Option<i64>,
+    pub(crate) message_uid: Option<Vec<u8>>,
+    pub(crate) attachment_number: i64,
+    pub(crate) key: Vec<u8>,
+    /// Size of the decrypted file
+    pub(crate) size: i64,
+    pub(crate) chunk_length: i64,
+    pub(crate) chunk_count: i64,
+    pub(crate) transferred_chunk_count: i64,
+    /// JSON array of the chunk upload URLs, only for outbound attachments
+    pub(crate) upload_urls: Option<String>,
+    pub(crate) file_path: String,
+    pub(crate) file_name: String,
+    pub(crate) mime_type: String,
+    pub(crate) status: i64,
+    pub(crate) attempt_count: i64,
+    pub(crate) next_attempt_timestamp: i64,
+}
+
+#[bon]
+impl Attachment {
+    #[builder]
+    pub fn new(
+        owned_identity: Vec<u8>,
+        direction: AttachmentDirection,
+        message_uid: Option<Vec<u8>>,
+        #[builder(default)]
+        attachment_number: i64,
+        key: Vec<u8>,
+        size: i64,
+        chunk_length: i64,
+        file_path: String,
+        file_name: String,
+        mime_type: String,
+    ) -> Result<Self> {
+        let status = match direction {
+            AttachmentDirection::Outbound => AttachmentStatus::Pending,
+            AttachmentDirection::Inbound => AttachmentStatus::InProgress,
+        };
+
+        Ok(Self {
+            id: None,
+            owned_identity,
+            direction: direction.into(),
+            outbox_message_id: None,
+            message_uid,
+            attachment_number,
+            key,
+            size,
+            chunk_length,
+            chunk_count: Self::chunk_count_for(size, chunk_length)?,
+            transferred_chunk_count: 0,
+            upload_urls: None,
+            file_path,
+            file_name,
+            mime_type,
+            status: status.into(),
+            attempt_count: 0,
+            next_attempt_timestamp: current_timestamp(),
+        })
+    }
+}
+
+impl Attachment {
+    /// Fails with `InvalidAttachmentMetadata` for a negative size or a chunk length that is not positive, the metadata
+    /// of inbound attachments comes from their sender
+    pub fn chunk_count_for(size: i64, chunk_length: i64) -> Result<i64> {
+        if size < 0 || chunk_length <= 0 {
+            return Err(EngineError::InvalidAttachmentMetadata);
+        }
+        // An empty file still needs one (empty) chunk
+        let chunk_count = size.checked_add(chunk_length - 1).ok_or(EngineError::InvalidAttachmentMetadata)? / chunk_length;
+        Ok(chunk_count.max(1))
+    }
+
+    pub fn get_id(&self) -> Option<i64> {
+        self.id
+    }
+
+    pub fn get_owned_identity(&self) -> &[u8] {
+        &self.owned_identity
+    }
+
+    pub fn get_direction(&self) -> Result<AttachmentDirection> {
+        self.direction.try_into()
+    }
+
+    pub fn get_outbox_message_id(&self) -> Option<i64> {
+        self.outbox_message_id
+    }
+
+    pub fn get_message_uid(&self) -> Option<&[u8]> {
+        self.message_uid.as_deref()
+    }
+
+    pub fn get_attachment_number(&self) -> i64 {
+        self.attachment_number
+    }
+
+    pub fn get_key(&self) -> &[u8] {
+        &self.key
+    }
+
+    pub fn get_size(&self) -> i64 {
+        self.size
+    }
+
+    pub fn get_chunk_length(&self) -> i64 {
+        self.chunk_length
+    }
+
+    pub fn get_chunk_count(&self) -> i64 {
+        self.chunk_count
+    }
+
+    pub fn get_transferred_chunk_count(&self) -> i64 {
+        self.transferred_chunk_count
+    }
+
+    pub fn get_upload_urls(&self) -> Result<Vec<String>> {
+        match &self.upload_urls {
+            Some(upload_urls) => Ok(serde_json::from_str(upload_urls)?),
+            None => Ok(vec![]),
+        }
+    }
+
+    pub fn get_file_path(&self) -> &str {
+        &self.file_path
+    }
+
+    pub fn get_file_name(&self) -> &str {
+        &self.file_name
+    }
+
+    pub fn get_mime_type(&self) -> &str {
+        &self.mime_type
+    }
+
+    pub fn get_status(&self) -> Result<AttachmentStatus> {
+        self.status.try_into()
+    }
+
+    pub fn get_attempt_count(&self) -> i64 {
+        self.attempt_count
+    }
+
+    pub async fn get_by_id<'e>(db: impl SqliteExecutor<'e>, id: i64) -> Result<Option<Attachment>> {
+        let attachment = sqlx::query_as::<_, Attachment>("SELECT * FROM attachments WHERE id = $1")
+            .bind(id)
+            .fetch_optional(db)
+            .await?;
+
+        Ok(attachment)
+    }
+
+    pub async fn get_by_outbox_message<'e>(db: impl SqliteExecutor<'e>, outbox_message_id: i64) -> Result<Vec<Attachment>> {
+        let attachments = sqlx::query_as::<_, Attachment>("SELECT * FROM attachments WHERE outbox_message_id = $1 ORDER BY attachment_number")
+            .bind(outbox_message_id)
+            .fetch_all(db)
+            .await?;
+
+        Ok(attachments)
+    }
+
+    pub async fn get_inbound_by_message<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8], message_uid: &[u8]) -> Result<Vec<Attachment>> {
+        let attachments = sqlx::query_as::<_, Attachment>("SELECT * FROM attachments WHERE owned_identity = $1 AND message_uid = $2 AND direction = $3 ORDER BY attachment_number")
+            .bind(owned_identity)
+            .bind(message_uid)
+            .bind(i64::from(AttachmentDirection::Inbound))
+            .fetch_all(db)
+            .await?;
+
+        Ok(attachments)
+    }
+
+    /// Attachments whose transfer can progress at `timestamp`, outbound ones need their upload URLs
+    pub async fn get_due<'e>(db: impl SqliteExecutor<'e>, timestamp: i64) -> Result<Vec<Attachment>> {
+        let attachments = sqlx::query_as::<_, Attachment>("SELECT * FROM attachments WHERE status = $1 AND next_attempt_timestamp <= $2 AND (direction = $3 OR upload_urls IS NOT NULL) ORDER BY next_attempt_timestamp")
+            .bind(i64::from(AttachmentStatus::InProgress))
+            .bind(timestamp)
+            .bind(i64::from(AttachmentDirection::Inbound))
+            .fetch_all(db)
+            .await?;
+
+        Ok(attachments)
+    }
+
+    pub async fn get_next_attempt_timestamp<'e>(db: impl SqliteExecutor<'e>) -> Result<Option<i64>> {
+        let next_attempt_timestamp: Option<i64> = sqlx::query_scalar("SELECT MIN(next_attempt_timestamp) FROM attachments WHERE status = $1 AND (direction = $2 OR upload_urls IS NOT NULL)")
+            .bind(i64::from(AttachmentStatus::InProgress))
+            .bind(i64::from(AttachmentDirection::Inbound))
+            .fetch_one(db)
+            .await?;
+
+        Ok(next_attempt_timestamp)
+    }
+
+    pub async fn insert<'e>(db: impl SqliteExecutor<'e>, attachment: Attachment) -> Result<i64> {
+        let result = sqlx::query(
+            r#"
+            INSERT INTO attachments
+            (
+                owned_identity,
+                direction,
+                outbox_message_id,
+                message_uid,
+                attachment_number,
+                key,
+                size,
+                chunk_length,
+                chunk_count,
+                transferred_chunk_count,
+                upload_urls,
+                file_path,
+                file_name,
+                mime_type,
+                status,
+                attempt_count,
+                next_attempt_timestamp
+            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17)
+            "#
+        )
+        .bind(attachment.owned_identity)
+        .bind(attachment.direction)
+        .bind(attachment.outbox_message_id)
+        .bind(attachment.message_uid)
+        .bind(attachment.attachment_number)
+        .bind(attachment.key)
+        .bind(attachment.size)
+        .bind(attachment.chunk_length)
+        .bind(attachment.chunk_count)
+        .bind(attachment.transferred_chunk_count)
+        .bind(attachment.upload_urls)
+        .bind(attachment.file_path)
+        .bind(attachment.file_name)
+        .bind(attachment.mime_type)
+        .bind(attachment.status)
+        .bind(attachment.attempt_count)
+        .bind(attachment.next_attempt_timestamp)
+        .execute(db)
+        .await?;
+
+        Ok(result.last_insert_rowid())
+    }
+
+    /// Attaches a pending outbound attachment to the message that carries it
+    pub async fn link_to_outbox_message<'e>(db: impl SqliteExecutor<'e>, id: i64, outbox_message_id: i64, attachment_number: i64) -> Result<()> {
+        let result = sqlx::query("UPDATE attachments SET outbox_message_id = $1, attachment_number = $2, status = $3 WHERE id = $4 AND status = $5 AND direction = $6")
+            .bind(outbox_message_id)
+            .bind(attachment_number)
+            .bind(i64::from(AttachmentStatus::InProgress))
+            .bind(id)
+            .bind(i64::from(AttachmentStatus::Pending))
+            .bind(i64::from(AttachmentDirection::Outbound))
+            .execute(db)
+            .await?;
+
+        match result.rows_affected() {
+            0 => Err(EngineError::Technical),
+            _ => Ok(()),
+        }
+    }
+
+    pub async fn set_upload_urls<'e>(db: impl SqliteExecutor<'e>, id: i64, message_uid: &[u8], upload_urls: &[String]) -> Result<()> {
+        sqlx::query("UPDATE attachments SET message_uid = $1, upload_urls = $2 WHERE id = $3")
+            .bind(message_uid)
+            .bind(serde_json::to_string(upload_urls)?)
+            .bind(id)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn set_transferred_chunk_count<'e>(db: impl SqliteExecutor<'e>, id: i64, transferred_chunk_count: i64) -> Result<()> {
+        sqlx::query("UPDATE attachments SET transferred_chunk_count = $1, attempt_count = 0 WHERE id = $2")
+            .bind(transferred_chunk_count)
+            .bind(id)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn schedule_retry<'e>(db: impl SqliteExecutor<'e>, id: i64, attempt_count: i64, next_attempt_timestamp: i64) -> Result<()> {
+        sqlx::query("UPDATE attachments SET attempt_count = $1, next_attempt_timestamp = $2 WHERE id = $3")
+            .bind(attempt_count)
+            .bind(next_attempt_timestamp)
+            .bind(id)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn set_status<'e>(db: impl SqliteExecutor<'e>, id: i64, status: AttachmentStatus) -> Result<()> {
+        sqlx::query("UPDATE attachments SET status = $1 WHERE id = $2")
+            .bind(i64::from(status))
+            .bind(id)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Only removes the row, the caller deletes the file
+    pub async fn delete<'e>(db: impl SqliteExecutor<'e>, id: i64) -> Result<()> {
+        sqlx::query("DELETE FROM attachments WHERE id = $1")
+            .bind(id)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Only removes the rows, the caller deletes the files
+    pub async fn delete_by_owned_identity<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8]) -> Result<()> {
+        sqlx::query("DELETE FROM attachments WHERE owned_identity = $1")
+            .bind(owned_identity)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Attachments of the messages of a discussion, sent or received
+    pub async fn get_by_discussion<'e>(db: impl SqliteExecutor<'e>, discussion_id: i64) -> Result<Vec<Attachment>> {
+        let attachments = sqlx::query_as::<_, Attachment>(
+            r#"
+            SELECT attachments.* FROM attachments
+            INNER JOIN messages ON messages.outbox_message_id = attachments.outbox_message_id
+                OR (attachments.direction = $2 AND messages.message_uid = attachments.message_uid)
+            INNER JOIN discussions ON discussions.id = messages.discussion_id AND discussions.owned_identity = attachments.owned_identity
+            WHERE messages.discussion_id = $1
+            "#
+        )
+        .bind(discussion_id)
+        .bind(i64::from(AttachmentDirection::Inbound))
+        .fetch_all(db)
+        .await?;
+
+        Ok(attachments)
+    }
+
+    pub async fn get_by_owned_identity<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8]) -> Result<Vec<Attachment>> {
+        let attachments = sqlx::query_as::<_, Attachment>("SELECT * FROM attachments WHERE owned_identity = $1")
+            .bind(owned_identity)
+            .fetch_all(db)
+            .await?;
+
+        Ok(attachments)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::EngineError;
+
+    use super::Attachment;
+
+    #[test]
+    fn chunk_count_rounds_up() {
+        assert_eq!(Attachment::chunk_count_for(0, 10).unwrap(), 1);
+        assert_eq!(Attachment::chunk_count_for(10, 10).unwrap(), 1);
+        assert_eq!(Attachment::chunk_count_for(11, 10).unwrap(), 2);
+        assert_eq!(Attachment::chunk_count_for(35, 10).unwrap(), 4);
+    }
+
+    #[test]
+    fn invalid_metadata_is_rejected() {
+        for (size, chunk_length) in [(10, 0), (10, -1), (-1, 10), (i64::MAX, 10), (i64::MIN, 10)] {
+            assert!(matches!(Attachment::chunk_count_for(size, chunk_length), Err(EngineError::InvalidAttachmentMetadata)));
+        }
+    }
+}
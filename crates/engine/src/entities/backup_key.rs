@@ -0,0 +1,43 @@
+use sqlx::{FromRow, SqliteExecutor};
+
+use crate::{current_timestamp, Result};
+
+/// Seed the backup key is derived from, written down by the user to restore the backups
+#[derive(Clone, FromRow, Debug)]
+pub struct BackupKey {
+    pub(crate) seed: Vec<u8>,
+    pub(crate) creation_timestamp: i64,
+}
+
+impl BackupKey {
+    pub fn new(seed: &[u8]) -> Self {
+        Self { seed: seed.to_vec(), creation_timestamp: current_timestamp() }
+    }
+
+    pub fn get_seed(&self) -> &[u8] {
+        &self.seed
+    }
+
+    pub fn get_creation_timestamp(&self) -> i64 {
+        self.creation_timestamp
+    }
+
+    pub async fn get<'e>(db: impl SqliteExecutor<'e>) -> Result<Option<BackupKey>> {
+        let backup_key = sqlx::query_as::<_, BackupKey>("SELECT seed, creation_timestamp FROM backup_key WHERE id = 0")
+            .fetch_optional(db)
+            .await?;
+
+        Ok(backup_key)
+    }
+
+    /// Replaces the previous backup key
+    pub async fn set<'e>(db: impl SqliteExecutor<'e>, backup_key: BackupKey) -> Result<()> {
+        sqlx::query("INSERT INTO backup_key (id, seed, creation_timestamp) VALUES (0, $1, $2) ON CONFLICT (id) DO UPDATE SET seed = $1, creation_timestamp = $2")
+            .bind(backup_key.seed)
+            .bind(backup_key.creation_timestamp)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,88 @@
+use sqlx::{FromRow, SqliteExecutor};
+
+use crate::{current_timestamp, Result};
+
+/// Identity blocked by an owned identity, whether it is still a contact or not.
+///
+/// Messages from a blocked identity are dropped, and it can't become a contact again until it is unblocked.
+#[derive(Clone, FromRow, Debug)]
+pub struct BlockedContact {
+    pub(crate) id: Option<i64>,
+    pub(crate) owned_identity: Vec<u8>,
+    pub(crate) contact_identity: Vec<u8>,
+    pub(crate) blocked_timestamp: i64,
+}
+
+impl BlockedContact {
+    pub fn new(owned_identity: &[u8], contact_identity: &[u8]) -> Self {
+        Self { id: None, owned_identity: owned_identity.to_vec(), contact_identity: contact_identity.to_vec(), blocked_timestamp: current_timestamp() }
+    }
+
+    pub fn get_id(&self) -> Option<i64> {
+        self.id
+    }
+
+    pub fn get_owned_identity(&self) -> &[u8] {
+        &self.owned_identity
+    }
+
+    pub fn get_contact_identity(&self) -> &[u8] {
+        &self.contact_identity
+    }
+
+    pub fn get_blocked_timestamp(&self) -> i64 {
+        self.blocked_timestamp
+    }
+
+    pub async fn get<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8], contact_identity: &[u8]) -> Result<Option<BlockedContact>> {
+        let blocked_contact = sqlx::query_as::<_, BlockedContact>("SELECT * FROM blocked_contacts WHERE owned_identity = $1 AND contact_identity = $2")
+            .bind(owned_identity)
+            .bind(contact_identity)
+            .fetch_optional(db)
+            .await?;
+
+        Ok(blocked_contact)
+    }
+
+    pub async fn get_all<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8]) -> Result<Vec<BlockedContact>> {
+        let blocked_contacts = sqlx::query_as::<_, BlockedContact>("SELECT * FROM blocked_contacts WHERE owned_identity = $1 ORDER BY blocked_timestamp, id")
+            .bind(owned_identity)
+            .fetch_all(db)
+            .await?;
+
+        Ok(blocked_contacts)
+    }
+
+    /// Returns false when the identity was already blocked
+    pub async fn insert_if_absent<'e>(db: impl SqliteExecutor<'e>, blocked_contact: BlockedContact) -> Result<bool> {
+        let result = sqlx::query("INSERT OR IGNORE INTO blocked_contacts (owned_identity, contact_identity, blocked_timestamp) VALUES ($1, $2, $3)")
+            .bind(blocked_contact.owned_identity)
+            .bind(blocked_contact.contact_identity)
+            .bind(blocked_contact.blocked_timestamp)
+            .execute(db)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Returns false when the identity was not blocked
+    pub async fn delete<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8], contact_identity: &[u8]) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM blocked_contacts WHERE owned_identity = $1 AND contact_identity = $2")
+            .bind(owned_identity)
+            .bind(contact_identity)
+            .execute(db)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Used when the owned identity is deleted
+    pub async fn delete_by_owned_identity<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8]) -> Result<()> {
+        sqlx::query("DELETE FROM blocked_contacts WHERE owned_identity = $1")
+            .bind(owned_identity)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,334 @@
+use olvid_core::cryptographic_identity::CryptographicIdentity;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, SqliteExecutor};
+
+use crate::{current_timestamp, messages::payload::base64_bytes, EngineError, Result};
+
+use super::identity::{resolve_display_name, DisplayNameFormat, JsonIdentityDetails};
+
+/// Raised by an introduction, then by a verification, only a revocation lowers it back
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ContactTrustLevel {
+    /// Added directly, without any proof of its identity
+    Unverified,
+    /// Introduced by another contact, with signed proofs from both sides
+    Introduced,
+    Verified,
+}
+
+impl From<ContactTrustLevel> for i64 {
+    fn from(trust_level: ContactTrustLevel) -> Self {
+        match trust_level {
+            ContactTrustLevel::Unverified => 0,
+            ContactTrustLevel::Introduced => 1,
+            ContactTrustLevel::Verified => 2,
+        }
+    }
+}
+
+impl TryFrom<i64> for ContactTrustLevel {
+    type Error = EngineError;
+
+    fn try_from(value: i64) -> std::result::Result<Self, Self::Error> {
+        match value {
+            0 => Ok(ContactTrustLevel::Unverified),
+            1 => Ok(ContactTrustLevel::Introduced),
+            2 => Ok(ContactTrustLevel::Verified),
+            _ => Err(EngineError::Technical),
+        }
+    }
+}
+
+/// Identity an owned identity can exchange messages with, serialized as is in backups
+#[derive(Clone, FromRow, Serialize, Deserialize, Debug)]
+pub struct Contact {
+    #[serde(skip)]
+    pub(crate) id: Option<i64>,
+    #[serde(with = "base64_bytes")]
+    pub(crate) owned_identity: Vec<u8>,
+    #[serde(with = "base64_bytes")]
+    pub(crate) contact_identity: Vec<u8>,
+    /// JSON serialized `JsonIdentityDetails`
+    pub(crate) identity_details: String,
+    pub(crate) display_name: String,
+    pub(crate) creation_timestamp: i64,
+    pub(crate) trust_level: i64,
+    /// JSON serialized `JsonIdentityDetails`, published by the contact and not accepted yet
+    pub(crate) published_identity_details: Option<String>,
+    /// Latest version of the details published by the contact, accepted or not
+    pub(crate) published_details_version: i64,
+    /// Nickname chosen by the owned identity, shown instead of the formatted details
+    pub(crate) custom_display_name: Option<String>,
+    pub(crate) sort_key: String,
+    /// Details signed by the keycloak server of the owned identity, until the server revokes the contact
+    #[serde(default)]
+    pub(crate) keycloak_managed: bool,
+}
+
+impl Contact {
+    pub fn new(owned_identity: &[u8], contact_identity: &[u8], identity_details: &JsonIdentityDetails, format: &DisplayNameFormat) -> Result<Self> {
+        Ok(Self {
+            id: None,
+            owned_identity: owned_identity.to_vec(),
+            contact_identity: contact_identity.to_vec(),
+            identity_details: serde_json::to_string(identity_details)?,
+            display_name: identity_details.format_display_name(format),
+            creation_timestamp: current_timestamp(),
+            trust_level: ContactTrustLevel::Unverified.into(),
+            published_identity_details: None,
+            published_details_version: 0,
+            custom_display_name: None,
+            sort_key: identity_details.format_sort_key(format),
+            keycloak_managed: false,
+        })
+    }
+
+    pub fn get_id(&self) -> Option<i64> {
+        self.id
+    }
+
+    pub fn get_owned_identity(&self) -> &[u8] {
+        &self.owned_identity
+    }
+
+    pub fn get_contact_identity(&self) -> &[u8] {
+        &self.contact_identity
+    }
+
+    /// Public keys of the contact, used to encrypt the messages sent to it
+    pub fn get_cryptographic_identity(&self) -> Result<CryptographicIdentity> {
+        CryptographicIdentity::from_raw(&self.contact_identity).map_err(|_| EngineError::Technical)
+    }
+
+    pub fn get_identity_details(&self) -> Result<JsonIdentityDetails> {
+        Ok(serde_json::from_str(&self.identity_details)?)
+    }
+
+    pub fn get_display_name(&self) -> &str {
+        &self.display_name
+    }
+
+    pub fn get_custom_display_name(&self) -> Option<&str> {
+        self.custom_display_name.as_deref()
+    }
+
+    /// What contact lists are sorted by
+    pub fn get_sort_key(&self) -> &str {
+        &self.sort_key
+    }
+
+    pub fn get_creation_timestamp(&self) -> i64 {
+        self.creation_timestamp
+    }
+
+    pub fn get_trust_level(&self) -> Result<ContactTrustLevel> {
+        self.trust_level.try_into()
+    }
+
+    pub fn is_keycloak_managed(&self) -> bool {
+        self.keycloak_managed
+    }
+
+    /// Details published by the contact that differ from the trusted ones, until they are accepted
+    pub fn get_published_identity_details(&self) -> Result<Option<JsonIdentityDetails>> {
+        self.published_identity_details.as_deref().map(serde_json::from_str).transpose().map_err(Into::into)
+    }
+
+    pub fn get_published_details_version(&self) -> i64 {
+        self.published_details_version
+    }
+
+    /// Display name and sort key from the details, the custom display name and the format
+    pub(crate) fn format_display_name(&self, format: &DisplayNameFormat) -> Result<(String, String)> {
+        let identity_details = self.get_identity_details()?;
+        let display_name = resolve_display_name(&identity_details, self.get_custom_display_name(), format);
+        let sort_key = match self.get_custom_display_name().filter(|custom_display_name| !custom_display_name.is_empty()) {
+            Some(custom_display_name) => custom_display_name.to_lowercase(),
+            None => identity_details.format_sort_key(format),
+        };
+
+        Ok((display_name, sort_key))
+    }
+
+    pub async fn get<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8], contact_identity: &[u8]) -> Result<Option<Contact>> {
+        let contact = sqlx::query_as::<_, Contact>("SELECT * FROM contacts WHERE owned_identity = $1 AND contact_identity = $2")
+            .bind(owned_identity)
+            .bind(contact_identity)
+            .fetch_optional(db)
+            .await?;
+
+        Ok(contact)
+    }
+
+    pub async fn get_all<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8]) -> Result<Vec<Contact>> {
+        let contacts = sqlx::query_as::<_, Contact>("SELECT * FROM contacts WHERE owned_identity = $1 ORDER BY sort_key, display_name")
+            .bind(owned_identity)
+            .fetch_all(db)
+            .await?;
+
+        Ok(contacts)
+    }
+
+    /// Returns false when the contact already existed
+    pub async fn insert_if_absent<'e>(db: impl SqliteExecutor<'e>, contact: Contact) -> Result<bool> {
+        let result = sqlx::query(
+            r#"
+            INSERT OR IGNORE INTO contacts
+            (
+                owned_identity,
+                contact_identity,
+                identity_details,
+                display_name,
+                creation_timestamp,
+                trust_level,
+                published_identity_details,
+                published_details_version,
+                custom_display_name,
+                sort_key,
+                keycloak_managed
+            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+            "#
+        )
+        .bind(contact.owned_identity)
+        .bind(contact.contact_identity)
+        .bind(contact.identity_details)
+        .bind(contact.display_name)
+        .bind(contact.creation_timestamp)
+        .bind(contact.trust_level)
+        .bind(contact.published_identity_details)
+        .bind(contact.published_details_version)
+        .bind(contact.custom_display_name)
+        .bind(contact.sort_key)
+        .bind(contact.keycloak_managed)
+        .execute(db)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Returns false when the contact already had this trust level or a higher one
+    pub async fn raise_trust_level<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8], contact_identity: &[u8], trust_level: ContactTrustLevel) -> Result<bool> {
+        let result = sqlx::query("UPDATE contacts SET trust_level = $1 WHERE owned_identity = $2 AND contact_identity = $3 AND trust_level < $1")
+            .bind(i64::from(trust_level))
+            .bind(owned_identity)
+            .bind(contact_identity)
+            .execute(db)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Back to unverified once the trust is revoked, returns false when the contact was not trusted
+    pub async fn revoke_trust<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8], contact_identity: &[u8]) -> Result<bool> {
+        let result = sqlx::query("UPDATE contacts SET trust_level = $1 WHERE owned_identity = $2 AND contact_identity = $3 AND trust_level > $1")
+            .bind(i64::from(ContactTrustLevel::Unverified))
+            .bind(owned_identity)
+            .bind(contact_identity)
+            .execute(db)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Keeps details published by the contact, given serialized, `None` when they are the same as the trusted ones
+    pub async fn set_published_details<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8], contact_identity: &[u8], version: i64, published_identity_details: Option<&str>) -> Result<()> {
+        sqlx::query("UPDATE contacts SET published_identity_details = $1, published_details_version = $2 WHERE owned_identity = $3 AND contact_identity = $4")
+            .bind(published_identity_details)
+            .bind(version)
+            .bind(owned_identity)
+            .bind(contact_identity)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Trusts the details published by the contact, returns false when there were none.
+    ///
+    /// The display name is then refreshed by the caller.
+    pub async fn accept_published_details<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8], contact_identity: &[u8]) -> Result<bool> {
+        let result = sqlx::query(
+            r#"
+            UPDATE contacts SET
+                identity_details = published_identity_details,
+                published_identity_details = NULL
+            WHERE owned_identity = $1 AND contact_identity = $2 AND published_identity_details IS NOT NULL
+            "#
+        )
+        .bind(owned_identity)
+        .bind(contact_identity)
+        .execute(db)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// See `ContactRepository::refresh_display_name`, both are computed from the decrypted details
+    pub async fn set_display_name<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8], contact_identity: &[u8], display_name: &str, sort_key: &str) -> Result<()> {
+        sqlx::query("UPDATE contacts SET display_name = $1, sort_key = $2 WHERE owned_identity = $3 AND contact_identity = $4")
+            .bind(display_name)
+            .bind(sort_key)
+            .bind(owned_identity)
+            .bind(contact_identity)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Replaces the details with the ones signed by the keycloak server, given serialized, or only drops the certification.
+    ///
+    /// The display name is then refreshed by the caller.
+    pub async fn set_keycloak_managed<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8], contact_identity: &[u8], signed_details: Option<&str>) -> Result<()> {
+        match signed_details {
+            Some(identity_details) => {
+                sqlx::query("UPDATE contacts SET identity_details = $1, keycloak_managed = TRUE WHERE owned_identity = $2 AND contact_identity = $3")
+                    .bind(identity_details)
+                    .bind(owned_identity)
+                    .bind(contact_identity)
+                    .execute(db)
+                    .await?;
+            }
+            None => {
+                sqlx::query("UPDATE contacts SET keycloak_managed = FALSE WHERE owned_identity = $1 AND contact_identity = $2")
+                    .bind(owned_identity)
+                    .bind(contact_identity)
+                    .execute(db)
+                    .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub async fn set_custom_display_name<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8], contact_identity: &[u8], custom_display_name: Option<&str>) -> Result<()> {
+        sqlx::query("UPDATE contacts SET custom_display_name = $1 WHERE owned_identity = $2 AND contact_identity = $3")
+            .bind(custom_display_name)
+            .bind(owned_identity)
+            .bind(contact_identity)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8], contact_identity: &[u8]) -> Result<()> {
+        sqlx::query("DELETE FROM contacts WHERE owned_identity = $1 AND contact_identity = $2")
+            .bind(owned_identity)
+            .bind(contact_identity)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Used when the owned identity is deleted
+    pub async fn delete_by_owned_identity<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8]) -> Result<()> {
+        sqlx::query("DELETE FROM contacts WHERE owned_identity = $1")
+            .bind(owned_identity)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,86 @@
+use sqlx::{FromRow, SqliteExecutor};
+
+use crate::{current_timestamp, Result};
+
+/// Channel an owned identity encrypts its messages to another identity with, contact or not.
+///
+/// It is created with the first message sent to the identity and kept until it is torn down, the next message then
+/// creates a new one with a new key.
+#[derive(Clone, FromRow, Debug)]
+pub struct ContactChannel {
+    pub(crate) id: Option<i64>,
+    pub(crate) owned_identity: Vec<u8>,
+    pub(crate) contact_identity: Vec<u8>,
+    pub(crate) channel_key: Vec<u8>,
+    pub(crate) creation_timestamp: i64,
+}
+
+impl ContactChannel {
+    pub fn new(owned_identity: &[u8], contact_identity: &[u8], channel_key: Vec<u8>) -> Self {
+        Self { id: None, owned_identity: owned_identity.to_vec(), contact_identity: contact_identity.to_vec(), channel_key, creation_timestamp: current_timestamp() }
+    }
+
+    pub fn get_id(&self) -> Option<i64> {
+        self.id
+    }
+
+    pub fn get_owned_identity(&self) -> &[u8] {
+        &self.owned_identity
+    }
+
+    pub fn get_contact_identity(&self) -> &[u8] {
+        &self.contact_identity
+    }
+
+    pub fn get_channel_key(&self) -> &[u8] {
+        &self.channel_key
+    }
+
+    pub fn get_creation_timestamp(&self) -> i64 {
+        self.creation_timestamp
+    }
+
+    pub async fn get<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8], contact_identity: &[u8]) -> Result<Option<ContactChannel>> {
+        let channel = sqlx::query_as::<_, ContactChannel>("SELECT * FROM contact_channels WHERE owned_identity = $1 AND contact_identity = $2")
+            .bind(owned_identity)
+            .bind(contact_identity)
+            .fetch_optional(db)
+            .await?;
+
+        Ok(channel)
+    }
+
+    /// Returns false when the owned identity already has a channel with the identity
+    pub async fn insert_if_absent<'e>(db: impl SqliteExecutor<'e>, channel: ContactChannel) -> Result<bool> {
+        let result = sqlx::query("INSERT OR IGNORE INTO contact_channels (owned_identity, contact_identity, channel_key, creation_timestamp) VALUES ($1, $2, $3, $4)")
+            .bind(channel.owned_identity)
+            .bind(channel.contact_identity)
+            .bind(channel.channel_key)
+            .bind(channel.creation_timestamp)
+            .execute(db)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Returns false when there was no channel to tear down
+    pub async fn delete<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8], contact_identity: &[u8]) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM contact_channels WHERE owned_identity = $1 AND contact_identity = $2")
+            .bind(owned_identity)
+            .bind(contact_identity)
+            .execute(db)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Used when the owned identity is deleted
+    pub async fn delete_by_owned_identity<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8]) -> Result<()> {
+        sqlx::query("DELETE FROM contact_channels WHERE owned_identity = $1")
+            .bind(owned_identity)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+}
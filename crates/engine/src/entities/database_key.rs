@@ -0,0 +1,67 @@
+use sqlx::{FromRow, SqliteExecutor};
+
+use crate::{EngineError, Result};
+
+/// Key the sensitive columns are encrypted with, wrapped by a key derived from a passphrase or a key file
+#[derive(Clone, FromRow, Debug)]
+pub struct DatabaseKey {
+    pub(crate) wrapped_key: Vec<u8>,
+    pub(crate) kdf_salt: Vec<u8>,
+    pub(crate) kdf_iterations: i64,
+}
+
+impl DatabaseKey {
+    pub fn new(wrapped_key: &[u8], kdf_salt: &[u8], kdf_iterations: i64) -> Self {
+        Self { wrapped_key: wrapped_key.to_vec(), kdf_salt: kdf_salt.to_vec(), kdf_iterations }
+    }
+
+    pub fn get_wrapped_key(&self) -> &[u8] {
+        &self.wrapped_key
+    }
+
+    pub fn get_kdf_salt(&self) -> &[u8] {
+        &self.kdf_salt
+    }
+
+    pub fn get_kdf_iterations(&self) -> i64 {
+        self.kdf_iterations
+    }
+
+    pub async fn get<'e>(db: impl SqliteExecutor<'e>) -> Result<Option<DatabaseKey>> {
+        let database_key = sqlx::query_as::<_, DatabaseKey>("SELECT wrapped_key, kdf_salt, kdf_iterations FROM database_key WHERE id = 0")
+            .fetch_optional(db)
+            .await?;
+
+        Ok(database_key)
+    }
+
+    /// Replaces the previous wrapping, the key itself never changes
+    pub async fn set<'e>(db: impl SqliteExecutor<'e>, database_key: DatabaseKey) -> Result<()> {
+        sqlx::query("INSERT INTO database_key (id, wrapped_key, kdf_salt, kdf_iterations) VALUES (0, $1, $2, $3) ON CONFLICT (id) DO UPDATE SET wrapped_key = $1, kdf_salt = $2, kdf_iterations = $3")
+            .bind(database_key.wrapped_key)
+            .bind(database_key.kdf_salt)
+            .bind(database_key.kdf_iterations)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Columns are counted in the order of `SENSITIVE_COLUMNS`
+    pub async fn get_encrypted_column_count<'e>(db: impl SqliteExecutor<'e>) -> Result<usize> {
+        let encrypted_column_count = sqlx::query_scalar::<_, i64>("SELECT encrypted_column_count FROM database_key WHERE id = 0")
+            .fetch_one(db)
+            .await?;
+
+        usize::try_from(encrypted_column_count).map_err(|_| EngineError::Technical)
+    }
+
+    pub async fn set_encrypted_column_count<'e>(db: impl SqliteExecutor<'e>, encrypted_column_count: usize) -> Result<()> {
+        sqlx::query("UPDATE database_key SET encrypted_column_count = $1 WHERE id = 0")
+            .bind(i64::try_from(encrypted_column_count).map_err(|_| EngineError::Technical)?)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,297 @@
+use sqlx::{FromRow, SqliteExecutor};
+use uuid::Uuid;
+
+use crate::{current_timestamp, EngineError, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiscussionType {
+    OneToOne,
+    Group,
+}
+
+impl From<DiscussionType> for i64 {
+    fn from(discussion_type: DiscussionType) -> Self {
+        match discussion_type {
+            DiscussionType::OneToOne => 0,
+            DiscussionType::Group => 1,
+        }
+    }
+}
+
+impl TryFrom<i64> for DiscussionType {
+    type Error = EngineError;
+
+    fn try_from(value: i64) -> std::result::Result<Self, Self::Error> {
+        match value {
+            0 => Ok(DiscussionType::OneToOne),
+            1 => Ok(DiscussionType::Group),
+            _ => Err(EngineError::Technical),
+        }
+    }
+}
+
+/// Disappearing message settings, durations are in seconds
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Ephemerality {
+    /// Received messages are wiped once read
+    pub read_once: bool,
+    /// Time a message stays readable once read, or once sent for outbound messages
+    pub visibility_duration: Option<i64>,
+    /// Time after which a message is deleted, read or not
+    pub existence_duration: Option<i64>,
+}
+
+impl Ephemerality {
+    pub fn is_ephemeral(&self) -> bool {
+        self.read_once || self.visibility_duration.is_some() || self.existence_duration.is_some()
+    }
+}
+
+/// Conversation of an owned identity, either with one contact or with the members of a group.
+///
+/// The messages sent in a discussion are numbered in the thread given by `sender_thread_identifier`, which lets
+/// recipients reference them.
+#[derive(Clone, FromRow, Debug)]
+pub struct Discussion {
+    pub(crate) id: Option<i64>,
+    pub(crate) owned_identity: Vec<u8>,
+    pub(crate) discussion_type: i64,
+    /// Only for one-to-one discussions
+    pub(crate) contact_identity: Option<Vec<u8>>,
+    /// Only for group discussions
+    pub(crate) group_identifier: Option<Vec<u8>>,
+    pub(crate) title: String,
+    pub(crate) sender_thread_identifier: Vec<u8>,
+    pub(crate) last_message_timestamp: i64,
+    pub(crate) creation_timestamp: i64,
+    /// Overrides the setting of the owned identity when set
+    pub(crate) pref_send_read_receipt: Option<bool>,
+    /// Settings shared with the other participants, only updated by a higher version
+    pub(crate) settings_version: i64,
+    pub(crate) settings_read_once: bool,
+    pub(crate) settings_visibility_duration: Option<i64>,
+    pub(crate) settings_existence_duration: Option<i64>,
+}
+
+impl Discussion {
+    pub fn new_one_to_one(owned_identity: &[u8], contact_identity: &[u8], title: &str) -> Self {
+        Self::new(owned_identity, DiscussionType::OneToOne, Some(contact_identity.to_vec()), None, title)
+    }
+
+    pub fn new_group(owned_identity: &[u8], group_identifier: &[u8], title: &str) -> Self {
+        Self::new(owned_identity, DiscussionType::Group, None, Some(group_identifier.to_vec()), title)
+    }
+
+    fn new(owned_identity: &[u8], discussion_type: DiscussionType, contact_identity: Option<Vec<u8>>, group_identifier: Option<Vec<u8>>, title: &str) -> Self {
+        let timestamp = current_timestamp();
+        Self {
+            id: None,
+            owned_identity: owned_identity.to_vec(),
+            discussion_type: discussion_type.into(),
+            contact_identity,
+            group_identifier,
+            title: title.to_owned(),
+            sender_thread_identifier: Uuid::new_v4().as_bytes().to_vec(),
+            last_message_timestamp: timestamp,
+            creation_timestamp: timestamp,
+            pref_send_read_receipt: None,
+            settings_version: 0,
+            settings_read_once: false,
+            settings_visibility_duration: None,
+            settings_existence_duration: None,
+        }
+    }
+
+    pub fn get_id(&self) -> Option<i64> {
+        self.id
+    }
+
+    pub fn get_owned_identity(&self) -> &[u8] {
+        &self.owned_identity
+    }
+
+    pub fn get_discussion_type(&self) -> Result<DiscussionType> {
+        self.discussion_type.try_into()
+    }
+
+    pub fn get_contact_identity(&self) -> Option<&[u8]> {
+        self.contact_identity.as_deref()
+    }
+
+    pub fn get_group_identifier(&self) -> Option<&[u8]> {
+        self.group_identifier.as_deref()
+    }
+
+    pub fn get_title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn get_sender_thread_identifier(&self) -> Result<Uuid> {
+        Uuid::from_slice(&self.sender_thread_identifier).map_err(|_| EngineError::Technical)
+    }
+
+    pub fn get_last_message_timestamp(&self) -> i64 {
+        self.last_message_timestamp
+    }
+
+    pub fn get_creation_timestamp(&self) -> i64 {
+        self.creation_timestamp
+    }
+
+    pub fn get_pref_send_read_receipt(&self) -> Option<bool> {
+        self.pref_send_read_receipt
+    }
+
+    pub fn get_settings_version(&self) -> i64 {
+        self.settings_version
+    }
+
+    pub fn get_ephemerality(&self) -> Ephemerality {
+        Ephemerality {
+            read_once: self.settings_read_once,
+            visibility_duration: self.settings_visibility_duration,
+            existence_duration: self.settings_existence_duration,
+        }
+    }
+
+    pub async fn get_by_id<'e>(db: impl SqliteExecutor<'e>, id: i64) -> Result<Option<Discussion>> {
+        let discussion = sqlx::query_as::<_, Discussion>("SELECT * FROM discussions WHERE id = $1")
+            .bind(id)
+            .fetch_optional(db)
+            .await?;
+
+        Ok(discussion)
+    }
+
+    pub async fn get_one_to_one<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8], contact_identity: &[u8]) -> Result<Option<Discussion>> {
+        let discussion = sqlx::query_as::<_, Discussion>("SELECT * FROM discussions WHERE owned_identity = $1 AND contact_identity = $2")
+            .bind(owned_identity)
+            .bind(contact_identity)
+            .fetch_optional(db)
+            .await?;
+
+        Ok(discussion)
+    }
+
+    pub async fn get_group<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8], group_identifier: &[u8]) -> Result<Option<Discussion>> {
+        let discussion = sqlx::query_as::<_, Discussion>("SELECT * FROM discussions WHERE owned_identity = $1 AND group_identifier = $2")
+            .bind(owned_identity)
+            .bind(group_identifier)
+            .fetch_optional(db)
+            .await?;
+
+        Ok(discussion)
+    }
+
+    /// Most recently active first
+    pub async fn get_all<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8]) -> Result<Vec<Discussion>> {
+        let discussions = sqlx::query_as::<_, Discussion>("SELECT * FROM discussions WHERE owned_identity = $1 ORDER BY last_message_timestamp DESC")
+            .bind(owned_identity)
+            .fetch_all(db)
+            .await?;
+
+        Ok(discussions)
+    }
+
+    pub async fn insert<'e>(db: impl SqliteExecutor<'e>, discussion: Discussion) -> Result<i64> {
+        let result = sqlx::query(
+            r#"
+            INSERT INTO discussions
+            (
+                owned_identity,
+                discussion_type,
+                contact_identity,
+                group_identifier,
+                title,
+                sender_thread_identifier,
+                last_message_timestamp,
+                creation_timestamp,
+                pref_send_read_receipt
+            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            "#
+        )
+        .bind(discussion.owned_identity)
+        .bind(discussion.discussion_type)
+        .bind(discussion.contact_identity)
+        .bind(discussion.group_identifier)
+        .bind(discussion.title)
+        .bind(discussion.sender_thread_identifier)
+        .bind(discussion.last_message_timestamp)
+        .bind(discussion.creation_timestamp)
+        .bind(discussion.pref_send_read_receipt)
+        .execute(db)
+        .await?;
+
+        Ok(result.last_insert_rowid())
+    }
+
+    pub async fn set_last_message_timestamp<'e>(db: impl SqliteExecutor<'e>, id: i64, last_message_timestamp: i64) -> Result<()> {
+        sqlx::query("UPDATE discussions SET last_message_timestamp = MAX(last_message_timestamp, $1) WHERE id = $2")
+            .bind(last_message_timestamp)
+            .bind(id)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn set_title<'e>(db: impl SqliteExecutor<'e>, id: i64, title: &str) -> Result<()> {
+        sqlx::query("UPDATE discussions SET title = $1 WHERE id = $2")
+            .bind(title)
+            .bind(id)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn set_pref_send_read_receipt<'e>(db: impl SqliteExecutor<'e>, id: i64, pref_send_read_receipt: Option<bool>) -> Result<()> {
+        sqlx::query("UPDATE discussions SET pref_send_read_receipt = $1 WHERE id = $2")
+            .bind(pref_send_read_receipt)
+            .bind(id)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Returns false when the discussion already has settings with this version or a higher one
+    pub async fn update_shared_settings<'e>(db: impl SqliteExecutor<'e>, id: i64, settings_version: i64, ephemerality: &Ephemerality) -> Result<bool> {
+        let result = sqlx::query(
+            r#"
+            UPDATE discussions
+            SET settings_version = $1, settings_read_once = $2, settings_visibility_duration = $3, settings_existence_duration = $4
+            WHERE id = $5 AND settings_version < $1
+            "#
+        )
+        .bind(settings_version)
+        .bind(ephemerality.read_once)
+        .bind(ephemerality.visibility_duration)
+        .bind(ephemerality.existence_duration)
+        .bind(id)
+        .execute(db)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Only removes the discussion, see `Message::delete_by_discussion` for its messages
+    pub async fn delete<'e>(db: impl SqliteExecutor<'e>, id: i64) -> Result<()> {
+        sqlx::query("DELETE FROM discussions WHERE id = $1")
+            .bind(id)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Only removes the discussions, see `Message::delete_by_owned_identity` for their messages
+    pub async fn delete_by_owned_identity<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8]) -> Result<()> {
+        sqlx::query("DELETE FROM discussions WHERE owned_identity = $1")
+            .bind(owned_identity)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,280 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, SqliteConnection, SqliteExecutor};
+
+use crate::{current_timestamp, messages::payload::base64_bytes, EngineError, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum GroupStatus {
+    /// The owned identity is a pending member that did not accept the invitation yet
+    Invited,
+    Joined,
+}
+
+impl From<GroupStatus> for i64 {
+    fn from(status: GroupStatus) -> Self {
+        match status {
+            GroupStatus::Invited => 0,
+            GroupStatus::Joined => 1,
+        }
+    }
+}
+
+impl TryFrom<i64> for GroupStatus {
+    type Error = EngineError;
+
+    fn try_from(value: i64) -> std::result::Result<Self, Self::Error> {
+        match value {
+            0 => Ok(GroupStatus::Invited),
+            1 => Ok(GroupStatus::Joined),
+            _ => Err(EngineError::Technical),
+        }
+    }
+}
+
+/// Group v2 of an owned identity, mirroring the last version of its blob stored on the server.
+///
+/// `blob_key` encrypts the blob, it changes whenever a member is removed.
+#[derive(Clone, FromRow, Serialize, Deserialize, Debug)]
+pub struct Group {
+    #[serde(skip)]
+    pub(crate) id: Option<i64>,
+    #[serde(with = "base64_bytes")]
+    pub(crate) owned_identity: Vec<u8>,
+    #[serde(with = "base64_bytes")]
+    pub(crate) group_uid: Vec<u8>,
+    pub(crate) name: String,
+    pub(crate) version: i64,
+    #[serde(with = "base64_bytes")]
+    pub(crate) blob_key: Vec<u8>,
+    pub(crate) status: i64,
+    pub(crate) creation_timestamp: i64,
+}
+
+impl Group {
+    pub fn new(owned_identity: &[u8], group_uid: &[u8], name: &str, version: i64, blob_key: &[u8], status: GroupStatus) -> Self {
+        Self {
+            id: None,
+            owned_identity: owned_identity.to_vec(),
+            group_uid: group_uid.to_vec(),
+            name: name.to_owned(),
+            version,
+            blob_key: blob_key.to_vec(),
+            status: status.into(),
+            creation_timestamp: current_timestamp(),
+        }
+    }
+
+    pub fn get_id(&self) -> Option<i64> {
+        self.id
+    }
+
+    pub fn get_owned_identity(&self) -> &[u8] {
+        &self.owned_identity
+    }
+
+    pub fn get_group_uid(&self) -> &[u8] {
+        &self.group_uid
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get_version(&self) -> i64 {
+        self.version
+    }
+
+    pub fn get_blob_key(&self) -> &[u8] {
+        &self.blob_key
+    }
+
+    pub fn get_status(&self) -> Result<GroupStatus> {
+        self.status.try_into()
+    }
+
+    pub fn get_creation_timestamp(&self) -> i64 {
+        self.creation_timestamp
+    }
+
+    pub async fn get_by_id<'e>(db: impl SqliteExecutor<'e>, id: i64) -> Result<Option<Group>> {
+        let group = sqlx::query_as::<_, Group>("SELECT * FROM groups_v2 WHERE id = $1")
+            .bind(id)
+            .fetch_optional(db)
+            .await?;
+
+        Ok(group)
+    }
+
+    pub async fn get_by_uid<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8], group_uid: &[u8]) -> Result<Option<Group>> {
+        let group = sqlx::query_as::<_, Group>("SELECT * FROM groups_v2 WHERE owned_identity = $1 AND group_uid = $2")
+            .bind(owned_identity)
+            .bind(group_uid)
+            .fetch_optional(db)
+            .await?;
+
+        Ok(group)
+    }
+
+    pub async fn get_all<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8]) -> Result<Vec<Group>> {
+        let groups = sqlx::query_as::<_, Group>("SELECT * FROM groups_v2 WHERE owned_identity = $1 ORDER BY name")
+            .bind(owned_identity)
+            .fetch_all(db)
+            .await?;
+
+        Ok(groups)
+    }
+
+    /// Inserts the group or updates it with a new version of its blob, a joined group stays joined
+    pub async fn upsert<'e>(db: impl SqliteExecutor<'e>, group: Group) -> Result<i64> {
+        let id = sqlx::query_scalar(
+            r#"
+            INSERT INTO groups_v2
+            (
+                owned_identity,
+                group_uid,
+                name,
+                version,
+                blob_key,
+                status,
+                creation_timestamp
+            ) VALUES ($1, $2, $3, $4, $5, $6, $7)
+            ON CONFLICT (owned_identity, group_uid) DO UPDATE SET
+                name = excluded.name,
+                version = excluded.version,
+                blob_key = excluded.blob_key,
+                status = MAX(status, excluded.status)
+            RETURNING id
+            "#
+        )
+        .bind(group.owned_identity)
+        .bind(group.group_uid)
+        .bind(group.name)
+        .bind(group.version)
+        .bind(group.blob_key)
+        .bind(group.status)
+        .bind(group.creation_timestamp)
+        .fetch_one(db)
+        .await?;
+
+        Ok(id)
+    }
+
+    pub async fn set_status<'e>(db: impl SqliteExecutor<'e>, id: i64, status: GroupStatus) -> Result<()> {
+        sqlx::query("UPDATE groups_v2 SET status = $1 WHERE id = $2")
+            .bind(i64::from(status))
+            .bind(id)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Deletes the group and its members
+    pub async fn delete(connection: &mut SqliteConnection, id: i64) -> Result<()> {
+        sqlx::query("DELETE FROM group_v2_members WHERE group_id = $1")
+            .bind(id)
+            .execute(&mut *connection)
+            .await?;
+        sqlx::query("DELETE FROM groups_v2 WHERE id = $1")
+            .bind(id)
+            .execute(&mut *connection)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Groups of an owned identity with their members, used when the owned identity is deleted
+    pub async fn delete_by_owned_identity(connection: &mut SqliteConnection, owned_identity: &[u8]) -> Result<()> {
+        sqlx::query("DELETE FROM group_v2_members WHERE group_id IN (SELECT id FROM groups_v2 WHERE owned_identity = $1)")
+            .bind(owned_identity)
+            .execute(&mut *connection)
+            .await?;
+        sqlx::query("DELETE FROM groups_v2 WHERE owned_identity = $1")
+            .bind(owned_identity)
+            .execute(&mut *connection)
+            .await?;
+
+        Ok(())
+    }
+}
+
+/// Member of a group, the owned identity included.
+///
+/// Pending members were invited but did not join yet, they don't receive the messages of the group.
+#[derive(Clone, FromRow, Serialize, Deserialize, Debug)]
+pub struct GroupMember {
+    #[serde(skip)]
+    pub(crate) id: Option<i64>,
+    /// Serialized without, the group is restored with another id
+    #[serde(skip)]
+    pub(crate) group_id: i64,
+    #[serde(with = "base64_bytes")]
+    pub(crate) member_identity: Vec<u8>,
+    pub(crate) is_admin: bool,
+    pub(crate) is_pending: bool,
+}
+
+impl GroupMember {
+    pub fn new(group_id: i64, member_identity: &[u8], is_admin: bool, is_pending: bool) -> Self {
+        Self { id: None, group_id, member_identity: member_identity.to_vec(), is_admin, is_pending }
+    }
+
+    pub fn get_id(&self) -> Option<i64> {
+        self.id
+    }
+
+    pub fn get_group_id(&self) -> i64 {
+        self.group_id
+    }
+
+    pub fn get_member_identity(&self) -> &[u8] {
+        &self.member_identity
+    }
+
+    pub fn is_admin(&self) -> bool {
+        self.is_admin
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.is_pending
+    }
+
+    pub async fn get_all<'e>(db: impl SqliteExecutor<'e>, group_id: i64) -> Result<Vec<GroupMember>> {
+        let members = sqlx::query_as::<_, GroupMember>("SELECT * FROM group_v2_members WHERE group_id = $1 ORDER BY id")
+            .bind(group_id)
+            .fetch_all(db)
+            .await?;
+
+        Ok(members)
+    }
+
+    pub async fn get<'e>(db: impl SqliteExecutor<'e>, group_id: i64, member_identity: &[u8]) -> Result<Option<GroupMember>> {
+        let member = sqlx::query_as::<_, GroupMember>("SELECT * FROM group_v2_members WHERE group_id = $1 AND member_identity = $2")
+            .bind(group_id)
+            .bind(member_identity)
+            .fetch_optional(db)
+            .await?;
+
+        Ok(member)
+    }
+
+    /// Replaces the members of a group by the ones of a new version of its blob
+    pub async fn replace_all(connection: &mut SqliteConnection, group_id: i64, members: Vec<GroupMember>) -> Result<()> {
+        sqlx::query("DELETE FROM group_v2_members WHERE group_id = $1")
+            .bind(group_id)
+            .execute(&mut *connection)
+            .await?;
+
+        for member in members {
+            sqlx::query("INSERT INTO group_v2_members (group_id, member_identity, is_admin, is_pending) VALUES ($1, $2, $3, $4)")
+                .bind(group_id)
+                .bind(member.member_identity)
+                .bind(member.is_admin)
+                .bind(member.is_pending)
+                .execute(&mut *connection)
+                .await?;
+        }
+
+        Ok(())
+    }
+}
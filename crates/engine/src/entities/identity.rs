@@ -1,26 +1,125 @@
 use std::collections::HashMap;
 
 use bon::Builder;
-use olvid_core::cryptographic_identity::CryptographicIdentity;
+use olvid_core::cryptographic_identity::{CryptographicIdentity, OwnedCryptographicIdentity};
 use serde::{Deserialize, Serialize};
-use sqlx::{FromRow, SqlitePool};
+use sqlx::{FromRow, SqliteExecutor};
 
-use crate::{EngineError, Result};
+use crate::{current_timestamp, messages::payload::{base64_bytes, optional_base64_bytes}, server::ApiKeyStatus, EngineError, Result};
 
-#[derive(Builder, Serialize, Deserialize, Debug, Clone)]
+#[derive(Builder, Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct JsonIdentityDetails {
-    first_name: String,
-    last_name: Option<String>,
-    company: Option<String>,
-    position: Option<String>,
-    signed_user_details: Option<String>,
-    custom_fields: Option<HashMap<String, String>>,
+    pub(crate) first_name: String,
+    pub(crate) last_name: Option<String>,
+    pub(crate) company: Option<String>,
+    pub(crate) position: Option<String>,
+    pub(crate) signed_user_details: Option<String>,
+    pub(crate) custom_fields: Option<HashMap<String, String>>,
 }
 
 impl JsonIdentityDetails {
-    pub fn format_display_name(&self) -> String {
-        // TODO: handle pref display
-        format!("{}", self.first_name)
+    pub fn format_display_name(&self, format: &DisplayNameFormat) -> String {
+        let names = self.format_names(format.name_order);
+        if !format.show_company_and_position {
+            return names;
+        }
+
+        let position = self.position.as_deref().filter(|position| !position.is_empty());
+        let company = self.company.as_deref().filter(|company| !company.is_empty());
+        match (position, company) {
+            (Some(position), Some(company)) => format!("{names} ({position} @ {company})"),
+            (Some(detail), None) | (None, Some(detail)) => format!("{names} ({detail})"),
+            (None, None) => names,
+        }
+    }
+
+    /// JWS of the details, signed by the keycloak server of the identity
+    pub fn get_signed_user_details(&self) -> Option<&str> {
+        self.signed_user_details.as_deref()
+    }
+
+    /// Case insensitive key in the name order of the format, without the company and position
+    pub fn format_sort_key(&self, format: &DisplayNameFormat) -> String {
+        self.format_names(format.name_order).to_lowercase()
+    }
+
+    fn format_names(&self, name_order: NameOrder) -> String {
+        let last_name = self.last_name.as_deref().filter(|last_name| !last_name.is_empty());
+        match (last_name, name_order) {
+            (None, _) => self.first_name.clone(),
+            (Some(last_name), NameOrder::FirstLast) => format!("{} {}", self.first_name, last_name),
+            (Some(last_name), NameOrder::LastFirst) => format!("{} {}", last_name, self.first_name),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NameOrder {
+    #[default]
+    FirstLast,
+    LastFirst,
+}
+
+impl From<NameOrder> for i64 {
+    fn from(name_order: NameOrder) -> Self {
+        match name_order {
+            NameOrder::FirstLast => 0,
+            NameOrder::LastFirst => 1,
+        }
+    }
+}
+
+impl TryFrom<i64> for NameOrder {
+    type Error = EngineError;
+
+    fn try_from(value: i64) -> std::result::Result<Self, Self::Error> {
+        match value {
+            0 => Ok(NameOrder::FirstLast),
+            1 => Ok(NameOrder::LastFirst),
+            _ => Err(EngineError::Technical),
+        }
+    }
+}
+
+/// How display names are formatted from the identity details, the same for owned identities and contacts
+#[derive(Builder, Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DisplayNameFormat {
+    #[builder(default)]
+    pub name_order: NameOrder,
+    /// Appends them after the names, as in "Alice Liddell (Explorer @ Wonderland)"
+    #[builder(default)]
+    pub show_company_and_position: bool,
+}
+
+impl DisplayNameFormat {
+    /// The default format until one is set
+    pub async fn get<'e>(db: impl SqliteExecutor<'e>) -> Result<DisplayNameFormat> {
+        let row = sqlx::query_as::<_, (i64, bool)>("SELECT name_order, show_company_and_position FROM display_name_format WHERE id = 0")
+            .fetch_optional(db)
+            .await?;
+
+        match row {
+            Some((name_order, show_company_and_position)) => Ok(DisplayNameFormat { name_order: name_order.try_into()?, show_company_and_position }),
+            None => Ok(DisplayNameFormat::default()),
+        }
+    }
+
+    pub async fn set<'e>(db: impl SqliteExecutor<'e>, format: &DisplayNameFormat) -> Result<()> {
+        sqlx::query("INSERT INTO display_name_format (id, name_order, show_company_and_position) VALUES (0, $1, $2) ON CONFLICT (id) DO UPDATE SET name_order = $1, show_company_and_position = $2")
+            .bind(i64::from(format.name_order))
+            .bind(format.show_company_and_position)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+}
+
+/// The nickname chosen by the user wins over the formatted details
+pub(crate) fn resolve_display_name(identity_details: &JsonIdentityDetails, custom_display_name: Option<&str>, format: &DisplayNameFormat) -> String {
+    match custom_display_name.filter(|custom_display_name| !custom_display_name.is_empty()) {
+        Some(custom_display_name) => custom_display_name.to_owned(),
+        None => identity_details.format_display_name(format),
     }
 }
 
@@ -30,11 +129,23 @@ impl TryInto<ObvIdentity> for OwnedIdentity {
     fn try_into(self) -> std::result::Result<ObvIdentity, Self::Error> {
         let parsed_identity_details: JsonIdentityDetails = serde_json::from_str(&self.identity_details)?;
         println!("{:?}", self.bytes_owned_identity);
-        Ok(ObvIdentity::new(
+        let api_key_status = self.get_api_key_status();
+        let mut obv_identity = ObvIdentity::new(
             CryptographicIdentity::from_raw(&self.bytes_owned_identity).map_err(|_| EngineError::Technical)?, 
             parsed_identity_details, 
             self.keycloak_managed, 
-            self.active))
+            self.active);
+        obv_identity.display_name = self.display_name;
+        obv_identity.custom_display_name = self.custom_display_name;
+        obv_identity.photo_url = self.photo_url;
+        obv_identity.unpublished_details = match self.unpublished_details == i64::from(UNPUBLISHED_DETAILS_EXIST) {
+            true => self.unpublished_identity_details.as_deref().map(serde_json::from_str).transpose()?,
+            false => None,
+        };
+        obv_identity.unpublished_photo_url = self.unpublished_photo_url;
+        obv_identity.hidden = self.unlock_password.is_some();
+        obv_identity.api_key_status = api_key_status;
+        Ok(obv_identity)
     }
 }
 
@@ -44,6 +155,16 @@ pub struct ObvIdentity {
     pub identity_details: JsonIdentityDetails,
     pub keycloak_managed: bool,
     pub active: bool,
+    /// Formatted from the details, unless a custom display name is set
+    pub display_name: String,
+    pub custom_display_name: Option<String>,
+    pub photo_url: Option<String>,
+    /// Details edited but not published to the contacts yet
+    pub unpublished_details: Option<JsonIdentityDetails>,
+    pub unpublished_photo_url: Option<String>,
+    /// Only listed once unlocked with its password
+    pub hidden: bool,
+    pub api_key_status: ApiKeyStatus,
 }
 
 impl ObvIdentity {
@@ -55,9 +176,16 @@ impl ObvIdentity {
     ) -> Self {
         Self {
             identity,
+            display_name: identity_details.format_display_name(&DisplayNameFormat::default()),
             identity_details,
             keycloak_managed,
             active,
+            custom_display_name: None,
+            photo_url: None,
+            unpublished_details: None,
+            unpublished_photo_url: None,
+            hidden: false,
+            api_key_status: ApiKeyStatus::default(),
         }
     }
 }
@@ -75,37 +203,58 @@ pub const API_KEY_STATUS_AWAITING_PAYMENT_GRACE_PERIOD: u8 = 6;
 pub const API_KEY_STATUS_AWAITING_PAYMENT_ON_HOLD: u8 = 7;
 pub const API_KEY_STATUS_FREE_TRIAL_KEY_EXPIRED: u8 = 8;
 
-#[derive(Clone, FromRow, Debug)]
+/// Serialized as is in backups
+#[derive(Clone, FromRow, Serialize, Deserialize, Debug)]
 pub struct OwnedIdentity {
-    id: Option<i64>,
-    bytes_owned_identity: Vec<u8>,
-    display_name: String,
-    identity_details: String,
-    api_key_status: i64,
-    unpublished_details: i64,
-    photo_url: Option<String>,
-    api_key_permissions: i64,
-    api_key_expiration_timestamp: Option<i64>,
-    keycloak_managed: bool,
-    active: bool,
-    custom_display_name: Option<String>,
-    unlock_password: Option<Vec<u8>>,
-    unlock_salt: Option<Vec<u8>>,
-    pref_mute_notifications: bool,
-    pref_mute_notifications_except_mentioned: bool,
-    pref_mute_notifications_timestamp: Option<i64>,
-    pref_show_neutral_notification_when_hidden: bool,
-    capability_webrtc_continuous_ice: bool,
-    capability_groups_v2: bool,
-    capability_one_to_one_contacts: bool,
+    #[serde(with = "base64_bytes")]
+    pub(crate) bytes_owned_identity: Vec<u8>,
+    pub(crate) display_name: String,
+    pub(crate) identity_details: String,
+    pub(crate) api_key_status: i64,
+    pub(crate) unpublished_details: i64,
+    pub(crate) photo_url: Option<String>,
+    pub(crate) api_key_permissions: i64,
+    pub(crate) api_key_expiration_timestamp: Option<i64>,
+    pub(crate) keycloak_managed: bool,
+    pub(crate) active: bool,
+    pub(crate) custom_display_name: Option<String>,
+    #[serde(with = "optional_base64_bytes")]
+    pub(crate) unlock_password: Option<Vec<u8>>,
+    #[serde(with = "optional_base64_bytes")]
+    pub(crate) unlock_salt: Option<Vec<u8>>,
+    pub(crate) pref_mute_notifications: bool,
+    pub(crate) pref_mute_notifications_except_mentioned: bool,
+    pub(crate) pref_mute_notifications_timestamp: Option<i64>,
+    pub(crate) pref_show_neutral_notification_when_hidden: bool,
+    pub(crate) capability_webrtc_continuous_ice: bool,
+    pub(crate) capability_groups_v2: bool,
+    pub(crate) capability_one_to_one_contacts: bool,
+    #[serde(with = "optional_base64_bytes")]
+    pub(crate) private_identity: Option<Vec<u8>>,
+    #[serde(with = "optional_base64_bytes")]
+    pub(crate) current_device_uid: Option<Vec<u8>>,
+    pub(crate) pref_send_read_receipt: bool,
+    /// JSON serialized `JsonIdentityDetails`, set when `unpublished_details` is `UNPUBLISHED_DETAILS_EXIST`
+    pub(crate) unpublished_identity_details: Option<String>,
+    pub(crate) unpublished_photo_url: Option<String>,
+    /// Incremented each time the details are published
+    pub(crate) details_version: i64,
+    pub(crate) last_selection_timestamp: i64,
+}
+
+pub const DEVICE_UID_LENGTH: usize = 32;
+
+fn generate_device_uid() -> Result<Vec<u8>> {
+    let mut device_uid = vec![0; DEVICE_UID_LENGTH];
+    getrandom::fill(&mut device_uid).map_err(|_| EngineError::PRNG)?;
+    Ok(device_uid)
 }
 
 impl OwnedIdentity {
-    pub fn new(obv_identity: &ObvIdentity, api_key_status: u8) -> Result<Self> {
+    pub fn new(obv_identity: &ObvIdentity, owned_cryptographic_identity: &OwnedCryptographicIdentity, api_key_status: u8, format: &DisplayNameFormat) -> Result<Self> {
         Ok(Self {
-            id: None,
             bytes_owned_identity: obv_identity.identity.get_identity(),
-            display_name: obv_identity.identity_details.format_display_name(),
+            display_name: resolve_display_name(&obv_identity.identity_details, obv_identity.custom_display_name.as_deref(), format),
             identity_details: serde_json::to_string(&obv_identity.identity_details)?,
             api_key_status: api_key_status.into(),
             unpublished_details: UNPUBLISHED_DETAILS_NOTHING_NEW.into(),
@@ -114,7 +263,7 @@ impl OwnedIdentity {
             api_key_expiration_timestamp: None,
             keycloak_managed: obv_identity.keycloak_managed,
             active: obv_identity.active,
-            custom_display_name: None,
+            custom_display_name: obv_identity.custom_display_name.clone(),
             unlock_password: None,
             unlock_salt: None,
             pref_mute_notifications: false,
@@ -122,19 +271,141 @@ impl OwnedIdentity {
             pref_mute_notifications_timestamp: None,
             pref_show_neutral_notification_when_hidden: false,
             capability_webrtc_continuous_ice: false,
-            capability_groups_v2: false,
+            capability_groups_v2: true,
             capability_one_to_one_contacts: false,
+            private_identity: Some(owned_cryptographic_identity.serialize().map_err(|_| EngineError::Technical)?),
+            current_device_uid: Some(generate_device_uid()?),
+            pref_send_read_receipt: false,
+            unpublished_identity_details: None,
+            unpublished_photo_url: None,
+            details_version: 0,
+            last_selection_timestamp: current_timestamp(),
         })
     }
+
+    pub fn get_bytes_owned_identity(&self) -> &[u8] {
+        &self.bytes_owned_identity
+    }
+
+    pub fn get_identity_details(&self) -> Result<JsonIdentityDetails> {
+        Ok(serde_json::from_str(&self.identity_details)?)
+    }
+
+    pub fn get_display_name(&self) -> &str {
+        &self.display_name
+    }
+
+    pub fn get_custom_display_name(&self) -> Option<&str> {
+        self.custom_display_name.as_deref()
+    }
+
+    pub fn get_photo_url(&self) -> Option<&str> {
+        self.photo_url.as_deref()
+    }
+
+    /// Inactive identities don't receive messages
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Its details are signed by its keycloak server, see `KeycloakServer`
+    pub fn is_keycloak_managed(&self) -> bool {
+        self.keycloak_managed
+    }
+
+    pub fn get_api_key_status(&self) -> ApiKeyStatus {
+        ApiKeyStatus {
+            status: self.api_key_status,
+            permissions: self.api_key_permissions,
+            expiration_timestamp: self.api_key_expiration_timestamp,
+        }
+    }
+
+    pub fn is_hidden(&self) -> bool {
+        self.unlock_password.is_some()
+    }
+
+    /// Salted hash of the password of a hidden identity, with its salt
+    pub fn get_unlock_password(&self) -> Option<(&[u8], &[u8])> {
+        Some((self.unlock_password.as_deref()?, self.unlock_salt.as_deref()?))
+    }
+
+    pub fn get_last_selection_timestamp(&self) -> i64 {
+        self.last_selection_timestamp
+    }
+
+    pub fn get_details_version(&self) -> i64 {
+        self.details_version
+    }
+
+    /// Details and photo edited but not published yet
+    pub fn get_unpublished_details(&self) -> Result<Option<(JsonIdentityDetails, Option<&str>)>> {
+        if self.unpublished_details != i64::from(UNPUBLISHED_DETAILS_EXIST) {
+            return Ok(None);
+        }
+        let identity_details = self.unpublished_identity_details.as_deref().ok_or(EngineError::Technical)?;
+
+        Ok(Some((serde_json::from_str(identity_details)?, self.unpublished_photo_url.as_deref())))
+    }
+
+    /// Identities created before private keys were persisted can't be used with the server
+    pub fn get_private_identity(&self) -> Result<OwnedCryptographicIdentity> {
+        let private_identity = self.private_identity.as_ref().ok_or(EngineError::Technical)?;
+        OwnedCryptographicIdentity::deserialize(private_identity).map_err(|_| EngineError::Technical)
+    }
+
+    pub fn get_current_device_uid(&self) -> Result<&[u8]> {
+        self.current_device_uid.as_deref().ok_or(EngineError::Technical)
+    }
+
+    /// The same identity on a new device, as when a profile is transferred
+    pub fn with_new_device(self) -> Result<Self> {
+        Ok(Self { current_device_uid: Some(generate_device_uid()?), ..self })
+    }
+
+    /// Default for the discussions that don't override it
+    pub fn get_pref_send_read_receipt(&self) -> bool {
+        self.pref_send_read_receipt
+    }
+
+    pub fn get_pref_mute_notifications(&self) -> bool {
+        self.pref_mute_notifications
+    }
+
+    /// End of the muting, `None` mutes until unmuted
+    pub fn get_pref_mute_notifications_timestamp(&self) -> Option<i64> {
+        self.pref_mute_notifications_timestamp
+    }
+
+    /// The messages mentioning the owned identity still notify while muted
+    pub fn get_pref_mute_notifications_except_mentioned(&self) -> bool {
+        self.pref_mute_notifications_except_mentioned
+    }
+
+    /// Whether a message received at `timestamp` notifies
+    pub(crate) fn notifies(&self, timestamp: i64, mentioned: bool) -> bool {
+        let is_muted = self.pref_mute_notifications && self.pref_mute_notifications_timestamp.is_none_or(|until| timestamp < until);
+        !is_muted || (mentioned && self.pref_mute_notifications_except_mentioned)
+    }
     
-    pub async fn get_all(db :&SqlitePool) -> Result<Vec<OwnedIdentity>> {
-        let owned_identites = sqlx::query_as!(OwnedIdentity,
-        "SELECT * FROM identities").fetch_all(db).await?;
+    pub async fn get_all<'e>(db: impl SqliteExecutor<'e>) -> Result<Vec<OwnedIdentity>> {
+        let owned_identites = sqlx::query_as::<_, OwnedIdentity>("SELECT * FROM identities")
+            .fetch_all(db)
+            .await?;
 
         Ok(owned_identites)
     }
 
-    pub async fn insert(db: &SqlitePool, owned_identity: OwnedIdentity) -> Result<()> {
+    pub async fn get_by_identity<'e>(db: impl SqliteExecutor<'e>, bytes_owned_identity: &[u8]) -> Result<Option<OwnedIdentity>> {
+        let owned_identity = sqlx::query_as::<_, OwnedIdentity>("SELECT * FROM identities WHERE bytes_owned_identity = $1")
+            .bind(bytes_owned_identity)
+            .fetch_optional(db)
+            .await?;
+
+        Ok(owned_identity)
+    }
+
+    pub async fn insert<'e>(db: impl SqliteExecutor<'e>, owned_identity: OwnedIdentity) -> Result<()> {
         sqlx::query(
             r#"
             INSERT INTO identities 
@@ -158,8 +429,15 @@ impl OwnedIdentity {
                 pref_show_neutral_notification_when_hidden,
                 capability_webrtc_continuous_ice,
                 capability_groups_v2,
-                capability_one_to_one_contacts
-            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20)
+                capability_one_to_one_contacts,
+                private_identity,
+                current_device_uid,
+                pref_send_read_receipt,
+                unpublished_identity_details,
+                unpublished_photo_url,
+                details_version,
+                last_selection_timestamp
+            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21, $22, $23, $24, $25, $26, $27)
             "#
         )
         .bind(owned_identity.bytes_owned_identity)
@@ -182,11 +460,168 @@ impl OwnedIdentity {
         .bind(owned_identity.capability_webrtc_continuous_ice)
         .bind(owned_identity.capability_groups_v2)
         .bind(owned_identity.capability_one_to_one_contacts)
+        .bind(owned_identity.private_identity)
+        .bind(owned_identity.current_device_uid)
+        .bind(owned_identity.pref_send_read_receipt)
+        .bind(owned_identity.unpublished_identity_details)
+        .bind(owned_identity.unpublished_photo_url)
+        .bind(owned_identity.details_version)
+        .bind(owned_identity.last_selection_timestamp)
         .execute(db)
         .await?;
 
         Ok(())
     }
+
+    pub async fn set_pref_send_read_receipt<'e>(db: impl SqliteExecutor<'e>, bytes_owned_identity: &[u8], pref_send_read_receipt: bool) -> Result<()> {
+        sqlx::query("UPDATE identities SET pref_send_read_receipt = $1 WHERE bytes_owned_identity = $2")
+            .bind(pref_send_read_receipt)
+            .bind(bytes_owned_identity)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn set_pref_mute_notifications<'e>(db: impl SqliteExecutor<'e>, bytes_owned_identity: &[u8], mute: bool, until: Option<i64>, except_mentioned: bool) -> Result<()> {
+        sqlx::query("UPDATE identities SET pref_mute_notifications = $1, pref_mute_notifications_timestamp = $2, pref_mute_notifications_except_mentioned = $3 WHERE bytes_owned_identity = $4")
+            .bind(mute)
+            .bind(until)
+            .bind(except_mentioned)
+            .bind(bytes_owned_identity)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Keeps edited details until they are published, `UNPUBLISHED_DETAILS_EXIST` until then.
+    ///
+    /// The details are given serialized, as they are stored.
+    pub async fn set_unpublished_details<'e>(db: impl SqliteExecutor<'e>, bytes_owned_identity: &[u8], identity_details: &str, photo_url: Option<&str>) -> Result<()> {
+        sqlx::query("UPDATE identities SET unpublished_identity_details = $1, unpublished_photo_url = $2, unpublished_details = $3 WHERE bytes_owned_identity = $4")
+            .bind(identity_details)
+            .bind(photo_url)
+            .bind(i64::from(UNPUBLISHED_DETAILS_EXIST))
+            .bind(bytes_owned_identity)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Makes the unpublished details the published ones, returns their new version. The details are given serialized.
+    pub async fn publish_details<'e>(db: impl SqliteExecutor<'e>, bytes_owned_identity: &[u8], identity_details: &str, display_name: &str, photo_url: Option<&str>) -> Result<i64> {
+        let details_version = sqlx::query_scalar::<_, i64>(
+            r#"
+            UPDATE identities SET
+                identity_details = $1,
+                display_name = $2,
+                photo_url = $3,
+                unpublished_identity_details = NULL,
+                unpublished_photo_url = NULL,
+                unpublished_details = $4,
+                details_version = details_version + 1
+            WHERE bytes_owned_identity = $5
+            RETURNING details_version
+            "#
+        )
+        .bind(identity_details)
+        .bind(display_name)
+        .bind(photo_url)
+        .bind(i64::from(UNPUBLISHED_DETAILS_NOTHING_NEW))
+        .bind(bytes_owned_identity)
+        .fetch_one(db)
+        .await?;
+
+        Ok(details_version)
+    }
+
+    /// See `OwnedIdentityRepository::refresh_display_name`, the display name is computed from the decrypted details
+    pub async fn set_display_name<'e>(db: impl SqliteExecutor<'e>, bytes_owned_identity: &[u8], display_name: &str) -> Result<()> {
+        sqlx::query("UPDATE identities SET display_name = $1 WHERE bytes_owned_identity = $2")
+            .bind(display_name)
+            .bind(bytes_owned_identity)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn set_custom_display_name<'e>(db: impl SqliteExecutor<'e>, bytes_owned_identity: &[u8], custom_display_name: Option<&str>) -> Result<()> {
+        sqlx::query("UPDATE identities SET custom_display_name = $1 WHERE bytes_owned_identity = $2")
+            .bind(custom_display_name)
+            .bind(bytes_owned_identity)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// `None` makes the identity visible again
+    pub async fn set_unlock_password<'e>(db: impl SqliteExecutor<'e>, bytes_owned_identity: &[u8], unlock_password: Option<(&[u8], &[u8])>) -> Result<()> {
+        let (unlock_password, unlock_salt) = unlock_password.unzip();
+        sqlx::query("UPDATE identities SET unlock_password = $1, unlock_salt = $2 WHERE bytes_owned_identity = $3")
+            .bind(unlock_password)
+            .bind(unlock_salt)
+            .bind(bytes_owned_identity)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn set_active<'e>(db: impl SqliteExecutor<'e>, bytes_owned_identity: &[u8], active: bool) -> Result<()> {
+        sqlx::query("UPDATE identities SET active = $1 WHERE bytes_owned_identity = $2")
+            .bind(active)
+            .bind(bytes_owned_identity)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn set_keycloak_managed<'e>(db: impl SqliteExecutor<'e>, bytes_owned_identity: &[u8], keycloak_managed: bool) -> Result<()> {
+        sqlx::query("UPDATE identities SET keycloak_managed = $1 WHERE bytes_owned_identity = $2")
+            .bind(keycloak_managed)
+            .bind(bytes_owned_identity)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn set_api_key_status<'e>(db: impl SqliteExecutor<'e>, bytes_owned_identity: &[u8], api_key_status: &ApiKeyStatus) -> Result<()> {
+        sqlx::query("UPDATE identities SET api_key_status = $1, api_key_permissions = $2, api_key_expiration_timestamp = $3 WHERE bytes_owned_identity = $4")
+            .bind(api_key_status.status)
+            .bind(api_key_status.permissions)
+            .bind(api_key_status.expiration_timestamp)
+            .bind(bytes_owned_identity)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn set_last_selection_timestamp<'e>(db: impl SqliteExecutor<'e>, bytes_owned_identity: &[u8], last_selection_timestamp: i64) -> Result<()> {
+        sqlx::query("UPDATE identities SET last_selection_timestamp = $1 WHERE bytes_owned_identity = $2")
+            .bind(last_selection_timestamp)
+            .bind(bytes_owned_identity)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Only removes the identity, see `Engine::delete_owned_identity` for everything that belongs to it
+    pub async fn delete<'e>(db: impl SqliteExecutor<'e>, bytes_owned_identity: &[u8]) -> Result<()> {
+        sqlx::query("DELETE FROM identities WHERE bytes_owned_identity = $1")
+            .bind(bytes_owned_identity)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -195,7 +630,7 @@ mod tests {
 
     use crate::Engine;
 
-    use super::{CryptographicIdentity, JsonIdentityDetails, ObvIdentity, OwnedIdentity, API_KEY_STATUS_UNKNOWN};
+    use super::{DisplayNameFormat, JsonIdentityDetails, NameOrder, ObvIdentity, OwnedIdentity, API_KEY_STATUS_UNKNOWN};
 
     #[test]
     fn test_new() {
@@ -204,8 +639,28 @@ mod tests {
         
         let obv_identity = ObvIdentity::new(owned_identity.get_crypto_identity(), json_identity_details, false, true);
 
-        let owned_identity = OwnedIdentity::new(&obv_identity, API_KEY_STATUS_UNKNOWN).unwrap();
+        let owned_identity = OwnedIdentity::new(&obv_identity, &owned_identity, API_KEY_STATUS_UNKNOWN, &DisplayNameFormat::default()).unwrap();
+        assert_eq!(owned_identity.get_private_identity().unwrap().get_crypto_identity().get_identity(), obv_identity.identity.get_identity());
         // let raw_identity: Vec<u8> = vec![104, 116, 116, 112, 115, 58, 47, 47, 115, 101, 114, 118, 101, 114, 46, 111, 108, 118, 105, 100, 46, 105, 111, 0, 0, 128, 0, 0, 0, 31, 84, 186, 125, 239, 79, 221, 86, 70, 172, 140, 108, 137, 250, 146, 195, 155, 220, 148, 1, 163, 129, 252, 208, 251, 28, 86, 127, 134, 203, 120, 98, 50, 1, 128, 0, 0, 0, 31, 20, 204, 136, 15, 16, 37, 85, 11, 173, 33, 41, 173, 114, 59, 165, 110, 190, 73, 20, 7, 29, 143, 213, 126, 90, 233, 185, 155, 231, 239, 139, 83];
         // let test = CryptographicIdentity::from_raw(&raw_identity).unwrap();
     }
+
+    #[test]
+    fn display_names_follow_the_format() {
+        let identity_details = JsonIdentityDetails::builder()
+            .first_name("Alice".to_owned())
+            .last_name("Liddell".to_owned())
+            .company("Wonderland".to_owned())
+            .position("Explorer".to_owned())
+            .build();
+
+        assert_eq!(identity_details.format_display_name(&DisplayNameFormat::default()), "Alice Liddell");
+        let last_first = DisplayNameFormat::builder().name_order(NameOrder::LastFirst).show_company_and_position(true).build();
+        assert_eq!(identity_details.format_display_name(&last_first), "Liddell Alice (Explorer @ Wonderland)");
+        assert_eq!(identity_details.format_sort_key(&last_first), "liddell alice");
+
+        let first_name_only = JsonIdentityDetails::builder().first_name("Bob".to_owned()).company("Olvid".to_owned()).build();
+        assert_eq!(first_name_only.format_display_name(&last_first), "Bob (Olvid)");
+        assert_eq!(super::resolve_display_name(&first_name_only, Some("Bobby"), &last_first), "Bobby");
+    }
 }
\ No newline at end of file
@@ -0,0 +1,127 @@
+use sqlx::{FromRow, SqliteExecutor};
+
+use crate::{current_timestamp, server::DownloadedMessage, Result};
+
+/// Message downloaded from the server, persisted before it is processed so it can't be lost.
+///
+/// A message is only deleted from the server once stored, and the `(owned_identity, message_uid)` pair is unique,
+/// so downloading it again after a crash doesn't store it twice.
+#[derive(Clone, FromRow, Debug)]
+pub struct InboxMessage {
+    pub(crate) id: i64,
+    pub(crate) owned_identity: Vec<u8>,
+    pub(crate) message_uid: Vec<u8>,
+    pub(crate) server_timestamp: i64,
+    pub(crate) wrapped_key: Vec<u8>,
+    pub(crate) encrypted_content: Vec<u8>,
+    pub(crate) attachment_count: i64,
+    pub(crate) processed: bool,
+    pub(crate) reception_timestamp: i64,
+}
+
+impl InboxMessage {
+    pub fn get_id(&self) -> i64 {
+        self.id
+    }
+
+    pub fn get_owned_identity(&self) -> &[u8] {
+        &self.owned_identity
+    }
+
+    pub fn get_message_uid(&self) -> &[u8] {
+        &self.message_uid
+    }
+
+    pub fn get_server_timestamp(&self) -> i64 {
+        self.server_timestamp
+    }
+
+    pub fn get_wrapped_key(&self) -> &[u8] {
+        &self.wrapped_key
+    }
+
+    pub fn get_encrypted_content(&self) -> &[u8] {
+        &self.encrypted_content
+    }
+
+    pub fn get_attachment_count(&self) -> i64 {
+        self.attachment_count
+    }
+
+    pub fn is_processed(&self) -> bool {
+        self.processed
+    }
+
+    pub fn get_reception_timestamp(&self) -> i64 {
+        self.reception_timestamp
+    }
+
+    /// Returns false when the message was already stored
+    pub async fn insert_if_absent<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8], message: &DownloadedMessage) -> Result<bool> {
+        let result = sqlx::query(
+            r#"
+            INSERT OR IGNORE INTO inbox_messages
+            (
+                owned_identity,
+                message_uid,
+                server_timestamp,
+                wrapped_key,
+                encrypted_content,
+                attachment_count,
+                processed,
+                reception_timestamp
+            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            "#
+        )
+        .bind(owned_identity)
+        .bind(&message.message_uid)
+        .bind(message.server_timestamp)
+        .bind(&message.wrapped_key)
+        .bind(&message.encrypted_content)
+        .bind(message.attachment_count)
+        .bind(false)
+        .bind(current_timestamp())
+        .execute(db)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn get_by_message_uid<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8], message_uid: &[u8]) -> Result<Option<InboxMessage>> {
+        let inbox_message = sqlx::query_as::<_, InboxMessage>("SELECT * FROM inbox_messages WHERE owned_identity = $1 AND message_uid = $2")
+            .bind(owned_identity)
+            .bind(message_uid)
+            .fetch_optional(db)
+            .await?;
+
+        Ok(inbox_message)
+    }
+
+    pub async fn get_unprocessed<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8]) -> Result<Vec<InboxMessage>> {
+        let inbox_messages = sqlx::query_as::<_, InboxMessage>("SELECT * FROM inbox_messages WHERE owned_identity = $1 AND processed = FALSE ORDER BY server_timestamp, id")
+            .bind(owned_identity)
+            .fetch_all(db)
+            .await?;
+
+        Ok(inbox_messages)
+    }
+
+    pub async fn mark_processed<'e>(db: impl SqliteExecutor<'e>, id: i64) -> Result<()> {
+        sqlx::query("UPDATE inbox_messages SET processed = TRUE WHERE id = $1")
+            .bind(id)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Used when the owned identity is deleted
+    pub async fn delete_by_owned_identity<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8]) -> Result<()> {
+        sqlx::query("DELETE FROM inbox_messages WHERE owned_identity = $1")
+            .bind(owned_identity)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,202 @@
+use sqlx::{FromRow, SqliteExecutor};
+
+use crate::{current_timestamp, EngineError, Result};
+
+use super::identity::JsonIdentityDetails;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntroductionStatus {
+    /// Waiting for the owned identity to answer
+    Pending,
+    /// Accepted by the owned identity, waiting for the introduced identity
+    Accepted,
+    Rejected,
+    /// Accepted by both sides, the introduced identity is a contact
+    Completed,
+}
+
+impl From<IntroductionStatus> for i64 {
+    fn from(status: IntroductionStatus) -> Self {
+        match status {
+            IntroductionStatus::Pending => 0,
+            IntroductionStatus::Accepted => 1,
+            IntroductionStatus::Rejected => 2,
+            IntroductionStatus::Completed => 3,
+        }
+    }
+}
+
+impl TryFrom<i64> for IntroductionStatus {
+    type Error = EngineError;
+
+    fn try_from(value: i64) -> std::result::Result<Self, Self::Error> {
+        match value {
+            0 => Ok(IntroductionStatus::Pending),
+            1 => Ok(IntroductionStatus::Accepted),
+            2 => Ok(IntroductionStatus::Rejected),
+            3 => Ok(IntroductionStatus::Completed),
+            _ => Err(EngineError::Technical),
+        }
+    }
+}
+
+/// Introduction of `contact_identity` to an owned identity by one of its contacts.
+///
+/// `contact_signature` is the acceptance signed by the introduced identity, kept until the owned identity answers.
+#[derive(Clone, FromRow, Debug)]
+pub struct Introduction {
+    pub(crate) id: Option<i64>,
+    pub(crate) owned_identity: Vec<u8>,
+    pub(crate) introduction_uid: Vec<u8>,
+    pub(crate) introducer_identity: Vec<u8>,
+    pub(crate) contact_identity: Vec<u8>,
+    /// JSON serialized `JsonIdentityDetails`, the ones given by the introducer until the introduced identity accepts
+    pub(crate) identity_details: String,
+    pub(crate) status: i64,
+    pub(crate) contact_signature: Option<Vec<u8>>,
+    pub(crate) creation_timestamp: i64,
+}
+
+impl Introduction {
+    pub fn new(owned_identity: &[u8], introduction_uid: &[u8], introducer_identity: &[u8], contact_identity: &[u8], identity_details: &JsonIdentityDetails) -> Result<Self> {
+        Ok(Self {
+            id: None,
+            owned_identity: owned_identity.to_vec(),
+            introduction_uid: introduction_uid.to_vec(),
+            introducer_identity: introducer_identity.to_vec(),
+            contact_identity: contact_identity.to_vec(),
+            identity_details: serde_json::to_string(identity_details)?,
+            status: IntroductionStatus::Pending.into(),
+            contact_signature: None,
+            creation_timestamp: current_timestamp(),
+        })
+    }
+
+    pub fn get_id(&self) -> Option<i64> {
+        self.id
+    }
+
+    pub fn get_owned_identity(&self) -> &[u8] {
+        &self.owned_identity
+    }
+
+    pub fn get_introduction_uid(&self) -> &[u8] {
+        &self.introduction_uid
+    }
+
+    pub fn get_introducer_identity(&self) -> &[u8] {
+        &self.introducer_identity
+    }
+
+    pub fn get_contact_identity(&self) -> &[u8] {
+        &self.contact_identity
+    }
+
+    pub fn get_identity_details(&self) -> Result<JsonIdentityDetails> {
+        Ok(serde_json::from_str(&self.identity_details)?)
+    }
+
+    pub fn get_status(&self) -> Result<IntroductionStatus> {
+        self.status.try_into()
+    }
+
+    pub fn get_contact_signature(&self) -> Option<&[u8]> {
+        self.contact_signature.as_deref()
+    }
+
+    pub fn get_creation_timestamp(&self) -> i64 {
+        self.creation_timestamp
+    }
+
+    pub async fn get_by_id<'e>(db: impl SqliteExecutor<'e>, id: i64) -> Result<Option<Introduction>> {
+        let introduction = sqlx::query_as::<_, Introduction>("SELECT * FROM introductions WHERE id = $1")
+            .bind(id)
+            .fetch_optional(db)
+            .await?;
+
+        Ok(introduction)
+    }
+
+    pub async fn get_by_uid<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8], introduction_uid: &[u8]) -> Result<Option<Introduction>> {
+        let introduction = sqlx::query_as::<_, Introduction>("SELECT * FROM introductions WHERE owned_identity = $1 AND introduction_uid = $2")
+            .bind(owned_identity)
+            .bind(introduction_uid)
+            .fetch_optional(db)
+            .await?;
+
+        Ok(introduction)
+    }
+
+    /// Most recent first
+    pub async fn get_all<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8]) -> Result<Vec<Introduction>> {
+        let introductions = sqlx::query_as::<_, Introduction>("SELECT * FROM introductions WHERE owned_identity = $1 ORDER BY creation_timestamp DESC, id DESC")
+            .bind(owned_identity)
+            .fetch_all(db)
+            .await?;
+
+        Ok(introductions)
+    }
+
+    /// Returns the id of the new introduction, `None` when it was already received
+    pub async fn insert_if_absent<'e>(db: impl SqliteExecutor<'e>, introduction: Introduction) -> Result<Option<i64>> {
+        let result = sqlx::query(
+            r#"
+            INSERT OR IGNORE INTO introductions
+            (
+                owned_identity,
+                introduction_uid,
+                introducer_identity,
+                contact_identity,
+                identity_details,
+                status,
+                contact_signature,
+                creation_timestamp
+            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            "#
+        )
+        .bind(introduction.owned_identity)
+        .bind(introduction.introduction_uid)
+        .bind(introduction.introducer_identity)
+        .bind(introduction.contact_identity)
+        .bind(introduction.identity_details)
+        .bind(introduction.status)
+        .bind(introduction.contact_signature)
+        .bind(introduction.creation_timestamp)
+        .execute(db)
+        .await?;
+
+        Ok((result.rows_affected() > 0).then(|| result.last_insert_rowid()))
+    }
+
+    pub async fn set_status<'e>(db: impl SqliteExecutor<'e>, id: i64, status: IntroductionStatus) -> Result<()> {
+        sqlx::query("UPDATE introductions SET status = $1 WHERE id = $2")
+            .bind(i64::from(status))
+            .bind(id)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Keeps the acceptance of the introduced identity, with the details it sent along
+    pub async fn set_contact_acceptance<'e>(db: impl SqliteExecutor<'e>, id: i64, identity_details: &str, contact_signature: &[u8]) -> Result<()> {
+        sqlx::query("UPDATE introductions SET identity_details = $1, contact_signature = $2 WHERE id = $3")
+            .bind(identity_details)
+            .bind(contact_signature)
+            .bind(id)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Used when the owned identity is deleted
+    pub async fn delete_by_owned_identity<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8]) -> Result<()> {
+        sqlx::query("DELETE FROM introductions WHERE owned_identity = $1")
+            .bind(owned_identity)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,184 @@
+use bon::bon;
+use jose_jwk::{JwkSet, Key};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, SqliteExecutor};
+
+use crate::{messages::payload::base64_bytes, EngineError, Result};
+
+/// Keycloak server managing an owned identity, serialized in backups without the authentication state
+#[derive(Clone, FromRow, Serialize, Deserialize, Debug)]
+pub struct KeycloakServer {
+    #[serde(with = "base64_bytes")]
+    pub(crate) owned_identity: Vec<u8>,
+    pub(crate) server_url: String,
+    pub(crate) client_id: String,
+    pub(crate) client_secret: Option<String>,
+    /// JSON serialized `JwkSet` of the server
+    pub(crate) jwks: String,
+    /// JSON serialized `Key` the user details were signed with when the identity was bound, the only one accepted
+    pub(crate) signature_key: Option<String>,
+    /// Access and refresh tokens, the user must authenticate again without them
+    #[serde(skip)]
+    pub(crate) serialized_auth_state: Option<String>,
+    pub(crate) keycloak_user_id: Option<String>,
+    /// The identity can't be transferred to another device
+    pub(crate) transfer_restricted: bool,
+    /// Server timestamp of the latest revocations processed
+    pub(crate) latest_revocation_list_timestamp: i64,
+}
+
+#[bon]
+impl KeycloakServer {
+    #[builder]
+    pub fn new(
+        owned_identity: &[u8],
+        server_url: &str,
+        client_id: &str,
+        client_secret: Option<&str>,
+        jwks: &JwkSet,
+        signature_key: Option<&Key>,
+        serialized_auth_state: Option<&str>,
+        #[builder(default)]
+        transfer_restricted: bool,
+    ) -> Result<Self> {
+        Ok(Self {
+            owned_identity: owned_identity.to_vec(),
+            server_url: server_url.to_owned(),
+            client_id: client_id.to_owned(),
+            client_secret: client_secret.map(str::to_owned),
+            jwks: serde_json::to_string(jwks)?,
+            signature_key: signature_key.map(serde_json::to_string).transpose()?,
+            serialized_auth_state: serialized_auth_state.map(str::to_owned),
+            keycloak_user_id: None,
+            transfer_restricted,
+            latest_revocation_list_timestamp: 0,
+        })
+    }
+
+    pub fn get_owned_identity(&self) -> &[u8] {
+        &self.owned_identity
+    }
+
+    pub fn get_server_url(&self) -> &str {
+        &self.server_url
+    }
+
+    pub fn get_client_id(&self) -> &str {
+        &self.client_id
+    }
+
+    pub fn get_client_secret(&self) -> Option<&str> {
+        self.client_secret.as_deref()
+    }
+
+    pub fn get_jwks(&self) -> Result<JwkSet> {
+        Ok(serde_json::from_str(&self.jwks)?)
+    }
+
+    pub fn get_signature_key(&self) -> Result<Option<Key>> {
+        self.signature_key.as_deref().map(serde_json::from_str).transpose().map_err(EngineError::from)
+    }
+
+    pub fn get_serialized_auth_state(&self) -> Option<&str> {
+        self.serialized_auth_state.as_deref()
+    }
+
+    pub fn get_keycloak_user_id(&self) -> Option<&str> {
+        self.keycloak_user_id.as_deref()
+    }
+
+    pub fn is_transfer_restricted(&self) -> bool {
+        self.transfer_restricted
+    }
+
+    pub fn get_latest_revocation_list_timestamp(&self) -> i64 {
+        self.latest_revocation_list_timestamp
+    }
+
+    pub async fn get<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8]) -> Result<Option<KeycloakServer>> {
+        let keycloak_server = sqlx::query_as::<_, KeycloakServer>("SELECT * FROM keycloak_servers WHERE owned_identity = $1")
+            .bind(owned_identity)
+            .fetch_optional(db)
+            .await?;
+
+        Ok(keycloak_server)
+    }
+
+    /// Replaces the previous server of the owned identity
+    pub async fn upsert<'e>(db: impl SqliteExecutor<'e>, keycloak_server: KeycloakServer) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT OR REPLACE INTO keycloak_servers
+            (
+                owned_identity,
+                server_url,
+                client_id,
+                client_secret,
+                jwks,
+                signature_key,
+                serialized_auth_state,
+                keycloak_user_id,
+                transfer_restricted,
+                latest_revocation_list_timestamp
+            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+            "#
+        )
+        .bind(keycloak_server.owned_identity)
+        .bind(keycloak_server.server_url)
+        .bind(keycloak_server.client_id)
+        .bind(keycloak_server.client_secret)
+        .bind(keycloak_server.jwks)
+        .bind(keycloak_server.signature_key)
+        .bind(keycloak_server.serialized_auth_state)
+        .bind(keycloak_server.keycloak_user_id)
+        .bind(keycloak_server.transfer_restricted)
+        .bind(keycloak_server.latest_revocation_list_timestamp)
+        .execute(db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Keeps the tokens refreshed during a request
+    pub async fn set_serialized_auth_state<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8], serialized_auth_state: Option<&str>) -> Result<()> {
+        sqlx::query("UPDATE keycloak_servers SET serialized_auth_state = $1 WHERE owned_identity = $2")
+            .bind(serialized_auth_state)
+            .bind(owned_identity)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Keeps what the server answered about the user, once its signed details are verified
+    pub async fn set_user_state<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8], keycloak_user_id: &str, transfer_restricted: bool) -> Result<()> {
+        sqlx::query("UPDATE keycloak_servers SET keycloak_user_id = $1, transfer_restricted = $2 WHERE owned_identity = $3")
+            .bind(keycloak_user_id)
+            .bind(transfer_restricted)
+            .bind(owned_identity)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn set_latest_revocation_list_timestamp<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8], latest_revocation_list_timestamp: i64) -> Result<()> {
+        sqlx::query("UPDATE keycloak_servers SET latest_revocation_list_timestamp = $1 WHERE owned_identity = $2")
+            .bind(latest_revocation_list_timestamp)
+            .bind(owned_identity)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Used when the owned identity is deleted or revoked by its server
+    pub async fn delete<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8]) -> Result<()> {
+        sqlx::query("DELETE FROM keycloak_servers WHERE owned_identity = $1")
+            .bind(owned_identity)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,635 @@
+use bon::bon;
+use sqlx::{FromRow, SqliteExecutor};
+use uuid::Uuid;
+
+use crate::{current_timestamp, EngineError, Result};
+
+use super::discussion::Ephemerality;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageDirection {
+    Outbound,
+    Inbound,
+}
+
+impl From<MessageDirection> for i64 {
+    fn from(direction: MessageDirection) -> Self {
+        match direction {
+            MessageDirection::Outbound => 0,
+            MessageDirection::Inbound => 1,
+        }
+    }
+}
+
+impl TryFrom<i64> for MessageDirection {
+    type Error = EngineError;
+
+    fn try_from(value: i64) -> std::result::Result<Self, Self::Error> {
+        match value {
+            0 => Ok(MessageDirection::Outbound),
+            1 => Ok(MessageDirection::Inbound),
+            _ => Err(EngineError::Technical),
+        }
+    }
+}
+
+/// `Queued`, `Sent`, `Delivered` and `Failed` are only for outbound messages, `Unread` only for inbound ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageStatus {
+    Queued,
+    Sent,
+    Delivered,
+    Read,
+    Failed,
+    Unread,
+}
+
+impl From<MessageStatus> for i64 {
+    fn from(status: MessageStatus) -> Self {
+        match status {
+            MessageStatus::Queued => 0,
+            MessageStatus::Sent => 1,
+            MessageStatus::Delivered => 2,
+            MessageStatus::Read => 3,
+            MessageStatus::Failed => 4,
+            MessageStatus::Unread => 5,
+        }
+    }
+}
+
+impl MessageStatus {
+    /// Outbound statuses only move forward, a late `Sent` must not replace a `Delivered` received before it
+    pub(crate) fn outbound_rank(self) -> i64 {
+        match self {
+            MessageStatus::Queued | MessageStatus::Unread => 0,
+            MessageStatus::Sent | MessageStatus::Failed => 1,
+            MessageStatus::Delivered => 2,
+            MessageStatus::Read => 3,
+        }
+    }
+}
+
+impl TryFrom<i64> for MessageStatus {
+    type Error = EngineError;
+
+    fn try_from(value: i64) -> std::result::Result<Self, Self::Error> {
+        match value {
+            0 => Ok(MessageStatus::Queued),
+            1 => Ok(MessageStatus::Sent),
+            2 => Ok(MessageStatus::Delivered),
+            3 => Ok(MessageStatus::Read),
+            4 => Ok(MessageStatus::Failed),
+            5 => Ok(MessageStatus::Unread),
+            _ => Err(EngineError::Technical),
+        }
+    }
+}
+
+/// Identifies a message across devices, by its sender and its position in the sender thread
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageReference {
+    pub sender_identity: Vec<u8>,
+    pub sender_thread_identifier: Uuid,
+    pub sender_sequence_number: i64,
+}
+
+/// Message of a discussion, sent or received.
+///
+/// A message is identified across devices by its sender and its position in the sender thread, this is what
+/// replies reference.
+#[derive(Clone, FromRow, Debug)]
+pub struct Message {
+    pub(crate) id: Option<i64>,
+    pub(crate) discussion_id: i64,
+    pub(crate) direction: i64,
+    pub(crate) sender_identity: Vec<u8>,
+    pub(crate) sender_thread_identifier: Vec<u8>,
+    pub(crate) sender_sequence_number: i64,
+    pub(crate) body: Option<String>,
+    /// Only for inbound messages, the time the server received the message
+    pub(crate) server_timestamp: Option<i64>,
+    pub(crate) local_timestamp: i64,
+    /// Messages of a discussion are displayed by increasing sort index
+    pub(crate) sort_index: f64,
+    /// The replied message when it is in the discussion, see `reply_to_sender_identity` for the one referenced
+    pub(crate) reply_to_message_id: Option<i64>,
+    pub(crate) status: i64,
+    pub(crate) edited: bool,
+    /// Only for outbound messages, the outbox entry carrying the message
+    pub(crate) outbox_message_id: Option<i64>,
+    /// Identifies the return receipts of the message, they are encrypted with `return_receipt_key`
+    pub(crate) return_receipt_nonce: Option<Vec<u8>>,
+    pub(crate) return_receipt_key: Option<Vec<u8>>,
+    /// Only for inbound messages, their attachments are stored under this uid
+    pub(crate) message_uid: Option<Vec<u8>>,
+    pub(crate) read_once: bool,
+    pub(crate) visibility_duration: Option<i64>,
+    pub(crate) existence_duration: Option<i64>,
+    /// The body and attachments of the message were removed when it expired
+    pub(crate) wiped: bool,
+    /// Only for edited messages, the time of the edit the body comes from
+    pub(crate) edit_timestamp: Option<i64>,
+    /// The sender or a group administrator deleted the message for everyone, it is wiped as well
+    pub(crate) remotely_deleted: bool,
+    /// Only for replies, the message replied to as referenced by the payload
+    pub(crate) reply_to_sender_identity: Option<Vec<u8>>,
+    pub(crate) reply_to_sender_thread_identifier: Option<Vec<u8>>,
+    pub(crate) reply_to_sender_sequence_number: Option<i64>,
+    /// Only for inbound messages, they mention the owned identity or reply to one of its messages
+    pub(crate) mentioned: bool,
+    /// Only for inbound messages with attachments, the JSON of their metadata as received in the payload
+    pub(crate) attachment_metadata: Option<String>,
+}
+
+#[bon]
+impl Message {
+    #[builder]
+    pub fn new(
+        discussion_id: i64,
+        direction: MessageDirection,
+        sender_identity: Vec<u8>,
+        sender_thread_identifier: Uuid,
+        sender_sequence_number: i64,
+        body: Option<String>,
+        server_timestamp: Option<i64>,
+        reply_to_message_id: Option<i64>,
+        reply_to: Option<MessageReference>,
+        outbox_message_id: Option<i64>,
+        return_receipt_nonce: Option<Vec<u8>>,
+        return_receipt_key: Option<Vec<u8>>,
+        message_uid: Option<Vec<u8>>,
+        #[builder(default)]
+        ephemerality: Ephemerality,
+        #[builder(default)]
+        mentioned: bool,
+        attachment_metadata: Option<String>,
+    ) -> Self {
+        let local_timestamp = current_timestamp();
+        let status = match direction {
+            MessageDirection::Outbound => MessageStatus::Queued,
+            MessageDirection::Inbound => MessageStatus::Unread,
+        };
+
+        Self {
+            id: None,
+            discussion_id,
+            direction: direction.into(),
+            sender_identity,
+            sender_thread_identifier: sender_thread_identifier.as_bytes().to_vec(),
+            sender_sequence_number,
+            body,
+            server_timestamp,
+            local_timestamp,
+            sort_index: server_timestamp.unwrap_or(local_timestamp) as f64,
+            reply_to_message_id,
+            status: status.into(),
+            edited: false,
+            outbox_message_id,
+            return_receipt_nonce,
+            return_receipt_key,
+            message_uid,
+            read_once: ephemerality.read_once,
+            visibility_duration: ephemerality.visibility_duration,
+            existence_duration: ephemerality.existence_duration,
+            wiped: false,
+            edit_timestamp: None,
+            remotely_deleted: false,
+            reply_to_sender_identity: reply_to.as_ref().map(|reference| reference.sender_identity.clone()),
+            reply_to_sender_thread_identifier: reply_to.as_ref().map(|reference| reference.sender_thread_identifier.as_bytes().to_vec()),
+            reply_to_sender_sequence_number: reply_to.map(|reference| reference.sender_sequence_number),
+            mentioned,
+            attachment_metadata,
+        }
+    }
+}
+
+impl Message {
+    pub fn get_id(&self) -> Option<i64> {
+        self.id
+    }
+
+    pub fn get_discussion_id(&self) -> i64 {
+        self.discussion_id
+    }
+
+    pub fn get_direction(&self) -> Result<MessageDirection> {
+        self.direction.try_into()
+    }
+
+    pub fn get_sender_identity(&self) -> &[u8] {
+        &self.sender_identity
+    }
+
+    pub fn get_sender_thread_identifier(&self) -> Result<Uuid> {
+        Uuid::from_slice(&self.sender_thread_identifier).map_err(|_| EngineError::Technical)
+    }
+
+    pub fn get_sender_sequence_number(&self) -> i64 {
+        self.sender_sequence_number
+    }
+
+    pub fn get_reference(&self) -> Result<MessageReference> {
+        Ok(MessageReference {
+            sender_identity: self.sender_identity.clone(),
+            sender_thread_identifier: self.get_sender_thread_identifier()?,
+            sender_sequence_number: self.sender_sequence_number,
+        })
+    }
+
+    pub fn get_body(&self) -> Option<&str> {
+        self.body.as_deref()
+    }
+
+    pub fn get_server_timestamp(&self) -> Option<i64> {
+        self.server_timestamp
+    }
+
+    pub fn get_local_timestamp(&self) -> i64 {
+        self.local_timestamp
+    }
+
+    pub fn get_sort_index(&self) -> f64 {
+        self.sort_index
+    }
+
+    pub fn get_reply_to_message_id(&self) -> Option<i64> {
+        self.reply_to_message_id
+    }
+
+    /// The message replied to, it may not be in the discussion
+    pub fn get_reply_to(&self) -> Result<Option<MessageReference>> {
+        match (&self.reply_to_sender_identity, &self.reply_to_sender_thread_identifier, self.reply_to_sender_sequence_number) {
+            (Some(sender_identity), Some(sender_thread_identifier), Some(sender_sequence_number)) => Ok(Some(MessageReference {
+                sender_identity: sender_identity.clone(),
+                sender_thread_identifier: Uuid::from_slice(sender_thread_identifier).map_err(|_| EngineError::Technical)?,
+                sender_sequence_number,
+            })),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn get_status(&self) -> Result<MessageStatus> {
+        self.status.try_into()
+    }
+
+    pub fn is_edited(&self) -> bool {
+        self.edited
+    }
+
+    pub fn get_outbox_message_id(&self) -> Option<i64> {
+        self.outbox_message_id
+    }
+
+    pub fn get_return_receipt_nonce(&self) -> Option<&[u8]> {
+        self.return_receipt_nonce.as_deref()
+    }
+
+    pub fn get_return_receipt_key(&self) -> Option<&[u8]> {
+        self.return_receipt_key.as_deref()
+    }
+
+    pub fn get_message_uid(&self) -> Option<&[u8]> {
+        self.message_uid.as_deref()
+    }
+
+    pub fn get_ephemerality(&self) -> Ephemerality {
+        Ephemerality {
+            read_once: self.read_once,
+            visibility_duration: self.visibility_duration,
+            existence_duration: self.existence_duration,
+        }
+    }
+
+    pub fn is_wiped(&self) -> bool {
+        self.wiped
+    }
+
+    pub fn get_edit_timestamp(&self) -> Option<i64> {
+        self.edit_timestamp
+    }
+
+    pub fn is_remotely_deleted(&self) -> bool {
+        self.remotely_deleted
+    }
+
+    pub fn is_mentioned(&self) -> bool {
+        self.mentioned
+    }
+
+    /// Time of the current body, the one of the last edit or the one of the message
+    pub(crate) fn get_body_timestamp(&self) -> i64 {
+        self.edit_timestamp.or(self.server_timestamp).unwrap_or(self.local_timestamp)
+    }
+
+    pub async fn get_by_id<'e>(db: impl SqliteExecutor<'e>, id: i64) -> Result<Option<Message>> {
+        let message = sqlx::query_as::<_, Message>("SELECT * FROM messages WHERE id = $1")
+            .bind(id)
+            .fetch_optional(db)
+            .await?;
+
+        Ok(message)
+    }
+
+    pub async fn get_by_outbox_message<'e>(db: impl SqliteExecutor<'e>, outbox_message_id: i64) -> Result<Option<Message>> {
+        let message = sqlx::query_as::<_, Message>("SELECT * FROM messages WHERE outbox_message_id = $1")
+            .bind(outbox_message_id)
+            .fetch_optional(db)
+            .await?;
+
+        Ok(message)
+    }
+
+    /// Inbound message of an owned identity, its inbound attachments have the same message uid
+    pub async fn get_by_message_uid<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8], message_uid: &[u8]) -> Result<Option<Message>> {
+        let message = sqlx::query_as::<_, Message>(
+            r#"
+            SELECT messages.* FROM messages
+            INNER JOIN discussions ON discussions.id = messages.discussion_id
+            WHERE discussions.owned_identity = $1 AND messages.message_uid = $2
+            "#
+        )
+        .bind(owned_identity)
+        .bind(message_uid)
+        .fetch_optional(db)
+        .await?;
+
+        Ok(message)
+    }
+
+    /// Outbound messages of an owned identity whose return receipts use `nonce`
+    pub async fn get_by_return_receipt_nonce<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8], nonce: &[u8]) -> Result<Vec<Message>> {
+        let messages = sqlx::query_as::<_, Message>(
+            r#"
+            SELECT messages.* FROM messages
+            INNER JOIN discussions ON discussions.id = messages.discussion_id
+            WHERE discussions.owned_identity = $1 AND messages.return_receipt_nonce = $2 AND messages.direction = $3
+            "#
+        )
+        .bind(owned_identity)
+        .bind(nonce)
+        .bind(i64::from(MessageDirection::Outbound))
+        .fetch_all(db)
+        .await?;
+
+        Ok(messages)
+    }
+
+    pub async fn get_by_sender<'e>(db: impl SqliteExecutor<'e>, discussion_id: i64, sender_identity: &[u8], sender_thread_identifier: &Uuid, sender_sequence_number: i64) -> Result<Option<Message>> {
+        let message = sqlx::query_as::<_, Message>("SELECT * FROM messages WHERE discussion_id = $1 AND sender_identity = $2 AND sender_thread_identifier = $3 AND sender_sequence_number = $4")
+            .bind(discussion_id)
+            .bind(sender_identity)
+            .bind(sender_thread_identifier.as_bytes().as_slice())
+            .bind(sender_sequence_number)
+            .fetch_optional(db)
+            .await?;
+
+        Ok(message)
+    }
+
+    /// Page of the history of a discussion, most recent first.
+    ///
+    /// The next page is the one before the last message of the previous page, `before_message_id` is `None` for
+    /// the first one.
+    pub async fn get_page<'e>(db: impl SqliteExecutor<'e>, discussion_id: i64, before_message_id: Option<i64>, limit: i64) -> Result<Vec<Message>> {
+        let messages = match before_message_id {
+            Some(before_message_id) => {
+                sqlx::query_as::<_, Message>(
+                    r#"
+                    SELECT * FROM messages
+                    WHERE discussion_id = $1 AND (sort_index, id) < (SELECT sort_index, id FROM messages WHERE id = $2)
+                    ORDER BY sort_index DESC, id DESC
+                    LIMIT $3
+                    "#
+                )
+                .bind(discussion_id)
+                .bind(before_message_id)
+                .bind(limit)
+                .fetch_all(db)
+                .await?
+            }
+            None => {
+                sqlx::query_as::<_, Message>("SELECT * FROM messages WHERE discussion_id = $1 ORDER BY sort_index DESC, id DESC LIMIT $2")
+                    .bind(discussion_id)
+                    .bind(limit)
+                    .fetch_all(db)
+                    .await?
+            }
+        };
+
+        Ok(messages)
+    }
+
+    /// Page of the messages of an owned identity that mention it, most recent first, see `get_page`
+    pub async fn get_mentioning_page<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8], before_message_id: Option<i64>, limit: i64) -> Result<Vec<Message>> {
+        let messages = sqlx::query_as::<_, Message>(
+            r#"
+            SELECT messages.* FROM messages
+            INNER JOIN discussions ON discussions.id = messages.discussion_id
+            WHERE discussions.owned_identity = $1 AND messages.mentioned
+            AND ($2 IS NULL OR (messages.sort_index, messages.id) < (SELECT sort_index, id FROM messages WHERE id = $2))
+            ORDER BY messages.sort_index DESC, messages.id DESC
+            LIMIT $3
+            "#
+        )
+        .bind(owned_identity)
+        .bind(before_message_id)
+        .bind(limit)
+        .fetch_all(db)
+        .await?;
+
+        Ok(messages)
+    }
+
+    /// Sequence number of the next message sent in a discussion
+    pub async fn get_next_sequence_number<'e>(db: impl SqliteExecutor<'e>, discussion_id: i64) -> Result<i64> {
+        let sequence_number: i64 = sqlx::query_scalar("SELECT COALESCE(MAX(sender_sequence_number), 0) + 1 FROM messages WHERE discussion_id = $1 AND direction = $2")
+            .bind(discussion_id)
+            .bind(i64::from(MessageDirection::Outbound))
+            .fetch_one(db)
+            .await?;
+
+        Ok(sequence_number)
+    }
+
+    /// Returns `None` when a message with the same sender, thread and sequence number was already inserted
+    pub async fn insert_if_absent<'e>(db: impl SqliteExecutor<'e>, message: Message) -> Result<Option<i64>> {
+        let result = sqlx::query(
+            r#"
+            INSERT OR IGNORE INTO messages
+            (
+                discussion_id,
+                direction,
+                sender_identity,
+                sender_thread_identifier,
+                sender_sequence_number,
+                body,
+                server_timestamp,
+                local_timestamp,
+                sort_index,
+                reply_to_message_id,
+                status,
+                edited,
+                outbox_message_id,
+                return_receipt_nonce,
+                return_receipt_key,
+                message_uid,
+                read_once,
+                visibility_duration,
+                existence_duration,
+                wiped,
+                reply_to_sender_identity,
+                reply_to_sender_thread_identifier,
+                reply_to_sender_sequence_number,
+                mentioned,
+                attachment_metadata
+            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21, $22, $23, $24, $25)
+            "#
+        )
+        .bind(message.discussion_id)
+        .bind(message.direction)
+        .bind(message.sender_identity)
+        .bind(message.sender_thread_identifier)
+        .bind(message.sender_sequence_number)
+        .bind(message.body)
+        .bind(message.server_timestamp)
+        .bind(message.local_timestamp)
+        .bind(message.sort_index)
+        .bind(message.reply_to_message_id)
+        .bind(message.status)
+        .bind(message.edited)
+        .bind(message.outbox_message_id)
+        .bind(message.return_receipt_nonce)
+        .bind(message.return_receipt_key)
+        .bind(message.message_uid)
+        .bind(message.read_once)
+        .bind(message.visibility_duration)
+        .bind(message.existence_duration)
+        .bind(message.wiped)
+        .bind(message.reply_to_sender_identity)
+        .bind(message.reply_to_sender_thread_identifier)
+        .bind(message.reply_to_sender_sequence_number)
+        .bind(message.mentioned)
+        .bind(message.attachment_metadata)
+        .execute(db)
+        .await?;
+
+        match result.rows_affected() {
+            0 => Ok(None),
+            _ => Ok(Some(result.last_insert_rowid())),
+        }
+    }
+
+    /// Links the replies received before the message they reply to, once it is saved
+    pub async fn resolve_replies<'e>(db: impl SqliteExecutor<'e>, discussion_id: i64, reference: &MessageReference, message_id: i64) -> Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE messages SET reply_to_message_id = $1
+            WHERE discussion_id = $2 AND reply_to_sender_identity = $3 AND reply_to_sender_thread_identifier = $4 AND reply_to_sender_sequence_number = $5
+            AND reply_to_message_id IS NULL
+            "#
+        )
+        .bind(message_id)
+        .bind(discussion_id)
+        .bind(&reference.sender_identity)
+        .bind(reference.sender_thread_identifier.as_bytes().as_slice())
+        .bind(reference.sender_sequence_number)
+        .execute(db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Moves an outbound message forward to `status`, returns false when it already reached it or a later status
+    pub async fn advance_outbound_status<'e>(db: impl SqliteExecutor<'e>, id: i64, status: MessageStatus) -> Result<bool> {
+        let earlier_statuses = [MessageStatus::Queued, MessageStatus::Sent, MessageStatus::Delivered, MessageStatus::Failed]
+            .into_iter()
+            .filter(|earlier_status| earlier_status.outbound_rank() < status.outbound_rank())
+            .map(|earlier_status| i64::from(earlier_status).to_string())
+            .collect::<Vec<String>>();
+        if earlier_statuses.is_empty() {
+            return Ok(false);
+        }
+
+        let query = format!("UPDATE messages SET status = $1 WHERE id = $2 AND direction = $3 AND status IN ({})", earlier_statuses.join(", "));
+        let result = sqlx::query(&query)
+            .bind(i64::from(status))
+            .bind(id)
+            .bind(i64::from(MessageDirection::Outbound))
+            .execute(db)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Returns false when the message was not unread
+    pub async fn mark_read<'e>(db: impl SqliteExecutor<'e>, id: i64) -> Result<bool> {
+        let result = sqlx::query("UPDATE messages SET status = $1 WHERE id = $2 AND status = $3")
+            .bind(i64::from(MessageStatus::Read))
+            .bind(id)
+            .bind(i64::from(MessageStatus::Unread))
+            .execute(db)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Removes the body of an expired message, the message itself stays in the discussion
+    pub async fn wipe<'e>(db: impl SqliteExecutor<'e>, id: i64) -> Result<()> {
+        sqlx::query("UPDATE messages SET body = NULL, attachment_metadata = NULL, wiped = TRUE, mentioned = FALSE WHERE id = $1")
+            .bind(id)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Replaces the body with the one of a more recent edit
+    pub async fn set_edited_body<'e>(db: impl SqliteExecutor<'e>, id: i64, body: &str, edit_timestamp: i64) -> Result<()> {
+        sqlx::query("UPDATE messages SET body = $1, edited = TRUE, edit_timestamp = $2 WHERE id = $3")
+            .bind(body)
+            .bind(edit_timestamp)
+            .bind(id)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn mark_remotely_deleted<'e>(db: impl SqliteExecutor<'e>, id: i64) -> Result<()> {
+        sqlx::query("UPDATE messages SET body = NULL, attachment_metadata = NULL, wiped = TRUE, remotely_deleted = TRUE, mentioned = FALSE WHERE id = $1")
+            .bind(id)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete<'e>(db: impl SqliteExecutor<'e>, id: i64) -> Result<()> {
+        sqlx::query("DELETE FROM messages WHERE id = $1")
+            .bind(id)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Messages of a discussion, the discussion itself is kept
+    pub async fn delete_by_discussion<'e>(db: impl SqliteExecutor<'e>, discussion_id: i64) -> Result<()> {
+        sqlx::query("DELETE FROM messages WHERE discussion_id = $1")
+            .bind(discussion_id)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Messages of all the discussions of an owned identity, the discussions themselves are kept
+    pub async fn delete_by_owned_identity<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8]) -> Result<()> {
+        sqlx::query("DELETE FROM messages WHERE discussion_id IN (SELECT id FROM discussions WHERE owned_identity = $1)")
+            .bind(owned_identity)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,84 @@
+use sqlx::{FromRow, SqliteExecutor};
+
+use crate::Result;
+
+/// Previous body of an edited message, with the time it was written
+#[derive(Clone, FromRow, Debug)]
+pub struct MessageEdit {
+    pub(crate) id: Option<i64>,
+    pub(crate) message_id: i64,
+    pub(crate) body: String,
+    pub(crate) timestamp: i64,
+}
+
+impl MessageEdit {
+    pub fn new(message_id: i64, body: &str, timestamp: i64) -> Self {
+        Self { id: None, message_id, body: body.to_owned(), timestamp }
+    }
+
+    pub fn get_id(&self) -> Option<i64> {
+        self.id
+    }
+
+    pub fn get_message_id(&self) -> i64 {
+        self.message_id
+    }
+
+    pub fn get_body(&self) -> &str {
+        &self.body
+    }
+
+    pub fn get_timestamp(&self) -> i64 {
+        self.timestamp
+    }
+
+    /// Oldest first
+    pub async fn get_all<'e>(db: impl SqliteExecutor<'e>, message_id: i64) -> Result<Vec<MessageEdit>> {
+        let edits = sqlx::query_as::<_, MessageEdit>("SELECT * FROM message_edits WHERE message_id = $1 ORDER BY timestamp, id")
+            .bind(message_id)
+            .fetch_all(db)
+            .await?;
+
+        Ok(edits)
+    }
+
+    pub async fn insert<'e>(db: impl SqliteExecutor<'e>, edit: MessageEdit) -> Result<i64> {
+        let result = sqlx::query("INSERT INTO message_edits (message_id, body, timestamp) VALUES ($1, $2, $3)")
+            .bind(edit.message_id)
+            .bind(edit.body)
+            .bind(edit.timestamp)
+            .execute(db)
+            .await?;
+
+        Ok(result.last_insert_rowid())
+    }
+
+    pub async fn delete_by_message<'e>(db: impl SqliteExecutor<'e>, message_id: i64) -> Result<()> {
+        sqlx::query("DELETE FROM message_edits WHERE message_id = $1")
+            .bind(message_id)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Edits of the messages of a discussion, to delete before the messages
+    pub async fn delete_by_discussion<'e>(db: impl SqliteExecutor<'e>, discussion_id: i64) -> Result<()> {
+        sqlx::query("DELETE FROM message_edits WHERE message_id IN (SELECT id FROM messages WHERE discussion_id = $1)")
+            .bind(discussion_id)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Edits of the messages of an owned identity, to delete before the messages
+    pub async fn delete_by_owned_identity<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8]) -> Result<()> {
+        sqlx::query("DELETE FROM message_edits WHERE message_id IN (SELECT messages.id FROM messages JOIN discussions ON discussions.id = messages.discussion_id WHERE discussions.owned_identity = $1)")
+            .bind(owned_identity)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,103 @@
+use sqlx::{FromRow, SqliteExecutor};
+
+use crate::Result;
+
+/// Time at which a message is wiped, or deleted when `wipe_only` is false.
+///
+/// Expirations are persisted so that the ones reached while the engine was stopped are applied on the next start.
+#[derive(Clone, FromRow, Debug)]
+pub struct MessageExpiration {
+    pub(crate) id: Option<i64>,
+    pub(crate) message_id: i64,
+    pub(crate) expiration_timestamp: i64,
+    pub(crate) wipe_only: bool,
+}
+
+impl MessageExpiration {
+    pub fn new(message_id: i64, expiration_timestamp: i64, wipe_only: bool) -> Self {
+        Self { id: None, message_id, expiration_timestamp, wipe_only }
+    }
+
+    pub fn get_id(&self) -> Option<i64> {
+        self.id
+    }
+
+    pub fn get_message_id(&self) -> i64 {
+        self.message_id
+    }
+
+    pub fn get_expiration_timestamp(&self) -> i64 {
+        self.expiration_timestamp
+    }
+
+    pub fn is_wipe_only(&self) -> bool {
+        self.wipe_only
+    }
+
+    /// Expirations reached at `timestamp`, deletions first
+    pub async fn get_due<'e>(db: impl SqliteExecutor<'e>, timestamp: i64) -> Result<Vec<MessageExpiration>> {
+        let expirations = sqlx::query_as::<_, MessageExpiration>("SELECT * FROM message_expirations WHERE expiration_timestamp <= $1 ORDER BY wipe_only, expiration_timestamp")
+            .bind(timestamp)
+            .fetch_all(db)
+            .await?;
+
+        Ok(expirations)
+    }
+
+    pub async fn get_next_expiration_timestamp<'e>(db: impl SqliteExecutor<'e>) -> Result<Option<i64>> {
+        let next_expiration_timestamp: Option<i64> = sqlx::query_scalar("SELECT MIN(expiration_timestamp) FROM message_expirations")
+            .fetch_one(db)
+            .await?;
+
+        Ok(next_expiration_timestamp)
+    }
+
+    pub async fn insert<'e>(db: impl SqliteExecutor<'e>, expiration: MessageExpiration) -> Result<i64> {
+        let result = sqlx::query("INSERT INTO message_expirations (message_id, expiration_timestamp, wipe_only) VALUES ($1, $2, $3)")
+            .bind(expiration.message_id)
+            .bind(expiration.expiration_timestamp)
+            .bind(expiration.wipe_only)
+            .execute(db)
+            .await?;
+
+        Ok(result.last_insert_rowid())
+    }
+
+    pub async fn delete<'e>(db: impl SqliteExecutor<'e>, id: i64) -> Result<()> {
+        sqlx::query("DELETE FROM message_expirations WHERE id = $1")
+            .bind(id)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete_by_message<'e>(db: impl SqliteExecutor<'e>, message_id: i64) -> Result<()> {
+        sqlx::query("DELETE FROM message_expirations WHERE message_id = $1")
+            .bind(message_id)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Expirations of the messages of a discussion, to delete before the messages
+    pub async fn delete_by_discussion<'e>(db: impl SqliteExecutor<'e>, discussion_id: i64) -> Result<()> {
+        sqlx::query("DELETE FROM message_expirations WHERE message_id IN (SELECT id FROM messages WHERE discussion_id = $1)")
+            .bind(discussion_id)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Expirations of the messages of an owned identity, to delete before the messages
+    pub async fn delete_by_owned_identity<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8]) -> Result<()> {
+        sqlx::query("DELETE FROM message_expirations WHERE message_id IN (SELECT messages.id FROM messages JOIN discussions ON discussions.id = messages.discussion_id WHERE discussions.owned_identity = $1)")
+            .bind(owned_identity)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,112 @@
+use std::ops::Range;
+
+use sqlx::{FromRow, SqliteExecutor};
+
+use crate::Result;
+
+use super::contact::Contact;
+
+/// Identity mentioned in the body of a message, the mention covers a byte range of the body
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserMention {
+    pub mentioned_identity: Vec<u8>,
+    pub range: Range<usize>,
+}
+
+/// Mention of a received or sent message, with the contact it designates.
+///
+/// `contact` is `None` when the owned identity is mentioned, and for identities that are not contacts.
+#[derive(Debug, Clone)]
+pub struct ResolvedMention {
+    pub mention: MessageMention,
+    pub contact: Option<Contact>,
+}
+
+#[derive(Clone, FromRow, Debug)]
+pub struct MessageMention {
+    pub(crate) id: Option<i64>,
+    pub(crate) message_id: i64,
+    pub(crate) mentioned_identity: Vec<u8>,
+    pub(crate) range_start: i64,
+    pub(crate) range_end: i64,
+}
+
+impl MessageMention {
+    pub fn new(message_id: i64, mention: &UserMention) -> Self {
+        Self {
+            id: None,
+            message_id,
+            mentioned_identity: mention.mentioned_identity.clone(),
+            range_start: mention.range.start as i64,
+            range_end: mention.range.end as i64,
+        }
+    }
+
+    pub fn get_id(&self) -> Option<i64> {
+        self.id
+    }
+
+    pub fn get_message_id(&self) -> i64 {
+        self.message_id
+    }
+
+    pub fn get_mentioned_identity(&self) -> &[u8] {
+        &self.mentioned_identity
+    }
+
+    /// Byte range of the body
+    pub fn get_range(&self) -> Range<usize> {
+        self.range_start as usize..self.range_end as usize
+    }
+
+    /// In the order of the body
+    pub async fn get_all<'e>(db: impl SqliteExecutor<'e>, message_id: i64) -> Result<Vec<MessageMention>> {
+        let mentions = sqlx::query_as::<_, MessageMention>("SELECT * FROM message_mentions WHERE message_id = $1 ORDER BY range_start, id")
+            .bind(message_id)
+            .fetch_all(db)
+            .await?;
+
+        Ok(mentions)
+    }
+
+    pub async fn insert<'e>(db: impl SqliteExecutor<'e>, mention: MessageMention) -> Result<i64> {
+        let result = sqlx::query("INSERT INTO message_mentions (message_id, mentioned_identity, range_start, range_end) VALUES ($1, $2, $3, $4)")
+            .bind(mention.message_id)
+            .bind(mention.mentioned_identity)
+            .bind(mention.range_start)
+            .bind(mention.range_end)
+            .execute(db)
+            .await?;
+
+        Ok(result.last_insert_rowid())
+    }
+
+    pub async fn delete_by_message<'e>(db: impl SqliteExecutor<'e>, message_id: i64) -> Result<()> {
+        sqlx::query("DELETE FROM message_mentions WHERE message_id = $1")
+            .bind(message_id)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Mentions in the messages of a discussion, to delete before the messages
+    pub async fn delete_by_discussion<'e>(db: impl SqliteExecutor<'e>, discussion_id: i64) -> Result<()> {
+        sqlx::query("DELETE FROM message_mentions WHERE message_id IN (SELECT id FROM messages WHERE discussion_id = $1)")
+            .bind(discussion_id)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Mentions in the messages of an owned identity, to delete before the messages
+    pub async fn delete_by_owned_identity<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8]) -> Result<()> {
+        sqlx::query("DELETE FROM message_mentions WHERE message_id IN (SELECT messages.id FROM messages JOIN discussions ON discussions.id = messages.discussion_id WHERE discussions.owned_identity = $1)")
+            .bind(owned_identity)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+}
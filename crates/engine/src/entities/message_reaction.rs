@@ -0,0 +1,127 @@
+use sqlx::{FromRow, SqliteExecutor};
+
+use crate::Result;
+
+/// Last reaction of a participant to a message, the owned identity included.
+///
+/// A removed reaction is kept without emoji, so that an older reaction received afterwards does not replace it.
+#[derive(Clone, FromRow, Debug)]
+pub struct MessageReaction {
+    pub(crate) id: Option<i64>,
+    pub(crate) message_id: i64,
+    pub(crate) reactor_identity: Vec<u8>,
+    pub(crate) reaction: Option<String>,
+    pub(crate) timestamp: i64,
+}
+
+/// Participants who reacted to a message with the same emoji, earliest first
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AggregatedReaction {
+    pub reaction: String,
+    pub reactor_identities: Vec<Vec<u8>>,
+}
+
+/// Groups the reactions by emoji, the most used first and the earliest used first among equals
+pub(crate) fn aggregate(mut reactions: Vec<MessageReaction>) -> Vec<AggregatedReaction> {
+    reactions.sort_by_key(|reaction| (reaction.timestamp, reaction.id));
+
+    let mut aggregated: Vec<AggregatedReaction> = vec![];
+    for reaction in reactions {
+        let Some(emoji) = reaction.reaction else {
+            continue;
+        };
+        match aggregated.iter_mut().find(|aggregated| aggregated.reaction == emoji) {
+            Some(aggregated) => aggregated.reactor_identities.push(reaction.reactor_identity),
+            None => aggregated.push(AggregatedReaction { reaction: emoji, reactor_identities: vec![reaction.reactor_identity] }),
+        }
+    }
+    // Stable, equal counts keep the order of first use
+    aggregated.sort_by_key(|aggregated| std::cmp::Reverse(aggregated.reactor_identities.len()));
+
+    aggregated
+}
+
+impl MessageReaction {
+    /// `None` removes the previous reaction of the reactor
+    pub fn new(message_id: i64, reactor_identity: &[u8], reaction: Option<&str>, timestamp: i64) -> Self {
+        Self { id: None, message_id, reactor_identity: reactor_identity.to_vec(), reaction: reaction.map(str::to_owned), timestamp }
+    }
+
+    pub fn get_id(&self) -> Option<i64> {
+        self.id
+    }
+
+    pub fn get_message_id(&self) -> i64 {
+        self.message_id
+    }
+
+    pub fn get_reactor_identity(&self) -> &[u8] {
+        &self.reactor_identity
+    }
+
+    pub fn get_reaction(&self) -> Option<&str> {
+        self.reaction.as_deref()
+    }
+
+    pub fn get_timestamp(&self) -> i64 {
+        self.timestamp
+    }
+
+    /// Removed reactions included
+    pub async fn get_all<'e>(db: impl SqliteExecutor<'e>, message_id: i64) -> Result<Vec<MessageReaction>> {
+        let reactions = sqlx::query_as::<_, MessageReaction>("SELECT * FROM message_reactions WHERE message_id = $1 ORDER BY timestamp, id")
+            .bind(message_id)
+            .fetch_all(db)
+            .await?;
+
+        Ok(reactions)
+    }
+
+    /// Returns false when the reactor already has a reaction at least as recent
+    pub async fn upsert<'e>(db: impl SqliteExecutor<'e>, reaction: MessageReaction) -> Result<bool> {
+        let result = sqlx::query(
+            r#"
+            INSERT INTO message_reactions (message_id, reactor_identity, reaction, timestamp) VALUES ($1, $2, $3, $4)
+            ON CONFLICT (message_id, reactor_identity) DO UPDATE SET reaction = excluded.reaction, timestamp = excluded.timestamp
+            WHERE excluded.timestamp > message_reactions.timestamp
+            "#
+        )
+        .bind(reaction.message_id)
+        .bind(reaction.reactor_identity)
+        .bind(reaction.reaction)
+        .bind(reaction.timestamp)
+        .execute(db)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn delete_by_message<'e>(db: impl SqliteExecutor<'e>, message_id: i64) -> Result<()> {
+        sqlx::query("DELETE FROM message_reactions WHERE message_id = $1")
+            .bind(message_id)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Reactions to the messages of a discussion, to delete before the messages
+    pub async fn delete_by_discussion<'e>(db: impl SqliteExecutor<'e>, discussion_id: i64) -> Result<()> {
+        sqlx::query("DELETE FROM message_reactions WHERE message_id IN (SELECT id FROM messages WHERE discussion_id = $1)")
+            .bind(discussion_id)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Reactions to the messages of an owned identity, to delete before the messages
+    pub async fn delete_by_owned_identity<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8]) -> Result<()> {
+        sqlx::query("DELETE FROM message_reactions WHERE message_id IN (SELECT messages.id FROM messages JOIN discussions ON discussions.id = messages.discussion_id WHERE discussions.owned_identity = $1)")
+            .bind(owned_identity)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+}
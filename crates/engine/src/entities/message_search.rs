@@ -0,0 +1,100 @@
+use sqlx::SqliteExecutor;
+
+use crate::{search::MessageSearchQuery, Result};
+
+use super::message::Message;
+
+/// Row of the full-text index of a message
+pub struct MessageSearch;
+
+impl MessageSearch {
+    /// Replaces the indexed words of a message
+    pub async fn set<'e>(db: impl SqliteExecutor<'e>, message_id: i64, body_terms: &str, file_name_terms: &str) -> Result<()> {
+        sqlx::query("INSERT OR REPLACE INTO message_search (rowid, body, file_names) VALUES ($1, $2, $3)")
+            .bind(message_id)
+            .bind(body_terms)
+            .bind(file_name_terms)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete<'e>(db: impl SqliteExecutor<'e>, message_id: i64) -> Result<()> {
+        sqlx::query("DELETE FROM message_search WHERE rowid = $1")
+            .bind(message_id)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete_by_discussion<'e>(db: impl SqliteExecutor<'e>, discussion_id: i64) -> Result<()> {
+        sqlx::query("DELETE FROM message_search WHERE rowid IN (SELECT id FROM messages WHERE discussion_id = $1)")
+            .bind(discussion_id)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete_by_owned_identity<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8]) -> Result<()> {
+        sqlx::query("DELETE FROM message_search WHERE rowid IN (SELECT messages.id FROM messages INNER JOIN discussions ON discussions.id = messages.discussion_id WHERE discussions.owned_identity = $1)")
+            .bind(owned_identity)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Empties the index, the messages are indexed again by `get_unindexed_message_ids`
+    pub async fn delete_all<'e>(db: impl SqliteExecutor<'e>) -> Result<()> {
+        sqlx::query("DELETE FROM message_search").execute(db).await?;
+
+        Ok(())
+    }
+
+    /// Messages inserted before the index existed or emptied
+    pub async fn get_unindexed_message_ids<'e>(db: impl SqliteExecutor<'e>) -> Result<Vec<i64>> {
+        let message_ids = sqlx::query_scalar::<_, i64>("SELECT id FROM messages WHERE id NOT IN (SELECT rowid FROM message_search) ORDER BY id")
+            .fetch_all(db)
+            .await?;
+
+        Ok(message_ids)
+    }
+
+    /// Messages of an owned identity containing every term, in their body or in the file name of an attachment, latest first.
+    ///
+    /// The terms are the ones stored in the index, the text of the query is not used. Pages work as in `Message::get_page`.
+    pub async fn search<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8], terms: &[String], query: &MessageSearchQuery, before_message_id: Option<i64>, limit: i64) -> Result<Vec<Message>> {
+        let match_expression = terms.iter().map(|term| format!("\"{term}\"")).collect::<Vec<_>>().join(" ");
+        let messages = sqlx::query_as::<_, Message>(
+            r#"
+            SELECT messages.* FROM message_search
+            INNER JOIN messages ON messages.id = message_search.rowid
+            INNER JOIN discussions ON discussions.id = messages.discussion_id
+            WHERE message_search MATCH $1
+                AND discussions.owned_identity = $2
+                AND ($3 IS NULL OR messages.discussion_id = $3)
+                AND ($4 IS NULL OR messages.sender_identity = $4)
+                AND ($5 IS NULL OR COALESCE(messages.server_timestamp, messages.local_timestamp) >= $5)
+                AND ($6 IS NULL OR COALESCE(messages.server_timestamp, messages.local_timestamp) < $6)
+                AND ($7 IS NULL OR (messages.sort_index, messages.id) < (SELECT sort_index, id FROM messages WHERE id = $7))
+            ORDER BY messages.sort_index DESC, messages.id DESC
+            LIMIT $8
+            "#
+        )
+        .bind(match_expression)
+        .bind(owned_identity)
+        .bind(query.discussion_id)
+        .bind(query.sender_identity.as_deref())
+        .bind(query.from_timestamp)
+        .bind(query.to_timestamp)
+        .bind(before_message_id)
+        .bind(limit)
+        .fetch_all(db)
+        .await?;
+
+        Ok(messages)
+    }
+}
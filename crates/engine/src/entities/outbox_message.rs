@@ -0,0 +1,289 @@
+use olvid_core::encoding::{BytesArray, Decoder, Encoder};
+use sqlx::{FromRow, SqliteExecutor};
+
+use crate::{current_timestamp, server::OutboundServerMessage, EngineError, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryState {
+    Queued,
+    Sent,
+    Delivered,
+    Failed,
+}
+
+impl From<DeliveryState> for i64 {
+    fn from(delivery_state: DeliveryState) -> Self {
+        match delivery_state {
+            DeliveryState::Queued => 0,
+            DeliveryState::Sent => 1,
+            DeliveryState::Delivered => 2,
+            DeliveryState::Failed => 3,
+        }
+    }
+}
+
+impl TryFrom<i64> for DeliveryState {
+    type Error = EngineError;
+
+    fn try_from(value: i64) -> std::result::Result<Self, Self::Error> {
+        match value {
+            0 => Ok(DeliveryState::Queued),
+            1 => Ok(DeliveryState::Sent),
+            2 => Ok(DeliveryState::Delivered),
+            3 => Ok(DeliveryState::Failed),
+            _ => Err(EngineError::Technical),
+        }
+    }
+}
+
+/// Message waiting to be uploaded, it stays in the table once sent to keep track of its delivery state
+#[derive(Clone, FromRow, Debug)]
+pub struct OutboxMessage {
+    pub(crate) id: Option<i64>,
+    pub(crate) from_identity: Vec<u8>,
+    pub(crate) to_identity: Vec<u8>,
+    /// Encoded list of device uids
+    pub(crate) device_uids: Vec<u8>,
+    pub(crate) wrapped_key: Vec<u8>,
+    pub(crate) encrypted_content: Vec<u8>,
+    pub(crate) is_application_message: bool,
+    pub(crate) delivery_state: i64,
+    pub(crate) attempt_count: i64,
+    pub(crate) next_attempt_timestamp: i64,
+    pub(crate) message_uid: Option<Vec<u8>>,
+    pub(crate) creation_timestamp: i64,
+    /// Encoded list of the chunk count of each attachment
+    pub(crate) attachment_chunk_counts: Option<Vec<u8>>,
+    /// Set before the first upload attempt
+    pub(crate) upload_nonce: Option<Vec<u8>>,
+}
+
+impl OutboxMessage {
+    pub fn new(from_identity: &[u8], message: &OutboundServerMessage) -> Result<Self> {
+        let device_uids = message.device_uids.iter()
+            .map(|device_uid| device_uid.encode())
+            .collect::<std::result::Result<Vec<BytesArray>, _>>()?;
+        let attachment_chunk_counts = message.attachment_chunk_counts.iter()
+            .map(|chunk_count| chunk_count.encode())
+            .collect::<std::result::Result<Vec<BytesArray>, _>>()?;
+        let now = current_timestamp();
+
+        Ok(Self {
+            id: None,
+            from_identity: from_identity.to_vec(),
+            to_identity: message.to_identity.clone(),
+            device_uids: device_uids.encode()?,
+            wrapped_key: message.wrapped_key.clone(),
+            encrypted_content: message.encrypted_content.clone(),
+            is_application_message: message.is_application_message,
+            delivery_state: DeliveryState::Queued.into(),
+            attempt_count: 0,
+            next_attempt_timestamp: now,
+            message_uid: None,
+            creation_timestamp: now,
+            attachment_chunk_counts: Some(attachment_chunk_counts.encode()?),
+            upload_nonce: None,
+        })
+    }
+
+    pub fn get_id(&self) -> Option<i64> {
+        self.id
+    }
+
+    pub fn get_from_identity(&self) -> &[u8] {
+        &self.from_identity
+    }
+
+    pub fn get_delivery_state(&self) -> Result<DeliveryState> {
+        self.delivery_state.try_into()
+    }
+
+    pub fn get_attempt_count(&self) -> i64 {
+        self.attempt_count
+    }
+
+    pub fn get_message_uid(&self) -> Option<&[u8]> {
+        self.message_uid.as_deref()
+    }
+
+    pub fn get_upload_nonce(&self) -> Option<&[u8]> {
+        self.upload_nonce.as_deref()
+    }
+
+    pub fn to_outbound_message(&self) -> Result<OutboundServerMessage> {
+        let device_uids = Vec::<BytesArray>::decode(&self.device_uids)?.iter()
+            .map(|encoded_device_uid| BytesArray::decode(encoded_device_uid))
+            .collect::<std::result::Result<Vec<Vec<u8>>, _>>()?;
+        let attachment_chunk_counts = match &self.attachment_chunk_counts {
+            Some(encoded_chunk_counts) => Vec::<BytesArray>::decode(encoded_chunk_counts)?.iter()
+                .map(|encoded_chunk_count| i64::decode(encoded_chunk_count))
+                .collect::<std::result::Result<Vec<i64>, _>>()?,
+            None => vec![],
+        };
+
+        Ok(OutboundServerMessage {
+            to_identity: self.to_identity.clone(),
+            device_uids,
+            wrapped_key: self.wrapped_key.clone(),
+            encrypted_content: self.encrypted_content.clone(),
+            is_application_message: self.is_application_message,
+            attachment_chunk_counts,
+        })
+    }
+
+    pub async fn get_by_id<'e>(db: impl SqliteExecutor<'e>, id: i64) -> Result<Option<OutboxMessage>> {
+        let outbox_message = sqlx::query_as::<_, OutboxMessage>("SELECT * FROM outbox_messages WHERE id = $1")
+            .bind(id)
+            .fetch_optional(db)
+            .await?;
+
+        Ok(outbox_message)
+    }
+
+    /// Queued messages whose next attempt is due at `timestamp`
+    pub async fn get_due<'e>(db: impl SqliteExecutor<'e>, timestamp: i64) -> Result<Vec<OutboxMessage>> {
+        let outbox_messages = sqlx::query_as::<_, OutboxMessage>("SELECT * FROM outbox_messages WHERE delivery_state = $1 AND next_attempt_timestamp <= $2 ORDER BY next_attempt_timestamp")
+            .bind(i64::from(DeliveryState::Queued))
+            .bind(timestamp)
+            .fetch_all(db)
+            .await?;
+
+        Ok(outbox_messages)
+    }
+
+    pub async fn get_next_attempt_timestamp<'e>(db: impl SqliteExecutor<'e>) -> Result<Option<i64>> {
+        let next_attempt_timestamp: Option<i64> = sqlx::query_scalar("SELECT MIN(next_attempt_timestamp) FROM outbox_messages WHERE delivery_state = $1")
+            .bind(i64::from(DeliveryState::Queued))
+            .fetch_one(db)
+            .await?;
+
+        Ok(next_attempt_timestamp)
+    }
+
+    pub async fn insert<'e>(db: impl SqliteExecutor<'e>, outbox_message: OutboxMessage) -> Result<i64> {
+        let result = sqlx::query(
+            r#"
+            INSERT INTO outbox_messages
+            (
+                from_identity,
+                to_identity,
+                device_uids,
+                wrapped_key,
+                encrypted_content,
+                is_application_message,
+                delivery_state,
+                attempt_count,
+                next_attempt_timestamp,
+                message_uid,
+                creation_timestamp,
+                attachment_chunk_counts,
+                upload_nonce
+            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
+            "#
+        )
+        .bind(outbox_message.from_identity)
+        .bind(outbox_message.to_identity)
+        .bind(outbox_message.device_uids)
+        .bind(outbox_message.wrapped_key)
+        .bind(outbox_message.encrypted_content)
+        .bind(outbox_message.is_application_message)
+        .bind(outbox_message.delivery_state)
+        .bind(outbox_message.attempt_count)
+        .bind(outbox_message.next_attempt_timestamp)
+        .bind(outbox_message.message_uid)
+        .bind(outbox_message.creation_timestamp)
+        .bind(outbox_message.attachment_chunk_counts)
+        .bind(outbox_message.upload_nonce)
+        .execute(db)
+        .await?;
+
+        Ok(result.last_insert_rowid())
+    }
+
+    pub async fn set_upload_nonce<'e>(db: impl SqliteExecutor<'e>, id: i64, upload_nonce: &[u8]) -> Result<()> {
+        sqlx::query("UPDATE outbox_messages SET upload_nonce = $1 WHERE id = $2")
+            .bind(upload_nonce)
+            .bind(id)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn mark_sent<'e>(db: impl SqliteExecutor<'e>, id: i64, attempt_count: i64, message_uid: &[u8]) -> Result<()> {
+        sqlx::query("UPDATE outbox_messages SET delivery_state = $1, attempt_count = $2, message_uid = $3 WHERE id = $4")
+            .bind(i64::from(DeliveryState::Sent))
+            .bind(attempt_count)
+            .bind(message_uid)
+            .bind(id)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn schedule_retry<'e>(db: impl SqliteExecutor<'e>, id: i64, attempt_count: i64, next_attempt_timestamp: i64) -> Result<()> {
+        sqlx::query("UPDATE outbox_messages SET attempt_count = $1, next_attempt_timestamp = $2 WHERE id = $3")
+            .bind(attempt_count)
+            .bind(next_attempt_timestamp)
+            .bind(id)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn mark_failed<'e>(db: impl SqliteExecutor<'e>, id: i64, attempt_count: i64) -> Result<()> {
+        sqlx::query("UPDATE outbox_messages SET delivery_state = $1, attempt_count = $2 WHERE id = $3")
+            .bind(i64::from(DeliveryState::Failed))
+            .bind(attempt_count)
+            .bind(id)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn set_delivery_state<'e>(db: impl SqliteExecutor<'e>, id: i64, delivery_state: DeliveryState) -> Result<()> {
+        sqlx::query("UPDATE outbox_messages SET delivery_state = $1 WHERE id = $2")
+            .bind(i64::from(delivery_state))
+            .bind(id)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Used when the owned identity is deleted, messages not sent yet are dropped
+    pub async fn delete_by_owned_identity<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8]) -> Result<()> {
+        sqlx::query("DELETE FROM outbox_messages WHERE from_identity = $1")
+            .bind(owned_identity)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::server::OutboundServerMessage;
+
+    use super::{DeliveryState, OutboxMessage};
+
+    #[test]
+    fn outbound_message_round_trip() {
+        let message = OutboundServerMessage {
+            to_identity: vec![1; 32],
+            device_uids: vec![vec![2; 32], vec![3; 32]],
+            wrapped_key: vec![4; 32],
+            encrypted_content: vec![5; 64],
+            is_application_message: false,
+            attachment_chunk_counts: vec![3, 1],
+        };
+
+        let outbox_message = OutboxMessage::new(&[0; 32], &message).unwrap();
+        assert_eq!(outbox_message.to_outbound_message().unwrap(), message);
+        assert_eq!(outbox_message.get_delivery_state().unwrap(), DeliveryState::Queued);
+    }
+}
@@ -0,0 +1,68 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, SqliteExecutor};
+
+use crate::{current_timestamp, messages::payload::base64_bytes, Result};
+
+/// Other device of an owned identity, for example the one a profile was transferred from or to, serialized as is in backups
+#[derive(Clone, FromRow, Serialize, Deserialize, Debug)]
+pub struct OwnedDevice {
+    #[serde(skip)]
+    pub(crate) id: Option<i64>,
+    #[serde(with = "base64_bytes")]
+    pub(crate) owned_identity: Vec<u8>,
+    #[serde(with = "base64_bytes")]
+    pub(crate) device_uid: Vec<u8>,
+    pub(crate) creation_timestamp: i64,
+}
+
+impl OwnedDevice {
+    pub fn new(owned_identity: &[u8], device_uid: &[u8]) -> Self {
+        Self { id: None, owned_identity: owned_identity.to_vec(), device_uid: device_uid.to_vec(), creation_timestamp: current_timestamp() }
+    }
+
+    pub fn get_id(&self) -> Option<i64> {
+        self.id
+    }
+
+    pub fn get_owned_identity(&self) -> &[u8] {
+        &self.owned_identity
+    }
+
+    pub fn get_device_uid(&self) -> &[u8] {
+        &self.device_uid
+    }
+
+    pub fn get_creation_timestamp(&self) -> i64 {
+        self.creation_timestamp
+    }
+
+    pub async fn get_all<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8]) -> Result<Vec<OwnedDevice>> {
+        let owned_devices = sqlx::query_as::<_, OwnedDevice>("SELECT * FROM owned_devices WHERE owned_identity = $1 ORDER BY creation_timestamp, id")
+            .bind(owned_identity)
+            .fetch_all(db)
+            .await?;
+
+        Ok(owned_devices)
+    }
+
+    pub async fn insert_if_absent<'e>(db: impl SqliteExecutor<'e>, owned_device: OwnedDevice) -> Result<()> {
+        sqlx::query("INSERT OR IGNORE INTO owned_devices (owned_identity, device_uid, creation_timestamp) VALUES ($1, $2, $3)")
+            .bind(owned_device.owned_identity)
+            .bind(owned_device.device_uid)
+            .bind(owned_device.creation_timestamp)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Used when the owned identity is deleted
+    pub async fn delete_by_owned_identity<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8]) -> Result<()> {
+        sqlx::query("DELETE FROM owned_devices WHERE owned_identity = $1")
+            .bind(owned_identity)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+}
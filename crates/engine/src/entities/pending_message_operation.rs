@@ -0,0 +1,194 @@
+use bon::bon;
+use sqlx::{FromRow, SqliteExecutor};
+use uuid::Uuid;
+
+use crate::{EngineError, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageOperationType {
+    Edit,
+    Delete,
+    Reaction,
+}
+
+impl From<MessageOperationType> for i64 {
+    fn from(operation_type: MessageOperationType) -> Self {
+        match operation_type {
+            MessageOperationType::Edit => 0,
+            MessageOperationType::Delete => 1,
+            MessageOperationType::Reaction => 2,
+        }
+    }
+}
+
+impl TryFrom<i64> for MessageOperationType {
+    type Error = EngineError;
+
+    fn try_from(value: i64) -> std::result::Result<Self, Self::Error> {
+        match value {
+            0 => Ok(MessageOperationType::Edit),
+            1 => Ok(MessageOperationType::Delete),
+            2 => Ok(MessageOperationType::Reaction),
+            _ => Err(EngineError::Technical),
+        }
+    }
+}
+
+/// Edit, deletion or reaction received before the message it applies to, applied once that message arrives.
+///
+/// The message is referenced the way the payloads do, by its sender and its position in the sender thread.
+#[derive(Clone, FromRow, Debug)]
+pub struct PendingMessageOperation {
+    pub(crate) id: Option<i64>,
+    pub(crate) discussion_id: i64,
+    pub(crate) operation_type: i64,
+    /// Participant who edited, deleted or reacted
+    pub(crate) author_identity: Vec<u8>,
+    pub(crate) sender_identity: Vec<u8>,
+    pub(crate) sender_thread_identifier: Vec<u8>,
+    pub(crate) sender_sequence_number: i64,
+    /// The new body of an edit or the emoji of a reaction, `None` for a deletion or a removed reaction
+    pub(crate) body: Option<String>,
+    pub(crate) server_timestamp: i64,
+}
+
+#[bon]
+impl PendingMessageOperation {
+    #[builder]
+    pub fn new(
+        discussion_id: i64,
+        operation_type: MessageOperationType,
+        author_identity: Vec<u8>,
+        sender_identity: Vec<u8>,
+        sender_thread_identifier: Uuid,
+        sender_sequence_number: i64,
+        body: Option<String>,
+        server_timestamp: i64,
+    ) -> Self {
+        Self {
+            id: None,
+            discussion_id,
+            operation_type: operation_type.into(),
+            author_identity,
+            sender_identity,
+            sender_thread_identifier: sender_thread_identifier.as_bytes().to_vec(),
+            sender_sequence_number,
+            body,
+            server_timestamp,
+        }
+    }
+}
+
+impl PendingMessageOperation {
+    pub fn get_id(&self) -> Option<i64> {
+        self.id
+    }
+
+    pub fn get_discussion_id(&self) -> i64 {
+        self.discussion_id
+    }
+
+    pub fn get_operation_type(&self) -> Result<MessageOperationType> {
+        self.operation_type.try_into()
+    }
+
+    pub fn get_author_identity(&self) -> &[u8] {
+        &self.author_identity
+    }
+
+    pub fn get_sender_identity(&self) -> &[u8] {
+        &self.sender_identity
+    }
+
+    pub fn get_sender_thread_identifier(&self) -> Result<Uuid> {
+        Uuid::from_slice(&self.sender_thread_identifier).map_err(|_| EngineError::Technical)
+    }
+
+    pub fn get_sender_sequence_number(&self) -> i64 {
+        self.sender_sequence_number
+    }
+
+    pub fn get_body(&self) -> Option<&str> {
+        self.body.as_deref()
+    }
+
+    pub fn get_server_timestamp(&self) -> i64 {
+        self.server_timestamp
+    }
+
+    /// Operations waiting for a message, in the order the server received them
+    pub async fn get_by_message<'e>(db: impl SqliteExecutor<'e>, discussion_id: i64, sender_identity: &[u8], sender_thread_identifier: &Uuid, sender_sequence_number: i64) -> Result<Vec<PendingMessageOperation>> {
+        let operations = sqlx::query_as::<_, PendingMessageOperation>(
+            r#"
+            SELECT * FROM pending_message_operations
+            WHERE discussion_id = $1 AND sender_identity = $2 AND sender_thread_identifier = $3 AND sender_sequence_number = $4
+            ORDER BY server_timestamp, id
+            "#
+        )
+        .bind(discussion_id)
+        .bind(sender_identity)
+        .bind(sender_thread_identifier.as_bytes().as_slice())
+        .bind(sender_sequence_number)
+        .fetch_all(db)
+        .await?;
+
+        Ok(operations)
+    }
+
+    pub async fn insert<'e>(db: impl SqliteExecutor<'e>, operation: PendingMessageOperation) -> Result<i64> {
+        let result = sqlx::query(
+            r#"
+            INSERT INTO pending_message_operations
+            (
+                discussion_id,
+                operation_type,
+                author_identity,
+                sender_identity,
+                sender_thread_identifier,
+                sender_sequence_number,
+                body,
+                server_timestamp
+            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            "#
+        )
+        .bind(operation.discussion_id)
+        .bind(operation.operation_type)
+        .bind(operation.author_identity)
+        .bind(operation.sender_identity)
+        .bind(operation.sender_thread_identifier)
+        .bind(operation.sender_sequence_number)
+        .bind(operation.body)
+        .bind(operation.server_timestamp)
+        .execute(db)
+        .await?;
+
+        Ok(result.last_insert_rowid())
+    }
+
+    pub async fn delete<'e>(db: impl SqliteExecutor<'e>, id: i64) -> Result<()> {
+        sqlx::query("DELETE FROM pending_message_operations WHERE id = $1")
+            .bind(id)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete_by_discussion<'e>(db: impl SqliteExecutor<'e>, discussion_id: i64) -> Result<()> {
+        sqlx::query("DELETE FROM pending_message_operations WHERE discussion_id = $1")
+            .bind(discussion_id)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete_by_owned_identity<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8]) -> Result<()> {
+        sqlx::query("DELETE FROM pending_message_operations WHERE discussion_id IN (SELECT id FROM discussions WHERE owned_identity = $1)")
+            .bind(owned_identity)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+}
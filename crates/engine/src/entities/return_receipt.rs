@@ -0,0 +1,154 @@
+use sqlx::{FromRow, SqliteExecutor};
+
+use crate::{current_timestamp, EngineError, Result};
+
+/// Status reported to the sender of a message, encrypted in the payload of a return receipt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReturnReceiptStatus {
+    Delivered,
+    Read,
+}
+
+impl From<ReturnReceiptStatus> for i64 {
+    fn from(status: ReturnReceiptStatus) -> Self {
+        match status {
+            ReturnReceiptStatus::Delivered => 1,
+            ReturnReceiptStatus::Read => 2,
+        }
+    }
+}
+
+impl TryFrom<i64> for ReturnReceiptStatus {
+    type Error = EngineError;
+
+    fn try_from(value: i64) -> std::result::Result<Self, Self::Error> {
+        match value {
+            1 => Ok(ReturnReceiptStatus::Delivered),
+            2 => Ok(ReturnReceiptStatus::Read),
+            _ => Err(EngineError::Technical),
+        }
+    }
+}
+
+/// Return receipt waiting to be uploaded, it is deleted once the server accepted it
+#[derive(Clone, FromRow, Debug)]
+pub struct OutboxReturnReceipt {
+    pub(crate) id: Option<i64>,
+    pub(crate) owned_identity: Vec<u8>,
+    pub(crate) to_identity: Vec<u8>,
+    pub(crate) nonce: Vec<u8>,
+    pub(crate) encrypted_payload: Vec<u8>,
+    pub(crate) attempt_count: i64,
+    pub(crate) next_attempt_timestamp: i64,
+}
+
+impl OutboxReturnReceipt {
+    pub fn new(owned_identity: &[u8], to_identity: &[u8], nonce: &[u8], encrypted_payload: Vec<u8>) -> Self {
+        Self {
+            id: None,
+            owned_identity: owned_identity.to_vec(),
+            to_identity: to_identity.to_vec(),
+            nonce: nonce.to_vec(),
+            encrypted_payload,
+            attempt_count: 0,
+            next_attempt_timestamp: current_timestamp(),
+        }
+    }
+
+    pub fn get_id(&self) -> Option<i64> {
+        self.id
+    }
+
+    pub fn get_owned_identity(&self) -> &[u8] {
+        &self.owned_identity
+    }
+
+    pub fn get_to_identity(&self) -> &[u8] {
+        &self.to_identity
+    }
+
+    pub fn get_nonce(&self) -> &[u8] {
+        &self.nonce
+    }
+
+    pub fn get_encrypted_payload(&self) -> &[u8] {
+        &self.encrypted_payload
+    }
+
+    pub fn get_attempt_count(&self) -> i64 {
+        self.attempt_count
+    }
+
+    /// Receipts whose next attempt is due at `timestamp`
+    pub async fn get_due<'e>(db: impl SqliteExecutor<'e>, timestamp: i64) -> Result<Vec<OutboxReturnReceipt>> {
+        let return_receipts = sqlx::query_as::<_, OutboxReturnReceipt>("SELECT * FROM outbox_return_receipts WHERE next_attempt_timestamp <= $1 ORDER BY next_attempt_timestamp")
+            .bind(timestamp)
+            .fetch_all(db)
+            .await?;
+
+        Ok(return_receipts)
+    }
+
+    pub async fn get_next_attempt_timestamp<'e>(db: impl SqliteExecutor<'e>) -> Result<Option<i64>> {
+        let next_attempt_timestamp: Option<i64> = sqlx::query_scalar("SELECT MIN(next_attempt_timestamp) FROM outbox_return_receipts")
+            .fetch_one(db)
+            .await?;
+
+        Ok(next_attempt_timestamp)
+    }
+
+    pub async fn insert<'e>(db: impl SqliteExecutor<'e>, return_receipt: OutboxReturnReceipt) -> Result<i64> {
+        let result = sqlx::query(
+            r#"
+            INSERT INTO outbox_return_receipts
+            (
+                owned_identity,
+                to_identity,
+                nonce,
+                encrypted_payload,
+                attempt_count,
+                next_attempt_timestamp
+            ) VALUES ($1, $2, $3, $4, $5, $6)
+            "#
+        )
+        .bind(return_receipt.owned_identity)
+        .bind(return_receipt.to_identity)
+        .bind(return_receipt.nonce)
+        .bind(return_receipt.encrypted_payload)
+        .bind(return_receipt.attempt_count)
+        .bind(return_receipt.next_attempt_timestamp)
+        .execute(db)
+        .await?;
+
+        Ok(result.last_insert_rowid())
+    }
+
+    pub async fn schedule_retry<'e>(db: impl SqliteExecutor<'e>, id: i64, attempt_count: i64, next_attempt_timestamp: i64) -> Result<()> {
+        sqlx::query("UPDATE outbox_return_receipts SET attempt_count = $1, next_attempt_timestamp = $2 WHERE id = $3")
+            .bind(attempt_count)
+            .bind(next_attempt_timestamp)
+            .bind(id)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete<'e>(db: impl SqliteExecutor<'e>, id: i64) -> Result<()> {
+        sqlx::query("DELETE FROM outbox_return_receipts WHERE id = $1")
+            .bind(id)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete_by_owned_identity<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8]) -> Result<()> {
+        sqlx::query("DELETE FROM outbox_return_receipts WHERE owned_identity = $1")
+            .bind(owned_identity)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,290 @@
+use sqlx::{FromRow, SqliteExecutor};
+
+use crate::{current_timestamp, EngineError, Result};
+
+use super::identity::JsonIdentityDetails;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrustEstablishmentStatus {
+    /// Sent by the owned identity, waiting for the contact to accept
+    InvitationSent,
+    /// Waiting for the owned identity to accept or reject
+    InvitationReceived,
+    /// Accepted by the owned identity, waiting for the seed of the inviter
+    WaitingForSeed,
+    /// Both seeds are known, the owned identity must type the code displayed by the contact
+    CodeRequired,
+    /// The code typed by the owned identity is correct, waiting for the contact to type the other one
+    WaitingForContactCode,
+    /// Both codes were typed, the contact is verified
+    Finished,
+    /// Rejected by one of the sides, or aborted because the seed of the inviter did not match its commitment
+    Cancelled,
+}
+
+impl From<TrustEstablishmentStatus> for i64 {
+    fn from(status: TrustEstablishmentStatus) -> Self {
+        match status {
+            TrustEstablishmentStatus::InvitationSent => 0,
+            TrustEstablishmentStatus::InvitationReceived => 1,
+            TrustEstablishmentStatus::WaitingForSeed => 2,
+            TrustEstablishmentStatus::CodeRequired => 3,
+            TrustEstablishmentStatus::WaitingForContactCode => 4,
+            TrustEstablishmentStatus::Finished => 5,
+            TrustEstablishmentStatus::Cancelled => 6,
+        }
+    }
+}
+
+impl TryFrom<i64> for TrustEstablishmentStatus {
+    type Error = EngineError;
+
+    fn try_from(value: i64) -> std::result::Result<Self, Self::Error> {
+        match value {
+            0 => Ok(TrustEstablishmentStatus::InvitationSent),
+            1 => Ok(TrustEstablishmentStatus::InvitationReceived),
+            2 => Ok(TrustEstablishmentStatus::WaitingForSeed),
+            3 => Ok(TrustEstablishmentStatus::CodeRequired),
+            4 => Ok(TrustEstablishmentStatus::WaitingForContactCode),
+            5 => Ok(TrustEstablishmentStatus::Finished),
+            6 => Ok(TrustEstablishmentStatus::Cancelled),
+            _ => Err(EngineError::Technical),
+        }
+    }
+}
+
+/// One side of a trust establishment between an owned identity and `contact_identity`.
+///
+/// The inviter commits to its seed before learning the seed of the contact, `decommitment` is what it reveals once
+/// it did. The codes both users compare are derived from the two seeds.
+#[derive(Clone, FromRow, Debug)]
+pub struct TrustEstablishment {
+    pub(crate) id: Option<i64>,
+    pub(crate) owned_identity: Vec<u8>,
+    pub(crate) protocol_uid: Vec<u8>,
+    pub(crate) contact_identity: Vec<u8>,
+    /// JSON serialized `JsonIdentityDetails`, unknown to the inviter until the contact accepts
+    pub(crate) identity_details: Option<String>,
+    pub(crate) is_inviter: bool,
+    pub(crate) status: i64,
+    pub(crate) own_seed: Option<Vec<u8>>,
+    pub(crate) decommitment: Option<Vec<u8>>,
+    pub(crate) contact_commitment: Option<Vec<u8>>,
+    pub(crate) contact_seed: Option<Vec<u8>>,
+    pub(crate) contact_confirmed: bool,
+    pub(crate) creation_timestamp: i64,
+}
+
+impl TrustEstablishment {
+    /// Trust establishment started by the owned identity
+    pub fn new_sent(owned_identity: &[u8], protocol_uid: &[u8], contact_identity: &[u8], own_seed: &[u8], decommitment: &[u8]) -> Self {
+        Self {
+            id: None,
+            owned_identity: owned_identity.to_vec(),
+            protocol_uid: protocol_uid.to_vec(),
+            contact_identity: contact_identity.to_vec(),
+            identity_details: None,
+            is_inviter: true,
+            status: TrustEstablishmentStatus::InvitationSent.into(),
+            own_seed: Some(own_seed.to_vec()),
+            decommitment: Some(decommitment.to_vec()),
+            contact_commitment: None,
+            contact_seed: None,
+            contact_confirmed: false,
+            creation_timestamp: current_timestamp(),
+        }
+    }
+
+    /// Invitation received from `contact_identity`
+    pub fn new_received(owned_identity: &[u8], protocol_uid: &[u8], contact_identity: &[u8], identity_details: &JsonIdentityDetails, contact_commitment: &[u8]) -> Result<Self> {
+        Ok(Self {
+            id: None,
+            owned_identity: owned_identity.to_vec(),
+            protocol_uid: protocol_uid.to_vec(),
+            contact_identity: contact_identity.to_vec(),
+            identity_details: Some(serde_json::to_string(identity_details)?),
+            is_inviter: false,
+            status: TrustEstablishmentStatus::InvitationReceived.into(),
+            own_seed: None,
+            decommitment: None,
+            contact_commitment: Some(contact_commitment.to_vec()),
+            contact_seed: None,
+            contact_confirmed: false,
+            creation_timestamp: current_timestamp(),
+        })
+    }
+
+    pub fn get_id(&self) -> Option<i64> {
+        self.id
+    }
+
+    pub fn get_owned_identity(&self) -> &[u8] {
+        &self.owned_identity
+    }
+
+    pub fn get_protocol_uid(&self) -> &[u8] {
+        &self.protocol_uid
+    }
+
+    pub fn get_contact_identity(&self) -> &[u8] {
+        &self.contact_identity
+    }
+
+    pub fn get_identity_details(&self) -> Result<Option<JsonIdentityDetails>> {
+        self.identity_details.as_deref().map(serde_json::from_str).transpose().map_err(Into::into)
+    }
+
+    pub fn is_inviter(&self) -> bool {
+        self.is_inviter
+    }
+
+    pub fn get_status(&self) -> Result<TrustEstablishmentStatus> {
+        self.status.try_into()
+    }
+
+    pub fn get_decommitment(&self) -> Option<&[u8]> {
+        self.decommitment.as_deref()
+    }
+
+    pub fn get_contact_commitment(&self) -> Option<&[u8]> {
+        self.contact_commitment.as_deref()
+    }
+
+    /// Seeds of the inviter and of the invited identity, once both are known
+    pub fn get_seeds(&self) -> Option<(&[u8], &[u8])> {
+        let (own_seed, contact_seed) = (self.own_seed.as_deref()?, self.contact_seed.as_deref()?);
+        match self.is_inviter {
+            true => Some((own_seed, contact_seed)),
+            false => Some((contact_seed, own_seed)),
+        }
+    }
+
+    pub fn is_contact_confirmed(&self) -> bool {
+        self.contact_confirmed
+    }
+
+    pub fn get_creation_timestamp(&self) -> i64 {
+        self.creation_timestamp
+    }
+
+    pub async fn get_by_id<'e>(db: impl SqliteExecutor<'e>, id: i64) -> Result<Option<TrustEstablishment>> {
+        let trust_establishment = sqlx::query_as::<_, TrustEstablishment>("SELECT * FROM trust_establishments WHERE id = $1")
+            .bind(id)
+            .fetch_optional(db)
+            .await?;
+
+        Ok(trust_establishment)
+    }
+
+    pub async fn get_by_uid<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8], protocol_uid: &[u8]) -> Result<Option<TrustEstablishment>> {
+        let trust_establishment = sqlx::query_as::<_, TrustEstablishment>("SELECT * FROM trust_establishments WHERE owned_identity = $1 AND protocol_uid = $2")
+            .bind(owned_identity)
+            .bind(protocol_uid)
+            .fetch_optional(db)
+            .await?;
+
+        Ok(trust_establishment)
+    }
+
+    /// Most recent first
+    pub async fn get_all<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8]) -> Result<Vec<TrustEstablishment>> {
+        let trust_establishments = sqlx::query_as::<_, TrustEstablishment>("SELECT * FROM trust_establishments WHERE owned_identity = $1 ORDER BY creation_timestamp DESC, id DESC")
+            .bind(owned_identity)
+            .fetch_all(db)
+            .await?;
+
+        Ok(trust_establishments)
+    }
+
+    /// Returns the id of the new trust establishment, `None` when its invitation was already received
+    pub async fn insert_if_absent<'e>(db: impl SqliteExecutor<'e>, trust_establishment: TrustEstablishment) -> Result<Option<i64>> {
+        let result = sqlx::query(
+            r#"
+            INSERT OR IGNORE INTO trust_establishments
+            (
+                owned_identity,
+                protocol_uid,
+                contact_identity,
+                identity_details,
+                is_inviter,
+                status,
+                own_seed,
+                decommitment,
+                contact_commitment,
+                contact_seed,
+                contact_confirmed,
+                creation_timestamp
+            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+            "#
+        )
+        .bind(trust_establishment.owned_identity)
+        .bind(trust_establishment.protocol_uid)
+        .bind(trust_establishment.contact_identity)
+        .bind(trust_establishment.identity_details)
+        .bind(trust_establishment.is_inviter)
+        .bind(trust_establishment.status)
+        .bind(trust_establishment.own_seed)
+        .bind(trust_establishment.decommitment)
+        .bind(trust_establishment.contact_commitment)
+        .bind(trust_establishment.contact_seed)
+        .bind(trust_establishment.contact_confirmed)
+        .bind(trust_establishment.creation_timestamp)
+        .execute(db)
+        .await?;
+
+        Ok((result.rows_affected() > 0).then(|| result.last_insert_rowid()))
+    }
+
+    pub async fn set_status<'e>(db: impl SqliteExecutor<'e>, id: i64, status: TrustEstablishmentStatus) -> Result<()> {
+        sqlx::query("UPDATE trust_establishments SET status = $1 WHERE id = $2")
+            .bind(i64::from(status))
+            .bind(id)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Seed of the invited owned identity, chosen when it accepts
+    pub async fn set_own_seed<'e>(db: impl SqliteExecutor<'e>, id: i64, own_seed: &[u8]) -> Result<()> {
+        sqlx::query("UPDATE trust_establishments SET own_seed = $1 WHERE id = $2")
+            .bind(own_seed)
+            .bind(id)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Seed of the contact, with the details it sent along when it is the invited identity
+    pub async fn set_contact_seed<'e>(db: impl SqliteExecutor<'e>, id: i64, contact_seed: &[u8], identity_details: Option<&str>) -> Result<()> {
+        sqlx::query("UPDATE trust_establishments SET contact_seed = $1, identity_details = COALESCE($2, identity_details) WHERE id = $3")
+            .bind(contact_seed)
+            .bind(identity_details)
+            .bind(id)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// The contact typed the code displayed by the owned identity
+    pub async fn set_contact_confirmed<'e>(db: impl SqliteExecutor<'e>, id: i64) -> Result<()> {
+        sqlx::query("UPDATE trust_establishments SET contact_confirmed = 1 WHERE id = $1")
+            .bind(id)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Used when the owned identity is deleted
+    pub async fn delete_by_owned_identity<'e>(db: impl SqliteExecutor<'e>, owned_identity: &[u8]) -> Result<()> {
+        sqlx::query("DELETE FROM trust_establishments WHERE owned_identity = $1")
+            .bind(owned_identity)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,77 @@
+use tokio::sync::broadcast;
+
+use crate::{entities::{attachment::AttachmentStatus, message::MessageStatus, outbox_message::DeliveryState}, network::PushConnectionStatus};
+
+/// Number of events kept for slow subscribers before they start lagging
+pub const EVENT_CHANNEL_CAPACITY: usize = 256;
+
+/// Notifications published by the engine, see `Engine::subscribe_to_events`.
+///
+/// Identities are given by their bytes, consumers query the engine for the up to date entities.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EngineEvent {
+    OwnedIdentityCreated { owned_identity: Vec<u8> },
+    OwnedIdentityUpdated { owned_identity: Vec<u8> },
+    /// The owned identity and everything that belonged to it were deleted
+    OwnedIdentityDeleted { owned_identity: Vec<u8> },
+    /// The API key registered for the owned identity expired, it can't send messages anymore
+    ApiKeyExpired { owned_identity: Vec<u8> },
+    /// The license the API key of the owned identity belongs to has no seat left
+    ApiKeyLicenseExhausted { owned_identity: Vec<u8> },
+    ContactAdded { owned_identity: Vec<u8>, contact_identity: Vec<u8> },
+    /// The trust level or the details of a contact changed
+    ContactUpdated { owned_identity: Vec<u8>, contact_identity: Vec<u8> },
+    /// The contact was deleted with its discussion, it stays blocked if it was
+    ContactDeleted { owned_identity: Vec<u8>, contact_identity: Vec<u8> },
+    /// Messages from the identity are dropped until it is unblocked, it may not be a contact
+    ContactBlocked { owned_identity: Vec<u8>, contact_identity: Vec<u8> },
+    ContactUnblocked { owned_identity: Vec<u8>, contact_identity: Vec<u8> },
+    /// An introduction was received, answered, or accepted by the introduced identity
+    IntroductionUpdated { owned_identity: Vec<u8>, introduction_id: i64 },
+    /// A trust establishment was started, received, or moved to another step
+    TrustEstablishmentUpdated { owned_identity: Vec<u8>, trust_establishment_id: i64 },
+    /// An application message was decrypted and saved in a discussion.
+    ///
+    /// `notify` is false while the notifications of the owned identity are muted, unless the message mentions it.
+    MessageReceived { owned_identity: Vec<u8>, message_id: i64, notify: bool },
+    MessageDeliveryStateChanged { outbox_message_id: i64, delivery_state: DeliveryState },
+    /// A message was sent, delivered, read or failed, outbound statuses only move forward
+    MessageStatusChanged { message_id: i64, status: MessageStatus },
+    /// The body and attachments of an ephemeral message were removed
+    MessageWiped { message_id: i64 },
+    MessageDeleted { message_id: i64 },
+    /// The sender edited the body of a message, its previous bodies are kept
+    MessageEdited { message_id: i64 },
+    /// The sender or a group administrator deleted a message for everyone, its body and attachments were removed
+    MessageRemotelyDeleted { message_id: i64 },
+    /// A participant reacted to a message, changed or removed their reaction
+    MessageReactionsChanged { message_id: i64 },
+    /// The shared settings of a discussion changed, locally or from another participant
+    DiscussionSettingsUpdated { discussion_id: i64 },
+    /// The discussion was deleted with its messages and attachments
+    DiscussionDeleted { discussion_id: i64 },
+    /// A group was created, joined or updated, locally or by one of its administrators
+    GroupUpdated { owned_identity: Vec<u8>, group_id: i64 },
+    /// The owned identity left a group or was removed from it
+    GroupRemoved { owned_identity: Vec<u8>, group_uid: Vec<u8> },
+    /// A message downloaded from the server was stored in the inbox, waiting to be processed
+    InboxMessageStored { owned_identity: Vec<u8>, message_uid: Vec<u8> },
+    AttachmentProgress { attachment_id: i64, transferred_chunk_count: i64, chunk_count: i64 },
+    AttachmentStatusChanged { attachment_id: i64, status: AttachmentStatus },
+    /// An introduction or a trust establishment completed, `protocol_instance_uid` is its introduction or protocol uid
+    ProtocolFinished { owned_identity: Vec<u8>, protocol_instance_uid: Vec<u8> },
+    NetworkStatusChanged { owned_identity: Vec<u8>, status: PushConnectionStatus },
+}
+
+/// Sends an event to the current subscribers
+pub(crate) fn publish_event(events: &broadcast::Sender<EngineEvent>, event: EngineEvent) {
+    // Nobody listening is not an error
+    let _ = events.send(event);
+}
+
+/// Sends the events of the writes of a transaction, once it is committed
+pub(crate) fn publish_events(events: &broadcast::Sender<EngineEvent>, committed_events: Vec<EngineEvent>) {
+    for event in committed_events {
+        publish_event(events, event);
+    }
+}
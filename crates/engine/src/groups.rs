@@ -0,0 +1,568 @@
+//! Groups v2.
+//!
+//! A group is described by a blob stored on the server, see `blob`. Only administrators update it, then they send
+//! `BlobUpdated` to the members so that they fetch the new version. Invited members are pending until they accept:
+//! they send a `JoinRequest` to the administrators, which mark them as members in the blob. Removed members can't read
+//! the blob anymore, they get a `Kicked` signed by the administrator instead.
+
+use olvid_core::{crypto::prng::{PRNGHmacSHA256, PRNG}, cryptographic_identity::{CryptographicIdentity, OwnedCryptographicIdentity}, encoding::Encoder};
+
+use crate::{contacts::check_not_blocked, entities::{discussion::Discussion, group::{Group, GroupMember, GroupStatus}, message::Message, message_mention::UserMention}, events::{publish_events, EngineEvent}, messages::{payload::{JsonGroupMessage, JsonPayload}, queue_payload}, server::ServerClient, store::{Store, StoreTransaction}, Engine, EngineError, Result};
+
+use blob::{GroupChange, JsonGroupBlob, JsonGroupMember};
+
+mod blob;
+
+const GROUP_UID_LENGTH: usize = 32;
+/// Another administrator may update the blob between our download and our upload
+const GROUP_BLOB_UPDATE_ATTEMPTS: usize = 3;
+const KICK_SIGNATURE_PREFIX: &[u8] = b"groupKick";
+
+/// Removal of a member signed by an administrator, `version` is the one of the first blob without the member
+fn kick_statement(group_uid: &[u8], kicked_identity: &[u8], version: i64) -> Result<Vec<u8>> {
+    Ok(vec![group_uid.to_vec().encode()?, kicked_identity.to_vec().encode()?, version.encode()?].encode()?)
+}
+
+/// Queues a membership message for each recipient, the caller wakes up the outbox sender
+async fn queue_group_message(transaction: &mut dyn StoreTransaction, from_identity: &OwnedCryptographicIdentity, recipients: &[Vec<u8>], message: JsonGroupMessage, prng: &mut PRNGHmacSHA256) -> Result<()> {
+    let payload = JsonPayload { group: Some(message), ..Default::default() };
+    for recipient in recipients {
+        queue_payload(transaction, from_identity, recipient, &payload, prng).await?;
+    }
+
+    Ok(())
+}
+
+/// Creates the discussion of a joined group, or renames it after the group
+async fn sync_discussion(transaction: &mut dyn StoreTransaction, group: &Group) -> Result<()> {
+    let discussion = transaction.discussions().get_group(group.get_owned_identity(), group.get_group_uid()).await?;
+    match discussion {
+        Some(discussion) if discussion.get_title() != group.get_name() => transaction.discussions().set_title(discussion.get_id().ok_or(EngineError::Technical)?, group.get_name()).await,
+        Some(_) => Ok(()),
+        None => transaction.discussions().insert(Discussion::new_group(group.get_owned_identity(), group.get_group_uid(), group.get_name())).await.map(|_| ()),
+    }
+}
+
+/// Saves a version of the blob locally
+async fn save_blob(transaction: &mut dyn StoreTransaction, events: &mut Vec<EngineEvent>, owned_identity: &[u8], group_uid: &[u8], blob_key: &[u8], blob: &JsonGroupBlob, status: GroupStatus) -> Result<Group> {
+    let group_id = transaction.groups().upsert(Group::new(owned_identity, group_uid, &blob.name, blob.version, blob_key, status)).await?;
+    let members = blob.members.iter().map(|member| GroupMember::new(group_id, &member.identity, member.is_admin, member.is_pending)).collect();
+    transaction.group_members().replace_all(group_id, members).await?;
+
+    let group = transaction.groups().get_by_id(group_id).await?.ok_or(EngineError::Technical)?;
+    if group.get_status()? == GroupStatus::Joined {
+        sync_discussion(transaction, &group).await?;
+    }
+    events.push(EngineEvent::GroupUpdated { owned_identity: owned_identity.to_vec(), group_id });
+
+    Ok(group)
+}
+
+/// Whether `identity` is an administrator in the last version of the group saved locally
+async fn is_saved_admin(transaction: &mut dyn StoreTransaction, group: &Group, identity: &[u8]) -> Result<bool> {
+    Ok(transaction.group_members().get(group.get_id().ok_or(EngineError::Technical)?, identity).await?.is_some_and(|member| member.is_admin()))
+}
+
+async fn delete_group(transaction: &mut dyn StoreTransaction, events: &mut Vec<EngineEvent>, group: &Group) -> Result<()> {
+    transaction.groups().delete(group.get_id().ok_or(EngineError::Technical)?).await?;
+
+    events.push(EngineEvent::GroupRemoved { owned_identity: group.get_owned_identity().to_vec(), group_uid: group.get_group_uid().to_vec() });
+
+    Ok(())
+}
+
+/// Applies a change to the last version of the blob as an administrator, then notifies the members.
+///
+/// The blob key is rotated when members are removed, so that they can't read the next versions. The change requested
+/// by a member is ignored when `requester` is not a member in the last version.
+async fn update_group(
+    transaction: &mut dyn StoreTransaction,
+    events: &mut Vec<EngineEvent>,
+    server_client: &ServerClient,
+    owned_identity: &OwnedCryptographicIdentity,
+    group: &Group,
+    change: &GroupChange,
+    requester: Option<&[u8]>,
+) -> Result<()> {
+    let bytes_owned_identity = owned_identity.get_crypto_identity().get_identity();
+    let group_uid = group.get_group_uid();
+    let mut prng = Engine::get_default_hmac_prng()?;
+
+    let mut attempt = 0;
+    let (blob, blob_key, removed_identities) = loop {
+        attempt += 1;
+        let (encrypted_blob, version) = server_client.get_group_blob(owned_identity, group_uid, &mut prng).await?;
+        let (mut blob, signer_identity) = blob::open(&encrypted_blob, group.get_blob_key())?;
+        if !is_saved_admin(transaction, group, &signer_identity).await? {
+            return Err(EngineError::InvalidGroupBlob);
+        }
+        if !blob.is_admin(&bytes_owned_identity) {
+            return Err(EngineError::NotGroupAdmin);
+        }
+        if requester.is_some_and(|requester| blob.get_member(requester).is_none()) {
+            return Ok(());
+        }
+
+        let previous_identities: Vec<Vec<u8>> = blob.members.iter().map(|member| member.identity.clone()).collect();
+        if !blob.apply(change)? {
+            return Ok(());
+        }
+        blob.version = version + 1;
+
+        let removed_identities: Vec<Vec<u8>> = previous_identities.into_iter().filter(|identity| blob.get_member(identity).is_none()).collect();
+        let blob_key = match removed_identities.is_empty() {
+            true => group.get_blob_key().to_vec(),
+            false => blob::generate_blob_key(&mut prng)?,
+        };
+
+        let encrypted_blob = blob::seal(&blob, owned_identity, &blob_key, &mut prng)?;
+        match server_client.update_group_blob(owned_identity, group_uid, &encrypted_blob, blob.version, &mut prng).await {
+            Ok(()) => break (blob, blob_key, removed_identities),
+            Err(EngineError::GroupBlobOutdated) if attempt < GROUP_BLOB_UPDATE_ATTEMPTS => continue,
+            Err(error) => return Err(error),
+        }
+    };
+
+    save_blob(transaction, events, &bytes_owned_identity, group_uid, &blob_key, &blob, GroupStatus::Joined).await?;
+
+    let members: Vec<Vec<u8>> = blob.members.into_iter().map(|member| member.identity).filter(|identity| *identity != bytes_owned_identity).collect();
+    queue_group_message(transaction, owned_identity, &members, JsonGroupMessage::BlobUpdated { group_uid: group_uid.to_vec(), blob_key }, &mut prng).await?;
+    for removed_identity in removed_identities {
+        let signature = owned_identity.sign(KICK_SIGNATURE_PREFIX, &kick_statement(group_uid, &removed_identity, blob.version)?, &mut prng).map_err(|_| EngineError::Technical)?;
+        let kicked = JsonGroupMessage::Kicked { group_uid: group_uid.to_vec(), version: blob.version, signature };
+        queue_group_message(transaction, owned_identity, &[removed_identity], kicked, &mut prng).await?;
+    }
+
+    Ok(())
+}
+
+/// Members the messages of a group discussion are sent to: the ones that joined, except the owned identity
+pub(crate) async fn get_discussion_recipients(store: &dyn Store, discussion: &Discussion) -> Result<Vec<Vec<u8>>> {
+    let Some(group_uid) = discussion.get_group_identifier() else {
+        return Ok(vec![]);
+    };
+    let group = store.groups().get_by_uid(discussion.get_owned_identity(), group_uid).await?.ok_or(EngineError::UnknownGroup)?;
+
+    let members = store.group_members().get_all(group.get_id().ok_or(EngineError::Technical)?).await?;
+    Ok(
+        members.into_iter()
+            .filter(|member| !member.is_pending() && member.get_member_identity() != discussion.get_owned_identity())
+            .map(|member| member.get_member_identity().to_vec())
+            .collect()
+    )
+}
+
+/// Discussion of a joined group, `None` when `sender_identity` is not one of its members
+pub(crate) async fn get_member_discussion(transaction: &mut dyn StoreTransaction, owned_identity: &[u8], group_uid: &[u8], sender_identity: &[u8]) -> Result<Option<Discussion>> {
+    let Some(group) = transaction.groups().get_by_uid(owned_identity, group_uid).await? else {
+        return Ok(None);
+    };
+    if group.get_status()? != GroupStatus::Joined || transaction.group_members().get(group.get_id().ok_or(EngineError::Technical)?, sender_identity).await?.is_none() {
+        return Ok(None);
+    }
+
+    transaction.discussions().get_group(owned_identity, group_uid).await
+}
+
+/// Handles a membership message, the ones that can't be authenticated or don't apply are ignored
+pub(crate) async fn process_group_message(
+    transaction: &mut dyn StoreTransaction,
+    events: &mut Vec<EngineEvent>,
+    server_client: &ServerClient,
+    owned_identity: &OwnedCryptographicIdentity,
+    sender_identity: &[u8],
+    message: JsonGroupMessage,
+) -> Result<()> {
+    let result = match message {
+        JsonGroupMessage::BlobUpdated { group_uid, blob_key } => process_blob_updated(transaction, events, server_client, owned_identity, sender_identity, &group_uid, &blob_key).await,
+        JsonGroupMessage::JoinRequest { group_uid } => {
+            let change = GroupChange::ConfirmMember(sender_identity.to_vec());
+            process_admin_request(transaction, events, server_client, owned_identity, sender_identity, &group_uid, &change).await
+        }
+        JsonGroupMessage::Left { group_uid } => {
+            let change = GroupChange::RemoveMembers(vec![sender_identity.to_vec()]);
+            process_admin_request(transaction, events, server_client, owned_identity, sender_identity, &group_uid, &change).await
+        }
+        JsonGroupMessage::Kicked { group_uid, version, signature } => match is_kick_signed(owned_identity, sender_identity, &group_uid, version, &signature)? {
+            true => process_kicked(transaction, events, server_client, owned_identity, sender_identity, &group_uid, version).await,
+            false => Ok(()),
+        },
+    };
+
+    match result {
+        Err(EngineError::InvalidGroupBlob | EngineError::NotGroupAdmin | EngineError::UnknownGroup) => Ok(()),
+        result => result,
+    }
+}
+
+async fn process_blob_updated(
+    transaction: &mut dyn StoreTransaction,
+    events: &mut Vec<EngineEvent>,
+    server_client: &ServerClient,
+    owned_identity: &OwnedCryptographicIdentity,
+    sender_identity: &[u8],
+    group_uid: &[u8],
+    blob_key: &[u8],
+) -> Result<()> {
+    let bytes_owned_identity = owned_identity.get_crypto_identity().get_identity();
+
+    let (encrypted_blob, version) = server_client.get_group_blob(owned_identity, group_uid, &mut Engine::get_default_hmac_prng()?).await?;
+    let (blob, signer_identity) = blob::open(&encrypted_blob, blob_key)?;
+    // An older blob signed by an administrator must not be replayed as a newer version
+    if blob.version != version || signer_identity != sender_identity {
+        return Err(EngineError::InvalidGroupBlob);
+    }
+
+    // The administrators listed by the blob are trusted when joining only, afterwards they are the ones of the saved version
+    let local_group = transaction.groups().get_by_uid(&bytes_owned_identity, group_uid).await?;
+    if let Some(group) = local_group {
+        if group.get_version() >= version {
+            return Ok(());
+        }
+        if !is_saved_admin(transaction, &group, sender_identity).await? {
+            return Err(EngineError::NotGroupAdmin);
+        }
+    }
+    let Some(owned_member) = blob.get_member(&bytes_owned_identity) else {
+        return Ok(());
+    };
+    let status = match owned_member.is_pending {
+        true => GroupStatus::Invited,
+        false => GroupStatus::Joined,
+    };
+
+    save_blob(transaction, events, &bytes_owned_identity, group_uid, blob_key, &blob, status).await?;
+
+    Ok(())
+}
+
+/// Requests from members are applied by the administrators only, for the members of the last version of the blob
+async fn process_admin_request(
+    transaction: &mut dyn StoreTransaction,
+    events: &mut Vec<EngineEvent>,
+    server_client: &ServerClient,
+    owned_identity: &OwnedCryptographicIdentity,
+    sender_identity: &[u8],
+    group_uid: &[u8],
+    change: &GroupChange,
+) -> Result<()> {
+    let bytes_owned_identity = owned_identity.get_crypto_identity().get_identity();
+    let group = transaction.groups().get_by_uid(&bytes_owned_identity, group_uid).await?.ok_or(EngineError::UnknownGroup)?;
+
+    let is_admin = is_saved_admin(transaction, &group, &bytes_owned_identity).await?;
+    let is_member = transaction.group_members().get(group.get_id().ok_or(EngineError::Technical)?, sender_identity).await?.is_some();
+    if !is_admin || !is_member {
+        return Ok(());
+    }
+
+    update_group(transaction, events, server_client, owned_identity, &group, change, Some(sender_identity)).await
+}
+
+fn is_kick_signed(owned_identity: &OwnedCryptographicIdentity, sender_identity: &[u8], group_uid: &[u8], version: i64, signature: &[u8]) -> Result<bool> {
+    let statement = kick_statement(group_uid, &owned_identity.get_crypto_identity().get_identity(), version)?;
+
+    Ok(
+        CryptographicIdentity::from_raw(sender_identity)
+            .and_then(|identity| identity.verify_signature(KICK_SIGNATURE_PREFIX, &statement, signature))
+            .unwrap_or(false)
+    )
+}
+
+/// Deletes the group once its removal from the blob `version` is signed, see `is_kick_signed`, by an administrator of the
+/// known version, provided that the blob on the server no longer lists the owned identity
+async fn process_kicked(
+    transaction: &mut dyn StoreTransaction,
+    events: &mut Vec<EngineEvent>,
+    server_client: &ServerClient,
+    owned_identity: &OwnedCryptographicIdentity,
+    sender_identity: &[u8],
+    group_uid: &[u8],
+    version: i64,
+) -> Result<()> {
+    let bytes_owned_identity = owned_identity.get_crypto_identity().get_identity();
+    let group = transaction.groups().get_by_uid(&bytes_owned_identity, group_uid).await?.ok_or(EngineError::UnknownGroup)?;
+
+    if !is_saved_admin(transaction, &group, sender_identity).await? || version <= group.get_version() {
+        return Ok(());
+    }
+
+    // The blob of the removal must be on the server, and still readable only when the key was not rotated
+    let (encrypted_blob, server_version) = server_client.get_group_blob(owned_identity, group_uid, &mut Engine::get_default_hmac_prng()?).await?;
+    let is_still_member = blob::open(&encrypted_blob, group.get_blob_key()).is_ok_and(|(blob, _)| blob.get_member(&bytes_owned_identity).is_some());
+    if server_version < version || is_still_member {
+        return Ok(());
+    }
+
+    delete_group(transaction, events, &group).await
+}
+
+impl Engine {
+    pub async fn get_groups(&self, bytes_owned_identity: &[u8]) -> Result<Vec<Group>> {
+        self.store.groups().get_all(bytes_owned_identity).await
+    }
+
+    pub async fn get_group(&self, group_id: i64) -> Result<Option<Group>> {
+        self.store.groups().get_by_id(group_id).await
+    }
+
+    /// Members of a group, the owned identity included
+    pub async fn get_group_members(&self, group_id: i64) -> Result<Vec<GroupMember>> {
+        self.store.group_members().get_all(group_id).await
+    }
+
+    /// Creates a group administered by the owned identity and invites contacts to it
+    pub async fn create_group(&self, bytes_owned_identity: &[u8], name: &str, member_identities: &[Vec<u8>]) -> Result<Group> {
+        let owned_identity = self.get_owned_identity(bytes_owned_identity).await?.get_private_identity()?;
+        for member_identity in member_identities {
+            self.store.contacts().get(bytes_owned_identity, member_identity).await?.ok_or(EngineError::UnknownContact)?;
+            check_not_blocked(&*self.store, bytes_owned_identity, member_identity).await?;
+        }
+
+        let mut prng = Self::get_default_hmac_prng()?;
+        let group_uid = prng.bytes(GROUP_UID_LENGTH).map_err(|_| EngineError::PRNG)?;
+        let blob_key = blob::generate_blob_key(&mut prng)?;
+
+        let mut members = vec![JsonGroupMember { identity: bytes_owned_identity.to_vec(), is_admin: true, is_pending: false }];
+        for member_identity in member_identities.iter().filter(|identity| identity.as_slice() != bytes_owned_identity) {
+            if !members.iter().any(|member| member.identity == *member_identity) {
+                members.push(JsonGroupMember { identity: member_identity.clone(), is_admin: false, is_pending: true });
+            }
+        }
+        let blob = JsonGroupBlob { version: 0, name: name.to_owned(), members };
+
+        let encrypted_blob = blob::seal(&blob, &owned_identity, &blob_key, &mut prng)?;
+        self.server_client.create_group_blob(&owned_identity, &group_uid, &encrypted_blob, &mut prng).await?;
+
+        let mut events = vec![];
+        let mut transaction = self.store.begin().await?;
+        let group = save_blob(&mut *transaction, &mut events, bytes_owned_identity, &group_uid, &blob_key, &blob, GroupStatus::Joined).await?;
+        let invited_members: Vec<Vec<u8>> = blob.members.into_iter().skip(1).map(|member| member.identity).collect();
+        queue_group_message(&mut *transaction, &owned_identity, &invited_members, JsonGroupMessage::BlobUpdated { group_uid, blob_key }, &mut prng).await?;
+        transaction.commit().await?;
+        self.outbox_wake_up.notify_one();
+        publish_events(&self.events, events);
+
+        Ok(group)
+    }
+
+    /// Joins a group the owned identity was invited to, its administrators then mark it as a member
+    pub async fn accept_group_invitation(&self, group_id: i64) -> Result<()> {
+        let group = self.store.groups().get_by_id(group_id).await?.ok_or(EngineError::UnknownGroup)?;
+        if group.get_status()? == GroupStatus::Joined {
+            return Ok(());
+        }
+
+        let admins: Vec<Vec<u8>> = self.store.group_members().get_all(group_id).await?.into_iter()
+            .filter(GroupMember::is_admin)
+            .map(|member| member.get_member_identity().to_vec())
+            .collect();
+        let owned_identity = self.get_owned_identity(group.get_owned_identity()).await?.get_private_identity()?;
+        let join_request = JsonGroupMessage::JoinRequest { group_uid: group.get_group_uid().to_vec() };
+
+        let mut transaction = self.store.begin().await?;
+        transaction.groups().set_status(group_id, GroupStatus::Joined).await?;
+        sync_discussion(&mut *transaction, &group).await?;
+        queue_group_message(&mut *transaction, &owned_identity, &admins, join_request, &mut Self::get_default_hmac_prng()?).await?;
+        transaction.commit().await?;
+        self.outbox_wake_up.notify_one();
+        self.publish_event(EngineEvent::GroupUpdated { owned_identity: group.get_owned_identity().to_vec(), group_id });
+
+        Ok(())
+    }
+
+    /// Leaves a group, or declines the invitation to it.
+    ///
+    /// The last administrator can't leave while other members remain, it has to promote one of them first.
+    pub async fn leave_group(&self, group_id: i64) -> Result<()> {
+        let group = self.store.groups().get_by_id(group_id).await?.ok_or(EngineError::UnknownGroup)?;
+        let bytes_owned_identity = group.get_owned_identity();
+        let members = self.store.group_members().get_all(group_id).await?;
+
+        let is_admin = members.iter().any(|member| member.get_member_identity() == bytes_owned_identity && member.is_admin());
+        let other_admins: Vec<Vec<u8>> = members.iter()
+            .filter(|member| member.is_admin() && member.get_member_identity() != bytes_owned_identity)
+            .map(|member| member.get_member_identity().to_vec())
+            .collect();
+        if is_admin && other_admins.is_empty() && members.len() > 1 {
+            return Err(EngineError::LastGroupAdmin);
+        }
+
+        let owned_identity = self.get_owned_identity(bytes_owned_identity).await?.get_private_identity()?;
+        let left = JsonGroupMessage::Left { group_uid: group.get_group_uid().to_vec() };
+        let mut events = vec![];
+        let mut transaction = self.store.begin().await?;
+        queue_group_message(&mut *transaction, &owned_identity, &other_admins, left, &mut Self::get_default_hmac_prng()?).await?;
+        delete_group(&mut *transaction, &mut events, &group).await?;
+        transaction.commit().await?;
+        self.outbox_wake_up.notify_one();
+        publish_events(&self.events, events);
+
+        Ok(())
+    }
+
+    /// Invites contacts to a group administered by the owned identity
+    pub async fn add_group_members(&self, group_id: i64, member_identities: &[Vec<u8>]) -> Result<()> {
+        let group = self.store.groups().get_by_id(group_id).await?.ok_or(EngineError::UnknownGroup)?;
+        for member_identity in member_identities {
+            self.store.contacts().get(group.get_owned_identity(), member_identity).await?.ok_or(EngineError::UnknownContact)?;
+            check_not_blocked(&*self.store, group.get_owned_identity(), member_identity).await?;
+        }
+
+        self.update_group(&group, &GroupChange::AddMembers(member_identities.to_vec())).await
+    }
+
+    /// Removes members from a group administered by the owned identity, they can't read the group anymore
+    pub async fn remove_group_members(&self, group_id: i64, member_identities: &[Vec<u8>]) -> Result<()> {
+        let group = self.store.groups().get_by_id(group_id).await?.ok_or(EngineError::UnknownGroup)?;
+        // The owned identity leaves with `leave_group`
+        let member_identities = member_identities.iter().filter(|identity| identity.as_slice() != group.get_owned_identity()).cloned().collect();
+
+        self.update_group(&group, &GroupChange::RemoveMembers(member_identities)).await
+    }
+
+    /// Grants or revokes the administrator permission, a group always keeps at least one administrator
+    pub async fn set_group_member_admin(&self, group_id: i64, member_identity: &[u8], is_admin: bool) -> Result<()> {
+        let group = self.store.groups().get_by_id(group_id).await?.ok_or(EngineError::UnknownGroup)?;
+        self.update_group(&group, &GroupChange::SetAdmin { identity: member_identity.to_vec(), is_admin }).await
+    }
+
+    pub async fn rename_group(&self, group_id: i64, name: &str) -> Result<()> {
+        let group = self.store.groups().get_by_id(group_id).await?.ok_or(EngineError::UnknownGroup)?;
+        self.update_group(&group, &GroupChange::Rename(name.to_owned())).await
+    }
+
+    /// Saves a text message in the discussion of a joined group and queues a copy for each member that joined.
+    ///
+    /// The message references the copy of the first member, see `send_text_message` for `reply_to_message_id` and `mentions`.
+    pub async fn send_group_text_message(&self, group_id: i64, body: &str, reply_to_message_id: Option<i64>, mentions: &[UserMention]) -> Result<Message> {
+        let group = self.store.groups().get_by_id(group_id).await?
+            .filter(|group| matches!(group.get_status(), Ok(GroupStatus::Joined)))
+            .ok_or(EngineError::UnknownGroup)?;
+        let discussion = self.store.discussions().get_group(group.get_owned_identity(), group.get_group_uid()).await?.ok_or(EngineError::Technical)?;
+        let recipients = get_discussion_recipients(&*self.store, &discussion).await?;
+
+        self.send_text(&discussion, &recipients, body, reply_to_message_id, mentions, &[]).await
+    }
+
+    /// Removes an identity from the joined groups administered by the owned identity, used when it is blocked or deleted
+    pub(crate) async fn remove_from_administered_groups(&self, bytes_owned_identity: &[u8], identity: &[u8]) -> Result<()> {
+        for group in self.store.groups().get_all(bytes_owned_identity).await? {
+            if group.get_status()? != GroupStatus::Joined {
+                continue;
+            }
+            let members = self.store.group_members().get_all(group.get_id().ok_or(EngineError::Technical)?).await?;
+            let is_admin = members.iter().any(|member| member.get_member_identity() == bytes_owned_identity && member.is_admin());
+            if is_admin && members.iter().any(|member| member.get_member_identity() == identity) {
+                self.update_group(&group, &GroupChange::RemoveMembers(vec![identity.to_vec()])).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn update_group(&self, group: &Group, change: &GroupChange) -> Result<()> {
+        let owned_identity = self.get_owned_identity(group.get_owned_identity()).await?.get_private_identity()?;
+        let mut events = vec![];
+        let mut transaction = self.store.begin().await?;
+        update_group(&mut *transaction, &mut events, &self.server_client, &owned_identity, group, change, None).await?;
+        transaction.commit().await?;
+        self.outbox_wake_up.notify_one();
+        publish_events(&self.events, events);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use mock_server::MockServer;
+    use olvid_core::cryptographic_identity::OwnedCryptographicIdentity;
+
+    use crate::{configuration::EngineConfiguration, entities::{group::Group, identity::JsonIdentityDetails}, messages::payload::JsonGroupMessage, store::MemoryStore, Engine, EngineError};
+
+    use super::{blob, is_kick_signed, kick_statement, process_group_message, GroupChange, KICK_SIGNATURE_PREFIX};
+
+    fn details(first_name: &str) -> JsonIdentityDetails {
+        JsonIdentityDetails::builder().first_name(first_name.to_owned()).build()
+    }
+
+    async fn engine_with_identity(server: &MockServer, first_name: &str) -> (Engine, Vec<u8>) {
+        let configuration = EngineConfiguration::builder().server_url(server.url()).build();
+        let mut engine = Engine::init_with_store(configuration, Arc::new(MemoryStore::new())).await.unwrap();
+        let identity = engine.generate_simple_identity(details(first_name)).await.unwrap().identity.get_identity();
+        (engine, identity)
+    }
+
+    async fn fetch_until_invited(engine: &mut Engine, owned_identity: &[u8]) -> Group {
+        for _ in 0..100 {
+            engine.fetch_messages(owned_identity).await.unwrap();
+            if let Some(group) = engine.get_groups(owned_identity).await.unwrap().pop() {
+                return group;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        panic!("no group invitation received");
+    }
+
+    #[tokio::test]
+    async fn blob_forged_by_a_member_is_rejected() {
+        let server = MockServer::start().await.unwrap();
+        let (alice_engine, alice) = engine_with_identity(&server, "Alice").await;
+        let (mut bob_engine, bob) = engine_with_identity(&server, "Bob").await;
+        let (mut carol_engine, carol) = engine_with_identity(&server, "Carol").await;
+        for (engine, owned_identity, first_name) in [(&bob_engine, &bob, "Bob"), (&carol_engine, &carol, "Carol")] {
+            alice_engine.add_contact(&alice, owned_identity, &details(first_name)).await.unwrap();
+            engine.add_contact(owned_identity, &alice, &details("Alice")).await.unwrap();
+        }
+        let alice_group = alice_engine.create_group(&alice, "Friends", &[bob.clone(), carol.clone()]).await.unwrap();
+        let bob_group = fetch_until_invited(&mut bob_engine, &bob).await;
+        fetch_until_invited(&mut carol_engine, &carol).await;
+
+        // Bob, who is not an administrator, makes himself one in a blob he signs
+        let bob_identity = bob_engine.get_owned_identity(&bob).await.unwrap().get_private_identity().unwrap();
+        let mut prng = Engine::get_default_hmac_prng().unwrap();
+        let group_uid = bob_group.get_group_uid();
+        let (encrypted_blob, version) = bob_engine.server_client.get_group_blob(&bob_identity, group_uid, &mut prng).await.unwrap();
+        let (mut forged_blob, _) = blob::open(&encrypted_blob, bob_group.get_blob_key()).unwrap();
+        forged_blob.apply(&GroupChange::SetAdmin { identity: bob.clone(), is_admin: true }).unwrap();
+        forged_blob.apply(&GroupChange::Rename("Forged".to_owned())).unwrap();
+        forged_blob.version = version + 1;
+        let encrypted_forged_blob = blob::seal(&forged_blob, &bob_identity, bob_group.get_blob_key(), &mut prng).unwrap();
+        bob_engine.server_client.update_group_blob(&bob_identity, group_uid, &encrypted_forged_blob, forged_blob.version, &mut prng).await.unwrap();
+
+        for (engine, owned_identity) in [(&alice_engine, &alice), (&carol_engine, &carol)] {
+            let private_identity = engine.get_owned_identity(owned_identity).await.unwrap().get_private_identity().unwrap();
+            let blob_updated = JsonGroupMessage::BlobUpdated { group_uid: group_uid.to_vec(), blob_key: bob_group.get_blob_key().to_vec() };
+            let mut transaction = engine.store.begin().await.unwrap();
+            process_group_message(&mut *transaction, &mut Vec::new(), &engine.server_client, &private_identity, &bob, blob_updated).await.unwrap();
+            transaction.commit().await.unwrap();
+
+            let group = engine.get_groups(owned_identity).await.unwrap().pop().unwrap();
+            assert_eq!((group.get_name(), group.get_version()), ("Friends", 0));
+            let bob_member = engine.store.group_members().get(group.get_id().unwrap(), &bob).await.unwrap().unwrap();
+            assert!(!bob_member.is_admin());
+        }
+
+        // Alice does not build on the forged blob either
+        assert!(matches!(alice_engine.rename_group(alice_group.get_id().unwrap(), "Family").await, Err(EngineError::InvalidGroupBlob)));
+    }
+
+    #[test]
+    fn kick_signature_covers_the_member_and_the_version() {
+        let mut prng = Engine::get_default_hmac_prng().unwrap();
+        let admin = OwnedCryptographicIdentity::generate_owned_cryptographic_identity("https://server.olvid.io", &mut prng).unwrap();
+        let member = OwnedCryptographicIdentity::generate_owned_cryptographic_identity("https://server.olvid.io", &mut prng).unwrap();
+        let admin_identity = admin.get_crypto_identity().get_identity();
+        let member_identity = member.get_crypto_identity().get_identity();
+
+        let signature = admin.sign(KICK_SIGNATURE_PREFIX, &kick_statement(b"group", &member_identity, 3).unwrap(), &mut prng).unwrap();
+
+        assert!(is_kick_signed(&member, &admin_identity, b"group", 3, &signature).unwrap());
+        assert!(!is_kick_signed(&member, &admin_identity, b"group", 4, &signature).unwrap());
+        assert!(!is_kick_signed(&member, &admin_identity, b"other group", 3, &signature).unwrap());
+        assert!(!is_kick_signed(&admin, &admin_identity, b"group", 3, &signature).unwrap());
+        assert!(!is_kick_signed(&member, &member_identity, b"group", 3, &signature).unwrap());
+    }
+}
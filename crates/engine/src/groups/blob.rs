@@ -0,0 +1,187 @@
+//! Group blob stored on the server.
+//!
+//! The blob is the JSON description of the group, signed by the administrator who wrote it and encrypted with the
+//! blob key shared with the members, so the server learns neither the members nor the name of the group.
+
+use olvid_core::{crypto::{auth_encryption::{AuthEnc, AES256CTRHMACSHA256}, prng::{PRNGHmacSHA256, PRNG}}, cryptographic_identity::{CryptographicIdentity, OwnedCryptographicIdentity}, encoding::{BytesArray, Decoder, Encoder}, AES256CTRHMACSHA256Key, SymmetricKey};
+use serde::{Deserialize, Serialize};
+
+use crate::{messages::payload::base64_bytes, EngineError, Result};
+
+const BLOB_KEY_LENGTH: usize = 64;
+const BLOB_SIGNATURE_PREFIX: &[u8] = b"groupBlobSignature";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct JsonGroupBlob {
+    /// Same as the version of the blob on the server
+    #[serde(rename = "v")]
+    pub version: i64,
+    #[serde(rename = "n")]
+    pub name: String,
+    #[serde(rename = "m")]
+    pub members: Vec<JsonGroupMember>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct JsonGroupMember {
+    #[serde(rename = "id", with = "base64_bytes")]
+    pub identity: Vec<u8>,
+    #[serde(rename = "a", default)]
+    pub is_admin: bool,
+    #[serde(rename = "p", default)]
+    pub is_pending: bool,
+}
+
+/// Update of the blob by an administrator
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum GroupChange {
+    Rename(String),
+    /// New members are pending until they accept the invitation
+    AddMembers(Vec<Vec<u8>>),
+    RemoveMembers(Vec<Vec<u8>>),
+    /// A pending member accepted the invitation
+    ConfirmMember(Vec<u8>),
+    SetAdmin { identity: Vec<u8>, is_admin: bool },
+}
+
+impl JsonGroupBlob {
+    /// Returns false when the change does not modify the blob, fails when it would leave the group without administrator
+    pub fn apply(&mut self, change: &GroupChange) -> Result<bool> {
+        let previous = self.clone();
+        match change {
+            GroupChange::Rename(name) => self.name = name.clone(),
+            GroupChange::AddMembers(identities) => {
+                for identity in identities {
+                    if self.get_member(identity).is_none() {
+                        self.members.push(JsonGroupMember { identity: identity.clone(), is_admin: false, is_pending: true });
+                    }
+                }
+            }
+            GroupChange::RemoveMembers(identities) => self.members.retain(|member| !identities.contains(&member.identity)),
+            GroupChange::ConfirmMember(identity) => {
+                if let Some(member) = self.get_member_mut(identity) {
+                    member.is_pending = false;
+                }
+            }
+            GroupChange::SetAdmin { identity, is_admin } => {
+                if let Some(member) = self.get_member_mut(identity) {
+                    member.is_admin = *is_admin;
+                }
+            }
+        }
+
+        if !self.members.is_empty() && !self.members.iter().any(|member| member.is_admin) {
+            *self = previous;
+            return Err(EngineError::LastGroupAdmin);
+        }
+
+        Ok(*self != previous)
+    }
+
+    pub fn get_member(&self, identity: &[u8]) -> Option<&JsonGroupMember> {
+        self.members.iter().find(|member| member.identity == identity)
+    }
+
+    pub fn get_member_mut(&mut self, identity: &[u8]) -> Option<&mut JsonGroupMember> {
+        self.members.iter_mut().find(|member| member.identity == identity)
+    }
+
+    pub fn is_admin(&self, identity: &[u8]) -> bool {
+        self.get_member(identity).is_some_and(|member| member.is_admin)
+    }
+}
+
+pub(crate) fn generate_blob_key(prng: &mut PRNGHmacSHA256) -> Result<Vec<u8>> {
+    prng.bytes(BLOB_KEY_LENGTH).map_err(|_| EngineError::PRNG)
+}
+
+/// Signs the blob with `signer`, which must be one of its administrators, then encrypts it with the blob key
+pub(crate) fn seal(blob: &JsonGroupBlob, signer: &OwnedCryptographicIdentity, raw_key: &[u8], prng: &mut PRNGHmacSHA256) -> Result<Vec<u8>> {
+    let key = AES256CTRHMACSHA256Key::init(raw_key).map_err(|_| EngineError::Technical)?;
+
+    let json = serde_json::to_vec(blob)?;
+    let signature = signer.sign(BLOB_SIGNATURE_PREFIX, &json, prng).map_err(|_| EngineError::Technical)?;
+    let content = vec![json.encode()?, signer.get_crypto_identity().get_identity().encode()?, signature.encode()?].encode()?;
+
+    AES256CTRHMACSHA256::encrypt(&content, &key, prng).map_err(|_| EngineError::Technical)
+}
+
+/// Returns the blob and the identity that signed it, fails with `InvalidGroupBlob` unless the blob decrypts with the key
+/// and is signed by one of its administrators.
+///
+/// The administrators listed in the blob are only trusted for a new group, see `process_blob_updated`.
+pub(crate) fn open(encrypted_blob: &[u8], raw_key: &[u8]) -> Result<(JsonGroupBlob, Vec<u8>)> {
+    let key = AES256CTRHMACSHA256Key::init(raw_key).map_err(|_| EngineError::InvalidGroupBlob)?;
+    let content = AES256CTRHMACSHA256::decrypt(encrypted_blob, &key).map_err(|_| EngineError::InvalidGroupBlob)?;
+
+    let values = Vec::<BytesArray>::decode(&content).map_err(|_| EngineError::InvalidGroupBlob)?;
+    let [json, signer_identity, signature] = values.as_slice() else {
+        return Err(EngineError::InvalidGroupBlob);
+    };
+    let json: Vec<u8> = BytesArray::decode(json).map_err(|_| EngineError::InvalidGroupBlob)?;
+    let signer_identity: Vec<u8> = BytesArray::decode(signer_identity).map_err(|_| EngineError::InvalidGroupBlob)?;
+    let signature: Vec<u8> = BytesArray::decode(signature).map_err(|_| EngineError::InvalidGroupBlob)?;
+
+    let blob: JsonGroupBlob = serde_json::from_slice(&json).map_err(|_| EngineError::InvalidGroupBlob)?;
+    if !blob.is_admin(&signer_identity) {
+        return Err(EngineError::InvalidGroupBlob);
+    }
+
+    let signer = CryptographicIdentity::from_raw(&signer_identity).map_err(|_| EngineError::InvalidGroupBlob)?;
+    match signer.verify_signature(BLOB_SIGNATURE_PREFIX, &json, &signature) {
+        Ok(true) => Ok((blob, signer_identity)),
+        _ => Err(EngineError::InvalidGroupBlob),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use olvid_core::cryptographic_identity::OwnedCryptographicIdentity;
+
+    use crate::{Engine, EngineError};
+
+    use super::{generate_blob_key, open, seal, GroupChange, JsonGroupBlob, JsonGroupMember};
+
+    #[test]
+    fn blob_opens_only_when_signed_by_an_admin() {
+        let mut prng = Engine::get_default_hmac_prng().unwrap();
+        let admin = OwnedCryptographicIdentity::generate_owned_cryptographic_identity("https://server.olvid.io", &mut prng).unwrap();
+        let member = OwnedCryptographicIdentity::generate_owned_cryptographic_identity("https://server.olvid.io", &mut prng).unwrap();
+        let blob = JsonGroupBlob {
+            version: 0,
+            name: "Friends".to_owned(),
+            members: vec![
+                JsonGroupMember { identity: admin.get_crypto_identity().get_identity(), is_admin: true, is_pending: false },
+                JsonGroupMember { identity: member.get_crypto_identity().get_identity(), is_admin: false, is_pending: true },
+            ],
+        };
+        let key = generate_blob_key(&mut prng).unwrap();
+
+        let encrypted_blob = seal(&blob, &admin, &key, &mut prng).unwrap();
+        assert_eq!(open(&encrypted_blob, &key).unwrap(), (blob.clone(), admin.get_crypto_identity().get_identity()));
+        assert!(matches!(open(&encrypted_blob, &generate_blob_key(&mut prng).unwrap()), Err(EngineError::InvalidGroupBlob)));
+
+        let encrypted_by_member = seal(&blob, &member, &key, &mut prng).unwrap();
+        assert!(matches!(open(&encrypted_by_member, &key), Err(EngineError::InvalidGroupBlob)));
+    }
+
+    #[test]
+    fn changes_keep_an_admin() {
+        let mut blob = JsonGroupBlob {
+            version: 0,
+            name: "Friends".to_owned(),
+            members: vec![JsonGroupMember { identity: vec![1], is_admin: true, is_pending: false }],
+        };
+
+        assert!(blob.apply(&GroupChange::AddMembers(vec![vec![2], vec![1]])).unwrap());
+        assert_eq!(blob.get_member(&[2]), Some(&JsonGroupMember { identity: vec![2], is_admin: false, is_pending: true }));
+        assert!(blob.apply(&GroupChange::ConfirmMember(vec![2])).unwrap());
+        assert!(!blob.apply(&GroupChange::ConfirmMember(vec![2])).unwrap());
+
+        assert!(matches!(blob.apply(&GroupChange::SetAdmin { identity: vec![1], is_admin: false }), Err(EngineError::LastGroupAdmin)));
+        assert!(blob.is_admin(&[1]));
+        assert!(blob.apply(&GroupChange::SetAdmin { identity: vec![2], is_admin: true }).unwrap());
+        assert!(blob.apply(&GroupChange::RemoveMembers(vec![vec![1]])).unwrap());
+        assert_eq!(blob.members.len(), 1);
+    }
+}
@@ -0,0 +1,91 @@
+//! Publication of the details of owned identities to their contacts.
+//!
+//! Edited details are kept unpublished until the owned identity publishes them, then they are sent to every contact
+//! with an increasing version, signed so that nobody else can change the details of an identity. Contacts keep the
+//! published details apart from the trusted ones until their owned identity accepts them.
+//!
+//! The photo of an owned identity is not published: its URL only points to a file on the device, and photos can't be
+//! uploaded to the server for contacts to download yet. Contacts therefore have no photo.
+
+use olvid_core::cryptographic_identity::CryptographicIdentity;
+
+use crate::{entities::identity::{resolve_display_name, JsonIdentityDetails}, events::EngineEvent, messages::{payload::{JsonPayload, JsonPublishedDetails, JsonSignedPublishedDetails}, queue_payload}, store::StoreTransaction, Engine, EngineError, Result};
+
+const DETAILS_SIGNATURE_PREFIX: &[u8] = b"identityDetailsPublication";
+
+/// Keeps the details published by a contact, ignores the ones not signed by it and the outdated ones
+pub(crate) async fn process_published_details(transaction: &mut dyn StoreTransaction, events: &mut Vec<EngineEvent>, owned_identity: &[u8], sender_identity: &[u8], signed_published_details: JsonSignedPublishedDetails) -> Result<()> {
+    let Some(contact) = transaction.contacts().get(owned_identity, sender_identity).await? else {
+        return Ok(());
+    };
+    let is_signed = CryptographicIdentity::from_raw(sender_identity)
+        .and_then(|identity| identity.verify_signature(DETAILS_SIGNATURE_PREFIX, &signed_published_details.published_details, &signed_published_details.signature))
+        .unwrap_or(false);
+    if !is_signed {
+        return Ok(());
+    }
+    let Ok(published_details) = serde_json::from_slice::<JsonPublishedDetails>(&signed_published_details.published_details) else {
+        return Ok(());
+    };
+    if published_details.version <= contact.get_published_details_version() {
+        return Ok(());
+    }
+
+    // Nothing to accept when the contact published the details already trusted
+    let is_trusted = contact.get_identity_details()? == published_details.identity_details;
+    let published_identity_details = (!is_trusted).then_some(&published_details.identity_details);
+    transaction.contacts().set_published_details(owned_identity, sender_identity, published_details.version, published_identity_details).await?;
+
+    events.push(EngineEvent::ContactUpdated { owned_identity: owned_identity.to_vec(), contact_identity: sender_identity.to_vec() });
+
+    Ok(())
+}
+
+impl Engine {
+    /// Replaces the details and photo of an owned identity, they stay unpublished until `publish_owned_identity_details`
+    pub async fn update_owned_identity_details(&self, bytes_owned_identity: &[u8], identity_details: &JsonIdentityDetails, photo_url: Option<&str>) -> Result<()> {
+        self.get_owned_identity(bytes_owned_identity).await?;
+        self.store.owned_identities().set_unpublished_details(bytes_owned_identity, identity_details, photo_url).await?;
+        self.publish_event(EngineEvent::OwnedIdentityUpdated { owned_identity: bytes_owned_identity.to_vec() });
+
+        Ok(())
+    }
+
+    /// Publishes the unpublished details of an owned identity and sends them to all its contacts, except for the photo
+    pub async fn publish_owned_identity_details(&self, bytes_owned_identity: &[u8]) -> Result<()> {
+        let owned_identity = self.get_owned_identity(bytes_owned_identity).await?;
+        let Some((identity_details, photo_url)) = owned_identity.get_unpublished_details()? else {
+            return Ok(());
+        };
+        let private_identity = owned_identity.get_private_identity()?;
+        let contacts = self.store.contacts().get_all(bytes_owned_identity).await?;
+        let display_name = resolve_display_name(&identity_details, owned_identity.get_custom_display_name(), &self.store.settings().get_display_name_format().await?);
+
+        let mut prng = Self::get_default_hmac_prng()?;
+        let mut transaction = self.store.begin().await?;
+        let version = transaction.owned_identities().publish_details(bytes_owned_identity, &identity_details, &display_name, photo_url).await?;
+
+        let published_details = serde_json::to_vec(&JsonPublishedDetails { version, identity_details })?;
+        let signature = private_identity.sign(DETAILS_SIGNATURE_PREFIX, &published_details, &mut prng).map_err(|_| EngineError::Technical)?;
+        let payload = JsonPayload { published_details: Some(JsonSignedPublishedDetails { published_details, signature }), ..Default::default() };
+        for contact in &contacts {
+            queue_payload(&mut *transaction, &private_identity, contact.get_contact_identity(), &payload, &mut prng).await?;
+        }
+        transaction.commit().await?;
+        self.outbox_wake_up.notify_one();
+
+        self.publish_event(EngineEvent::OwnedIdentityUpdated { owned_identity: bytes_owned_identity.to_vec() });
+
+        Ok(())
+    }
+
+    /// Trusts the details published by a contact, nothing happens when there are none
+    pub async fn accept_contact_published_details(&self, bytes_owned_identity: &[u8], contact_identity: &[u8]) -> Result<()> {
+        self.store.contacts().get(bytes_owned_identity, contact_identity).await?.ok_or(EngineError::UnknownContact)?;
+        if self.store.contacts().accept_published_details(bytes_owned_identity, contact_identity).await? {
+            self.publish_event(EngineEvent::ContactUpdated { owned_identity: bytes_owned_identity.to_vec(), contact_identity: contact_identity.to_vec() });
+        }
+
+        Ok(())
+    }
+}
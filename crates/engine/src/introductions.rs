@@ -0,0 +1,225 @@
+//! Mutual introduction of two contacts.
+//!
+//! The introducer sends each introduced identity an invitation about the other one. Each side that accepts sends its
+//! acceptance to the other one, and adds it as a contact once both acceptances are known.
+//!
+//! Messages are signed by their sender, the ones that are not fail to open with `EngineError::InvalidSenderSignature`
+//! and never get here. The invitations and acceptances also carry a signature of the introduction itself, so that an
+//! introduced identity only accepts the other one for the introduction the introducer actually made.
+
+use olvid_core::{crypto::prng::PRNG, cryptographic_identity::CryptographicIdentity, encoding::Encoder};
+use crate::{contacts::check_not_blocked, entities::{contact::{Contact, ContactTrustLevel}, introduction::{Introduction, IntroductionStatus}}, events::{publish_events, EngineEvent}, messages::{payload::{JsonIntroductionMessage, JsonPayload}, queue_payload}, store::StoreTransaction, Engine, EngineError, Result};
+
+const INTRODUCTION_UID_LENGTH: usize = 32;
+const INTRODUCTION_SIGNATURE_PREFIX: &[u8] = b"mutualIntroduction";
+const ACCEPTANCE_SIGNATURE_PREFIX: &[u8] = b"mutualIntroductionAcceptance";
+
+/// What the introducer and both introduced identities sign, the same whatever the order of the introduced identities
+fn introduction_statement(introduction_uid: &[u8], introducer_identity: &[u8], first_identity: &[u8], second_identity: &[u8]) -> Result<Vec<u8>> {
+    let (first_identity, second_identity) = match first_identity <= second_identity {
+        true => (first_identity, second_identity),
+        false => (second_identity, first_identity),
+    };
+
+    Ok(vec![
+        introduction_uid.to_vec().encode()?,
+        introducer_identity.to_vec().encode()?,
+        first_identity.to_vec().encode()?,
+        second_identity.to_vec().encode()?,
+    ].encode()?)
+}
+
+fn is_signed_by(identity: &[u8], prefix: &[u8], statement: &[u8], signature: &[u8]) -> bool {
+    CryptographicIdentity::from_raw(identity)
+        .and_then(|identity| identity.verify_signature(prefix, statement, signature))
+        .unwrap_or(false)
+}
+
+fn introduction_updated(introduction: &Introduction) -> Result<EngineEvent> {
+    let introduction_id = introduction.get_id().ok_or(EngineError::Technical)?;
+    Ok(EngineEvent::IntroductionUpdated { owned_identity: introduction.get_owned_identity().to_vec(), introduction_id })
+}
+
+/// Adds the introduced identity as a contact once both sides accepted
+async fn complete(transaction: &mut dyn StoreTransaction, events: &mut Vec<EngineEvent>, introduction: &Introduction) -> Result<()> {
+    let owned_identity = introduction.get_owned_identity();
+    let contact_identity = introduction.get_contact_identity();
+
+    let display_name_format = transaction.settings().get_display_name_format().await?;
+    if transaction.contacts().insert_if_absent(Contact::new(owned_identity, contact_identity, &introduction.get_identity_details()?, &display_name_format)?).await? {
+        events.push(EngineEvent::ContactAdded { owned_identity: owned_identity.to_vec(), contact_identity: contact_identity.to_vec() });
+    }
+    if transaction.contacts().raise_trust_level(owned_identity, contact_identity, ContactTrustLevel::Introduced).await? {
+        events.push(EngineEvent::ContactUpdated { owned_identity: owned_identity.to_vec(), contact_identity: contact_identity.to_vec() });
+    }
+
+    transaction.introductions().set_status(introduction.get_id().ok_or(EngineError::Technical)?, IntroductionStatus::Completed).await?;
+    events.push(introduction_updated(introduction)?);
+    events.push(EngineEvent::ProtocolFinished { owned_identity: owned_identity.to_vec(), protocol_instance_uid: introduction.get_introduction_uid().to_vec() });
+
+    Ok(())
+}
+
+/// Handles an introduction message, the ones whose signature can't be verified are ignored
+pub(crate) async fn process_introduction_message(transaction: &mut dyn StoreTransaction, events: &mut Vec<EngineEvent>, owned_identity: &[u8], sender_identity: &[u8], message: JsonIntroductionMessage) -> Result<()> {
+    match message {
+        JsonIntroductionMessage::Invitation { introduction_uid, contact_identity, identity_details, signature } => {
+            // Only contacts introduce, and never the owned identity to itself
+            if transaction.contacts().get(owned_identity, sender_identity).await?.is_none() || contact_identity == owned_identity {
+                return Ok(());
+            }
+            let statement = introduction_statement(&introduction_uid, sender_identity, owned_identity, &contact_identity)?;
+            if !is_signed_by(sender_identity, INTRODUCTION_SIGNATURE_PREFIX, &statement, &signature) {
+                return Ok(());
+            }
+
+            let introduction = Introduction::new(owned_identity, &introduction_uid, sender_identity, &contact_identity, &identity_details)?;
+            if let Some(introduction_id) = transaction.introductions().insert_if_absent(introduction).await? {
+                events.push(EngineEvent::IntroductionUpdated { owned_identity: owned_identity.to_vec(), introduction_id });
+            }
+        }
+        JsonIntroductionMessage::Accepted { introduction_uid, identity_details, signature } => {
+            let Some(introduction) = transaction.introductions().get_by_uid(owned_identity, &introduction_uid).await?
+                .filter(|introduction| introduction.get_contact_identity() == sender_identity) else {
+                return Ok(());
+            };
+            let statement = introduction_statement(&introduction_uid, introduction.get_introducer_identity(), owned_identity, sender_identity)?;
+            if !is_signed_by(sender_identity, ACCEPTANCE_SIGNATURE_PREFIX, &statement, &signature) {
+                return Ok(());
+            }
+
+            let introduction_id = introduction.get_id().ok_or(EngineError::Technical)?;
+            match introduction.get_status()? {
+                IntroductionStatus::Pending => {
+                    transaction.introductions().set_contact_acceptance(introduction_id, &identity_details, &signature).await?;
+                    events.push(introduction_updated(&introduction)?);
+                }
+                IntroductionStatus::Accepted => {
+                    transaction.introductions().set_contact_acceptance(introduction_id, &identity_details, &signature).await?;
+                    let introduction = transaction.introductions().get_by_id(introduction_id).await?.ok_or(EngineError::Technical)?;
+                    complete(transaction, events, &introduction).await?;
+                }
+                IntroductionStatus::Rejected | IntroductionStatus::Completed => {}
+            }
+        }
+    }
+
+    Ok(())
+}
+
+impl Engine {
+    /// Introduces two contacts of an owned identity to each other, each of them gets an invitation to accept
+    pub async fn introduce_contacts(&self, bytes_owned_identity: &[u8], first_contact_identity: &[u8], second_contact_identity: &[u8]) -> Result<()> {
+        if first_contact_identity == second_contact_identity {
+            return Err(EngineError::InvalidIntroduction);
+        }
+        let owned_identity = self.get_owned_identity(bytes_owned_identity).await?.get_private_identity()?;
+        let first_contact = self.store.contacts().get(bytes_owned_identity, first_contact_identity).await?.ok_or(EngineError::UnknownContact)?;
+        let second_contact = self.store.contacts().get(bytes_owned_identity, second_contact_identity).await?.ok_or(EngineError::UnknownContact)?;
+        check_not_blocked(&*self.store, bytes_owned_identity, first_contact_identity).await?;
+        check_not_blocked(&*self.store, bytes_owned_identity, second_contact_identity).await?;
+
+        let mut prng = Self::get_default_hmac_prng()?;
+        let introduction_uid = prng.bytes(INTRODUCTION_UID_LENGTH).map_err(|_| EngineError::PRNG)?;
+        let statement = introduction_statement(&introduction_uid, bytes_owned_identity, first_contact_identity, second_contact_identity)?;
+        let signature = owned_identity.sign(INTRODUCTION_SIGNATURE_PREFIX, &statement, &mut prng).map_err(|_| EngineError::Technical)?;
+
+        let mut transaction = self.store.begin().await?;
+        for (recipient, introduced_contact) in [(&first_contact, &second_contact), (&second_contact, &first_contact)] {
+            let payload = JsonPayload {
+                introduction: Some(JsonIntroductionMessage::Invitation {
+                    introduction_uid: introduction_uid.clone(),
+                    contact_identity: introduced_contact.get_contact_identity().to_vec(),
+                    identity_details: introduced_contact.get_identity_details()?,
+                    signature: signature.clone(),
+                }),
+                ..Default::default()
+            };
+            queue_payload(&mut *transaction, &owned_identity, recipient.get_contact_identity(), &payload, &mut prng).await?;
+        }
+        transaction.commit().await?;
+        self.outbox_wake_up.notify_one();
+
+        Ok(())
+    }
+
+    /// Introductions received by an owned identity, most recent first
+    pub async fn get_introductions(&self, bytes_owned_identity: &[u8]) -> Result<Vec<Introduction>> {
+        self.store.introductions().get_all(bytes_owned_identity).await
+    }
+
+    /// Accepts a pending introduction, the introduced identity becomes a contact once it accepted as well.
+    ///
+    /// A blocked introduced identity has to be unblocked first.
+    pub async fn accept_introduction(&self, introduction_id: i64) -> Result<()> {
+        let introduction = self.get_pending_introduction(introduction_id).await?;
+        let bytes_owned_identity = introduction.get_owned_identity();
+        check_not_blocked(&*self.store, bytes_owned_identity, introduction.get_contact_identity()).await?;
+        let owned_identity = self.get_owned_identity(bytes_owned_identity).await?;
+        let private_identity = owned_identity.get_private_identity()?;
+
+        let mut prng = Self::get_default_hmac_prng()?;
+        let statement = introduction_statement(introduction.get_introduction_uid(), introduction.get_introducer_identity(), bytes_owned_identity, introduction.get_contact_identity())?;
+        let signature = private_identity.sign(ACCEPTANCE_SIGNATURE_PREFIX, &statement, &mut prng).map_err(|_| EngineError::Technical)?;
+        let payload = JsonPayload {
+            introduction: Some(JsonIntroductionMessage::Accepted {
+                introduction_uid: introduction.get_introduction_uid().to_vec(),
+                identity_details: owned_identity.get_identity_details()?,
+                signature,
+            }),
+            ..Default::default()
+        };
+        let mut events = vec![];
+        let mut transaction = self.store.begin().await?;
+        queue_payload(&mut *transaction, &private_identity, introduction.get_contact_identity(), &payload, &mut prng).await?;
+        transaction.introductions().set_status(introduction_id, IntroductionStatus::Accepted).await?;
+        match introduction.get_contact_signature() {
+            Some(_) => complete(&mut *transaction, &mut events, &introduction).await?,
+            None => events.push(introduction_updated(&introduction)?),
+        }
+        transaction.commit().await?;
+        self.outbox_wake_up.notify_one();
+        publish_events(&self.events, events);
+
+        Ok(())
+    }
+
+    /// Rejects a pending introduction, the introduced identity is not told
+    pub async fn reject_introduction(&self, introduction_id: i64) -> Result<()> {
+        let introduction = self.get_pending_introduction(introduction_id).await?;
+        self.store.introductions().set_status(introduction_id, IntroductionStatus::Rejected).await?;
+        self.publish_event(introduction_updated(&introduction)?);
+
+        Ok(())
+    }
+
+    async fn get_pending_introduction(&self, introduction_id: i64) -> Result<Introduction> {
+        self.store.introductions().get_by_id(introduction_id).await?
+            .filter(|introduction| matches!(introduction.get_status(), Ok(IntroductionStatus::Pending)))
+            .ok_or(EngineError::UnknownIntroduction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use olvid_core::cryptographic_identity::OwnedCryptographicIdentity;
+
+    use crate::Engine;
+
+    use super::{introduction_statement, is_signed_by, ACCEPTANCE_SIGNATURE_PREFIX, INTRODUCTION_SIGNATURE_PREFIX};
+
+    #[test]
+    fn both_introduced_identities_verify_the_same_statement() {
+        let mut prng = Engine::get_default_hmac_prng().unwrap();
+        let introducer = OwnedCryptographicIdentity::generate_owned_cryptographic_identity("https://server.olvid.io", &mut prng).unwrap();
+        let introducer_identity = introducer.get_crypto_identity().get_identity();
+
+        let statement = introduction_statement(&[1; 32], &introducer_identity, &[2], &[3]).unwrap();
+        assert_eq!(statement, introduction_statement(&[1; 32], &introducer_identity, &[3], &[2]).unwrap());
+
+        let signature = introducer.sign(INTRODUCTION_SIGNATURE_PREFIX, &statement, &mut prng).unwrap();
+        assert!(is_signed_by(&introducer_identity, INTRODUCTION_SIGNATURE_PREFIX, &statement, &signature));
+        assert!(!is_signed_by(&introducer_identity, ACCEPTANCE_SIGNATURE_PREFIX, &statement, &signature));
+        assert!(!is_signed_by(&introducer_identity, INTRODUCTION_SIGNATURE_PREFIX, &introduction_statement(&[1; 32], &introducer_identity, &[2], &[4]).unwrap(), &signature));
+    }
+}
@@ -0,0 +1,269 @@
+//! Owned identities managed by a keycloak server with the Olvid plugin.
+//!
+//! The server signs the details of its users as a JWS, verified with the key pinned when the identity was bound to
+//! the server, or with its JWKS when none was pinned. The signed details replace the ones of the owned identity and are
+//! published to its contacts, contacts added from the directory are certified by the server until it revokes them.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use jose_jwk::{JwkSet, Key};
+use p256::ecdsa::signature::Verifier;
+use serde::{Deserialize, Serialize};
+
+use crate::{entities::{contact::Contact, identity::JsonIdentityDetails, keycloak_server::KeycloakServer}, events::EngineEvent, messages::payload::{base64_bytes, optional_base64_bytes}, Engine, EngineError, Result};
+
+pub mod client;
+
+pub use client::KeycloakClient;
+
+/// Tokens of the keycloak user, serialized in `KeycloakServer`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct JsonKeycloakAuthState {
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+}
+
+/// Details of a keycloak user, as signed by the server or listed by a directory search
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct JsonKeycloakUserDetails {
+    pub id: String,
+    pub username: Option<String>,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    pub company: Option<String>,
+    pub position: Option<String>,
+    /// Olvid identity of the user, once it uploaded one
+    #[serde(default, with = "optional_base64_bytes")]
+    pub identity: Option<Vec<u8>>,
+    pub timestamp: Option<i64>,
+}
+
+impl JsonKeycloakUserDetails {
+    /// Details of the identity, with the JWS they were extracted from
+    pub fn to_identity_details(&self, signed_user_details: Option<String>) -> JsonIdentityDetails {
+        JsonIdentityDetails::builder()
+            .first_name(self.first_name.clone().or_else(|| self.username.clone()).unwrap_or_default())
+            .maybe_last_name(self.last_name.clone())
+            .maybe_company(self.company.clone())
+            .maybe_position(self.position.clone())
+            .maybe_signed_user_details(signed_user_details)
+            .build()
+    }
+}
+
+#[derive(Deserialize)]
+struct JsonKeycloakRevocation {
+    #[serde(with = "base64_bytes")]
+    identity: Vec<u8>,
+}
+
+#[derive(Deserialize)]
+struct JsonJwsHeader {
+    alg: String,
+    kid: Option<String>,
+}
+
+fn verify_signature(alg: &str, key: &Key, signing_input: &[u8], signature: &[u8]) -> bool {
+    match (alg, key) {
+        ("ES256", Key::Ec(ec)) => {
+            let (Ok(public_key), Ok(signature)) = (p256::PublicKey::try_from(ec), p256::ecdsa::Signature::from_slice(signature)) else {
+                return false;
+            };
+            p256::ecdsa::VerifyingKey::from(&public_key).verify(signing_input, &signature).is_ok()
+        }
+        ("RS256", Key::Rsa(rsa)) => {
+            let (Ok(public_key), Ok(signature)) = (rsa::RsaPublicKey::try_from(rsa), rsa::pkcs1v15::Signature::try_from(signature)) else {
+                return false;
+            };
+            rsa::pkcs1v15::VerifyingKey::<rsa::sha2::Sha256>::new(public_key).verify(signing_input, &signature).is_ok()
+        }
+        _ => false,
+    }
+}
+
+/// Verifies a compact JWS and returns its payload, only the pinned signature key is accepted when there is one
+pub(crate) fn verify_jws(jws: &str, jwks: &JwkSet, signature_key: Option<&Key>) -> Result<Vec<u8>> {
+    let parts: Vec<&str> = jws.split('.').collect();
+    let [encoded_header, encoded_payload, encoded_signature] = parts[..] else {
+        return Err(EngineError::InvalidKeycloakSignature);
+    };
+    let decode = |encoded: &str| URL_SAFE_NO_PAD.decode(encoded).map_err(|_| EngineError::InvalidKeycloakSignature);
+    let header: JsonJwsHeader = serde_json::from_slice(&decode(encoded_header)?).map_err(|_| EngineError::InvalidKeycloakSignature)?;
+    let signature = decode(encoded_signature)?;
+    let signing_input = format!("{}.{}", encoded_header, encoded_payload);
+
+    let candidate_keys: Vec<&Key> = match signature_key {
+        Some(signature_key) => vec![signature_key],
+        None => jwks.keys.iter().filter(|jwk| header.kid.is_none() || jwk.prm.kid == header.kid).map(|jwk| &jwk.key).collect(),
+    };
+    if !candidate_keys.into_iter().any(|key| verify_signature(&header.alg, key, signing_input.as_bytes(), &signature)) {
+        return Err(EngineError::InvalidKeycloakSignature);
+    }
+
+    decode(encoded_payload)
+}
+
+/// Verifies signed user details with the keys of a server
+fn verify_user_details(keycloak_server: &KeycloakServer, signed_user_details: &str) -> Result<JsonKeycloakUserDetails> {
+    let payload = verify_jws(signed_user_details, &keycloak_server.get_jwks()?, keycloak_server.get_signature_key()?.as_ref())?;
+    serde_json::from_slice(&payload).map_err(|_| EngineError::InvalidKeycloakSignature)
+}
+
+impl Engine {
+    pub async fn get_keycloak_server(&self, bytes_owned_identity: &[u8]) -> Result<Option<KeycloakServer>> {
+        self.store.keycloak_servers().get(bytes_owned_identity).await
+    }
+
+    /// Replaces the tokens of the keycloak user, once it authenticated again
+    pub async fn set_keycloak_auth_state(&self, bytes_owned_identity: &[u8], serialized_auth_state: &str) -> Result<()> {
+        serde_json::from_str::<JsonKeycloakAuthState>(serialized_auth_state)?;
+        self.store.keycloak_servers().get(bytes_owned_identity).await?.ok_or(EngineError::NotKeycloakManaged)?;
+        self.store.keycloak_servers().set_serialized_auth_state(bytes_owned_identity, Some(serialized_auth_state)).await
+    }
+
+    async fn get_keycloak_session(&self, bytes_owned_identity: &[u8]) -> Result<(KeycloakServer, JsonKeycloakAuthState)> {
+        let keycloak_server = self.store.keycloak_servers().get(bytes_owned_identity).await?.ok_or(EngineError::NotKeycloakManaged)?;
+        let auth_state = keycloak_server.get_serialized_auth_state().map(serde_json::from_str).transpose()?.ok_or(EngineError::KeycloakAuthenticationRequired)?;
+
+        Ok((keycloak_server, auth_state))
+    }
+
+    /// Keeps the tokens refreshed during a request, even when the request failed
+    async fn save_keycloak_auth_state(&self, keycloak_server: &KeycloakServer, auth_state: &JsonKeycloakAuthState) -> Result<()> {
+        let serialized_auth_state = serde_json::to_string(auth_state)?;
+        if keycloak_server.get_serialized_auth_state() != Some(serialized_auth_state.as_str()) {
+            self.store.keycloak_servers().set_serialized_auth_state(keycloak_server.get_owned_identity(), Some(&serialized_auth_state)).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Uploads the identity to the keycloak server, binding it to the authenticated user
+    pub async fn upload_keycloak_identity(&self, bytes_owned_identity: &[u8]) -> Result<()> {
+        let (keycloak_server, mut auth_state) = self.get_keycloak_session(bytes_owned_identity).await?;
+        let result = self.keycloak_client.put_key(&keycloak_server, &mut auth_state, bytes_owned_identity).await;
+        self.save_keycloak_auth_state(&keycloak_server, &auth_state).await?;
+
+        result
+    }
+
+    /// Pulls the signed details of the user, publishing them to the contacts when they changed, and processes the
+    /// revocations since the previous synchronization
+    pub async fn synchronize_keycloak(&self, bytes_owned_identity: &[u8]) -> Result<()> {
+        let owned_identity = self.get_owned_identity(bytes_owned_identity).await?;
+        let (keycloak_server, mut auth_state) = self.get_keycloak_session(bytes_owned_identity).await?;
+        let result = self.keycloak_client.me(&keycloak_server, &mut auth_state).await;
+        self.save_keycloak_auth_state(&keycloak_server, &auth_state).await?;
+        let me = result?;
+
+        let user_details = verify_user_details(&keycloak_server, &me.signature)?;
+        if user_details.identity.as_ref().is_some_and(|identity| identity != bytes_owned_identity) {
+            return Err(EngineError::KeycloakIdentityMismatch);
+        }
+        self.store.keycloak_servers().set_user_state(bytes_owned_identity, &user_details.id, me.transfer_restricted).await?;
+
+        // The signature changes with its timestamp, only new details are published
+        let identity_details = owned_identity.get_identity_details()?;
+        if user_details.to_identity_details(identity_details.get_signed_user_details().map(str::to_owned)) != identity_details {
+            let signed_identity_details = user_details.to_identity_details(Some(me.signature.clone()));
+            self.store.owned_identities().set_unpublished_details(bytes_owned_identity, &signed_identity_details, owned_identity.get_photo_url()).await?;
+            self.publish_owned_identity_details(bytes_owned_identity).await?;
+        }
+
+        for signed_revocation in &me.signed_revocations {
+            let Ok(payload) = verify_jws(signed_revocation, &keycloak_server.get_jwks()?, keycloak_server.get_signature_key()?.as_ref()) else {
+                continue;
+            };
+            let Ok(revocation) = serde_json::from_slice::<JsonKeycloakRevocation>(&payload) else {
+                continue;
+            };
+
+            if revocation.identity == bytes_owned_identity {
+                return self.unbind_keycloak(bytes_owned_identity).await;
+            }
+            let is_certified = self.store.contacts().get(bytes_owned_identity, &revocation.identity).await?.is_some_and(|contact| contact.is_keycloak_managed());
+            if is_certified {
+                self.store.contacts().set_keycloak_managed(bytes_owned_identity, &revocation.identity, None).await?;
+                self.publish_event(EngineEvent::ContactUpdated { owned_identity: bytes_owned_identity.to_vec(), contact_identity: revocation.identity });
+            }
+        }
+        self.store.keycloak_servers().set_latest_revocation_list_timestamp(bytes_owned_identity, me.current_server_timestamp).await?;
+
+        Ok(())
+    }
+
+    /// The identity stays, without its keycloak server
+    async fn unbind_keycloak(&self, bytes_owned_identity: &[u8]) -> Result<()> {
+        let mut transaction = self.store.begin().await?;
+        transaction.keycloak_servers().delete(bytes_owned_identity).await?;
+        transaction.owned_identities().set_keycloak_managed(bytes_owned_identity, false).await?;
+        transaction.commit().await?;
+
+        self.publish_event(EngineEvent::OwnedIdentityUpdated { owned_identity: bytes_owned_identity.to_vec() });
+
+        Ok(())
+    }
+
+    pub async fn search_keycloak_directory(&self, bytes_owned_identity: &[u8], filter: &str) -> Result<Vec<JsonKeycloakUserDetails>> {
+        let (keycloak_server, mut auth_state) = self.get_keycloak_session(bytes_owned_identity).await?;
+        let result = self.keycloak_client.search(&keycloak_server, &mut auth_state, filter).await;
+        self.save_keycloak_auth_state(&keycloak_server, &auth_state).await?;
+
+        result
+    }
+
+    /// Adds a user of the directory as a contact certified by the keycloak server
+    pub async fn add_keycloak_contact(&self, bytes_owned_identity: &[u8], user_id: &str) -> Result<Contact> {
+        let (keycloak_server, mut auth_state) = self.get_keycloak_session(bytes_owned_identity).await?;
+        let result = self.keycloak_client.get_key(&keycloak_server, &mut auth_state, user_id).await;
+        self.save_keycloak_auth_state(&keycloak_server, &auth_state).await?;
+        let signed_user_details = result?;
+
+        let user_details = verify_user_details(&keycloak_server, &signed_user_details)?;
+        let contact_identity = user_details.identity.clone().ok_or(EngineError::KeycloakUserWithoutIdentity)?;
+        let identity_details = user_details.to_identity_details(Some(signed_user_details));
+        self.add_contact(bytes_owned_identity, &contact_identity, &identity_details).await?;
+        self.store.contacts().set_keycloak_managed(bytes_owned_identity, &contact_identity, Some(&identity_details)).await?;
+        self.publish_event(EngineEvent::ContactUpdated { owned_identity: bytes_owned_identity.to_vec(), contact_identity: contact_identity.clone() });
+
+        self.store.contacts().get(bytes_owned_identity, &contact_identity).await?.ok_or(EngineError::Technical)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    use jose_jwk::{Ec, Jwk, JwkSet, Key, Parameters};
+    use p256::ecdsa::{signature::Signer, Signature, SigningKey};
+
+    use crate::EngineError;
+
+    use super::verify_jws;
+
+    fn sign(signing_key: &SigningKey, payload: &str) -> String {
+        let signing_input = format!("{}.{}", URL_SAFE_NO_PAD.encode(r#"{"alg":"ES256","kid":"test"}"#), URL_SAFE_NO_PAD.encode(payload));
+        let signature: Signature = signing_key.sign(signing_input.as_bytes());
+        format!("{}.{}", signing_input, URL_SAFE_NO_PAD.encode(signature.to_bytes()))
+    }
+
+    fn public_key(signing_key: &SigningKey) -> Key {
+        Key::Ec(Ec::from(p256::PublicKey::from(signing_key.verifying_key())))
+    }
+
+    #[test]
+    fn jws_is_verified_with_the_pinned_key() {
+        let signing_key = SigningKey::from_slice(&[7; 32]).unwrap();
+        let other_key = SigningKey::from_slice(&[8; 32]).unwrap();
+        let jwks = JwkSet { keys: vec![Jwk { key: public_key(&signing_key), prm: Parameters { kid: Some("test".to_owned()), ..Default::default() } }] };
+        let jws = sign(&signing_key, r#"{"id":"alice"}"#);
+
+        assert_eq!(verify_jws(&jws, &jwks, None).unwrap(), br#"{"id":"alice"}"#);
+        assert_eq!(verify_jws(&jws, &JwkSet::default(), Some(&public_key(&signing_key))).unwrap(), br#"{"id":"alice"}"#);
+        assert!(matches!(verify_jws(&jws, &jwks, Some(&public_key(&other_key))), Err(EngineError::InvalidKeycloakSignature)));
+
+        let forged = sign(&other_key, r#"{"id":"alice"}"#);
+        assert!(matches!(verify_jws(&forged, &jwks, None), Err(EngineError::InvalidKeycloakSignature)));
+        let tampered = format!("{}.{}.{}", jws.split('.').next().unwrap(), URL_SAFE_NO_PAD.encode(r#"{"id":"eve"}"#), jws.split('.').nth(2).unwrap());
+        assert!(matches!(verify_jws(&tampered, &jwks, None), Err(EngineError::InvalidKeycloakSignature)));
+    }
+}
@@ -0,0 +1,175 @@
+use std::time::Duration;
+
+use reqwest::{header, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use url::Url;
+
+use crate::{entities::keycloak_server::KeycloakServer, messages::payload::base64_bytes, EngineError, Result};
+
+use super::{JsonKeycloakAuthState, JsonKeycloakUserDetails};
+
+const KEYCLOAK_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Serialize)]
+struct JsonMeRequest {
+    timestamp: i64,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct JsonMeResponse {
+    /// JWS of the `JsonKeycloakUserDetails` of the authenticated user
+    pub signature: String,
+    #[serde(default)]
+    pub transfer_restricted: bool,
+    /// JWS of each `JsonKeycloakRevocation` since the requested timestamp
+    #[serde(default)]
+    pub signed_revocations: Vec<String>,
+    pub current_server_timestamp: i64,
+}
+
+#[derive(Serialize)]
+struct JsonPutKeyRequest {
+    #[serde(with = "base64_bytes")]
+    identity: Vec<u8>,
+}
+
+#[derive(Serialize)]
+struct JsonSearchRequest<'a> {
+    filter: &'a str,
+}
+
+#[derive(Deserialize)]
+struct JsonSearchResponse {
+    #[serde(default)]
+    results: Vec<JsonKeycloakUserDetails>,
+}
+
+#[derive(Serialize)]
+struct JsonGetKeyRequest<'a> {
+    user: &'a str,
+}
+
+#[derive(Deserialize)]
+struct JsonGetKeyResponse {
+    signature: String,
+}
+
+#[derive(Deserialize)]
+struct JsonOpenIdConfiguration {
+    token_endpoint: String,
+}
+
+#[derive(Deserialize)]
+struct JsonTokenResponse {
+    access_token: String,
+    refresh_token: Option<String>,
+}
+
+/// Client of the `olvid-rest` API of the keycloak plugin, authenticated with the tokens of the user.
+///
+/// A rejected access token is refreshed once with the refresh token, through the token endpoint of the OIDC discovery.
+/// The caller persists the authentication state, it changes when the token is refreshed.
+pub struct KeycloakClient {
+    http_client: reqwest::Client,
+}
+
+impl KeycloakClient {
+    pub fn new() -> Result<Self> {
+        let http_client = reqwest::Client::builder()
+            .timeout(KEYCLOAK_REQUEST_TIMEOUT)
+            .build()?;
+
+        Ok(Self { http_client })
+    }
+
+    /// The URL of a realm may come without its trailing slash, everything is relative to it
+    fn server_url(keycloak_server: &KeycloakServer) -> Result<Url> {
+        let server_url = keycloak_server.get_server_url();
+        match server_url.ends_with('/') {
+            true => Ok(Url::parse(server_url)?),
+            false => Ok(Url::parse(&format!("{}/", server_url))?),
+        }
+    }
+
+    async fn post<T: DeserializeOwned>(&self, keycloak_server: &KeycloakServer, auth_state: &mut JsonKeycloakAuthState, method: &str, body: &impl Serialize) -> Result<T> {
+        let url = Self::server_url(keycloak_server)?.join("olvid-rest/")?.join(method)?;
+        let body = serde_json::to_vec(body)?;
+
+        let mut refreshed = false;
+        loop {
+            let response = self.http_client
+                .post(url.clone())
+                .bearer_auth(&auth_state.access_token)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(body.clone())
+                .send()
+                .await?;
+
+            if response.status() == StatusCode::UNAUTHORIZED && !refreshed {
+                self.refresh_access_token(keycloak_server, auth_state).await?;
+                refreshed = true;
+                continue;
+            }
+            if response.status() == StatusCode::UNAUTHORIZED {
+                return Err(EngineError::KeycloakAuthenticationRequired);
+            }
+
+            let response_bytes = response.error_for_status()?.bytes().await?;
+            return serde_json::from_slice(&response_bytes).map_err(|_| EngineError::MalformedServerResponse);
+        }
+    }
+
+    async fn refresh_access_token(&self, keycloak_server: &KeycloakServer, auth_state: &mut JsonKeycloakAuthState) -> Result<()> {
+        let refresh_token = auth_state.refresh_token.clone().ok_or(EngineError::KeycloakAuthenticationRequired)?;
+        let discovery_url = Self::server_url(keycloak_server)?.join(".well-known/openid-configuration")?;
+        let discovery_bytes = self.http_client.get(discovery_url).send().await?.error_for_status()?.bytes().await?;
+        let openid_configuration: JsonOpenIdConfiguration = serde_json::from_slice(&discovery_bytes).map_err(|_| EngineError::MalformedServerResponse)?;
+
+        let mut form = vec![("grant_type", "refresh_token"), ("refresh_token", &refresh_token), ("client_id", keycloak_server.get_client_id())];
+        if let Some(client_secret) = keycloak_server.get_client_secret() {
+            form.push(("client_secret", client_secret));
+        }
+        let response = self.http_client.post(Url::parse(&openid_configuration.token_endpoint)?).form(&form).send().await?;
+        // An expired refresh token is answered with a bad request
+        if response.status().is_client_error() {
+            return Err(EngineError::KeycloakAuthenticationRequired);
+        }
+        let token_bytes = response.error_for_status()?.bytes().await?;
+        let token_response: JsonTokenResponse = serde_json::from_slice(&token_bytes).map_err(|_| EngineError::MalformedServerResponse)?;
+
+        auth_state.access_token = token_response.access_token;
+        if token_response.refresh_token.is_some() {
+            auth_state.refresh_token = token_response.refresh_token;
+        }
+
+        Ok(())
+    }
+
+    /// Signed details of the user and the revocations since `latest_revocation_list_timestamp`
+    pub async fn me(&self, keycloak_server: &KeycloakServer, auth_state: &mut JsonKeycloakAuthState) -> Result<JsonMeResponse> {
+        let request = JsonMeRequest { timestamp: keycloak_server.get_latest_revocation_list_timestamp() };
+        self.post(keycloak_server, auth_state, "me", &request).await
+    }
+
+    /// Binds the identity to the keycloak user
+    pub async fn put_key(&self, keycloak_server: &KeycloakServer, auth_state: &mut JsonKeycloakAuthState, identity: &[u8]) -> Result<()> {
+        let _: serde_json::Value = self.post(keycloak_server, auth_state, "putKey", &JsonPutKeyRequest { identity: identity.to_vec() }).await?;
+
+        Ok(())
+    }
+
+    /// Unsigned details of the users matching the filter
+    pub async fn search(&self, keycloak_server: &KeycloakServer, auth_state: &mut JsonKeycloakAuthState, filter: &str) -> Result<Vec<JsonKeycloakUserDetails>> {
+        let response: JsonSearchResponse = self.post(keycloak_server, auth_state, "search", &JsonSearchRequest { filter }).await?;
+
+        Ok(response.results)
+    }
+
+    /// JWS of the details of a user, with its identity
+    pub async fn get_key(&self, keycloak_server: &KeycloakServer, auth_state: &mut JsonKeycloakAuthState, user_id: &str) -> Result<String> {
+        let response: JsonGetKeyResponse = self.post(keycloak_server, auth_state, "getKey", &JsonGetKeyRequest { user: user_id }).await?;
+
+        Ok(response.signature)
+    }
+}
@@ -1,11 +1,45 @@
+use std::{collections::{HashMap, HashSet}, path::PathBuf, sync::{Arc, Mutex}, time::{SystemTime, UNIX_EPOCH}};
+
 use olvid_core::{crypto::prng::{PRNGHmacSHA256, PRNG}, cryptographic_identity::OwnedCryptographicIdentity};
-use entities::identity::{JsonIdentityDetails, ObvIdentity, OwnedIdentity, API_KEY_STATUS_UNKNOWN};
+use api_keys::ApiKeyMonitor;
+use configuration::EngineConfiguration;
+use entities::{identity::{JsonIdentityDetails, ObvIdentity, OwnedIdentity, API_KEY_STATUS_UNKNOWN}, inbox_message::InboxMessage, keycloak_server::KeycloakServer, message::MessageStatus, outbox_message::{DeliveryState, OutboxMessage}};
+use events::{EngineEvent, EVENT_CHANNEL_CAPACITY};
+use messages::MessageExpirer;
 use jose_jwk::{JwkSet, Key};
-use sqlx::{migrate::MigrateDatabase, Sqlite, SqlitePool};
+use keycloak::{verify_jws, JsonKeycloakUserDetails, KeycloakClient};
+use olvid_core::encoding::DecodingParsingError;
+use server::{DownloadedMessage, OutboundServerMessage, ServerClient, ServerSession, UploadedMessage};
+use profile_transfers::ProfileTransfer;
+use store::{SqliteStore, Store};
+use network::{AttachmentTransfer, InboxFetcher, OutboxSender, PushConnectionStatus, PushListener, PushListenerHandle, RetryPolicy};
 use thiserror::Error;
+use tokio::{sync::{broadcast, watch, Notify}, task::JoinHandle};
 use uuid::Uuid;
 
+mod api_keys;
+mod attachments;
+mod backups;
+mod channel;
+pub mod configuration;
+mod contacts;
+mod database_keys;
+mod display_names;
 pub mod entities;
+pub mod events;
+mod groups;
+mod identity_details;
+mod introductions;
+pub mod keycloak;
+mod messages;
+pub mod network;
+pub mod profile_transfers;
+mod profiles;
+mod receipts;
+pub mod search;
+pub mod server;
+pub mod store;
+mod trust_establishments;
 
 #[derive(Debug, Error)]
 pub enum EngineError {
@@ -15,60 +49,265 @@ pub enum EngineError {
     Persistence(#[from] sqlx::Error),
     #[error("JSON encoding error")]
     JSONEncoding(#[from] serde_json::Error),
+    #[error("Olvid encoding error")]
+    Encoding(#[from] DecodingParsingError),
+    #[error("Invalid server URL")]
+    InvalidServerUrl(#[from] url::ParseError),
+    #[error("Network error")]
+    Network(#[from] reqwest::Error),
+    #[error("Server session is invalid")]
+    InvalidSession,
+    #[error("Identity is not active on the server")]
+    IdentityNotActive,
+    #[error("Deleted from server")]
+    DeletedFromServer,
+    #[error("Payload too large")]
+    PayloadTooLarge,
+    #[error("Group uid already used")]
+    GroupUidAlreadyUsed,
+    #[error("Group blob was updated by someone else")]
+    GroupBlobOutdated,
+    #[error("The server does not know this API key")]
+    InvalidApiKey,
+    #[error("No API key was given")]
+    NoApiKey,
+    #[error("Server general error")]
+    ServerGeneralError,
+    #[error("Unknown server status {0}")]
+    UnknownServerStatus(u8),
+    #[error("Malformed server response")]
+    MalformedServerResponse,
+    #[error("Push connection error")]
+    PushConnection,
+    #[error("Unknown owned identity")]
+    UnknownOwnedIdentity,
+    #[error("File error")]
+    Io(#[from] std::io::Error),
+    #[error("Attachment chunk could not be decrypted")]
+    AttachmentDecryption,
+    #[error("Unknown attachment")]
+    UnknownAttachment,
+    #[error("Attachment size or chunk length is invalid")]
+    InvalidAttachmentMetadata,
+    #[error("Message could not be decrypted")]
+    ChannelDecryption,
+    #[error("Message is not signed by its sender")]
+    InvalidSenderSignature,
+    #[error("Unknown contact")]
+    UnknownContact,
+    #[error("The identity is blocked, it must be unblocked first")]
+    ContactBlocked,
+    #[error("Unknown message")]
+    UnknownMessage,
+    #[error("Only the sender of the message, or a group administrator for deletions, can do this")]
+    NotMessageSender,
+    #[error("A mention must cover a part of the message body")]
+    InvalidMention,
+    #[error("Unknown discussion")]
+    UnknownDiscussion,
+    #[error("Unknown group")]
+    UnknownGroup,
+    #[error("Group blob could not be decrypted or is not signed by an administrator")]
+    InvalidGroupBlob,
+    #[error("Only group administrators can do this")]
+    NotGroupAdmin,
+    #[error("The last administrator of a group with other members can't leave it")]
+    LastGroupAdmin,
+    #[error("Unknown introduction")]
+    UnknownIntroduction,
+    #[error("A contact can't be introduced to itself")]
+    InvalidIntroduction,
+    #[error("No backup key was generated")]
+    NoBackupKey,
+    #[error("Invalid backup key")]
+    InvalidBackupKey,
+    #[error("Invalid or unsupported backup")]
+    InvalidBackup,
+    #[error("Backups can only be restored without any owned identity")]
+    BackupRestoreNotEmpty,
+    #[error("Unknown profile transfer")]
+    UnknownProfileTransfer,
+    #[error("The profile transfer is not at this step")]
+    UnexpectedProfileTransferStep,
+    #[error("Identity is not managed by a keycloak server")]
+    NotKeycloakManaged,
+    #[error("Keycloak server signature is invalid")]
+    InvalidKeycloakSignature,
+    #[error("The keycloak user must authenticate again")]
+    KeycloakAuthenticationRequired,
+    #[error("Another identity is bound to the keycloak user")]
+    KeycloakIdentityMismatch,
+    #[error("The keycloak user has no identity")]
+    KeycloakUserWithoutIdentity,
+    #[error("The keycloak server forbids transferring this profile")]
+    ProfileTransferRestricted,
+    #[error("Profile is deactivated")]
+    ProfileDeactivated,
+    #[error("At least one profile must stay visible")]
+    LastVisibleProfile,
+    #[error("No hidden profile has this password")]
+    InvalidUnlockPassword,
+    #[error("Unknown trust establishment")]
+    UnknownTrustEstablishment,
+    #[error("The code does not match the one displayed by the contact")]
+    InvalidSasCode,
+    #[error("The database is encrypted, a passphrase or key file is needed")]
+    DatabaseLocked,
+    #[error("Invalid database passphrase or key file")]
+    InvalidDatabaseSecret,
+    #[error("The database is not encrypted")]
+    DatabaseNotEncrypted,
+    #[error("A column of the database could not be decrypted")]
+    DatabaseDecryption,
     #[error("Technical error")]
     Technical
 }
 
 pub type Result<T, E = EngineError> = std::result::Result<T, E>;
 
-const DB_URL: &str = "sqlite://olvid_engine.db";
+pub(crate) const DB_URL: &str = "sqlite://olvid_engine.db";
+
+/// Milliseconds since the Unix epoch, the unit of every timestamp stored by the engine
+pub(crate) fn current_timestamp() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|duration| duration.as_millis() as i64).unwrap_or_default()
+}
 
 pub struct Engine {
     server_url: String,
     api_key: Option<Uuid>,
     prng: Box<dyn PRNG + Send>,
-    db: SqlitePool
+    store: Arc<dyn Store>,
+    server_client: Arc<ServerClient>,
+    keycloak_client: KeycloakClient,
+    events: broadcast::Sender<EngineEvent>,
+    outbox_wake_up: Arc<Notify>,
+    outbox_sender_task: JoinHandle<()>,
+    attachment_wake_up: Arc<Notify>,
+    attachment_transfer_task: JoinHandle<()>,
+    expiration_wake_up: Arc<Notify>,
+    message_expirer_task: JoinHandle<()>,
+    api_key_monitor_task: JoinHandle<()>,
+    data_directory: PathBuf,
+    attachment_chunk_length: i64,
+    inbox_fetcher: InboxFetcher,
+    push_listeners: HashMap<Vec<u8>, PushListenerHandle>,
+    /// Hidden owned identities listed until `lock_hidden_profiles`
+    unlocked_profiles: HashSet<Vec<u8>>,
+    /// In progress on this device, by session number
+    profile_transfers: HashMap<i64, ProfileTransfer>,
 }
 
 impl Engine {
     pub async fn init(server_url: &str, api_key: Option<Uuid>) -> Result<Self, EngineError> {
+        Self::init_with_configuration(EngineConfiguration::builder().server_url(server_url.to_owned()).maybe_api_key(api_key).build()).await
+    }
+
+    /// Same as `init` with a custom database, `sqlite::memory:` gives an engine that leaves nothing on disk
+    pub async fn init_with_database_url(server_url: &str, api_key: Option<Uuid>, database_url: &str) -> Result<Self, EngineError> {
+        Self::init_with_configuration(
+            EngineConfiguration::builder()
+                .server_url(server_url.to_owned())
+                .maybe_api_key(api_key)
+                .database_url(database_url.to_owned())
+                .build()
+        ).await
+    }
+
+    pub async fn init_with_configuration(configuration: EngineConfiguration) -> Result<Self, EngineError> {
+        let store = Arc::new(SqliteStore::connect(&configuration.database_url, configuration.database_secret.as_ref()).await?);
+        Self::init_with_store(configuration, store).await
+    }
+
+    /// Same as `init_with_configuration` with another store, the database URL and secret of the configuration are then ignored
+    pub async fn init_with_store(configuration: EngineConfiguration, store: Arc<dyn Store>) -> Result<Self, EngineError> {
+        let server_client = Arc::new(ServerClient::new(&configuration.server_url)?);
+        let (events, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+        let outbox_wake_up = Arc::new(Notify::new());
+        let attachment_wake_up = Arc::new(Notify::new());
+        let expiration_wake_up = Arc::new(Notify::new());
+
+        let outbox_sender_task = OutboxSender {
+            store: Arc::clone(&store),
+            server_client: Arc::clone(&server_client),
+            events: events.clone(),
+            wake_up: Arc::clone(&outbox_wake_up),
+            attachment_wake_up: Arc::clone(&attachment_wake_up),
+            expiration_wake_up: Arc::clone(&expiration_wake_up),
+            retry_policy: configuration.retry_policy,
+            prng: Mutex::new(Self::get_default_prng()?),
+        }.spawn();
+
+        let attachment_transfer_task = AttachmentTransfer {
+            store: Arc::clone(&store),
+            server_client: Arc::clone(&server_client),
+            events: events.clone(),
+            wake_up: Arc::clone(&attachment_wake_up),
+            retry_policy: configuration.retry_policy,
+        }.spawn()?;
+
+        let inbox_fetcher = InboxFetcher {
+            store: Arc::clone(&store),
+            server_client: Arc::clone(&server_client),
+            events: events.clone(),
+            outbox_wake_up: Arc::clone(&outbox_wake_up),
+            expiration_wake_up: Arc::clone(&expiration_wake_up),
+        };
+
+        let message_expirer_task = MessageExpirer {
+            store: Arc::clone(&store),
+            events: events.clone(),
+            wake_up: Arc::clone(&expiration_wake_up),
+        }.spawn();
+
+        let api_key_monitor_task = ApiKeyMonitor {
+            store: Arc::clone(&store),
+            events: events.clone(),
+            new_sessions: server_client.subscribe_to_new_sessions(),
+        }.spawn();
+
         Ok(
             Self { 
-                server_url: server_url.to_owned(), 
-                api_key,
+                server_url: configuration.server_url, 
+                api_key: configuration.api_key,
                 prng: Self::get_default_prng()?,
-                db: Self::init_database().await?, 
+                store,
+                server_client,
+                keycloak_client: KeycloakClient::new()?,
+                events,
+                outbox_wake_up,
+                outbox_sender_task,
+                attachment_wake_up,
+                attachment_transfer_task,
+                expiration_wake_up,
+                message_expirer_task,
+                api_key_monitor_task,
+                data_directory: configuration.data_directory,
+                attachment_chunk_length: configuration.attachment_chunk_length,
+                inbox_fetcher,
+                push_listeners: HashMap::new(),
+                unlocked_profiles: HashSet::new(),
+                profile_transfers: HashMap::new(),
             }
         )
     }
 
-    async fn init_database() -> Result<SqlitePool> {
-        if !Sqlite::database_exists(DB_URL).await.unwrap_or(false) {
-            println!("Creating database {}", DB_URL);
-            match Sqlite::create_database(DB_URL).await {
-                Ok(_) => println!("Create db success"),
-                Err(error) => panic!("error: {}", error),
-            }
-        } else {
-            println!("Database already exists");
-        }
-
-        let db = SqlitePool::connect(DB_URL).await.unwrap();
-        // let crate_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
-        // let migrations = std::path::Path::new(&crate_dir).join("../migrations");
-
-        sqlx::migrate!().run(&db).await.map_err(|err| EngineError::Persistence(sqlx::Error::Migrate(Box::new(err))))?;
+    pub fn subscribe_to_events(&self) -> broadcast::Receiver<EngineEvent> {
+        self.events.subscribe()
+    }
 
-        Ok(db)
+    fn publish_event(&self, event: EngineEvent) {
+        events::publish_event(&self.events, event);
     }
 
     pub async fn generate_simple_identity(&mut self, identity_details: JsonIdentityDetails) -> Result<ObvIdentity> {
         let owned_identity = OwnedCryptographicIdentity::generate_owned_cryptographic_identity(&self.server_url, &mut *self.prng).unwrap();
-        let obv_identity = ObvIdentity::new(owned_identity.get_crypto_identity(), identity_details, false, true);
+        let mut obv_identity = ObvIdentity::new(owned_identity.get_crypto_identity(), identity_details, false, true);
 
         // Store in db
-        let owned_identity = OwnedIdentity::new(&obv_identity, API_KEY_STATUS_UNKNOWN)?;
-        OwnedIdentity::insert(&self.db, owned_identity).await?;
+        let owned_identity = OwnedIdentity::new(&obv_identity, &owned_identity, API_KEY_STATUS_UNKNOWN, &self.store.settings().get_display_name_format().await?)?;
+        obv_identity.display_name = owned_identity.get_display_name().to_owned();
+        self.store.owned_identities().insert(owned_identity).await?;
+        self.publish_event(EngineEvent::OwnedIdentityCreated { owned_identity: obv_identity.identity.get_identity() });
 
         Ok(obv_identity)
     }
@@ -80,36 +319,216 @@ impl Engine {
         custom_diplay_name: &str, 
         unlock_password: &[u8],
         unlock_salt: &[u8],
-        keyclock_server: Option<String>,
+        keycloak_server: Option<String>,
         client_id: &str,
         client_secret: &str,
         jwks: JwkSet,
         signature_key: Key,
-        serailized_keycloak_state: Option<String>,
+        serialized_keycloak_state: Option<String>,
         keycloak_transfer_restricted: bool
     ) -> Result<ObvIdentity> {
-        // Todo: handle keycloak
+        // The details of a keycloak managed identity are the ones signed by the server
+        let keycloak_server = keycloak_server.filter(|server_url| !server_url.is_empty());
+        let identity_details = match keycloak_server {
+            Some(_) => {
+                let signed_user_details = identity_details.get_signed_user_details().ok_or(EngineError::InvalidKeycloakSignature)?;
+                let payload = verify_jws(signed_user_details, &jwks, Some(&signature_key))?;
+                let user_details: JsonKeycloakUserDetails = serde_json::from_slice(&payload).map_err(|_| EngineError::InvalidKeycloakSignature)?;
+                user_details.to_identity_details(Some(signed_user_details.to_owned()))
+            }
+            None => identity_details,
+        };
 
         let owned_identity = OwnedCryptographicIdentity::generate_owned_cryptographic_identity(&self.server_url, &mut *self.prng).unwrap();
-        let obv_identity = ObvIdentity::new(owned_identity.get_crypto_identity(), identity_details, false, true);
+        let mut obv_identity = ObvIdentity::new(owned_identity.get_crypto_identity(), identity_details, keycloak_server.is_some(), true);
+        obv_identity.custom_display_name = Some(custom_diplay_name.to_owned()).filter(|custom_display_name| !custom_display_name.is_empty());
 
         // Store in db
-        let owned_identity = OwnedIdentity::new(&obv_identity, API_KEY_STATUS_UNKNOWN)?;
-        OwnedIdentity::insert(&self.db, owned_identity).await?;
+        let bytes_owned_identity = obv_identity.identity.get_identity();
+        let owned_identity = OwnedIdentity::new(&obv_identity, &owned_identity, API_KEY_STATUS_UNKNOWN, &self.store.settings().get_display_name_format().await?)?;
+        obv_identity.display_name = owned_identity.get_display_name().to_owned();
+        let mut transaction = self.store.begin().await?;
+        transaction.owned_identities().insert(owned_identity).await?;
+        if let Some(server_url) = keycloak_server {
+            let keycloak_server = KeycloakServer::builder()
+                .owned_identity(&bytes_owned_identity)
+                .server_url(&server_url)
+                .client_id(client_id)
+                .maybe_client_secret(Some(client_secret).filter(|client_secret| !client_secret.is_empty()))
+                .jwks(&jwks)
+                .signature_key(&signature_key)
+                .maybe_serialized_auth_state(serialized_keycloak_state.as_deref())
+                .transfer_restricted(keycloak_transfer_restricted)
+                .build()?;
+            transaction.keycloak_servers().upsert(keycloak_server).await?;
+        }
+        transaction.commit().await?;
+        self.publish_event(EngineEvent::OwnedIdentityCreated { owned_identity: obv_identity.identity.get_identity() });
 
         Ok(obv_identity)
     }
 
+    /// Hidden identities are only listed once unlocked, see `unlock_hidden_profiles`
     pub async fn get_all_owned_identities(&self) -> Result<Vec<ObvIdentity>> {
-        let owned_identities = OwnedIdentity::get_all(&self.db).await?;
+        let owned_identities = self.store.owned_identities().get_all().await?.into_iter()
+            .filter(|owned_identity| self.is_visible(owned_identity));
         let obv_identites: Vec<ObvIdentity> = owned_identities.into_iter().map(|owned_identity| OwnedIdentity::try_into(owned_identity)).collect::<Result<Vec<ObvIdentity>>>()?;
         Ok(obv_identites)
     }
 
+    pub fn get_server_client(&self) -> &ServerClient {
+        &self.server_client
+    }
+
+    async fn get_owned_identity(&self, bytes_owned_identity: &[u8]) -> Result<OwnedIdentity> {
+        self.store.owned_identities().get_by_identity(bytes_owned_identity).await?.ok_or(EngineError::UnknownOwnedIdentity)
+    }
+
+    pub async fn authenticate_owned_identity(&mut self, bytes_owned_identity: &[u8]) -> Result<ServerSession> {
+        let owned_identity = self.get_owned_identity(bytes_owned_identity).await?;
+        let session = self.server_client.get_session(&owned_identity.get_private_identity()?, &mut *self.prng).await?;
+        self.save_session_api_key_status(bytes_owned_identity, &session).await?;
+
+        Ok(session)
+    }
+
+    /// Uploads a message right away, without the retries of `queue_message` nor their upload nonce
+    pub async fn upload_message(&self, message: &OutboundServerMessage) -> Result<UploadedMessage> {
+        self.server_client.upload_message_and_get_uids(message, None).await
+    }
+
+    /// Lists the messages waiting on the server for the current device of an owned identity
+    pub async fn download_messages(&mut self, bytes_owned_identity: &[u8]) -> Result<Vec<DownloadedMessage>> {
+        let owned_identity = self.get_owned_identity(bytes_owned_identity).await?;
+        self.server_client.download_messages_and_list_attachments(&owned_identity.get_private_identity()?, owned_identity.get_current_device_uid()?, &mut *self.prng).await
+    }
+
+    pub async fn delete_message(&mut self, bytes_owned_identity: &[u8], message_uid: &[u8]) -> Result<()> {
+        let owned_identity = self.get_owned_identity(bytes_owned_identity).await?;
+        self.server_client.delete_message_and_attachments(&owned_identity.get_private_identity()?, owned_identity.get_current_device_uid()?, message_uid, &mut *self.prng).await
+    }
+
+    /// Persists a message in the outbox, the background sender uploads it and retries on failure
+    pub async fn queue_message(&self, from_identity: &[u8], message: &OutboundServerMessage) -> Result<i64> {
+        self.queue_message_with_attachments(from_identity, message, &[]).await
+    }
+
+    /// Same as `queue_message`, the attachments prepared with `prepare_outbound_attachment` are uploaded once the message is
+    pub async fn queue_message_with_attachments(&self, from_identity: &[u8], message: &OutboundServerMessage, attachment_ids: &[i64]) -> Result<i64> {
+        let mut message = message.clone();
+        message.attachment_chunk_counts = vec![];
+        for attachment_id in attachment_ids {
+            let attachment = self.store.attachments().get_by_id(*attachment_id).await?.ok_or(EngineError::UnknownAttachment)?;
+            message.attachment_chunk_counts.push(attachment.get_chunk_count());
+        }
+
+        let mut transaction = self.store.begin().await?;
+        let outbox_message_id = transaction.outbox_messages().insert(OutboxMessage::new(from_identity, &message)?).await?;
+        for (attachment_number, attachment_id) in attachment_ids.iter().enumerate() {
+            transaction.attachments().link_to_outbox_message(*attachment_id, outbox_message_id, attachment_number as i64).await?;
+        }
+        transaction.commit().await?;
+
+        self.publish_event(EngineEvent::MessageDeliveryStateChanged { outbox_message_id, delivery_state: DeliveryState::Queued });
+        self.outbox_wake_up.notify_one();
+
+        Ok(outbox_message_id)
+    }
+
+    pub async fn get_outbox_message(&self, outbox_message_id: i64) -> Result<Option<OutboxMessage>> {
+        self.store.outbox_messages().get_by_id(outbox_message_id).await
+    }
+
+    /// Called once the recipient acknowledged a sent message
+    pub async fn mark_message_delivered(&self, outbox_message_id: i64) -> Result<()> {
+        self.store.outbox_messages().set_delivery_state(outbox_message_id, DeliveryState::Delivered).await?;
+        if let Some(message_id) = self.store.messages().get_by_outbox_message(outbox_message_id).await?.and_then(|message| message.get_id()) {
+            receipts::advance_message_status(&*self.store, &self.events, message_id, MessageStatus::Delivered).await?;
+        }
+        self.publish_event(EngineEvent::MessageDeliveryStateChanged { outbox_message_id, delivery_state: DeliveryState::Delivered });
+
+        Ok(())
+    }
+
+    /// Downloads the messages of an owned identity into the inbox, then deletes them from the server.
+    ///
+    /// Returns the number of messages that were not already in the inbox.
+    pub async fn fetch_messages(&mut self, bytes_owned_identity: &[u8]) -> Result<usize> {
+        let owned_identity = self.get_owned_identity(bytes_owned_identity).await?;
+        if !owned_identity.is_active() {
+            return Err(EngineError::ProfileDeactivated);
+        }
+        self.inbox_fetcher.fetch_all(&owned_identity.get_private_identity()?, owned_identity.get_current_device_uid()?, &mut *self.prng).await
+    }
+
+    /// Starts listening to the push notifications of an owned identity, does nothing if it already listens
+    pub async fn start_push_listener(&mut self, bytes_owned_identity: &[u8]) -> Result<watch::Receiver<PushConnectionStatus>> {
+        if let Some(push_listener) = self.push_listeners.get(bytes_owned_identity) {
+            return Ok(push_listener.status.clone());
+        }
+
+        let owned_identity = self.get_owned_identity(bytes_owned_identity).await?;
+        if !owned_identity.is_active() {
+            return Err(EngineError::ProfileDeactivated);
+        }
+        let push_listener = PushListener {
+            push_url: self.server_client.get_push_url()?,
+            server_client: Arc::clone(&self.server_client),
+            inbox_fetcher: self.inbox_fetcher.clone(),
+            events: self.events.clone(),
+            owned_identity: owned_identity.get_private_identity()?,
+            device_uid: owned_identity.get_current_device_uid()?.to_vec(),
+            prng: Self::get_default_prng()?,
+            retry_policy: RetryPolicy::default(),
+        }.spawn();
+
+        let status = push_listener.status.clone();
+        self.push_listeners.insert(bytes_owned_identity.to_vec(), push_listener);
+
+        Ok(status)
+    }
+
+    pub fn stop_push_listener(&mut self, bytes_owned_identity: &[u8]) {
+        if let Some(push_listener) = self.push_listeners.remove(bytes_owned_identity) {
+            push_listener.task.abort();
+        }
+    }
+
+    pub fn get_push_connection_status(&self, bytes_owned_identity: &[u8]) -> PushConnectionStatus {
+        self.push_listeners.get(bytes_owned_identity)
+            .map(|push_listener| *push_listener.status.borrow())
+            .unwrap_or(PushConnectionStatus::Disconnected)
+    }
+
+    pub async fn get_pending_inbox_messages(&self, bytes_owned_identity: &[u8]) -> Result<Vec<InboxMessage>> {
+        self.store.inbox_messages().get_unprocessed(bytes_owned_identity).await
+    }
+
+    pub async fn mark_inbox_message_processed(&self, inbox_message_id: i64) -> Result<()> {
+        self.store.inbox_messages().mark_processed(inbox_message_id).await
+    }
+
     pub fn get_default_prng() -> Result<Box<dyn PRNG + Send>> {
+        Ok(Box::new(Self::get_default_hmac_prng()?))
+    }
+
+    /// Concrete PRNG, for the primitives that can't take a `dyn PRNG`
+    pub(crate) fn get_default_hmac_prng() -> Result<PRNGHmacSHA256> {
         let mut seed: [u8; 32] = [0; 32];
         getrandom::fill(&mut seed).map_err(|_| EngineError::PRNG)?;
 
-        Ok(Box::new(PRNGHmacSHA256::init(&seed).map_err(|_| EngineError::PRNG)?))
+        PRNGHmacSHA256::init(&seed).map_err(|_| EngineError::PRNG)
     }
-}
\ No newline at end of file
+}
+
+impl Drop for Engine {
+    fn drop(&mut self) {
+        self.outbox_sender_task.abort();
+        self.attachment_transfer_task.abort();
+        self.message_expirer_task.abort();
+        self.api_key_monitor_task.abort();
+        for push_listener in self.push_listeners.values() {
+            push_listener.task.abort();
+        }
+    }
+}
@@ -0,0 +1,369 @@
+use olvid_core::{crypto::prng::PRNGHmacSHA256, cryptographic_identity::{CryptographicIdentity, OwnedCryptographicIdentity}};
+use tokio::sync::broadcast;
+
+use crate::{channel::{self, ChannelMessage}, contacts, current_timestamp, entities::{attachment::{Attachment, AttachmentStatus}, contact::Contact, contact_channel::ContactChannel, discussion::{Discussion, DiscussionType, Ephemerality}, inbox_message::InboxMessage, message::{Message, MessageDirection, MessageReference}, message_mention::{MessageMention, UserMention}, outbox_message::{DeliveryState, OutboxMessage}, return_receipt::ReturnReceiptStatus}, events::{publish_events, EngineEvent}, groups, identity_details, introductions, receipts::{self, queue_return_receipt}, server::{OutboundServerMessage, ServerClient}, store::{Store, StoreTransaction}, trust_establishments, Engine, EngineError, Result};
+
+use payload::{JsonMessage, JsonPayload, JsonReturnReceipt, JsonUserMention};
+use updates::AppliedOperation;
+
+pub(crate) use expiration::{schedule_on_read, schedule_on_sent, MessageExpirer};
+
+mod expiration;
+mod mentions;
+pub(crate) mod payload;
+mod updates;
+
+/// Returns the discussion of an owned identity with a contact, creating it on the first message
+pub(crate) async fn get_or_create_one_to_one_discussion(transaction: &mut dyn StoreTransaction, contact: &Contact) -> Result<Discussion> {
+    let owned_identity = contact.get_owned_identity();
+    let contact_identity = contact.get_contact_identity();
+
+    if let Some(discussion) = transaction.discussions().get_one_to_one(owned_identity, contact_identity).await? {
+        return Ok(discussion);
+    }
+
+    let id = transaction.discussions().insert(Discussion::new_one_to_one(owned_identity, contact_identity, contact.get_display_name())).await?;
+    transaction.discussions().get_by_id(id).await?.ok_or(EngineError::Technical)
+}
+
+/// Attachments of a message, found by its outbox message when sent and by its message uid when received
+pub(crate) async fn get_message_attachments(store: &dyn Store, message: &Message) -> Result<Vec<Attachment>> {
+    if let Some(outbox_message_id) = message.get_outbox_message_id() {
+        return store.attachments().get_by_outbox_message(outbox_message_id).await;
+    }
+
+    match (store.discussions().get_by_id(message.get_discussion_id()).await?, message.get_message_uid()) {
+        (Some(discussion), Some(message_uid)) => store.attachments().get_inbound_by_message(discussion.get_owned_identity(), message_uid).await,
+        _ => Ok(vec![]),
+    }
+}
+
+/// Identities the messages of a discussion are sent to, the contact or the members of the group
+pub(crate) async fn get_discussion_recipients(store: &dyn Store, discussion: &Discussion) -> Result<Vec<Vec<u8>>> {
+    match (discussion.get_discussion_type()?, discussion.get_contact_identity()) {
+        (DiscussionType::OneToOne, Some(contact_identity)) => {
+            store.contacts().get(discussion.get_owned_identity(), contact_identity).await?.ok_or(EngineError::UnknownContact)?;
+            Ok(vec![contact_identity.to_vec()])
+        }
+        (DiscussionType::Group, _) => groups::get_discussion_recipients(store, discussion).await,
+        _ => Ok(vec![]),
+    }
+}
+
+/// Key of the channel of an owned identity with `contact_identity`, the channel is created with the first message
+async fn get_channel_key(transaction: &mut dyn StoreTransaction, owned_identity: &[u8], contact_identity: &[u8], prng: &mut PRNGHmacSHA256) -> Result<Vec<u8>> {
+    if let Some(channel) = transaction.contact_channels().get(owned_identity, contact_identity).await? {
+        return Ok(channel.get_channel_key().to_vec());
+    }
+
+    let channel_key = channel::generate_channel_key(prng)?;
+    transaction.contact_channels().insert_if_absent(ContactChannel::new(owned_identity, contact_identity, channel_key.clone())).await?;
+    Ok(channel_key)
+}
+
+/// Encrypts a payload for `to_identity` on their channel and queues it in the outbox, returns the id of the outbox message
+pub(crate) async fn queue_payload(transaction: &mut dyn StoreTransaction, from_identity: &OwnedCryptographicIdentity, to_identity: &[u8], payload: &JsonPayload, prng: &mut PRNGHmacSHA256) -> Result<i64> {
+    queue_payload_with_attachments(transaction, from_identity, to_identity, payload, vec![], prng).await
+}
+
+/// Same as `queue_payload`, with the chunk count of each attachment the outbox message will be linked to
+pub(crate) async fn queue_payload_with_attachments(transaction: &mut dyn StoreTransaction, from_identity: &OwnedCryptographicIdentity, to_identity: &[u8], payload: &JsonPayload, attachment_chunk_counts: Vec<i64>, prng: &mut PRNGHmacSHA256) -> Result<i64> {
+    let recipient = CryptographicIdentity::from_raw(to_identity).map_err(|_| EngineError::Technical)?;
+    let bytes_from_identity = from_identity.get_crypto_identity().get_identity();
+    let channel_key = get_channel_key(transaction, &bytes_from_identity, to_identity, prng).await?;
+    let (wrapped_key, encrypted_content) = channel::seal(from_identity, &channel_key, &serde_json::to_vec(payload)?, &recipient, prng)?;
+
+    let outbound_message = OutboundServerMessage {
+        to_identity: to_identity.to_vec(),
+        device_uids: vec![],
+        wrapped_key,
+        encrypted_content,
+        is_application_message: true,
+        attachment_chunk_counts,
+    };
+
+    transaction.outbox_messages().insert(OutboxMessage::new(&bytes_from_identity, &outbound_message)?).await
+}
+
+/// Decrypts an inbox message and saves the message it carries in the discussion with its sender.
+///
+/// Inbox messages the channel layer can't decrypt are left unprocessed. The ones not signed by their sender or from
+/// blocked senders are dropped, as well as the ones from senders that are neither contacts nor members of the group the
+/// message belongs to. What the message changes is written in the transaction that marks it processed, so a failure in
+/// between leaves it to be processed again.
+pub(crate) async fn process_inbox_message(store: &dyn Store, events: &broadcast::Sender<EngineEvent>, server_client: &ServerClient, owned_identity: &OwnedCryptographicIdentity, inbox_message: &InboxMessage) -> Result<()> {
+    let channel_message = match channel::open(owned_identity, inbox_message.get_wrapped_key(), inbox_message.get_encrypted_content()) {
+        Ok(channel_message) => Some(channel_message),
+        Err(EngineError::ChannelDecryption | EngineError::Encoding(_)) => return Ok(()),
+        Err(EngineError::InvalidSenderSignature) => None,
+        Err(error) => return Err(error),
+    };
+
+    let mut committed_events = vec![];
+    let mut applied_operations = vec![];
+    let mut transaction = store.begin().await?;
+    if let Some(channel_message) = channel_message {
+        apply_channel_message(&mut *transaction, &mut committed_events, &mut applied_operations, server_client, owned_identity, inbox_message, channel_message).await?;
+    }
+    transaction.inbox_messages().mark_processed(inbox_message.get_id()).await?;
+    transaction.commit().await?;
+
+    publish_events(events, committed_events);
+    updates::publish_applied_operations(events, applied_operations).await;
+
+    Ok(())
+}
+
+/// Applies what a decrypted inbox message carries, `events` and `applied_operations` are published once committed
+async fn apply_channel_message(
+    transaction: &mut dyn StoreTransaction,
+    events: &mut Vec<EngineEvent>,
+    applied_operations: &mut Vec<AppliedOperation>,
+    server_client: &ServerClient,
+    owned_identity: &OwnedCryptographicIdentity,
+    inbox_message: &InboxMessage,
+    channel_message: ChannelMessage,
+) -> Result<()> {
+    let sender_identity = channel_message.sender_identity;
+    if transaction.blocked_contacts().get(inbox_message.get_owned_identity(), &sender_identity).await?.is_some() {
+        return Ok(());
+    }
+    let payload = serde_json::from_slice::<JsonPayload>(&channel_message.payload).unwrap_or_default();
+
+    if let Some(group_message) = payload.group {
+        return groups::process_group_message(transaction, events, server_client, owned_identity, &sender_identity, group_message).await;
+    }
+    if let Some(introduction_message) = payload.introduction {
+        return introductions::process_introduction_message(transaction, events, inbox_message.get_owned_identity(), &sender_identity, introduction_message).await;
+    }
+    if let Some(signed_published_details) = payload.published_details {
+        return identity_details::process_published_details(transaction, events, inbox_message.get_owned_identity(), &sender_identity, signed_published_details).await;
+    }
+    if let Some(trust_establishment_message) = payload.trust_establishment {
+        return trust_establishments::process_trust_establishment_message(transaction, events, inbox_message.get_owned_identity(), &sender_identity, trust_establishment_message).await;
+    }
+    if let Some(revocation) = payload.trust_revocation {
+        return contacts::process_trust_revocation(transaction, events, inbox_message.get_owned_identity(), &sender_identity, revocation).await;
+    }
+
+    let group_uid = payload.message.as_ref().and_then(|message| message.group_uid.clone())
+        .or_else(|| payload.discussion_shared_settings.as_ref().and_then(|settings| settings.group_uid.clone()))
+        .or_else(|| payload.update_message.as_ref().and_then(|update_message| update_message.group_uid.clone()))
+        .or_else(|| payload.delete_messages.as_ref().and_then(|delete_messages| delete_messages.group_uid.clone()))
+        .or_else(|| payload.reaction.as_ref().and_then(|reaction| reaction.group_uid.clone()));
+    let discussion = match group_uid {
+        Some(group_uid) => groups::get_member_discussion(transaction, inbox_message.get_owned_identity(), &group_uid, &sender_identity).await?,
+        None => {
+            let contact = transaction.contacts().get(inbox_message.get_owned_identity(), &sender_identity).await?;
+            match contact {
+                Some(contact) => Some(get_or_create_one_to_one_discussion(transaction, &contact).await?),
+                None => None,
+            }
+        }
+    };
+    let Some(discussion) = discussion else {
+        return Ok(());
+    };
+    let discussion_id = discussion.get_id().ok_or(EngineError::Technical)?;
+
+    let operations = updates::get_received_operations(&payload, &sender_identity, inbox_message.get_server_timestamp());
+    if let Some(settings) = payload.discussion_shared_settings {
+        expiration::process_shared_settings(transaction, events, &discussion, &sender_identity, settings).await?;
+    }
+    if !operations.is_empty() {
+        applied_operations.extend(updates::process_received_operations(transaction, &discussion, operations).await?);
+    }
+
+    let Some(json_message) = payload.message else {
+        return Ok(());
+    };
+    let return_receipt = payload.return_receipt;
+    let ephemerality = json_message.expiration.map(Ephemerality::from).unwrap_or_default();
+
+    // A reply received before the message it replies to is linked to it once it arrives
+    let reply_to = json_message.reply_to.map(MessageReference::from);
+    let replied_message = match &reply_to {
+        Some(reference) => transaction.messages().get_by_sender(discussion_id, &reference.sender_identity, &reference.sender_thread_identifier, reference.sender_sequence_number).await?,
+        None => None,
+    };
+    let mentions = mentions::get_received_mentions(json_message.body.as_deref(), json_message.user_mentions);
+    let attachment_metadata = match json_message.attachments.is_empty() {
+        true => None,
+        false => Some(serde_json::to_string(&json_message.attachments)?),
+    };
+    let mentioned = mentions.iter().any(|mention| mention.mentioned_identity == inbox_message.get_owned_identity())
+        || replied_message.as_ref().is_some_and(|message| matches!(message.get_direction(), Ok(MessageDirection::Outbound)));
+    let notify = transaction.owned_identities().get_by_identity(inbox_message.get_owned_identity()).await?
+        .is_some_and(|owned_identity| owned_identity.notifies(current_timestamp(), mentioned));
+
+    let message = Message::builder()
+        .discussion_id(discussion_id)
+        .direction(MessageDirection::Inbound)
+        .sender_identity(sender_identity.clone())
+        .sender_thread_identifier(json_message.sender_thread_identifier)
+        .sender_sequence_number(json_message.sender_sequence_number)
+        .maybe_body(json_message.body)
+        .server_timestamp(inbox_message.get_server_timestamp())
+        .maybe_reply_to_message_id(replied_message.and_then(|message| message.get_id()))
+        .maybe_reply_to(reply_to)
+        .maybe_return_receipt_nonce(return_receipt.as_ref().map(|return_receipt| return_receipt.nonce.clone()))
+        .maybe_return_receipt_key(return_receipt.as_ref().map(|return_receipt| return_receipt.key.clone()))
+        .message_uid(inbox_message.get_message_uid().to_vec())
+        .ephemerality(ephemerality)
+        .mentioned(mentioned)
+        .maybe_attachment_metadata(attachment_metadata)
+        .build();
+    let reference = message.get_reference()?;
+
+    // A message received twice, from two copies of the same inbox message, is only saved once
+    let Some(message_id) = transaction.messages().insert_if_absent(message).await? else {
+        return Ok(());
+    };
+    for mention in &mentions {
+        transaction.message_mentions().insert(MessageMention::new(message_id, mention)).await?;
+    }
+    transaction.messages().resolve_replies(discussion_id, &reference, message_id).await?;
+    applied_operations.extend(updates::apply_pending_operations(transaction, &discussion, message_id).await?);
+    transaction.discussions().set_last_message_timestamp(discussion_id, inbox_message.get_server_timestamp()).await?;
+    expiration::schedule_on_creation(transaction, message_id, MessageDirection::Inbound, &ephemerality, inbox_message.get_server_timestamp()).await?;
+    if let Some(return_receipt) = return_receipt {
+        queue_return_receipt(&mut *transaction.return_receipts(), inbox_message.get_owned_identity(), &sender_identity, &return_receipt.nonce, &return_receipt.key, ReturnReceiptStatus::Delivered, &mut Engine::get_default_hmac_prng()?).await?;
+    }
+    events.push(EngineEvent::MessageReceived { owned_identity: inbox_message.get_owned_identity().to_vec(), message_id, notify });
+
+    Ok(())
+}
+
+impl Engine {
+    /// Discussions of an owned identity, most recently active first
+    pub async fn get_discussions(&self, bytes_owned_identity: &[u8]) -> Result<Vec<Discussion>> {
+        self.store.discussions().get_all(bytes_owned_identity).await
+    }
+
+    pub async fn get_message(&self, message_id: i64) -> Result<Option<Message>> {
+        self.store.messages().get_by_id(message_id).await
+    }
+
+    /// Up to `limit` messages of a discussion, most recent first.
+    ///
+    /// Older messages are obtained by passing the id of the last message returned as `before_message_id`.
+    pub async fn get_discussion_messages(&self, discussion_id: i64, before_message_id: Option<i64>, limit: i64) -> Result<Vec<Message>> {
+        self.store.messages().get_page(discussion_id, before_message_id, limit).await
+    }
+
+    /// Saves a text message in the discussion with a contact and queues it in the outbox.
+    ///
+    /// `reply_to_message_id` must be a message of the same discussion, `mentions` must cover parts of the body.
+    pub async fn send_text_message(&self, bytes_owned_identity: &[u8], contact_identity: &[u8], body: &str, reply_to_message_id: Option<i64>, mentions: &[UserMention]) -> Result<Message> {
+        self.send_text_message_with_attachments(bytes_owned_identity, contact_identity, body, reply_to_message_id, mentions, &[]).await
+    }
+
+    /// Same as `send_text_message`, the attachments prepared with `prepare_outbound_attachment` are uploaded once the message is
+    /// and their metadata is sent in the message
+    pub async fn send_text_message_with_attachments(&self, bytes_owned_identity: &[u8], contact_identity: &[u8], body: &str, reply_to_message_id: Option<i64>, mentions: &[UserMention], attachment_ids: &[i64]) -> Result<Message> {
+        let contact = self.store.contacts().get(bytes_owned_identity, contact_identity).await?.ok_or(EngineError::UnknownContact)?;
+        contacts::check_not_blocked(&*self.store, bytes_owned_identity, contact_identity).await?;
+        let mut transaction = self.store.begin().await?;
+        let discussion = get_or_create_one_to_one_discussion(&mut *transaction, &contact).await?;
+        transaction.commit().await?;
+
+        self.send_text(&discussion, &[contact_identity.to_vec()], body, reply_to_message_id, mentions, attachment_ids).await
+    }
+
+    /// Saves a text message in a discussion and queues a copy for each recipient, the message references the first copy.
+    ///
+    /// Attachments are uploaded with the first copy only, they are for discussions with a single recipient.
+    pub(crate) async fn send_text(&self, discussion: &Discussion, recipients: &[Vec<u8>], body: &str, reply_to_message_id: Option<i64>, mentions: &[UserMention], attachment_ids: &[i64]) -> Result<Message> {
+        mentions::check_mentions(body, mentions)?;
+        let bytes_owned_identity = discussion.get_owned_identity();
+        let discussion_id = discussion.get_id().ok_or(EngineError::Technical)?;
+        let sender_thread_identifier = discussion.get_sender_thread_identifier()?;
+
+        let reply_to = match reply_to_message_id {
+            Some(reply_to_message_id) => {
+                let replied_message = self.store.messages().get_by_id(reply_to_message_id).await?
+                    .filter(|message| message.get_discussion_id() == discussion_id)
+                    .ok_or(EngineError::UnknownMessage)?;
+
+                Some(replied_message.get_reference()?)
+            }
+            None => None,
+        };
+
+        let mut attachments = vec![];
+        for attachment_id in attachment_ids {
+            let attachment = self.store.attachments().get_by_id(*attachment_id).await?
+                .filter(|attachment| attachment.get_owned_identity() == bytes_owned_identity && matches!(attachment.get_status(), Ok(AttachmentStatus::Pending)))
+                .ok_or(EngineError::UnknownAttachment)?;
+            attachments.push(attachment);
+        }
+
+        let owned_identity = self.get_owned_identity(bytes_owned_identity).await?.get_private_identity()?;
+        let mut prng = Self::get_default_hmac_prng()?;
+        let (return_receipt_nonce, return_receipt_key) = receipts::generate_return_receipt_secrets(&mut prng)?;
+        let ephemerality = discussion.get_ephemerality();
+
+        let mut transaction = self.store.begin().await?;
+        let sender_sequence_number = transaction.messages().get_next_sequence_number(discussion_id).await?;
+
+        let payload = JsonPayload {
+            message: Some(JsonMessage {
+                body: Some(body.to_owned()),
+                sender_thread_identifier,
+                sender_sequence_number,
+                reply_to: reply_to.clone().map(Into::into),
+                user_mentions: mentions.iter().map(JsonUserMention::from).collect(),
+                expiration: ephemerality.is_ephemeral().then(|| ephemerality.into()),
+                group_uid: discussion.get_group_identifier().map(<[u8]>::to_vec),
+                attachments: attachments.iter().map(|attachment| attachment.get_metadata().into()).collect(),
+            }),
+            return_receipt: Some(JsonReturnReceipt { nonce: return_receipt_nonce.clone(), key: return_receipt_key.clone() }),
+            ..Default::default()
+        };
+        let mut outbox_message_ids = vec![];
+        for (recipient_number, recipient) in recipients.iter().enumerate() {
+            let attachment_chunk_counts = match recipient_number {
+                0 => attachments.iter().map(Attachment::get_chunk_count).collect(),
+                _ => vec![],
+            };
+            outbox_message_ids.push(queue_payload_with_attachments(&mut *transaction, &owned_identity, recipient, &payload, attachment_chunk_counts, &mut prng).await?);
+        }
+        let outbox_message_id = outbox_message_ids.first().copied();
+        if let Some(outbox_message_id) = outbox_message_id {
+            for (attachment_number, attachment_id) in attachment_ids.iter().enumerate() {
+                transaction.attachments().link_to_outbox_message(*attachment_id, outbox_message_id, attachment_number as i64).await?;
+            }
+        }
+
+        let message = Message::builder()
+            .discussion_id(discussion_id)
+            .direction(MessageDirection::Outbound)
+            .sender_identity(bytes_owned_identity.to_vec())
+            .sender_thread_identifier(sender_thread_identifier)
+            .sender_sequence_number(sender_sequence_number)
+            .body(body.to_owned())
+            .maybe_reply_to_message_id(reply_to_message_id)
+            .maybe_reply_to(reply_to)
+            .maybe_outbox_message_id(outbox_message_id)
+            .return_receipt_nonce(return_receipt_nonce)
+            .return_receipt_key(return_receipt_key)
+            .ephemerality(ephemerality)
+            .build();
+        let local_timestamp = message.get_local_timestamp();
+        let message_id = transaction.messages().insert_if_absent(message).await?.ok_or(EngineError::Technical)?;
+        for mention in mentions {
+            transaction.message_mentions().insert(MessageMention::new(message_id, mention)).await?;
+        }
+        transaction.discussions().set_last_message_timestamp(discussion_id, current_timestamp()).await?;
+        expiration::schedule_on_creation(&mut *transaction, message_id, MessageDirection::Outbound, &ephemerality, local_timestamp).await?;
+        transaction.commit().await?;
+
+        for outbox_message_id in outbox_message_ids {
+            self.publish_event(EngineEvent::MessageDeliveryStateChanged { outbox_message_id, delivery_state: DeliveryState::Queued });
+        }
+        self.outbox_wake_up.notify_one();
+        self.expiration_wake_up.notify_one();
+
+        self.store.messages().get_by_id(message_id).await?.ok_or(EngineError::Technical)
+    }
+}
@@ -0,0 +1,260 @@
+//! Disappearing messages.
+//!
+//! The expirations of a message are scheduled when it is created, read or sent, and persisted in
+//! `message_expirations`. The `MessageExpirer` applies them when due, including the ones reached while the engine
+//! was stopped.
+
+use std::{sync::Arc, time::Duration};
+
+use tokio::{sync::{broadcast, Notify}, task::JoinHandle};
+
+use crate::{current_timestamp, entities::{discussion::{Discussion, Ephemerality}, message::{Message, MessageDirection}, message_expiration::MessageExpiration}, events::{publish_event, EngineEvent}, store::{Store, StoreTransaction}, Engine, EngineError, Result};
+
+use super::{get_discussion_recipients, get_message_attachments, payload::{JsonPayload, JsonSharedSettings}, queue_payload, updates::is_group_admin};
+
+const EXPIRATION_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+fn after_seconds(timestamp: i64, seconds: i64) -> i64 {
+    timestamp.saturating_add(seconds.saturating_mul(1000))
+}
+
+/// Schedules the expirations starting with the message, `start_timestamp` is its upload time for inbound messages.
+///
+/// Outbound messages start their visibility right away, inbound ones once read.
+pub(crate) async fn schedule_on_creation(transaction: &mut dyn StoreTransaction, message_id: i64, direction: MessageDirection, ephemerality: &Ephemerality, start_timestamp: i64) -> Result<()> {
+    if let Some(existence_duration) = ephemerality.existence_duration {
+        transaction.message_expirations().insert(MessageExpiration::new(message_id, after_seconds(start_timestamp, existence_duration), false)).await?;
+    }
+    if let (MessageDirection::Outbound, Some(visibility_duration)) = (direction, ephemerality.visibility_duration) {
+        transaction.message_expirations().insert(MessageExpiration::new(message_id, after_seconds(start_timestamp, visibility_duration), true)).await?;
+    }
+
+    Ok(())
+}
+
+/// Read once messages are wiped right away, the others once their visibility duration elapsed
+pub(crate) async fn schedule_on_read(transaction: &mut dyn StoreTransaction, message: &Message) -> Result<bool> {
+    let message_id = message.get_id().ok_or(EngineError::Technical)?;
+    let ephemerality = message.get_ephemerality();
+    let now = current_timestamp();
+
+    let wipe_timestamp = match (ephemerality.read_once, ephemerality.visibility_duration) {
+        (true, _) => now,
+        (false, Some(visibility_duration)) => after_seconds(now, visibility_duration),
+        (false, None) => return Ok(false),
+    };
+    transaction.message_expirations().insert(MessageExpiration::new(message_id, wipe_timestamp, true)).await?;
+
+    Ok(true)
+}
+
+/// The sender does not keep read once messages once they left the device
+pub(crate) async fn schedule_on_sent(store: &dyn Store, message: &Message) -> Result<bool> {
+    if !message.get_ephemerality().read_once {
+        return Ok(false);
+    }
+
+    let message_id = message.get_id().ok_or(EngineError::Technical)?;
+    store.message_expirations().insert(MessageExpiration::new(message_id, current_timestamp(), true)).await?;
+
+    Ok(true)
+}
+
+/// Applies shared settings received from a contact, older versions are ignored, as the ones of a group not sent by an
+/// administrator
+pub(crate) async fn process_shared_settings(transaction: &mut dyn StoreTransaction, events: &mut Vec<EngineEvent>, discussion: &Discussion, sender_identity: &[u8], settings: JsonSharedSettings) -> Result<()> {
+    let discussion_id = discussion.get_id().ok_or(EngineError::Technical)?;
+    if discussion.get_group_identifier().is_some() && !is_group_admin(transaction, discussion, sender_identity).await? {
+        return Ok(());
+    }
+    let ephemerality = settings.expiration.map(Ephemerality::from).unwrap_or_default();
+
+    if transaction.discussions().update_shared_settings(discussion_id, settings.version, &ephemerality).await? {
+        events.push(EngineEvent::DiscussionSettingsUpdated { discussion_id });
+    }
+
+    Ok(())
+}
+
+/// Background task wiping and deleting the messages whose expiration is reached
+pub(crate) struct MessageExpirer {
+    pub store: Arc<dyn Store>,
+    pub events: broadcast::Sender<EngineEvent>,
+    /// Notified whenever an expiration is scheduled
+    pub wake_up: Arc<Notify>,
+}
+
+impl MessageExpirer {
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(self.run())
+    }
+
+    async fn run(self) {
+        loop {
+            let wait_duration = match self.expire_due_messages().await {
+                Ok(()) => self.get_wait_duration().await,
+                Err(_) => Ok(Some(EXPIRATION_RETRY_DELAY)),
+            };
+
+            match wait_duration {
+                Ok(Some(wait_duration)) => {
+                    tokio::select! {
+                        _ = self.wake_up.notified() => {}
+                        _ = tokio::time::sleep(wait_duration) => {}
+                    }
+                }
+                Ok(None) => self.wake_up.notified().await,
+                Err(_) => tokio::time::sleep(EXPIRATION_RETRY_DELAY).await,
+            }
+        }
+    }
+
+    async fn get_wait_duration(&self) -> Result<Option<Duration>> {
+        let next_expiration_timestamp = self.store.message_expirations().get_next_expiration_timestamp().await?;
+        Ok(next_expiration_timestamp.map(|timestamp| Duration::from_millis(timestamp.saturating_sub(current_timestamp()).max(0) as u64)))
+    }
+
+    /// An expiration that fails is retried later, without holding back the other ones
+    async fn expire_due_messages(&self) -> Result<()> {
+        let mut result = Ok(());
+        for expiration in self.store.message_expirations().get_due(current_timestamp()).await? {
+            if let Err(error) = self.expire(expiration).await {
+                result = Err(error);
+            }
+        }
+
+        result
+    }
+
+    async fn expire(&self, expiration: MessageExpiration) -> Result<()> {
+        let expiration_id = expiration.get_id().ok_or(EngineError::Technical)?;
+        let message_id = expiration.get_message_id();
+
+        // A deletion due at the same time as a wipe already removed the message
+        let Some(message) = self.store.messages().get_by_id(message_id).await? else {
+            return self.store.message_expirations().delete(expiration_id).await;
+        };
+        let attachments = get_message_attachments(&*self.store, &message).await?;
+
+        let mut transaction = self.store.begin().await?;
+        for attachment in &attachments {
+            transaction.attachments().delete(attachment.get_id().ok_or(EngineError::Technical)?).await?;
+        }
+        transaction.message_edits().delete_by_message(message_id).await?;
+        transaction.message_mentions().delete_by_message(message_id).await?;
+        if expiration.is_wipe_only() {
+            transaction.messages().wipe(message_id).await?;
+            transaction.message_expirations().delete(expiration_id).await?;
+        } else {
+            transaction.message_reactions().delete_by_message(message_id).await?;
+            transaction.messages().delete(message_id).await?;
+            transaction.message_expirations().delete_by_message(message_id).await?;
+        }
+        transaction.commit().await?;
+
+        for attachment in &attachments {
+            // The file of an attachment not downloaded yet may not exist
+            let _ = tokio::fs::remove_file(attachment.get_file_path()).await;
+        }
+
+        let event = match expiration.is_wipe_only() {
+            true => EngineEvent::MessageWiped { message_id },
+            false => EngineEvent::MessageDeleted { message_id },
+        };
+        publish_event(&self.events, event);
+
+        Ok(())
+    }
+}
+
+impl Engine {
+    /// Changes the disappearing message settings of a discussion and shares them with its participants.
+    ///
+    /// The settings apply to the messages sent afterwards, by any participant. Only the administrators of a group change
+    /// the settings of its discussion.
+    pub async fn set_discussion_ephemerality(&self, discussion_id: i64, ephemerality: Ephemerality) -> Result<()> {
+        let discussion = self.store.discussions().get_by_id(discussion_id).await?.ok_or(EngineError::UnknownDiscussion)?;
+        let settings_version = discussion.get_settings_version() + 1;
+
+        let recipients = get_discussion_recipients(&*self.store, &discussion).await?;
+
+        let payload = JsonPayload {
+            discussion_shared_settings: Some(JsonSharedSettings {
+                version: settings_version,
+                expiration: ephemerality.is_ephemeral().then(|| ephemerality.into()),
+                group_uid: discussion.get_group_identifier().map(<[u8]>::to_vec),
+            }),
+            ..Default::default()
+        };
+        let owned_identity = self.get_owned_identity(discussion.get_owned_identity()).await?.get_private_identity()?;
+        let mut prng = Self::get_default_hmac_prng()?;
+
+        let mut transaction = self.store.begin().await?;
+        if discussion.get_group_identifier().is_some() && !is_group_admin(&mut *transaction, &discussion, discussion.get_owned_identity()).await? {
+            return Err(EngineError::NotGroupAdmin);
+        }
+        transaction.discussions().update_shared_settings(discussion_id, settings_version, &ephemerality).await?;
+        for recipient in &recipients {
+            queue_payload(&mut *transaction, &owned_identity, recipient, &payload, &mut prng).await?;
+        }
+        transaction.commit().await?;
+
+        self.outbox_wake_up.notify_one();
+        self.publish_event(EngineEvent::DiscussionSettingsUpdated { discussion_id });
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use mock_server::MockServer;
+
+    use crate::{configuration::EngineConfiguration, entities::{discussion::{Discussion, Ephemerality}, identity::JsonIdentityDetails}, messages::payload::JsonSharedSettings, store::MemoryStore, Engine};
+
+    use super::process_shared_settings;
+
+    fn details(first_name: &str) -> JsonIdentityDetails {
+        JsonIdentityDetails::builder().first_name(first_name.to_owned()).build()
+    }
+
+    async fn engine_with_identity(server: &MockServer, first_name: &str) -> (Engine, Vec<u8>) {
+        let configuration = EngineConfiguration::builder().server_url(server.url()).build();
+        let mut engine = Engine::init_with_store(configuration, Arc::new(MemoryStore::new())).await.unwrap();
+        let identity = engine.generate_simple_identity(details(first_name)).await.unwrap().identity.get_identity();
+        (engine, identity)
+    }
+
+    /// Applies the settings as if received from the sender, returns the discussion as saved afterwards
+    async fn receive_settings(engine: &Engine, discussion: &Discussion, sender_identity: &[u8], settings: JsonSharedSettings) -> Discussion {
+        let mut transaction = engine.store.begin().await.unwrap();
+        process_shared_settings(&mut *transaction, &mut Vec::new(), discussion, sender_identity, settings).await.unwrap();
+        transaction.commit().await.unwrap();
+        engine.store.discussions().get_by_id(discussion.get_id().unwrap()).await.unwrap().unwrap()
+    }
+
+    #[tokio::test]
+    async fn group_settings_are_only_accepted_from_administrators() {
+        let server = MockServer::start().await.unwrap();
+        let (alice_engine, alice) = engine_with_identity(&server, "Alice").await;
+        let (_bob_engine, bob) = engine_with_identity(&server, "Bob").await;
+        alice_engine.add_contact(&alice, &bob, &details("Bob")).await.unwrap();
+        let group = alice_engine.create_group(&alice, "Friends", std::slice::from_ref(&bob)).await.unwrap();
+        let discussion = alice_engine.get_discussions(&alice).await.unwrap().into_iter()
+            .find(|discussion| discussion.get_group_identifier() == Some(group.get_group_uid()))
+            .unwrap();
+        let ephemerality = Ephemerality { existence_duration: Some(60), ..Default::default() };
+        let settings = JsonSharedSettings { version: 1, expiration: Some(ephemerality.into()), group_uid: Some(group.get_group_uid().to_vec()) };
+
+        // Bob is a member but not an administrator
+        let discussion = receive_settings(&alice_engine, &discussion, &bob, settings.clone()).await;
+        assert_eq!(discussion.get_settings_version(), 0);
+        assert_eq!(discussion.get_ephemerality(), Ephemerality::default());
+
+        let discussion = receive_settings(&alice_engine, &discussion, &alice, settings).await;
+        assert_eq!(discussion.get_settings_version(), 1);
+        assert_eq!(discussion.get_ephemerality(), ephemerality);
+    }
+}
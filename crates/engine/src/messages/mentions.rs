@@ -0,0 +1,106 @@
+//! Mentions of identities in the body of the messages, and the notifications they bypass.
+//!
+//! Mentions cover byte ranges of the body. The ones received are checked against the body, an invalid one is
+//! dropped without rejecting the message. A received message mentioning the owned identity, or replying to one
+//! of its messages, is flagged as mentioned: it is listed by `get_mentioning_messages` and notifies while the
+//! notifications are muted with `except_mentioned`.
+
+use std::ops::Range;
+
+use crate::{
+    entities::{
+        message::Message,
+        message_mention::{ResolvedMention, UserMention},
+    },
+    events::EngineEvent,
+    Engine, EngineError, Result,
+};
+
+use super::payload::JsonUserMention;
+
+/// Whether a range is a non empty part of a body, starting and ending between characters
+fn is_valid_range(body: &str, range: &Range<usize>) -> bool {
+    range.start < range.end && range.end <= body.len() && body.is_char_boundary(range.start) && body.is_char_boundary(range.end)
+}
+
+/// Mentions of a message to send, they must all be valid
+pub(crate) fn check_mentions(body: &str, mentions: &[UserMention]) -> Result<()> {
+    match mentions.iter().all(|mention| is_valid_range(body, &mention.range)) {
+        true => Ok(()),
+        false => Err(EngineError::InvalidMention),
+    }
+}
+
+/// Valid mentions of a received message
+pub(crate) fn get_received_mentions(body: Option<&str>, mentions: Vec<JsonUserMention>) -> Vec<UserMention> {
+    let body = body.unwrap_or_default();
+    mentions.into_iter()
+        .filter_map(|mention| {
+            let range = usize::try_from(mention.range_start).ok()?..usize::try_from(mention.range_end).ok()?;
+            is_valid_range(body, &range).then_some(UserMention { mentioned_identity: mention.mentioned_identity, range })
+        })
+        .collect()
+}
+
+impl Engine {
+    /// Mentions in the body of a message, in the order of the body
+    pub async fn get_message_mentions(&self, message_id: i64) -> Result<Vec<ResolvedMention>> {
+        let message = self.store.messages().get_by_id(message_id).await?.ok_or(EngineError::UnknownMessage)?;
+        let discussion = self.store.discussions().get_by_id(message.get_discussion_id()).await?.ok_or(EngineError::Technical)?;
+
+        let mut resolved_mentions = vec![];
+        for mention in self.store.message_mentions().get_all(message_id).await? {
+            let contact = self.store.contacts().get(discussion.get_owned_identity(), mention.get_mentioned_identity()).await?;
+            resolved_mentions.push(ResolvedMention { mention, contact });
+        }
+
+        Ok(resolved_mentions)
+    }
+
+    /// Up to `limit` received messages mentioning an owned identity or replying to its messages, most recent first.
+    ///
+    /// Older messages are obtained by passing the id of the last message returned as `before_message_id`.
+    pub async fn get_mentioning_messages(&self, bytes_owned_identity: &[u8], before_message_id: Option<i64>, limit: i64) -> Result<Vec<Message>> {
+        self.store.messages().get_mentioning_page(bytes_owned_identity, before_message_id, limit).await
+    }
+
+    /// Mutes the notifications of the messages received by an owned identity, until `until` or until unmuted.
+    ///
+    /// With `except_mentioned`, the messages mentioning the owned identity still notify.
+    pub async fn mute_notifications(&self, bytes_owned_identity: &[u8], until: Option<i64>, except_mentioned: bool) -> Result<()> {
+        self.get_owned_identity(bytes_owned_identity).await?;
+        self.store.owned_identities().set_pref_mute_notifications(bytes_owned_identity, true, until, except_mentioned).await?;
+        self.publish_event(EngineEvent::OwnedIdentityUpdated { owned_identity: bytes_owned_identity.to_vec() });
+
+        Ok(())
+    }
+
+    pub async fn unmute_notifications(&self, bytes_owned_identity: &[u8]) -> Result<()> {
+        let owned_identity = self.get_owned_identity(bytes_owned_identity).await?;
+        self.store.owned_identities().set_pref_mute_notifications(bytes_owned_identity, false, None, owned_identity.get_pref_mute_notifications_except_mentioned()).await?;
+        self.publish_event(EngineEvent::OwnedIdentityUpdated { owned_identity: bytes_owned_identity.to_vec() });
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{entities::message_mention::UserMention, messages::payload::JsonUserMention};
+
+    use super::{check_mentions, get_received_mentions};
+
+    #[test]
+    fn mentions_must_cover_characters_of_the_body() {
+        let mention = |range| UserMention { mentioned_identity: vec![1], range };
+        assert!(check_mentions("Hi @Zoé!", &[mention(3..8)]).is_ok());
+        assert!(check_mentions("Hi @Zoé!", &[mention(3..7)]).is_err());
+        assert!(check_mentions("Hi @Zoé!", &[mention(3..3)]).is_err());
+        assert!(check_mentions("Hi", &[mention(0..3)]).is_err());
+
+        let received = |range_start, range_end| JsonUserMention { mentioned_identity: vec![1], range_start, range_end };
+        let mentions = get_received_mentions(Some("Hi @Zoé!"), vec![received(3, 8), received(-1, 2), received(3, 7), received(0, 20)]);
+        assert_eq!(mentions, [mention(3..8)]);
+        assert!(get_received_mentions(None, vec![received(0, 1)]).is_empty());
+    }
+}
@@ -0,0 +1,450 @@
+//! JSON payload of the application messages, with the field names used by the Olvid apps.
+//!
+//! Unknown fields are ignored, so payloads from newer clients still parse.
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{attachments::AttachmentMetadata, entities::{discussion::Ephemerality, identity::JsonIdentityDetails, message::MessageReference, message_mention::UserMention}};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub(crate) struct JsonPayload {
+    #[serde(rename = "message", default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<JsonMessage>,
+    #[serde(rename = "rr", default, skip_serializing_if = "Option::is_none")]
+    pub return_receipt: Option<JsonReturnReceipt>,
+    #[serde(rename = "settings", default, skip_serializing_if = "Option::is_none")]
+    pub discussion_shared_settings: Option<JsonSharedSettings>,
+    #[serde(rename = "group", default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<JsonGroupMessage>,
+    #[serde(rename = "intro", default, skip_serializing_if = "Option::is_none")]
+    pub introduction: Option<JsonIntroductionMessage>,
+    #[serde(rename = "trust", default, skip_serializing_if = "Option::is_none")]
+    pub trust_establishment: Option<JsonTrustEstablishmentMessage>,
+    #[serde(rename = "details", default, skip_serializing_if = "Option::is_none")]
+    pub published_details: Option<JsonSignedPublishedDetails>,
+    #[serde(rename = "upm", default, skip_serializing_if = "Option::is_none")]
+    pub update_message: Option<JsonUpdateMessage>,
+    #[serde(rename = "delm", default, skip_serializing_if = "Option::is_none")]
+    pub delete_messages: Option<JsonDeleteMessages>,
+    #[serde(rename = "reacm", default, skip_serializing_if = "Option::is_none")]
+    pub reaction: Option<JsonReaction>,
+    #[serde(rename = "revoke", default, skip_serializing_if = "Option::is_none")]
+    pub trust_revocation: Option<JsonTrustRevocation>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct JsonMessage {
+    #[serde(rename = "body", default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+    #[serde(rename = "sti")]
+    pub sender_thread_identifier: Uuid,
+    #[serde(rename = "ssn")]
+    pub sender_sequence_number: i64,
+    #[serde(rename = "re", default, skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<JsonMessageReference>,
+    #[serde(rename = "um", default, skip_serializing_if = "Vec::is_empty")]
+    pub user_mentions: Vec<JsonUserMention>,
+    #[serde(rename = "e", default, skip_serializing_if = "Option::is_none")]
+    pub expiration: Option<JsonExpiration>,
+    /// Only for the messages of a group discussion
+    #[serde(rename = "gid2", default, skip_serializing_if = "Option::is_none", with = "optional_base64_bytes")]
+    pub group_uid: Option<Vec<u8>>,
+    /// In the order of the attachments uploaded with the message
+    #[serde(rename = "atts", default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<JsonAttachment>,
+}
+
+/// What the recipients of an attachment need to download and decrypt it, sizes are in bytes
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct JsonAttachment {
+    #[serde(rename = "k", with = "base64_bytes")]
+    pub key: Vec<u8>,
+    #[serde(rename = "s")]
+    pub size: i64,
+    #[serde(rename = "cl")]
+    pub chunk_length: i64,
+    #[serde(rename = "fn")]
+    pub file_name: String,
+    #[serde(rename = "mt")]
+    pub mime_type: String,
+}
+
+impl From<AttachmentMetadata> for JsonAttachment {
+    fn from(metadata: AttachmentMetadata) -> Self {
+        Self { key: metadata.key, size: metadata.size, chunk_length: metadata.chunk_length, file_name: metadata.file_name, mime_type: metadata.mime_type }
+    }
+}
+
+impl From<JsonAttachment> for AttachmentMetadata {
+    fn from(attachment: JsonAttachment) -> Self {
+        Self { key: attachment.key, size: attachment.size, chunk_length: attachment.chunk_length, file_name: attachment.file_name, mime_type: attachment.mime_type }
+    }
+}
+
+/// Durations are in seconds
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub(crate) struct JsonExpiration {
+    #[serde(rename = "ro", default, skip_serializing_if = "std::ops::Not::not")]
+    pub read_once: bool,
+    #[serde(rename = "vis", default, skip_serializing_if = "Option::is_none")]
+    pub visibility_duration: Option<i64>,
+    #[serde(rename = "ex", default, skip_serializing_if = "Option::is_none")]
+    pub existence_duration: Option<i64>,
+}
+
+impl From<Ephemerality> for JsonExpiration {
+    fn from(ephemerality: Ephemerality) -> Self {
+        Self { read_once: ephemerality.read_once, visibility_duration: ephemerality.visibility_duration, existence_duration: ephemerality.existence_duration }
+    }
+}
+
+impl From<JsonExpiration> for Ephemerality {
+    fn from(expiration: JsonExpiration) -> Self {
+        Self { read_once: expiration.read_once, visibility_duration: expiration.visibility_duration, existence_duration: expiration.existence_duration }
+    }
+}
+
+/// Settings of a discussion shared by all its participants
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct JsonSharedSettings {
+    #[serde(rename = "v")]
+    pub version: i64,
+    #[serde(rename = "exp", default, skip_serializing_if = "Option::is_none")]
+    pub expiration: Option<JsonExpiration>,
+    /// Only for the settings of a group discussion
+    #[serde(rename = "gid2", default, skip_serializing_if = "Option::is_none", with = "optional_base64_bytes")]
+    pub group_uid: Option<Vec<u8>>,
+}
+
+/// Identifies a message of a discussion, for instance the one a message replies to
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct JsonMessageReference {
+    #[serde(rename = "ssn")]
+    pub sender_sequence_number: i64,
+    #[serde(rename = "sti")]
+    pub sender_thread_identifier: Uuid,
+    #[serde(rename = "si", with = "base64_bytes")]
+    pub sender_identifier: Vec<u8>,
+}
+
+impl From<MessageReference> for JsonMessageReference {
+    fn from(reference: MessageReference) -> Self {
+        Self { sender_sequence_number: reference.sender_sequence_number, sender_thread_identifier: reference.sender_thread_identifier, sender_identifier: reference.sender_identity }
+    }
+}
+
+impl From<JsonMessageReference> for MessageReference {
+    fn from(reference: JsonMessageReference) -> Self {
+        Self { sender_identity: reference.sender_identifier, sender_thread_identifier: reference.sender_thread_identifier, sender_sequence_number: reference.sender_sequence_number }
+    }
+}
+
+/// Identity mentioned in the body of a message, the range is in bytes and may be invalid in a received payload
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct JsonUserMention {
+    #[serde(rename = "uid", with = "base64_bytes")]
+    pub mentioned_identity: Vec<u8>,
+    #[serde(rename = "rs")]
+    pub range_start: i64,
+    #[serde(rename = "re")]
+    pub range_end: i64,
+}
+
+impl From<&UserMention> for JsonUserMention {
+    fn from(mention: &UserMention) -> Self {
+        Self { mentioned_identity: mention.mentioned_identity.clone(), range_start: mention.range.start as i64, range_end: mention.range.end as i64 }
+    }
+}
+
+/// New body of a message, only its sender can edit it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct JsonUpdateMessage {
+    #[serde(rename = "body")]
+    pub body: String,
+    #[serde(rename = "ref")]
+    pub reference: JsonMessageReference,
+    /// Only for the messages of a group discussion
+    #[serde(rename = "gid2", default, skip_serializing_if = "Option::is_none", with = "optional_base64_bytes")]
+    pub group_uid: Option<Vec<u8>>,
+}
+
+/// Messages deleted for everyone, by their sender or by an administrator of the group
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct JsonDeleteMessages {
+    #[serde(rename = "refs")]
+    pub references: Vec<JsonMessageReference>,
+    /// Only for the messages of a group discussion
+    #[serde(rename = "gid2", default, skip_serializing_if = "Option::is_none", with = "optional_base64_bytes")]
+    pub group_uid: Option<Vec<u8>>,
+}
+
+/// Reaction of the sender to a message, `None` removes the previous one
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct JsonReaction {
+    #[serde(rename = "reac", default, skip_serializing_if = "Option::is_none")]
+    pub reaction: Option<String>,
+    #[serde(rename = "ref")]
+    pub reference: JsonMessageReference,
+    /// Only for the messages of a group discussion
+    #[serde(rename = "gid2", default, skip_serializing_if = "Option::is_none", with = "optional_base64_bytes")]
+    pub group_uid: Option<Vec<u8>>,
+}
+
+/// Lets the recipient of a message send receipts only the sender can read
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct JsonReturnReceipt {
+    #[serde(rename = "n", with = "base64_bytes")]
+    pub nonce: Vec<u8>,
+    #[serde(rename = "k", with = "base64_bytes")]
+    pub key: Vec<u8>,
+}
+
+/// Membership protocol of the groups v2, see the `groups` module
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "t")]
+pub(crate) enum JsonGroupMessage {
+    /// Sent by an administrator to every member, invitations included, once the blob on the server changed
+    #[serde(rename = "blob")]
+    BlobUpdated {
+        #[serde(rename = "guid", with = "base64_bytes")]
+        group_uid: Vec<u8>,
+        #[serde(rename = "k", with = "base64_bytes")]
+        blob_key: Vec<u8>,
+    },
+    /// Sent to the administrators by an invited member accepting the invitation
+    #[serde(rename = "join")]
+    JoinRequest {
+        #[serde(rename = "guid", with = "base64_bytes")]
+        group_uid: Vec<u8>,
+    },
+    /// Sent to the administrators by a member leaving the group
+    #[serde(rename = "left")]
+    Left {
+        #[serde(rename = "guid", with = "base64_bytes")]
+        group_uid: Vec<u8>,
+    },
+    /// Sent by an administrator to each removed member, which can't read the blob anymore. The administrator signs the
+    /// removal of this member from the blob `version`.
+    #[serde(rename = "kick")]
+    Kicked {
+        #[serde(rename = "guid", with = "base64_bytes")]
+        group_uid: Vec<u8>,
+        #[serde(rename = "v")]
+        version: i64,
+        #[serde(rename = "sig", with = "base64_bytes")]
+        signature: Vec<u8>,
+    },
+}
+
+/// Mutual introduction protocol, see the `introductions` module
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "t")]
+pub(crate) enum JsonIntroductionMessage {
+    /// Sent by the introducer to each introduced identity, about the other one
+    #[serde(rename = "invite")]
+    Invitation {
+        #[serde(rename = "uid", with = "base64_bytes")]
+        introduction_uid: Vec<u8>,
+        #[serde(rename = "c", with = "base64_bytes")]
+        contact_identity: Vec<u8>,
+        #[serde(rename = "d")]
+        identity_details: JsonIdentityDetails,
+        #[serde(rename = "sig", with = "base64_bytes")]
+        signature: Vec<u8>,
+    },
+    /// Sent by an introduced identity accepting the introduction to the other one
+    #[serde(rename = "accept")]
+    Accepted {
+        #[serde(rename = "uid", with = "base64_bytes")]
+        introduction_uid: Vec<u8>,
+        #[serde(rename = "d")]
+        identity_details: JsonIdentityDetails,
+        #[serde(rename = "sig", with = "base64_bytes")]
+        signature: Vec<u8>,
+    },
+}
+
+/// Trust establishment with short authentication strings, see the `trust_establishments` module
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "t")]
+pub(crate) enum JsonTrustEstablishmentMessage {
+    /// Sent by the inviter, committing to its seed
+    #[serde(rename = "invite")]
+    Invitation {
+        #[serde(rename = "uid", with = "base64_bytes")]
+        protocol_uid: Vec<u8>,
+        #[serde(rename = "cmt", with = "base64_bytes")]
+        commitment: Vec<u8>,
+        #[serde(rename = "d")]
+        identity_details: JsonIdentityDetails,
+    },
+    /// Sent by the invited identity accepting the invitation
+    #[serde(rename = "accept")]
+    Accepted {
+        #[serde(rename = "uid", with = "base64_bytes")]
+        protocol_uid: Vec<u8>,
+        #[serde(rename = "seed", with = "base64_bytes")]
+        seed: Vec<u8>,
+        #[serde(rename = "d")]
+        identity_details: JsonIdentityDetails,
+    },
+    /// Sent by the invited identity rejecting the invitation
+    #[serde(rename = "reject")]
+    Rejected {
+        #[serde(rename = "uid", with = "base64_bytes")]
+        protocol_uid: Vec<u8>,
+    },
+    /// Sent by the inviter once it knows the seed of the invited identity, opens its commitment
+    #[serde(rename = "reveal")]
+    SeedRevealed {
+        #[serde(rename = "uid", with = "base64_bytes")]
+        protocol_uid: Vec<u8>,
+        #[serde(rename = "dec", with = "base64_bytes")]
+        decommitment: Vec<u8>,
+    },
+    /// Sent by each side once its user typed the code of the other one
+    #[serde(rename = "confirm")]
+    Confirmed {
+        #[serde(rename = "uid", with = "base64_bytes")]
+        protocol_uid: Vec<u8>,
+        #[serde(rename = "sig", with = "base64_bytes")]
+        signature: Vec<u8>,
+    },
+}
+
+/// Details published by an owned identity to its contacts, see the `identity_details` module
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct JsonPublishedDetails {
+    #[serde(rename = "v")]
+    pub version: i64,
+    #[serde(rename = "d")]
+    pub identity_details: JsonIdentityDetails,
+}
+
+/// Serialized `JsonPublishedDetails`, signed by the identity they describe
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct JsonSignedPublishedDetails {
+    #[serde(rename = "pd", with = "base64_bytes")]
+    pub published_details: Vec<u8>,
+    #[serde(rename = "sig", with = "base64_bytes")]
+    pub signature: Vec<u8>,
+}
+
+/// Sent to a contact whose trust was revoked, signed by the revoking identity, see the `contacts` module
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct JsonTrustRevocation {
+    #[serde(rename = "c", with = "base64_bytes")]
+    pub contact_identity: Vec<u8>,
+    #[serde(rename = "sig", with = "base64_bytes")]
+    pub signature: Vec<u8>,
+}
+
+/// Byte arrays are base64 strings in the Olvid JSON payloads
+pub(crate) mod base64_bytes {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        STANDARD.decode(encoded).map_err(serde::de::Error::custom)
+    }
+}
+
+pub(crate) mod optional_base64_bytes {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &Option<Vec<u8>>, serializer: S) -> Result<S::Ok, S::Error> {
+        match bytes {
+            Some(bytes) => super::base64_bytes::serialize(bytes, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Vec<u8>>, D::Error> {
+        let encoded = Option::<String>::deserialize(deserializer)?;
+        encoded.map(|encoded| STANDARD.decode(encoded)).transpose().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::{JsonAttachment, JsonExpiration, JsonGroupMessage, JsonMessage, JsonMessageReference, JsonPayload, JsonReaction, JsonUpdateMessage, JsonUserMention};
+
+    #[test]
+    fn payload_uses_olvid_field_names() {
+        let sender_thread_identifier = Uuid::new_v4();
+        let payload = JsonPayload {
+            message: Some(JsonMessage {
+                body: Some("Hello".to_owned()),
+                sender_thread_identifier,
+                sender_sequence_number: 2,
+                reply_to: Some(JsonMessageReference { sender_sequence_number: 1, sender_thread_identifier, sender_identifier: vec![0, 1, 2] }),
+                user_mentions: vec![JsonUserMention { mentioned_identity: vec![4], range_start: 0, range_end: 5 }],
+                expiration: Some(JsonExpiration { read_once: true, visibility_duration: None, existence_duration: Some(60) }),
+                group_uid: Some(vec![3]),
+                attachments: vec![JsonAttachment { key: vec![5], size: 10, chunk_length: 4, file_name: "a.txt".to_owned(), mime_type: "text/plain".to_owned() }],
+            }),
+            return_receipt: None,
+            discussion_shared_settings: None,
+            group: None,
+            introduction: None,
+            trust_establishment: None,
+            published_details: None,
+            update_message: None,
+            delete_messages: None,
+            reaction: None,
+            trust_revocation: None,
+        };
+
+        let json: serde_json::Value = serde_json::to_value(&payload).unwrap();
+        assert_eq!(json["message"]["body"], "Hello");
+        assert_eq!(json["message"]["ssn"], 2);
+        assert_eq!(json["message"]["sti"], sender_thread_identifier.to_string());
+        assert_eq!(json["message"]["re"]["si"], "AAEC");
+        assert_eq!(json["message"]["um"], serde_json::json!([{ "uid": "BA==", "rs": 0, "re": 5 }]));
+        assert_eq!(json["message"]["e"], serde_json::json!({ "ro": true, "ex": 60 }));
+        assert_eq!(json["message"]["gid2"], "Aw==");
+        assert_eq!(json["message"]["atts"], serde_json::json!([{ "k": "BQ==", "s": 10, "cl": 4, "fn": "a.txt", "mt": "text/plain" }]));
+
+        let parsed: JsonPayload = serde_json::from_str(r#"{"message":{"sti":"00000000-0000-0000-0000-000000000001","ssn":1,"unknown":true}}"#).unwrap();
+        let parsed_message = parsed.message.unwrap();
+        assert_eq!(parsed_message.body, None);
+        assert_eq!(parsed_message.group_uid, None);
+        assert!(parsed_message.user_mentions.is_empty());
+        assert!(parsed_message.attachments.is_empty());
+    }
+
+    #[test]
+    fn group_messages_are_tagged() {
+        let payload = JsonPayload { group: Some(JsonGroupMessage::Left { group_uid: vec![0, 1, 2] }), ..Default::default() };
+
+        let json: serde_json::Value = serde_json::to_value(&payload).unwrap();
+        assert_eq!(json, serde_json::json!({ "group": { "t": "left", "guid": "AAEC" } }));
+        assert_eq!(serde_json::from_value::<JsonPayload>(json).unwrap(), payload);
+    }
+
+    #[test]
+    fn message_updates_reference_the_message() {
+        let sender_thread_identifier = Uuid::nil();
+        let reference = JsonMessageReference { sender_sequence_number: 4, sender_thread_identifier, sender_identifier: vec![0, 1, 2] };
+        let payload = JsonPayload {
+            update_message: Some(JsonUpdateMessage { body: "Fixed".to_owned(), reference: reference.clone(), group_uid: None }),
+            reaction: Some(JsonReaction { reaction: None, reference, group_uid: Some(vec![3]) }),
+            ..Default::default()
+        };
+
+        let json: serde_json::Value = serde_json::to_value(&payload).unwrap();
+        let reference_json = serde_json::json!({ "ssn": 4, "sti": sender_thread_identifier.to_string(), "si": "AAEC" });
+        assert_eq!(json["upm"], serde_json::json!({ "body": "Fixed", "ref": reference_json }));
+        assert_eq!(json["reacm"], serde_json::json!({ "ref": reference_json, "gid2": "Aw==" }));
+        assert_eq!(serde_json::from_value::<JsonPayload>(json).unwrap(), payload);
+    }
+}
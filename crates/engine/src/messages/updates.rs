@@ -0,0 +1,334 @@
+//! Edits, deletions for everyone and reactions.
+//!
+//! Only the sender of a message can edit it, the sender and the administrators of a group can delete it for everyone,
+//! and every participant can react to it. The messages are referenced by their sender and their position in the sender
+//! thread, an operation received before the message it applies to is kept in `pending_message_operations` and applied
+//! once that message arrives. Edits and reactions carry the time they were made, so that a late one never replaces a
+//! more recent one.
+
+use tokio::sync::broadcast;
+
+use crate::{
+    current_timestamp,
+    entities::{
+        attachment::Attachment,
+        discussion::Discussion,
+        message::Message,
+        message_edit::MessageEdit,
+        message_reaction::{self, AggregatedReaction, MessageReaction},
+        pending_message_operation::{MessageOperationType, PendingMessageOperation},
+    },
+    events::{publish_event, EngineEvent},
+    store::StoreTransaction,
+    Engine, EngineError, Result,
+};
+
+use super::{get_discussion_recipients, payload::{JsonDeleteMessages, JsonMessageReference, JsonPayload, JsonReaction, JsonUpdateMessage}, queue_payload};
+
+/// Edit, deletion or reaction made by a participant at `timestamp`
+pub(crate) struct MessageOperation {
+    operation_type: MessageOperationType,
+    author_identity: Vec<u8>,
+    /// The new body of an edit or the emoji of a reaction
+    body: Option<String>,
+    timestamp: i64,
+}
+
+impl TryFrom<PendingMessageOperation> for MessageOperation {
+    type Error = EngineError;
+
+    fn try_from(operation: PendingMessageOperation) -> std::result::Result<Self, Self::Error> {
+        Ok(Self {
+            operation_type: operation.get_operation_type()?,
+            author_identity: operation.author_identity,
+            body: operation.body,
+            timestamp: operation.server_timestamp,
+        })
+    }
+}
+
+/// What applying an operation changed, the files of the removed attachments are deleted once committed
+pub(crate) struct AppliedOperation {
+    event: EngineEvent,
+    removed_attachments: Vec<Attachment>,
+}
+
+/// Operations carried by a payload, with the messages they apply to, timed by the server.
+///
+/// Their author is `sender_identity`, the sender whose signature the channel layer checked, never an identity read
+/// from the payload.
+pub(crate) fn get_received_operations(payload: &JsonPayload, sender_identity: &[u8], server_timestamp: i64) -> Vec<(JsonMessageReference, MessageOperation)> {
+    let operation = |operation_type, body: Option<&str>| MessageOperation {
+        operation_type,
+        author_identity: sender_identity.to_vec(),
+        body: body.map(str::to_owned),
+        timestamp: server_timestamp,
+    };
+
+    let mut operations = vec![];
+    if let Some(update_message) = &payload.update_message {
+        operations.push((update_message.reference.clone(), operation(MessageOperationType::Edit, Some(&update_message.body))));
+    }
+    if let Some(delete_messages) = &payload.delete_messages {
+        for reference in &delete_messages.references {
+            operations.push((reference.clone(), operation(MessageOperationType::Delete, None)));
+        }
+    }
+    if let Some(reaction) = &payload.reaction {
+        operations.push((reaction.reference.clone(), operation(MessageOperationType::Reaction, reaction.reaction.as_deref())));
+    }
+
+    operations
+}
+
+/// Applies received operations to the messages of a discussion, the ones whose message is not there yet wait for it.
+///
+/// The caller publishes the applied operations once the transaction is committed.
+pub(crate) async fn process_received_operations(transaction: &mut dyn StoreTransaction, discussion: &Discussion, operations: Vec<(JsonMessageReference, MessageOperation)>) -> Result<Vec<AppliedOperation>> {
+    let discussion_id = discussion.get_id().ok_or(EngineError::Technical)?;
+
+    let mut applied_operations = vec![];
+    for (reference, operation) in operations {
+        let message = transaction.messages().get_by_sender(discussion_id, &reference.sender_identifier, &reference.sender_thread_identifier, reference.sender_sequence_number).await?;
+        match message {
+            Some(message) => applied_operations.extend(apply_operation(transaction, discussion, &message, &operation).await?),
+            None => {
+                let pending_operation = PendingMessageOperation::builder()
+                    .discussion_id(discussion_id)
+                    .operation_type(operation.operation_type)
+                    .author_identity(operation.author_identity)
+                    .sender_identity(reference.sender_identifier)
+                    .sender_thread_identifier(reference.sender_thread_identifier)
+                    .sender_sequence_number(reference.sender_sequence_number)
+                    .maybe_body(operation.body)
+                    .server_timestamp(operation.timestamp)
+                    .build();
+                transaction.pending_message_operations().insert(pending_operation).await?;
+            }
+        }
+    }
+
+    Ok(applied_operations)
+}
+
+/// Applies the operations received before a message that was just saved
+pub(crate) async fn apply_pending_operations(transaction: &mut dyn StoreTransaction, discussion: &Discussion, message_id: i64) -> Result<Vec<AppliedOperation>> {
+    let message = transaction.messages().get_by_id(message_id).await?.ok_or(EngineError::Technical)?;
+    let pending_operations = transaction.pending_message_operations()
+        .get_by_message(message.get_discussion_id(), message.get_sender_identity(), &message.get_sender_thread_identifier()?, message.get_sender_sequence_number())
+        .await?;
+
+    let mut applied_operations = vec![];
+    for pending_operation in pending_operations {
+        transaction.pending_message_operations().delete(pending_operation.get_id().ok_or(EngineError::Technical)?).await?;
+        // Each operation sees the message as left by the previous one
+        let message = transaction.messages().get_by_id(message_id).await?.ok_or(EngineError::Technical)?;
+        applied_operations.extend(apply_operation(transaction, discussion, &message, &pending_operation.try_into()?).await?);
+    }
+
+    Ok(applied_operations)
+}
+
+/// Deletes the files of the attachments removed by the operations and publishes their events
+pub(crate) async fn publish_applied_operations(events: &broadcast::Sender<EngineEvent>, applied_operations: Vec<AppliedOperation>) {
+    for applied_operation in applied_operations {
+        for attachment in &applied_operation.removed_attachments {
+            // The file of an attachment not downloaded yet may not exist
+            let _ = tokio::fs::remove_file(attachment.get_file_path()).await;
+        }
+        publish_event(events, applied_operation.event);
+    }
+}
+
+/// Whether an identity administers the group of a discussion, always false for one to one discussions
+pub(crate) async fn is_group_admin(transaction: &mut dyn StoreTransaction, discussion: &Discussion, identity: &[u8]) -> Result<bool> {
+    let Some(group_uid) = discussion.get_group_identifier() else {
+        return Ok(false);
+    };
+    let Some(group) = transaction.groups().get_by_uid(discussion.get_owned_identity(), group_uid).await? else {
+        return Ok(false);
+    };
+    let group_id = group.get_id().ok_or(EngineError::Technical)?;
+
+    Ok(transaction.group_members().get(group_id, identity).await?.is_some_and(|member| member.is_admin()))
+}
+
+/// Only the sender edits a message that still has a body, the sender or a group administrator deletes it
+async fn is_allowed(transaction: &mut dyn StoreTransaction, discussion: &Discussion, message: &Message, operation: &MessageOperation) -> Result<bool> {
+    let is_sender = operation.author_identity == message.get_sender_identity();
+    if message.is_remotely_deleted() {
+        return Ok(false);
+    }
+
+    match operation.operation_type {
+        MessageOperationType::Edit => Ok(is_sender && !message.is_wiped() && operation.body.is_some()),
+        MessageOperationType::Delete => Ok(is_sender || is_group_admin(transaction, discussion, &operation.author_identity).await?),
+        MessageOperationType::Reaction => Ok(true),
+    }
+}
+
+/// Applies an operation its author is allowed to make, returns `None` when it is ignored
+async fn apply_operation(transaction: &mut dyn StoreTransaction, discussion: &Discussion, message: &Message, operation: &MessageOperation) -> Result<Option<AppliedOperation>> {
+    let message_id = message.get_id().ok_or(EngineError::Technical)?;
+    if !is_allowed(transaction, discussion, message, operation).await? {
+        return Ok(None);
+    }
+
+    let event = match operation.operation_type {
+        MessageOperationType::Edit => {
+            let body = operation.body.as_deref().unwrap_or_default();
+            // The latest edit gives the body, the other versions go to the history whatever the order they arrive in
+            let body_timestamp = message.get_body_timestamp();
+            if operation.timestamp > body_timestamp {
+                if let Some(current_body) = message.get_body() {
+                    transaction.message_edits().insert(MessageEdit::new(message_id, current_body, body_timestamp)).await?;
+                }
+                transaction.messages().set_edited_body(message_id, body, operation.timestamp).await?;
+                // The ranges of the mentions were in the previous body
+                transaction.message_mentions().delete_by_message(message_id).await?;
+            } else {
+                transaction.message_edits().insert(MessageEdit::new(message_id, body, operation.timestamp)).await?;
+            }
+            EngineEvent::MessageEdited { message_id }
+        }
+        MessageOperationType::Delete => {
+            let removed_attachments = match (message.get_outbox_message_id(), message.get_message_uid()) {
+                (Some(outbox_message_id), _) => transaction.attachments().get_by_outbox_message(outbox_message_id).await?,
+                (None, Some(message_uid)) => transaction.attachments().get_inbound_by_message(discussion.get_owned_identity(), message_uid).await?,
+                (None, None) => vec![],
+            };
+            for attachment in &removed_attachments {
+                transaction.attachments().delete(attachment.get_id().ok_or(EngineError::Technical)?).await?;
+            }
+            transaction.message_edits().delete_by_message(message_id).await?;
+            transaction.message_mentions().delete_by_message(message_id).await?;
+            transaction.message_reactions().delete_by_message(message_id).await?;
+            transaction.messages().mark_remotely_deleted(message_id).await?;
+
+            return Ok(Some(AppliedOperation { event: EngineEvent::MessageRemotelyDeleted { message_id }, removed_attachments }));
+        }
+        MessageOperationType::Reaction => {
+            let reaction = MessageReaction::new(message_id, &operation.author_identity, operation.body.as_deref(), operation.timestamp);
+            if !transaction.message_reactions().upsert(reaction).await? {
+                return Ok(None);
+            }
+            EngineEvent::MessageReactionsChanged { message_id }
+        }
+    };
+
+    Ok(Some(AppliedOperation { event, removed_attachments: vec![] }))
+}
+
+impl Engine {
+    /// Applies an operation of the owned identity and sends it to the other participants of the discussion
+    async fn send_operation(&self, message: &Message, discussion: &Discussion, operation: MessageOperation, payload: JsonPayload) -> Result<()> {
+        let recipients = get_discussion_recipients(&*self.store, discussion).await?;
+        let owned_identity = self.get_owned_identity(discussion.get_owned_identity()).await?.get_private_identity()?;
+        let mut prng = Self::get_default_hmac_prng()?;
+
+        let mut transaction = self.store.begin().await?;
+        if !is_allowed(&mut *transaction, discussion, message, &operation).await? {
+            return Err(EngineError::NotMessageSender);
+        }
+        let applied_operation = apply_operation(&mut *transaction, discussion, message, &operation).await?;
+        for recipient in &recipients {
+            queue_payload(&mut *transaction, &owned_identity, recipient, &payload, &mut prng).await?;
+        }
+        transaction.commit().await?;
+
+        self.outbox_wake_up.notify_one();
+        publish_applied_operations(&self.events, applied_operation.into_iter().collect()).await;
+
+        Ok(())
+    }
+
+    async fn get_message_with_discussion(&self, message_id: i64) -> Result<(Message, Discussion)> {
+        let message = self.store.messages().get_by_id(message_id).await?
+            .filter(|message| !message.is_remotely_deleted())
+            .ok_or(EngineError::UnknownMessage)?;
+        let discussion = self.store.discussions().get_by_id(message.get_discussion_id()).await?.ok_or(EngineError::UnknownDiscussion)?;
+
+        Ok((message, discussion))
+    }
+
+    /// Replaces the body of a message sent by the owned identity, for every participant.
+    ///
+    /// The previous bodies are kept, see `get_message_edits`.
+    pub async fn edit_message(&self, message_id: i64, body: &str) -> Result<Message> {
+        let (message, discussion) = self.get_message_with_discussion(message_id).await?;
+        if message.is_wiped() {
+            return Err(EngineError::UnknownMessage);
+        }
+
+        let payload = JsonPayload {
+            update_message: Some(JsonUpdateMessage {
+                body: body.to_owned(),
+                reference: message.get_reference()?.into(),
+                group_uid: discussion.get_group_identifier().map(<[u8]>::to_vec),
+            }),
+            ..Default::default()
+        };
+        let operation = MessageOperation {
+            operation_type: MessageOperationType::Edit,
+            author_identity: discussion.get_owned_identity().to_vec(),
+            body: Some(body.to_owned()),
+            timestamp: current_timestamp().max(message.get_body_timestamp() + 1),
+        };
+        self.send_operation(&message, &discussion, operation, payload).await?;
+
+        self.store.messages().get_by_id(message_id).await?.ok_or(EngineError::Technical)
+    }
+
+    /// Deletes a message for every participant, it stays in the discussion without body nor attachments.
+    ///
+    /// The owned identity can delete the messages it sent, and every message of the groups it administers.
+    pub async fn delete_message_for_everyone(&self, message_id: i64) -> Result<()> {
+        let (message, discussion) = self.get_message_with_discussion(message_id).await?;
+
+        let payload = JsonPayload {
+            delete_messages: Some(JsonDeleteMessages {
+                references: vec![message.get_reference()?.into()],
+                group_uid: discussion.get_group_identifier().map(<[u8]>::to_vec),
+            }),
+            ..Default::default()
+        };
+        let operation = MessageOperation {
+            operation_type: MessageOperationType::Delete,
+            author_identity: discussion.get_owned_identity().to_vec(),
+            body: None,
+            timestamp: current_timestamp(),
+        };
+        self.send_operation(&message, &discussion, operation, payload).await
+    }
+
+    /// Reacts to a message of a discussion with an emoji, `None` removes the reaction of the owned identity
+    pub async fn react_to_message(&self, message_id: i64, reaction: Option<&str>) -> Result<()> {
+        let (message, discussion) = self.get_message_with_discussion(message_id).await?;
+
+        let payload = JsonPayload {
+            reaction: Some(JsonReaction {
+                reaction: reaction.map(str::to_owned),
+                reference: message.get_reference()?.into(),
+                group_uid: discussion.get_group_identifier().map(<[u8]>::to_vec),
+            }),
+            ..Default::default()
+        };
+        let operation = MessageOperation {
+            operation_type: MessageOperationType::Reaction,
+            author_identity: discussion.get_owned_identity().to_vec(),
+            body: reaction.map(str::to_owned),
+            timestamp: current_timestamp(),
+        };
+        self.send_operation(&message, &discussion, operation, payload).await
+    }
+
+    /// Previous bodies of an edited message, oldest first
+    pub async fn get_message_edits(&self, message_id: i64) -> Result<Vec<MessageEdit>> {
+        self.store.message_edits().get_all(message_id).await
+    }
+
+    /// Reactions to a message grouped by emoji, the most used first
+    pub async fn get_message_reactions(&self, message_id: i64) -> Result<Vec<AggregatedReaction>> {
+        Ok(message_reaction::aggregate(self.store.message_reactions().get_all(message_id).await?))
+    }
+}
@@ -0,0 +1,25 @@
+mod attachment_transfer;
+mod backoff;
+mod inbox_fetcher;
+mod outbox_sender;
+mod push_listener;
+
+pub use backoff::RetryPolicy;
+pub use push_listener::PushConnectionStatus;
+pub(crate) use attachment_transfer::AttachmentTransfer;
+pub(crate) use inbox_fetcher::InboxFetcher;
+pub(crate) use outbox_sender::OutboxSender;
+pub(crate) use push_listener::{PushListener, PushListenerHandle};
+
+use crate::EngineError;
+
+/// Errors that may disappear by themselves, the others will fail again whatever the number of attempts
+pub(crate) fn is_retryable(error: &EngineError) -> bool {
+    matches!(error,
+        EngineError::Network(_)
+        | EngineError::ServerGeneralError
+        | EngineError::UnknownServerStatus(_)
+        | EngineError::MalformedServerResponse
+        | EngineError::Persistence(_)
+    )
+}
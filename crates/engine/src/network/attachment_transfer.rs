@@ -0,0 +1,195 @@
+use std::{io::SeekFrom, sync::Arc, time::Duration};
+
+use olvid_core::{crypto::{auth_encryption::{AuthEnc, AES256CTRHMACSHA256}, prng::PRNGHmacSHA256}, AES256CTRHMACSHA256Key, SymmetricKey};
+use tokio::{fs::{File, OpenOptions}, io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt}, sync::{broadcast, Notify}, task::JoinHandle};
+
+use crate::{current_timestamp, entities::attachment::{Attachment, AttachmentDirection, AttachmentStatus}, events::{publish_event, EngineEvent}, server::ServerClient, store::Store, Engine, EngineError, Result};
+
+use super::{is_retryable, RetryPolicy};
+
+/// Background task uploading and downloading attachment chunks.
+///
+/// Each chunk is encrypted on its own with the key of the attachment, so that a transfer can resume from any chunk.
+/// An inbound message is only deleted from the server once all its attachments are downloaded.
+pub(crate) struct AttachmentTransfer {
+    pub store: Arc<dyn Store>,
+    pub server_client: Arc<ServerClient>,
+    pub events: broadcast::Sender<EngineEvent>,
+    /// Notified whenever an attachment becomes ready to transfer
+    pub wake_up: Arc<Notify>,
+    pub retry_policy: RetryPolicy,
+}
+
+impl AttachmentTransfer {
+    pub fn spawn(self) -> Result<JoinHandle<()>> {
+        let prng = Engine::get_default_hmac_prng()?;
+        Ok(tokio::spawn(self.run(prng)))
+    }
+
+    async fn run(self, mut prng: PRNGHmacSHA256) {
+        loop {
+            let wait_duration = match self.transfer_due_attachments(&mut prng).await {
+                Ok(()) => self.get_wait_duration().await,
+                Err(_) => Ok(Some(self.retry_policy.initial_delay)),
+            };
+
+            match wait_duration {
+                Ok(Some(wait_duration)) => {
+                    tokio::select! {
+                        _ = self.wake_up.notified() => {}
+                        _ = tokio::time::sleep(wait_duration) => {}
+                    }
+                }
+                Ok(None) => self.wake_up.notified().await,
+                Err(_) => tokio::time::sleep(self.retry_policy.initial_delay).await,
+            }
+        }
+    }
+
+    async fn get_wait_duration(&self) -> Result<Option<Duration>> {
+        let next_attempt_timestamp = self.store.attachments().get_next_attempt_timestamp().await?;
+        Ok(next_attempt_timestamp.map(|timestamp| Duration::from_millis(timestamp.saturating_sub(current_timestamp()).max(0) as u64)))
+    }
+
+    async fn transfer_due_attachments(&self, prng: &mut PRNGHmacSHA256) -> Result<()> {
+        for attachment in self.store.attachments().get_due(current_timestamp()).await? {
+            self.transfer(attachment, prng).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn transfer(&self, attachment: Attachment, prng: &mut PRNGHmacSHA256) -> Result<()> {
+        let id = attachment.get_id().ok_or(EngineError::Technical)?;
+        let direction = attachment.get_direction()?;
+
+        let result = match direction {
+            AttachmentDirection::Outbound => self.upload(id, &attachment, prng).await,
+            AttachmentDirection::Inbound => self.download(id, &attachment, prng).await,
+        };
+
+        let attempt_count = attachment.get_attempt_count() + 1;
+        match result {
+            Ok(()) => {
+                self.store.attachments().set_status(id, AttachmentStatus::Complete).await?;
+                if direction == AttachmentDirection::Inbound {
+                    self.delete_message_once_downloaded(&attachment, prng).await?;
+                }
+
+                self.publish(EngineEvent::AttachmentStatusChanged { attachment_id: id, status: AttachmentStatus::Complete });
+            }
+            Err(error) if is_retryable(&error) && self.retry_policy.should_retry(attempt_count as u32) => {
+                let delay = self.retry_policy.random_delay(attempt_count as u32);
+                self.store.attachments().schedule_retry(id, attempt_count, current_timestamp() + delay.as_millis() as i64).await?;
+            }
+            Err(_) => {
+                self.store.attachments().set_status(id, AttachmentStatus::Failed).await?;
+                self.publish(EngineEvent::AttachmentStatusChanged { attachment_id: id, status: AttachmentStatus::Failed });
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn upload(&self, id: i64, attachment: &Attachment, prng: &mut PRNGHmacSHA256) -> Result<()> {
+        let upload_urls = attachment.get_upload_urls()?;
+        if upload_urls.len() as i64 != attachment.get_chunk_count() {
+            return Err(EngineError::MalformedServerResponse);
+        }
+
+        let key = AES256CTRHMACSHA256Key::init(attachment.get_key()).map_err(|_| EngineError::Technical)?;
+        let mut file = File::open(attachment.get_file_path()).await?;
+
+        for chunk_number in attachment.get_transferred_chunk_count()..attachment.get_chunk_count() {
+            let chunk = read_chunk(&mut file, attachment, chunk_number).await?;
+            let encrypted_chunk = AES256CTRHMACSHA256::encrypt(&chunk, &key, prng).map_err(|_| EngineError::Technical)?;
+            self.server_client.upload_attachment_chunk(&upload_urls[chunk_number as usize], encrypted_chunk).await?;
+
+            self.chunk_transferred(id, attachment, chunk_number).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn download(&self, id: i64, attachment: &Attachment, prng: &mut PRNGHmacSHA256) -> Result<()> {
+        let owned_identity = self.store.owned_identities().get_by_identity(attachment.get_owned_identity()).await?.ok_or(EngineError::UnknownOwnedIdentity)?;
+        let message_uid = attachment.get_message_uid().ok_or(EngineError::Technical)?;
+
+        let download_urls = self.server_client.get_attachment_chunk_download_urls(
+            &owned_identity.get_private_identity()?,
+            owned_identity.get_current_device_uid()?,
+            message_uid,
+            attachment.get_attachment_number(),
+            prng,
+        ).await?;
+        if download_urls.len() as i64 != attachment.get_chunk_count() {
+            return Err(EngineError::MalformedServerResponse);
+        }
+
+        let key = AES256CTRHMACSHA256Key::init(attachment.get_key()).map_err(|_| EngineError::Technical)?;
+        let mut file = OpenOptions::new().write(true).create(true).truncate(false).open(attachment.get_file_path()).await?;
+
+        for chunk_number in attachment.get_transferred_chunk_count()..attachment.get_chunk_count() {
+            let encrypted_chunk = self.server_client.download_attachment_chunk(&download_urls[chunk_number as usize]).await?;
+            let chunk = AES256CTRHMACSHA256::decrypt(&encrypted_chunk, &key).map_err(|_| EngineError::AttachmentDecryption)?;
+            if chunk.len() as i64 != expected_chunk_length(attachment, chunk_number) {
+                return Err(EngineError::AttachmentDecryption);
+            }
+
+            file.seek(SeekFrom::Start((chunk_number * attachment.get_chunk_length()) as u64)).await?;
+            file.write_all(&chunk).await?;
+            // The chunk must be on disk before it is recorded as transferred
+            file.sync_data().await?;
+
+            self.chunk_transferred(id, attachment, chunk_number).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn chunk_transferred(&self, id: i64, attachment: &Attachment, chunk_number: i64) -> Result<()> {
+        self.store.attachments().set_transferred_chunk_count(id, chunk_number + 1).await?;
+        self.publish(EngineEvent::AttachmentProgress {
+            attachment_id: id,
+            transferred_chunk_count: chunk_number + 1,
+            chunk_count: attachment.get_chunk_count(),
+        });
+
+        Ok(())
+    }
+
+    async fn delete_message_once_downloaded(&self, attachment: &Attachment, prng: &mut PRNGHmacSHA256) -> Result<()> {
+        let message_uid = attachment.get_message_uid().ok_or(EngineError::Technical)?;
+        let Some(inbox_message) = self.store.inbox_messages().get_by_message_uid(attachment.get_owned_identity(), message_uid).await? else {
+            return Ok(());
+        };
+
+        let attachments = self.store.attachments().get_inbound_by_message(attachment.get_owned_identity(), message_uid).await?;
+        let complete_count = attachments.iter()
+            .filter(|attachment| matches!(attachment.get_status(), Ok(AttachmentStatus::Complete)))
+            .count();
+        if (complete_count as i64) < inbox_message.get_attachment_count() {
+            return Ok(());
+        }
+
+        let owned_identity = self.store.owned_identities().get_by_identity(attachment.get_owned_identity()).await?.ok_or(EngineError::UnknownOwnedIdentity)?;
+        self.server_client.delete_message_and_attachments(&owned_identity.get_private_identity()?, owned_identity.get_current_device_uid()?, message_uid, prng).await
+    }
+
+    fn publish(&self, event: EngineEvent) {
+        publish_event(&self.events, event);
+    }
+}
+
+fn expected_chunk_length(attachment: &Attachment, chunk_number: i64) -> i64 {
+    let offset = chunk_number * attachment.get_chunk_length();
+    (attachment.get_size() - offset).clamp(0, attachment.get_chunk_length())
+}
+
+async fn read_chunk(file: &mut File, attachment: &Attachment, chunk_number: i64) -> Result<Vec<u8>> {
+    let mut chunk = vec![0; expected_chunk_length(attachment, chunk_number) as usize];
+    file.seek(SeekFrom::Start((chunk_number * attachment.get_chunk_length()) as u64)).await?;
+    file.read_exact(&mut chunk).await?;
+
+    Ok(chunk)
+}
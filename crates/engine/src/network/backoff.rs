@@ -0,0 +1,73 @@
+use std::time::Duration;
+
+/// Exponential backoff with jitter used when a network operation fails
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    /// Attempts after which the operation is abandoned
+    pub max_attempts: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(300),
+            max_attempts: 20,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before the attempt following `failed_attempts` failures.
+    ///
+    /// The exponential delay is halved and the other half is scaled by `jitter` (between 0 and 1),
+    /// so that clients failing together don't retry together.
+    pub fn delay(&self, failed_attempts: u32, jitter: f64) -> Duration {
+        let exponent = failed_attempts.saturating_sub(1).min(31);
+        let exponential_delay = self.initial_delay.saturating_mul(1 << exponent).min(self.max_delay);
+
+        exponential_delay / 2 + (exponential_delay / 2).mul_f64(jitter.clamp(0.0, 1.0))
+    }
+
+    pub fn random_delay(&self, failed_attempts: u32) -> Duration {
+        let mut random_bytes = [0u8; 4];
+        // Falling back to no jitter is harmless
+        let jitter = match getrandom::fill(&mut random_bytes) {
+            Ok(()) => f64::from(u32::from_be_bytes(random_bytes)) / f64::from(u32::MAX),
+            Err(_) => 1.0,
+        };
+
+        self.delay(failed_attempts, jitter)
+    }
+
+    pub fn should_retry(&self, failed_attempts: u32) -> bool {
+        failed_attempts < self.max_attempts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::RetryPolicy;
+
+    #[test]
+    fn delay_grows_exponentially_up_to_max() {
+        let policy = RetryPolicy { initial_delay: Duration::from_secs(1), max_delay: Duration::from_secs(10), max_attempts: 5 };
+
+        assert_eq!(policy.delay(1, 1.0), Duration::from_secs(1));
+        assert_eq!(policy.delay(2, 1.0), Duration::from_secs(2));
+        assert_eq!(policy.delay(3, 1.0), Duration::from_secs(4));
+        assert_eq!(policy.delay(5, 1.0), Duration::from_secs(10));
+        assert_eq!(policy.delay(100, 1.0), Duration::from_secs(10));
+        assert_eq!(policy.delay(3, 0.0), Duration::from_secs(2));
+
+        let random_delay = policy.random_delay(3);
+        assert!(random_delay >= Duration::from_secs(2) && random_delay <= Duration::from_secs(4));
+
+        assert!(policy.should_retry(4));
+        assert!(!policy.should_retry(5));
+    }
+}
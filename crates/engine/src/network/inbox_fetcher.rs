@@ -0,0 +1,71 @@
+use std::sync::Arc;
+
+use olvid_core::{crypto::prng::PRNG, cryptographic_identity::OwnedCryptographicIdentity};
+use tokio::sync::{broadcast, Notify};
+
+use crate::{events::{publish_event, EngineEvent}, messages::process_inbox_message, receipts::process_return_receipt, server::{DownloadedMessage, ServerClient}, store::Store, Result};
+
+/// Moves messages from the server to the inbox.
+///
+/// A message is deleted from the server only once stored, so it is never lost, and storing it twice is a no-op.
+/// Messages with attachments stay on the server until their attachments are downloaded. Stored messages are
+/// then decrypted and saved in their discussion.
+#[derive(Clone)]
+pub(crate) struct InboxFetcher {
+    pub store: Arc<dyn Store>,
+    pub server_client: Arc<ServerClient>,
+    pub events: broadcast::Sender<EngineEvent>,
+    /// Processing a message may queue a delivery receipt and schedule expirations
+    pub outbox_wake_up: Arc<Notify>,
+    pub expiration_wake_up: Arc<Notify>,
+}
+
+impl InboxFetcher {
+    /// Returns the number of messages that were not already in the inbox
+    pub async fn fetch_all(&self, owned_identity: &OwnedCryptographicIdentity, device_uid: &[u8], prng: &mut (dyn PRNG + Send)) -> Result<usize> {
+        let mut new_message_count = 0;
+        for message in self.server_client.download_messages_and_list_attachments(owned_identity, device_uid, prng).await? {
+            if self.store(owned_identity, device_uid, &message, prng).await? {
+                new_message_count += 1;
+            }
+        }
+
+        Ok(new_message_count)
+    }
+
+    /// Returns whether the message was new, a message already deleted from the server is ignored
+    pub async fn fetch_one(&self, owned_identity: &OwnedCryptographicIdentity, device_uid: &[u8], message_uid: &[u8], prng: &mut (dyn PRNG + Send)) -> Result<bool> {
+        match self.server_client.download_message_and_list_attachments(owned_identity, device_uid, message_uid, prng).await? {
+            Some(message) => self.store(owned_identity, device_uid, &message, prng).await,
+            None => Ok(false),
+        }
+    }
+
+    /// Return receipts are pushed by the server, they are never stored in the inbox
+    pub async fn receive_return_receipt(&self, owned_identity: &OwnedCryptographicIdentity, nonce: &[u8], encrypted_payload: &[u8]) -> Result<()> {
+        process_return_receipt(&*self.store, &self.events, &owned_identity.get_crypto_identity().get_identity(), nonce, encrypted_payload).await
+    }
+
+    async fn store(&self, owned_identity: &OwnedCryptographicIdentity, device_uid: &[u8], message: &DownloadedMessage, prng: &mut (dyn PRNG + Send)) -> Result<bool> {
+        let bytes_owned_identity = owned_identity.get_crypto_identity().get_identity();
+
+        let is_new = self.store.inbox_messages().insert_if_absent(&bytes_owned_identity, message).await?;
+        if message.attachment_count == 0 {
+            self.server_client.delete_message_and_attachments(owned_identity, device_uid, &message.message_uid, prng).await?;
+        }
+
+        if is_new {
+            publish_event(&self.events, EngineEvent::InboxMessageStored { owned_identity: bytes_owned_identity.clone(), message_uid: message.message_uid.clone() });
+        }
+
+        if let Some(inbox_message) = self.store.inbox_messages().get_by_message_uid(&bytes_owned_identity, &message.message_uid).await? {
+            if !inbox_message.is_processed() {
+                process_inbox_message(&*self.store, &self.events, &self.server_client, owned_identity, &inbox_message).await?;
+                self.outbox_wake_up.notify_one();
+                self.expiration_wake_up.notify_one();
+            }
+        }
+
+        Ok(is_new)
+    }
+}
@@ -0,0 +1,260 @@
+use std::{sync::{Arc, Mutex}, time::Duration};
+
+use olvid_core::crypto::prng::PRNG;
+use tokio::{sync::{broadcast, Notify}, task::JoinHandle};
+
+use crate::{current_timestamp, entities::{message::MessageStatus, outbox_message::{DeliveryState, OutboxMessage}, return_receipt::OutboxReturnReceipt}, events::{publish_event, EngineEvent}, messages::schedule_on_sent, receipts::advance_message_status, server::{ServerClient, UploadedMessage}, store::Store, EngineError, Result};
+
+use super::{is_retryable, RetryPolicy};
+
+const UPLOAD_NONCE_LENGTH: usize = 32;
+
+/// Background task uploading the messages and the return receipts of the outbox.
+///
+/// Messages are read back from the database, so the ones queued before a crash or a restart are sent once the
+/// sender starts again. Each message gets an upload nonce persisted before its first attempt: a message uploaded
+/// right before a crash is uploaded again with the same nonce, and the server answers the uids of the message it
+/// already stored instead of delivering it twice.
+pub(crate) struct OutboxSender {
+    pub store: Arc<dyn Store>,
+    pub server_client: Arc<ServerClient>,
+    pub events: broadcast::Sender<EngineEvent>,
+    /// Notified whenever a message or a return receipt is queued
+    pub wake_up: Arc<Notify>,
+    /// Notified once the upload URLs of attachments are known
+    pub attachment_wake_up: Arc<Notify>,
+    /// Notified when a read once message is sent
+    pub expiration_wake_up: Arc<Notify>,
+    pub retry_policy: RetryPolicy,
+    pub prng: Mutex<Box<dyn PRNG + Send>>,
+}
+
+impl OutboxSender {
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(self.run())
+    }
+
+    async fn run(self) {
+        loop {
+            let wait_duration = match self.send_due_messages().await {
+                Ok(()) => self.get_wait_duration().await,
+                Err(_) => Ok(Some(self.retry_policy.initial_delay)),
+            };
+
+            match wait_duration {
+                Ok(Some(wait_duration)) => {
+                    tokio::select! {
+                        _ = self.wake_up.notified() => {}
+                        _ = tokio::time::sleep(wait_duration) => {}
+                    }
+                }
+                Ok(None) => self.wake_up.notified().await,
+                Err(_) => tokio::time::sleep(self.retry_policy.initial_delay).await,
+            }
+        }
+    }
+
+    /// Time until the next queued message or receipt is due, `None` when the outbox is empty
+    async fn get_wait_duration(&self) -> Result<Option<Duration>> {
+        let next_message_timestamp = self.store.outbox_messages().get_next_attempt_timestamp().await?;
+        let next_return_receipt_timestamp = self.store.return_receipts().get_next_attempt_timestamp().await?;
+        let next_attempt_timestamp = next_message_timestamp.into_iter().chain(next_return_receipt_timestamp).min();
+        Ok(next_attempt_timestamp.map(|timestamp| Duration::from_millis(timestamp.saturating_sub(current_timestamp()).max(0) as u64)))
+    }
+
+    async fn send_due_messages(&self) -> Result<()> {
+        for outbox_message in self.store.outbox_messages().get_due(current_timestamp()).await? {
+            self.send(outbox_message).await?;
+        }
+        for return_receipt in self.store.return_receipts().get_due(current_timestamp()).await? {
+            self.send_return_receipt(return_receipt).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn send(&self, outbox_message: OutboxMessage) -> Result<()> {
+        let id = outbox_message.get_id().ok_or(EngineError::Technical)?;
+        let attempt_count = outbox_message.get_attempt_count() + 1;
+        let upload_nonce = match outbox_message.get_upload_nonce() {
+            Some(upload_nonce) => upload_nonce.to_vec(),
+            None => {
+                let upload_nonce = self.prng.lock().map_err(|_| EngineError::Technical)?.bytes(UPLOAD_NONCE_LENGTH).map_err(|_| EngineError::PRNG)?;
+                self.store.outbox_messages().set_upload_nonce(id, &upload_nonce).await?;
+                upload_nonce
+            }
+        };
+
+        match self.server_client.upload_message_and_get_uids(&outbox_message.to_outbound_message()?, Some(&upload_nonce)).await {
+            Ok(uploaded_message) => {
+                self.store.outbox_messages().mark_sent(id, attempt_count, &uploaded_message.message_uid).await?;
+                self.save_attachment_upload_urls(id, &uploaded_message).await?;
+                self.advance_message_status(id, MessageStatus::Sent).await?;
+                self.schedule_read_once_wipe(id).await?;
+                self.publish(id, DeliveryState::Sent);
+            }
+            Err(error) if is_retryable(&error) && self.retry_policy.should_retry(attempt_count as u32) => {
+                let delay = self.retry_policy.random_delay(attempt_count as u32);
+                self.store.outbox_messages().schedule_retry(id, attempt_count, current_timestamp() + delay.as_millis() as i64).await?;
+            }
+            Err(_) => {
+                self.store.outbox_messages().mark_failed(id, attempt_count).await?;
+                self.advance_message_status(id, MessageStatus::Failed).await?;
+                self.publish(id, DeliveryState::Failed);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// A receipt that can't be sent is dropped, its message stays in its previous status on the sender side
+    async fn send_return_receipt(&self, return_receipt: OutboxReturnReceipt) -> Result<()> {
+        let id = return_receipt.get_id().ok_or(EngineError::Technical)?;
+        let attempt_count = return_receipt.get_attempt_count() + 1;
+
+        match self.server_client.upload_return_receipt(return_receipt.get_to_identity(), &[], return_receipt.get_nonce(), return_receipt.get_encrypted_payload()).await {
+            Err(error) if is_retryable(&error) && self.retry_policy.should_retry(attempt_count as u32) => {
+                let delay = self.retry_policy.random_delay(attempt_count as u32);
+                self.store.return_receipts().schedule_retry(id, attempt_count, current_timestamp() + delay.as_millis() as i64).await?;
+            }
+            _ => self.store.return_receipts().delete(id).await?,
+        }
+
+        Ok(())
+    }
+
+    async fn schedule_read_once_wipe(&self, outbox_message_id: i64) -> Result<()> {
+        if let Some(message) = self.store.messages().get_by_outbox_message(outbox_message_id).await? {
+            if schedule_on_sent(&*self.store, &message).await? {
+                self.expiration_wake_up.notify_one();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Outbox messages not carrying a discussion message, such as protocol messages, have no status to update
+    async fn advance_message_status(&self, outbox_message_id: i64, status: MessageStatus) -> Result<()> {
+        if let Some(message_id) = self.store.messages().get_by_outbox_message(outbox_message_id).await?.and_then(|message| message.get_id()) {
+            advance_message_status(&*self.store, &self.events, message_id, status).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn save_attachment_upload_urls(&self, outbox_message_id: i64, uploaded_message: &UploadedMessage) -> Result<()> {
+        let attachments = self.store.attachments().get_by_outbox_message(outbox_message_id).await?;
+        if attachments.is_empty() {
+            return Ok(());
+        }
+
+        for attachment in attachments {
+            let attachment_id = attachment.get_id().ok_or(EngineError::Technical)?;
+            let upload_urls = uploaded_message.attachment_upload_urls.get(attachment.get_attachment_number() as usize).ok_or(EngineError::MalformedServerResponse)?;
+            self.store.attachments().set_upload_urls(attachment_id, &uploaded_message.message_uid, upload_urls).await?;
+        }
+        self.attachment_wake_up.notify_one();
+
+        Ok(())
+    }
+
+    fn publish(&self, outbox_message_id: i64, delivery_state: DeliveryState) {
+        publish_event(&self.events, EngineEvent::MessageDeliveryStateChanged { outbox_message_id, delivery_state });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use olvid_core::encoding::{BytesArray, Decoder, Encoder};
+    use tokio::sync::broadcast::Receiver;
+    use wiremock::{matchers::{method, path}, Mock, MockServer, ResponseTemplate};
+
+    use crate::{entities::outbox_message::DeliveryState, events::EngineEvent, server::{OutboundServerMessage, SERVER_STATUS_GENERAL_ERROR, SERVER_STATUS_OK, SERVER_STATUS_PAYLOAD_TOO_LARGE}, Engine};
+
+    fn server_response(status: u8, outputs: Vec<BytesArray>) -> ResponseTemplate {
+        let mut body = vec![status];
+        body.append(&mut outputs.encode().unwrap());
+        ResponseTemplate::new(200).set_body_bytes(body)
+    }
+
+    fn message() -> OutboundServerMessage {
+        OutboundServerMessage {
+            to_identity: vec![1; 32],
+            device_uids: vec![],
+            wrapped_key: vec![2; 32],
+            encrypted_content: vec![3; 64],
+            is_application_message: true,
+            attachment_chunk_counts: vec![],
+        }
+    }
+
+    async fn next_delivery_state(events: &mut Receiver<EngineEvent>) -> DeliveryState {
+        let event = tokio::time::timeout(Duration::from_secs(10), events.recv()).await.unwrap().unwrap();
+        match event {
+            EngineEvent::MessageDeliveryStateChanged { delivery_state, .. } => delivery_state,
+            event => panic!("unexpected event {:?}", event),
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_upload_is_retried() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/uploadMessageAndGetUids"))
+            .respond_with(server_response(SERVER_STATUS_GENERAL_ERROR, vec![]))
+            .up_to_n_times(1)
+            .with_priority(1)
+            .mount(&mock_server)
+            .await;
+
+        Mock::given(method("POST"))
+            .and(path("/uploadMessageAndGetUids"))
+            .respond_with(server_response(SERVER_STATUS_OK, vec![vec![9; 16].encode().unwrap(), Vec::<BytesArray>::new().encode().unwrap()]))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let engine = Engine::init_with_database_url(&mock_server.uri(), None, "sqlite::memory:").await.unwrap();
+        let mut events = engine.subscribe_to_events();
+
+        let id = engine.queue_message(&[0; 32], &message()).await.unwrap();
+        assert_eq!(next_delivery_state(&mut events).await, DeliveryState::Queued);
+        assert_eq!(next_delivery_state(&mut events).await, DeliveryState::Sent);
+
+        let outbox_message = engine.get_outbox_message(id).await.unwrap().unwrap();
+        assert_eq!(outbox_message.get_attempt_count(), 2);
+        assert_eq!(outbox_message.get_message_uid(), Some(&[9; 16][..]));
+
+        // The server may have stored the first attempt, the second one carries the same nonce
+        let upload_nonces = mock_server.received_requests().await.unwrap().iter()
+            .map(|request| Vec::<u8>::decode(&Vec::<BytesArray>::decode(&request.body).unwrap()[6]).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(upload_nonces.len(), 2);
+        assert!(upload_nonces.iter().all(|upload_nonce| Some(upload_nonce.as_slice()) == outbox_message.get_upload_nonce()));
+    }
+
+    #[tokio::test]
+    async fn permanent_error_fails_message() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/uploadMessageAndGetUids"))
+            .respond_with(server_response(SERVER_STATUS_PAYLOAD_TOO_LARGE, vec![]))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let engine = Engine::init_with_database_url(&mock_server.uri(), None, "sqlite::memory:").await.unwrap();
+        let mut events = engine.subscribe_to_events();
+
+        let id = engine.queue_message(&[0; 32], &message()).await.unwrap();
+        assert_eq!(next_delivery_state(&mut events).await, DeliveryState::Queued);
+        assert_eq!(next_delivery_state(&mut events).await, DeliveryState::Failed);
+
+        let outbox_message = engine.get_outbox_message(id).await.unwrap().unwrap();
+        assert_eq!(outbox_message.get_delivery_state().unwrap(), DeliveryState::Failed);
+    }
+}
@@ -0,0 +1,181 @@
+use std::{collections::{HashSet, VecDeque}, sync::Arc};
+
+use futures::{SinkExt, StreamExt};
+use olvid_core::{crypto::prng::PRNG, cryptographic_identity::OwnedCryptographicIdentity};
+use serde::{Deserialize, Serialize};
+use tokio::{sync::{broadcast, watch}, task::JoinHandle};
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+use url::Url;
+
+use crate::{events::{publish_event, EngineEvent}, server::ServerClient, EngineError, Result};
+
+use super::{InboxFetcher, RetryPolicy};
+
+/// Notified message uids remembered to ignore repeated notifications
+const RECENT_MESSAGE_UIDS_CAPACITY: usize = 1024;
+const INVALID_SESSION_REASON: &str = "invalidSession";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PushConnectionStatus {
+    Disconnected,
+    Connecting,
+    Connected,
+}
+
+/// Messages sent on the push WebSocket, binary values are hex encoded
+#[derive(Debug, Serialize)]
+#[serde(tag = "action", rename_all = "camelCase", rename_all_fields = "camelCase")]
+enum ClientPushMessage {
+    Register { identity: String, token: String, device_uid: String },
+}
+
+/// Only the fields used by the engine are deserialized
+#[derive(Debug, Deserialize)]
+#[serde(tag = "action", rename_all = "camelCase", rename_all_fields = "camelCase")]
+enum ServerPushMessage {
+    Registered {},
+    Error { reason: String },
+    Message { message_uid: String },
+    ReturnReceipt { nonce: String, encrypted_payload: String },
+}
+
+struct RecentMessageUids {
+    order: VecDeque<Vec<u8>>,
+    uids: HashSet<Vec<u8>>,
+    capacity: usize,
+}
+
+impl RecentMessageUids {
+    fn new(capacity: usize) -> Self {
+        Self { order: VecDeque::with_capacity(capacity), uids: HashSet::with_capacity(capacity), capacity }
+    }
+
+    /// Returns false when the uid was already seen
+    fn insert(&mut self, message_uid: &[u8]) -> bool {
+        if self.uids.contains(message_uid) {
+            return false;
+        }
+
+        if self.order.len() == self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.uids.remove(&oldest);
+            }
+        }
+        self.order.push_back(message_uid.to_vec());
+        self.uids.insert(message_uid.to_vec());
+
+        true
+    }
+}
+
+pub(crate) struct PushListenerHandle {
+    pub task: JoinHandle<()>,
+    pub status: watch::Receiver<PushConnectionStatus>,
+}
+
+/// Keeps a WebSocket open with the server for one owned identity, downloads the messages it announces and applies
+/// the return receipts it pushes.
+///
+/// Every (re)connection also triggers a full download, to get the messages received while disconnected.
+pub(crate) struct PushListener {
+    pub push_url: Url,
+    pub server_client: Arc<ServerClient>,
+    pub inbox_fetcher: InboxFetcher,
+    pub events: broadcast::Sender<EngineEvent>,
+    pub owned_identity: OwnedCryptographicIdentity,
+    pub device_uid: Vec<u8>,
+    pub prng: Box<dyn PRNG + Send>,
+    pub retry_policy: RetryPolicy,
+}
+
+impl PushListener {
+    pub fn spawn(self) -> PushListenerHandle {
+        let (status_sender, status) = watch::channel(PushConnectionStatus::Disconnected);
+        let task = tokio::spawn(self.run(status_sender));
+
+        PushListenerHandle { task, status }
+    }
+
+    async fn run(mut self, status: watch::Sender<PushConnectionStatus>) {
+        let mut recent_message_uids = RecentMessageUids::new(RECENT_MESSAGE_UIDS_CAPACITY);
+        let mut failed_attempts = 0;
+
+        loop {
+            self.set_status(&status, PushConnectionStatus::Connecting);
+            let result = self.listen(&status, &mut recent_message_uids, &mut failed_attempts).await;
+            self.set_status(&status, PushConnectionStatus::Disconnected);
+
+            if let Err(EngineError::InvalidSession) = result {
+                self.server_client.invalidate_session(&self.owned_identity.get_crypto_identity().get_identity());
+            }
+
+            failed_attempts += 1;
+            tokio::time::sleep(self.retry_policy.random_delay(failed_attempts)).await;
+        }
+    }
+
+    fn set_status(&self, status: &watch::Sender<PushConnectionStatus>, new_status: PushConnectionStatus) {
+        status.send_replace(new_status);
+        publish_event(&self.events, EngineEvent::NetworkStatusChanged {
+            owned_identity: self.owned_identity.get_crypto_identity().get_identity(),
+            status: new_status,
+        });
+    }
+
+    /// Only returns when the connection is lost
+    async fn listen(&mut self, status: &watch::Sender<PushConnectionStatus>, recent_message_uids: &mut RecentMessageUids, failed_attempts: &mut u32) -> Result<()> {
+        let session = self.server_client.get_session(&self.owned_identity, self.prng.as_mut()).await?;
+        let (mut socket, _) = connect_async(self.push_url.as_str()).await.map_err(|_| EngineError::PushConnection)?;
+
+        let register = ClientPushMessage::Register {
+            identity: hex::encode(self.owned_identity.get_crypto_identity().get_identity()),
+            token: hex::encode(&session.token),
+            device_uid: hex::encode(&self.device_uid),
+        };
+        socket.send(Message::Text(serde_json::to_string(&register)?.into())).await.map_err(|_| EngineError::PushConnection)?;
+
+        while let Some(message) = socket.next().await {
+            let Message::Text(text) = message.map_err(|_| EngineError::PushConnection)? else { continue };
+            let Ok(server_message) = serde_json::from_str::<ServerPushMessage>(text.as_str()) else { continue };
+
+            match server_message {
+                ServerPushMessage::Registered { .. } => {
+                    *failed_attempts = 0;
+                    self.set_status(status, PushConnectionStatus::Connected);
+                    self.inbox_fetcher.fetch_all(&self.owned_identity, &self.device_uid, self.prng.as_mut()).await?;
+                }
+                ServerPushMessage::Error { reason } if reason == INVALID_SESSION_REASON => return Err(EngineError::InvalidSession),
+                ServerPushMessage::Error { .. } => return Err(EngineError::PushConnection),
+                ServerPushMessage::Message { message_uid, .. } => {
+                    let Ok(message_uid) = hex::decode(message_uid) else { continue };
+                    if recent_message_uids.insert(&message_uid) {
+                        self.inbox_fetcher.fetch_one(&self.owned_identity, &self.device_uid, &message_uid, self.prng.as_mut()).await?;
+                    }
+                }
+                ServerPushMessage::ReturnReceipt { nonce, encrypted_payload } => {
+                    let (Ok(nonce), Ok(encrypted_payload)) = (hex::decode(nonce), hex::decode(encrypted_payload)) else { continue };
+                    self.inbox_fetcher.receive_return_receipt(&self.owned_identity, &nonce, &encrypted_payload).await?;
+                }
+            }
+        }
+
+        Err(EngineError::PushConnection)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RecentMessageUids;
+
+    #[test]
+    fn recent_message_uids_deduplicates_and_forgets_oldest() {
+        let mut recent_message_uids = RecentMessageUids::new(2);
+
+        assert!(recent_message_uids.insert(&[1]));
+        assert!(!recent_message_uids.insert(&[1]));
+        assert!(recent_message_uids.insert(&[2]));
+        assert!(recent_message_uids.insert(&[3]));
+        assert!(recent_message_uids.insert(&[1]));
+        assert!(!recent_message_uids.insert(&[3]));
+    }
+}
@@ -0,0 +1,358 @@
+//! Transfer of a profile to a new device through the server relay.
+//!
+//! The source device opens a relay session and displays its number, the target device joins it with an ephemeral
+//! identity and a commitment to its seed. Once the source revealed its seed and the target opened its commitment,
+//! both devices display the same code derived from the two seeds, which the user confirms on each of them. Only then
+//! does the source send the profile, encrypted for the ephemeral identity, and the target registers as a new device of
+//! the owned identity.
+
+use olvid_core::{crypto::{commitment::{Commitment, CommitmentWithSHA256}, prng::PRNG}, cryptographic_identity::{CryptographicIdentity, OwnedCryptographicIdentity}, encoding::Encoder};
+use serde::{Deserialize, Serialize};
+
+use crate::{backups::{collect_owned_identity_backup, restore_owned_identity_backup, JsonOwnedIdentityBackup}, channel, current_timestamp, entities::owned_device::OwnedDevice, events::EngineEvent, messages::payload::base64_bytes, trust_establishments::compute_sas, Engine, EngineError, Result};
+
+const TRANSFER_SEED_LENGTH: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProfileTransferStatus {
+    /// The source device waits for the target device to join with the session number
+    WaitingForTarget,
+    /// The devices exchange their seeds
+    Connecting,
+    /// Both devices display this code, the user must confirm it on each of them
+    SasReady { sas: String },
+    /// Confirmed on this device, waiting for the other one
+    WaitingForOtherDevice,
+    /// The owned identity is on both devices
+    Finished { owned_identity: Vec<u8> },
+    /// The target changed its seed after learning the one of the source, or sent an invalid profile
+    Failed,
+}
+
+enum ProfileTransferRole {
+    Source { owned_identity: Vec<u8>, target_commitment: Option<Vec<u8>> },
+    Target { ephemeral_identity: Box<OwnedCryptographicIdentity>, decommitment: Vec<u8>, encrypted_profile: Option<(Vec<u8>, Vec<u8>)> },
+}
+
+/// Profile transfer in progress on this device, only kept in memory
+pub(crate) struct ProfileTransfer {
+    role: ProfileTransferRole,
+    /// Of the target device, the profile is encrypted for it
+    ephemeral_identity: Option<Vec<u8>>,
+    source_seed: Option<Vec<u8>>,
+    target_seed: Option<Vec<u8>>,
+    confirmed: bool,
+    status: ProfileTransferStatus,
+}
+
+impl ProfileTransfer {
+    fn is_source(&self) -> bool {
+        matches!(self.role, ProfileTransferRole::Source { .. })
+    }
+
+    fn compute_sas(&self) -> Result<String> {
+        match (&self.source_seed, &self.target_seed, &self.ephemeral_identity) {
+            (Some(source_seed), Some(target_seed), Some(ephemeral_identity)) => compute_sas(source_seed, target_seed, ephemeral_identity),
+            _ => Err(EngineError::Technical),
+        }
+    }
+}
+
+/// Payloads relayed between the devices
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "camelCase", rename_all_fields = "camelCase")]
+enum JsonProfileTransferMessage {
+    /// From the target, committing to its seed
+    Join {
+        #[serde(with = "base64_bytes")]
+        ephemeral_identity: Vec<u8>,
+        #[serde(with = "base64_bytes")]
+        commitment: Vec<u8>,
+    },
+    SourceSeed {
+        #[serde(with = "base64_bytes")]
+        seed: Vec<u8>,
+    },
+    /// From the target, opening its commitment
+    TargetSeed {
+        #[serde(with = "base64_bytes")]
+        decommitment: Vec<u8>,
+    },
+    /// From the source once the code was confirmed, `JsonTransferredProfile` encrypted for the ephemeral identity
+    Profile {
+        #[serde(with = "base64_bytes")]
+        wrapped_key: Vec<u8>,
+        #[serde(with = "base64_bytes")]
+        encrypted_content: Vec<u8>,
+    },
+    /// From the target once the profile is imported
+    Registered {
+        #[serde(with = "base64_bytes")]
+        device_uid: Vec<u8>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct JsonTransferredProfile {
+    profile: JsonOwnedIdentityBackup,
+    #[serde(with = "base64_bytes")]
+    source_device_uid: Vec<u8>,
+}
+
+/// Binds the commitment of the target to the session and to its ephemeral identity
+fn commitment_tag(session_number: i64, ephemeral_identity: &[u8]) -> Result<Vec<u8>> {
+    Ok(vec![session_number.encode()?, ephemeral_identity.to_vec().encode()?].encode()?)
+}
+
+impl Engine {
+    /// Starts transferring an owned identity from this device, returns the session number to type on the target device
+    pub async fn start_profile_transfer(&mut self, bytes_owned_identity: &[u8]) -> Result<i64> {
+        let private_identity = self.get_owned_identity(bytes_owned_identity).await?.get_private_identity()?;
+        if self.store.keycloak_servers().get(bytes_owned_identity).await?.is_some_and(|keycloak_server| keycloak_server.is_transfer_restricted()) {
+            return Err(EngineError::ProfileTransferRestricted);
+        }
+        let session_number = self.server_client.transfer_open_session(&private_identity, &mut *self.prng).await?;
+
+        self.profile_transfers.insert(session_number, ProfileTransfer {
+            role: ProfileTransferRole::Source { owned_identity: bytes_owned_identity.to_vec(), target_commitment: None },
+            ephemeral_identity: None,
+            source_seed: None,
+            target_seed: None,
+            confirmed: false,
+            status: ProfileTransferStatus::WaitingForTarget,
+        });
+
+        Ok(session_number)
+    }
+
+    /// Joins, from the target device, the transfer started on the source device
+    pub async fn join_profile_transfer(&mut self, session_number: i64) -> Result<()> {
+        let mut prng = Self::get_default_hmac_prng()?;
+        let ephemeral_identity = OwnedCryptographicIdentity::generate_owned_cryptographic_identity(&self.server_url, &mut prng).map_err(|_| EngineError::Technical)?;
+        let bytes_ephemeral_identity = ephemeral_identity.get_crypto_identity().get_identity();
+        let seed = prng.bytes(TRANSFER_SEED_LENGTH).map_err(|_| EngineError::PRNG)?;
+        let (commitment, decommitment) = CommitmentWithSHA256::commit(&commitment_tag(session_number, &bytes_ephemeral_identity)?, &seed, &mut prng)
+            .map_err(|_| EngineError::PRNG)?;
+
+        let message = JsonProfileTransferMessage::Join { ephemeral_identity: bytes_ephemeral_identity.clone(), commitment };
+        self.server_client.transfer_relay(session_number, true, &serde_json::to_vec(&message)?).await?;
+
+        self.profile_transfers.insert(session_number, ProfileTransfer {
+            role: ProfileTransferRole::Target { ephemeral_identity: Box::new(ephemeral_identity), decommitment, encrypted_profile: None },
+            ephemeral_identity: Some(bytes_ephemeral_identity),
+            source_seed: None,
+            target_seed: Some(seed),
+            confirmed: false,
+            status: ProfileTransferStatus::Connecting,
+        });
+
+        Ok(())
+    }
+
+    /// Processes what the other device sent since the previous call, and returns the new status of the transfer
+    pub async fn poll_profile_transfer(&mut self, session_number: i64) -> Result<ProfileTransferStatus> {
+        let mut profile_transfer = self.profile_transfers.remove(&session_number).ok_or(EngineError::UnknownProfileTransfer)?;
+        let result = self.process_profile_transfer_messages(session_number, &mut profile_transfer).await;
+        self.keep_profile_transfer(session_number, profile_transfer, result)
+    }
+
+    /// Confirms the code displayed by both devices, it must be typed on each of them
+    pub async fn confirm_profile_transfer(&mut self, session_number: i64, sas: &str) -> Result<ProfileTransferStatus> {
+        let mut profile_transfer = self.profile_transfers.remove(&session_number).ok_or(EngineError::UnknownProfileTransfer)?;
+        let result = self.confirm_sas(session_number, &mut profile_transfer, sas).await;
+        self.keep_profile_transfer(session_number, profile_transfer, result)
+    }
+
+    pub async fn cancel_profile_transfer(&mut self, session_number: i64) -> Result<()> {
+        let profile_transfer = self.profile_transfers.remove(&session_number).ok_or(EngineError::UnknownProfileTransfer)?;
+        if let ProfileTransferRole::Source { owned_identity, .. } = &profile_transfer.role {
+            let private_identity = self.get_owned_identity(owned_identity).await?.get_private_identity()?;
+            self.server_client.transfer_close_session(&private_identity, session_number, &mut *self.prng).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Other devices of an owned identity, the ones it was transferred from or to
+    pub async fn get_owned_devices(&self, bytes_owned_identity: &[u8]) -> Result<Vec<OwnedDevice>> {
+        self.store.owned_devices().get_all(bytes_owned_identity).await
+    }
+
+    /// Finished and failed transfers are forgotten, the others are kept even when processing them failed
+    fn keep_profile_transfer(&mut self, session_number: i64, profile_transfer: ProfileTransfer, result: Result<()>) -> Result<ProfileTransferStatus> {
+        let status = profile_transfer.status.clone();
+        if !matches!(status, ProfileTransferStatus::Finished { .. } | ProfileTransferStatus::Failed) {
+            self.profile_transfers.insert(session_number, profile_transfer);
+        }
+
+        result.map(|_| status)
+    }
+
+    async fn process_profile_transfer_messages(&mut self, session_number: i64, profile_transfer: &mut ProfileTransfer) -> Result<()> {
+        let payloads = self.server_client.transfer_fetch(session_number, profile_transfer.is_source()).await?;
+        for payload in payloads {
+            // Anybody knowing the session number can relay payloads, the code protects the transfer
+            let Ok(message) = serde_json::from_slice::<JsonProfileTransferMessage>(&payload) else {
+                continue;
+            };
+            self.process_profile_transfer_message(session_number, profile_transfer, message).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn process_profile_transfer_message(&mut self, session_number: i64, profile_transfer: &mut ProfileTransfer, message: JsonProfileTransferMessage) -> Result<()> {
+        match (&mut profile_transfer.role, message) {
+            (ProfileTransferRole::Source { target_commitment, .. }, JsonProfileTransferMessage::Join { ephemeral_identity, commitment }) => {
+                if profile_transfer.status != ProfileTransferStatus::WaitingForTarget {
+                    return Ok(());
+                }
+                let seed = Self::get_default_hmac_prng()?.bytes(TRANSFER_SEED_LENGTH).map_err(|_| EngineError::PRNG)?;
+                let message = JsonProfileTransferMessage::SourceSeed { seed: seed.clone() };
+                self.server_client.transfer_relay(session_number, false, &serde_json::to_vec(&message)?).await?;
+
+                *target_commitment = Some(commitment);
+                profile_transfer.ephemeral_identity = Some(ephemeral_identity);
+                profile_transfer.source_seed = Some(seed);
+                profile_transfer.status = ProfileTransferStatus::Connecting;
+            }
+            (ProfileTransferRole::Source { target_commitment: Some(commitment), .. }, JsonProfileTransferMessage::TargetSeed { decommitment }) => {
+                if profile_transfer.status != ProfileTransferStatus::Connecting {
+                    return Ok(());
+                }
+                let ephemeral_identity = profile_transfer.ephemeral_identity.as_deref().ok_or(EngineError::Technical)?;
+                match CommitmentWithSHA256::open(commitment, &commitment_tag(session_number, ephemeral_identity)?, &decommitment) {
+                    Ok(seed) if seed.len() == TRANSFER_SEED_LENGTH => {
+                        profile_transfer.target_seed = Some(seed);
+                        profile_transfer.status = ProfileTransferStatus::SasReady { sas: profile_transfer.compute_sas()? };
+                    }
+                    _ => profile_transfer.status = ProfileTransferStatus::Failed,
+                }
+            }
+            (ProfileTransferRole::Source { owned_identity, .. }, JsonProfileTransferMessage::Registered { device_uid }) => {
+                if profile_transfer.status != ProfileTransferStatus::WaitingForOtherDevice {
+                    return Ok(());
+                }
+                self.store.owned_devices().insert_if_absent(OwnedDevice::new(owned_identity, &device_uid)).await?;
+                let private_identity = self.get_owned_identity(owned_identity).await?.get_private_identity()?;
+                self.server_client.transfer_close_session(&private_identity, session_number, &mut *self.prng).await?;
+                profile_transfer.status = ProfileTransferStatus::Finished { owned_identity: owned_identity.clone() };
+            }
+            (ProfileTransferRole::Target { decommitment, .. }, JsonProfileTransferMessage::SourceSeed { seed }) => {
+                if profile_transfer.status != ProfileTransferStatus::Connecting || seed.len() != TRANSFER_SEED_LENGTH {
+                    return Ok(());
+                }
+                let message = JsonProfileTransferMessage::TargetSeed { decommitment: decommitment.clone() };
+                self.server_client.transfer_relay(session_number, true, &serde_json::to_vec(&message)?).await?;
+
+                profile_transfer.source_seed = Some(seed);
+                profile_transfer.status = ProfileTransferStatus::SasReady { sas: profile_transfer.compute_sas()? };
+            }
+            (ProfileTransferRole::Target { encrypted_profile, .. }, JsonProfileTransferMessage::Profile { wrapped_key, encrypted_content }) => {
+                if profile_transfer.source_seed.is_none() {
+                    return Ok(());
+                }
+                *encrypted_profile = Some((wrapped_key, encrypted_content));
+                if profile_transfer.confirmed {
+                    self.import_transferred_profile(session_number, profile_transfer).await?;
+                }
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    async fn confirm_sas(&mut self, session_number: i64, profile_transfer: &mut ProfileTransfer, sas: &str) -> Result<()> {
+        let ProfileTransferStatus::SasReady { sas: expected_sas } = &profile_transfer.status else {
+            return Err(EngineError::UnexpectedProfileTransferStep);
+        };
+        if sas.trim() != expected_sas {
+            return Err(EngineError::InvalidSasCode);
+        }
+        profile_transfer.confirmed = true;
+
+        match &profile_transfer.role {
+            ProfileTransferRole::Source { owned_identity, .. } => {
+                let owned_identity = self.get_owned_identity(owned_identity).await?;
+                let private_identity = owned_identity.get_private_identity()?;
+                let source_device_uid = owned_identity.get_current_device_uid()?.to_vec();
+                let transferred_profile = JsonTransferredProfile { profile: collect_owned_identity_backup(&*self.store, owned_identity).await?, source_device_uid };
+
+                let ephemeral_identity = CryptographicIdentity::from_raw(profile_transfer.ephemeral_identity.as_deref().ok_or(EngineError::Technical)?)
+                    .map_err(|_| EngineError::Technical)?;
+                // The ephemeral identity of the target is only used once, a channel with it would never be reused
+                let mut prng = Self::get_default_hmac_prng()?;
+                let channel_key = channel::generate_channel_key(&mut prng)?;
+                let (wrapped_key, encrypted_content) = channel::seal(&private_identity, &channel_key, &serde_json::to_vec(&transferred_profile)?, &ephemeral_identity, &mut prng)?;
+                let message = JsonProfileTransferMessage::Profile { wrapped_key, encrypted_content };
+                self.server_client.transfer_relay(session_number, false, &serde_json::to_vec(&message)?).await?;
+
+                profile_transfer.status = ProfileTransferStatus::WaitingForOtherDevice;
+            }
+            ProfileTransferRole::Target { encrypted_profile: Some(_), .. } => self.import_transferred_profile(session_number, profile_transfer).await?,
+            ProfileTransferRole::Target { .. } => profile_transfer.status = ProfileTransferStatus::WaitingForOtherDevice,
+        }
+
+        Ok(())
+    }
+
+    /// Stores the profile sent by the source with a new device, and registers this device on the server
+    async fn import_transferred_profile(&mut self, session_number: i64, profile_transfer: &mut ProfileTransfer) -> Result<()> {
+        let ProfileTransferRole::Target { ephemeral_identity, encrypted_profile: Some((wrapped_key, encrypted_content)), .. } = &profile_transfer.role else {
+            return Err(EngineError::Technical);
+        };
+        let transferred_profile = channel::open(ephemeral_identity, wrapped_key, encrypted_content).ok()
+            .and_then(|channel_message| {
+                let transferred_profile: JsonTransferredProfile = serde_json::from_slice(&channel_message.payload).ok()?;
+                (transferred_profile.profile.owned_identity.get_bytes_owned_identity() == channel_message.sender_identity).then_some(transferred_profile)
+            });
+        let Some(mut transferred_profile) = transferred_profile else {
+            profile_transfer.status = ProfileTransferStatus::Failed;
+            return Ok(());
+        };
+
+        let owned_identity = transferred_profile.profile.owned_identity.with_new_device()?;
+        let bytes_owned_identity = owned_identity.get_bytes_owned_identity().to_vec();
+        if self.store.owned_identities().get_by_identity(&bytes_owned_identity).await?.is_some() {
+            profile_transfer.status = ProfileTransferStatus::Failed;
+            return Ok(());
+        }
+        let device_uid = owned_identity.get_current_device_uid()?.to_vec();
+        let private_identity = owned_identity.get_private_identity()?;
+        transferred_profile.profile.owned_identity = owned_identity;
+
+        let mut transaction = self.store.begin().await?;
+        restore_owned_identity_backup(&mut *transaction, transferred_profile.profile).await?;
+        transaction.owned_devices().insert_if_absent(OwnedDevice::new(&bytes_owned_identity, &transferred_profile.source_device_uid)).await?;
+        transaction.commit().await?;
+
+        // Displayed as the other profiles of this device, and selected
+        let format = self.store.settings().get_display_name_format().await?;
+        self.store.owned_identities().refresh_display_name(&bytes_owned_identity, &format).await?;
+        for contact in self.store.contacts().get_all(&bytes_owned_identity).await? {
+            self.store.contacts().refresh_display_name(&bytes_owned_identity, contact.get_contact_identity(), &format).await?;
+        }
+        self.store.owned_identities().set_last_selection_timestamp(&bytes_owned_identity, current_timestamp()).await?;
+        self.publish_event(EngineEvent::OwnedIdentityCreated { owned_identity: bytes_owned_identity.clone() });
+
+        self.server_client.get_session(&private_identity, &mut *self.prng).await?;
+        let message = JsonProfileTransferMessage::Registered { device_uid };
+        self.server_client.transfer_relay(session_number, true, &serde_json::to_vec(&message)?).await?;
+
+        profile_transfer.status = ProfileTransferStatus::Finished { owned_identity: bytes_owned_identity };
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::JsonProfileTransferMessage;
+
+    #[test]
+    fn relayed_messages_are_tagged() {
+        let message = JsonProfileTransferMessage::Registered { device_uid: vec![1, 2, 3] };
+        assert_eq!(serde_json::to_string(&message).unwrap(), r#"{"type":"registered","deviceUid":"AQID"}"#);
+    }
+}
@@ -0,0 +1,166 @@
+//! Switching between owned identities, hiding, deactivating and deleting them.
+//!
+//! A hidden identity is only listed once the password it was hidden with is entered, the engine only keeps a salted
+//! hash of that password, derived as the key of a database encrypted with a passphrase.
+
+use subtle::ConstantTimeEq;
+
+use crate::{entities::identity::{ObvIdentity, OwnedIdentity}, current_timestamp, events::EngineEvent, store::encryption::{derive_seed, KDF_SALT_LENGTH, PASSPHRASE_KDF_ITERATIONS}, Engine, EngineError, Result};
+
+fn hash_unlock_password(password: &str, salt: &[u8]) -> Result<[u8; 32]> {
+    derive_seed(password.as_bytes(), salt, PASSPHRASE_KDF_ITERATIONS)
+}
+
+impl Engine {
+    pub(crate) fn is_visible(&self, owned_identity: &OwnedIdentity) -> bool {
+        !owned_identity.is_hidden() || self.unlocked_profiles.contains(owned_identity.get_bytes_owned_identity())
+    }
+
+    /// Visible owned identity selected last, `None` when there are none
+    pub async fn get_current_owned_identity(&self) -> Result<Option<ObvIdentity>> {
+        let current_identity = self.store.owned_identities().get_all().await?.into_iter()
+            .filter(|owned_identity| self.is_visible(owned_identity))
+            .max_by_key(OwnedIdentity::get_last_selection_timestamp);
+
+        current_identity.map(OwnedIdentity::try_into).transpose()
+    }
+
+    /// Makes an owned identity the current one, see `get_current_owned_identity`
+    pub async fn select_owned_identity(&self, bytes_owned_identity: &[u8]) -> Result<()> {
+        let owned_identity = self.get_owned_identity(bytes_owned_identity).await?;
+        if !self.is_visible(&owned_identity) {
+            return Err(EngineError::UnknownOwnedIdentity);
+        }
+        self.store.owned_identities().set_last_selection_timestamp(bytes_owned_identity, current_timestamp()).await?;
+        self.publish_event(EngineEvent::OwnedIdentityUpdated { owned_identity: bytes_owned_identity.to_vec() });
+
+        Ok(())
+    }
+
+    /// Hides an owned identity behind a password, it stays listed until `lock_hidden_profiles`
+    pub async fn hide_owned_identity(&mut self, bytes_owned_identity: &[u8], password: &str) -> Result<()> {
+        self.get_owned_identity(bytes_owned_identity).await?;
+        if password.is_empty() {
+            return Err(EngineError::InvalidUnlockPassword);
+        }
+        let other_visible_identity = self.store.owned_identities().get_all().await?.iter()
+            .any(|owned_identity| owned_identity.get_bytes_owned_identity() != bytes_owned_identity && !owned_identity.is_hidden());
+        if !other_visible_identity {
+            return Err(EngineError::LastVisibleProfile);
+        }
+
+        let mut salt = vec![0; KDF_SALT_LENGTH];
+        getrandom::fill(&mut salt).map_err(|_| EngineError::PRNG)?;
+        self.store.owned_identities().set_unlock_password(bytes_owned_identity, Some((&hash_unlock_password(password, &salt)?, &salt))).await?;
+        self.unlocked_profiles.insert(bytes_owned_identity.to_vec());
+        self.publish_event(EngineEvent::OwnedIdentityUpdated { owned_identity: bytes_owned_identity.to_vec() });
+
+        Ok(())
+    }
+
+    pub async fn unhide_owned_identity(&mut self, bytes_owned_identity: &[u8]) -> Result<()> {
+        self.get_owned_identity(bytes_owned_identity).await?;
+        self.store.owned_identities().set_unlock_password(bytes_owned_identity, None).await?;
+        self.unlocked_profiles.remove(bytes_owned_identity);
+        self.publish_event(EngineEvent::OwnedIdentityUpdated { owned_identity: bytes_owned_identity.to_vec() });
+
+        Ok(())
+    }
+
+    /// Lists the hidden owned identities hidden with this password, and returns them
+    pub async fn unlock_hidden_profiles(&mut self, password: &str) -> Result<Vec<ObvIdentity>> {
+        let mut unlocked_identities: Vec<ObvIdentity> = Vec::new();
+        for owned_identity in self.store.owned_identities().get_all().await? {
+            let Some((unlock_password, salt)) = owned_identity.get_unlock_password() else {
+                continue;
+            };
+            if bool::from(hash_unlock_password(password, salt)?.ct_eq(unlock_password)) {
+                self.unlocked_profiles.insert(owned_identity.get_bytes_owned_identity().to_vec());
+                unlocked_identities.push(owned_identity.try_into()?);
+            }
+        }
+        if unlocked_identities.is_empty() {
+            return Err(EngineError::InvalidUnlockPassword);
+        }
+        for unlocked_identity in &unlocked_identities {
+            self.publish_event(EngineEvent::OwnedIdentityUpdated { owned_identity: unlocked_identity.identity.get_identity() });
+        }
+
+        Ok(unlocked_identities)
+    }
+
+    /// Stops listing the hidden owned identities
+    pub fn lock_hidden_profiles(&mut self) {
+        for bytes_owned_identity in std::mem::take(&mut self.unlocked_profiles) {
+            self.publish_event(EngineEvent::OwnedIdentityUpdated { owned_identity: bytes_owned_identity });
+        }
+    }
+
+    /// Inactive owned identities keep their data but neither fetch nor listen to their messages, they listen again once
+    /// reactivated
+    pub async fn set_owned_identity_active(&mut self, bytes_owned_identity: &[u8], active: bool) -> Result<()> {
+        self.get_owned_identity(bytes_owned_identity).await?;
+        if !active {
+            self.stop_push_listener(bytes_owned_identity);
+        }
+        self.store.owned_identities().set_active(bytes_owned_identity, active).await?;
+        if active {
+            self.start_push_listener(bytes_owned_identity).await?;
+        }
+        self.publish_event(EngineEvent::OwnedIdentityUpdated { owned_identity: bytes_owned_identity.to_vec() });
+
+        Ok(())
+    }
+
+    /// Deletes an owned identity with its contacts, groups, discussions, messages and attachments
+    pub async fn delete_owned_identity(&mut self, bytes_owned_identity: &[u8]) -> Result<()> {
+        self.get_owned_identity(bytes_owned_identity).await?;
+        self.stop_push_listener(bytes_owned_identity);
+        let attachments = self.store.attachments().get_by_owned_identity(bytes_owned_identity).await?;
+
+        let mut transaction = self.store.begin().await?;
+        transaction.message_expirations().delete_by_owned_identity(bytes_owned_identity).await?;
+        transaction.message_edits().delete_by_owned_identity(bytes_owned_identity).await?;
+        transaction.message_mentions().delete_by_owned_identity(bytes_owned_identity).await?;
+        transaction.message_reactions().delete_by_owned_identity(bytes_owned_identity).await?;
+        transaction.pending_message_operations().delete_by_owned_identity(bytes_owned_identity).await?;
+        transaction.messages().delete_by_owned_identity(bytes_owned_identity).await?;
+        transaction.discussions().delete_by_owned_identity(bytes_owned_identity).await?;
+        transaction.groups().delete_by_owned_identity(bytes_owned_identity).await?;
+        transaction.contacts().delete_by_owned_identity(bytes_owned_identity).await?;
+        transaction.blocked_contacts().delete_by_owned_identity(bytes_owned_identity).await?;
+        transaction.contact_channels().delete_by_owned_identity(bytes_owned_identity).await?;
+        transaction.introductions().delete_by_owned_identity(bytes_owned_identity).await?;
+        transaction.trust_establishments().delete_by_owned_identity(bytes_owned_identity).await?;
+        transaction.inbox_messages().delete_by_owned_identity(bytes_owned_identity).await?;
+        transaction.attachments().delete_by_owned_identity(bytes_owned_identity).await?;
+        transaction.outbox_messages().delete_by_owned_identity(bytes_owned_identity).await?;
+        transaction.return_receipts().delete_by_owned_identity(bytes_owned_identity).await?;
+        transaction.owned_devices().delete_by_owned_identity(bytes_owned_identity).await?;
+        transaction.keycloak_servers().delete(bytes_owned_identity).await?;
+        transaction.owned_identities().delete(bytes_owned_identity).await?;
+        transaction.commit().await?;
+
+        for attachment in &attachments {
+            // The file may not be downloaded yet
+            let _ = tokio::fs::remove_file(attachment.get_file_path()).await;
+        }
+        self.unlocked_profiles.remove(bytes_owned_identity);
+        self.publish_event(EngineEvent::OwnedIdentityDeleted { owned_identity: bytes_owned_identity.to_vec() });
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unlock_password_hash_depends_on_salt_and_password() {
+        let hash = hash_unlock_password("secret", &[1; KDF_SALT_LENGTH]).unwrap();
+        assert_eq!(hash, hash_unlock_password("secret", &[1; KDF_SALT_LENGTH]).unwrap());
+        assert_ne!(hash, hash_unlock_password("secret", &[2; KDF_SALT_LENGTH]).unwrap());
+        assert_ne!(hash, hash_unlock_password("Secret", &[1; KDF_SALT_LENGTH]).unwrap());
+    }
+}
@@ -0,0 +1,149 @@
+//! Delivery and read receipts.
+//!
+//! Each outbound message carries a random nonce and key. Its recipient sends back return receipts made of the nonce
+//! and of a status encrypted with the key, so that the server can route them without learning what they report.
+
+use olvid_core::{crypto::{auth_encryption::{AuthEnc, AES256CTRHMACSHA256}, prng::PRNGHmacSHA256, prng::PRNG}, encoding::{BytesArray, Decoder, Encoder}, AES256CTRHMACSHA256Key, SymmetricKey};
+use tokio::sync::broadcast;
+
+use crate::{entities::{message::{MessageDirection, MessageStatus}, return_receipt::{OutboxReturnReceipt, ReturnReceiptStatus}}, events::{publish_event, EngineEvent}, messages::schedule_on_read, store::{ReturnReceiptRepository, Store}, Engine, EngineError, Result};
+
+const RETURN_RECEIPT_NONCE_LENGTH: usize = 16;
+const RETURN_RECEIPT_KEY_LENGTH: usize = 64;
+
+/// Returns a fresh nonce and key for the return receipts of an outbound message
+pub(crate) fn generate_return_receipt_secrets(prng: &mut PRNGHmacSHA256) -> Result<(Vec<u8>, Vec<u8>)> {
+    let nonce = prng.bytes(RETURN_RECEIPT_NONCE_LENGTH).map_err(|_| EngineError::PRNG)?;
+    let key = prng.bytes(RETURN_RECEIPT_KEY_LENGTH).map_err(|_| EngineError::PRNG)?;
+
+    Ok((nonce, key))
+}
+
+fn seal_status(raw_key: &[u8], status: ReturnReceiptStatus, prng: &mut PRNGHmacSHA256) -> Result<Vec<u8>> {
+    let key = AES256CTRHMACSHA256Key::init(raw_key).map_err(|_| EngineError::Technical)?;
+    let payload = vec![i64::from(status).encode()?].encode()?;
+
+    AES256CTRHMACSHA256::encrypt(&payload, &key, prng).map_err(|_| EngineError::Technical)
+}
+
+/// `None` when the payload was not encrypted with `raw_key`
+fn open_status(raw_key: &[u8], encrypted_payload: &[u8]) -> Option<ReturnReceiptStatus> {
+    let key = AES256CTRHMACSHA256Key::init(raw_key).ok()?;
+    let payload = AES256CTRHMACSHA256::decrypt(encrypted_payload, &key).ok()?;
+
+    let values = Vec::<BytesArray>::decode(&payload).ok()?;
+    let encoded_status = values.first()?;
+    i64::decode(encoded_status).ok()?.try_into().ok()
+}
+
+/// Queues a receipt for the sender of a message, the caller wakes up the outbox sender once committed
+pub(crate) async fn queue_return_receipt(return_receipts: &mut dyn ReturnReceiptRepository, owned_identity: &[u8], to_identity: &[u8], nonce: &[u8], raw_key: &[u8], status: ReturnReceiptStatus, prng: &mut PRNGHmacSHA256) -> Result<()> {
+    let encrypted_payload = seal_status(raw_key, status, prng)?;
+    return_receipts.insert(OutboxReturnReceipt::new(owned_identity, to_identity, nonce, encrypted_payload)).await?;
+
+    Ok(())
+}
+
+/// Moves an outbound message forward, a status it already reached or passed is ignored
+pub(crate) async fn advance_message_status(store: &dyn Store, events: &broadcast::Sender<EngineEvent>, message_id: i64, status: MessageStatus) -> Result<()> {
+    if store.messages().advance_outbound_status(message_id, status).await? {
+        publish_event(events, EngineEvent::MessageStatusChanged { message_id, status });
+    }
+
+    Ok(())
+}
+
+/// Applies a receipt received by an owned identity, receipts matching none of its messages are dropped
+pub(crate) async fn process_return_receipt(store: &dyn Store, events: &broadcast::Sender<EngineEvent>, bytes_owned_identity: &[u8], nonce: &[u8], encrypted_payload: &[u8]) -> Result<()> {
+    for message in store.messages().get_by_return_receipt_nonce(bytes_owned_identity, nonce).await? {
+        let Some(raw_key) = message.get_return_receipt_key() else { continue };
+        let Some(status) = open_status(raw_key, encrypted_payload) else { continue };
+
+        let message_status = match status {
+            ReturnReceiptStatus::Delivered => MessageStatus::Delivered,
+            ReturnReceiptStatus::Read => MessageStatus::Read,
+        };
+        advance_message_status(store, events, message.get_id().ok_or(EngineError::Technical)?, message_status).await?;
+    }
+
+    Ok(())
+}
+
+impl Engine {
+    /// Marks a received message as read, which starts its visibility duration if it is ephemeral.
+    ///
+    /// Its sender gets a read receipt when the discussion, or else the owned identity, allows it.
+    pub async fn mark_message_read(&self, message_id: i64) -> Result<()> {
+        let message = self.store.messages().get_by_id(message_id).await?
+            .filter(|message| matches!(message.get_direction(), Ok(MessageDirection::Inbound)))
+            .ok_or(EngineError::UnknownMessage)?;
+        let discussion = self.store.discussions().get_by_id(message.get_discussion_id()).await?.ok_or(EngineError::Technical)?;
+        let send_read_receipt = match discussion.get_pref_send_read_receipt() {
+            Some(send_read_receipt) => send_read_receipt,
+            None => self.get_owned_identity(discussion.get_owned_identity()).await?.get_pref_send_read_receipt(),
+        };
+        let mut prng = Self::get_default_hmac_prng()?;
+
+        let mut transaction = self.store.begin().await?;
+        if !transaction.messages().mark_read(message_id).await? {
+            return Ok(());
+        }
+        let is_expiration_scheduled = schedule_on_read(&mut *transaction, &message).await?;
+        let read_receipt = match (send_read_receipt, message.get_return_receipt_nonce(), message.get_return_receipt_key()) {
+            (true, Some(nonce), Some(raw_key)) => Some((nonce, raw_key)),
+            _ => None,
+        };
+        if let Some((nonce, raw_key)) = read_receipt {
+            queue_return_receipt(&mut *transaction.return_receipts(), discussion.get_owned_identity(), message.get_sender_identity(), nonce, raw_key, ReturnReceiptStatus::Read, &mut prng).await?;
+        }
+        transaction.commit().await?;
+
+        self.publish_event(EngineEvent::MessageStatusChanged { message_id, status: MessageStatus::Read });
+        if is_expiration_scheduled {
+            self.expiration_wake_up.notify_one();
+        }
+        if read_receipt.is_some() {
+            self.outbox_wake_up.notify_one();
+        }
+
+        Ok(())
+    }
+
+    /// Whether read receipts are sent for the discussions without their own setting
+    pub async fn set_send_read_receipts(&self, bytes_owned_identity: &[u8], send_read_receipts: bool) -> Result<()> {
+        self.get_owned_identity(bytes_owned_identity).await?;
+        self.store.owned_identities().set_pref_send_read_receipt(bytes_owned_identity, send_read_receipts).await?;
+        self.publish_event(EngineEvent::OwnedIdentityUpdated { owned_identity: bytes_owned_identity.to_vec() });
+
+        Ok(())
+    }
+
+    /// Overrides the read receipt setting of the owned identity for one discussion, `None` follows it again
+    pub async fn set_discussion_send_read_receipts(&self, discussion_id: i64, send_read_receipts: Option<bool>) -> Result<()> {
+        self.store.discussions().get_by_id(discussion_id).await?.ok_or(EngineError::UnknownDiscussion)?;
+        self.store.discussions().set_pref_send_read_receipt(discussion_id, send_read_receipts).await?;
+        self.publish_event(EngineEvent::DiscussionSettingsUpdated { discussion_id });
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use olvid_core::crypto::prng::{PRNGHmacSHA256, PRNG};
+
+    use crate::entities::return_receipt::ReturnReceiptStatus;
+
+    use super::{generate_return_receipt_secrets, open_status, seal_status};
+
+    #[test]
+    fn status_opens_with_message_key_only() {
+        let mut prng = PRNGHmacSHA256::init(&[7; 32]).unwrap();
+        let (_, key) = generate_return_receipt_secrets(&mut prng).unwrap();
+        let (_, other_key) = generate_return_receipt_secrets(&mut prng).unwrap();
+
+        let encrypted_payload = seal_status(&key, ReturnReceiptStatus::Read, &mut prng).unwrap();
+        assert_eq!(open_status(&key, &encrypted_payload), Some(ReturnReceiptStatus::Read));
+        assert_eq!(open_status(&other_key, &encrypted_payload), None);
+    }
+}
@@ -0,0 +1,108 @@
+//! Full-text search of the messages.
+//!
+//! Bodies and attachment file names are cut in lowercase words, which the store keeps in an FTS5 index in sync with
+//! the messages. A message matches when it contains every word of the query, the hits give the byte ranges of those
+//! words so that they can be highlighted.
+
+use std::ops::Range;
+
+use bon::Builder;
+
+use crate::{entities::message::Message, messages, Engine, Result};
+
+#[derive(Builder, Debug, Clone, Default)]
+pub struct MessageSearchQuery {
+    #[builder(into)]
+    pub text: String,
+    pub discussion_id: Option<i64>,
+    pub sender_identity: Option<Vec<u8>>,
+    /// Inclusive, compared to the time the server received the message or the time it was sent for outbound ones
+    pub from_timestamp: Option<i64>,
+    /// Exclusive
+    pub to_timestamp: Option<i64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MessageSearchHit {
+    pub message: Message,
+    /// Byte ranges of the words of the query in the body
+    pub body_highlights: Vec<Range<usize>>,
+    /// File names of the attachments containing words of the query, with the byte ranges of these words
+    pub file_name_highlights: Vec<(String, Vec<Range<usize>>)>,
+}
+
+/// Lowercase words of a text with their byte range, a word being a run of alphanumeric characters
+fn words(text: &str) -> Vec<(Range<usize>, String)> {
+    let mut words = vec![];
+    let mut start = None;
+    for (index, character) in text.char_indices().chain([(text.len(), ' ')]) {
+        match (character.is_alphanumeric(), start) {
+            (true, None) => start = Some(index),
+            (false, Some(word_start)) => {
+                words.push((word_start..index, text[word_start..index].to_lowercase()));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+
+    words
+}
+
+/// Distinct words of a text, the terms indexed and searched
+pub(crate) fn terms(text: &str) -> Vec<String> {
+    let mut terms: Vec<String> = words(text).into_iter().map(|(_, word)| word).collect();
+    terms.sort();
+    terms.dedup();
+
+    terms
+}
+
+/// Whether every term is a word of one of the texts, for stores without an index
+pub(crate) fn contains_all(texts: &[&str], terms: &[String]) -> bool {
+    let words: Vec<String> = texts.iter().flat_map(|text| self::terms(text)).collect();
+    terms.iter().all(|term| words.contains(term))
+}
+
+fn highlights(text: &str, terms: &[String]) -> Vec<Range<usize>> {
+    words(text).into_iter().filter(|(_, word)| terms.contains(word)).map(|(range, _)| range).collect()
+}
+
+impl Engine {
+    /// Up to `limit` messages of an owned identity matching a query, most recent first.
+    ///
+    /// Every word of the query must appear in the body or in the file name of an attachment, as a whole word and
+    /// regardless of case. Older hits are obtained by passing the id of the last message returned as `before_message_id`.
+    pub async fn search_messages(&self, bytes_owned_identity: &[u8], query: &MessageSearchQuery, before_message_id: Option<i64>, limit: i64) -> Result<Vec<MessageSearchHit>> {
+        let terms = terms(&query.text);
+        if terms.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let mut hits = vec![];
+        for message in self.store.messages().search(bytes_owned_identity, &terms, query, before_message_id, limit).await? {
+            let file_name_highlights = messages::get_message_attachments(&*self.store, &message).await?.into_iter()
+                .map(|attachment| (attachment.get_file_name().to_owned(), highlights(attachment.get_file_name(), &terms)))
+                .filter(|(_, highlights)| !highlights.is_empty())
+                .collect();
+            let body_highlights = message.get_body().map(|body| highlights(body, &terms)).unwrap_or_default();
+
+            hits.push(MessageSearchHit { message, body_highlights, file_name_highlights });
+        }
+
+        Ok(hits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{contains_all, highlights, terms};
+
+    #[test]
+    fn words_are_lowercase_runs_of_alphanumeric_characters() {
+        assert_eq!(terms("Café, CAFÉ and l'été 2024!"), ["2024", "and", "café", "l", "été"]);
+        assert_eq!(highlights("Café, CAFÉ and l'été", &["café".to_owned()]), [0..5, 7..12]);
+        assert!(contains_all(&["Holiday photos", "beach.jpg"], &terms("photos BEACH")));
+        assert!(!contains_all(&["Holiday photos"], &terms("photo")));
+    }
+}
@@ -0,0 +1,95 @@
+use olvid_core::encoding::{BytesArray, Decoder};
+
+use crate::{EngineError, Result};
+
+pub mod client;
+
+pub use client::ServerClient;
+
+pub const SERVER_STATUS_OK: u8 = 0x00;
+pub const SERVER_STATUS_INVALID_SESSION: u8 = 0x04;
+pub const SERVER_STATUS_IDENTITY_IS_NOT_ACTIVE: u8 = 0x08;
+pub const SERVER_STATUS_DELETED_FROM_SERVER: u8 = 0x09;
+pub const SERVER_STATUS_INVALID_API_KEY: u8 = 0x0b;
+pub const SERVER_STATUS_PAYLOAD_TOO_LARGE: u8 = 0x10;
+pub const SERVER_STATUS_GROUP_UID_ALREADY_USED: u8 = 0x13;
+pub const SERVER_STATUS_GROUP_BLOB_OUTDATED: u8 = 0x14;
+pub const SERVER_STATUS_GENERAL_ERROR: u8 = 0xff;
+
+pub fn check_server_status(status: u8) -> Result<()> {
+    match status {
+        SERVER_STATUS_OK => Ok(()),
+        SERVER_STATUS_INVALID_SESSION => Err(EngineError::InvalidSession),
+        SERVER_STATUS_IDENTITY_IS_NOT_ACTIVE => Err(EngineError::IdentityNotActive),
+        SERVER_STATUS_DELETED_FROM_SERVER => Err(EngineError::DeletedFromServer),
+        SERVER_STATUS_INVALID_API_KEY => Err(EngineError::InvalidApiKey),
+        SERVER_STATUS_PAYLOAD_TOO_LARGE => Err(EngineError::PayloadTooLarge),
+        SERVER_STATUS_GROUP_UID_ALREADY_USED => Err(EngineError::GroupUidAlreadyUsed),
+        SERVER_STATUS_GROUP_BLOB_OUTDATED => Err(EngineError::GroupBlobOutdated),
+        SERVER_STATUS_GENERAL_ERROR => Err(EngineError::ServerGeneralError),
+        unknown_status => Err(EngineError::UnknownServerStatus(unknown_status)),
+    }
+}
+
+/// Decodes the value at `index` of a server response, any failure means the server answered garbage
+pub(crate) fn decode_response_value<T: Decoder>(values: &[BytesArray], index: usize) -> Result<T> {
+    let encoded_value = values.get(index).ok_or(EngineError::MalformedServerResponse)?;
+    T::decode(encoded_value).map_err(|_| EngineError::MalformedServerResponse)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerChallenge {
+    pub challenge: Vec<u8>,
+    pub server_nonce: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerSession {
+    pub token: Vec<u8>,
+    pub api_key_status: i64,
+    pub api_key_permissions: i64,
+    pub api_key_expiration_timestamp: Option<i64>,
+}
+
+/// Status of the API key registered for an identity, one of the `API_KEY_STATUS_*` values
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ApiKeyStatus {
+    pub status: i64,
+    pub permissions: i64,
+    pub expiration_timestamp: Option<i64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutboundServerMessage {
+    pub to_identity: Vec<u8>,
+    /// Devices of `to_identity` that should receive the message, all of them when empty
+    pub device_uids: Vec<Vec<u8>>,
+    pub wrapped_key: Vec<u8>,
+    pub encrypted_content: Vec<u8>,
+    pub is_application_message: bool,
+    /// Number of chunks of each attachment, the server answers one upload URL per chunk
+    pub attachment_chunk_counts: Vec<i64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UploadedMessage {
+    pub message_uid: Vec<u8>,
+    /// Chunk upload URLs of each attachment
+    pub attachment_upload_urls: Vec<Vec<String>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DownloadedMessage {
+    pub message_uid: Vec<u8>,
+    pub server_timestamp: i64,
+    pub wrapped_key: Vec<u8>,
+    pub encrypted_content: Vec<u8>,
+    pub attachment_count: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeycloakData {
+    pub server_url: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+}
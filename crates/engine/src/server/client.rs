@@ -0,0 +1,502 @@
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+use olvid_core::{crypto::prng::PRNG, cryptographic_identity::OwnedCryptographicIdentity, encoding::{BytesArray, Decoder, Encoder}};
+use tokio::sync::broadcast;
+use url::Url;
+
+use crate::{EngineError, Result};
+
+use super::{check_server_status, decode_response_value, ApiKeyStatus, DownloadedMessage, KeycloakData, OutboundServerMessage, ServerChallenge, ServerSession, UploadedMessage};
+
+const SERVER_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+const CHALLENGE_NONCE_LENGTH: usize = 16;
+const NEW_SESSION_CHANNEL_CAPACITY: usize = 64;
+
+/// Typed client of the Olvid server API.
+///
+/// Every request body is an encoded list, every response starts with a status byte followed by an encoded list.
+/// Session tokens are cached per owned identity and renewed transparently when the server reports them invalid.
+/// Every new session is sent to the subscribers with its identity, as it carries the status of the API key.
+pub struct ServerClient {
+    http_client: reqwest::Client,
+    server_url: Url,
+    sessions: Mutex<HashMap<Vec<u8>, ServerSession>>,
+    new_sessions: broadcast::Sender<(Vec<u8>, ServerSession)>,
+}
+
+impl ServerClient {
+    pub fn new(server_url: &str) -> Result<Self> {
+        let http_client = reqwest::Client::builder()
+            .timeout(SERVER_REQUEST_TIMEOUT)
+            .build()?;
+        // Methods are joined to the URL, which would replace its last path segment without a trailing slash
+        let mut server_url = Url::parse(server_url)?;
+        if !server_url.path().ends_with('/') {
+            server_url.set_path(&format!("{}/", server_url.path()));
+        }
+
+        let (new_sessions, _) = broadcast::channel(NEW_SESSION_CHANNEL_CAPACITY);
+        Ok(Self {
+            http_client,
+            server_url,
+            sessions: Mutex::new(HashMap::new()),
+            new_sessions,
+        })
+    }
+
+    pub fn get_server_url(&self) -> &Url {
+        &self.server_url
+    }
+
+    /// WebSocket endpoint of the push notifications, next to the API
+    pub fn get_push_url(&self) -> Result<Url> {
+        let mut push_url = self.server_url.join("ws")?;
+        let scheme = match push_url.scheme() {
+            "https" => "wss",
+            _ => "ws",
+        };
+        push_url.set_scheme(scheme).map_err(|_| EngineError::Technical)?;
+
+        Ok(push_url)
+    }
+
+    async fn post(&self, method: &str, inputs: Vec<BytesArray>) -> Result<Vec<BytesArray>> {
+        let url = self.server_url.join(method)?;
+        let body = inputs.encode()?;
+
+        let response = self.http_client
+            .post(url)
+            .header(reqwest::header::CONTENT_TYPE, "application/bytes")
+            .body(body)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        let response_bytes = response.bytes().await?;
+        let (status, encoded_outputs) = response_bytes.split_first().ok_or(EngineError::MalformedServerResponse)?;
+        check_server_status(*status)?;
+
+        if encoded_outputs.is_empty() {
+            return Ok(vec![]);
+        }
+
+        Vec::<BytesArray>::decode(encoded_outputs).map_err(|_| EngineError::MalformedServerResponse)
+    }
+
+    /// Posts a method requiring a session token, the token is inserted right after the identity
+    async fn post_with_session(&self, owned_identity: &OwnedCryptographicIdentity, prng: &mut (dyn PRNG + Send), method: &str, inputs: Vec<BytesArray>) -> Result<Vec<BytesArray>> {
+        let identity = owned_identity.get_crypto_identity().get_identity();
+
+        let session = self.get_session(owned_identity, prng).await?;
+        match self.post(method, Self::session_inputs(&identity, &session.token, &inputs)?).await {
+            Err(EngineError::InvalidSession) => {
+                self.invalidate_session(&identity);
+                let session = self.get_session(owned_identity, prng).await?;
+                self.post(method, Self::session_inputs(&identity, &session.token, &inputs)?).await
+            }
+            result => result,
+        }
+    }
+
+    fn session_inputs(identity: &[u8], token: &[u8], inputs: &[BytesArray]) -> Result<Vec<BytesArray>> {
+        let mut session_inputs = vec![identity.to_vec().encode()?, token.to_vec().encode()?];
+        session_inputs.extend_from_slice(inputs);
+        Ok(session_inputs)
+    }
+
+    pub async fn request_challenge(&self, identity: &[u8], nonce: &[u8]) -> Result<ServerChallenge> {
+        let outputs = self.post("requestChallenge", vec![
+            identity.to_vec().encode()?,
+            nonce.to_vec().encode()?,
+        ]).await?;
+
+        Ok(ServerChallenge {
+            challenge: decode_response_value(&outputs, 0)?,
+            server_nonce: decode_response_value(&outputs, 1)?,
+        })
+    }
+
+    pub async fn get_token(&self, identity: &[u8], response: &[u8], nonce: &[u8]) -> Result<ServerSession> {
+        let outputs = self.post("getToken", vec![
+            identity.to_vec().encode()?,
+            response.to_vec().encode()?,
+            nonce.to_vec().encode()?,
+        ]).await?;
+
+        let api_key_expiration_timestamp: i64 = decode_response_value(&outputs, 3)?;
+        Ok(ServerSession {
+            token: decode_response_value(&outputs, 0)?,
+            api_key_status: decode_response_value(&outputs, 1)?,
+            api_key_permissions: decode_response_value(&outputs, 2)?,
+            api_key_expiration_timestamp: (api_key_expiration_timestamp > 0).then_some(api_key_expiration_timestamp),
+        })
+    }
+
+    /// Returns the cached session of the identity, authenticating with the server when there is none
+    pub async fn get_session(&self, owned_identity: &OwnedCryptographicIdentity, prng: &mut (dyn PRNG + Send)) -> Result<ServerSession> {
+        let identity = owned_identity.get_crypto_identity().get_identity();
+        if let Some(session) = self.get_cached_session(&identity) {
+            return Ok(session);
+        }
+
+        let nonce = prng.bytes(CHALLENGE_NONCE_LENGTH).map_err(|_| EngineError::PRNG)?;
+        let server_challenge = self.request_challenge(&identity, &nonce).await?;
+
+        let mut challenge = server_challenge.challenge.clone();
+        challenge.extend_from_slice(&nonce);
+        challenge.extend_from_slice(&server_challenge.server_nonce);
+        let response = owned_identity.solve_challenge(&challenge, prng).map_err(|_| EngineError::Technical)?;
+
+        let session = self.get_token(&identity, &response, &nonce).await?;
+        self.sessions.lock().map_err(|_| EngineError::Technical)?.insert(identity.clone(), session.clone());
+        // Nobody listening is not an error
+        let _ = self.new_sessions.send((identity, session.clone()));
+
+        Ok(session)
+    }
+
+    /// Identity and session of every session opened from now on
+    pub fn subscribe_to_new_sessions(&self) -> broadcast::Receiver<(Vec<u8>, ServerSession)> {
+        self.new_sessions.subscribe()
+    }
+
+    pub fn get_cached_session(&self, identity: &[u8]) -> Option<ServerSession> {
+        self.sessions.lock().ok()?.get(identity).cloned()
+    }
+
+    pub fn invalidate_session(&self, identity: &[u8]) {
+        if let Ok(mut sessions) = self.sessions.lock() {
+            sessions.remove(identity);
+        }
+    }
+
+    /// Binds an API key to the identity, the status of the key comes with the next session
+    pub async fn register_api_key(&self, owned_identity: &OwnedCryptographicIdentity, api_key: &str, prng: &mut (dyn PRNG + Send)) -> Result<()> {
+        self.post_with_session(owned_identity, prng, "registerApiKey", vec![
+            api_key.to_owned().encode()?,
+        ]).await?;
+
+        Ok(())
+    }
+
+    /// Status of an API key, whether or not it is registered, without authenticating
+    pub async fn query_api_key_status(&self, identity: &[u8], api_key: &str) -> Result<ApiKeyStatus> {
+        let outputs = self.post("queryApiKeyStatus", vec![
+            identity.to_vec().encode()?,
+            api_key.to_owned().encode()?,
+        ]).await?;
+
+        let expiration_timestamp: i64 = decode_response_value(&outputs, 2)?;
+        Ok(ApiKeyStatus {
+            status: decode_response_value(&outputs, 0)?,
+            permissions: decode_response_value(&outputs, 1)?,
+            expiration_timestamp: (expiration_timestamp > 0).then_some(expiration_timestamp),
+        })
+    }
+
+    /// The server stores the message once per `upload_nonce`, uploading it again with the same nonce answers the same uids
+    pub async fn upload_message_and_get_uids(&self, message: &OutboundServerMessage, upload_nonce: Option<&[u8]>) -> Result<UploadedMessage> {
+        let device_uids = message.device_uids.iter()
+            .map(|device_uid| device_uid.encode())
+            .collect::<std::result::Result<Vec<BytesArray>, _>>()?;
+
+        let attachment_chunk_counts = message.attachment_chunk_counts.iter()
+            .map(|chunk_count| chunk_count.encode())
+            .collect::<std::result::Result<Vec<BytesArray>, _>>()?;
+
+        let mut inputs = vec![
+            message.to_identity.encode()?,
+            device_uids.encode()?,
+            message.wrapped_key.encode()?,
+            message.encrypted_content.encode()?,
+            message.is_application_message.encode()?,
+            attachment_chunk_counts.encode()?,
+        ];
+        if let Some(upload_nonce) = upload_nonce {
+            inputs.push(upload_nonce.to_vec().encode()?);
+        }
+        let outputs = self.post("uploadMessageAndGetUids", inputs).await?;
+
+        let encoded_attachment_upload_urls: Vec<BytesArray> = decode_response_value(&outputs, 1)?;
+        let attachment_upload_urls = encoded_attachment_upload_urls.iter()
+            .map(|encoded_urls| Self::decode_urls(encoded_urls))
+            .collect::<Result<Vec<Vec<String>>>>()?;
+
+        Ok(UploadedMessage { message_uid: decode_response_value(&outputs, 0)?, attachment_upload_urls })
+    }
+
+    /// Return receipts need no session, the server only routes them with their nonce
+    pub async fn upload_return_receipt(&self, to_identity: &[u8], device_uids: &[Vec<u8>], nonce: &[u8], encrypted_payload: &[u8]) -> Result<()> {
+        let device_uids = device_uids.iter()
+            .map(|device_uid| device_uid.encode())
+            .collect::<std::result::Result<Vec<BytesArray>, _>>()?;
+
+        self.post("uploadReturnReceipt", vec![
+            to_identity.to_vec().encode()?,
+            device_uids.encode()?,
+            nonce.to_vec().encode()?,
+            encrypted_payload.to_vec().encode()?,
+        ]).await?;
+
+        Ok(())
+    }
+
+    fn decode_urls(encoded_urls: &[u8]) -> Result<Vec<String>> {
+        Vec::<BytesArray>::decode(encoded_urls).map_err(|_| EngineError::MalformedServerResponse)?
+            .iter()
+            .map(|encoded_url| String::decode(encoded_url).map_err(|_| EngineError::MalformedServerResponse))
+            .collect()
+    }
+
+    pub async fn get_attachment_chunk_download_urls(&self, owned_identity: &OwnedCryptographicIdentity, device_uid: &[u8], message_uid: &[u8], attachment_number: i64, prng: &mut (dyn PRNG + Send)) -> Result<Vec<String>> {
+        let outputs = self.post_with_session(owned_identity, prng, "getAttachmentChunkDownloadUrls", vec![
+            device_uid.to_vec().encode()?,
+            message_uid.to_vec().encode()?,
+            attachment_number.encode()?,
+        ]).await?;
+
+        let encoded_urls: BytesArray = outputs.first().cloned().ok_or(EngineError::MalformedServerResponse)?;
+        Self::decode_urls(&encoded_urls)
+    }
+
+    /// Chunk URLs are signed by the server, they are used without session
+    pub async fn upload_attachment_chunk(&self, url: &str, encrypted_chunk: Vec<u8>) -> Result<()> {
+        self.http_client.put(url).body(encrypted_chunk).send().await?.error_for_status()?;
+        Ok(())
+    }
+
+    pub async fn download_attachment_chunk(&self, url: &str) -> Result<Vec<u8>> {
+        let response = self.http_client.get(url).send().await?.error_for_status()?;
+        Ok(response.bytes().await?.to_vec())
+    }
+
+    pub async fn download_messages_and_list_attachments(&self, owned_identity: &OwnedCryptographicIdentity, device_uid: &[u8], prng: &mut (dyn PRNG + Send)) -> Result<Vec<DownloadedMessage>> {
+        let outputs = self.post_with_session(owned_identity, prng, "downloadMessagesAndListAttachments", vec![
+            device_uid.to_vec().encode()?,
+        ]).await?;
+
+        Self::decode_downloaded_messages(&outputs)
+    }
+
+    /// Downloads a single message, typically the one announced by a push notification
+    pub async fn download_message_and_list_attachments(&self, owned_identity: &OwnedCryptographicIdentity, device_uid: &[u8], message_uid: &[u8], prng: &mut (dyn PRNG + Send)) -> Result<Option<DownloadedMessage>> {
+        let outputs = self.post_with_session(owned_identity, prng, "downloadMessageAndListAttachments", vec![
+            device_uid.to_vec().encode()?,
+            message_uid.to_vec().encode()?,
+        ]).await?;
+
+        Ok(Self::decode_downloaded_messages(&outputs)?.into_iter().next())
+    }
+
+    fn decode_downloaded_messages(outputs: &[BytesArray]) -> Result<Vec<DownloadedMessage>> {
+        let encoded_messages: Vec<BytesArray> = decode_response_value(outputs, 0)?;
+        encoded_messages.iter()
+            .map(|encoded_message| {
+                let values = Vec::<BytesArray>::decode(encoded_message).map_err(|_| EngineError::MalformedServerResponse)?;
+                Ok(DownloadedMessage {
+                    message_uid: decode_response_value(&values, 0)?,
+                    server_timestamp: decode_response_value(&values, 1)?,
+                    wrapped_key: decode_response_value(&values, 2)?,
+                    encrypted_content: decode_response_value(&values, 3)?,
+                    attachment_count: decode_response_value(&values, 4)?,
+                })
+            })
+            .collect()
+    }
+
+    pub async fn delete_message_and_attachments(&self, owned_identity: &OwnedCryptographicIdentity, device_uid: &[u8], message_uid: &[u8], prng: &mut (dyn PRNG + Send)) -> Result<()> {
+        self.post_with_session(owned_identity, prng, "deleteMessageAndAttachments", vec![
+            device_uid.to_vec().encode()?,
+            message_uid.to_vec().encode()?,
+        ]).await?;
+
+        Ok(())
+    }
+
+    pub async fn get_keycloak_data(&self, owned_identity: &OwnedCryptographicIdentity, prng: &mut (dyn PRNG + Send)) -> Result<KeycloakData> {
+        let outputs = self.post_with_session(owned_identity, prng, "getKeycloakData", vec![]).await?;
+
+        let client_secret: String = decode_response_value(&outputs, 2)?;
+        Ok(KeycloakData {
+            server_url: decode_response_value(&outputs, 0)?,
+            client_id: decode_response_value(&outputs, 1)?,
+            client_secret: (!client_secret.is_empty()).then_some(client_secret),
+        })
+    }
+
+    /// Stores the first version of a group blob, fails when the group uid is taken
+    pub async fn create_group_blob(&self, owned_identity: &OwnedCryptographicIdentity, group_uid: &[u8], encrypted_blob: &[u8], prng: &mut (dyn PRNG + Send)) -> Result<()> {
+        self.post_with_session(owned_identity, prng, "createGroupBlob", vec![
+            group_uid.to_vec().encode()?,
+            encrypted_blob.to_vec().encode()?,
+        ]).await?;
+
+        Ok(())
+    }
+
+    /// Returns the encrypted blob and its version
+    pub async fn get_group_blob(&self, owned_identity: &OwnedCryptographicIdentity, group_uid: &[u8], prng: &mut (dyn PRNG + Send)) -> Result<(Vec<u8>, i64)> {
+        let outputs = self.post_with_session(owned_identity, prng, "getGroupBlob", vec![group_uid.to_vec().encode()?]).await?;
+        Ok((decode_response_value(&outputs, 0)?, decode_response_value(&outputs, 1)?))
+    }
+
+    /// `version` must follow the stored one, otherwise someone else updated the blob first
+    pub async fn update_group_blob(&self, owned_identity: &OwnedCryptographicIdentity, group_uid: &[u8], encrypted_blob: &[u8], version: i64, prng: &mut (dyn PRNG + Send)) -> Result<()> {
+        self.post_with_session(owned_identity, prng, "updateGroupBlob", vec![
+            group_uid.to_vec().encode()?,
+            encrypted_blob.to_vec().encode()?,
+            version.encode()?,
+        ]).await?;
+
+        Ok(())
+    }
+
+    /// Opens a profile transfer session on the source device, returns the session number the target device joins with
+    pub async fn transfer_open_session(&self, owned_identity: &OwnedCryptographicIdentity, prng: &mut (dyn PRNG + Send)) -> Result<i64> {
+        let outputs = self.post_with_session(owned_identity, prng, "transferOpenSession", vec![]).await?;
+        decode_response_value(&outputs, 0)
+    }
+
+    /// Relays a payload to the other device of a profile transfer session
+    pub async fn transfer_relay(&self, session_number: i64, to_source: bool, payload: &[u8]) -> Result<()> {
+        self.post("transferRelay", vec![session_number.encode()?, to_source.encode()?, payload.to_vec().encode()?]).await?;
+
+        Ok(())
+    }
+
+    /// Payloads relayed to one of the devices of a profile transfer session since the previous fetch
+    pub async fn transfer_fetch(&self, session_number: i64, to_source: bool) -> Result<Vec<Vec<u8>>> {
+        let outputs = self.post("transferFetch", vec![session_number.encode()?, to_source.encode()?]).await?;
+        let encoded_payloads: Vec<BytesArray> = decode_response_value(&outputs, 0)?;
+        encoded_payloads.iter().map(|encoded_payload| BytesArray::decode(encoded_payload).map_err(|_| EngineError::MalformedServerResponse)).collect()
+    }
+
+    pub async fn transfer_close_session(&self, owned_identity: &OwnedCryptographicIdentity, session_number: i64, prng: &mut (dyn PRNG + Send)) -> Result<()> {
+        self.post_with_session(owned_identity, prng, "transferCloseSession", vec![session_number.encode()?]).await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use olvid_core::{cryptographic_identity::OwnedCryptographicIdentity, encoding::{BytesArray, Encoder}};
+    use wiremock::{matchers::{method, path}, Mock, MockServer, ResponseTemplate};
+
+    use crate::{server::{OutboundServerMessage, SERVER_STATUS_INVALID_SESSION, SERVER_STATUS_OK, SERVER_STATUS_PAYLOAD_TOO_LARGE}, Engine, EngineError};
+
+    use super::ServerClient;
+
+    fn server_response(status: u8, outputs: Vec<BytesArray>) -> ResponseTemplate {
+        let mut body = vec![status];
+        body.append(&mut outputs.encode().unwrap());
+        ResponseTemplate::new(200).set_body_bytes(body)
+    }
+
+    async fn mount_authentication(mock_server: &MockServer, expected_authentications: u64) {
+        Mock::given(method("POST"))
+            .and(path("/requestChallenge"))
+            .respond_with(server_response(SERVER_STATUS_OK, vec![vec![1; 32].encode().unwrap(), vec![2; 16].encode().unwrap()]))
+            .expect(expected_authentications)
+            .mount(mock_server)
+            .await;
+
+        Mock::given(method("POST"))
+            .and(path("/getToken"))
+            .respond_with(server_response(SERVER_STATUS_OK, vec![vec![3; 32].encode().unwrap(), 1i64.encode().unwrap(), 0i64.encode().unwrap(), 0i64.encode().unwrap()]))
+            .expect(expected_authentications)
+            .mount(mock_server)
+            .await;
+    }
+
+    fn generate_owned_identity(server_url: &str) -> OwnedCryptographicIdentity {
+        OwnedCryptographicIdentity::generate_owned_cryptographic_identity(server_url, Engine::get_default_prng().unwrap().as_mut()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn session_token_is_cached() {
+        let mock_server = MockServer::start().await;
+        mount_authentication(&mock_server, 1).await;
+
+        Mock::given(method("POST"))
+            .and(path("/downloadMessagesAndListAttachments"))
+            .respond_with(server_response(SERVER_STATUS_OK, vec![Vec::<BytesArray>::new().encode().unwrap()]))
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+
+        let client = ServerClient::new(&mock_server.uri()).unwrap();
+        let owned_identity = generate_owned_identity(&mock_server.uri());
+        let mut prng = Engine::get_default_prng().unwrap();
+
+        for _ in 0..2 {
+            let messages = client.download_messages_and_list_attachments(&owned_identity, &[0; 32], prng.as_mut()).await.unwrap();
+            assert!(messages.is_empty());
+        }
+
+        let session = client.get_cached_session(&owned_identity.get_crypto_identity().get_identity()).unwrap();
+        assert_eq!(session.token, vec![3; 32]);
+        assert_eq!(session.api_key_expiration_timestamp, None);
+    }
+
+    #[test]
+    fn methods_are_posted_under_the_base_path() {
+        let client = ServerClient::new("https://server.olvid.io/api").unwrap();
+        assert_eq!(client.get_server_url().join("getToken").unwrap().as_str(), "https://server.olvid.io/api/getToken");
+        assert_eq!(client.get_push_url().unwrap().as_str(), "wss://server.olvid.io/api/ws");
+
+        let client = ServerClient::new("https://server.olvid.io").unwrap();
+        assert_eq!(client.get_server_url().join("getToken").unwrap().as_str(), "https://server.olvid.io/getToken");
+    }
+
+    #[tokio::test]
+    async fn invalid_session_is_refreshed() {
+        let mock_server = MockServer::start().await;
+        mount_authentication(&mock_server, 2).await;
+
+        Mock::given(method("POST"))
+            .and(path("/deleteMessageAndAttachments"))
+            .respond_with(server_response(SERVER_STATUS_INVALID_SESSION, vec![]))
+            .up_to_n_times(1)
+            .with_priority(1)
+            .mount(&mock_server)
+            .await;
+
+        Mock::given(method("POST"))
+            .and(path("/deleteMessageAndAttachments"))
+            .respond_with(server_response(SERVER_STATUS_OK, vec![]))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let client = ServerClient::new(&mock_server.uri()).unwrap();
+        let owned_identity = generate_owned_identity(&mock_server.uri());
+        let mut prng = Engine::get_default_prng().unwrap();
+
+        client.delete_message_and_attachments(&owned_identity, &[0; 32], &[1; 16], prng.as_mut()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn server_status_is_mapped_to_engine_error() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/uploadMessageAndGetUids"))
+            .respond_with(server_response(SERVER_STATUS_PAYLOAD_TOO_LARGE, vec![]))
+            .mount(&mock_server)
+            .await;
+
+        let client = ServerClient::new(&mock_server.uri()).unwrap();
+        let message = OutboundServerMessage {
+            to_identity: vec![4; 32],
+            device_uids: vec![],
+            wrapped_key: vec![5; 32],
+            encrypted_content: vec![6; 64],
+            is_application_message: true,
+            attachment_chunk_counts: vec![],
+        };
+
+        let result = client.upload_message_and_get_uids(&message, None).await;
+        assert!(matches!(result, Err(EngineError::PayloadTooLarge)));
+    }
+}
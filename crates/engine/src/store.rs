@@ -0,0 +1,432 @@
+//! Persistence of the engine, one repository per entity.
+//!
+//! `SqliteStore` keeps everything in the SQLite database of the engine, `MemoryStore` keeps it in memory and is meant
+//! for tests and for embedding the engine where no file can be written. Writes that must be applied together go
+//! through a `StoreTransaction`, nothing is applied when it is dropped without being committed.
+//!
+//! `SqliteStore` keeps the full-text index of the messages in sync with their bodies and attachments, `MemoryStore`
+//! scans the messages instead.
+
+use async_trait::async_trait;
+use uuid::Uuid;
+
+use crate::{
+    entities::{
+        attachment::{Attachment, AttachmentStatus},
+        backup_key::BackupKey,
+        blocked_contact::BlockedContact,
+        contact::{Contact, ContactTrustLevel},
+        contact_channel::ContactChannel,
+        database_key::DatabaseKey,
+        discussion::{Discussion, Ephemerality},
+        group::{Group, GroupMember, GroupStatus},
+        identity::{DisplayNameFormat, JsonIdentityDetails, OwnedIdentity},
+        inbox_message::InboxMessage,
+        introduction::{Introduction, IntroductionStatus},
+        keycloak_server::KeycloakServer,
+        message::{Message, MessageReference, MessageStatus},
+        message_edit::MessageEdit,
+        message_expiration::MessageExpiration,
+        message_mention::MessageMention,
+        message_reaction::MessageReaction,
+        outbox_message::{DeliveryState, OutboxMessage},
+        owned_device::OwnedDevice,
+        pending_message_operation::PendingMessageOperation,
+        return_receipt::OutboxReturnReceipt,
+        trust_establishment::{TrustEstablishment, TrustEstablishmentStatus},
+    },
+    search::MessageSearchQuery,
+    server::{ApiKeyStatus, DownloadedMessage},
+    Result,
+};
+
+pub mod encryption;
+pub mod memory;
+pub mod sqlite;
+
+pub use encryption::DatabaseSecret;
+pub use memory::MemoryStore;
+pub use sqlite::SqliteStore;
+
+#[async_trait]
+pub trait Store: Send + Sync {
+    /// Writes applied all together once committed, other writes wait until then
+    async fn begin(&self) -> Result<Box<dyn StoreTransaction + '_>>;
+
+    fn settings(&self) -> Box<dyn SettingsRepository + '_>;
+    fn owned_identities(&self) -> Box<dyn OwnedIdentityRepository + '_>;
+    fn owned_devices(&self) -> Box<dyn OwnedDeviceRepository + '_>;
+    fn keycloak_servers(&self) -> Box<dyn KeycloakServerRepository + '_>;
+    fn contacts(&self) -> Box<dyn ContactRepository + '_>;
+    fn blocked_contacts(&self) -> Box<dyn BlockedContactRepository + '_>;
+    fn contact_channels(&self) -> Box<dyn ContactChannelRepository + '_>;
+    fn introductions(&self) -> Box<dyn IntroductionRepository + '_>;
+    fn trust_establishments(&self) -> Box<dyn TrustEstablishmentRepository + '_>;
+    fn discussions(&self) -> Box<dyn DiscussionRepository + '_>;
+    fn messages(&self) -> Box<dyn MessageRepository + '_>;
+    fn message_expirations(&self) -> Box<dyn MessageExpirationRepository + '_>;
+    fn message_edits(&self) -> Box<dyn MessageEditRepository + '_>;
+    fn message_mentions(&self) -> Box<dyn MessageMentionRepository + '_>;
+    fn message_reactions(&self) -> Box<dyn MessageReactionRepository + '_>;
+    fn pending_message_operations(&self) -> Box<dyn PendingMessageOperationRepository + '_>;
+    fn groups(&self) -> Box<dyn GroupRepository + '_>;
+    fn group_members(&self) -> Box<dyn GroupMemberRepository + '_>;
+    fn attachments(&self) -> Box<dyn AttachmentRepository + '_>;
+    fn outbox_messages(&self) -> Box<dyn OutboxMessageRepository + '_>;
+    fn inbox_messages(&self) -> Box<dyn InboxMessageRepository + '_>;
+    fn return_receipts(&self) -> Box<dyn ReturnReceiptRepository + '_>;
+}
+
+#[async_trait]
+pub trait StoreTransaction: Send {
+    async fn commit(self: Box<Self>) -> Result<()>;
+
+    fn settings(&mut self) -> Box<dyn SettingsRepository + '_>;
+    fn owned_identities(&mut self) -> Box<dyn OwnedIdentityRepository + '_>;
+    fn owned_devices(&mut self) -> Box<dyn OwnedDeviceRepository + '_>;
+    fn keycloak_servers(&mut self) -> Box<dyn KeycloakServerRepository + '_>;
+    fn contacts(&mut self) -> Box<dyn ContactRepository + '_>;
+    fn blocked_contacts(&mut self) -> Box<dyn BlockedContactRepository + '_>;
+    fn contact_channels(&mut self) -> Box<dyn ContactChannelRepository + '_>;
+    fn introductions(&mut self) -> Box<dyn IntroductionRepository + '_>;
+    fn trust_establishments(&mut self) -> Box<dyn TrustEstablishmentRepository + '_>;
+    fn discussions(&mut self) -> Box<dyn DiscussionRepository + '_>;
+    fn messages(&mut self) -> Box<dyn MessageRepository + '_>;
+    fn message_expirations(&mut self) -> Box<dyn MessageExpirationRepository + '_>;
+    fn message_edits(&mut self) -> Box<dyn MessageEditRepository + '_>;
+    fn message_mentions(&mut self) -> Box<dyn MessageMentionRepository + '_>;
+    fn message_reactions(&mut self) -> Box<dyn MessageReactionRepository + '_>;
+    fn pending_message_operations(&mut self) -> Box<dyn PendingMessageOperationRepository + '_>;
+    fn groups(&mut self) -> Box<dyn GroupRepository + '_>;
+    fn group_members(&mut self) -> Box<dyn GroupMemberRepository + '_>;
+    fn attachments(&mut self) -> Box<dyn AttachmentRepository + '_>;
+    fn outbox_messages(&mut self) -> Box<dyn OutboxMessageRepository + '_>;
+    fn inbox_messages(&mut self) -> Box<dyn InboxMessageRepository + '_>;
+    fn return_receipts(&mut self) -> Box<dyn ReturnReceiptRepository + '_>;
+}
+
+/// Settings of the engine, shared by every owned identity
+#[async_trait]
+pub trait SettingsRepository: Send {
+    /// The default format until one is set
+    async fn get_display_name_format(&mut self) -> Result<DisplayNameFormat>;
+    async fn set_display_name_format(&mut self, format: &DisplayNameFormat) -> Result<()>;
+    async fn get_backup_key(&mut self) -> Result<Option<BackupKey>>;
+    /// Replaces the previous backup key
+    async fn set_backup_key(&mut self, backup_key: BackupKey) -> Result<()>;
+    /// `None` when the sensitive columns are not encrypted
+    async fn get_database_key(&mut self) -> Result<Option<DatabaseKey>>;
+    /// Replaces the previous wrapping of the database key
+    async fn set_database_key(&mut self, database_key: DatabaseKey) -> Result<()>;
+}
+
+#[async_trait]
+pub trait OwnedIdentityRepository: Send {
+    async fn get_all(&mut self) -> Result<Vec<OwnedIdentity>>;
+    async fn get_by_identity(&mut self, bytes_owned_identity: &[u8]) -> Result<Option<OwnedIdentity>>;
+    async fn insert(&mut self, owned_identity: OwnedIdentity) -> Result<()>;
+    async fn set_pref_send_read_receipt(&mut self, bytes_owned_identity: &[u8], pref_send_read_receipt: bool) -> Result<()>;
+    /// Notifications muted until `until` when given, not muted when `mute` is false
+    async fn set_pref_mute_notifications(&mut self, bytes_owned_identity: &[u8], mute: bool, until: Option<i64>, except_mentioned: bool) -> Result<()>;
+    /// Keeps edited details until they are published
+    async fn set_unpublished_details(&mut self, bytes_owned_identity: &[u8], identity_details: &JsonIdentityDetails, photo_url: Option<&str>) -> Result<()>;
+    /// Makes the unpublished details the published ones, returns their new version
+    async fn publish_details(&mut self, bytes_owned_identity: &[u8], identity_details: &JsonIdentityDetails, display_name: &str, photo_url: Option<&str>) -> Result<i64>;
+    /// Recomputes the display name from the details, the custom display name and the format
+    async fn refresh_display_name(&mut self, bytes_owned_identity: &[u8], format: &DisplayNameFormat) -> Result<()>;
+    async fn set_custom_display_name(&mut self, bytes_owned_identity: &[u8], custom_display_name: Option<&str>) -> Result<()>;
+    /// Salted hash of the password and its salt, `None` makes the identity visible again
+    async fn set_unlock_password(&mut self, bytes_owned_identity: &[u8], unlock_password: Option<(&[u8], &[u8])>) -> Result<()>;
+    async fn set_active(&mut self, bytes_owned_identity: &[u8], active: bool) -> Result<()>;
+    async fn set_keycloak_managed(&mut self, bytes_owned_identity: &[u8], keycloak_managed: bool) -> Result<()>;
+    async fn set_api_key_status(&mut self, bytes_owned_identity: &[u8], api_key_status: &ApiKeyStatus) -> Result<()>;
+    async fn set_last_selection_timestamp(&mut self, bytes_owned_identity: &[u8], last_selection_timestamp: i64) -> Result<()>;
+    /// Only removes the identity, see `Engine::delete_owned_identity` for everything that belongs to it
+    async fn delete(&mut self, bytes_owned_identity: &[u8]) -> Result<()>;
+}
+
+#[async_trait]
+pub trait OwnedDeviceRepository: Send {
+    /// Oldest first
+    async fn get_all(&mut self, owned_identity: &[u8]) -> Result<Vec<OwnedDevice>>;
+    async fn insert_if_absent(&mut self, owned_device: OwnedDevice) -> Result<()>;
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()>;
+}
+
+#[async_trait]
+pub trait KeycloakServerRepository: Send {
+    async fn get(&mut self, owned_identity: &[u8]) -> Result<Option<KeycloakServer>>;
+    /// Replaces the previous server of the owned identity
+    async fn upsert(&mut self, keycloak_server: KeycloakServer) -> Result<()>;
+    async fn set_serialized_auth_state(&mut self, owned_identity: &[u8], serialized_auth_state: Option<&str>) -> Result<()>;
+    async fn set_user_state(&mut self, owned_identity: &[u8], keycloak_user_id: &str, transfer_restricted: bool) -> Result<()>;
+    async fn set_latest_revocation_list_timestamp(&mut self, owned_identity: &[u8], latest_revocation_list_timestamp: i64) -> Result<()>;
+    async fn delete(&mut self, owned_identity: &[u8]) -> Result<()>;
+}
+
+#[async_trait]
+pub trait ContactRepository: Send {
+    async fn get(&mut self, owned_identity: &[u8], contact_identity: &[u8]) -> Result<Option<Contact>>;
+    /// Sorted by sort key
+    async fn get_all(&mut self, owned_identity: &[u8]) -> Result<Vec<Contact>>;
+    /// Returns false when the contact already existed
+    async fn insert_if_absent(&mut self, contact: Contact) -> Result<bool>;
+    /// Returns false when the contact already had this trust level or a higher one
+    async fn raise_trust_level(&mut self, owned_identity: &[u8], contact_identity: &[u8], trust_level: ContactTrustLevel) -> Result<bool>;
+    /// Back to unverified, returns false when the contact was not trusted
+    async fn revoke_trust(&mut self, owned_identity: &[u8], contact_identity: &[u8]) -> Result<bool>;
+    /// Keeps details published by the contact, `None` when they are the same as the trusted ones
+    async fn set_published_details(&mut self, owned_identity: &[u8], contact_identity: &[u8], version: i64, published_identity_details: Option<&JsonIdentityDetails>) -> Result<()>;
+    /// Trusts the details published by the contact, returns false when there were none
+    async fn accept_published_details(&mut self, owned_identity: &[u8], contact_identity: &[u8]) -> Result<bool>;
+    /// Recomputes the display name and the sort key from the details, the custom display name and the format
+    async fn refresh_display_name(&mut self, owned_identity: &[u8], contact_identity: &[u8], format: &DisplayNameFormat) -> Result<()>;
+    /// Replaces the details with the ones signed by the keycloak server, or only drops the certification
+    async fn set_keycloak_managed(&mut self, owned_identity: &[u8], contact_identity: &[u8], signed_details: Option<&JsonIdentityDetails>) -> Result<()>;
+    async fn set_custom_display_name(&mut self, owned_identity: &[u8], contact_identity: &[u8], custom_display_name: Option<&str>) -> Result<()>;
+    async fn delete(&mut self, owned_identity: &[u8], contact_identity: &[u8]) -> Result<()>;
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()>;
+}
+
+#[async_trait]
+pub trait BlockedContactRepository: Send {
+    async fn get(&mut self, owned_identity: &[u8], contact_identity: &[u8]) -> Result<Option<BlockedContact>>;
+    /// Earliest blocked first
+    async fn get_all(&mut self, owned_identity: &[u8]) -> Result<Vec<BlockedContact>>;
+    /// Returns false when the identity was already blocked
+    async fn insert_if_absent(&mut self, blocked_contact: BlockedContact) -> Result<bool>;
+    /// Returns false when the identity was not blocked
+    async fn delete(&mut self, owned_identity: &[u8], contact_identity: &[u8]) -> Result<bool>;
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()>;
+}
+
+#[async_trait]
+pub trait ContactChannelRepository: Send {
+    async fn get(&mut self, owned_identity: &[u8], contact_identity: &[u8]) -> Result<Option<ContactChannel>>;
+    /// Returns false when the owned identity already has a channel with the identity
+    async fn insert_if_absent(&mut self, channel: ContactChannel) -> Result<bool>;
+    /// Returns false when there was no channel to tear down
+    async fn delete(&mut self, owned_identity: &[u8], contact_identity: &[u8]) -> Result<bool>;
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()>;
+}
+
+#[async_trait]
+pub trait IntroductionRepository: Send {
+    async fn get_by_id(&mut self, id: i64) -> Result<Option<Introduction>>;
+    async fn get_by_uid(&mut self, owned_identity: &[u8], introduction_uid: &[u8]) -> Result<Option<Introduction>>;
+    /// Newest first
+    async fn get_all(&mut self, owned_identity: &[u8]) -> Result<Vec<Introduction>>;
+    /// Returns the id of the introduction, `None` when it was already known
+    async fn insert_if_absent(&mut self, introduction: Introduction) -> Result<Option<i64>>;
+    async fn set_status(&mut self, id: i64, status: IntroductionStatus) -> Result<()>;
+    async fn set_contact_acceptance(&mut self, id: i64, identity_details: &JsonIdentityDetails, contact_signature: &[u8]) -> Result<()>;
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()>;
+}
+
+#[async_trait]
+pub trait TrustEstablishmentRepository: Send {
+    async fn get_by_id(&mut self, id: i64) -> Result<Option<TrustEstablishment>>;
+    async fn get_by_uid(&mut self, owned_identity: &[u8], protocol_uid: &[u8]) -> Result<Option<TrustEstablishment>>;
+    /// Newest first
+    async fn get_all(&mut self, owned_identity: &[u8]) -> Result<Vec<TrustEstablishment>>;
+    /// Returns the id of the trust establishment, `None` when it was already known
+    async fn insert_if_absent(&mut self, trust_establishment: TrustEstablishment) -> Result<Option<i64>>;
+    async fn set_status(&mut self, id: i64, status: TrustEstablishmentStatus) -> Result<()>;
+    async fn set_own_seed(&mut self, id: i64, own_seed: &[u8]) -> Result<()>;
+    /// The details are only replaced when given
+    async fn set_contact_seed(&mut self, id: i64, contact_seed: &[u8], identity_details: Option<&JsonIdentityDetails>) -> Result<()>;
+    async fn set_contact_confirmed(&mut self, id: i64) -> Result<()>;
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()>;
+}
+
+#[async_trait]
+pub trait DiscussionRepository: Send {
+    async fn get_by_id(&mut self, id: i64) -> Result<Option<Discussion>>;
+    async fn get_one_to_one(&mut self, owned_identity: &[u8], contact_identity: &[u8]) -> Result<Option<Discussion>>;
+    async fn get_group(&mut self, owned_identity: &[u8], group_identifier: &[u8]) -> Result<Option<Discussion>>;
+    /// Most recently active first
+    async fn get_all(&mut self, owned_identity: &[u8]) -> Result<Vec<Discussion>>;
+    async fn insert(&mut self, discussion: Discussion) -> Result<i64>;
+    /// Never moves the timestamp back
+    async fn set_last_message_timestamp(&mut self, id: i64, last_message_timestamp: i64) -> Result<()>;
+    async fn set_title(&mut self, id: i64, title: &str) -> Result<()>;
+    async fn set_pref_send_read_receipt(&mut self, id: i64, pref_send_read_receipt: Option<bool>) -> Result<()>;
+    /// Returns false when the discussion already has settings with this version or a higher one
+    async fn update_shared_settings(&mut self, id: i64, settings_version: i64, ephemerality: &Ephemerality) -> Result<bool>;
+    /// Only removes the discussion, its messages are deleted with the `delete_by_discussion` of each repository
+    async fn delete(&mut self, id: i64) -> Result<()>;
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()>;
+}
+
+#[async_trait]
+pub trait MessageRepository: Send {
+    async fn get_by_id(&mut self, id: i64) -> Result<Option<Message>>;
+    async fn get_by_outbox_message(&mut self, outbox_message_id: i64) -> Result<Option<Message>>;
+    /// Outbound messages of the owned identity sent with this return receipt nonce
+    async fn get_by_return_receipt_nonce(&mut self, owned_identity: &[u8], nonce: &[u8]) -> Result<Vec<Message>>;
+    async fn get_by_sender(&mut self, discussion_id: i64, sender_identity: &[u8], sender_thread_identifier: &Uuid, sender_sequence_number: i64) -> Result<Option<Message>>;
+    /// Latest messages first, the ones displayed before `before_message_id` when given
+    async fn get_page(&mut self, discussion_id: i64, before_message_id: Option<i64>, limit: i64) -> Result<Vec<Message>>;
+    /// Messages of the owned identity flagged as mentioning it, latest first as in `get_page`
+    async fn get_mentioning_page(&mut self, owned_identity: &[u8], before_message_id: Option<i64>, limit: i64) -> Result<Vec<Message>>;
+    /// Sequence number of the next outbound message of the discussion
+    async fn get_next_sequence_number(&mut self, discussion_id: i64) -> Result<i64>;
+    /// Messages of the owned identity containing every term in their body or in the file name of an attachment.
+    ///
+    /// Latest messages first, the ones displayed before `before_message_id` when given.
+    async fn search(&mut self, owned_identity: &[u8], terms: &[String], query: &MessageSearchQuery, before_message_id: Option<i64>, limit: i64) -> Result<Vec<Message>>;
+    /// Returns the id of the message, `None` when the sender already sent it
+    async fn insert_if_absent(&mut self, message: Message) -> Result<Option<i64>>;
+    /// Links the replies to a message received before it
+    async fn resolve_replies(&mut self, discussion_id: i64, reference: &MessageReference, message_id: i64) -> Result<()>;
+    /// Returns false when the message already had this status or a later one
+    async fn advance_outbound_status(&mut self, id: i64, status: MessageStatus) -> Result<bool>;
+    /// Returns false when the message was not unread
+    async fn mark_read(&mut self, id: i64) -> Result<bool>;
+    /// Removes the body and the attachment metadata, flags the message as wiped and no longer mentioning the owned identity
+    async fn wipe(&mut self, id: i64) -> Result<()>;
+    /// Replaces the body with the one of an edit more recent than the current body, flags the message as edited
+    async fn set_edited_body(&mut self, id: i64, body: &str, edit_timestamp: i64) -> Result<()>;
+    /// Wipes the message and flags it as deleted for everyone
+    async fn mark_remotely_deleted(&mut self, id: i64) -> Result<()>;
+    async fn delete(&mut self, id: i64) -> Result<()>;
+    async fn delete_by_discussion(&mut self, discussion_id: i64) -> Result<()>;
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()>;
+}
+
+#[async_trait]
+pub trait MessageExpirationRepository: Send {
+    /// Wipes after deletions, earliest first
+    async fn get_due(&mut self, timestamp: i64) -> Result<Vec<MessageExpiration>>;
+    async fn get_next_expiration_timestamp(&mut self) -> Result<Option<i64>>;
+    async fn insert(&mut self, expiration: MessageExpiration) -> Result<i64>;
+    async fn delete(&mut self, id: i64) -> Result<()>;
+    async fn delete_by_message(&mut self, message_id: i64) -> Result<()>;
+    async fn delete_by_discussion(&mut self, discussion_id: i64) -> Result<()>;
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()>;
+}
+
+#[async_trait]
+pub trait MessageEditRepository: Send {
+    /// Previous bodies of a message, oldest first
+    async fn get_all(&mut self, message_id: i64) -> Result<Vec<MessageEdit>>;
+    async fn insert(&mut self, edit: MessageEdit) -> Result<i64>;
+    async fn delete_by_message(&mut self, message_id: i64) -> Result<()>;
+    async fn delete_by_discussion(&mut self, discussion_id: i64) -> Result<()>;
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()>;
+}
+
+#[async_trait]
+pub trait MessageMentionRepository: Send {
+    /// In the order of the body
+    async fn get_all(&mut self, message_id: i64) -> Result<Vec<MessageMention>>;
+    async fn insert(&mut self, mention: MessageMention) -> Result<i64>;
+    async fn delete_by_message(&mut self, message_id: i64) -> Result<()>;
+    async fn delete_by_discussion(&mut self, discussion_id: i64) -> Result<()>;
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()>;
+}
+
+#[async_trait]
+pub trait MessageReactionRepository: Send {
+    /// Earliest first, removed reactions included
+    async fn get_all(&mut self, message_id: i64) -> Result<Vec<MessageReaction>>;
+    /// Replaces the reaction of the same reactor, returns false when that one is at least as recent
+    async fn upsert(&mut self, reaction: MessageReaction) -> Result<bool>;
+    async fn delete_by_message(&mut self, message_id: i64) -> Result<()>;
+    async fn delete_by_discussion(&mut self, discussion_id: i64) -> Result<()>;
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()>;
+}
+
+#[async_trait]
+pub trait PendingMessageOperationRepository: Send {
+    /// Operations waiting for the message with this sender, thread and sequence number, in the order the server received them
+    async fn get_by_message(&mut self, discussion_id: i64, sender_identity: &[u8], sender_thread_identifier: &Uuid, sender_sequence_number: i64) -> Result<Vec<PendingMessageOperation>>;
+    async fn insert(&mut self, operation: PendingMessageOperation) -> Result<i64>;
+    async fn delete(&mut self, id: i64) -> Result<()>;
+    async fn delete_by_discussion(&mut self, discussion_id: i64) -> Result<()>;
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()>;
+}
+
+#[async_trait]
+pub trait GroupRepository: Send {
+    async fn get_by_id(&mut self, id: i64) -> Result<Option<Group>>;
+    async fn get_by_uid(&mut self, owned_identity: &[u8], group_uid: &[u8]) -> Result<Option<Group>>;
+    /// Sorted by name
+    async fn get_all(&mut self, owned_identity: &[u8]) -> Result<Vec<Group>>;
+    /// Inserts the group or updates the known one, its status never goes back, returns its id
+    async fn upsert(&mut self, group: Group) -> Result<i64>;
+    async fn set_status(&mut self, id: i64, status: GroupStatus) -> Result<()>;
+    /// Removes the group and its members
+    async fn delete(&mut self, id: i64) -> Result<()>;
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()>;
+}
+
+#[async_trait]
+pub trait GroupMemberRepository: Send {
+    async fn get_all(&mut self, group_id: i64) -> Result<Vec<GroupMember>>;
+    async fn get(&mut self, group_id: i64, member_identity: &[u8]) -> Result<Option<GroupMember>>;
+    async fn replace_all(&mut self, group_id: i64, members: Vec<GroupMember>) -> Result<()>;
+}
+
+#[async_trait]
+pub trait AttachmentRepository: Send {
+    async fn get_by_id(&mut self, id: i64) -> Result<Option<Attachment>>;
+    async fn get_by_outbox_message(&mut self, outbox_message_id: i64) -> Result<Vec<Attachment>>;
+    async fn get_inbound_by_message(&mut self, owned_identity: &[u8], message_uid: &[u8]) -> Result<Vec<Attachment>>;
+    async fn get_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<Vec<Attachment>>;
+    /// Attachments of the messages of a discussion, sent or received
+    async fn get_by_discussion(&mut self, discussion_id: i64) -> Result<Vec<Attachment>>;
+    /// Attachments whose transfer can progress at `timestamp`, outbound ones need their upload URLs
+    async fn get_due(&mut self, timestamp: i64) -> Result<Vec<Attachment>>;
+    async fn get_next_attempt_timestamp(&mut self) -> Result<Option<i64>>;
+    async fn insert(&mut self, attachment: Attachment) -> Result<i64>;
+    /// Attaches a pending outbound attachment to the message that carries it
+    async fn link_to_outbox_message(&mut self, id: i64, outbox_message_id: i64, attachment_number: i64) -> Result<()>;
+    async fn set_upload_urls(&mut self, id: i64, message_uid: &[u8], upload_urls: &[String]) -> Result<()>;
+    /// Also resets the attempt count, the transfer progressed
+    async fn set_transferred_chunk_count(&mut self, id: i64, transferred_chunk_count: i64) -> Result<()>;
+    async fn schedule_retry(&mut self, id: i64, attempt_count: i64, next_attempt_timestamp: i64) -> Result<()>;
+    async fn set_status(&mut self, id: i64, status: AttachmentStatus) -> Result<()>;
+    /// Only removes the attachment, the caller deletes the file
+    async fn delete(&mut self, id: i64) -> Result<()>;
+    /// Only removes the attachments, the caller deletes the files
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()>;
+}
+
+#[async_trait]
+pub trait OutboxMessageRepository: Send {
+    async fn get_by_id(&mut self, id: i64) -> Result<Option<OutboxMessage>>;
+    /// Queued messages whose next attempt is due at `timestamp`
+    async fn get_due(&mut self, timestamp: i64) -> Result<Vec<OutboxMessage>>;
+    async fn get_next_attempt_timestamp(&mut self) -> Result<Option<i64>>;
+    async fn insert(&mut self, outbox_message: OutboxMessage) -> Result<i64>;
+    /// Kept for every attempt, the server ignores an upload whose nonce it already received
+    async fn set_upload_nonce(&mut self, id: i64, upload_nonce: &[u8]) -> Result<()>;
+    async fn mark_sent(&mut self, id: i64, attempt_count: i64, message_uid: &[u8]) -> Result<()>;
+    async fn schedule_retry(&mut self, id: i64, attempt_count: i64, next_attempt_timestamp: i64) -> Result<()>;
+    async fn mark_failed(&mut self, id: i64, attempt_count: i64) -> Result<()>;
+    async fn set_delivery_state(&mut self, id: i64, delivery_state: DeliveryState) -> Result<()>;
+    /// Messages not sent yet are dropped
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()>;
+}
+
+#[async_trait]
+pub trait InboxMessageRepository: Send {
+    /// Returns false when the message was already downloaded
+    async fn insert_if_absent(&mut self, owned_identity: &[u8], message: &DownloadedMessage) -> Result<bool>;
+    async fn get_by_message_uid(&mut self, owned_identity: &[u8], message_uid: &[u8]) -> Result<Option<InboxMessage>>;
+    /// In the order the server received them
+    async fn get_unprocessed(&mut self, owned_identity: &[u8]) -> Result<Vec<InboxMessage>>;
+    async fn mark_processed(&mut self, id: i64) -> Result<()>;
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()>;
+}
+
+#[async_trait]
+pub trait ReturnReceiptRepository: Send {
+    async fn get_due(&mut self, timestamp: i64) -> Result<Vec<OutboxReturnReceipt>>;
+    async fn get_next_attempt_timestamp(&mut self) -> Result<Option<i64>>;
+    async fn insert(&mut self, return_receipt: OutboxReturnReceipt) -> Result<i64>;
+    async fn schedule_retry(&mut self, id: i64, attempt_count: i64, next_attempt_timestamp: i64) -> Result<()>;
+    async fn delete(&mut self, id: i64) -> Result<()>;
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()>;
+}
@@ -0,0 +1,426 @@
+//! At-rest encryption of the sensitive columns of the SQLite database.
+//!
+//! The details, names and private keys of the owned identities, the details and names of the contacts and of the
+//! pending introductions and trust establishments, the discussion titles and group names, the message bodies, previous
+//! ones and pending edits included, the return receipt keys, the keys and file names of the attachments, the backup key
+//! seed, the keycloak client secrets and authentication states, the keys of the group blobs and the channel keys are
+//! encrypted with `AES256CTRHMACSHA256` under a random database key. That key is stored wrapped by a key derived with
+//! PBKDF2 from a passphrase or from the content of a key file, so changing the secret only re-wraps the key.
+//!
+//! The words of the full-text index of the messages are replaced by keyed hashes, searching only needs exact words.
+//! Lists ordered by an encrypted column, as the contacts by sort key, are sorted once decrypted.
+
+use std::{fmt, path::PathBuf};
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use olvid_core::{crypto::{auth_encryption::{AuthEnc, AES256CTRHMACSHA256}, prng::PRNG}, AES256CTRHMACSHA256Key};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use sqlx::SqliteConnection;
+
+use crate::{
+    entities::{
+        attachment::Attachment,
+        backup_key::BackupKey,
+        contact::Contact,
+        contact_channel::ContactChannel,
+        database_key::DatabaseKey,
+        discussion::Discussion,
+        group::Group,
+        identity::OwnedIdentity,
+        introduction::Introduction,
+        keycloak_server::KeycloakServer,
+        message::Message,
+        message_edit::MessageEdit,
+        pending_message_operation::PendingMessageOperation,
+        trust_establishment::TrustEstablishment,
+    },
+    Engine, EngineError, Result,
+};
+
+const DATABASE_KEY_SEED_LENGTH: usize = 32;
+pub(crate) const KDF_SALT_LENGTH: usize = 16;
+pub(crate) const PASSPHRASE_KDF_ITERATIONS: i64 = 100_000;
+/// A key file already holds random bytes, deriving is only needed for the length
+const KEY_FILE_KDF_ITERATIONS: i64 = 1;
+const KEY_FILE_MINIMUM_LENGTH: usize = 32;
+/// Keeps the hashes of the search index apart from any other use of the database key
+const SEARCH_TERM_DOMAIN: &[u8] = b"message-search-term";
+const SEARCH_TERM_HASH_LENGTH: usize = 16;
+
+/// Columns encrypted once a database key exists, `true` for the text ones which are stored as base64.
+///
+/// Columns are only appended: a database records how many of them it encrypted, the following ones are encrypted
+/// when it is opened.
+const SENSITIVE_COLUMNS: [(&str, &str, bool); 27] = [
+    ("identities", "identity_details", true),
+    ("identities", "unpublished_identity_details", true),
+    ("identities", "private_identity", false),
+    ("contacts", "identity_details", true),
+    ("contacts", "published_identity_details", true),
+    ("messages", "body", true),
+    ("messages", "attachment_metadata", true),
+    ("message_edits", "body", true),
+    ("pending_message_operations", "body", true),
+    ("backup_key", "seed", false),
+    ("keycloak_servers", "client_secret", true),
+    ("keycloak_servers", "serialized_auth_state", true),
+    ("groups_v2", "blob_key", false),
+    ("contact_channels", "channel_key", false),
+    ("identities", "display_name", true),
+    ("identities", "custom_display_name", true),
+    ("contacts", "display_name", true),
+    ("contacts", "custom_display_name", true),
+    ("contacts", "sort_key", true),
+    ("discussions", "title", true),
+    ("groups_v2", "name", true),
+    ("introductions", "identity_details", true),
+    ("trust_establishments", "identity_details", true),
+    ("messages", "return_receipt_key", false),
+    ("attachments", "key", false),
+    ("attachments", "file_path", true),
+    ("attachments", "file_name", true),
+];
+
+/// What the database key is wrapped with
+#[derive(Clone)]
+pub enum DatabaseSecret {
+    Passphrase(String),
+    /// File holding at least 32 random bytes, kept out of the data directory
+    KeyFile(PathBuf),
+}
+
+/// Never prints the passphrase
+impl fmt::Debug for DatabaseSecret {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DatabaseSecret::Passphrase(_) => formatter.write_str("Passphrase(..)"),
+            DatabaseSecret::KeyFile(path) => formatter.debug_tuple("KeyFile").field(path).finish(),
+        }
+    }
+}
+
+impl DatabaseSecret {
+    async fn get_bytes(&self) -> Result<Vec<u8>> {
+        match self {
+            DatabaseSecret::Passphrase(passphrase) if passphrase.is_empty() => Err(EngineError::InvalidDatabaseSecret),
+            DatabaseSecret::Passphrase(passphrase) => Ok(passphrase.as_bytes().to_vec()),
+            DatabaseSecret::KeyFile(path) => {
+                let content = tokio::fs::read(path).await?;
+                match content.len() >= KEY_FILE_MINIMUM_LENGTH {
+                    true => Ok(content),
+                    false => Err(EngineError::InvalidDatabaseSecret),
+                }
+            }
+        }
+    }
+
+    fn get_kdf_iterations(&self) -> i64 {
+        match self {
+            DatabaseSecret::Passphrase(_) => PASSPHRASE_KDF_ITERATIONS,
+            DatabaseSecret::KeyFile(_) => KEY_FILE_KDF_ITERATIONS,
+        }
+    }
+}
+
+/// PBKDF2 with HMAC-SHA256, also used to hash the passwords hidden profiles are unlocked with
+pub(crate) fn derive_seed(secret: &[u8], kdf_salt: &[u8], kdf_iterations: i64) -> Result<[u8; 32]> {
+    let rounds = u32::try_from(kdf_iterations).map_err(|_| EngineError::Technical)?;
+    Ok(pbkdf2::pbkdf2_hmac_array::<Sha256, 32>(secret, kdf_salt, rounds))
+}
+
+async fn derive_wrapping_key(secret: &DatabaseSecret, kdf_salt: &[u8], kdf_iterations: i64) -> Result<AES256CTRHMACSHA256Key> {
+    let seed = derive_seed(&secret.get_bytes().await?, kdf_salt, kdf_iterations)?;
+
+    AES256CTRHMACSHA256::generate_key(&seed).map_err(|_| EngineError::Technical)
+}
+
+/// Wraps the seed of the database key with a new salt
+pub(crate) async fn wrap_database_key(seed: &[u8], secret: &DatabaseSecret) -> Result<DatabaseKey> {
+    let mut prng = Engine::get_default_hmac_prng()?;
+    let kdf_salt = prng.bytes(KDF_SALT_LENGTH).map_err(|_| EngineError::PRNG)?;
+    let kdf_iterations = secret.get_kdf_iterations();
+
+    let wrapping_key = derive_wrapping_key(secret, &kdf_salt, kdf_iterations).await?;
+    let wrapped_key = AES256CTRHMACSHA256::encrypt(seed, &wrapping_key, &mut prng).map_err(|_| EngineError::Technical)?;
+
+    Ok(DatabaseKey::new(&wrapped_key, &kdf_salt, kdf_iterations))
+}
+
+/// Fails with `InvalidDatabaseSecret` when the key was wrapped with another secret
+pub(crate) async fn unwrap_database_key(database_key: &DatabaseKey, secret: &DatabaseSecret) -> Result<Vec<u8>> {
+    let wrapping_key = derive_wrapping_key(secret, database_key.get_kdf_salt(), database_key.get_kdf_iterations()).await?;
+    AES256CTRHMACSHA256::decrypt(database_key.get_wrapped_key(), &wrapping_key).map_err(|_| EngineError::InvalidDatabaseSecret)
+}
+
+/// Encrypts and decrypts the sensitive columns with the database key
+pub(crate) struct ColumnCipher {
+    seed: Vec<u8>,
+}
+
+impl ColumnCipher {
+    pub(crate) fn new(seed: Vec<u8>) -> Self {
+        Self { seed }
+    }
+
+    /// A new database key, for a database whose columns are not encrypted yet
+    pub(crate) fn generate() -> Result<Self> {
+        let seed = Engine::get_default_hmac_prng()?.bytes(DATABASE_KEY_SEED_LENGTH).map_err(|_| EngineError::PRNG)?;
+        Ok(Self::new(seed))
+    }
+
+    pub(crate) fn get_seed(&self) -> &[u8] {
+        &self.seed
+    }
+
+    fn get_key(&self) -> Result<AES256CTRHMACSHA256Key> {
+        AES256CTRHMACSHA256::generate_key(&self.seed).map_err(|_| EngineError::Technical)
+    }
+
+    pub(crate) fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        AES256CTRHMACSHA256::encrypt(plaintext, &self.get_key()?, &mut Engine::get_default_hmac_prng()?).map_err(|_| EngineError::Technical)
+    }
+
+    pub(crate) fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>> {
+        AES256CTRHMACSHA256::decrypt(ciphertext, &self.get_key()?).map_err(|_| EngineError::DatabaseDecryption)
+    }
+
+    pub(crate) fn encrypt_text(&self, plaintext: &str) -> Result<String> {
+        Ok(STANDARD.encode(self.encrypt(plaintext.as_bytes())?))
+    }
+
+    pub(crate) fn decrypt_text(&self, ciphertext: &str) -> Result<String> {
+        let ciphertext = STANDARD.decode(ciphertext).map_err(|_| EngineError::DatabaseDecryption)?;
+        String::from_utf8(self.decrypt(&ciphertext)?).map_err(|_| EngineError::DatabaseDecryption)
+    }
+
+    /// Stands for a word in the search index, the same word always giving the same hash
+    pub(crate) fn hash_term(&self, term: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.seed).expect("HMAC accepts keys of any length");
+        mac.update(SEARCH_TERM_DOMAIN);
+        mac.update(term.as_bytes());
+
+        hex::encode(&mac.finalize().into_bytes()[..SEARCH_TERM_HASH_LENGTH])
+    }
+
+    pub(crate) fn encrypt_optional_text(&self, plaintext: Option<String>) -> Result<Option<String>> {
+        plaintext.map(|plaintext| self.encrypt_text(&plaintext)).transpose()
+    }
+
+    fn decrypt_optional_text(&self, ciphertext: Option<String>) -> Result<Option<String>> {
+        ciphertext.map(|ciphertext| self.decrypt_text(&ciphertext)).transpose()
+    }
+
+    /// Encrypts the sensitive columns written in clear, from the first one not encrypted yet, returns how many are encrypted
+    pub(crate) async fn encrypt_existing_rows(&self, connection: &mut SqliteConnection, encrypted_column_count: usize) -> Result<usize> {
+        for (table, column, is_text) in SENSITIVE_COLUMNS.into_iter().skip(encrypted_column_count) {
+            let select = format!("SELECT rowid, {column} FROM {table} WHERE {column} IS NOT NULL");
+            let update = format!("UPDATE {table} SET {column} = $1 WHERE rowid = $2");
+            if is_text {
+                let rows = sqlx::query_as::<_, (i64, String)>(&select).fetch_all(&mut *connection).await?;
+                for (rowid, plaintext) in rows {
+                    sqlx::query(&update).bind(self.encrypt_text(&plaintext)?).bind(rowid).execute(&mut *connection).await?;
+                }
+            } else {
+                let rows = sqlx::query_as::<_, (i64, Vec<u8>)>(&select).fetch_all(&mut *connection).await?;
+                for (rowid, plaintext) in rows {
+                    sqlx::query(&update).bind(self.encrypt(&plaintext)?).bind(rowid).execute(&mut *connection).await?;
+                }
+            }
+        }
+
+        Ok(SENSITIVE_COLUMNS.len())
+    }
+}
+
+/// Entities with sensitive columns, encrypted before they are written and decrypted once read
+pub(crate) trait SensitiveColumns: Sized {
+    fn encrypt_columns(self, cipher: &ColumnCipher) -> Result<Self>;
+    fn decrypt_columns(self, cipher: &ColumnCipher) -> Result<Self>;
+}
+
+impl SensitiveColumns for OwnedIdentity {
+    fn encrypt_columns(self, cipher: &ColumnCipher) -> Result<Self> {
+        Ok(Self {
+            display_name: cipher.encrypt_text(&self.display_name)?,
+            identity_details: cipher.encrypt_text(&self.identity_details)?,
+            custom_display_name: cipher.encrypt_optional_text(self.custom_display_name)?,
+            unpublished_identity_details: cipher.encrypt_optional_text(self.unpublished_identity_details)?,
+            private_identity: self.private_identity.map(|private_identity| cipher.encrypt(&private_identity)).transpose()?,
+            ..self
+        })
+    }
+
+    fn decrypt_columns(self, cipher: &ColumnCipher) -> Result<Self> {
+        Ok(Self {
+            display_name: cipher.decrypt_text(&self.display_name)?,
+            identity_details: cipher.decrypt_text(&self.identity_details)?,
+            custom_display_name: cipher.decrypt_optional_text(self.custom_display_name)?,
+            unpublished_identity_details: cipher.decrypt_optional_text(self.unpublished_identity_details)?,
+            private_identity: self.private_identity.map(|private_identity| cipher.decrypt(&private_identity)).transpose()?,
+            ..self
+        })
+    }
+}
+
+impl SensitiveColumns for Contact {
+    fn encrypt_columns(self, cipher: &ColumnCipher) -> Result<Self> {
+        Ok(Self {
+            identity_details: cipher.encrypt_text(&self.identity_details)?,
+            display_name: cipher.encrypt_text(&self.display_name)?,
+            published_identity_details: cipher.encrypt_optional_text(self.published_identity_details)?,
+            custom_display_name: cipher.encrypt_optional_text(self.custom_display_name)?,
+            sort_key: cipher.encrypt_text(&self.sort_key)?,
+            ..self
+        })
+    }
+
+    fn decrypt_columns(self, cipher: &ColumnCipher) -> Result<Self> {
+        Ok(Self {
+            identity_details: cipher.decrypt_text(&self.identity_details)?,
+            display_name: cipher.decrypt_text(&self.display_name)?,
+            published_identity_details: cipher.decrypt_optional_text(self.published_identity_details)?,
+            custom_display_name: cipher.decrypt_optional_text(self.custom_display_name)?,
+            sort_key: cipher.decrypt_text(&self.sort_key)?,
+            ..self
+        })
+    }
+}
+
+impl SensitiveColumns for Message {
+    fn encrypt_columns(self, cipher: &ColumnCipher) -> Result<Self> {
+        Ok(Self {
+            body: cipher.encrypt_optional_text(self.body)?,
+            return_receipt_key: self.return_receipt_key.map(|return_receipt_key| cipher.encrypt(&return_receipt_key)).transpose()?,
+            attachment_metadata: cipher.encrypt_optional_text(self.attachment_metadata)?,
+            ..self
+        })
+    }
+
+    fn decrypt_columns(self, cipher: &ColumnCipher) -> Result<Self> {
+        Ok(Self {
+            body: cipher.decrypt_optional_text(self.body)?,
+            return_receipt_key: self.return_receipt_key.map(|return_receipt_key| cipher.decrypt(&return_receipt_key)).transpose()?,
+            attachment_metadata: cipher.decrypt_optional_text(self.attachment_metadata)?,
+            ..self
+        })
+    }
+}
+
+impl SensitiveColumns for MessageEdit {
+    fn encrypt_columns(self, cipher: &ColumnCipher) -> Result<Self> {
+        Ok(Self { body: cipher.encrypt_text(&self.body)?, ..self })
+    }
+
+    fn decrypt_columns(self, cipher: &ColumnCipher) -> Result<Self> {
+        Ok(Self { body: cipher.decrypt_text(&self.body)?, ..self })
+    }
+}
+
+impl SensitiveColumns for PendingMessageOperation {
+    fn encrypt_columns(self, cipher: &ColumnCipher) -> Result<Self> {
+        Ok(Self { body: cipher.encrypt_optional_text(self.body)?, ..self })
+    }
+
+    fn decrypt_columns(self, cipher: &ColumnCipher) -> Result<Self> {
+        Ok(Self { body: cipher.decrypt_optional_text(self.body)?, ..self })
+    }
+}
+
+impl SensitiveColumns for BackupKey {
+    fn encrypt_columns(self, cipher: &ColumnCipher) -> Result<Self> {
+        Ok(Self { seed: cipher.encrypt(&self.seed)?, ..self })
+    }
+
+    fn decrypt_columns(self, cipher: &ColumnCipher) -> Result<Self> {
+        Ok(Self { seed: cipher.decrypt(&self.seed)?, ..self })
+    }
+}
+
+impl SensitiveColumns for KeycloakServer {
+    fn encrypt_columns(self, cipher: &ColumnCipher) -> Result<Self> {
+        Ok(Self {
+            client_secret: cipher.encrypt_optional_text(self.client_secret)?,
+            serialized_auth_state: cipher.encrypt_optional_text(self.serialized_auth_state)?,
+            ..self
+        })
+    }
+
+    fn decrypt_columns(self, cipher: &ColumnCipher) -> Result<Self> {
+        Ok(Self {
+            client_secret: cipher.decrypt_optional_text(self.client_secret)?,
+            serialized_auth_state: cipher.decrypt_optional_text(self.serialized_auth_state)?,
+            ..self
+        })
+    }
+}
+
+impl SensitiveColumns for Group {
+    fn encrypt_columns(self, cipher: &ColumnCipher) -> Result<Self> {
+        Ok(Self { name: cipher.encrypt_text(&self.name)?, blob_key: cipher.encrypt(&self.blob_key)?, ..self })
+    }
+
+    fn decrypt_columns(self, cipher: &ColumnCipher) -> Result<Self> {
+        Ok(Self { name: cipher.decrypt_text(&self.name)?, blob_key: cipher.decrypt(&self.blob_key)?, ..self })
+    }
+}
+
+impl SensitiveColumns for ContactChannel {
+    fn encrypt_columns(self, cipher: &ColumnCipher) -> Result<Self> {
+        Ok(Self { channel_key: cipher.encrypt(&self.channel_key)?, ..self })
+    }
+
+    fn decrypt_columns(self, cipher: &ColumnCipher) -> Result<Self> {
+        Ok(Self { channel_key: cipher.decrypt(&self.channel_key)?, ..self })
+    }
+}
+
+impl SensitiveColumns for Discussion {
+    fn encrypt_columns(self, cipher: &ColumnCipher) -> Result<Self> {
+        Ok(Self { title: cipher.encrypt_text(&self.title)?, ..self })
+    }
+
+    fn decrypt_columns(self, cipher: &ColumnCipher) -> Result<Self> {
+        Ok(Self { title: cipher.decrypt_text(&self.title)?, ..self })
+    }
+}
+
+impl SensitiveColumns for Introduction {
+    fn encrypt_columns(self, cipher: &ColumnCipher) -> Result<Self> {
+        Ok(Self { identity_details: cipher.encrypt_text(&self.identity_details)?, ..self })
+    }
+
+    fn decrypt_columns(self, cipher: &ColumnCipher) -> Result<Self> {
+        Ok(Self { identity_details: cipher.decrypt_text(&self.identity_details)?, ..self })
+    }
+}
+
+impl SensitiveColumns for TrustEstablishment {
+    fn encrypt_columns(self, cipher: &ColumnCipher) -> Result<Self> {
+        Ok(Self { identity_details: cipher.encrypt_optional_text(self.identity_details)?, ..self })
+    }
+
+    fn decrypt_columns(self, cipher: &ColumnCipher) -> Result<Self> {
+        Ok(Self { identity_details: cipher.decrypt_optional_text(self.identity_details)?, ..self })
+    }
+}
+
+impl SensitiveColumns for Attachment {
+    fn encrypt_columns(self, cipher: &ColumnCipher) -> Result<Self> {
+        Ok(Self {
+            key: cipher.encrypt(&self.key)?,
+            file_path: cipher.encrypt_text(&self.file_path)?,
+            file_name: cipher.encrypt_text(&self.file_name)?,
+            ..self
+        })
+    }
+
+    fn decrypt_columns(self, cipher: &ColumnCipher) -> Result<Self> {
+        Ok(Self {
+            key: cipher.decrypt(&self.key)?,
+            file_path: cipher.decrypt_text(&self.file_path)?,
+            file_name: cipher.decrypt_text(&self.file_name)?,
+            ..self
+        })
+    }
+}
@@ -0,0 +1,1629 @@
+use std::{cmp::Reverse, collections::{BTreeMap, HashMap}, ops::{Deref, DerefMut}, sync::Arc};
+
+use async_trait::async_trait;
+use tokio::sync::{Mutex, MutexGuard, OwnedMutexGuard};
+use uuid::Uuid;
+
+use crate::{
+    current_timestamp,
+    entities::{
+        attachment::{Attachment, AttachmentDirection, AttachmentStatus},
+        backup_key::BackupKey,
+        blocked_contact::BlockedContact,
+        contact_channel::ContactChannel,
+        contact::{Contact, ContactTrustLevel},
+        database_key::DatabaseKey,
+        discussion::{Discussion, Ephemerality},
+        group::{Group, GroupMember, GroupStatus},
+        identity::{resolve_display_name, DisplayNameFormat, JsonIdentityDetails, OwnedIdentity, UNPUBLISHED_DETAILS_EXIST, UNPUBLISHED_DETAILS_NOTHING_NEW},
+        inbox_message::InboxMessage,
+        introduction::{Introduction, IntroductionStatus},
+        keycloak_server::KeycloakServer,
+        message::{Message, MessageDirection, MessageReference, MessageStatus},
+        message_edit::MessageEdit,
+        message_expiration::MessageExpiration,
+        message_mention::MessageMention,
+        message_reaction::MessageReaction,
+        outbox_message::{DeliveryState, OutboxMessage},
+        owned_device::OwnedDevice,
+        pending_message_operation::PendingMessageOperation,
+        return_receipt::OutboxReturnReceipt,
+        trust_establishment::{TrustEstablishment, TrustEstablishmentStatus},
+    },
+    search,
+    server::{ApiKeyStatus, DownloadedMessage},
+    EngineError, Result,
+};
+
+use super::*;
+
+/// Store keeping everything in memory, with the same constraints and orderings as `SqliteStore`.
+///
+/// Clones share the same data, it is lost once the last one is dropped. Nothing being written, no column is encrypted.
+#[derive(Clone, Default)]
+pub struct MemoryStore {
+    data: Arc<Mutex<MemoryData>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn repository(&self) -> Box<MemoryRepository<'_>> {
+        Box::new(MemoryRepository::Store(&self.data))
+    }
+}
+
+/// Rows by id, a new row gets the id following the highest one like a SQLite rowid
+#[derive(Clone)]
+struct Table<T> {
+    rows: BTreeMap<i64, T>,
+}
+
+impl<T> Default for Table<T> {
+    fn default() -> Self {
+        Self { rows: BTreeMap::new() }
+    }
+}
+
+impl<T: Clone> Table<T> {
+    fn insert(&mut self, row: impl FnOnce(i64) -> T) -> i64 {
+        let id = self.rows.keys().next_back().map_or(1, |id| id + 1);
+        self.rows.insert(id, row(id));
+        id
+    }
+
+    fn get(&self, id: i64) -> Option<T> {
+        self.rows.get(&id).cloned()
+    }
+
+    fn get_mut(&mut self, id: i64) -> Option<&mut T> {
+        self.rows.get_mut(&id)
+    }
+
+    fn find(&self, predicate: impl Fn(&T) -> bool) -> Option<T> {
+        self.rows.values().find(|row| predicate(row)).cloned()
+    }
+
+    fn find_mut(&mut self, predicate: impl Fn(&T) -> bool) -> Option<&mut T> {
+        self.rows.values_mut().find(|row| predicate(row))
+    }
+
+    /// In id order
+    fn select(&self, predicate: impl Fn(&T) -> bool) -> Vec<T> {
+        self.rows.values().filter(|row| predicate(row)).cloned().collect()
+    }
+
+    fn delete(&mut self, predicate: impl Fn(&T) -> bool) {
+        self.rows.retain(|_, row| !predicate(row));
+    }
+}
+
+/// Whether a message is displayed before the start of a page, see `MemoryData::page_start`
+fn is_before(message: &Message, page_start: Option<(f64, i64)>) -> bool {
+    page_start.is_none_or(|(sort_index, id)| message.sort_index.total_cmp(&sort_index).then(message.id.cmp(&Some(id))).is_lt())
+}
+
+/// Orders messages as in the discussions, latest first, and keeps the first `limit` ones
+fn latest_first(mut messages: Vec<Message>, limit: i64) -> Vec<Message> {
+    messages.sort_by(|a, b| b.sort_index.total_cmp(&a.sort_index).then(b.id.cmp(&a.id)));
+    messages.truncate(usize::try_from(limit).unwrap_or_default());
+    messages
+}
+
+#[derive(Clone, Default)]
+struct MemoryData {
+    display_name_format: Option<DisplayNameFormat>,
+    backup_key: Option<BackupKey>,
+    database_key: Option<DatabaseKey>,
+    owned_identities: Table<OwnedIdentity>,
+    owned_devices: Table<OwnedDevice>,
+    keycloak_servers: HashMap<Vec<u8>, KeycloakServer>,
+    contacts: Table<Contact>,
+    blocked_contacts: Table<BlockedContact>,
+    contact_channels: Table<ContactChannel>,
+    introductions: Table<Introduction>,
+    trust_establishments: Table<TrustEstablishment>,
+    discussions: Table<Discussion>,
+    messages: Table<Message>,
+    message_expirations: Table<MessageExpiration>,
+    message_edits: Table<MessageEdit>,
+    message_mentions: Table<MessageMention>,
+    message_reactions: Table<MessageReaction>,
+    pending_message_operations: Table<PendingMessageOperation>,
+    groups: Table<Group>,
+    group_members: Table<GroupMember>,
+    attachments: Table<Attachment>,
+    outbox_messages: Table<OutboxMessage>,
+    inbox_messages: Table<InboxMessage>,
+    return_receipts: Table<OutboxReturnReceipt>,
+}
+
+impl MemoryData {
+    fn format(&self) -> DisplayNameFormat {
+        self.display_name_format.unwrap_or_default()
+    }
+
+    fn discussion_ids(&self, owned_identity: &[u8]) -> Vec<i64> {
+        self.discussions.select(|discussion| discussion.owned_identity == owned_identity).into_iter().filter_map(|discussion| discussion.id).collect()
+    }
+
+    fn message_ids(&self, owned_identity: &[u8]) -> Vec<i64> {
+        let discussion_ids = self.discussion_ids(owned_identity);
+        self.messages.select(|message| discussion_ids.contains(&message.discussion_id)).into_iter().filter_map(|message| message.id).collect()
+    }
+
+    fn discussion_message_ids(&self, discussion_id: i64) -> Vec<i64> {
+        self.messages.select(|message| message.discussion_id == discussion_id).into_iter().filter_map(|message| message.id).collect()
+    }
+
+    fn refresh_contact_display_name(&mut self, owned_identity: &[u8], contact_identity: &[u8], format: &DisplayNameFormat) -> Result<()> {
+        let contact = self.contacts.find_mut(|contact| contact.owned_identity == owned_identity && contact.contact_identity == contact_identity).ok_or(EngineError::UnknownContact)?;
+        (contact.display_name, contact.sort_key) = contact.format_display_name(format)?;
+        Ok(())
+    }
+
+    /// Sort index and id of the message a page ends before, `None` when that message does not exist
+    fn page_start(&self, before_message_id: Option<i64>) -> Option<Option<(f64, i64)>> {
+        match before_message_id {
+            Some(before_message_id) => self.messages.get(before_message_id).map(|before| Some((before.sort_index, before_message_id))),
+            None => Some(None),
+        }
+    }
+
+    /// Body and attachment file names of a message, what a search looks into
+    fn searchable_texts(&self, owned_identity: &[u8], message: &Message) -> Vec<String> {
+        let attachments = self.attachments.select(|attachment| match message.outbox_message_id {
+            Some(outbox_message_id) => attachment.direction == i64::from(AttachmentDirection::Outbound) && attachment.outbox_message_id == Some(outbox_message_id),
+            None => attachment.direction == i64::from(AttachmentDirection::Inbound)
+                && attachment.owned_identity == owned_identity
+                && message.message_uid.is_some()
+                && attachment.message_uid == message.message_uid,
+        });
+
+        message.body.iter().cloned().chain(attachments.into_iter().map(|attachment| attachment.file_name)).collect()
+    }
+
+    fn owned_identity_mut(&mut self, bytes_owned_identity: &[u8]) -> Option<&mut OwnedIdentity> {
+        self.owned_identities.find_mut(|owned_identity| owned_identity.bytes_owned_identity == bytes_owned_identity)
+    }
+
+    fn contact_mut(&mut self, owned_identity: &[u8], contact_identity: &[u8]) -> Option<&mut Contact> {
+        self.contacts.find_mut(|contact| contact.owned_identity == owned_identity && contact.contact_identity == contact_identity)
+    }
+}
+
+#[async_trait]
+impl Store for MemoryStore {
+    async fn begin(&self) -> Result<Box<dyn StoreTransaction + '_>> {
+        let data = Arc::clone(&self.data).lock_owned().await;
+        let snapshot = Some(data.clone());
+        Ok(Box::new(MemoryTransaction { data, snapshot }))
+    }
+
+    fn settings(&self) -> Box<dyn SettingsRepository + '_> { self.repository() }
+    fn owned_identities(&self) -> Box<dyn OwnedIdentityRepository + '_> { self.repository() }
+    fn owned_devices(&self) -> Box<dyn OwnedDeviceRepository + '_> { self.repository() }
+    fn keycloak_servers(&self) -> Box<dyn KeycloakServerRepository + '_> { self.repository() }
+    fn contacts(&self) -> Box<dyn ContactRepository + '_> { self.repository() }
+    fn blocked_contacts(&self) -> Box<dyn BlockedContactRepository + '_> { self.repository() }
+    fn contact_channels(&self) -> Box<dyn ContactChannelRepository + '_> { self.repository() }
+    fn introductions(&self) -> Box<dyn IntroductionRepository + '_> { self.repository() }
+    fn trust_establishments(&self) -> Box<dyn TrustEstablishmentRepository + '_> { self.repository() }
+    fn discussions(&self) -> Box<dyn DiscussionRepository + '_> { self.repository() }
+    fn messages(&self) -> Box<dyn MessageRepository + '_> { self.repository() }
+    fn message_expirations(&self) -> Box<dyn MessageExpirationRepository + '_> { self.repository() }
+    fn message_edits(&self) -> Box<dyn MessageEditRepository + '_> { self.repository() }
+    fn message_mentions(&self) -> Box<dyn MessageMentionRepository + '_> { self.repository() }
+    fn message_reactions(&self) -> Box<dyn MessageReactionRepository + '_> { self.repository() }
+    fn pending_message_operations(&self) -> Box<dyn PendingMessageOperationRepository + '_> { self.repository() }
+    fn groups(&self) -> Box<dyn GroupRepository + '_> { self.repository() }
+    fn group_members(&self) -> Box<dyn GroupMemberRepository + '_> { self.repository() }
+    fn attachments(&self) -> Box<dyn AttachmentRepository + '_> { self.repository() }
+    fn outbox_messages(&self) -> Box<dyn OutboxMessageRepository + '_> { self.repository() }
+    fn inbox_messages(&self) -> Box<dyn InboxMessageRepository + '_> { self.repository() }
+    fn return_receipts(&self) -> Box<dyn ReturnReceiptRepository + '_> { self.repository() }
+}
+
+/// Holds the data until committed or dropped, the data as it was at the start is restored when dropped
+pub struct MemoryTransaction {
+    data: OwnedMutexGuard<MemoryData>,
+    snapshot: Option<MemoryData>,
+}
+
+impl MemoryTransaction {
+    fn repository(&mut self) -> Box<MemoryRepository<'_>> {
+        Box::new(MemoryRepository::Transaction(&mut self.data))
+    }
+}
+
+impl Drop for MemoryTransaction {
+    fn drop(&mut self) {
+        if let Some(snapshot) = self.snapshot.take() {
+            *self.data = snapshot;
+        }
+    }
+}
+
+#[async_trait]
+impl StoreTransaction for MemoryTransaction {
+    async fn commit(mut self: Box<Self>) -> Result<()> {
+        self.snapshot = None;
+        Ok(())
+    }
+
+    fn settings(&mut self) -> Box<dyn SettingsRepository + '_> { self.repository() }
+    fn owned_identities(&mut self) -> Box<dyn OwnedIdentityRepository + '_> { self.repository() }
+    fn owned_devices(&mut self) -> Box<dyn OwnedDeviceRepository + '_> { self.repository() }
+    fn keycloak_servers(&mut self) -> Box<dyn KeycloakServerRepository + '_> { self.repository() }
+    fn contacts(&mut self) -> Box<dyn ContactRepository + '_> { self.repository() }
+    fn blocked_contacts(&mut self) -> Box<dyn BlockedContactRepository + '_> { self.repository() }
+    fn contact_channels(&mut self) -> Box<dyn ContactChannelRepository + '_> { self.repository() }
+    fn introductions(&mut self) -> Box<dyn IntroductionRepository + '_> { self.repository() }
+    fn trust_establishments(&mut self) -> Box<dyn TrustEstablishmentRepository + '_> { self.repository() }
+    fn discussions(&mut self) -> Box<dyn DiscussionRepository + '_> { self.repository() }
+    fn messages(&mut self) -> Box<dyn MessageRepository + '_> { self.repository() }
+    fn message_expirations(&mut self) -> Box<dyn MessageExpirationRepository + '_> { self.repository() }
+    fn message_edits(&mut self) -> Box<dyn MessageEditRepository + '_> { self.repository() }
+    fn message_mentions(&mut self) -> Box<dyn MessageMentionRepository + '_> { self.repository() }
+    fn message_reactions(&mut self) -> Box<dyn MessageReactionRepository + '_> { self.repository() }
+    fn pending_message_operations(&mut self) -> Box<dyn PendingMessageOperationRepository + '_> { self.repository() }
+    fn groups(&mut self) -> Box<dyn GroupRepository + '_> { self.repository() }
+    fn group_members(&mut self) -> Box<dyn GroupMemberRepository + '_> { self.repository() }
+    fn attachments(&mut self) -> Box<dyn AttachmentRepository + '_> { self.repository() }
+    fn outbox_messages(&mut self) -> Box<dyn OutboxMessageRepository + '_> { self.repository() }
+    fn inbox_messages(&mut self) -> Box<dyn InboxMessageRepository + '_> { self.repository() }
+    fn return_receipts(&mut self) -> Box<dyn ReturnReceiptRepository + '_> { self.repository() }
+}
+
+/// Locks the data for each operation, or works on the data held by the transaction
+enum MemoryRepository<'a> {
+    Store(&'a Mutex<MemoryData>),
+    Transaction(&'a mut MemoryData),
+}
+
+enum Data<'a> {
+    Locked(MutexGuard<'a, MemoryData>),
+    Borrowed(&'a mut MemoryData),
+}
+
+impl Deref for Data<'_> {
+    type Target = MemoryData;
+
+    fn deref(&self) -> &MemoryData {
+        match self {
+            Data::Locked(data) => data,
+            Data::Borrowed(data) => data,
+        }
+    }
+}
+
+impl DerefMut for Data<'_> {
+    fn deref_mut(&mut self) -> &mut MemoryData {
+        match self {
+            Data::Locked(data) => data,
+            Data::Borrowed(data) => data,
+        }
+    }
+}
+
+impl MemoryRepository<'_> {
+    async fn data(&mut self) -> Data<'_> {
+        match self {
+            MemoryRepository::Store(data) => Data::Locked(data.lock().await),
+            MemoryRepository::Transaction(data) => Data::Borrowed(data),
+        }
+    }
+}
+
+#[async_trait]
+impl SettingsRepository for MemoryRepository<'_> {
+    async fn get_display_name_format(&mut self) -> Result<DisplayNameFormat> {
+        Ok(self.data().await.format())
+    }
+
+    async fn set_display_name_format(&mut self, format: &DisplayNameFormat) -> Result<()> {
+        self.data().await.display_name_format = Some(*format);
+        Ok(())
+    }
+
+    async fn get_backup_key(&mut self) -> Result<Option<BackupKey>> {
+        Ok(self.data().await.backup_key.clone())
+    }
+
+    async fn set_backup_key(&mut self, backup_key: BackupKey) -> Result<()> {
+        self.data().await.backup_key = Some(backup_key);
+        Ok(())
+    }
+
+    async fn get_database_key(&mut self) -> Result<Option<DatabaseKey>> {
+        Ok(self.data().await.database_key.clone())
+    }
+
+    async fn set_database_key(&mut self, database_key: DatabaseKey) -> Result<()> {
+        self.data().await.database_key = Some(database_key);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl OwnedIdentityRepository for MemoryRepository<'_> {
+    async fn get_all(&mut self) -> Result<Vec<OwnedIdentity>> {
+        Ok(self.data().await.owned_identities.select(|_| true))
+    }
+
+    async fn get_by_identity(&mut self, bytes_owned_identity: &[u8]) -> Result<Option<OwnedIdentity>> {
+        Ok(self.data().await.owned_identities.find(|owned_identity| owned_identity.bytes_owned_identity == bytes_owned_identity))
+    }
+
+    async fn insert(&mut self, owned_identity: OwnedIdentity) -> Result<()> {
+        self.data().await.owned_identities.insert(|_| owned_identity);
+        Ok(())
+    }
+
+    async fn set_pref_send_read_receipt(&mut self, bytes_owned_identity: &[u8], pref_send_read_receipt: bool) -> Result<()> {
+        if let Some(owned_identity) = self.data().await.owned_identity_mut(bytes_owned_identity) {
+            owned_identity.pref_send_read_receipt = pref_send_read_receipt;
+        }
+        Ok(())
+    }
+
+    async fn set_pref_mute_notifications(&mut self, bytes_owned_identity: &[u8], mute: bool, until: Option<i64>, except_mentioned: bool) -> Result<()> {
+        if let Some(owned_identity) = self.data().await.owned_identity_mut(bytes_owned_identity) {
+            owned_identity.pref_mute_notifications = mute;
+            owned_identity.pref_mute_notifications_timestamp = until;
+            owned_identity.pref_mute_notifications_except_mentioned = except_mentioned;
+        }
+        Ok(())
+    }
+
+    async fn set_unpublished_details(&mut self, bytes_owned_identity: &[u8], identity_details: &JsonIdentityDetails, photo_url: Option<&str>) -> Result<()> {
+        let unpublished_identity_details = serde_json::to_string(identity_details)?;
+        if let Some(owned_identity) = self.data().await.owned_identity_mut(bytes_owned_identity) {
+            owned_identity.unpublished_identity_details = Some(unpublished_identity_details);
+            owned_identity.unpublished_photo_url = photo_url.map(str::to_owned);
+            owned_identity.unpublished_details = UNPUBLISHED_DETAILS_EXIST.into();
+        }
+        Ok(())
+    }
+
+    async fn publish_details(&mut self, bytes_owned_identity: &[u8], identity_details: &JsonIdentityDetails, display_name: &str, photo_url: Option<&str>) -> Result<i64> {
+        let identity_details = serde_json::to_string(identity_details)?;
+        let mut data = self.data().await;
+        let owned_identity = data.owned_identity_mut(bytes_owned_identity).ok_or(EngineError::UnknownOwnedIdentity)?;
+        owned_identity.identity_details = identity_details;
+        owned_identity.display_name = display_name.to_owned();
+        owned_identity.photo_url = photo_url.map(str::to_owned);
+        owned_identity.unpublished_identity_details = None;
+        owned_identity.unpublished_photo_url = None;
+        owned_identity.unpublished_details = UNPUBLISHED_DETAILS_NOTHING_NEW.into();
+        owned_identity.details_version += 1;
+        Ok(owned_identity.details_version)
+    }
+
+    async fn refresh_display_name(&mut self, bytes_owned_identity: &[u8], format: &DisplayNameFormat) -> Result<()> {
+        let mut data = self.data().await;
+        let owned_identity = data.owned_identity_mut(bytes_owned_identity).ok_or(EngineError::UnknownOwnedIdentity)?;
+        owned_identity.display_name = resolve_display_name(&owned_identity.get_identity_details()?, owned_identity.get_custom_display_name(), format);
+        Ok(())
+    }
+
+    async fn set_custom_display_name(&mut self, bytes_owned_identity: &[u8], custom_display_name: Option<&str>) -> Result<()> {
+        if let Some(owned_identity) = self.data().await.owned_identity_mut(bytes_owned_identity) {
+            owned_identity.custom_display_name = custom_display_name.map(str::to_owned);
+        }
+        Ok(())
+    }
+
+    async fn set_unlock_password(&mut self, bytes_owned_identity: &[u8], unlock_password: Option<(&[u8], &[u8])>) -> Result<()> {
+        if let Some(owned_identity) = self.data().await.owned_identity_mut(bytes_owned_identity) {
+            (owned_identity.unlock_password, owned_identity.unlock_salt) = unlock_password.map(|(password, salt)| (password.to_vec(), salt.to_vec())).unzip();
+        }
+        Ok(())
+    }
+
+    async fn set_active(&mut self, bytes_owned_identity: &[u8], active: bool) -> Result<()> {
+        if let Some(owned_identity) = self.data().await.owned_identity_mut(bytes_owned_identity) {
+            owned_identity.active = active;
+        }
+        Ok(())
+    }
+
+    async fn set_keycloak_managed(&mut self, bytes_owned_identity: &[u8], keycloak_managed: bool) -> Result<()> {
+        if let Some(owned_identity) = self.data().await.owned_identity_mut(bytes_owned_identity) {
+            owned_identity.keycloak_managed = keycloak_managed;
+        }
+        Ok(())
+    }
+
+    async fn set_api_key_status(&mut self, bytes_owned_identity: &[u8], api_key_status: &ApiKeyStatus) -> Result<()> {
+        if let Some(owned_identity) = self.data().await.owned_identity_mut(bytes_owned_identity) {
+            owned_identity.api_key_status = api_key_status.status;
+            owned_identity.api_key_permissions = api_key_status.permissions;
+            owned_identity.api_key_expiration_timestamp = api_key_status.expiration_timestamp;
+        }
+        Ok(())
+    }
+
+    async fn set_last_selection_timestamp(&mut self, bytes_owned_identity: &[u8], last_selection_timestamp: i64) -> Result<()> {
+        if let Some(owned_identity) = self.data().await.owned_identity_mut(bytes_owned_identity) {
+            owned_identity.last_selection_timestamp = last_selection_timestamp;
+        }
+        Ok(())
+    }
+
+    async fn delete(&mut self, bytes_owned_identity: &[u8]) -> Result<()> {
+        self.data().await.owned_identities.delete(|owned_identity| owned_identity.bytes_owned_identity == bytes_owned_identity);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl OwnedDeviceRepository for MemoryRepository<'_> {
+    async fn get_all(&mut self, owned_identity: &[u8]) -> Result<Vec<OwnedDevice>> {
+        let mut owned_devices = self.data().await.owned_devices.select(|owned_device| owned_device.owned_identity == owned_identity);
+        owned_devices.sort_by_key(|owned_device| owned_device.creation_timestamp);
+        Ok(owned_devices)
+    }
+
+    async fn insert_if_absent(&mut self, owned_device: OwnedDevice) -> Result<()> {
+        let mut data = self.data().await;
+        if data.owned_devices.find(|existing| existing.owned_identity == owned_device.owned_identity && existing.device_uid == owned_device.device_uid).is_none() {
+            data.owned_devices.insert(|id| OwnedDevice { id: Some(id), ..owned_device });
+        }
+        Ok(())
+    }
+
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()> {
+        self.data().await.owned_devices.delete(|owned_device| owned_device.owned_identity == owned_identity);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl KeycloakServerRepository for MemoryRepository<'_> {
+    async fn get(&mut self, owned_identity: &[u8]) -> Result<Option<KeycloakServer>> {
+        Ok(self.data().await.keycloak_servers.get(owned_identity).cloned())
+    }
+
+    async fn upsert(&mut self, keycloak_server: KeycloakServer) -> Result<()> {
+        self.data().await.keycloak_servers.insert(keycloak_server.owned_identity.clone(), keycloak_server);
+        Ok(())
+    }
+
+    async fn set_serialized_auth_state(&mut self, owned_identity: &[u8], serialized_auth_state: Option<&str>) -> Result<()> {
+        if let Some(keycloak_server) = self.data().await.keycloak_servers.get_mut(owned_identity) {
+            keycloak_server.serialized_auth_state = serialized_auth_state.map(str::to_owned);
+        }
+        Ok(())
+    }
+
+    async fn set_user_state(&mut self, owned_identity: &[u8], keycloak_user_id: &str, transfer_restricted: bool) -> Result<()> {
+        if let Some(keycloak_server) = self.data().await.keycloak_servers.get_mut(owned_identity) {
+            keycloak_server.keycloak_user_id = Some(keycloak_user_id.to_owned());
+            keycloak_server.transfer_restricted = transfer_restricted;
+        }
+        Ok(())
+    }
+
+    async fn set_latest_revocation_list_timestamp(&mut self, owned_identity: &[u8], latest_revocation_list_timestamp: i64) -> Result<()> {
+        if let Some(keycloak_server) = self.data().await.keycloak_servers.get_mut(owned_identity) {
+            keycloak_server.latest_revocation_list_timestamp = latest_revocation_list_timestamp;
+        }
+        Ok(())
+    }
+
+    async fn delete(&mut self, owned_identity: &[u8]) -> Result<()> {
+        self.data().await.keycloak_servers.remove(owned_identity);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ContactRepository for MemoryRepository<'_> {
+    async fn get(&mut self, owned_identity: &[u8], contact_identity: &[u8]) -> Result<Option<Contact>> {
+        Ok(self.data().await.contacts.find(|contact| contact.owned_identity == owned_identity && contact.contact_identity == contact_identity))
+    }
+
+    async fn get_all(&mut self, owned_identity: &[u8]) -> Result<Vec<Contact>> {
+        let mut contacts = self.data().await.contacts.select(|contact| contact.owned_identity == owned_identity);
+        contacts.sort_by(|a, b| (&a.sort_key, &a.display_name).cmp(&(&b.sort_key, &b.display_name)));
+        Ok(contacts)
+    }
+
+    async fn insert_if_absent(&mut self, contact: Contact) -> Result<bool> {
+        let mut data = self.data().await;
+        if data.contact_mut(&contact.owned_identity, &contact.contact_identity).is_some() {
+            return Ok(false);
+        }
+        data.contacts.insert(|id| Contact { id: Some(id), ..contact });
+        Ok(true)
+    }
+
+    async fn raise_trust_level(&mut self, owned_identity: &[u8], contact_identity: &[u8], trust_level: ContactTrustLevel) -> Result<bool> {
+        let mut data = self.data().await;
+        match data.contact_mut(owned_identity, contact_identity) {
+            Some(contact) if contact.trust_level < i64::from(trust_level) => {
+                contact.trust_level = trust_level.into();
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn revoke_trust(&mut self, owned_identity: &[u8], contact_identity: &[u8]) -> Result<bool> {
+        let mut data = self.data().await;
+        match data.contact_mut(owned_identity, contact_identity) {
+            Some(contact) if contact.trust_level > i64::from(ContactTrustLevel::Unverified) => {
+                contact.trust_level = ContactTrustLevel::Unverified.into();
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn set_published_details(&mut self, owned_identity: &[u8], contact_identity: &[u8], version: i64, published_identity_details: Option<&JsonIdentityDetails>) -> Result<()> {
+        let published_identity_details = published_identity_details.map(serde_json::to_string).transpose()?;
+        if let Some(contact) = self.data().await.contact_mut(owned_identity, contact_identity) {
+            contact.published_identity_details = published_identity_details;
+            contact.published_details_version = version;
+        }
+        Ok(())
+    }
+
+    async fn accept_published_details(&mut self, owned_identity: &[u8], contact_identity: &[u8]) -> Result<bool> {
+        let mut data = self.data().await;
+        let format = data.format();
+        let Some(contact) = data.contact_mut(owned_identity, contact_identity) else {
+            return Ok(false);
+        };
+        let Some(published_identity_details) = contact.published_identity_details.take() else {
+            return Ok(false);
+        };
+
+        contact.identity_details = published_identity_details;
+        data.refresh_contact_display_name(owned_identity, contact_identity, &format)?;
+
+        Ok(true)
+    }
+
+    async fn refresh_display_name(&mut self, owned_identity: &[u8], contact_identity: &[u8], format: &DisplayNameFormat) -> Result<()> {
+        self.data().await.refresh_contact_display_name(owned_identity, contact_identity, format)
+    }
+
+    async fn set_keycloak_managed(&mut self, owned_identity: &[u8], contact_identity: &[u8], signed_details: Option<&JsonIdentityDetails>) -> Result<()> {
+        let identity_details = signed_details.map(serde_json::to_string).transpose()?;
+        let mut data = self.data().await;
+        let format = data.format();
+        let Some(contact) = data.contact_mut(owned_identity, contact_identity) else {
+            return Ok(());
+        };
+
+        contact.keycloak_managed = identity_details.is_some();
+        if let Some(identity_details) = identity_details {
+            contact.identity_details = identity_details;
+            data.refresh_contact_display_name(owned_identity, contact_identity, &format)?;
+        }
+
+        Ok(())
+    }
+
+    async fn set_custom_display_name(&mut self, owned_identity: &[u8], contact_identity: &[u8], custom_display_name: Option<&str>) -> Result<()> {
+        if let Some(contact) = self.data().await.contact_mut(owned_identity, contact_identity) {
+            contact.custom_display_name = custom_display_name.map(str::to_owned);
+        }
+        Ok(())
+    }
+
+    async fn delete(&mut self, owned_identity: &[u8], contact_identity: &[u8]) -> Result<()> {
+        self.data().await.contacts.delete(|contact| contact.owned_identity == owned_identity && contact.contact_identity == contact_identity);
+        Ok(())
+    }
+
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()> {
+        self.data().await.contacts.delete(|contact| contact.owned_identity == owned_identity);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl BlockedContactRepository for MemoryRepository<'_> {
+    async fn get(&mut self, owned_identity: &[u8], contact_identity: &[u8]) -> Result<Option<BlockedContact>> {
+        Ok(self.data().await.blocked_contacts.find(|blocked| blocked.owned_identity == owned_identity && blocked.contact_identity == contact_identity))
+    }
+
+    async fn get_all(&mut self, owned_identity: &[u8]) -> Result<Vec<BlockedContact>> {
+        let mut blocked_contacts = self.data().await.blocked_contacts.select(|blocked| blocked.owned_identity == owned_identity);
+        blocked_contacts.sort_by_key(|blocked| (blocked.blocked_timestamp, blocked.id));
+        Ok(blocked_contacts)
+    }
+
+    async fn insert_if_absent(&mut self, blocked_contact: BlockedContact) -> Result<bool> {
+        let mut data = self.data().await;
+        let exists = |existing: &BlockedContact| existing.owned_identity == blocked_contact.owned_identity && existing.contact_identity == blocked_contact.contact_identity;
+        if data.blocked_contacts.find(exists).is_some() {
+            return Ok(false);
+        }
+        data.blocked_contacts.insert(|id| BlockedContact { id: Some(id), ..blocked_contact });
+        Ok(true)
+    }
+
+    async fn delete(&mut self, owned_identity: &[u8], contact_identity: &[u8]) -> Result<bool> {
+        let mut data = self.data().await;
+        let is_blocked = |blocked: &BlockedContact| blocked.owned_identity == owned_identity && blocked.contact_identity == contact_identity;
+        let existed = data.blocked_contacts.find(is_blocked).is_some();
+        data.blocked_contacts.delete(is_blocked);
+        Ok(existed)
+    }
+
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()> {
+        self.data().await.blocked_contacts.delete(|blocked| blocked.owned_identity == owned_identity);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ContactChannelRepository for MemoryRepository<'_> {
+    async fn get(&mut self, owned_identity: &[u8], contact_identity: &[u8]) -> Result<Option<ContactChannel>> {
+        Ok(self.data().await.contact_channels.find(|channel| channel.owned_identity == owned_identity && channel.contact_identity == contact_identity))
+    }
+
+    async fn insert_if_absent(&mut self, channel: ContactChannel) -> Result<bool> {
+        let mut data = self.data().await;
+        let exists = |existing: &ContactChannel| existing.owned_identity == channel.owned_identity && existing.contact_identity == channel.contact_identity;
+        if data.contact_channels.find(exists).is_some() {
+            return Ok(false);
+        }
+        data.contact_channels.insert(|id| ContactChannel { id: Some(id), ..channel });
+        Ok(true)
+    }
+
+    async fn delete(&mut self, owned_identity: &[u8], contact_identity: &[u8]) -> Result<bool> {
+        let mut data = self.data().await;
+        let is_channel = |channel: &ContactChannel| channel.owned_identity == owned_identity && channel.contact_identity == contact_identity;
+        let existed = data.contact_channels.find(is_channel).is_some();
+        data.contact_channels.delete(is_channel);
+        Ok(existed)
+    }
+
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()> {
+        self.data().await.contact_channels.delete(|channel| channel.owned_identity == owned_identity);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl IntroductionRepository for MemoryRepository<'_> {
+    async fn get_by_id(&mut self, id: i64) -> Result<Option<Introduction>> {
+        Ok(self.data().await.introductions.get(id))
+    }
+
+    async fn get_by_uid(&mut self, owned_identity: &[u8], introduction_uid: &[u8]) -> Result<Option<Introduction>> {
+        Ok(self.data().await.introductions.find(|introduction| introduction.owned_identity == owned_identity && introduction.introduction_uid == introduction_uid))
+    }
+
+    async fn get_all(&mut self, owned_identity: &[u8]) -> Result<Vec<Introduction>> {
+        let mut introductions = self.data().await.introductions.select(|introduction| introduction.owned_identity == owned_identity);
+        introductions.sort_by_key(|introduction| Reverse((introduction.creation_timestamp, introduction.id)));
+        Ok(introductions)
+    }
+
+    async fn insert_if_absent(&mut self, introduction: Introduction) -> Result<Option<i64>> {
+        let mut data = self.data().await;
+        if data.introductions.find(|existing| existing.owned_identity == introduction.owned_identity && existing.introduction_uid == introduction.introduction_uid).is_some() {
+            return Ok(None);
+        }
+        Ok(Some(data.introductions.insert(|id| Introduction { id: Some(id), ..introduction })))
+    }
+
+    async fn set_status(&mut self, id: i64, status: IntroductionStatus) -> Result<()> {
+        if let Some(introduction) = self.data().await.introductions.get_mut(id) {
+            introduction.status = status.into();
+        }
+        Ok(())
+    }
+
+    async fn set_contact_acceptance(&mut self, id: i64, identity_details: &JsonIdentityDetails, contact_signature: &[u8]) -> Result<()> {
+        let identity_details = serde_json::to_string(identity_details)?;
+        if let Some(introduction) = self.data().await.introductions.get_mut(id) {
+            introduction.identity_details = identity_details;
+            introduction.contact_signature = Some(contact_signature.to_vec());
+        }
+        Ok(())
+    }
+
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()> {
+        self.data().await.introductions.delete(|introduction| introduction.owned_identity == owned_identity);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl TrustEstablishmentRepository for MemoryRepository<'_> {
+    async fn get_by_id(&mut self, id: i64) -> Result<Option<TrustEstablishment>> {
+        Ok(self.data().await.trust_establishments.get(id))
+    }
+
+    async fn get_by_uid(&mut self, owned_identity: &[u8], protocol_uid: &[u8]) -> Result<Option<TrustEstablishment>> {
+        Ok(self.data().await.trust_establishments.find(|trust_establishment| trust_establishment.owned_identity == owned_identity && trust_establishment.protocol_uid == protocol_uid))
+    }
+
+    async fn get_all(&mut self, owned_identity: &[u8]) -> Result<Vec<TrustEstablishment>> {
+        let mut trust_establishments = self.data().await.trust_establishments.select(|trust_establishment| trust_establishment.owned_identity == owned_identity);
+        trust_establishments.sort_by_key(|trust_establishment| Reverse((trust_establishment.creation_timestamp, trust_establishment.id)));
+        Ok(trust_establishments)
+    }
+
+    async fn insert_if_absent(&mut self, trust_establishment: TrustEstablishment) -> Result<Option<i64>> {
+        let mut data = self.data().await;
+        if data.trust_establishments.find(|existing| existing.owned_identity == trust_establishment.owned_identity && existing.protocol_uid == trust_establishment.protocol_uid).is_some() {
+            return Ok(None);
+        }
+        Ok(Some(data.trust_establishments.insert(|id| TrustEstablishment { id: Some(id), ..trust_establishment })))
+    }
+
+    async fn set_status(&mut self, id: i64, status: TrustEstablishmentStatus) -> Result<()> {
+        if let Some(trust_establishment) = self.data().await.trust_establishments.get_mut(id) {
+            trust_establishment.status = status.into();
+        }
+        Ok(())
+    }
+
+    async fn set_own_seed(&mut self, id: i64, own_seed: &[u8]) -> Result<()> {
+        if let Some(trust_establishment) = self.data().await.trust_establishments.get_mut(id) {
+            trust_establishment.own_seed = Some(own_seed.to_vec());
+        }
+        Ok(())
+    }
+
+    async fn set_contact_seed(&mut self, id: i64, contact_seed: &[u8], identity_details: Option<&JsonIdentityDetails>) -> Result<()> {
+        let identity_details = identity_details.map(serde_json::to_string).transpose()?;
+        if let Some(trust_establishment) = self.data().await.trust_establishments.get_mut(id) {
+            trust_establishment.contact_seed = Some(contact_seed.to_vec());
+            if identity_details.is_some() {
+                trust_establishment.identity_details = identity_details;
+            }
+        }
+        Ok(())
+    }
+
+    async fn set_contact_confirmed(&mut self, id: i64) -> Result<()> {
+        if let Some(trust_establishment) = self.data().await.trust_establishments.get_mut(id) {
+            trust_establishment.contact_confirmed = true;
+        }
+        Ok(())
+    }
+
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()> {
+        self.data().await.trust_establishments.delete(|trust_establishment| trust_establishment.owned_identity == owned_identity);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl DiscussionRepository for MemoryRepository<'_> {
+    async fn get_by_id(&mut self, id: i64) -> Result<Option<Discussion>> {
+        Ok(self.data().await.discussions.get(id))
+    }
+
+    async fn get_one_to_one(&mut self, owned_identity: &[u8], contact_identity: &[u8]) -> Result<Option<Discussion>> {
+        Ok(self.data().await.discussions.find(|discussion| discussion.owned_identity == owned_identity && discussion.contact_identity.as_deref() == Some(contact_identity)))
+    }
+
+    async fn get_group(&mut self, owned_identity: &[u8], group_identifier: &[u8]) -> Result<Option<Discussion>> {
+        Ok(self.data().await.discussions.find(|discussion| discussion.owned_identity == owned_identity && discussion.group_identifier.as_deref() == Some(group_identifier)))
+    }
+
+    async fn get_all(&mut self, owned_identity: &[u8]) -> Result<Vec<Discussion>> {
+        let mut discussions = self.data().await.discussions.select(|discussion| discussion.owned_identity == owned_identity);
+        discussions.sort_by_key(|discussion| Reverse(discussion.last_message_timestamp));
+        Ok(discussions)
+    }
+
+    async fn insert(&mut self, discussion: Discussion) -> Result<i64> {
+        let mut data = self.data().await;
+        let conflicts = |existing: &Discussion| {
+            existing.owned_identity == discussion.owned_identity
+                && ((discussion.contact_identity.is_some() && existing.contact_identity == discussion.contact_identity)
+                    || (discussion.group_identifier.is_some() && existing.group_identifier == discussion.group_identifier))
+        };
+        if data.discussions.find(conflicts).is_some() {
+            return Err(EngineError::Technical);
+        }
+
+        // Shared settings start from their defaults, as the columns are not inserted
+        Ok(data.discussions.insert(|id| Discussion {
+            id: Some(id),
+            settings_version: 0,
+            settings_read_once: false,
+            settings_visibility_duration: None,
+            settings_existence_duration: None,
+            ..discussion
+        }))
+    }
+
+    async fn set_last_message_timestamp(&mut self, id: i64, last_message_timestamp: i64) -> Result<()> {
+        if let Some(discussion) = self.data().await.discussions.get_mut(id) {
+            discussion.last_message_timestamp = discussion.last_message_timestamp.max(last_message_timestamp);
+        }
+        Ok(())
+    }
+
+    async fn set_title(&mut self, id: i64, title: &str) -> Result<()> {
+        if let Some(discussion) = self.data().await.discussions.get_mut(id) {
+            discussion.title = title.to_owned();
+        }
+        Ok(())
+    }
+
+    async fn set_pref_send_read_receipt(&mut self, id: i64, pref_send_read_receipt: Option<bool>) -> Result<()> {
+        if let Some(discussion) = self.data().await.discussions.get_mut(id) {
+            discussion.pref_send_read_receipt = pref_send_read_receipt;
+        }
+        Ok(())
+    }
+
+    async fn update_shared_settings(&mut self, id: i64, settings_version: i64, ephemerality: &Ephemerality) -> Result<bool> {
+        let mut data = self.data().await;
+        match data.discussions.get_mut(id) {
+            Some(discussion) if discussion.settings_version < settings_version => {
+                discussion.settings_version = settings_version;
+                discussion.settings_read_once = ephemerality.read_once;
+                discussion.settings_visibility_duration = ephemerality.visibility_duration;
+                discussion.settings_existence_duration = ephemerality.existence_duration;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn delete(&mut self, id: i64) -> Result<()> {
+        self.data().await.discussions.rows.remove(&id);
+        Ok(())
+    }
+
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()> {
+        self.data().await.discussions.delete(|discussion| discussion.owned_identity == owned_identity);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl MessageRepository for MemoryRepository<'_> {
+    async fn get_by_id(&mut self, id: i64) -> Result<Option<Message>> {
+        Ok(self.data().await.messages.get(id))
+    }
+
+    async fn get_by_outbox_message(&mut self, outbox_message_id: i64) -> Result<Option<Message>> {
+        Ok(self.data().await.messages.find(|message| message.outbox_message_id == Some(outbox_message_id)))
+    }
+
+    async fn get_by_return_receipt_nonce(&mut self, owned_identity: &[u8], nonce: &[u8]) -> Result<Vec<Message>> {
+        let data = self.data().await;
+        let discussion_ids = data.discussion_ids(owned_identity);
+        Ok(data.messages.select(|message| {
+            discussion_ids.contains(&message.discussion_id)
+                && message.return_receipt_nonce.as_deref() == Some(nonce)
+                && message.direction == i64::from(MessageDirection::Outbound)
+        }))
+    }
+
+    async fn get_by_sender(&mut self, discussion_id: i64, sender_identity: &[u8], sender_thread_identifier: &Uuid, sender_sequence_number: i64) -> Result<Option<Message>> {
+        Ok(self.data().await.messages.find(|message| {
+            message.discussion_id == discussion_id
+                && message.sender_identity == sender_identity
+                && message.sender_thread_identifier == sender_thread_identifier.as_bytes()
+                && message.sender_sequence_number == sender_sequence_number
+        }))
+    }
+
+    async fn get_page(&mut self, discussion_id: i64, before_message_id: Option<i64>, limit: i64) -> Result<Vec<Message>> {
+        let data = self.data().await;
+        let Some(before) = data.page_start(before_message_id) else {
+            return Ok(vec![]);
+        };
+
+        let messages = data.messages.select(|message| message.discussion_id == discussion_id && is_before(message, before));
+        Ok(latest_first(messages, limit))
+    }
+
+    async fn get_mentioning_page(&mut self, owned_identity: &[u8], before_message_id: Option<i64>, limit: i64) -> Result<Vec<Message>> {
+        let data = self.data().await;
+        let Some(before) = data.page_start(before_message_id) else {
+            return Ok(vec![]);
+        };
+
+        let discussion_ids = data.discussion_ids(owned_identity);
+        let messages = data.messages.select(|message| discussion_ids.contains(&message.discussion_id) && message.mentioned && is_before(message, before));
+        Ok(latest_first(messages, limit))
+    }
+
+    async fn search(&mut self, owned_identity: &[u8], terms: &[String], query: &MessageSearchQuery, before_message_id: Option<i64>, limit: i64) -> Result<Vec<Message>> {
+        let data = self.data().await;
+        let Some(before) = data.page_start(before_message_id) else {
+            return Ok(vec![]);
+        };
+
+        let discussion_ids = data.discussion_ids(owned_identity);
+        let messages = data.messages.select(|message| {
+            let timestamp = message.server_timestamp.unwrap_or(message.local_timestamp);
+            discussion_ids.contains(&message.discussion_id)
+                && query.discussion_id.is_none_or(|discussion_id| message.discussion_id == discussion_id)
+                && query.sender_identity.as_ref().is_none_or(|sender_identity| message.sender_identity == *sender_identity)
+                && query.from_timestamp.is_none_or(|from_timestamp| timestamp >= from_timestamp)
+                && query.to_timestamp.is_none_or(|to_timestamp| timestamp < to_timestamp)
+                && is_before(message, before)
+                && search::contains_all(&data.searchable_texts(owned_identity, message).iter().map(String::as_str).collect::<Vec<_>>(), terms)
+        });
+        Ok(latest_first(messages, limit))
+    }
+
+    async fn get_next_sequence_number(&mut self, discussion_id: i64) -> Result<i64> {
+        let data = self.data().await;
+        let outbound_messages = data.messages.select(|message| message.discussion_id == discussion_id && message.direction == i64::from(MessageDirection::Outbound));
+        Ok(outbound_messages.iter().map(|message| message.sender_sequence_number).max().unwrap_or(0) + 1)
+    }
+
+    async fn insert_if_absent(&mut self, message: Message) -> Result<Option<i64>> {
+        let mut data = self.data().await;
+        let already_inserted = data.messages.find(|existing| {
+            existing.discussion_id == message.discussion_id
+                && existing.sender_identity == message.sender_identity
+                && existing.sender_thread_identifier == message.sender_thread_identifier
+                && existing.sender_sequence_number == message.sender_sequence_number
+        });
+        if already_inserted.is_some() {
+            return Ok(None);
+        }
+        Ok(Some(data.messages.insert(|id| Message { id: Some(id), ..message })))
+    }
+
+    async fn resolve_replies(&mut self, discussion_id: i64, reference: &MessageReference, message_id: i64) -> Result<()> {
+        let mut data = self.data().await;
+        let replies = data.messages.select(|message| {
+            message.discussion_id == discussion_id
+                && message.reply_to_message_id.is_none()
+                && message.reply_to_sender_identity.as_ref() == Some(&reference.sender_identity)
+                && message.reply_to_sender_thread_identifier.as_deref() == Some(reference.sender_thread_identifier.as_bytes().as_slice())
+                && message.reply_to_sender_sequence_number == Some(reference.sender_sequence_number)
+        });
+        for reply in replies.into_iter().filter_map(|reply| reply.id) {
+            if let Some(reply) = data.messages.get_mut(reply) {
+                reply.reply_to_message_id = Some(message_id);
+            }
+        }
+        Ok(())
+    }
+
+    async fn advance_outbound_status(&mut self, id: i64, status: MessageStatus) -> Result<bool> {
+        let mut data = self.data().await;
+        let Some(message) = data.messages.get_mut(id) else {
+            return Ok(false);
+        };
+        if message.direction != i64::from(MessageDirection::Outbound) {
+            return Ok(false);
+        }
+
+        let current_status = MessageStatus::try_from(message.status)?;
+        let is_earlier = matches!(current_status, MessageStatus::Queued | MessageStatus::Sent | MessageStatus::Delivered | MessageStatus::Failed)
+            && current_status.outbound_rank() < status.outbound_rank();
+        if is_earlier {
+            message.status = status.into();
+        }
+        Ok(is_earlier)
+    }
+
+    async fn mark_read(&mut self, id: i64) -> Result<bool> {
+        let mut data = self.data().await;
+        match data.messages.get_mut(id) {
+            Some(message) if message.status == i64::from(MessageStatus::Unread) => {
+                message.status = MessageStatus::Read.into();
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn wipe(&mut self, id: i64) -> Result<()> {
+        if let Some(message) = self.data().await.messages.get_mut(id) {
+            message.body = None;
+            message.attachment_metadata = None;
+            message.wiped = true;
+            message.mentioned = false;
+        }
+        Ok(())
+    }
+
+    async fn set_edited_body(&mut self, id: i64, body: &str, edit_timestamp: i64) -> Result<()> {
+        if let Some(message) = self.data().await.messages.get_mut(id) {
+            message.body = Some(body.to_owned());
+            message.edited = true;
+            message.edit_timestamp = Some(edit_timestamp);
+        }
+        Ok(())
+    }
+
+    async fn mark_remotely_deleted(&mut self, id: i64) -> Result<()> {
+        if let Some(message) = self.data().await.messages.get_mut(id) {
+            message.body = None;
+            message.attachment_metadata = None;
+            message.wiped = true;
+            message.remotely_deleted = true;
+            message.mentioned = false;
+        }
+        Ok(())
+    }
+
+    async fn delete(&mut self, id: i64) -> Result<()> {
+        self.data().await.messages.rows.remove(&id);
+        Ok(())
+    }
+
+    async fn delete_by_discussion(&mut self, discussion_id: i64) -> Result<()> {
+        self.data().await.messages.delete(|message| message.discussion_id == discussion_id);
+        Ok(())
+    }
+
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()> {
+        let mut data = self.data().await;
+        let discussion_ids = data.discussion_ids(owned_identity);
+        data.messages.delete(|message| discussion_ids.contains(&message.discussion_id));
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl MessageExpirationRepository for MemoryRepository<'_> {
+    async fn get_due(&mut self, timestamp: i64) -> Result<Vec<MessageExpiration>> {
+        let mut expirations = self.data().await.message_expirations.select(|expiration| expiration.expiration_timestamp <= timestamp);
+        expirations.sort_by_key(|expiration| (expiration.wipe_only, expiration.expiration_timestamp));
+        Ok(expirations)
+    }
+
+    async fn get_next_expiration_timestamp(&mut self) -> Result<Option<i64>> {
+        Ok(self.data().await.message_expirations.rows.values().map(|expiration| expiration.expiration_timestamp).min())
+    }
+
+    async fn insert(&mut self, expiration: MessageExpiration) -> Result<i64> {
+        Ok(self.data().await.message_expirations.insert(|id| MessageExpiration { id: Some(id), ..expiration }))
+    }
+
+    async fn delete(&mut self, id: i64) -> Result<()> {
+        self.data().await.message_expirations.rows.remove(&id);
+        Ok(())
+    }
+
+    async fn delete_by_message(&mut self, message_id: i64) -> Result<()> {
+        self.data().await.message_expirations.delete(|expiration| expiration.message_id == message_id);
+        Ok(())
+    }
+
+    async fn delete_by_discussion(&mut self, discussion_id: i64) -> Result<()> {
+        let mut data = self.data().await;
+        let message_ids = data.discussion_message_ids(discussion_id);
+        data.message_expirations.delete(|expiration| message_ids.contains(&expiration.message_id));
+        Ok(())
+    }
+
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()> {
+        let mut data = self.data().await;
+        let message_ids = data.message_ids(owned_identity);
+        data.message_expirations.delete(|expiration| message_ids.contains(&expiration.message_id));
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl MessageEditRepository for MemoryRepository<'_> {
+    async fn get_all(&mut self, message_id: i64) -> Result<Vec<MessageEdit>> {
+        let mut edits = self.data().await.message_edits.select(|edit| edit.message_id == message_id);
+        edits.sort_by_key(|edit| (edit.timestamp, edit.id));
+        Ok(edits)
+    }
+
+    async fn insert(&mut self, edit: MessageEdit) -> Result<i64> {
+        Ok(self.data().await.message_edits.insert(|id| MessageEdit { id: Some(id), ..edit }))
+    }
+
+    async fn delete_by_message(&mut self, message_id: i64) -> Result<()> {
+        self.data().await.message_edits.delete(|edit| edit.message_id == message_id);
+        Ok(())
+    }
+
+    async fn delete_by_discussion(&mut self, discussion_id: i64) -> Result<()> {
+        let mut data = self.data().await;
+        let message_ids = data.discussion_message_ids(discussion_id);
+        data.message_edits.delete(|edit| message_ids.contains(&edit.message_id));
+        Ok(())
+    }
+
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()> {
+        let mut data = self.data().await;
+        let message_ids = data.message_ids(owned_identity);
+        data.message_edits.delete(|edit| message_ids.contains(&edit.message_id));
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl MessageMentionRepository for MemoryRepository<'_> {
+    async fn get_all(&mut self, message_id: i64) -> Result<Vec<MessageMention>> {
+        let mut mentions = self.data().await.message_mentions.select(|mention| mention.message_id == message_id);
+        mentions.sort_by_key(|mention| (mention.range_start, mention.id));
+        Ok(mentions)
+    }
+
+    async fn insert(&mut self, mention: MessageMention) -> Result<i64> {
+        Ok(self.data().await.message_mentions.insert(|id| MessageMention { id: Some(id), ..mention }))
+    }
+
+    async fn delete_by_message(&mut self, message_id: i64) -> Result<()> {
+        self.data().await.message_mentions.delete(|mention| mention.message_id == message_id);
+        Ok(())
+    }
+
+    async fn delete_by_discussion(&mut self, discussion_id: i64) -> Result<()> {
+        let mut data = self.data().await;
+        let message_ids = data.discussion_message_ids(discussion_id);
+        data.message_mentions.delete(|mention| message_ids.contains(&mention.message_id));
+        Ok(())
+    }
+
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()> {
+        let mut data = self.data().await;
+        let message_ids = data.message_ids(owned_identity);
+        data.message_mentions.delete(|mention| message_ids.contains(&mention.message_id));
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl MessageReactionRepository for MemoryRepository<'_> {
+    async fn get_all(&mut self, message_id: i64) -> Result<Vec<MessageReaction>> {
+        let mut reactions = self.data().await.message_reactions.select(|reaction| reaction.message_id == message_id);
+        reactions.sort_by_key(|reaction| (reaction.timestamp, reaction.id));
+        Ok(reactions)
+    }
+
+    async fn upsert(&mut self, reaction: MessageReaction) -> Result<bool> {
+        let mut data = self.data().await;
+        match data.message_reactions.find_mut(|existing| existing.message_id == reaction.message_id && existing.reactor_identity == reaction.reactor_identity) {
+            Some(existing) if existing.timestamp >= reaction.timestamp => Ok(false),
+            Some(existing) => {
+                existing.reaction = reaction.reaction;
+                existing.timestamp = reaction.timestamp;
+                Ok(true)
+            }
+            None => {
+                data.message_reactions.insert(|id| MessageReaction { id: Some(id), ..reaction });
+                Ok(true)
+            }
+        }
+    }
+
+    async fn delete_by_message(&mut self, message_id: i64) -> Result<()> {
+        self.data().await.message_reactions.delete(|reaction| reaction.message_id == message_id);
+        Ok(())
+    }
+
+    async fn delete_by_discussion(&mut self, discussion_id: i64) -> Result<()> {
+        let mut data = self.data().await;
+        let message_ids = data.discussion_message_ids(discussion_id);
+        data.message_reactions.delete(|reaction| message_ids.contains(&reaction.message_id));
+        Ok(())
+    }
+
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()> {
+        let mut data = self.data().await;
+        let message_ids = data.message_ids(owned_identity);
+        data.message_reactions.delete(|reaction| message_ids.contains(&reaction.message_id));
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl PendingMessageOperationRepository for MemoryRepository<'_> {
+    async fn get_by_message(&mut self, discussion_id: i64, sender_identity: &[u8], sender_thread_identifier: &Uuid, sender_sequence_number: i64) -> Result<Vec<PendingMessageOperation>> {
+        let mut operations = self.data().await.pending_message_operations.select(|operation| {
+            operation.discussion_id == discussion_id
+                && operation.sender_identity == sender_identity
+                && operation.sender_thread_identifier == sender_thread_identifier.as_bytes()
+                && operation.sender_sequence_number == sender_sequence_number
+        });
+        operations.sort_by_key(|operation| (operation.server_timestamp, operation.id));
+        Ok(operations)
+    }
+
+    async fn insert(&mut self, operation: PendingMessageOperation) -> Result<i64> {
+        Ok(self.data().await.pending_message_operations.insert(|id| PendingMessageOperation { id: Some(id), ..operation }))
+    }
+
+    async fn delete(&mut self, id: i64) -> Result<()> {
+        self.data().await.pending_message_operations.rows.remove(&id);
+        Ok(())
+    }
+
+    async fn delete_by_discussion(&mut self, discussion_id: i64) -> Result<()> {
+        self.data().await.pending_message_operations.delete(|operation| operation.discussion_id == discussion_id);
+        Ok(())
+    }
+
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()> {
+        let mut data = self.data().await;
+        let discussion_ids = data.discussion_ids(owned_identity);
+        data.pending_message_operations.delete(|operation| discussion_ids.contains(&operation.discussion_id));
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl GroupRepository for MemoryRepository<'_> {
+    async fn get_by_id(&mut self, id: i64) -> Result<Option<Group>> {
+        Ok(self.data().await.groups.get(id))
+    }
+
+    async fn get_by_uid(&mut self, owned_identity: &[u8], group_uid: &[u8]) -> Result<Option<Group>> {
+        Ok(self.data().await.groups.find(|group| group.owned_identity == owned_identity && group.group_uid == group_uid))
+    }
+
+    async fn get_all(&mut self, owned_identity: &[u8]) -> Result<Vec<Group>> {
+        let mut groups = self.data().await.groups.select(|group| group.owned_identity == owned_identity);
+        groups.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(groups)
+    }
+
+    async fn upsert(&mut self, group: Group) -> Result<i64> {
+        let mut data = self.data().await;
+        match data.groups.find_mut(|existing| existing.owned_identity == group.owned_identity && existing.group_uid == group.group_uid) {
+            Some(existing) => {
+                existing.name = group.name;
+                existing.version = group.version;
+                existing.blob_key = group.blob_key;
+                existing.status = existing.status.max(group.status);
+                existing.id.ok_or(EngineError::Technical)
+            }
+            None => Ok(data.groups.insert(|id| Group { id: Some(id), ..group })),
+        }
+    }
+
+    async fn set_status(&mut self, id: i64, status: GroupStatus) -> Result<()> {
+        if let Some(group) = self.data().await.groups.get_mut(id) {
+            group.status = status.into();
+        }
+        Ok(())
+    }
+
+    async fn delete(&mut self, id: i64) -> Result<()> {
+        let mut data = self.data().await;
+        data.group_members.delete(|member| member.group_id == id);
+        data.groups.rows.remove(&id);
+        Ok(())
+    }
+
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()> {
+        let mut data = self.data().await;
+        let group_ids = data.groups.select(|group| group.owned_identity == owned_identity).into_iter().filter_map(|group| group.id).collect::<Vec<i64>>();
+        data.group_members.delete(|member| group_ids.contains(&member.group_id));
+        data.groups.delete(|group| group.owned_identity == owned_identity);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl GroupMemberRepository for MemoryRepository<'_> {
+    async fn get_all(&mut self, group_id: i64) -> Result<Vec<GroupMember>> {
+        Ok(self.data().await.group_members.select(|member| member.group_id == group_id))
+    }
+
+    async fn get(&mut self, group_id: i64, member_identity: &[u8]) -> Result<Option<GroupMember>> {
+        Ok(self.data().await.group_members.find(|member| member.group_id == group_id && member.member_identity == member_identity))
+    }
+
+    async fn replace_all(&mut self, group_id: i64, members: Vec<GroupMember>) -> Result<()> {
+        let mut data = self.data().await;
+        data.group_members.delete(|member| member.group_id == group_id);
+        for member in members {
+            if data.group_members.find(|existing| existing.group_id == group_id && existing.member_identity == member.member_identity).is_some() {
+                return Err(EngineError::Technical);
+            }
+            data.group_members.insert(|id| GroupMember { id: Some(id), group_id, ..member });
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl AttachmentRepository for MemoryRepository<'_> {
+    async fn get_by_id(&mut self, id: i64) -> Result<Option<Attachment>> {
+        Ok(self.data().await.attachments.get(id))
+    }
+
+    async fn get_by_outbox_message(&mut self, outbox_message_id: i64) -> Result<Vec<Attachment>> {
+        let mut attachments = self.data().await.attachments.select(|attachment| attachment.outbox_message_id == Some(outbox_message_id));
+        attachments.sort_by_key(|attachment| attachment.attachment_number);
+        Ok(attachments)
+    }
+
+    async fn get_inbound_by_message(&mut self, owned_identity: &[u8], message_uid: &[u8]) -> Result<Vec<Attachment>> {
+        let mut attachments = self.data().await.attachments.select(|attachment| {
+            attachment.owned_identity == owned_identity
+                && attachment.message_uid.as_deref() == Some(message_uid)
+                && attachment.direction == i64::from(AttachmentDirection::Inbound)
+        });
+        attachments.sort_by_key(|attachment| attachment.attachment_number);
+        Ok(attachments)
+    }
+
+    async fn get_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<Vec<Attachment>> {
+        Ok(self.data().await.attachments.select(|attachment| attachment.owned_identity == owned_identity))
+    }
+
+    async fn get_by_discussion(&mut self, discussion_id: i64) -> Result<Vec<Attachment>> {
+        let data = self.data().await;
+        let Some(discussion) = data.discussions.get(discussion_id) else {
+            return Ok(Vec::new());
+        };
+        let messages = data.messages.select(|message| message.discussion_id == discussion_id);
+
+        Ok(data.attachments.select(|attachment| {
+            messages.iter().any(|message| {
+                (message.outbox_message_id.is_some() && attachment.outbox_message_id == message.outbox_message_id)
+                    || (attachment.direction == i64::from(AttachmentDirection::Inbound)
+                        && attachment.owned_identity == discussion.owned_identity
+                        && message.message_uid.is_some()
+                        && attachment.message_uid == message.message_uid)
+            })
+        }))
+    }
+
+    async fn get_due(&mut self, timestamp: i64) -> Result<Vec<Attachment>> {
+        let mut attachments = self.data().await.attachments.select(|attachment| is_transferable(attachment) && attachment.next_attempt_timestamp <= timestamp);
+        attachments.sort_by_key(|attachment| attachment.next_attempt_timestamp);
+        Ok(attachments)
+    }
+
+    async fn get_next_attempt_timestamp(&mut self) -> Result<Option<i64>> {
+        Ok(self.data().await.attachments.rows.values().filter(|attachment| is_transferable(attachment)).map(|attachment| attachment.next_attempt_timestamp).min())
+    }
+
+    async fn insert(&mut self, attachment: Attachment) -> Result<i64> {
+        Ok(self.data().await.attachments.insert(|id| Attachment { id: Some(id), ..attachment }))
+    }
+
+    async fn link_to_outbox_message(&mut self, id: i64, outbox_message_id: i64, attachment_number: i64) -> Result<()> {
+        let mut data = self.data().await;
+        match data.attachments.get_mut(id) {
+            Some(attachment) if attachment.status == i64::from(AttachmentStatus::Pending) && attachment.direction == i64::from(AttachmentDirection::Outbound) => {
+                attachment.outbox_message_id = Some(outbox_message_id);
+                attachment.attachment_number = attachment_number;
+                attachment.status = AttachmentStatus::InProgress.into();
+                Ok(())
+            }
+            _ => Err(EngineError::Technical),
+        }
+    }
+
+    async fn set_upload_urls(&mut self, id: i64, message_uid: &[u8], upload_urls: &[String]) -> Result<()> {
+        let upload_urls = serde_json::to_string(upload_urls)?;
+        if let Some(attachment) = self.data().await.attachments.get_mut(id) {
+            attachment.message_uid = Some(message_uid.to_vec());
+            attachment.upload_urls = Some(upload_urls);
+        }
+        Ok(())
+    }
+
+    async fn set_transferred_chunk_count(&mut self, id: i64, transferred_chunk_count: i64) -> Result<()> {
+        if let Some(attachment) = self.data().await.attachments.get_mut(id) {
+            attachment.transferred_chunk_count = transferred_chunk_count;
+            attachment.attempt_count = 0;
+        }
+        Ok(())
+    }
+
+    async fn schedule_retry(&mut self, id: i64, attempt_count: i64, next_attempt_timestamp: i64) -> Result<()> {
+        if let Some(attachment) = self.data().await.attachments.get_mut(id) {
+            attachment.attempt_count = attempt_count;
+            attachment.next_attempt_timestamp = next_attempt_timestamp;
+        }
+        Ok(())
+    }
+
+    async fn set_status(&mut self, id: i64, status: AttachmentStatus) -> Result<()> {
+        if let Some(attachment) = self.data().await.attachments.get_mut(id) {
+            attachment.status = status.into();
+        }
+        Ok(())
+    }
+
+    async fn delete(&mut self, id: i64) -> Result<()> {
+        self.data().await.attachments.rows.remove(&id);
+        Ok(())
+    }
+
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()> {
+        self.data().await.attachments.delete(|attachment| attachment.owned_identity == owned_identity);
+        Ok(())
+    }
+}
+
+/// In progress, and outbound ones have their upload URLs
+fn is_transferable(attachment: &Attachment) -> bool {
+    attachment.status == i64::from(AttachmentStatus::InProgress)
+        && (attachment.direction == i64::from(AttachmentDirection::Inbound) || attachment.upload_urls.is_some())
+}
+
+#[async_trait]
+impl OutboxMessageRepository for MemoryRepository<'_> {
+    async fn get_by_id(&mut self, id: i64) -> Result<Option<OutboxMessage>> {
+        Ok(self.data().await.outbox_messages.get(id))
+    }
+
+    async fn get_due(&mut self, timestamp: i64) -> Result<Vec<OutboxMessage>> {
+        let mut outbox_messages = self.data().await.outbox_messages.select(|outbox_message| {
+            outbox_message.delivery_state == i64::from(DeliveryState::Queued) && outbox_message.next_attempt_timestamp <= timestamp
+        });
+        outbox_messages.sort_by_key(|outbox_message| outbox_message.next_attempt_timestamp);
+        Ok(outbox_messages)
+    }
+
+    async fn get_next_attempt_timestamp(&mut self) -> Result<Option<i64>> {
+        Ok(self.data().await.outbox_messages.rows.values()
+            .filter(|outbox_message| outbox_message.delivery_state == i64::from(DeliveryState::Queued))
+            .map(|outbox_message| outbox_message.next_attempt_timestamp)
+            .min())
+    }
+
+    async fn insert(&mut self, outbox_message: OutboxMessage) -> Result<i64> {
+        Ok(self.data().await.outbox_messages.insert(|id| OutboxMessage { id: Some(id), ..outbox_message }))
+    }
+
+    async fn set_upload_nonce(&mut self, id: i64, upload_nonce: &[u8]) -> Result<()> {
+        if let Some(outbox_message) = self.data().await.outbox_messages.get_mut(id) {
+            outbox_message.upload_nonce = Some(upload_nonce.to_vec());
+        }
+        Ok(())
+    }
+
+    async fn mark_sent(&mut self, id: i64, attempt_count: i64, message_uid: &[u8]) -> Result<()> {
+        if let Some(outbox_message) = self.data().await.outbox_messages.get_mut(id) {
+            outbox_message.delivery_state = DeliveryState::Sent.into();
+            outbox_message.attempt_count = attempt_count;
+            outbox_message.message_uid = Some(message_uid.to_vec());
+        }
+        Ok(())
+    }
+
+    async fn schedule_retry(&mut self, id: i64, attempt_count: i64, next_attempt_timestamp: i64) -> Result<()> {
+        if let Some(outbox_message) = self.data().await.outbox_messages.get_mut(id) {
+            outbox_message.attempt_count = attempt_count;
+            outbox_message.next_attempt_timestamp = next_attempt_timestamp;
+        }
+        Ok(())
+    }
+
+    async fn mark_failed(&mut self, id: i64, attempt_count: i64) -> Result<()> {
+        if let Some(outbox_message) = self.data().await.outbox_messages.get_mut(id) {
+            outbox_message.delivery_state = DeliveryState::Failed.into();
+            outbox_message.attempt_count = attempt_count;
+        }
+        Ok(())
+    }
+
+    async fn set_delivery_state(&mut self, id: i64, delivery_state: DeliveryState) -> Result<()> {
+        if let Some(outbox_message) = self.data().await.outbox_messages.get_mut(id) {
+            outbox_message.delivery_state = delivery_state.into();
+        }
+        Ok(())
+    }
+
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()> {
+        self.data().await.outbox_messages.delete(|outbox_message| outbox_message.from_identity == owned_identity);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl InboxMessageRepository for MemoryRepository<'_> {
+    async fn insert_if_absent(&mut self, owned_identity: &[u8], message: &DownloadedMessage) -> Result<bool> {
+        let mut data = self.data().await;
+        if data.inbox_messages.find(|existing| existing.owned_identity == owned_identity && existing.message_uid == message.message_uid).is_some() {
+            return Ok(false);
+        }
+
+        data.inbox_messages.insert(|id| InboxMessage {
+            id,
+            owned_identity: owned_identity.to_vec(),
+            message_uid: message.message_uid.clone(),
+            server_timestamp: message.server_timestamp,
+            wrapped_key: message.wrapped_key.clone(),
+            encrypted_content: message.encrypted_content.clone(),
+            attachment_count: message.attachment_count,
+            processed: false,
+            reception_timestamp: current_timestamp(),
+        });
+        Ok(true)
+    }
+
+    async fn get_by_message_uid(&mut self, owned_identity: &[u8], message_uid: &[u8]) -> Result<Option<InboxMessage>> {
+        Ok(self.data().await.inbox_messages.find(|inbox_message| inbox_message.owned_identity == owned_identity && inbox_message.message_uid == message_uid))
+    }
+
+    async fn get_unprocessed(&mut self, owned_identity: &[u8]) -> Result<Vec<InboxMessage>> {
+        let mut inbox_messages = self.data().await.inbox_messages.select(|inbox_message| inbox_message.owned_identity == owned_identity && !inbox_message.processed);
+        inbox_messages.sort_by_key(|inbox_message| (inbox_message.server_timestamp, inbox_message.id));
+        Ok(inbox_messages)
+    }
+
+    async fn mark_processed(&mut self, id: i64) -> Result<()> {
+        if let Some(inbox_message) = self.data().await.inbox_messages.get_mut(id) {
+            inbox_message.processed = true;
+        }
+        Ok(())
+    }
+
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()> {
+        self.data().await.inbox_messages.delete(|inbox_message| inbox_message.owned_identity == owned_identity);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ReturnReceiptRepository for MemoryRepository<'_> {
+    async fn get_due(&mut self, timestamp: i64) -> Result<Vec<OutboxReturnReceipt>> {
+        let mut return_receipts = self.data().await.return_receipts.select(|return_receipt| return_receipt.next_attempt_timestamp <= timestamp);
+        return_receipts.sort_by_key(|return_receipt| return_receipt.next_attempt_timestamp);
+        Ok(return_receipts)
+    }
+
+    async fn get_next_attempt_timestamp(&mut self) -> Result<Option<i64>> {
+        Ok(self.data().await.return_receipts.rows.values().map(|return_receipt| return_receipt.next_attempt_timestamp).min())
+    }
+
+    async fn insert(&mut self, return_receipt: OutboxReturnReceipt) -> Result<i64> {
+        Ok(self.data().await.return_receipts.insert(|id| OutboxReturnReceipt { id: Some(id), ..return_receipt }))
+    }
+
+    async fn schedule_retry(&mut self, id: i64, attempt_count: i64, next_attempt_timestamp: i64) -> Result<()> {
+        if let Some(return_receipt) = self.data().await.return_receipts.get_mut(id) {
+            return_receipt.attempt_count = attempt_count;
+            return_receipt.next_attempt_timestamp = next_attempt_timestamp;
+        }
+        Ok(())
+    }
+
+    async fn delete(&mut self, id: i64) -> Result<()> {
+        self.data().await.return_receipts.rows.remove(&id);
+        Ok(())
+    }
+
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()> {
+        self.data().await.return_receipts.delete(|return_receipt| return_receipt.owned_identity == owned_identity);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::entities::{contact::Contact, identity::{DisplayNameFormat, JsonIdentityDetails}};
+
+    use super::{MemoryStore, Store};
+
+    #[tokio::test]
+    async fn dropped_transaction_is_rolled_back() {
+        let store = MemoryStore::new();
+        let identity_details = JsonIdentityDetails::builder().first_name("Bob".to_owned()).build();
+        let contact = Contact::new(&[1; 32], &[2; 32], &identity_details, &DisplayNameFormat::default()).unwrap();
+
+        let mut transaction = store.begin().await.unwrap();
+        assert!(transaction.contacts().insert_if_absent(contact.clone()).await.unwrap());
+        drop(transaction);
+        assert!(store.contacts().get_all(&[1; 32]).await.unwrap().is_empty());
+
+        let mut transaction = store.begin().await.unwrap();
+        assert!(transaction.contacts().insert_if_absent(contact.clone()).await.unwrap());
+        transaction.commit().await.unwrap();
+        assert!(!store.contacts().insert_if_absent(contact).await.unwrap());
+        assert_eq!(store.contacts().get_all(&[1; 32]).await.unwrap().len(), 1);
+    }
+}
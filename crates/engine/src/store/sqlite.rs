@@ -0,0 +1,1194 @@
+use std::{ops::{Deref, DerefMut}, sync::Arc};
+
+use async_trait::async_trait;
+use sqlx::{migrate::MigrateDatabase, pool::PoolConnection, sqlite::SqlitePoolOptions, Sqlite, SqliteConnection, SqlitePool};
+use uuid::Uuid;
+
+use crate::{
+    entities::{
+        attachment::{Attachment, AttachmentDirection, AttachmentStatus},
+        backup_key::BackupKey,
+        blocked_contact::BlockedContact,
+        contact::{Contact, ContactTrustLevel},
+        contact_channel::ContactChannel,
+        database_key::DatabaseKey,
+        discussion::{Discussion, Ephemerality},
+        group::{Group, GroupMember, GroupStatus},
+        identity::{resolve_display_name, DisplayNameFormat, JsonIdentityDetails, OwnedIdentity},
+        inbox_message::InboxMessage,
+        introduction::{Introduction, IntroductionStatus},
+        keycloak_server::KeycloakServer,
+        message::{Message, MessageReference, MessageStatus},
+        message_edit::MessageEdit,
+        message_mention::MessageMention,
+        message_expiration::MessageExpiration,
+        message_reaction::MessageReaction,
+        message_search::MessageSearch,
+        outbox_message::{DeliveryState, OutboxMessage},
+        owned_device::OwnedDevice,
+        pending_message_operation::PendingMessageOperation,
+        return_receipt::OutboxReturnReceipt,
+        trust_establishment::{TrustEstablishment, TrustEstablishmentStatus},
+    },
+    search,
+    server::{ApiKeyStatus, DownloadedMessage},
+    EngineError, Result,
+};
+
+use super::{encryption::{ColumnCipher, DatabaseSecret, SensitiveColumns}, *};
+
+/// Store backed by the SQLite database of the engine
+#[derive(Clone)]
+pub struct SqliteStore {
+    db: SqlitePool,
+    /// Set when the sensitive columns are encrypted
+    cipher: Option<Arc<ColumnCipher>>,
+}
+
+impl SqliteStore {
+    /// Creates the database when needed and applies the migrations, `sqlite::memory:` gives a database that leaves nothing on disk.
+    ///
+    /// With a secret, the sensitive columns are encrypted with a database key wrapped by it, which is generated on the
+    /// first connection. A database encrypted once can't be opened without its secret.
+    pub async fn connect(database_url: &str, secret: Option<&DatabaseSecret>) -> Result<Self> {
+        if database_url.contains(":memory:") {
+            // Each connection to an in-memory database gets its own database, so keep a single one alive
+            let db = SqlitePoolOptions::new()
+                .max_connections(1)
+                .idle_timeout(None)
+                .max_lifetime(None)
+                .connect(database_url)
+                .await?;
+            return Self::with_pool(db, secret).await;
+        }
+
+        if !Sqlite::database_exists(database_url).await.unwrap_or(false) {
+            println!("Creating database {}", database_url);
+            match Sqlite::create_database(database_url).await {
+                Ok(_) => println!("Create db success"),
+                Err(error) => panic!("error: {}", error),
+            }
+        } else {
+            println!("Database already exists");
+        }
+
+        // Transactions read before they write, another connection committing in between would make them fail
+        let db = SqlitePoolOptions::new().max_connections(1).connect(database_url).await?;
+        Self::with_pool(db, secret).await
+    }
+
+    /// Applies the migrations to an existing pool, see `connect` for the secret
+    pub async fn with_pool(db: SqlitePool, secret: Option<&DatabaseSecret>) -> Result<Self> {
+        sqlx::migrate!().run(&db).await.map_err(|err| EngineError::Persistence(sqlx::Error::Migrate(Box::new(err))))?;
+
+        let cipher = match (DatabaseKey::get(&db).await?, secret) {
+            (Some(database_key), Some(secret)) => {
+                let cipher = ColumnCipher::new(encryption::unwrap_database_key(&database_key, secret).await?);
+                Self::encrypt_new_columns(&db, &cipher).await?;
+                Some(cipher)
+            }
+            (Some(_), None) => return Err(EngineError::DatabaseLocked),
+            (None, Some(secret)) => Some(Self::encrypt_columns(&db, secret).await?),
+            (None, None) => None,
+        };
+
+        let store = Self { db, cipher: cipher.map(Arc::new) };
+        store.index_unindexed_messages().await?;
+
+        Ok(store)
+    }
+
+    /// Indexes the messages saved before the search index existed, or all of them once the columns got encrypted
+    async fn index_unindexed_messages(&self) -> Result<()> {
+        let mut repository = self.repository();
+        for message_id in MessageSearch::get_unindexed_message_ids(&self.db).await? {
+            repository.index_message(message_id).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Generates the database key and encrypts what was written in clear until now
+    async fn encrypt_columns(db: &SqlitePool, secret: &DatabaseSecret) -> Result<ColumnCipher> {
+        let cipher = ColumnCipher::generate()?;
+        let database_key = encryption::wrap_database_key(cipher.get_seed(), secret).await?;
+
+        let mut transaction = db.begin().await?;
+        let encrypted_column_count = cipher.encrypt_existing_rows(&mut transaction, 0).await?;
+        DatabaseKey::set(&mut *transaction, database_key).await?;
+        DatabaseKey::set_encrypted_column_count(&mut *transaction, encrypted_column_count).await?;
+        // Indexed again with keyed hashes of the words
+        MessageSearch::delete_all(&mut *transaction).await?;
+        transaction.commit().await?;
+
+        Ok(cipher)
+    }
+
+    /// Encrypts the sensitive columns added since the database was encrypted
+    async fn encrypt_new_columns(db: &SqlitePool, cipher: &ColumnCipher) -> Result<()> {
+        let mut transaction = db.begin().await?;
+        let encrypted_column_count = DatabaseKey::get_encrypted_column_count(&mut *transaction).await?;
+        let encrypted_column_count = cipher.encrypt_existing_rows(&mut transaction, encrypted_column_count).await?;
+        DatabaseKey::set_encrypted_column_count(&mut *transaction, encrypted_column_count).await?;
+        transaction.commit().await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Store for SqliteStore {
+    async fn begin(&self) -> Result<Box<dyn StoreTransaction + '_>> {
+        Ok(Box::new(SqliteTransaction { transaction: self.db.begin().await?, cipher: self.cipher.as_deref() }))
+    }
+
+    fn settings(&self) -> Box<dyn SettingsRepository + '_> { self.repository() }
+    fn owned_identities(&self) -> Box<dyn OwnedIdentityRepository + '_> { self.repository() }
+    fn owned_devices(&self) -> Box<dyn OwnedDeviceRepository + '_> { self.repository() }
+    fn keycloak_servers(&self) -> Box<dyn KeycloakServerRepository + '_> { self.repository() }
+    fn contacts(&self) -> Box<dyn ContactRepository + '_> { self.repository() }
+    fn blocked_contacts(&self) -> Box<dyn BlockedContactRepository + '_> { self.repository() }
+    fn contact_channels(&self) -> Box<dyn ContactChannelRepository + '_> { self.repository() }
+    fn introductions(&self) -> Box<dyn IntroductionRepository + '_> { self.repository() }
+    fn trust_establishments(&self) -> Box<dyn TrustEstablishmentRepository + '_> { self.repository() }
+    fn discussions(&self) -> Box<dyn DiscussionRepository + '_> { self.repository() }
+    fn messages(&self) -> Box<dyn MessageRepository + '_> { self.repository() }
+    fn message_expirations(&self) -> Box<dyn MessageExpirationRepository + '_> { self.repository() }
+    fn message_edits(&self) -> Box<dyn MessageEditRepository + '_> { self.repository() }
+    fn message_mentions(&self) -> Box<dyn MessageMentionRepository + '_> { self.repository() }
+    fn message_reactions(&self) -> Box<dyn MessageReactionRepository + '_> { self.repository() }
+    fn pending_message_operations(&self) -> Box<dyn PendingMessageOperationRepository + '_> { self.repository() }
+    fn groups(&self) -> Box<dyn GroupRepository + '_> { self.repository() }
+    fn group_members(&self) -> Box<dyn GroupMemberRepository + '_> { self.repository() }
+    fn attachments(&self) -> Box<dyn AttachmentRepository + '_> { self.repository() }
+    fn outbox_messages(&self) -> Box<dyn OutboxMessageRepository + '_> { self.repository() }
+    fn inbox_messages(&self) -> Box<dyn InboxMessageRepository + '_> { self.repository() }
+    fn return_receipts(&self) -> Box<dyn ReturnReceiptRepository + '_> { self.repository() }
+}
+
+impl SqliteStore {
+    fn repository(&self) -> Box<SqliteRepository<'_>> {
+        Box::new(SqliteRepository { target: Target::Pool(&self.db), cipher: self.cipher.as_deref() })
+    }
+}
+
+pub struct SqliteTransaction<'s> {
+    transaction: sqlx::Transaction<'static, Sqlite>,
+    cipher: Option<&'s ColumnCipher>,
+}
+
+impl SqliteTransaction<'_> {
+    fn repository(&mut self) -> Box<SqliteRepository<'_>> {
+        Box::new(SqliteRepository { target: Target::Transaction(&mut self.transaction), cipher: self.cipher })
+    }
+}
+
+#[async_trait]
+impl StoreTransaction for SqliteTransaction<'_> {
+    async fn commit(self: Box<Self>) -> Result<()> {
+        Ok(self.transaction.commit().await?)
+    }
+
+    fn settings(&mut self) -> Box<dyn SettingsRepository + '_> { self.repository() }
+    fn owned_identities(&mut self) -> Box<dyn OwnedIdentityRepository + '_> { self.repository() }
+    fn owned_devices(&mut self) -> Box<dyn OwnedDeviceRepository + '_> { self.repository() }
+    fn keycloak_servers(&mut self) -> Box<dyn KeycloakServerRepository + '_> { self.repository() }
+    fn contacts(&mut self) -> Box<dyn ContactRepository + '_> { self.repository() }
+    fn blocked_contacts(&mut self) -> Box<dyn BlockedContactRepository + '_> { self.repository() }
+    fn contact_channels(&mut self) -> Box<dyn ContactChannelRepository + '_> { self.repository() }
+    fn introductions(&mut self) -> Box<dyn IntroductionRepository + '_> { self.repository() }
+    fn trust_establishments(&mut self) -> Box<dyn TrustEstablishmentRepository + '_> { self.repository() }
+    fn discussions(&mut self) -> Box<dyn DiscussionRepository + '_> { self.repository() }
+    fn messages(&mut self) -> Box<dyn MessageRepository + '_> { self.repository() }
+    fn message_expirations(&mut self) -> Box<dyn MessageExpirationRepository + '_> { self.repository() }
+    fn message_edits(&mut self) -> Box<dyn MessageEditRepository + '_> { self.repository() }
+    fn message_mentions(&mut self) -> Box<dyn MessageMentionRepository + '_> { self.repository() }
+    fn message_reactions(&mut self) -> Box<dyn MessageReactionRepository + '_> { self.repository() }
+    fn pending_message_operations(&mut self) -> Box<dyn PendingMessageOperationRepository + '_> { self.repository() }
+    fn groups(&mut self) -> Box<dyn GroupRepository + '_> { self.repository() }
+    fn group_members(&mut self) -> Box<dyn GroupMemberRepository + '_> { self.repository() }
+    fn attachments(&mut self) -> Box<dyn AttachmentRepository + '_> { self.repository() }
+    fn outbox_messages(&mut self) -> Box<dyn OutboxMessageRepository + '_> { self.repository() }
+    fn inbox_messages(&mut self) -> Box<dyn InboxMessageRepository + '_> { self.repository() }
+    fn return_receipts(&mut self) -> Box<dyn ReturnReceiptRepository + '_> { self.repository() }
+}
+
+/// Runs the entity queries, encrypting and decrypting the sensitive columns when the store has a database key
+struct SqliteRepository<'c> {
+    target: Target<'c>,
+    cipher: Option<&'c ColumnCipher>,
+}
+
+/// A connection of the pool, or the one of the transaction
+enum Target<'c> {
+    Pool(&'c SqlitePool),
+    Transaction(&'c mut SqliteConnection),
+}
+
+enum Connection<'c> {
+    Pooled(PoolConnection<Sqlite>),
+    Borrowed(&'c mut SqliteConnection),
+}
+
+impl Deref for Connection<'_> {
+    type Target = SqliteConnection;
+
+    fn deref(&self) -> &SqliteConnection {
+        match self {
+            Connection::Pooled(connection) => connection,
+            Connection::Borrowed(connection) => connection,
+        }
+    }
+}
+
+impl DerefMut for Connection<'_> {
+    fn deref_mut(&mut self) -> &mut SqliteConnection {
+        match self {
+            Connection::Pooled(connection) => connection,
+            Connection::Borrowed(connection) => connection,
+        }
+    }
+}
+
+impl SqliteRepository<'_> {
+    async fn connection(&mut self) -> Result<Connection<'_>> {
+        Ok(match &mut self.target {
+            Target::Pool(db) => Connection::Pooled(db.acquire().await?),
+            Target::Transaction(connection) => Connection::Borrowed(connection),
+        })
+    }
+
+    fn seal<T: SensitiveColumns>(&self, row: T) -> Result<T> {
+        match self.cipher {
+            Some(cipher) => row.encrypt_columns(cipher),
+            None => Ok(row),
+        }
+    }
+
+    fn open<T: SensitiveColumns>(&self, row: Option<T>) -> Result<Option<T>> {
+        match self.cipher {
+            Some(cipher) => row.map(|row| row.decrypt_columns(cipher)).transpose(),
+            None => Ok(row),
+        }
+    }
+
+    fn open_all<T: SensitiveColumns>(&self, rows: Vec<T>) -> Result<Vec<T>> {
+        match self.cipher {
+            Some(cipher) => rows.into_iter().map(|row| row.decrypt_columns(cipher)).collect(),
+            None => Ok(rows),
+        }
+    }
+
+    /// Words as stored in the search index, keyed hashes of them when the columns are encrypted
+    fn index_terms(&self, terms: Vec<String>) -> Vec<String> {
+        match self.cipher {
+            Some(cipher) => terms.iter().map(|term| cipher.hash_term(term)).collect(),
+            None => terms,
+        }
+    }
+
+    /// Indexes the current body and attachment file names of a message, removes it from the index once deleted
+    async fn index_message(&mut self, message_id: i64) -> Result<()> {
+        let Some(message) = MessageRepository::get_by_id(self, message_id).await? else {
+            return MessageSearch::delete(&mut *self.connection().await?, message_id).await;
+        };
+
+        let attachments = match (message.get_outbox_message_id(), message.get_message_uid()) {
+            (Some(outbox_message_id), _) => AttachmentRepository::get_by_outbox_message(self, outbox_message_id).await?,
+            (None, Some(message_uid)) => {
+                let discussion = Discussion::get_by_id(&mut *self.connection().await?, message.get_discussion_id()).await?;
+                match discussion {
+                    Some(discussion) => AttachmentRepository::get_inbound_by_message(self, discussion.get_owned_identity(), message_uid).await?,
+                    None => vec![],
+                }
+            }
+            (None, None) => vec![],
+        };
+        let file_names = attachments.iter().map(Attachment::get_file_name).collect::<Vec<_>>().join(" ");
+
+        let body_terms = self.index_terms(search::terms(message.get_body().unwrap_or_default())).join(" ");
+        let file_name_terms = self.index_terms(search::terms(&file_names)).join(" ");
+        MessageSearch::set(&mut *self.connection().await?, message_id, &body_terms, &file_name_terms).await
+    }
+
+    /// Indexes again the message an attachment belongs to, once the attachment is linked to one
+    async fn index_attachment_message(&mut self, attachment: Option<Attachment>) -> Result<()> {
+        let Some(attachment) = attachment else {
+            return Ok(());
+        };
+
+        let message = match (attachment.get_direction()?, attachment.get_outbox_message_id(), attachment.get_message_uid()) {
+            (AttachmentDirection::Outbound, Some(outbox_message_id), _) => Message::get_by_outbox_message(&mut *self.connection().await?, outbox_message_id).await?,
+            (AttachmentDirection::Inbound, _, Some(message_uid)) => Message::get_by_message_uid(&mut *self.connection().await?, attachment.get_owned_identity(), message_uid).await?,
+            _ => None,
+        };
+        match message.and_then(|message| message.get_id()) {
+            Some(message_id) => self.index_message(message_id).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Text as stored in the encrypted text columns
+    fn seal_text(&self, text: &str) -> Result<String> {
+        match self.cipher {
+            Some(cipher) => cipher.encrypt_text(text),
+            None => Ok(text.to_owned()),
+        }
+    }
+
+    /// Serialized details, as stored in the text columns
+    fn seal_details(&self, identity_details: &JsonIdentityDetails) -> Result<String> {
+        self.seal_text(&serde_json::to_string(identity_details)?)
+    }
+}
+
+#[async_trait]
+impl SettingsRepository for SqliteRepository<'_> {
+    async fn get_display_name_format(&mut self) -> Result<DisplayNameFormat> {
+        DisplayNameFormat::get(&mut *self.connection().await?).await
+    }
+
+    async fn set_display_name_format(&mut self, format: &DisplayNameFormat) -> Result<()> {
+        DisplayNameFormat::set(&mut *self.connection().await?, format).await
+    }
+
+    async fn get_backup_key(&mut self) -> Result<Option<BackupKey>> {
+        let backup_key = BackupKey::get(&mut *self.connection().await?).await?;
+        self.open(backup_key)
+    }
+
+    async fn set_backup_key(&mut self, backup_key: BackupKey) -> Result<()> {
+        let backup_key = self.seal(backup_key)?;
+        BackupKey::set(&mut *self.connection().await?, backup_key).await
+    }
+
+    async fn get_database_key(&mut self) -> Result<Option<DatabaseKey>> {
+        DatabaseKey::get(&mut *self.connection().await?).await
+    }
+
+    async fn set_database_key(&mut self, database_key: DatabaseKey) -> Result<()> {
+        DatabaseKey::set(&mut *self.connection().await?, database_key).await
+    }
+}
+
+#[async_trait]
+impl OwnedIdentityRepository for SqliteRepository<'_> {
+    async fn get_all(&mut self) -> Result<Vec<OwnedIdentity>> {
+        let owned_identities = OwnedIdentity::get_all(&mut *self.connection().await?).await?;
+        self.open_all(owned_identities)
+    }
+
+    async fn get_by_identity(&mut self, bytes_owned_identity: &[u8]) -> Result<Option<OwnedIdentity>> {
+        let owned_identity = OwnedIdentity::get_by_identity(&mut *self.connection().await?, bytes_owned_identity).await?;
+        self.open(owned_identity)
+    }
+
+    async fn insert(&mut self, owned_identity: OwnedIdentity) -> Result<()> {
+        let owned_identity = self.seal(owned_identity)?;
+        OwnedIdentity::insert(&mut *self.connection().await?, owned_identity).await
+    }
+
+    async fn set_pref_send_read_receipt(&mut self, bytes_owned_identity: &[u8], pref_send_read_receipt: bool) -> Result<()> {
+        OwnedIdentity::set_pref_send_read_receipt(&mut *self.connection().await?, bytes_owned_identity, pref_send_read_receipt).await
+    }
+
+    async fn set_pref_mute_notifications(&mut self, bytes_owned_identity: &[u8], mute: bool, until: Option<i64>, except_mentioned: bool) -> Result<()> {
+        OwnedIdentity::set_pref_mute_notifications(&mut *self.connection().await?, bytes_owned_identity, mute, until, except_mentioned).await
+    }
+
+    async fn set_unpublished_details(&mut self, bytes_owned_identity: &[u8], identity_details: &JsonIdentityDetails, photo_url: Option<&str>) -> Result<()> {
+        let identity_details = self.seal_details(identity_details)?;
+        OwnedIdentity::set_unpublished_details(&mut *self.connection().await?, bytes_owned_identity, &identity_details, photo_url).await
+    }
+
+    async fn publish_details(&mut self, bytes_owned_identity: &[u8], identity_details: &JsonIdentityDetails, display_name: &str, photo_url: Option<&str>) -> Result<i64> {
+        let identity_details = self.seal_details(identity_details)?;
+        let display_name = self.seal_text(display_name)?;
+        OwnedIdentity::publish_details(&mut *self.connection().await?, bytes_owned_identity, &identity_details, &display_name, photo_url).await
+    }
+
+    async fn refresh_display_name(&mut self, bytes_owned_identity: &[u8], format: &DisplayNameFormat) -> Result<()> {
+        let owned_identity = OwnedIdentityRepository::get_by_identity(self, bytes_owned_identity).await?.ok_or(EngineError::UnknownOwnedIdentity)?;
+        let display_name = self.seal_text(&resolve_display_name(&owned_identity.get_identity_details()?, owned_identity.get_custom_display_name(), format))?;
+        OwnedIdentity::set_display_name(&mut *self.connection().await?, bytes_owned_identity, &display_name).await
+    }
+
+    async fn set_custom_display_name(&mut self, bytes_owned_identity: &[u8], custom_display_name: Option<&str>) -> Result<()> {
+        let custom_display_name = custom_display_name.map(|custom_display_name| self.seal_text(custom_display_name)).transpose()?;
+        OwnedIdentity::set_custom_display_name(&mut *self.connection().await?, bytes_owned_identity, custom_display_name.as_deref()).await
+    }
+
+    async fn set_unlock_password(&mut self, bytes_owned_identity: &[u8], unlock_password: Option<(&[u8], &[u8])>) -> Result<()> {
+        OwnedIdentity::set_unlock_password(&mut *self.connection().await?, bytes_owned_identity, unlock_password).await
+    }
+
+    async fn set_active(&mut self, bytes_owned_identity: &[u8], active: bool) -> Result<()> {
+        OwnedIdentity::set_active(&mut *self.connection().await?, bytes_owned_identity, active).await
+    }
+
+    async fn set_keycloak_managed(&mut self, bytes_owned_identity: &[u8], keycloak_managed: bool) -> Result<()> {
+        OwnedIdentity::set_keycloak_managed(&mut *self.connection().await?, bytes_owned_identity, keycloak_managed).await
+    }
+
+    async fn set_api_key_status(&mut self, bytes_owned_identity: &[u8], api_key_status: &ApiKeyStatus) -> Result<()> {
+        OwnedIdentity::set_api_key_status(&mut *self.connection().await?, bytes_owned_identity, api_key_status).await
+    }
+
+    async fn set_last_selection_timestamp(&mut self, bytes_owned_identity: &[u8], last_selection_timestamp: i64) -> Result<()> {
+        OwnedIdentity::set_last_selection_timestamp(&mut *self.connection().await?, bytes_owned_identity, last_selection_timestamp).await
+    }
+
+    async fn delete(&mut self, bytes_owned_identity: &[u8]) -> Result<()> {
+        OwnedIdentity::delete(&mut *self.connection().await?, bytes_owned_identity).await
+    }
+}
+
+#[async_trait]
+impl OwnedDeviceRepository for SqliteRepository<'_> {
+    async fn get_all(&mut self, owned_identity: &[u8]) -> Result<Vec<OwnedDevice>> {
+        OwnedDevice::get_all(&mut *self.connection().await?, owned_identity).await
+    }
+
+    async fn insert_if_absent(&mut self, owned_device: OwnedDevice) -> Result<()> {
+        OwnedDevice::insert_if_absent(&mut *self.connection().await?, owned_device).await
+    }
+
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()> {
+        OwnedDevice::delete_by_owned_identity(&mut *self.connection().await?, owned_identity).await
+    }
+}
+
+#[async_trait]
+impl KeycloakServerRepository for SqliteRepository<'_> {
+    async fn get(&mut self, owned_identity: &[u8]) -> Result<Option<KeycloakServer>> {
+        let keycloak_server = KeycloakServer::get(&mut *self.connection().await?, owned_identity).await?;
+        self.open(keycloak_server)
+    }
+
+    async fn upsert(&mut self, keycloak_server: KeycloakServer) -> Result<()> {
+        let keycloak_server = self.seal(keycloak_server)?;
+        KeycloakServer::upsert(&mut *self.connection().await?, keycloak_server).await
+    }
+
+    async fn set_serialized_auth_state(&mut self, owned_identity: &[u8], serialized_auth_state: Option<&str>) -> Result<()> {
+        let serialized_auth_state = match self.cipher {
+            Some(cipher) => cipher.encrypt_optional_text(serialized_auth_state.map(str::to_owned))?,
+            None => serialized_auth_state.map(str::to_owned),
+        };
+        KeycloakServer::set_serialized_auth_state(&mut *self.connection().await?, owned_identity, serialized_auth_state.as_deref()).await
+    }
+
+    async fn set_user_state(&mut self, owned_identity: &[u8], keycloak_user_id: &str, transfer_restricted: bool) -> Result<()> {
+        KeycloakServer::set_user_state(&mut *self.connection().await?, owned_identity, keycloak_user_id, transfer_restricted).await
+    }
+
+    async fn set_latest_revocation_list_timestamp(&mut self, owned_identity: &[u8], latest_revocation_list_timestamp: i64) -> Result<()> {
+        KeycloakServer::set_latest_revocation_list_timestamp(&mut *self.connection().await?, owned_identity, latest_revocation_list_timestamp).await
+    }
+
+    async fn delete(&mut self, owned_identity: &[u8]) -> Result<()> {
+        KeycloakServer::delete(&mut *self.connection().await?, owned_identity).await
+    }
+}
+
+#[async_trait]
+impl ContactRepository for SqliteRepository<'_> {
+    async fn get(&mut self, owned_identity: &[u8], contact_identity: &[u8]) -> Result<Option<Contact>> {
+        let contact = Contact::get(&mut *self.connection().await?, owned_identity, contact_identity).await?;
+        self.open(contact)
+    }
+
+    async fn get_all(&mut self, owned_identity: &[u8]) -> Result<Vec<Contact>> {
+        let contacts = Contact::get_all(&mut *self.connection().await?, owned_identity).await?;
+        let mut contacts = self.open_all(contacts)?;
+        contacts.sort_by(|a, b| (&a.sort_key, &a.display_name).cmp(&(&b.sort_key, &b.display_name)));
+
+        Ok(contacts)
+    }
+
+    async fn insert_if_absent(&mut self, contact: Contact) -> Result<bool> {
+        let contact = self.seal(contact)?;
+        Contact::insert_if_absent(&mut *self.connection().await?, contact).await
+    }
+
+    async fn raise_trust_level(&mut self, owned_identity: &[u8], contact_identity: &[u8], trust_level: ContactTrustLevel) -> Result<bool> {
+        Contact::raise_trust_level(&mut *self.connection().await?, owned_identity, contact_identity, trust_level).await
+    }
+
+    async fn revoke_trust(&mut self, owned_identity: &[u8], contact_identity: &[u8]) -> Result<bool> {
+        Contact::revoke_trust(&mut *self.connection().await?, owned_identity, contact_identity).await
+    }
+
+    async fn set_published_details(&mut self, owned_identity: &[u8], contact_identity: &[u8], version: i64, published_identity_details: Option<&JsonIdentityDetails>) -> Result<()> {
+        let published_identity_details = published_identity_details.map(|identity_details| self.seal_details(identity_details)).transpose()?;
+        Contact::set_published_details(&mut *self.connection().await?, owned_identity, contact_identity, version, published_identity_details.as_deref()).await
+    }
+
+    async fn accept_published_details(&mut self, owned_identity: &[u8], contact_identity: &[u8]) -> Result<bool> {
+        if !Contact::accept_published_details(&mut *self.connection().await?, owned_identity, contact_identity).await? {
+            return Ok(false);
+        }
+        let format = DisplayNameFormat::get(&mut *self.connection().await?).await?;
+        ContactRepository::refresh_display_name(self, owned_identity, contact_identity, &format).await?;
+
+        Ok(true)
+    }
+
+    async fn refresh_display_name(&mut self, owned_identity: &[u8], contact_identity: &[u8], format: &DisplayNameFormat) -> Result<()> {
+        let contact = ContactRepository::get(self, owned_identity, contact_identity).await?.ok_or(EngineError::UnknownContact)?;
+        let (display_name, sort_key) = contact.format_display_name(format)?;
+        let (display_name, sort_key) = (self.seal_text(&display_name)?, self.seal_text(&sort_key)?);
+        Contact::set_display_name(&mut *self.connection().await?, owned_identity, contact_identity, &display_name, &sort_key).await
+    }
+
+    async fn set_keycloak_managed(&mut self, owned_identity: &[u8], contact_identity: &[u8], signed_details: Option<&JsonIdentityDetails>) -> Result<()> {
+        let signed_details = signed_details.map(|identity_details| self.seal_details(identity_details)).transpose()?;
+        Contact::set_keycloak_managed(&mut *self.connection().await?, owned_identity, contact_identity, signed_details.as_deref()).await?;
+        if signed_details.is_some() {
+            let format = DisplayNameFormat::get(&mut *self.connection().await?).await?;
+            ContactRepository::refresh_display_name(self, owned_identity, contact_identity, &format).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn set_custom_display_name(&mut self, owned_identity: &[u8], contact_identity: &[u8], custom_display_name: Option<&str>) -> Result<()> {
+        let custom_display_name = custom_display_name.map(|custom_display_name| self.seal_text(custom_display_name)).transpose()?;
+        Contact::set_custom_display_name(&mut *self.connection().await?, owned_identity, contact_identity, custom_display_name.as_deref()).await
+    }
+
+    async fn delete(&mut self, owned_identity: &[u8], contact_identity: &[u8]) -> Result<()> {
+        Contact::delete(&mut *self.connection().await?, owned_identity, contact_identity).await
+    }
+
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()> {
+        Contact::delete_by_owned_identity(&mut *self.connection().await?, owned_identity).await
+    }
+}
+
+#[async_trait]
+impl BlockedContactRepository for SqliteRepository<'_> {
+    async fn get(&mut self, owned_identity: &[u8], contact_identity: &[u8]) -> Result<Option<BlockedContact>> {
+        BlockedContact::get(&mut *self.connection().await?, owned_identity, contact_identity).await
+    }
+
+    async fn get_all(&mut self, owned_identity: &[u8]) -> Result<Vec<BlockedContact>> {
+        BlockedContact::get_all(&mut *self.connection().await?, owned_identity).await
+    }
+
+    async fn insert_if_absent(&mut self, blocked_contact: BlockedContact) -> Result<bool> {
+        BlockedContact::insert_if_absent(&mut *self.connection().await?, blocked_contact).await
+    }
+
+    async fn delete(&mut self, owned_identity: &[u8], contact_identity: &[u8]) -> Result<bool> {
+        BlockedContact::delete(&mut *self.connection().await?, owned_identity, contact_identity).await
+    }
+
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()> {
+        BlockedContact::delete_by_owned_identity(&mut *self.connection().await?, owned_identity).await
+    }
+}
+
+#[async_trait]
+impl ContactChannelRepository for SqliteRepository<'_> {
+    async fn get(&mut self, owned_identity: &[u8], contact_identity: &[u8]) -> Result<Option<ContactChannel>> {
+        let channel = ContactChannel::get(&mut *self.connection().await?, owned_identity, contact_identity).await?;
+        self.open(channel)
+    }
+
+    async fn insert_if_absent(&mut self, channel: ContactChannel) -> Result<bool> {
+        let channel = self.seal(channel)?;
+        ContactChannel::insert_if_absent(&mut *self.connection().await?, channel).await
+    }
+
+    async fn delete(&mut self, owned_identity: &[u8], contact_identity: &[u8]) -> Result<bool> {
+        ContactChannel::delete(&mut *self.connection().await?, owned_identity, contact_identity).await
+    }
+
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()> {
+        ContactChannel::delete_by_owned_identity(&mut *self.connection().await?, owned_identity).await
+    }
+}
+
+#[async_trait]
+impl IntroductionRepository for SqliteRepository<'_> {
+    async fn get_by_id(&mut self, id: i64) -> Result<Option<Introduction>> {
+        let introduction = Introduction::get_by_id(&mut *self.connection().await?, id).await?;
+        self.open(introduction)
+    }
+
+    async fn get_by_uid(&mut self, owned_identity: &[u8], introduction_uid: &[u8]) -> Result<Option<Introduction>> {
+        let introduction = Introduction::get_by_uid(&mut *self.connection().await?, owned_identity, introduction_uid).await?;
+        self.open(introduction)
+    }
+
+    async fn get_all(&mut self, owned_identity: &[u8]) -> Result<Vec<Introduction>> {
+        let introductions = Introduction::get_all(&mut *self.connection().await?, owned_identity).await?;
+        self.open_all(introductions)
+    }
+
+    async fn insert_if_absent(&mut self, introduction: Introduction) -> Result<Option<i64>> {
+        let introduction = self.seal(introduction)?;
+        Introduction::insert_if_absent(&mut *self.connection().await?, introduction).await
+    }
+
+    async fn set_status(&mut self, id: i64, status: IntroductionStatus) -> Result<()> {
+        Introduction::set_status(&mut *self.connection().await?, id, status).await
+    }
+
+    async fn set_contact_acceptance(&mut self, id: i64, identity_details: &JsonIdentityDetails, contact_signature: &[u8]) -> Result<()> {
+        let identity_details = self.seal_details(identity_details)?;
+        Introduction::set_contact_acceptance(&mut *self.connection().await?, id, &identity_details, contact_signature).await
+    }
+
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()> {
+        Introduction::delete_by_owned_identity(&mut *self.connection().await?, owned_identity).await
+    }
+}
+
+#[async_trait]
+impl TrustEstablishmentRepository for SqliteRepository<'_> {
+    async fn get_by_id(&mut self, id: i64) -> Result<Option<TrustEstablishment>> {
+        let trust_establishment = TrustEstablishment::get_by_id(&mut *self.connection().await?, id).await?;
+        self.open(trust_establishment)
+    }
+
+    async fn get_by_uid(&mut self, owned_identity: &[u8], protocol_uid: &[u8]) -> Result<Option<TrustEstablishment>> {
+        let trust_establishment = TrustEstablishment::get_by_uid(&mut *self.connection().await?, owned_identity, protocol_uid).await?;
+        self.open(trust_establishment)
+    }
+
+    async fn get_all(&mut self, owned_identity: &[u8]) -> Result<Vec<TrustEstablishment>> {
+        let trust_establishments = TrustEstablishment::get_all(&mut *self.connection().await?, owned_identity).await?;
+        self.open_all(trust_establishments)
+    }
+
+    async fn insert_if_absent(&mut self, trust_establishment: TrustEstablishment) -> Result<Option<i64>> {
+        let trust_establishment = self.seal(trust_establishment)?;
+        TrustEstablishment::insert_if_absent(&mut *self.connection().await?, trust_establishment).await
+    }
+
+    async fn set_status(&mut self, id: i64, status: TrustEstablishmentStatus) -> Result<()> {
+        TrustEstablishment::set_status(&mut *self.connection().await?, id, status).await
+    }
+
+    async fn set_own_seed(&mut self, id: i64, own_seed: &[u8]) -> Result<()> {
+        TrustEstablishment::set_own_seed(&mut *self.connection().await?, id, own_seed).await
+    }
+
+    async fn set_contact_seed(&mut self, id: i64, contact_seed: &[u8], identity_details: Option<&JsonIdentityDetails>) -> Result<()> {
+        let identity_details = identity_details.map(|identity_details| self.seal_details(identity_details)).transpose()?;
+        TrustEstablishment::set_contact_seed(&mut *self.connection().await?, id, contact_seed, identity_details.as_deref()).await
+    }
+
+    async fn set_contact_confirmed(&mut self, id: i64) -> Result<()> {
+        TrustEstablishment::set_contact_confirmed(&mut *self.connection().await?, id).await
+    }
+
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()> {
+        TrustEstablishment::delete_by_owned_identity(&mut *self.connection().await?, owned_identity).await
+    }
+}
+
+#[async_trait]
+impl DiscussionRepository for SqliteRepository<'_> {
+    async fn get_by_id(&mut self, id: i64) -> Result<Option<Discussion>> {
+        let discussion = Discussion::get_by_id(&mut *self.connection().await?, id).await?;
+        self.open(discussion)
+    }
+
+    async fn get_one_to_one(&mut self, owned_identity: &[u8], contact_identity: &[u8]) -> Result<Option<Discussion>> {
+        let discussion = Discussion::get_one_to_one(&mut *self.connection().await?, owned_identity, contact_identity).await?;
+        self.open(discussion)
+    }
+
+    async fn get_group(&mut self, owned_identity: &[u8], group_identifier: &[u8]) -> Result<Option<Discussion>> {
+        let discussion = Discussion::get_group(&mut *self.connection().await?, owned_identity, group_identifier).await?;
+        self.open(discussion)
+    }
+
+    async fn get_all(&mut self, owned_identity: &[u8]) -> Result<Vec<Discussion>> {
+        let discussions = Discussion::get_all(&mut *self.connection().await?, owned_identity).await?;
+        self.open_all(discussions)
+    }
+
+    async fn insert(&mut self, discussion: Discussion) -> Result<i64> {
+        let discussion = self.seal(discussion)?;
+        Discussion::insert(&mut *self.connection().await?, discussion).await
+    }
+
+    async fn set_last_message_timestamp(&mut self, id: i64, last_message_timestamp: i64) -> Result<()> {
+        Discussion::set_last_message_timestamp(&mut *self.connection().await?, id, last_message_timestamp).await
+    }
+
+    async fn set_title(&mut self, id: i64, title: &str) -> Result<()> {
+        let title = self.seal_text(title)?;
+        Discussion::set_title(&mut *self.connection().await?, id, &title).await
+    }
+
+    async fn set_pref_send_read_receipt(&mut self, id: i64, pref_send_read_receipt: Option<bool>) -> Result<()> {
+        Discussion::set_pref_send_read_receipt(&mut *self.connection().await?, id, pref_send_read_receipt).await
+    }
+
+    async fn update_shared_settings(&mut self, id: i64, settings_version: i64, ephemerality: &Ephemerality) -> Result<bool> {
+        Discussion::update_shared_settings(&mut *self.connection().await?, id, settings_version, ephemerality).await
+    }
+
+    async fn delete(&mut self, id: i64) -> Result<()> {
+        Discussion::delete(&mut *self.connection().await?, id).await
+    }
+
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()> {
+        Discussion::delete_by_owned_identity(&mut *self.connection().await?, owned_identity).await
+    }
+}
+
+#[async_trait]
+impl MessageRepository for SqliteRepository<'_> {
+    async fn get_by_id(&mut self, id: i64) -> Result<Option<Message>> {
+        let message = Message::get_by_id(&mut *self.connection().await?, id).await?;
+        self.open(message)
+    }
+
+    async fn get_by_outbox_message(&mut self, outbox_message_id: i64) -> Result<Option<Message>> {
+        let message = Message::get_by_outbox_message(&mut *self.connection().await?, outbox_message_id).await?;
+        self.open(message)
+    }
+
+    async fn get_by_return_receipt_nonce(&mut self, owned_identity: &[u8], nonce: &[u8]) -> Result<Vec<Message>> {
+        let messages = Message::get_by_return_receipt_nonce(&mut *self.connection().await?, owned_identity, nonce).await?;
+        self.open_all(messages)
+    }
+
+    async fn get_by_sender(&mut self, discussion_id: i64, sender_identity: &[u8], sender_thread_identifier: &Uuid, sender_sequence_number: i64) -> Result<Option<Message>> {
+        let message = Message::get_by_sender(&mut *self.connection().await?, discussion_id, sender_identity, sender_thread_identifier, sender_sequence_number).await?;
+        self.open(message)
+    }
+
+    async fn get_page(&mut self, discussion_id: i64, before_message_id: Option<i64>, limit: i64) -> Result<Vec<Message>> {
+        let messages = Message::get_page(&mut *self.connection().await?, discussion_id, before_message_id, limit).await?;
+        self.open_all(messages)
+    }
+
+    async fn get_mentioning_page(&mut self, owned_identity: &[u8], before_message_id: Option<i64>, limit: i64) -> Result<Vec<Message>> {
+        let messages = Message::get_mentioning_page(&mut *self.connection().await?, owned_identity, before_message_id, limit).await?;
+        self.open_all(messages)
+    }
+
+    async fn get_next_sequence_number(&mut self, discussion_id: i64) -> Result<i64> {
+        Message::get_next_sequence_number(&mut *self.connection().await?, discussion_id).await
+    }
+
+    async fn search(&mut self, owned_identity: &[u8], terms: &[String], query: &MessageSearchQuery, before_message_id: Option<i64>, limit: i64) -> Result<Vec<Message>> {
+        let terms = self.index_terms(terms.to_vec());
+        let messages = MessageSearch::search(&mut *self.connection().await?, owned_identity, &terms, query, before_message_id, limit).await?;
+        self.open_all(messages)
+    }
+
+    async fn insert_if_absent(&mut self, message: Message) -> Result<Option<i64>> {
+        let message = self.seal(message)?;
+        let message_id = Message::insert_if_absent(&mut *self.connection().await?, message).await?;
+        if let Some(message_id) = message_id {
+            self.index_message(message_id).await?;
+        }
+
+        Ok(message_id)
+    }
+
+    async fn resolve_replies(&mut self, discussion_id: i64, reference: &MessageReference, message_id: i64) -> Result<()> {
+        Message::resolve_replies(&mut *self.connection().await?, discussion_id, reference, message_id).await
+    }
+
+    async fn advance_outbound_status(&mut self, id: i64, status: MessageStatus) -> Result<bool> {
+        Message::advance_outbound_status(&mut *self.connection().await?, id, status).await
+    }
+
+    async fn mark_read(&mut self, id: i64) -> Result<bool> {
+        Message::mark_read(&mut *self.connection().await?, id).await
+    }
+
+    async fn wipe(&mut self, id: i64) -> Result<()> {
+        Message::wipe(&mut *self.connection().await?, id).await?;
+        self.index_message(id).await
+    }
+
+    async fn set_edited_body(&mut self, id: i64, body: &str, edit_timestamp: i64) -> Result<()> {
+        let body = match self.cipher {
+            Some(cipher) => cipher.encrypt_text(body)?,
+            None => body.to_owned(),
+        };
+        Message::set_edited_body(&mut *self.connection().await?, id, &body, edit_timestamp).await?;
+        self.index_message(id).await
+    }
+
+    async fn mark_remotely_deleted(&mut self, id: i64) -> Result<()> {
+        Message::mark_remotely_deleted(&mut *self.connection().await?, id).await?;
+        self.index_message(id).await
+    }
+
+    async fn delete(&mut self, id: i64) -> Result<()> {
+        MessageSearch::delete(&mut *self.connection().await?, id).await?;
+        Message::delete(&mut *self.connection().await?, id).await
+    }
+
+    async fn delete_by_discussion(&mut self, discussion_id: i64) -> Result<()> {
+        MessageSearch::delete_by_discussion(&mut *self.connection().await?, discussion_id).await?;
+        Message::delete_by_discussion(&mut *self.connection().await?, discussion_id).await
+    }
+
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()> {
+        MessageSearch::delete_by_owned_identity(&mut *self.connection().await?, owned_identity).await?;
+        Message::delete_by_owned_identity(&mut *self.connection().await?, owned_identity).await
+    }
+}
+
+#[async_trait]
+impl MessageExpirationRepository for SqliteRepository<'_> {
+    async fn get_due(&mut self, timestamp: i64) -> Result<Vec<MessageExpiration>> {
+        MessageExpiration::get_due(&mut *self.connection().await?, timestamp).await
+    }
+
+    async fn get_next_expiration_timestamp(&mut self) -> Result<Option<i64>> {
+        MessageExpiration::get_next_expiration_timestamp(&mut *self.connection().await?).await
+    }
+
+    async fn insert(&mut self, expiration: MessageExpiration) -> Result<i64> {
+        MessageExpiration::insert(&mut *self.connection().await?, expiration).await
+    }
+
+    async fn delete(&mut self, id: i64) -> Result<()> {
+        MessageExpiration::delete(&mut *self.connection().await?, id).await
+    }
+
+    async fn delete_by_message(&mut self, message_id: i64) -> Result<()> {
+        MessageExpiration::delete_by_message(&mut *self.connection().await?, message_id).await
+    }
+
+    async fn delete_by_discussion(&mut self, discussion_id: i64) -> Result<()> {
+        MessageExpiration::delete_by_discussion(&mut *self.connection().await?, discussion_id).await
+    }
+
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()> {
+        MessageExpiration::delete_by_owned_identity(&mut *self.connection().await?, owned_identity).await
+    }
+}
+
+#[async_trait]
+impl MessageEditRepository for SqliteRepository<'_> {
+    async fn get_all(&mut self, message_id: i64) -> Result<Vec<MessageEdit>> {
+        let edits = MessageEdit::get_all(&mut *self.connection().await?, message_id).await?;
+        self.open_all(edits)
+    }
+
+    async fn insert(&mut self, edit: MessageEdit) -> Result<i64> {
+        let edit = self.seal(edit)?;
+        MessageEdit::insert(&mut *self.connection().await?, edit).await
+    }
+
+    async fn delete_by_message(&mut self, message_id: i64) -> Result<()> {
+        MessageEdit::delete_by_message(&mut *self.connection().await?, message_id).await
+    }
+
+    async fn delete_by_discussion(&mut self, discussion_id: i64) -> Result<()> {
+        MessageEdit::delete_by_discussion(&mut *self.connection().await?, discussion_id).await
+    }
+
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()> {
+        MessageEdit::delete_by_owned_identity(&mut *self.connection().await?, owned_identity).await
+    }
+}
+
+#[async_trait]
+impl MessageMentionRepository for SqliteRepository<'_> {
+    async fn get_all(&mut self, message_id: i64) -> Result<Vec<MessageMention>> {
+        MessageMention::get_all(&mut *self.connection().await?, message_id).await
+    }
+
+    async fn insert(&mut self, mention: MessageMention) -> Result<i64> {
+        MessageMention::insert(&mut *self.connection().await?, mention).await
+    }
+
+    async fn delete_by_message(&mut self, message_id: i64) -> Result<()> {
+        MessageMention::delete_by_message(&mut *self.connection().await?, message_id).await
+    }
+
+    async fn delete_by_discussion(&mut self, discussion_id: i64) -> Result<()> {
+        MessageMention::delete_by_discussion(&mut *self.connection().await?, discussion_id).await
+    }
+
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()> {
+        MessageMention::delete_by_owned_identity(&mut *self.connection().await?, owned_identity).await
+    }
+}
+
+#[async_trait]
+impl MessageReactionRepository for SqliteRepository<'_> {
+    async fn get_all(&mut self, message_id: i64) -> Result<Vec<MessageReaction>> {
+        MessageReaction::get_all(&mut *self.connection().await?, message_id).await
+    }
+
+    async fn upsert(&mut self, reaction: MessageReaction) -> Result<bool> {
+        MessageReaction::upsert(&mut *self.connection().await?, reaction).await
+    }
+
+    async fn delete_by_message(&mut self, message_id: i64) -> Result<()> {
+        MessageReaction::delete_by_message(&mut *self.connection().await?, message_id).await
+    }
+
+    async fn delete_by_discussion(&mut self, discussion_id: i64) -> Result<()> {
+        MessageReaction::delete_by_discussion(&mut *self.connection().await?, discussion_id).await
+    }
+
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()> {
+        MessageReaction::delete_by_owned_identity(&mut *self.connection().await?, owned_identity).await
+    }
+}
+
+#[async_trait]
+impl PendingMessageOperationRepository for SqliteRepository<'_> {
+    async fn get_by_message(&mut self, discussion_id: i64, sender_identity: &[u8], sender_thread_identifier: &Uuid, sender_sequence_number: i64) -> Result<Vec<PendingMessageOperation>> {
+        let operations = PendingMessageOperation::get_by_message(&mut *self.connection().await?, discussion_id, sender_identity, sender_thread_identifier, sender_sequence_number).await?;
+        self.open_all(operations)
+    }
+
+    async fn insert(&mut self, operation: PendingMessageOperation) -> Result<i64> {
+        let operation = self.seal(operation)?;
+        PendingMessageOperation::insert(&mut *self.connection().await?, operation).await
+    }
+
+    async fn delete(&mut self, id: i64) -> Result<()> {
+        PendingMessageOperation::delete(&mut *self.connection().await?, id).await
+    }
+
+    async fn delete_by_discussion(&mut self, discussion_id: i64) -> Result<()> {
+        PendingMessageOperation::delete_by_discussion(&mut *self.connection().await?, discussion_id).await
+    }
+
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()> {
+        PendingMessageOperation::delete_by_owned_identity(&mut *self.connection().await?, owned_identity).await
+    }
+}
+
+#[async_trait]
+impl GroupRepository for SqliteRepository<'_> {
+    async fn get_by_id(&mut self, id: i64) -> Result<Option<Group>> {
+        let group = Group::get_by_id(&mut *self.connection().await?, id).await?;
+        self.open(group)
+    }
+
+    async fn get_by_uid(&mut self, owned_identity: &[u8], group_uid: &[u8]) -> Result<Option<Group>> {
+        let group = Group::get_by_uid(&mut *self.connection().await?, owned_identity, group_uid).await?;
+        self.open(group)
+    }
+
+    async fn get_all(&mut self, owned_identity: &[u8]) -> Result<Vec<Group>> {
+        let groups = Group::get_all(&mut *self.connection().await?, owned_identity).await?;
+        let mut groups = self.open_all(groups)?;
+        groups.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(groups)
+    }
+
+    async fn upsert(&mut self, group: Group) -> Result<i64> {
+        let group = self.seal(group)?;
+        Group::upsert(&mut *self.connection().await?, group).await
+    }
+
+    async fn set_status(&mut self, id: i64, status: GroupStatus) -> Result<()> {
+        Group::set_status(&mut *self.connection().await?, id, status).await
+    }
+
+    async fn delete(&mut self, id: i64) -> Result<()> {
+        Group::delete(&mut *self.connection().await?, id).await
+    }
+
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()> {
+        Group::delete_by_owned_identity(&mut *self.connection().await?, owned_identity).await
+    }
+}
+
+#[async_trait]
+impl GroupMemberRepository for SqliteRepository<'_> {
+    async fn get_all(&mut self, group_id: i64) -> Result<Vec<GroupMember>> {
+        GroupMember::get_all(&mut *self.connection().await?, group_id).await
+    }
+
+    async fn get(&mut self, group_id: i64, member_identity: &[u8]) -> Result<Option<GroupMember>> {
+        GroupMember::get(&mut *self.connection().await?, group_id, member_identity).await
+    }
+
+    async fn replace_all(&mut self, group_id: i64, members: Vec<GroupMember>) -> Result<()> {
+        GroupMember::replace_all(&mut *self.connection().await?, group_id, members).await
+    }
+}
+
+#[async_trait]
+impl AttachmentRepository for SqliteRepository<'_> {
+    async fn get_by_id(&mut self, id: i64) -> Result<Option<Attachment>> {
+        let attachment = Attachment::get_by_id(&mut *self.connection().await?, id).await?;
+        self.open(attachment)
+    }
+
+    async fn get_by_outbox_message(&mut self, outbox_message_id: i64) -> Result<Vec<Attachment>> {
+        let attachments = Attachment::get_by_outbox_message(&mut *self.connection().await?, outbox_message_id).await?;
+        self.open_all(attachments)
+    }
+
+    async fn get_inbound_by_message(&mut self, owned_identity: &[u8], message_uid: &[u8]) -> Result<Vec<Attachment>> {
+        let attachments = Attachment::get_inbound_by_message(&mut *self.connection().await?, owned_identity, message_uid).await?;
+        self.open_all(attachments)
+    }
+
+    async fn get_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<Vec<Attachment>> {
+        let attachments = Attachment::get_by_owned_identity(&mut *self.connection().await?, owned_identity).await?;
+        self.open_all(attachments)
+    }
+
+    async fn get_by_discussion(&mut self, discussion_id: i64) -> Result<Vec<Attachment>> {
+        let attachments = Attachment::get_by_discussion(&mut *self.connection().await?, discussion_id).await?;
+        self.open_all(attachments)
+    }
+
+    async fn get_due(&mut self, timestamp: i64) -> Result<Vec<Attachment>> {
+        let attachments = Attachment::get_due(&mut *self.connection().await?, timestamp).await?;
+        self.open_all(attachments)
+    }
+
+    async fn get_next_attempt_timestamp(&mut self) -> Result<Option<i64>> {
+        Attachment::get_next_attempt_timestamp(&mut *self.connection().await?).await
+    }
+
+    async fn insert(&mut self, attachment: Attachment) -> Result<i64> {
+        let attachment = self.seal(attachment)?;
+        let id = Attachment::insert(&mut *self.connection().await?, attachment).await?;
+        let attachment = Attachment::get_by_id(&mut *self.connection().await?, id).await?;
+        self.index_attachment_message(attachment).await?;
+
+        Ok(id)
+    }
+
+    async fn link_to_outbox_message(&mut self, id: i64, outbox_message_id: i64, attachment_number: i64) -> Result<()> {
+        Attachment::link_to_outbox_message(&mut *self.connection().await?, id, outbox_message_id, attachment_number).await?;
+        let attachment = Attachment::get_by_id(&mut *self.connection().await?, id).await?;
+        self.index_attachment_message(attachment).await
+    }
+
+    async fn set_upload_urls(&mut self, id: i64, message_uid: &[u8], upload_urls: &[String]) -> Result<()> {
+        Attachment::set_upload_urls(&mut *self.connection().await?, id, message_uid, upload_urls).await
+    }
+
+    async fn set_transferred_chunk_count(&mut self, id: i64, transferred_chunk_count: i64) -> Result<()> {
+        Attachment::set_transferred_chunk_count(&mut *self.connection().await?, id, transferred_chunk_count).await
+    }
+
+    async fn schedule_retry(&mut self, id: i64, attempt_count: i64, next_attempt_timestamp: i64) -> Result<()> {
+        Attachment::schedule_retry(&mut *self.connection().await?, id, attempt_count, next_attempt_timestamp).await
+    }
+
+    async fn set_status(&mut self, id: i64, status: AttachmentStatus) -> Result<()> {
+        Attachment::set_status(&mut *self.connection().await?, id, status).await
+    }
+
+    async fn delete(&mut self, id: i64) -> Result<()> {
+        let attachment = Attachment::get_by_id(&mut *self.connection().await?, id).await?;
+        Attachment::delete(&mut *self.connection().await?, id).await?;
+        self.index_attachment_message(attachment).await
+    }
+
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()> {
+        Attachment::delete_by_owned_identity(&mut *self.connection().await?, owned_identity).await
+    }
+}
+
+#[async_trait]
+impl OutboxMessageRepository for SqliteRepository<'_> {
+    async fn get_by_id(&mut self, id: i64) -> Result<Option<OutboxMessage>> {
+        OutboxMessage::get_by_id(&mut *self.connection().await?, id).await
+    }
+
+    async fn get_due(&mut self, timestamp: i64) -> Result<Vec<OutboxMessage>> {
+        OutboxMessage::get_due(&mut *self.connection().await?, timestamp).await
+    }
+
+    async fn get_next_attempt_timestamp(&mut self) -> Result<Option<i64>> {
+        OutboxMessage::get_next_attempt_timestamp(&mut *self.connection().await?).await
+    }
+
+    async fn insert(&mut self, outbox_message: OutboxMessage) -> Result<i64> {
+        OutboxMessage::insert(&mut *self.connection().await?, outbox_message).await
+    }
+
+    async fn set_upload_nonce(&mut self, id: i64, upload_nonce: &[u8]) -> Result<()> {
+        OutboxMessage::set_upload_nonce(&mut *self.connection().await?, id, upload_nonce).await
+    }
+
+    async fn mark_sent(&mut self, id: i64, attempt_count: i64, message_uid: &[u8]) -> Result<()> {
+        OutboxMessage::mark_sent(&mut *self.connection().await?, id, attempt_count, message_uid).await
+    }
+
+    async fn schedule_retry(&mut self, id: i64, attempt_count: i64, next_attempt_timestamp: i64) -> Result<()> {
+        OutboxMessage::schedule_retry(&mut *self.connection().await?, id, attempt_count, next_attempt_timestamp).await
+    }
+
+    async fn mark_failed(&mut self, id: i64, attempt_count: i64) -> Result<()> {
+        OutboxMessage::mark_failed(&mut *self.connection().await?, id, attempt_count).await
+    }
+
+    async fn set_delivery_state(&mut self, id: i64, delivery_state: DeliveryState) -> Result<()> {
+        OutboxMessage::set_delivery_state(&mut *self.connection().await?, id, delivery_state).await
+    }
+
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()> {
+        OutboxMessage::delete_by_owned_identity(&mut *self.connection().await?, owned_identity).await
+    }
+}
+
+#[async_trait]
+impl InboxMessageRepository for SqliteRepository<'_> {
+    async fn insert_if_absent(&mut self, owned_identity: &[u8], message: &DownloadedMessage) -> Result<bool> {
+        InboxMessage::insert_if_absent(&mut *self.connection().await?, owned_identity, message).await
+    }
+
+    async fn get_by_message_uid(&mut self, owned_identity: &[u8], message_uid: &[u8]) -> Result<Option<InboxMessage>> {
+        InboxMessage::get_by_message_uid(&mut *self.connection().await?, owned_identity, message_uid).await
+    }
+
+    async fn get_unprocessed(&mut self, owned_identity: &[u8]) -> Result<Vec<InboxMessage>> {
+        InboxMessage::get_unprocessed(&mut *self.connection().await?, owned_identity).await
+    }
+
+    async fn mark_processed(&mut self, id: i64) -> Result<()> {
+        InboxMessage::mark_processed(&mut *self.connection().await?, id).await
+    }
+
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()> {
+        InboxMessage::delete_by_owned_identity(&mut *self.connection().await?, owned_identity).await
+    }
+}
+
+#[async_trait]
+impl ReturnReceiptRepository for SqliteRepository<'_> {
+    async fn get_due(&mut self, timestamp: i64) -> Result<Vec<OutboxReturnReceipt>> {
+        OutboxReturnReceipt::get_due(&mut *self.connection().await?, timestamp).await
+    }
+
+    async fn get_next_attempt_timestamp(&mut self) -> Result<Option<i64>> {
+        OutboxReturnReceipt::get_next_attempt_timestamp(&mut *self.connection().await?).await
+    }
+
+    async fn insert(&mut self, return_receipt: OutboxReturnReceipt) -> Result<i64> {
+        OutboxReturnReceipt::insert(&mut *self.connection().await?, return_receipt).await
+    }
+
+    async fn schedule_retry(&mut self, id: i64, attempt_count: i64, next_attempt_timestamp: i64) -> Result<()> {
+        OutboxReturnReceipt::schedule_retry(&mut *self.connection().await?, id, attempt_count, next_attempt_timestamp).await
+    }
+
+    async fn delete(&mut self, id: i64) -> Result<()> {
+        OutboxReturnReceipt::delete(&mut *self.connection().await?, id).await
+    }
+
+    async fn delete_by_owned_identity(&mut self, owned_identity: &[u8]) -> Result<()> {
+        OutboxReturnReceipt::delete_by_owned_identity(&mut *self.connection().await?, owned_identity).await
+    }
+}
@@ -0,0 +1,333 @@
+//! Trust establishment with short authentication strings (SAS).
+//!
+//! The inviter commits to a random seed, the invited identity answers with its own seed, then the inviter opens its
+//! commitment. Each side displays a short code derived from both seeds and its own identity, which the other user
+//! types. Someone in the middle can't choose the seeds so that both codes match, hence typing the right code proves
+//! the contact is who it claims to be. Each side then sends a signed confirmation, and the contact is verified once
+//! both codes were typed.
+
+use olvid_core::{crypto::{commitment::{Commitment, CommitmentWithSHA256}, hash::{Hash, SHA256}, prng::PRNG}, cryptographic_identity::CryptographicIdentity, encoding::Encoder};
+
+use crate::{contacts::check_not_blocked, entities::{contact::{Contact, ContactTrustLevel}, trust_establishment::{TrustEstablishment, TrustEstablishmentStatus}}, events::{publish_events, EngineEvent}, messages::{payload::{JsonPayload, JsonTrustEstablishmentMessage}, queue_payload}, store::StoreTransaction, Engine, EngineError, Result};
+
+const PROTOCOL_UID_LENGTH: usize = 32;
+const SEED_LENGTH: usize = 32;
+/// Number of digits of the codes
+const SAS_LENGTH: u32 = 4;
+const CONFIRMATION_SIGNATURE_PREFIX: &[u8] = b"trustEstablishmentSasConfirmation";
+
+/// Binds the commitment of the inviter to the protocol and to its identity
+fn commitment_tag(protocol_uid: &[u8], inviter_identity: &[u8]) -> Result<Vec<u8>> {
+    Ok(vec![protocol_uid.to_vec().encode()?, inviter_identity.to_vec().encode()?].encode()?)
+}
+
+/// Code displayed by `identity`, and typed by the other side
+pub(crate) fn compute_sas(inviter_seed: &[u8], invitee_seed: &[u8], identity: &[u8]) -> Result<String> {
+    let input = vec![inviter_seed.to_vec().encode()?, invitee_seed.to_vec().encode()?, identity.to_vec().encode()?].encode()?;
+    let digest = SHA256::digest(&input);
+    let value = u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]]) % 10u32.pow(SAS_LENGTH);
+
+    Ok(format!("{:0width$}", value, width = SAS_LENGTH as usize))
+}
+
+/// What each side signs once its user typed the right code
+fn confirmation_statement(trust_establishment: &TrustEstablishment) -> Result<Vec<u8>> {
+    let (inviter_seed, invitee_seed) = trust_establishment.get_seeds().ok_or(EngineError::Technical)?;
+    let (inviter_identity, invitee_identity) = match trust_establishment.is_inviter() {
+        true => (trust_establishment.get_owned_identity(), trust_establishment.get_contact_identity()),
+        false => (trust_establishment.get_contact_identity(), trust_establishment.get_owned_identity()),
+    };
+
+    Ok(vec![
+        trust_establishment.get_protocol_uid().to_vec().encode()?,
+        inviter_identity.to_vec().encode()?,
+        invitee_identity.to_vec().encode()?,
+        inviter_seed.to_vec().encode()?,
+        invitee_seed.to_vec().encode()?,
+    ].encode()?)
+}
+
+fn trust_establishment_updated(owned_identity: &[u8], trust_establishment_id: i64) -> EngineEvent {
+    EngineEvent::TrustEstablishmentUpdated { owned_identity: owned_identity.to_vec(), trust_establishment_id }
+}
+
+/// Adds the contact if needed and marks it verified, once both codes were typed
+async fn complete(transaction: &mut dyn StoreTransaction, events: &mut Vec<EngineEvent>, trust_establishment: &TrustEstablishment) -> Result<()> {
+    let owned_identity = trust_establishment.get_owned_identity();
+    let contact_identity = trust_establishment.get_contact_identity();
+    let identity_details = trust_establishment.get_identity_details()?.ok_or(EngineError::Technical)?;
+
+    let display_name_format = transaction.settings().get_display_name_format().await?;
+    if transaction.contacts().insert_if_absent(Contact::new(owned_identity, contact_identity, &identity_details, &display_name_format)?).await? {
+        events.push(EngineEvent::ContactAdded { owned_identity: owned_identity.to_vec(), contact_identity: contact_identity.to_vec() });
+    }
+    if transaction.contacts().raise_trust_level(owned_identity, contact_identity, ContactTrustLevel::Verified).await? {
+        events.push(EngineEvent::ContactUpdated { owned_identity: owned_identity.to_vec(), contact_identity: contact_identity.to_vec() });
+    }
+
+    let trust_establishment_id = trust_establishment.get_id().ok_or(EngineError::Technical)?;
+    transaction.trust_establishments().set_status(trust_establishment_id, TrustEstablishmentStatus::Finished).await?;
+    events.push(trust_establishment_updated(owned_identity, trust_establishment_id));
+    events.push(EngineEvent::ProtocolFinished { owned_identity: owned_identity.to_vec(), protocol_instance_uid: trust_establishment.get_protocol_uid().to_vec() });
+
+    Ok(())
+}
+
+/// Handles a trust establishment message, the ones that don't match the current step of the protocol are ignored
+pub(crate) async fn process_trust_establishment_message(transaction: &mut dyn StoreTransaction, events: &mut Vec<EngineEvent>, owned_identity: &[u8], sender_identity: &[u8], message: JsonTrustEstablishmentMessage) -> Result<()> {
+    let protocol_uid = match &message {
+        JsonTrustEstablishmentMessage::Invitation { protocol_uid, commitment, identity_details } => {
+            if sender_identity != owned_identity {
+                let trust_establishment = TrustEstablishment::new_received(owned_identity, protocol_uid, sender_identity, identity_details, commitment)?;
+                if let Some(trust_establishment_id) = transaction.trust_establishments().insert_if_absent(trust_establishment).await? {
+                    events.push(trust_establishment_updated(owned_identity, trust_establishment_id));
+                }
+            }
+            return Ok(());
+        }
+        JsonTrustEstablishmentMessage::Accepted { protocol_uid, .. }
+        | JsonTrustEstablishmentMessage::Rejected { protocol_uid }
+        | JsonTrustEstablishmentMessage::SeedRevealed { protocol_uid, .. }
+        | JsonTrustEstablishmentMessage::Confirmed { protocol_uid, .. } => protocol_uid,
+    };
+    let Some(trust_establishment) = transaction.trust_establishments().get_by_uid(owned_identity, protocol_uid).await?
+        .filter(|trust_establishment| trust_establishment.get_contact_identity() == sender_identity) else {
+        return Ok(());
+    };
+    let trust_establishment_id = trust_establishment.get_id().ok_or(EngineError::Technical)?;
+    let status = trust_establishment.get_status()?;
+
+    match message {
+        // Handled above
+        JsonTrustEstablishmentMessage::Invitation { .. } => {}
+        JsonTrustEstablishmentMessage::Accepted { protocol_uid, seed, identity_details } => {
+            if !trust_establishment.is_inviter() || status != TrustEstablishmentStatus::InvitationSent {
+                return Ok(());
+            }
+            let decommitment = trust_establishment.get_decommitment().ok_or(EngineError::Technical)?.to_vec();
+            let payload = JsonPayload {
+                trust_establishment: Some(JsonTrustEstablishmentMessage::SeedRevealed { protocol_uid, decommitment }),
+                ..Default::default()
+            };
+
+            let private_identity = transaction.owned_identities().get_by_identity(owned_identity).await?.ok_or(EngineError::UnknownOwnedIdentity)?.get_private_identity()?;
+
+            transaction.trust_establishments().set_contact_seed(trust_establishment_id, &seed, Some(&identity_details)).await?;
+            queue_payload(transaction, &private_identity, sender_identity, &payload, &mut Engine::get_default_hmac_prng()?).await?;
+            transaction.trust_establishments().set_status(trust_establishment_id, TrustEstablishmentStatus::CodeRequired).await?;
+            events.push(trust_establishment_updated(owned_identity, trust_establishment_id));
+        }
+        JsonTrustEstablishmentMessage::Rejected { .. } => {
+            if matches!(status, TrustEstablishmentStatus::Finished | TrustEstablishmentStatus::Cancelled) {
+                return Ok(());
+            }
+            transaction.trust_establishments().set_status(trust_establishment_id, TrustEstablishmentStatus::Cancelled).await?;
+            events.push(trust_establishment_updated(owned_identity, trust_establishment_id));
+        }
+        JsonTrustEstablishmentMessage::SeedRevealed { protocol_uid, decommitment } => {
+            if trust_establishment.is_inviter() || status != TrustEstablishmentStatus::WaitingForSeed {
+                return Ok(());
+            }
+            let commitment = trust_establishment.get_contact_commitment().ok_or(EngineError::Technical)?;
+            // An inviter that changed its seed after learning ours would choose the codes
+            match CommitmentWithSHA256::open(commitment, &commitment_tag(&protocol_uid, sender_identity)?, &decommitment) {
+                Ok(seed) if seed.len() == SEED_LENGTH => {
+                    transaction.trust_establishments().set_contact_seed(trust_establishment_id, &seed, None).await?;
+                    transaction.trust_establishments().set_status(trust_establishment_id, TrustEstablishmentStatus::CodeRequired).await?;
+                }
+                _ => transaction.trust_establishments().set_status(trust_establishment_id, TrustEstablishmentStatus::Cancelled).await?,
+            }
+            events.push(trust_establishment_updated(owned_identity, trust_establishment_id));
+        }
+        JsonTrustEstablishmentMessage::Confirmed { signature, .. } => {
+            if !matches!(status, TrustEstablishmentStatus::CodeRequired | TrustEstablishmentStatus::WaitingForContactCode) {
+                return Ok(());
+            }
+            let statement = confirmation_statement(&trust_establishment)?;
+            let is_signed = CryptographicIdentity::from_raw(sender_identity)
+                .and_then(|identity| identity.verify_signature(CONFIRMATION_SIGNATURE_PREFIX, &statement, &signature))
+                .unwrap_or(false);
+            if !is_signed {
+                return Ok(());
+            }
+
+            transaction.trust_establishments().set_contact_confirmed(trust_establishment_id).await?;
+            match status {
+                TrustEstablishmentStatus::WaitingForContactCode => complete(transaction, events, &trust_establishment).await?,
+                _ => events.push(trust_establishment_updated(owned_identity, trust_establishment_id)),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+impl Engine {
+    /// Invites an identity, usually scanned from its QR code, to verify each other by typing the codes displayed
+    pub async fn start_trust_establishment(&self, bytes_owned_identity: &[u8], contact_identity: &[u8]) -> Result<TrustEstablishment> {
+        let owned_identity = self.get_owned_identity(bytes_owned_identity).await?;
+        check_not_blocked(&*self.store, bytes_owned_identity, contact_identity).await?;
+
+        let mut prng = Self::get_default_hmac_prng()?;
+        let protocol_uid = prng.bytes(PROTOCOL_UID_LENGTH).map_err(|_| EngineError::PRNG)?;
+        let seed = prng.bytes(SEED_LENGTH).map_err(|_| EngineError::PRNG)?;
+        let (commitment, decommitment) = CommitmentWithSHA256::commit(&commitment_tag(&protocol_uid, bytes_owned_identity)?, &seed, &mut prng)
+            .map_err(|_| EngineError::PRNG)?;
+
+        let payload = JsonPayload {
+            trust_establishment: Some(JsonTrustEstablishmentMessage::Invitation {
+                protocol_uid: protocol_uid.clone(),
+                commitment,
+                identity_details: owned_identity.get_identity_details()?,
+            }),
+            ..Default::default()
+        };
+        let trust_establishment = TrustEstablishment::new_sent(bytes_owned_identity, &protocol_uid, contact_identity, &seed, &decommitment);
+        let mut transaction = self.store.begin().await?;
+        queue_payload(&mut *transaction, &owned_identity.get_private_identity()?, contact_identity, &payload, &mut prng).await?;
+        let trust_establishment_id = transaction.trust_establishments().insert_if_absent(trust_establishment).await?.ok_or(EngineError::Technical)?;
+        transaction.commit().await?;
+        self.outbox_wake_up.notify_one();
+        self.publish_event(EngineEvent::TrustEstablishmentUpdated { owned_identity: bytes_owned_identity.to_vec(), trust_establishment_id });
+
+        self.store.trust_establishments().get_by_id(trust_establishment_id).await?.ok_or(EngineError::Technical)
+    }
+
+    /// Trust establishments sent and received by an owned identity, most recent first
+    pub async fn get_trust_establishments(&self, bytes_owned_identity: &[u8]) -> Result<Vec<TrustEstablishment>> {
+        self.store.trust_establishments().get_all(bytes_owned_identity).await
+    }
+
+    pub async fn get_trust_establishment(&self, trust_establishment_id: i64) -> Result<Option<TrustEstablishment>> {
+        self.store.trust_establishments().get_by_id(trust_establishment_id).await
+    }
+
+    /// Code the owned identity displays for the contact to type, known once both seeds were exchanged
+    pub async fn get_trust_establishment_code(&self, trust_establishment_id: i64) -> Result<Option<String>> {
+        let trust_establishment = self.get_trust_establishment(trust_establishment_id).await?.ok_or(EngineError::UnknownTrustEstablishment)?;
+
+        trust_establishment.get_seeds()
+            .map(|(inviter_seed, invitee_seed)| compute_sas(inviter_seed, invitee_seed, trust_establishment.get_owned_identity()))
+            .transpose()
+    }
+
+    /// Accepts a received invitation, the codes are known once the inviter revealed its seed
+    pub async fn accept_trust_establishment(&self, trust_establishment_id: i64) -> Result<()> {
+        let trust_establishment = self.get_trust_establishment_with_status(trust_establishment_id, TrustEstablishmentStatus::InvitationReceived).await?;
+        let bytes_owned_identity = trust_establishment.get_owned_identity();
+        let owned_identity = self.get_owned_identity(bytes_owned_identity).await?;
+
+        let mut prng = Self::get_default_hmac_prng()?;
+        let seed = prng.bytes(SEED_LENGTH).map_err(|_| EngineError::PRNG)?;
+
+        let payload = JsonPayload {
+            trust_establishment: Some(JsonTrustEstablishmentMessage::Accepted {
+                protocol_uid: trust_establishment.get_protocol_uid().to_vec(),
+                seed: seed.clone(),
+                identity_details: owned_identity.get_identity_details()?,
+            }),
+            ..Default::default()
+        };
+        let mut transaction = self.store.begin().await?;
+        transaction.trust_establishments().set_own_seed(trust_establishment_id, &seed).await?;
+        queue_payload(&mut *transaction, &owned_identity.get_private_identity()?, trust_establishment.get_contact_identity(), &payload, &mut prng).await?;
+        transaction.trust_establishments().set_status(trust_establishment_id, TrustEstablishmentStatus::WaitingForSeed).await?;
+        transaction.commit().await?;
+        self.outbox_wake_up.notify_one();
+
+        self.publish_event(trust_establishment_updated(bytes_owned_identity, trust_establishment_id));
+
+        Ok(())
+    }
+
+    /// Rejects a received invitation, the inviter is told
+    pub async fn reject_trust_establishment(&self, trust_establishment_id: i64) -> Result<()> {
+        let trust_establishment = self.get_trust_establishment_with_status(trust_establishment_id, TrustEstablishmentStatus::InvitationReceived).await?;
+        let bytes_owned_identity = trust_establishment.get_owned_identity();
+
+        let payload = JsonPayload {
+            trust_establishment: Some(JsonTrustEstablishmentMessage::Rejected { protocol_uid: trust_establishment.get_protocol_uid().to_vec() }),
+            ..Default::default()
+        };
+        let owned_identity = self.get_owned_identity(bytes_owned_identity).await?.get_private_identity()?;
+        let mut transaction = self.store.begin().await?;
+        queue_payload(&mut *transaction, &owned_identity, trust_establishment.get_contact_identity(), &payload, &mut Self::get_default_hmac_prng()?).await?;
+        transaction.trust_establishments().set_status(trust_establishment_id, TrustEstablishmentStatus::Cancelled).await?;
+        transaction.commit().await?;
+        self.outbox_wake_up.notify_one();
+
+        self.publish_event(trust_establishment_updated(bytes_owned_identity, trust_establishment_id));
+
+        Ok(())
+    }
+
+    /// Checks the code typed by the user against the one displayed by the contact, fails with `InvalidSasCode` when
+    /// it does not match. The contact is verified once it typed the code of the owned identity as well.
+    pub async fn enter_trust_establishment_code(&self, trust_establishment_id: i64, code: &str) -> Result<()> {
+        let trust_establishment = self.get_trust_establishment_with_status(trust_establishment_id, TrustEstablishmentStatus::CodeRequired).await?;
+        let (inviter_seed, invitee_seed) = trust_establishment.get_seeds().ok_or(EngineError::Technical)?;
+        if code.trim() != compute_sas(inviter_seed, invitee_seed, trust_establishment.get_contact_identity())? {
+            return Err(EngineError::InvalidSasCode);
+        }
+
+        let bytes_owned_identity = trust_establishment.get_owned_identity();
+        let private_identity = self.get_owned_identity(bytes_owned_identity).await?.get_private_identity()?;
+        let mut prng = Self::get_default_hmac_prng()?;
+        let signature = private_identity.sign(CONFIRMATION_SIGNATURE_PREFIX, &confirmation_statement(&trust_establishment)?, &mut prng)
+            .map_err(|_| EngineError::Technical)?;
+        let payload = JsonPayload {
+            trust_establishment: Some(JsonTrustEstablishmentMessage::Confirmed { protocol_uid: trust_establishment.get_protocol_uid().to_vec(), signature }),
+            ..Default::default()
+        };
+        let mut events = vec![];
+        let mut transaction = self.store.begin().await?;
+        queue_payload(&mut *transaction, &private_identity, trust_establishment.get_contact_identity(), &payload, &mut prng).await?;
+        match trust_establishment.is_contact_confirmed() {
+            true => complete(&mut *transaction, &mut events, &trust_establishment).await?,
+            false => {
+                transaction.trust_establishments().set_status(trust_establishment_id, TrustEstablishmentStatus::WaitingForContactCode).await?;
+                events.push(trust_establishment_updated(bytes_owned_identity, trust_establishment_id));
+            }
+        }
+        transaction.commit().await?;
+        self.outbox_wake_up.notify_one();
+        publish_events(&self.events, events);
+
+        Ok(())
+    }
+
+    async fn get_trust_establishment_with_status(&self, trust_establishment_id: i64, status: TrustEstablishmentStatus) -> Result<TrustEstablishment> {
+        self.store.trust_establishments().get_by_id(trust_establishment_id).await?
+            .filter(|trust_establishment| trust_establishment.get_status().is_ok_and(|current_status| current_status == status))
+            .ok_or(EngineError::UnknownTrustEstablishment)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use olvid_core::crypto::commitment::{Commitment, CommitmentWithSHA256};
+
+    use crate::Engine;
+
+    use super::{commitment_tag, compute_sas};
+
+    #[test]
+    fn codes_depend_on_both_seeds_and_on_the_displaying_identity() {
+        let code = compute_sas(&[1; 32], &[2; 32], &[3]).unwrap();
+        assert_eq!(code.len(), 4);
+        assert!(code.chars().all(|character| character.is_ascii_digit()));
+        assert_eq!(code, compute_sas(&[1; 32], &[2; 32], &[3]).unwrap());
+
+        let other_codes = [compute_sas(&[1; 32], &[2; 32], &[4]), compute_sas(&[1; 32], &[5; 32], &[3]), compute_sas(&[2; 32], &[1; 32], &[3])];
+        assert!(other_codes.into_iter().any(|other_code| other_code.unwrap() != code));
+    }
+
+    #[test]
+    fn commitment_only_opens_for_the_inviter() {
+        let mut prng = Engine::get_default_hmac_prng().unwrap();
+        let (commitment, decommitment) = CommitmentWithSHA256::commit(&commitment_tag(&[0; 32], &[1]).unwrap(), &[7; 32], &mut prng).unwrap();
+
+        assert_eq!(CommitmentWithSHA256::open(&commitment, &commitment_tag(&[0; 32], &[1]).unwrap(), &decommitment).unwrap(), vec![7; 32]);
+        assert!(CommitmentWithSHA256::open(&commitment, &commitment_tag(&[0; 32], &[2]).unwrap(), &decommitment).is_err());
+    }
+}
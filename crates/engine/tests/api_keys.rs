@@ -0,0 +1,98 @@
+mod common;
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use engine::{entities::identity::{API_KEY_STATUS_EXPIRED, API_KEY_STATUS_LICENSE_EXHAUSTED, API_KEY_STATUS_VALID}, events::EngineEvent, Engine, EngineError};
+use mock_server::{MockServer, StoredApiKey};
+use uuid::Uuid;
+
+use common::{details, wait_for_event};
+
+const API_KEY: Uuid = Uuid::from_u128(0x6f1e_2c5a_0d4b_4e8f_9a71_3b2c_5d6e_7f80);
+
+fn stored_api_key(status: u8) -> StoredApiKey {
+    StoredApiKey { status: status.into(), permissions: 3, expiration_timestamp: 4_102_444_800_000 }
+}
+
+#[tokio::test]
+async fn api_key_is_registered_and_its_status_tracked() {
+    let server = MockServer::start().await.unwrap();
+    server.state().set_api_key(&API_KEY.to_string(), stored_api_key(API_KEY_STATUS_VALID));
+    let mut engine = Engine::init_with_database_url(&server.url(), Some(API_KEY), "sqlite::memory:").await.unwrap();
+    let alice = engine.generate_simple_identity(details("Alice")).await.unwrap().identity.get_identity();
+    let mut events = engine.subscribe_to_events();
+
+    let unknown_key = Uuid::from_u128(1);
+    assert!(matches!(engine.query_api_key_status(&alice, unknown_key).await, Err(EngineError::InvalidApiKey)));
+    assert!(matches!(engine.register_api_key(&alice, Some(unknown_key)).await, Err(EngineError::InvalidApiKey)));
+    assert_eq!(engine.query_api_key_status(&alice, API_KEY).await.unwrap().status, i64::from(API_KEY_STATUS_VALID));
+
+    // The key the engine was initialized with
+    let api_key_status = engine.register_api_key(&alice, None).await.unwrap();
+    assert_eq!(api_key_status.permissions, 3);
+    let obv_identity = engine.get_all_owned_identities().await.unwrap().remove(0);
+    assert_eq!(obv_identity.api_key_status, api_key_status);
+
+    server.state().set_api_key(&API_KEY.to_string(), stored_api_key(API_KEY_STATUS_EXPIRED));
+    engine.refresh_api_key_status(&alice).await.unwrap();
+    server.state().set_api_key(&API_KEY.to_string(), stored_api_key(API_KEY_STATUS_LICENSE_EXHAUSTED));
+    engine.refresh_api_key_status(&alice).await.unwrap();
+    assert_eq!(engine.get_all_owned_identities().await.unwrap()[0].api_key_status.status, i64::from(API_KEY_STATUS_LICENSE_EXHAUSTED));
+
+    let mut api_key_events = vec![];
+    while let Ok(event) = events.try_recv() {
+        if matches!(event, EngineEvent::ApiKeyExpired { .. } | EngineEvent::ApiKeyLicenseExhausted { .. }) {
+            api_key_events.push(event);
+        }
+    }
+    assert!(matches!(api_key_events.as_slice(), [EngineEvent::ApiKeyExpired { .. }, EngineEvent::ApiKeyLicenseExhausted { .. }]));
+}
+
+#[tokio::test]
+async fn registering_requires_an_api_key() {
+    let server = MockServer::start().await.unwrap();
+    let mut engine = Engine::init_with_database_url(&server.url(), None, "sqlite::memory:").await.unwrap();
+    let alice = engine.generate_simple_identity(details("Alice")).await.unwrap().identity.get_identity();
+
+    assert!(matches!(engine.register_api_key(&alice, None).await, Err(EngineError::NoApiKey)));
+}
+
+#[tokio::test]
+async fn sessions_opened_in_the_background_update_the_status() {
+    let server = MockServer::start().await.unwrap();
+    server.state().set_api_key(&API_KEY.to_string(), stored_api_key(API_KEY_STATUS_VALID));
+    let mut engine = Engine::init_with_database_url(&server.url(), Some(API_KEY), "sqlite::memory:").await.unwrap();
+    let alice = engine.generate_simple_identity(details("Alice")).await.unwrap().identity.get_identity();
+    engine.register_api_key(&alice, None).await.unwrap();
+    let mut events = engine.subscribe_to_events();
+
+    // Fetching opens a new session once the server dropped the previous one
+    server.state().set_api_key(&API_KEY.to_string(), stored_api_key(API_KEY_STATUS_LICENSE_EXHAUSTED));
+    server.state().invalidate_sessions();
+    engine.fetch_messages(&alice).await.unwrap();
+
+    wait_for_event(&mut events, |event| matches!(event, EngineEvent::ApiKeyLicenseExhausted { owned_identity } if *owned_identity == alice)).await;
+    assert_eq!(engine.get_all_owned_identities().await.unwrap()[0].api_key_status.status, i64::from(API_KEY_STATUS_LICENSE_EXHAUSTED));
+}
+
+#[tokio::test]
+async fn api_key_expires_at_its_expiration_timestamp() {
+    let server = MockServer::start().await.unwrap();
+    let mut engine = Engine::init_with_database_url(&server.url(), Some(API_KEY), "sqlite::memory:").await.unwrap();
+    let alice = engine.generate_simple_identity(details("Alice")).await.unwrap().identity.get_identity();
+    let mut events = engine.subscribe_to_events();
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as i64;
+    let stored_api_key = StoredApiKey { expiration_timestamp: now + 3000, ..stored_api_key(API_KEY_STATUS_VALID) };
+    server.state().set_api_key(&API_KEY.to_string(), stored_api_key.clone());
+
+    assert_eq!(engine.register_api_key(&alice, None).await.unwrap().status, i64::from(API_KEY_STATUS_VALID));
+
+    wait_for_event(&mut events, |event| matches!(event, EngineEvent::ApiKeyExpired { owned_identity } if *owned_identity == alice)).await;
+    let api_key_status = engine.get_all_owned_identities().await.unwrap().remove(0).api_key_status;
+    assert_eq!(api_key_status.status, i64::from(API_KEY_STATUS_EXPIRED));
+    assert_eq!(api_key_status.expiration_timestamp, Some(stored_api_key.expiration_timestamp));
+
+    // The server still answering the key is valid does not make it valid again
+    assert_eq!(engine.refresh_api_key_status(&alice).await.unwrap().status, i64::from(API_KEY_STATUS_EXPIRED));
+}
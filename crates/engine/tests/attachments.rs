@@ -0,0 +1,125 @@
+mod common;
+
+use std::{path::Path, time::Duration};
+
+use engine::{configuration::EngineConfiguration, entities::attachment::AttachmentStatus, events::EngineEvent, network::RetryPolicy, server::OutboundServerMessage, Engine, EngineError};
+use mock_server::MockServer;
+
+use common::{configuration, details, wait_for_event, wait_for_received};
+
+const CHUNK_LENGTH: i64 = 1024;
+
+/// Engine with small chunks and fast retries
+async fn attachment_engine(server: &MockServer, directory: &Path, database_url: &str) -> Engine {
+    let configuration = EngineConfiguration {
+        database_url: database_url.to_owned(),
+        attachment_chunk_length: CHUNK_LENGTH,
+        retry_policy: RetryPolicy { initial_delay: Duration::from_millis(100), max_delay: Duration::from_millis(500), max_attempts: 50 },
+        ..configuration(server, directory)
+    };
+
+    Engine::init_with_configuration(configuration).await.unwrap()
+}
+
+fn message_to(identity: &[u8]) -> OutboundServerMessage {
+    OutboundServerMessage {
+        to_identity: identity.to_vec(),
+        device_uids: vec![],
+        wrapped_key: vec![1; 32],
+        encrypted_content: b"with attachment".to_vec(),
+        is_application_message: true,
+        attachment_chunk_counts: vec![],
+    }
+}
+
+fn write_source_file(directory: &Path, size: usize) -> (std::path::PathBuf, Vec<u8>) {
+    let content = (0..size).map(|index| (index % 251) as u8).collect::<Vec<u8>>();
+    let path = directory.join("report.pdf");
+    std::fs::write(&path, &content).unwrap();
+    (path, content)
+}
+
+#[tokio::test]
+async fn attachment_is_uploaded_then_downloaded() {
+    let server = MockServer::start().await.unwrap();
+    let alice_directory = tempfile::tempdir().unwrap();
+    let bob_directory = tempfile::tempdir().unwrap();
+    let mut alice_engine = attachment_engine(&server, alice_directory.path(), "sqlite::memory:").await;
+    let mut bob_engine = attachment_engine(&server, bob_directory.path(), "sqlite::memory:").await;
+
+    let alice = alice_engine.generate_simple_identity(details("Alice")).await.unwrap().identity.get_identity();
+    let bob = bob_engine.generate_simple_identity(details("Bob")).await.unwrap().identity.get_identity();
+    alice_engine.add_contact(&alice, &bob, &details("Bob")).await.unwrap();
+    bob_engine.add_contact(&bob, &alice, &details("Alice")).await.unwrap();
+    let mut alice_events = alice_engine.subscribe_to_events();
+    let mut bob_events = bob_engine.subscribe_to_events();
+
+    let (source_path, content) = write_source_file(alice_directory.path(), 4500);
+    let attachment = alice_engine.prepare_outbound_attachment(&alice, &source_path, "application/pdf").await.unwrap();
+    assert_eq!(attachment.get_chunk_count(), 5);
+    let attachment_id = attachment.get_id().unwrap();
+
+    let sent_message = alice_engine.send_text_message_with_attachments(&alice, &bob, "Report", None, &[], &[attachment_id]).await.unwrap();
+    assert!(sent_message.get_attachment_metadata().unwrap().is_empty());
+    wait_for_event(&mut alice_events, |event| *event == EngineEvent::AttachmentStatusChanged { attachment_id, status: AttachmentStatus::Complete }).await;
+    // An attachment is only sent once
+    assert!(matches!(alice_engine.send_text_message_with_attachments(&alice, &bob, "Again", None, &[], &[attachment_id]).await, Err(EngineError::UnknownAttachment)));
+
+    assert_eq!(bob_engine.fetch_messages(&bob).await.unwrap(), 1);
+    let message_id = wait_for_received(&mut bob_events).await;
+    let message = bob_engine.get_message(message_id).await.unwrap().unwrap();
+    let metadata = message.get_attachment_metadata().unwrap();
+    assert_eq!(metadata.len(), 1);
+    assert_eq!((metadata[0].size, metadata[0].file_name.as_str(), metadata[0].mime_type.as_str()), (4500, "report.pdf", "application/pdf"));
+
+    let inbound_attachment_id = bob_engine.start_attachment_download(&bob, message.get_message_uid().unwrap(), 0, metadata[0].clone()).await.unwrap();
+    wait_for_event(&mut bob_events, |event| *event == EngineEvent::AttachmentStatusChanged { attachment_id: inbound_attachment_id, status: AttachmentStatus::Complete }).await;
+
+    let inbound_attachment = bob_engine.get_attachment(inbound_attachment_id).await.unwrap().unwrap();
+    assert_eq!(std::fs::read(inbound_attachment.get_file_path()).unwrap(), content);
+    assert!(inbound_attachment.get_file_path().starts_with(bob_directory.path().to_str().unwrap()));
+
+    // The message is deleted from the server once its attachments are downloaded
+    assert!(bob_engine.download_messages(&bob).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn interrupted_upload_resumes_after_restart() {
+    let server = MockServer::start().await.unwrap();
+    let alice_directory = tempfile::tempdir().unwrap();
+    let database_url = format!("sqlite://{}", alice_directory.path().join("alice.db").display());
+
+    let (source_path, _) = write_source_file(alice_directory.path(), 4500);
+    server.state().limit_chunk_transfers(Some(2));
+
+    let (alice, attachment_id) = {
+        let mut alice_engine = attachment_engine(&server, alice_directory.path(), &database_url).await;
+        let alice = alice_engine.generate_simple_identity(details("Alice")).await.unwrap().identity.get_identity();
+        let mut alice_events = alice_engine.subscribe_to_events();
+
+        let attachment = alice_engine.prepare_outbound_attachment(&alice, &source_path, "application/pdf").await.unwrap();
+        let attachment_id = attachment.get_id().unwrap();
+        alice_engine.queue_message_with_attachments(&alice, &message_to(&alice), &[attachment_id]).await.unwrap();
+
+        wait_for_event(&mut alice_events, |event| *event == EngineEvent::AttachmentProgress { attachment_id, transferred_chunk_count: 2, chunk_count: 5 }).await;
+        (alice, attachment_id)
+    };
+
+    server.state().limit_chunk_transfers(None);
+    let alice_engine = attachment_engine(&server, alice_directory.path(), &database_url).await;
+    let mut alice_events = alice_engine.subscribe_to_events();
+
+    // Chunks already uploaded before the restart are not uploaded again
+    let first_progress = loop {
+        let event = tokio::time::timeout(Duration::from_secs(20), alice_events.recv()).await.unwrap().unwrap();
+        if let EngineEvent::AttachmentProgress { transferred_chunk_count, .. } = event {
+            break transferred_chunk_count;
+        }
+    };
+    assert_eq!(first_progress, 3);
+    wait_for_event(&mut alice_events, |event| *event == EngineEvent::AttachmentStatusChanged { attachment_id, status: AttachmentStatus::Complete }).await;
+
+    let attachment = alice_engine.get_attachment(attachment_id).await.unwrap().unwrap();
+    assert_eq!(attachment.get_transferred_chunk_count(), 5);
+    assert_eq!(attachment.get_owned_identity(), alice);
+}
@@ -0,0 +1,75 @@
+mod common;
+
+use engine::{entities::identity::{DisplayNameFormat, NameOrder}, EngineError};
+use mock_server::MockServer;
+
+use common::{details, new_engine, transfer_profile};
+
+#[tokio::test]
+async fn backup_restores_identities_contacts_groups_and_settings() {
+    let server = MockServer::start().await.unwrap();
+    let mut engine = new_engine(&server).await;
+    let mut contacts_engine = new_engine(&server).await;
+    let alice = engine.generate_simple_identity(details("Alice")).await.unwrap().identity.get_identity();
+    let secret = engine.generate_simple_identity(details("Secret Alice")).await.unwrap().identity.get_identity();
+    engine.hide_owned_identity(&secret, "1234").await.unwrap();
+    let bob = contacts_engine.generate_simple_identity(details("Bob")).await.unwrap().identity.get_identity();
+    engine.add_contact(&alice, &bob, &details("Bob")).await.unwrap();
+    engine.set_contact_custom_display_name(&alice, &bob, Some("Bobby")).await.unwrap();
+    engine.create_group(&alice, "Friends", std::slice::from_ref(&bob)).await.unwrap();
+    let format = DisplayNameFormat::builder().name_order(NameOrder::LastFirst).build();
+    engine.set_display_name_format(&format).await.unwrap();
+
+    assert!(matches!(engine.export_backup().await, Err(EngineError::NoBackupKey)));
+    let backup_key = engine.generate_backup_key().await.unwrap();
+    let backup = engine.export_backup().await.unwrap();
+    assert_eq!(engine.export_backup().await.unwrap(), backup);
+
+    let mut restored_engine = new_engine(&server).await;
+    let wrong_key = restored_engine.generate_backup_key().await.unwrap();
+    assert!(matches!(restored_engine.restore_backup(&wrong_key, &backup).await, Err(EngineError::InvalidBackupKey)));
+    restored_engine.restore_backup(&backup_key.to_lowercase(), &backup).await.unwrap();
+
+    // Same content, same key
+    assert_eq!(restored_engine.export_backup().await.unwrap(), backup);
+    assert_eq!(restored_engine.get_display_name_format().await.unwrap(), format);
+    let owned_identities = restored_engine.get_all_owned_identities().await.unwrap();
+    assert_eq!(owned_identities.len(), 1);
+    assert_eq!(owned_identities[0].identity.get_identity(), alice);
+    assert_eq!(restored_engine.unlock_hidden_profiles("1234").await.unwrap()[0].identity.get_identity(), secret);
+
+    let contacts = restored_engine.get_contacts(&alice).await.unwrap();
+    assert_eq!(contacts.len(), 1);
+    assert_eq!(contacts[0].get_display_name(), "Bobby");
+    let groups = restored_engine.get_groups(&alice).await.unwrap();
+    assert_eq!(groups.len(), 1);
+    assert_eq!(groups[0].get_name(), "Friends");
+    assert_eq!(restored_engine.get_group_members(groups[0].get_id().unwrap()).await.unwrap().len(), 2);
+
+    // The restored identity keeps its private key
+    restored_engine.fetch_messages(&alice).await.unwrap();
+
+    assert!(matches!(restored_engine.restore_backup(&backup_key, &backup).await, Err(EngineError::BackupRestoreNotEmpty)));
+}
+
+#[tokio::test]
+async fn backup_restores_the_other_devices() {
+    let server = MockServer::start().await.unwrap();
+    let mut engine = new_engine(&server).await;
+    let mut other_device = new_engine(&server).await;
+    let alice = engine.generate_simple_identity(details("Alice")).await.unwrap().identity.get_identity();
+    transfer_profile(&mut engine, &mut other_device, &alice).await;
+    let owned_devices = engine.get_owned_devices(&alice).await.unwrap();
+    assert_eq!(owned_devices.len(), 1);
+
+    let backup_key = engine.generate_backup_key().await.unwrap();
+    let backup = engine.export_backup().await.unwrap();
+    let restored_engine = new_engine(&server).await;
+    restored_engine.restore_backup(&backup_key, &backup).await.unwrap();
+
+    let restored_devices = restored_engine.get_owned_devices(&alice).await.unwrap();
+    assert_eq!(restored_devices.len(), 1);
+    assert_eq!(restored_devices[0].get_device_uid(), owned_devices[0].get_device_uid());
+    assert_eq!(restored_devices[0].get_creation_timestamp(), owned_devices[0].get_creation_timestamp());
+    assert_eq!(restored_engine.export_backup().await.unwrap(), backup);
+}
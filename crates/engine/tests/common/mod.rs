@@ -0,0 +1,145 @@
+//! Fixtures shared by the integration tests, each test file only uses some of them.
+#![allow(dead_code)]
+
+use std::{path::Path, sync::Arc, time::Duration};
+
+use engine::{configuration::EngineConfiguration, entities::{identity::JsonIdentityDetails, outbox_message::DeliveryState}, events::EngineEvent, profile_transfers::ProfileTransferStatus, store::{DatabaseSecret, MemoryStore}, Engine, EngineError};
+use mock_server::MockServer;
+use tokio::{sync::broadcast, time::Instant};
+
+pub fn details(first_name: &str) -> JsonIdentityDetails {
+    JsonIdentityDetails::builder().first_name(first_name.to_owned()).build()
+}
+
+/// Engine on an in-memory SQLite database
+pub async fn new_engine(server: &MockServer) -> Engine {
+    open_engine(server, "sqlite::memory:", None).await.unwrap()
+}
+
+/// Engine on the given database, which may already exist
+pub async fn open_engine(server: &MockServer, database_url: &str, database_secret: Option<DatabaseSecret>) -> Result<Engine, EngineError> {
+    Engine::init_with_configuration(
+        EngineConfiguration::builder()
+            .server_url(server.url())
+            .database_url(database_url.to_owned())
+            .maybe_database_secret(database_secret)
+            .build()
+    ).await
+}
+
+/// Two engines whose owned identities are contacts of each other
+pub async fn alice_and_bob(server: &MockServer) -> ((Engine, Vec<u8>), (Engine, Vec<u8>)) {
+    let mut alice_engine = new_engine(server).await;
+    let mut bob_engine = new_engine(server).await;
+
+    let alice = alice_engine.generate_simple_identity(details("Alice")).await.unwrap().identity.get_identity();
+    let bob = bob_engine.generate_simple_identity(details("Bob")).await.unwrap().identity.get_identity();
+
+    alice_engine.add_contact(&alice, &bob, &details("Bob")).await.unwrap();
+    bob_engine.add_contact(&bob, &alice, &details("Alice")).await.unwrap();
+
+    ((alice_engine, alice), (bob_engine, bob))
+}
+
+/// Alice knows Bob and Carol, who only know Alice
+pub async fn alice_bob_and_carol(server: &MockServer) -> ((Engine, Vec<u8>), (Engine, Vec<u8>), (Engine, Vec<u8>)) {
+    let mut alice_engine = new_engine(server).await;
+    let mut bob_engine = new_engine(server).await;
+    let mut carol_engine = new_engine(server).await;
+    let alice = alice_engine.generate_simple_identity(details("Alice")).await.unwrap().identity.get_identity();
+    let bob = bob_engine.generate_simple_identity(details("Bob")).await.unwrap().identity.get_identity();
+    let carol = carol_engine.generate_simple_identity(details("Carol")).await.unwrap().identity.get_identity();
+    alice_engine.add_contact(&alice, &bob, &details("Bob")).await.unwrap();
+    alice_engine.add_contact(&alice, &carol, &details("Carol")).await.unwrap();
+    bob_engine.add_contact(&bob, &alice, &details("Alice")).await.unwrap();
+    carol_engine.add_contact(&carol, &alice, &details("Alice")).await.unwrap();
+
+    ((alice_engine, alice), (bob_engine, bob), (carol_engine, carol))
+}
+
+pub fn configuration(server: &MockServer, directory: &Path) -> EngineConfiguration {
+    EngineConfiguration::builder()
+        .server_url(server.url())
+        .database_url("sqlite::memory:".to_owned())
+        .data_directory(directory.to_path_buf())
+        .build()
+}
+
+/// The same engine on SQLite, on an encrypted SQLite database and on a memory store
+pub async fn engines(server: &MockServer, directory: &Path) -> Vec<Engine> {
+    let encrypted_configuration = EngineConfiguration {
+        database_url: format!("sqlite://{}", directory.join("encrypted.db").display()),
+        database_secret: Some(DatabaseSecret::Passphrase("correct horse".to_owned())),
+        ..configuration(server, directory)
+    };
+
+    vec![
+        Engine::init_with_configuration(configuration(server, directory)).await.unwrap(),
+        Engine::init_with_configuration(encrypted_configuration).await.unwrap(),
+        Engine::init_with_store(configuration(server, directory), Arc::new(MemoryStore::new())).await.unwrap(),
+    ]
+}
+
+/// Waits until the server accepted one more outbox message
+pub async fn wait_until_sent(events: &mut broadcast::Receiver<EngineEvent>) {
+    wait_for_event(events, |event| matches!(event, EngineEvent::MessageDeliveryStateChanged { delivery_state: DeliveryState::Sent, .. })).await;
+}
+
+/// Waits for the first event matching `is_expected`
+pub async fn wait_for_event(events: &mut broadcast::Receiver<EngineEvent>, is_expected: impl Fn(&EngineEvent) -> bool) -> EngineEvent {
+    loop {
+        let event = tokio::time::timeout(Duration::from_secs(20), events.recv()).await.unwrap().unwrap();
+        if is_expected(&event) {
+            return event;
+        }
+    }
+}
+
+/// Waits until a message is received, returns its id
+pub async fn wait_for_received(events: &mut broadcast::Receiver<EngineEvent>) -> i64 {
+    match wait_for_event(events, |event| matches!(event, EngineEvent::MessageReceived { .. })).await {
+        EngineEvent::MessageReceived { message_id, .. } => message_id,
+        _ => unreachable!(),
+    }
+}
+
+/// Fetches the messages of an owned identity until processing one of them publishes the expected event
+pub async fn fetch_until(engine: &mut Engine, owned_identity: &[u8], events: &mut broadcast::Receiver<EngineEvent>, is_expected: impl Fn(&EngineEvent) -> bool) -> EngineEvent {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        assert!(Instant::now() < deadline, "expected event not received");
+        engine.fetch_messages(owned_identity).await.unwrap();
+        while let Ok(event) = events.try_recv() {
+            if is_expected(&event) {
+                return event;
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+/// Polls both devices until both display the code
+pub async fn exchange_seeds(source: &mut Engine, target: &mut Engine, session_number: i64) -> String {
+    for _ in 0..10 {
+        let source_status = source.poll_profile_transfer(session_number).await.unwrap();
+        let target_status = target.poll_profile_transfer(session_number).await.unwrap();
+        if let (ProfileTransferStatus::SasReady { sas: source_sas }, ProfileTransferStatus::SasReady { sas: target_sas }) = (source_status, target_status) {
+            assert_eq!(source_sas, target_sas);
+            return source_sas;
+        }
+    }
+    panic!("The devices did not exchange their seeds");
+}
+
+/// Transfers an owned identity to the target device, after which each device knows the other one
+pub async fn transfer_profile(source: &mut Engine, target: &mut Engine, owned_identity: &[u8]) {
+    let session_number = source.start_profile_transfer(owned_identity).await.unwrap();
+    target.join_profile_transfer(session_number).await.unwrap();
+    let sas = exchange_seeds(source, target, session_number).await;
+    target.confirm_profile_transfer(session_number, &sas).await.unwrap();
+    source.confirm_profile_transfer(session_number, &sas).await.unwrap();
+
+    let finished = ProfileTransferStatus::Finished { owned_identity: owned_identity.to_vec() };
+    assert_eq!(target.poll_profile_transfer(session_number).await.unwrap(), finished);
+    assert_eq!(source.poll_profile_transfer(session_number).await.unwrap(), finished);
+}
@@ -0,0 +1,208 @@
+mod common;
+
+use std::sync::Arc;
+
+use engine::{
+    entities::{contact::ContactTrustLevel, introduction::IntroductionStatus},
+    events::EngineEvent,
+    search::MessageSearchQuery,
+    store::{MemoryStore, Store},
+    Engine, EngineError,
+};
+use mock_server::MockServer;
+
+use common::{configuration, details, engines, fetch_until, wait_until_sent};
+
+fn message_received(event: &EngineEvent) -> bool {
+    matches!(event, EngineEvent::MessageReceived { .. })
+}
+
+fn introduction_updated(event: &EngineEvent) -> bool {
+    matches!(event, EngineEvent::IntroductionUpdated { .. })
+}
+
+#[tokio::test]
+async fn messages_from_blocked_identities_are_dropped_until_unblocked() {
+    let server = MockServer::start().await.unwrap();
+    let directory = tempfile::tempdir().unwrap();
+    let mut alice_engine = Engine::init_with_configuration(configuration(&server, directory.path())).await.unwrap();
+    let alice = alice_engine.generate_simple_identity(details("Alice")).await.unwrap().identity.get_identity();
+    let mut alice_events = alice_engine.subscribe_to_events();
+
+    for mut bob_engine in engines(&server, directory.path()).await {
+        let bob = bob_engine.generate_simple_identity(details("Bob")).await.unwrap().identity.get_identity();
+        alice_engine.add_contact(&alice, &bob, &details("Bob")).await.unwrap();
+        bob_engine.add_contact(&bob, &alice, &details("Alice")).await.unwrap();
+        let mut bob_events = bob_engine.subscribe_to_events();
+
+        bob_engine.block_contact(&bob, &alice).await.unwrap();
+        assert_eq!(bob_events.try_recv().unwrap(), EngineEvent::ContactBlocked { owned_identity: bob.clone(), contact_identity: alice.clone() });
+        let blocked_contacts = bob_engine.get_blocked_contacts(&bob).await.unwrap();
+        assert_eq!(blocked_contacts.len(), 1);
+        assert_eq!(blocked_contacts[0].get_contact_identity(), alice);
+        // Blocking does not delete the contact, but nothing can be sent to it
+        assert_eq!(bob_engine.get_contacts(&bob).await.unwrap().len(), 1);
+        assert!(matches!(bob_engine.send_text_message(&bob, &alice, "Hi", None, &[]).await, Err(EngineError::ContactBlocked)));
+        assert!(matches!(bob_engine.add_contact(&bob, &alice, &details("Alice")).await, Err(EngineError::ContactBlocked)));
+
+        alice_engine.send_text_message(&alice, &bob, "Dropped", None, &[]).await.unwrap();
+        wait_until_sent(&mut alice_events).await;
+        assert_eq!(bob_engine.fetch_messages(&bob).await.unwrap(), 1);
+        assert!(bob_engine.get_pending_inbox_messages(&bob).await.unwrap().is_empty());
+        assert!(std::iter::from_fn(|| bob_events.try_recv().ok()).all(|event| !message_received(&event)));
+
+        bob_engine.unblock_contact(&bob, &alice).await.unwrap();
+        assert!(bob_engine.get_blocked_contacts(&bob).await.unwrap().is_empty());
+        alice_engine.send_text_message(&alice, &bob, "Delivered", None, &[]).await.unwrap();
+        wait_until_sent(&mut alice_events).await;
+        let EngineEvent::MessageReceived { message_id, .. } = fetch_until(&mut bob_engine, &bob, &mut bob_events, message_received).await else { unreachable!() };
+        let message = bob_engine.get_message(message_id).await.unwrap().unwrap();
+        assert_eq!(message.get_body(), Some("Delivered"));
+        assert_eq!(bob_engine.get_discussion_messages(message.get_discussion_id(), None, 10).await.unwrap().len(), 1);
+    }
+}
+
+#[tokio::test]
+async fn blocked_identities_are_not_added_by_introductions() {
+    let server = MockServer::start().await.unwrap();
+    let directory = tempfile::tempdir().unwrap();
+    let mut alice_engine = Engine::init_with_configuration(configuration(&server, directory.path())).await.unwrap();
+    let mut bob_engine = Engine::init_with_configuration(configuration(&server, directory.path())).await.unwrap();
+    let mut carol_engine = Engine::init_with_configuration(configuration(&server, directory.path())).await.unwrap();
+    let alice = alice_engine.generate_simple_identity(details("Alice")).await.unwrap().identity.get_identity();
+    let bob = bob_engine.generate_simple_identity(details("Bob")).await.unwrap().identity.get_identity();
+    let carol = carol_engine.generate_simple_identity(details("Carol")).await.unwrap().identity.get_identity();
+    alice_engine.add_contact(&alice, &bob, &details("Bob")).await.unwrap();
+    alice_engine.add_contact(&alice, &carol, &details("Carol")).await.unwrap();
+    bob_engine.add_contact(&bob, &alice, &details("Alice")).await.unwrap();
+    let mut bob_events = bob_engine.subscribe_to_events();
+
+    // Carol was a contact of Bob, deleted and blocked
+    bob_engine.add_contact(&bob, &carol, &details("Carol")).await.unwrap();
+    bob_engine.block_contact(&bob, &carol).await.unwrap();
+    bob_engine.delete_contact(&bob, &carol).await.unwrap();
+    assert_eq!(bob_engine.get_blocked_contacts(&bob).await.unwrap().len(), 1);
+
+    alice_engine.introduce_contacts(&alice, &bob, &carol).await.unwrap();
+    let EngineEvent::IntroductionUpdated { introduction_id, .. } = fetch_until(&mut bob_engine, &bob, &mut bob_events, introduction_updated).await else { unreachable!() };
+    assert!(matches!(bob_engine.accept_introduction(introduction_id).await, Err(EngineError::ContactBlocked)));
+    assert_eq!(bob_engine.get_introductions(&bob).await.unwrap()[0].get_status().unwrap(), IntroductionStatus::Pending);
+    assert!(bob_engine.get_contacts(&bob).await.unwrap().iter().all(|contact| contact.get_contact_identity() != carol));
+
+    // Blocking the introducer cancels its pending introductions
+    bob_engine.block_contact(&bob, &alice).await.unwrap();
+    assert_eq!(bob_engine.get_introductions(&bob).await.unwrap()[0].get_status().unwrap(), IntroductionStatus::Rejected);
+    alice_engine.block_contact(&alice, &carol).await.unwrap();
+    assert!(matches!(alice_engine.introduce_contacts(&alice, &bob, &carol).await, Err(EngineError::ContactBlocked)));
+}
+
+#[tokio::test]
+async fn revoked_trust_is_lowered_on_both_sides() {
+    let server = MockServer::start().await.unwrap();
+    let directory = tempfile::tempdir().unwrap();
+    let mut alice_engine = Engine::init_with_configuration(configuration(&server, directory.path())).await.unwrap();
+    // The stores of Bob and Carol are kept to look at their channels
+    let bob_store = Arc::new(MemoryStore::new());
+    let carol_store = Arc::new(MemoryStore::new());
+    let mut bob_engine = Engine::init_with_store(configuration(&server, directory.path()), bob_store.clone()).await.unwrap();
+    let mut carol_engine = Engine::init_with_store(configuration(&server, directory.path()), carol_store.clone()).await.unwrap();
+    let alice = alice_engine.generate_simple_identity(details("Alice")).await.unwrap().identity.get_identity();
+    let bob = bob_engine.generate_simple_identity(details("Bob")).await.unwrap().identity.get_identity();
+    let carol = carol_engine.generate_simple_identity(details("Carol")).await.unwrap().identity.get_identity();
+    alice_engine.add_contact(&alice, &bob, &details("Bob")).await.unwrap();
+    alice_engine.add_contact(&alice, &carol, &details("Carol")).await.unwrap();
+    bob_engine.add_contact(&bob, &alice, &details("Alice")).await.unwrap();
+    carol_engine.add_contact(&carol, &alice, &details("Alice")).await.unwrap();
+    let mut bob_events = bob_engine.subscribe_to_events();
+    let mut carol_events = carol_engine.subscribe_to_events();
+
+    // Bob and Carol trust each other through an introduction, and have a channel with each other once they accepted it
+    alice_engine.introduce_contacts(&alice, &bob, &carol).await.unwrap();
+    let EngineEvent::IntroductionUpdated { introduction_id: bob_introduction_id, .. } = fetch_until(&mut bob_engine, &bob, &mut bob_events, introduction_updated).await else { unreachable!() };
+    let EngineEvent::IntroductionUpdated { introduction_id: carol_introduction_id, .. } = fetch_until(&mut carol_engine, &carol, &mut carol_events, introduction_updated).await else { unreachable!() };
+    bob_engine.accept_introduction(bob_introduction_id).await.unwrap();
+    fetch_until(&mut carol_engine, &carol, &mut carol_events, introduction_updated).await;
+    carol_engine.accept_introduction(carol_introduction_id).await.unwrap();
+    fetch_until(&mut bob_engine, &bob, &mut bob_events, |event| matches!(event, EngineEvent::ContactAdded { .. })).await;
+    let first_channel = bob_store.contact_channels().get(&bob, &carol).await.unwrap().unwrap();
+    assert!(carol_store.contact_channels().get(&carol, &bob).await.unwrap().is_some());
+
+    let trust_level = |contacts: Vec<engine::entities::contact::Contact>, identity: &[u8]| {
+        contacts.into_iter().find(|contact| contact.get_contact_identity() == identity).unwrap().get_trust_level().unwrap()
+    };
+    assert_eq!(trust_level(bob_engine.get_contacts(&bob).await.unwrap(), &carol), ContactTrustLevel::Introduced);
+    assert_eq!(trust_level(carol_engine.get_contacts(&carol).await.unwrap(), &bob), ContactTrustLevel::Introduced);
+    assert!(matches!(bob_engine.revoke_contact_trust(&bob, &[7; 32]).await, Err(EngineError::UnknownContact)));
+
+    // Carol already got a contact update when the introduction completed
+    while carol_events.try_recv().is_ok() {}
+    bob_engine.revoke_contact_trust(&bob, &carol).await.unwrap();
+    assert_eq!(trust_level(bob_engine.get_contacts(&bob).await.unwrap(), &carol), ContactTrustLevel::Unverified);
+    assert!(bob_store.contact_channels().get(&bob, &carol).await.unwrap().is_none());
+    let updated = fetch_until(&mut carol_engine, &carol, &mut carol_events, |event| matches!(event, EngineEvent::ContactUpdated { .. })).await;
+    assert_eq!(updated, EngineEvent::ContactUpdated { owned_identity: carol.clone(), contact_identity: bob.clone() });
+    assert_eq!(trust_level(carol_engine.get_contacts(&carol).await.unwrap(), &bob), ContactTrustLevel::Unverified);
+    assert!(carol_store.contact_channels().get(&carol, &bob).await.unwrap().is_none());
+
+    // Revoking does not remove the contact, the next message opens a new channel
+    bob_engine.send_text_message(&bob, &carol, "Still here", None, &[]).await.unwrap();
+    let EngineEvent::MessageReceived { message_id, .. } = fetch_until(&mut carol_engine, &carol, &mut carol_events, message_received).await else { unreachable!() };
+    assert_eq!(carol_engine.get_message(message_id).await.unwrap().unwrap().get_body(), Some("Still here"));
+    let second_channel = bob_store.contact_channels().get(&bob, &carol).await.unwrap().unwrap();
+    assert_ne!(second_channel.get_channel_key(), first_channel.get_channel_key());
+}
+
+#[tokio::test]
+async fn deleted_contacts_lose_their_discussion_and_group_membership() {
+    let server = MockServer::start().await.unwrap();
+    let directory = tempfile::tempdir().unwrap();
+    let mut alice_engine = Engine::init_with_configuration(configuration(&server, directory.path())).await.unwrap();
+    let alice = alice_engine.generate_simple_identity(details("Alice")).await.unwrap().identity.get_identity();
+    let mut alice_events = alice_engine.subscribe_to_events();
+    let source_path = directory.path().join("Menu.pdf");
+    std::fs::write(&source_path, b"pdf").unwrap();
+    let metadata = alice_engine.prepare_outbound_attachment(&alice, &source_path, "application/pdf").await.unwrap().get_metadata();
+
+    for mut bob_engine in engines(&server, directory.path()).await {
+        let bob = bob_engine.generate_simple_identity(details("Bob")).await.unwrap().identity.get_identity();
+        alice_engine.add_contact(&alice, &bob, &details("Bob")).await.unwrap();
+        bob_engine.add_contact(&bob, &alice, &details("Alice")).await.unwrap();
+        let mut bob_events = bob_engine.subscribe_to_events();
+        while alice_events.try_recv().is_ok() {}
+
+        // Bob administers a group Alice joined
+        let bob_group_id = bob_engine.create_group(&bob, "Lunch", std::slice::from_ref(&alice)).await.unwrap().get_id().unwrap();
+        let EngineEvent::GroupUpdated { group_id: alice_group_id, .. } = fetch_until(&mut alice_engine, &alice, &mut alice_events, |event| matches!(event, EngineEvent::GroupUpdated { .. })).await else { unreachable!() };
+        alice_engine.accept_group_invitation(alice_group_id).await.unwrap();
+        fetch_until(&mut bob_engine, &bob, &mut bob_events, |event| matches!(event, EngineEvent::GroupUpdated { .. })).await;
+
+        alice_engine.send_text_message(&alice, &bob, "Today's menu", None, &[]).await.unwrap();
+        wait_until_sent(&mut alice_events).await;
+        let EngineEvent::MessageReceived { message_id, .. } = fetch_until(&mut bob_engine, &bob, &mut bob_events, message_received).await else { unreachable!() };
+        let message = bob_engine.get_message(message_id).await.unwrap().unwrap();
+        let attachment_id = bob_engine.start_attachment_download(&bob, message.get_message_uid().unwrap(), 0, metadata.clone()).await.unwrap();
+
+        assert!(matches!(bob_engine.delete_contact(&bob, &[7; 32]).await, Err(EngineError::UnknownContact)));
+        bob_engine.delete_contact(&bob, &alice).await.unwrap();
+        let events: Vec<EngineEvent> = std::iter::from_fn(|| bob_events.try_recv().ok()).collect();
+        assert!(events.contains(&EngineEvent::ContactDeleted { owned_identity: bob.clone(), contact_identity: alice.clone() }));
+        assert!(events.contains(&EngineEvent::DiscussionDeleted { discussion_id: message.get_discussion_id() }));
+
+        assert!(bob_engine.get_contacts(&bob).await.unwrap().is_empty());
+        assert!(bob_engine.get_message(message_id).await.unwrap().is_none());
+        assert!(bob_engine.get_attachment(attachment_id).await.unwrap().is_none());
+        assert!(bob_engine.search_messages(&bob, &MessageSearchQuery::builder().text("menu").build(), None, 10).await.unwrap().is_empty());
+        let discussions = bob_engine.get_discussions(&bob).await.unwrap();
+        assert_eq!(discussions.len(), 1);
+        assert!(discussions[0].get_group_identifier().is_some());
+        let members = bob_engine.get_group_members(bob_group_id).await.unwrap();
+        assert!(members.iter().all(|member| member.get_member_identity() != alice));
+
+        let removed = fetch_until(&mut alice_engine, &alice, &mut alice_events, |event| matches!(event, EngineEvent::GroupRemoved { .. })).await;
+        assert!(matches!(removed, EngineEvent::GroupRemoved { owned_identity, .. } if owned_identity == alice));
+        assert!(alice_engine.get_group(alice_group_id).await.unwrap().is_none());
+
+        // A deleted contact that is not blocked can be added again
+        bob_engine.add_contact(&bob, &alice, &details("Alice")).await.unwrap();
+    }
+}
@@ -0,0 +1,251 @@
+mod common;
+
+use std::path::Path;
+
+use engine::{configuration::EngineConfiguration, entities::identity::JsonIdentityDetails, keycloak::JsonKeycloakAuthState, store::DatabaseSecret, Engine, EngineError};
+use jose_jwk::JwkSet;
+use mock_server::{keycloak::MockKeycloakState, MockServer};
+use sqlx::SqlitePool;
+
+use common::{configuration, details, new_engine, open_engine};
+
+fn passphrase(passphrase: &str) -> Option<DatabaseSecret> {
+    Some(DatabaseSecret::Passphrase(passphrase.to_owned()))
+}
+
+
+/// Every value of a text column that contains `needle`, in any table, read without the engine
+async fn find_in_database(database_path: &Path, needle: &str) -> Vec<String> {
+    let db = SqlitePool::connect(&format!("sqlite://{}", database_path.display())).await.unwrap();
+    let text_columns: Vec<(String, String)> = sqlx::query_as(
+        "SELECT tables.name, columns.name FROM sqlite_master AS tables, pragma_table_info(tables.name) AS columns WHERE tables.type = 'table' AND tables.name != '_sqlx_migrations' AND columns.type = 'TEXT'"
+    )
+    .fetch_all(&db)
+    .await
+    .unwrap();
+    assert!(text_columns.len() > 30);
+
+    let mut found = Vec::new();
+    for (table, column) in text_columns {
+        let rows: Vec<(String,)> = sqlx::query_as(&format!("SELECT {column} FROM {table} WHERE {column} LIKE $1"))
+            .bind(format!("%{needle}%"))
+            .fetch_all(&db)
+            .await
+            .unwrap();
+        found.extend(rows.into_iter().map(|(text,)| format!("{table}.{column}: {text}")));
+    }
+    db.close().await;
+
+    found
+}
+
+/// Alice with Bob as contact and a message sent to him
+async fn populate(engine: &mut Engine, server: &MockServer) -> (Vec<u8>, Vec<u8>, i64) {
+    let alice = engine.generate_simple_identity(details("Alice")).await.unwrap().identity.get_identity();
+    let bob = new_engine(server).await.generate_simple_identity(details("Bob")).await.unwrap().identity.get_identity();
+    engine.add_contact(&alice, &bob, &details("Bob")).await.unwrap();
+    let message_id = engine.send_text_message(&alice, &bob, "Meet at noon", None, &[]).await.unwrap().get_id().unwrap();
+
+    (alice, bob, message_id)
+}
+
+#[tokio::test]
+async fn sensitive_columns_are_encrypted_at_rest() {
+    let server = MockServer::start().await.unwrap();
+    let directory = tempfile::tempdir().unwrap();
+    let database_path = directory.path().join("alice.db");
+    let database_url = format!("sqlite://{}", database_path.display());
+
+    let (alice, message_id) = {
+        let mut engine = open_engine(&server, &database_url, passphrase("correct horse")).await.unwrap();
+        let (alice, _, message_id) = populate(&mut engine, &server).await;
+        (alice, message_id)
+    };
+
+    assert_eq!(find_in_database(&database_path, "Alice").await, Vec::<String>::new());
+    assert_eq!(find_in_database(&database_path, "Bob").await, Vec::<String>::new());
+    assert_eq!(find_in_database(&database_path, "noon").await, Vec::<String>::new());
+
+    let engine = open_engine(&server, &database_url, passphrase("correct horse")).await.unwrap();
+    assert_eq!(engine.get_current_owned_identity().await.unwrap().unwrap().identity_details, details("Alice"));
+    let contacts = engine.get_contacts(&alice).await.unwrap();
+    assert_eq!(contacts[0].get_identity_details().unwrap(), details("Bob"));
+    assert_eq!(contacts[0].get_display_name(), "Bob");
+    assert_eq!(engine.get_message(message_id).await.unwrap().unwrap().get_body(), Some("Meet at noon"));
+}
+
+#[tokio::test]
+async fn names_attachments_and_return_receipt_keys_are_encrypted_at_rest() {
+    let server = MockServer::start().await.unwrap();
+    let directory = tempfile::tempdir().unwrap();
+    let database_path = directory.path().join("alice.db");
+    let configuration = EngineConfiguration {
+        database_url: format!("sqlite://{}", database_path.display()),
+        database_secret: passphrase("correct horse"),
+        ..configuration(&server, directory.path())
+    };
+    let source_path = directory.path().join("noon-plan.txt");
+    std::fs::write(&source_path, b"Bring towels").unwrap();
+
+    let (alice, attachment_id, message_id) = {
+        let mut engine = Engine::init_with_configuration(configuration.clone()).await.unwrap();
+        let (alice, bob, _) = populate(&mut engine, &server).await;
+        engine.set_contact_custom_display_name(&alice, &bob, Some("Bobby")).await.unwrap();
+        engine.set_owned_identity_custom_display_name(&alice, Some("Alice L.")).await.unwrap();
+        engine.create_group(&alice, "Noon club", std::slice::from_ref(&bob)).await.unwrap();
+        let attachment_id = engine.prepare_outbound_attachment(&alice, &source_path, "text/plain").await.unwrap().get_id().unwrap();
+        let message_id = engine.send_text_message_with_attachments(&alice, &bob, "Plan attached", None, &[], &[attachment_id]).await.unwrap().get_id().unwrap();
+        (alice, attachment_id, message_id)
+    };
+
+    for needle in ["Alice", "Bob", "noon"] {
+        assert_eq!(find_in_database(&database_path, needle).await, Vec::<String>::new());
+    }
+
+    let engine = Engine::init_with_configuration(configuration).await.unwrap();
+    let attachment = engine.get_attachment(attachment_id).await.unwrap().unwrap();
+    let message = engine.get_message(message_id).await.unwrap().unwrap();
+    let db = SqlitePool::connect(&format!("sqlite://{}", database_path.display())).await.unwrap();
+    let (key,): (Vec<u8>,) = sqlx::query_as("SELECT key FROM attachments WHERE id = $1").bind(attachment_id).fetch_one(&db).await.unwrap();
+    let (return_receipt_key,): (Vec<u8>,) = sqlx::query_as("SELECT return_receipt_key FROM messages WHERE id = $1").bind(message_id).fetch_one(&db).await.unwrap();
+    db.close().await;
+    assert!(!contains(&key, attachment.get_key()));
+    assert!(!contains(&return_receipt_key, message.get_return_receipt_key().unwrap()));
+
+    assert_eq!(attachment.get_file_name(), "noon-plan.txt");
+    assert_eq!(std::fs::read(attachment.get_file_path()).unwrap(), b"Bring towels");
+    assert_eq!(engine.get_current_owned_identity().await.unwrap().unwrap().display_name, "Alice L.");
+    assert_eq!(engine.get_contacts(&alice).await.unwrap()[0].get_display_name(), "Bobby");
+    assert_eq!(engine.get_groups(&alice).await.unwrap()[0].get_name(), "Noon club");
+    let titles = engine.get_discussions(&alice).await.unwrap().iter().map(|discussion| discussion.get_title().to_owned()).collect::<Vec<_>>();
+    assert!(titles.contains(&"Bob".to_owned()) && titles.contains(&"Noon club".to_owned()));
+}
+
+#[tokio::test]
+async fn encrypted_database_needs_its_secret() {
+    let server = MockServer::start().await.unwrap();
+    let directory = tempfile::tempdir().unwrap();
+    let database_url = format!("sqlite://{}", directory.path().join("alice.db").display());
+
+    populate(&mut open_engine(&server, &database_url, passphrase("correct horse")).await.unwrap(), &server).await;
+
+    assert!(matches!(open_engine(&server, &database_url, None).await, Err(EngineError::DatabaseLocked)));
+    assert!(matches!(open_engine(&server, &database_url, passphrase("wrong horse")).await, Err(EngineError::InvalidDatabaseSecret)));
+    assert!(matches!(open_engine(&server, &database_url, passphrase("")).await, Err(EngineError::InvalidDatabaseSecret)));
+}
+
+#[tokio::test]
+async fn changing_the_passphrase_keeps_the_rows() {
+    let server = MockServer::start().await.unwrap();
+    let directory = tempfile::tempdir().unwrap();
+    let database_path = directory.path().join("alice.db");
+    let database_url = format!("sqlite://{}", database_path.display());
+
+    let message_id = {
+        let mut engine = open_engine(&server, &database_url, passphrase("correct horse")).await.unwrap();
+        let (_, _, message_id) = populate(&mut engine, &server).await;
+
+        let wrong_secret = DatabaseSecret::Passphrase("wrong horse".to_owned());
+        let new_secret = DatabaseSecret::Passphrase("battery staple".to_owned());
+        assert!(matches!(engine.change_database_secret(&wrong_secret, &new_secret).await, Err(EngineError::InvalidDatabaseSecret)));
+        engine.change_database_secret(&DatabaseSecret::Passphrase("correct horse".to_owned()), &new_secret).await.unwrap();
+        message_id
+    };
+
+    assert!(matches!(open_engine(&server, &database_url, passphrase("correct horse")).await, Err(EngineError::InvalidDatabaseSecret)));
+    let engine = open_engine(&server, &database_url, passphrase("battery staple")).await.unwrap();
+    assert_eq!(engine.get_message(message_id).await.unwrap().unwrap().get_body(), Some("Meet at noon"));
+}
+
+#[tokio::test]
+async fn secret_given_to_a_plain_database_encrypts_its_rows() {
+    let server = MockServer::start().await.unwrap();
+    let directory = tempfile::tempdir().unwrap();
+    let database_path = directory.path().join("alice.db");
+    let database_url = format!("sqlite://{}", database_path.display());
+    let key_file = directory.path().join("database.key");
+    std::fs::write(&key_file, [7; 32]).unwrap();
+
+    let message_id = {
+        let mut engine = open_engine(&server, &database_url, None).await.unwrap();
+        let (_, _, message_id) = populate(&mut engine, &server).await;
+
+        let secret = DatabaseSecret::Passphrase("correct horse".to_owned());
+        assert!(matches!(engine.change_database_secret(&secret, &secret).await, Err(EngineError::DatabaseNotEncrypted)));
+        message_id
+    };
+    assert!(!find_in_database(&database_path, "noon").await.is_empty());
+
+    let engine = open_engine(&server, &database_url, Some(DatabaseSecret::KeyFile(key_file.clone()))).await.unwrap();
+    assert_eq!(engine.get_message(message_id).await.unwrap().unwrap().get_body(), Some("Meet at noon"));
+    drop(engine);
+
+    assert!(find_in_database(&database_path, "noon").await.is_empty());
+    assert!(find_in_database(&database_path, "Alice").await.is_empty());
+
+    std::fs::write(&key_file, [8; 32]).unwrap();
+    assert!(matches!(open_engine(&server, &database_url, Some(DatabaseSecret::KeyFile(key_file))).await, Err(EngineError::InvalidDatabaseSecret)));
+}
+
+/// Raw values of the blob and text columns holding secrets other than details and bodies
+async fn read_secrets(database_path: &Path) -> (Vec<u8>, Vec<u8>, Vec<u8>, String, String) {
+    let db = SqlitePool::connect(&format!("sqlite://{}", database_path.display())).await.unwrap();
+    let (seed,): (Vec<u8>,) = sqlx::query_as("SELECT seed FROM backup_key").fetch_one(&db).await.unwrap();
+    let (blob_key,): (Vec<u8>,) = sqlx::query_as("SELECT blob_key FROM groups_v2").fetch_one(&db).await.unwrap();
+    let (channel_key,): (Vec<u8>,) = sqlx::query_as("SELECT channel_key FROM contact_channels").fetch_one(&db).await.unwrap();
+    let (client_secret, serialized_auth_state): (String, String) = sqlx::query_as("SELECT client_secret, serialized_auth_state FROM keycloak_servers").fetch_one(&db).await.unwrap();
+    db.close().await;
+
+    (seed, blob_key, channel_key, client_secret, serialized_auth_state)
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|window| window == needle)
+}
+
+#[tokio::test]
+async fn backup_group_channel_and_keycloak_secrets_are_encrypted_at_rest() {
+    let server = MockServer::start().await.unwrap();
+    let directory = tempfile::tempdir().unwrap();
+    let database_path = directory.path().join("alice.db");
+    let database_url = format!("sqlite://{}", database_path.display());
+
+    let (alice, backup_key) = {
+        let mut engine = open_engine(&server, &database_url, None).await.unwrap();
+        let (alice, bob, _) = populate(&mut engine, &server).await;
+        engine.create_group(&alice, "Friends", std::slice::from_ref(&bob)).await.unwrap();
+
+        let keycloak_state = MockKeycloakState::new();
+        let jwks: JwkSet = serde_json::from_value(keycloak_state.jwks()).unwrap();
+        let signature_key = jwks.keys[0].key.clone();
+        let auth_state = JsonKeycloakAuthState { access_token: "first-access-token".to_owned(), refresh_token: None };
+        let signed_user_details = keycloak_state.sign(&serde_json::json!({ "id": "carol", "firstName": "Carol" }));
+        engine.generate_identity(
+            JsonIdentityDetails::builder().first_name("Carol".to_owned()).signed_user_details(signed_user_details).build(), "", "", &[], &[],
+            Some("https://keycloak.example".to_owned()), "olvid", "client-secret-value", jwks, signature_key, Some(serde_json::to_string(&auth_state).unwrap()), false,
+        ).await.unwrap();
+        (alice, engine.generate_backup_key().await.unwrap())
+    };
+    let (plain_seed, plain_blob_key, plain_channel_key, plain_client_secret, plain_auth_state) = read_secrets(&database_path).await;
+    assert_eq!(plain_client_secret, "client-secret-value");
+    assert!(plain_auth_state.contains("first-access-token"));
+
+    let engine = open_engine(&server, &database_url, passphrase("correct horse")).await.unwrap();
+    let carol = engine.get_all_owned_identities().await.unwrap().into_iter().find(|owned_identity| owned_identity.keycloak_managed).unwrap().identity.get_identity();
+    let auth_state = JsonKeycloakAuthState { access_token: "second-access-token".to_owned(), refresh_token: None };
+    engine.set_keycloak_auth_state(&carol, &serde_json::to_string(&auth_state).unwrap()).await.unwrap();
+
+    let (seed, blob_key, channel_key, client_secret, serialized_auth_state) = read_secrets(&database_path).await;
+    assert!(!contains(&seed, &plain_seed));
+    assert!(!contains(&blob_key, &plain_blob_key));
+    assert!(!contains(&channel_key, &plain_channel_key));
+    assert!(!client_secret.contains("client-secret-value"));
+    assert!(!serialized_auth_state.contains("access-token"));
+
+    let keycloak_server = engine.get_keycloak_server(&carol).await.unwrap().unwrap();
+    assert_eq!(keycloak_server.get_client_secret(), Some("client-secret-value"));
+    assert!(keycloak_server.get_serialized_auth_state().unwrap().contains("second-access-token"));
+    assert_eq!(engine.get_groups(&alice).await.unwrap()[0].get_name(), "Friends");
+    let backup = engine.export_backup().await.unwrap();
+    new_engine(&server).await.restore_backup(&backup_key, &backup).await.unwrap();
+}
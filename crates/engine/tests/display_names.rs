@@ -0,0 +1,48 @@
+use engine::{entities::identity::{DisplayNameFormat, JsonIdentityDetails, NameOrder}, Engine};
+use mock_server::MockServer;
+
+fn details(first_name: &str, last_name: &str, company: Option<&str>) -> JsonIdentityDetails {
+    JsonIdentityDetails::builder().first_name(first_name.to_owned()).last_name(last_name.to_owned()).maybe_company(company.map(str::to_owned)).build()
+}
+
+async fn contact_display_names(engine: &Engine, owned_identity: &[u8]) -> Vec<String> {
+    engine.get_contacts(owned_identity).await.unwrap().iter().map(|contact| contact.get_display_name().to_owned()).collect()
+}
+
+#[tokio::test]
+async fn display_names_and_contact_order_follow_the_format() {
+    let server = MockServer::start().await.unwrap();
+    let mut engine = Engine::init_with_database_url(&server.url(), None, "sqlite::memory:").await.unwrap();
+    let mut contacts_engine = Engine::init_with_database_url(&server.url(), None, "sqlite::memory:").await.unwrap();
+    let alice = engine.generate_simple_identity(details("Alice", "Liddell", Some("Wonderland"))).await.unwrap();
+    assert_eq!(alice.display_name, "Alice Liddell");
+    let alice = alice.identity.get_identity();
+
+    let zoe = contacts_engine.generate_simple_identity(details("Zoe", "Adams", None)).await.unwrap().identity.get_identity();
+    let bob = contacts_engine.generate_simple_identity(details("Bob", "Young", Some("Olvid"))).await.unwrap().identity.get_identity();
+    engine.add_contact(&alice, &zoe, &details("Zoe", "Adams", None)).await.unwrap();
+    engine.add_contact(&alice, &bob, &details("Bob", "Young", Some("Olvid"))).await.unwrap();
+    assert_eq!(contact_display_names(&engine, &alice).await, ["Bob Young", "Zoe Adams"]);
+
+    let format = DisplayNameFormat::builder().name_order(NameOrder::LastFirst).show_company_and_position(true).build();
+    engine.set_display_name_format(&format).await.unwrap();
+    assert_eq!(engine.get_display_name_format().await.unwrap(), format);
+    assert_eq!(contact_display_names(&engine, &alice).await, ["Adams Zoe", "Young Bob (Olvid)"]);
+    assert_eq!(engine.get_all_owned_identities().await.unwrap()[0].display_name, "Liddell Alice (Wonderland)");
+
+    // Nicknames win over the details, and are sorted as such
+    engine.set_contact_custom_display_name(&alice, &bob, Some("Bobby")).await.unwrap();
+    engine.set_owned_identity_custom_display_name(&alice, Some("Me")).await.unwrap();
+    assert_eq!(contact_display_names(&engine, &alice).await, ["Adams Zoe", "Bobby"]);
+    let contacts = engine.get_contacts(&alice).await.unwrap();
+    assert_eq!(contacts[1].get_sort_key(), "bobby");
+    assert_eq!(engine.get_all_owned_identities().await.unwrap()[0].display_name, "Me");
+
+    // New contacts use the current format
+    let carol = contacts_engine.generate_simple_identity(details("Carol", "Baker", None)).await.unwrap().identity.get_identity();
+    engine.add_contact(&alice, &carol, &details("Carol", "Baker", None)).await.unwrap();
+    assert_eq!(contact_display_names(&engine, &alice).await, ["Adams Zoe", "Baker Carol", "Bobby"]);
+
+    engine.set_contact_custom_display_name(&alice, &bob, None).await.unwrap();
+    assert_eq!(contact_display_names(&engine, &alice).await, ["Adams Zoe", "Baker Carol", "Young Bob (Olvid)"]);
+}
@@ -0,0 +1,112 @@
+mod common;
+
+use std::time::Duration;
+
+use engine::{entities::discussion::Ephemerality, events::EngineEvent};
+use mock_server::MockServer;
+use sqlx::SqlitePool;
+
+use common::{details, new_engine, open_engine, wait_for_event, wait_for_received};
+
+#[tokio::test]
+async fn shared_settings_make_messages_disappear() {
+    let server = MockServer::start().await.unwrap();
+    let mut alice_engine = new_engine(&server).await;
+    let mut bob_engine = new_engine(&server).await;
+    let alice = alice_engine.generate_simple_identity(details("Alice")).await.unwrap().identity.get_identity();
+    let bob = bob_engine.generate_simple_identity(details("Bob")).await.unwrap().identity.get_identity();
+    alice_engine.add_contact(&alice, &bob, &details("Bob")).await.unwrap();
+    bob_engine.add_contact(&bob, &alice, &details("Alice")).await.unwrap();
+    let mut alice_events = alice_engine.subscribe_to_events();
+    let mut bob_events = bob_engine.subscribe_to_events();
+
+    alice_engine.send_text_message(&alice, &bob, "Hello", None, &[]).await.unwrap();
+    let alice_discussion_id = alice_engine.get_discussions(&alice).await.unwrap()[0].get_id().unwrap();
+    // Long enough for Bob to read the message before it is deleted, signing and verifying are slow in debug builds
+    let ephemerality = Ephemerality { read_once: true, visibility_duration: None, existence_duration: Some(8) };
+    alice_engine.set_discussion_ephemerality(alice_discussion_id, ephemerality).await.unwrap();
+
+    let secret = alice_engine.send_text_message(&alice, &bob, "Secret", None, &[]).await.unwrap();
+    let secret_id = secret.get_id().unwrap();
+    assert_eq!(secret.get_ephemerality(), ephemerality);
+    // Read once messages leave the sender device once sent
+    wait_for_event(&mut alice_events, |event| *event == EngineEvent::MessageWiped { message_id: secret_id }).await;
+    assert!(alice_engine.get_message(secret_id).await.unwrap().unwrap().is_wiped());
+
+    bob_engine.fetch_messages(&bob).await.unwrap();
+    wait_for_received(&mut bob_events).await;
+    let received_secret_id = wait_for_received(&mut bob_events).await;
+    let bob_discussion = bob_engine.get_discussions(&bob).await.unwrap().remove(0);
+    assert_eq!(bob_discussion.get_ephemerality(), ephemerality);
+    assert_eq!(bob_discussion.get_settings_version(), 1);
+
+    let received_secret = bob_engine.get_message(received_secret_id).await.unwrap().unwrap();
+    assert_eq!(received_secret.get_body(), Some("Secret"));
+    assert_eq!(received_secret.get_ephemerality(), ephemerality);
+
+    bob_engine.mark_message_read(received_secret_id).await.unwrap();
+    wait_for_event(&mut bob_events, |event| *event == EngineEvent::MessageWiped { message_id: received_secret_id }).await;
+    assert_eq!(bob_engine.get_message(received_secret_id).await.unwrap().unwrap().get_body(), None);
+
+    wait_for_event(&mut alice_events, |event| *event == EngineEvent::MessageDeleted { message_id: secret_id }).await;
+    wait_for_event(&mut bob_events, |event| *event == EngineEvent::MessageDeleted { message_id: received_secret_id }).await;
+    assert!(alice_engine.get_message(secret_id).await.unwrap().is_none());
+    assert!(bob_engine.get_message(received_secret_id).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn expiration_reached_while_stopped_applies_on_restart() {
+    let server = MockServer::start().await.unwrap();
+    let directory = tempfile::tempdir().unwrap();
+    let database_url = format!("sqlite://{}", directory.path().join("alice.db").display());
+
+    let message_id = {
+        let mut alice_engine = open_engine(&server, &database_url, None).await.unwrap();
+        let alice = alice_engine.generate_simple_identity(details("Alice")).await.unwrap().identity.get_identity();
+        let bob = new_engine(&server).await.generate_simple_identity(details("Bob")).await.unwrap().identity.get_identity();
+        alice_engine.add_contact(&alice, &bob, &details("Bob")).await.unwrap();
+
+        alice_engine.send_text_message(&alice, &bob, "Hello", None, &[]).await.unwrap();
+        let discussion_id = alice_engine.get_discussions(&alice).await.unwrap()[0].get_id().unwrap();
+        let ephemerality = Ephemerality { existence_duration: Some(1), ..Default::default() };
+        alice_engine.set_discussion_ephemerality(discussion_id, ephemerality).await.unwrap();
+        alice_engine.send_text_message(&alice, &bob, "Short lived", None, &[]).await.unwrap().get_id().unwrap()
+    };
+
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    let alice_engine = open_engine(&server, &database_url, None).await.unwrap();
+
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while alice_engine.get_message(message_id).await.unwrap().is_some() {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }).await.unwrap();
+}
+
+#[tokio::test]
+async fn failing_expiration_does_not_hold_back_the_others() {
+    let server = MockServer::start().await.unwrap();
+    let directory = tempfile::tempdir().unwrap();
+    let database_url = format!("sqlite://{}", directory.path().join("alice.db").display());
+    let mut alice_engine = open_engine(&server, &database_url, None).await.unwrap();
+    let alice = alice_engine.generate_simple_identity(details("Alice")).await.unwrap().identity.get_identity();
+    let bob = new_engine(&server).await.generate_simple_identity(details("Bob")).await.unwrap().identity.get_identity();
+    alice_engine.add_contact(&alice, &bob, &details("Bob")).await.unwrap();
+    let mut alice_events = alice_engine.subscribe_to_events();
+
+    let db = SqlitePool::connect(&database_url).await.unwrap();
+    sqlx::query("CREATE TRIGGER stuck BEFORE DELETE ON messages WHEN old.body = 'Stuck' BEGIN SELECT RAISE(ABORT, 'stuck'); END").execute(&db).await.unwrap();
+    alice_engine.send_text_message(&alice, &bob, "Hello", None, &[]).await.unwrap();
+    let discussion_id = alice_engine.get_discussions(&alice).await.unwrap()[0].get_id().unwrap();
+    let ephemerality = Ephemerality { existence_duration: Some(1), ..Default::default() };
+    alice_engine.set_discussion_ephemerality(discussion_id, ephemerality).await.unwrap();
+    let stuck_id = alice_engine.send_text_message(&alice, &bob, "Stuck", None, &[]).await.unwrap().get_id().unwrap();
+    let short_lived_id = alice_engine.send_text_message(&alice, &bob, "Short lived", None, &[]).await.unwrap().get_id().unwrap();
+
+    // Due after the stuck message
+    wait_for_event(&mut alice_events, |event| *event == EngineEvent::MessageDeleted { message_id: short_lived_id }).await;
+    assert!(alice_engine.get_message(stuck_id).await.unwrap().is_some());
+
+    sqlx::query("DROP TRIGGER stuck").execute(&db).await.unwrap();
+    wait_for_event(&mut alice_events, |event| *event == EngineEvent::MessageDeleted { message_id: stuck_id }).await;
+}
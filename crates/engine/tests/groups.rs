@@ -0,0 +1,119 @@
+mod common;
+
+use std::time::Duration;
+
+use engine::{entities::group::{Group, GroupMember, GroupStatus}, events::EngineEvent, Engine, EngineError};
+use mock_server::MockServer;
+use tokio::time::Instant;
+
+use common::{details, fetch_until, new_engine};
+
+/// Fetches the messages of an owned identity until a group reaches the expected state
+async fn fetch_until_group(engine: &mut Engine, owned_identity: &[u8], group_id: i64, is_expected: impl Fn(&Group, &[GroupMember]) -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        let group = engine.get_group(group_id).await.unwrap().unwrap();
+        if is_expected(&group, &engine.get_group_members(group_id).await.unwrap()) {
+            return;
+        }
+        assert!(Instant::now() < deadline, "expected group state not reached");
+        engine.fetch_messages(owned_identity).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+fn group_updated(event: &EngineEvent) -> bool {
+    matches!(event, EngineEvent::GroupUpdated { .. })
+}
+
+#[tokio::test]
+async fn group_members_join_exchange_messages_and_get_removed() {
+    let server = MockServer::start().await.unwrap();
+    let mut alice_engine = new_engine(&server).await;
+    let mut bob_engine = new_engine(&server).await;
+    let mut carol_engine = new_engine(&server).await;
+    let alice = alice_engine.generate_simple_identity(details("Alice")).await.unwrap().identity.get_identity();
+    let bob = bob_engine.generate_simple_identity(details("Bob")).await.unwrap().identity.get_identity();
+    let carol = carol_engine.generate_simple_identity(details("Carol")).await.unwrap().identity.get_identity();
+    // Bob and Carol only know each other through the group
+    alice_engine.add_contact(&alice, &bob, &details("Bob")).await.unwrap();
+    alice_engine.add_contact(&alice, &carol, &details("Carol")).await.unwrap();
+    bob_engine.add_contact(&bob, &alice, &details("Alice")).await.unwrap();
+    carol_engine.add_contact(&carol, &alice, &details("Alice")).await.unwrap();
+    let mut alice_events = alice_engine.subscribe_to_events();
+    let mut bob_events = bob_engine.subscribe_to_events();
+    let mut carol_events = carol_engine.subscribe_to_events();
+
+    let alice_group = alice_engine.create_group(&alice, "Friends", &[bob.clone(), carol.clone()]).await.unwrap();
+    let alice_group_id = alice_group.get_id().unwrap();
+    assert_eq!(alice_group.get_status().unwrap(), GroupStatus::Joined);
+    let members = alice_engine.get_group_members(alice_group_id).await.unwrap();
+    assert_eq!(members.len(), 3);
+    assert!(members.iter().all(|member| member.is_admin() == (member.get_member_identity() == alice)));
+
+    // Invitations
+    let EngineEvent::GroupUpdated { group_id: bob_group_id, .. } = fetch_until(&mut bob_engine, &bob, &mut bob_events, group_updated).await else { unreachable!() };
+    let bob_group = bob_engine.get_group(bob_group_id).await.unwrap().unwrap();
+    assert_eq!(bob_group.get_name(), "Friends");
+    assert_eq!(bob_group.get_status().unwrap(), GroupStatus::Invited);
+    assert!(bob_engine.get_discussions(&bob).await.unwrap().iter().all(|discussion| discussion.get_group_identifier().is_none()));
+    let EngineEvent::GroupUpdated { group_id: carol_group_id, .. } = fetch_until(&mut carol_engine, &carol, &mut carol_events, group_updated).await else { unreachable!() };
+
+    // Pending members can't administer the group
+    assert!(matches!(bob_engine.rename_group(bob_group_id, "Mine").await, Err(EngineError::NotGroupAdmin)));
+
+    bob_engine.accept_group_invitation(bob_group_id).await.unwrap();
+    carol_engine.accept_group_invitation(carol_group_id).await.unwrap();
+    fetch_until_group(&mut alice_engine, &alice, alice_group_id, |_, members| members.iter().all(|member| !member.is_pending())).await;
+    fetch_until_group(&mut bob_engine, &bob, bob_group_id, |_, members| members.iter().all(|member| !member.is_pending())).await;
+    fetch_until_group(&mut carol_engine, &carol, carol_group_id, |_, members| members.iter().all(|member| !member.is_pending())).await;
+
+    // Messages between members that are not contacts
+    let sent_message = bob_engine.send_group_text_message(bob_group_id, "Hello everyone", None, &[]).await.unwrap();
+    for (engine, owned_identity, events) in [(&mut alice_engine, &alice, &mut alice_events), (&mut carol_engine, &carol, &mut carol_events)] {
+        let EngineEvent::MessageReceived { message_id, .. } = fetch_until(engine, owned_identity, events, |event| matches!(event, EngineEvent::MessageReceived { .. })).await else { unreachable!() };
+        let message = engine.get_message(message_id).await.unwrap().unwrap();
+        assert_eq!(message.get_body(), Some("Hello everyone"));
+        assert_eq!(message.get_sender_identity(), bob);
+        assert_eq!(message.get_sender_sequence_number(), sent_message.get_sender_sequence_number());
+        let discussion = engine.get_discussions(owned_identity).await.unwrap().into_iter().find(|discussion| discussion.get_id() == Some(message.get_discussion_id())).unwrap();
+        assert_eq!(discussion.get_title(), "Friends");
+    }
+
+    // Removal
+    assert!(matches!(alice_engine.leave_group(alice_group_id).await, Err(EngineError::LastGroupAdmin)));
+    alice_engine.remove_group_members(alice_group_id, std::slice::from_ref(&carol)).await.unwrap();
+    let removed = fetch_until(&mut carol_engine, &carol, &mut carol_events, |event| matches!(event, EngineEvent::GroupRemoved { .. })).await;
+    assert_eq!(removed, EngineEvent::GroupRemoved { owned_identity: carol.clone(), group_uid: alice_group.get_group_uid().to_vec() });
+    assert!(carol_engine.get_groups(&carol).await.unwrap().is_empty());
+
+    fetch_until_group(&mut bob_engine, &bob, bob_group_id, |_, members| members.len() == 2).await;
+    let bob_group = bob_engine.get_group(bob_group_id).await.unwrap().unwrap();
+    assert_ne!(bob_group.get_blob_key(), alice_group.get_blob_key());
+}
+
+#[tokio::test]
+async fn admin_permission_is_shared_and_members_can_leave() {
+    let server = MockServer::start().await.unwrap();
+    let mut alice_engine = new_engine(&server).await;
+    let mut bob_engine = new_engine(&server).await;
+    let alice = alice_engine.generate_simple_identity(details("Alice")).await.unwrap().identity.get_identity();
+    let bob = bob_engine.generate_simple_identity(details("Bob")).await.unwrap().identity.get_identity();
+    alice_engine.add_contact(&alice, &bob, &details("Bob")).await.unwrap();
+    bob_engine.add_contact(&bob, &alice, &details("Alice")).await.unwrap();
+    let mut bob_events = bob_engine.subscribe_to_events();
+
+    let alice_group_id = alice_engine.create_group(&alice, "Team", std::slice::from_ref(&bob)).await.unwrap().get_id().unwrap();
+    assert!(matches!(alice_engine.set_group_member_admin(alice_group_id, &alice, false).await, Err(EngineError::LastGroupAdmin)));
+    alice_engine.set_group_member_admin(alice_group_id, &bob, true).await.unwrap();
+
+    let EngineEvent::GroupUpdated { group_id: bob_group_id, .. } = fetch_until(&mut bob_engine, &bob, &mut bob_events, group_updated).await else { unreachable!() };
+    bob_engine.accept_group_invitation(bob_group_id).await.unwrap();
+    // Bob administers the group as well, but is still pending until Alice confirms
+    bob_engine.rename_group(bob_group_id, "Team 2").await.unwrap();
+    fetch_until_group(&mut alice_engine, &alice, alice_group_id, |group, members| group.get_name() == "Team 2" && members.iter().all(|member| !member.is_pending())).await;
+
+    alice_engine.leave_group(alice_group_id).await.unwrap();
+    assert!(alice_engine.get_groups(&alice).await.unwrap().is_empty());
+    fetch_until_group(&mut bob_engine, &bob, bob_group_id, |_, members| members.len() == 1 && members[0].get_member_identity() == bob).await;
+}
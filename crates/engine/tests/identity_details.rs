@@ -0,0 +1,70 @@
+mod common;
+
+use std::collections::HashMap;
+
+use engine::{entities::{contact::Contact, identity::{JsonIdentityDetails, ObvIdentity}}, events::EngineEvent, Engine};
+use mock_server::MockServer;
+
+use common::{alice_and_bob, details, fetch_until};
+
+async fn get_obv_identity(engine: &Engine, owned_identity: &[u8]) -> ObvIdentity {
+    engine.get_all_owned_identities().await.unwrap().into_iter().find(|identity| identity.identity.get_identity() == owned_identity).unwrap()
+}
+
+async fn get_contact(engine: &Engine, owned_identity: &[u8]) -> Contact {
+    engine.get_contacts(owned_identity).await.unwrap().remove(0)
+}
+
+#[tokio::test]
+async fn published_details_are_trusted_once_accepted() {
+    let server = MockServer::start().await.unwrap();
+    let ((alice_engine, alice), (mut bob_engine, bob)) = alice_and_bob(&server).await;
+    let mut bob_events = bob_engine.subscribe_to_events();
+    let new_details = JsonIdentityDetails::builder()
+        .first_name("Alice".to_owned())
+        .last_name("Liddell".to_owned())
+        .company("Wonderland".to_owned())
+        .position("Explorer".to_owned())
+        .custom_fields(HashMap::from([("Pet".to_owned(), "Dinah".to_owned())]))
+        .build();
+
+    alice_engine.update_owned_identity_details(&alice, &new_details, Some("https://example.com/alice.png")).await.unwrap();
+    let alice_identity = get_obv_identity(&alice_engine, &alice).await;
+    assert_eq!(alice_identity.identity_details, details("Alice"));
+    assert_eq!(alice_identity.unpublished_details, Some(new_details.clone()));
+    assert_eq!(alice_identity.unpublished_photo_url.as_deref(), Some("https://example.com/alice.png"));
+
+    alice_engine.publish_owned_identity_details(&alice).await.unwrap();
+    let alice_identity = get_obv_identity(&alice_engine, &alice).await;
+    assert_eq!(alice_identity.identity_details, new_details);
+    assert_eq!(alice_identity.photo_url.as_deref(), Some("https://example.com/alice.png"));
+    assert_eq!(alice_identity.unpublished_details, None);
+
+    fetch_until(&mut bob_engine, &bob, &mut bob_events, |event| matches!(event, EngineEvent::ContactUpdated { .. })).await;
+    let contact = get_contact(&bob_engine, &bob).await;
+    assert_eq!(contact.get_identity_details().unwrap(), details("Alice"));
+    assert_eq!(contact.get_published_identity_details().unwrap(), Some(new_details.clone()));
+    assert_eq!(contact.get_published_details_version(), 1);
+
+    bob_engine.accept_contact_published_details(&bob, &alice).await.unwrap();
+    let contact = get_contact(&bob_engine, &bob).await;
+    assert_eq!(contact.get_identity_details().unwrap(), new_details);
+    assert_eq!(contact.get_published_identity_details().unwrap(), None);
+}
+
+#[tokio::test]
+async fn publishing_the_trusted_details_needs_no_acceptance() {
+    let server = MockServer::start().await.unwrap();
+    let ((alice_engine, alice), (mut bob_engine, bob)) = alice_and_bob(&server).await;
+    let mut bob_events = bob_engine.subscribe_to_events();
+
+    // Nothing to publish yet
+    alice_engine.publish_owned_identity_details(&alice).await.unwrap();
+    alice_engine.update_owned_identity_details(&alice, &details("Alice"), None).await.unwrap();
+    alice_engine.publish_owned_identity_details(&alice).await.unwrap();
+
+    fetch_until(&mut bob_engine, &bob, &mut bob_events, |event| matches!(event, EngineEvent::ContactUpdated { .. })).await;
+    let contact = get_contact(&bob_engine, &bob).await;
+    assert_eq!(contact.get_published_details_version(), 1);
+    assert_eq!(contact.get_published_identity_details().unwrap(), None);
+}
@@ -0,0 +1,70 @@
+mod common;
+
+use engine::{entities::{contact::ContactTrustLevel, introduction::IntroductionStatus}, events::EngineEvent, EngineError};
+use mock_server::MockServer;
+
+use common::{alice_bob_and_carol, details, fetch_until};
+
+fn introduction_updated(event: &EngineEvent) -> bool {
+    matches!(event, EngineEvent::IntroductionUpdated { .. })
+}
+
+#[tokio::test]
+async fn accepted_introduction_makes_trusted_contacts() {
+    let server = MockServer::start().await.unwrap();
+    let ((alice_engine, alice), (mut bob_engine, bob), (mut carol_engine, carol)) = alice_bob_and_carol(&server).await;
+    let mut bob_events = bob_engine.subscribe_to_events();
+    let mut carol_events = carol_engine.subscribe_to_events();
+
+    assert!(matches!(alice_engine.introduce_contacts(&alice, &bob, &bob).await, Err(EngineError::InvalidIntroduction)));
+    alice_engine.introduce_contacts(&alice, &bob, &carol).await.unwrap();
+
+    let EngineEvent::IntroductionUpdated { introduction_id: bob_introduction_id, .. } = fetch_until(&mut bob_engine, &bob, &mut bob_events, introduction_updated).await else { unreachable!() };
+    let bob_introductions = bob_engine.get_introductions(&bob).await.unwrap();
+    assert_eq!(bob_introductions.len(), 1);
+    assert_eq!(bob_introductions[0].get_introducer_identity(), alice);
+    assert_eq!(bob_introductions[0].get_contact_identity(), carol);
+    assert_eq!(bob_introductions[0].get_identity_details().unwrap(), details("Carol"));
+    assert_eq!(bob_introductions[0].get_status().unwrap(), IntroductionStatus::Pending);
+    let EngineEvent::IntroductionUpdated { introduction_id: carol_introduction_id, .. } = fetch_until(&mut carol_engine, &carol, &mut carol_events, introduction_updated).await else { unreachable!() };
+
+    bob_engine.accept_introduction(bob_introduction_id).await.unwrap();
+    assert!(matches!(bob_engine.accept_introduction(bob_introduction_id).await, Err(EngineError::UnknownIntroduction)));
+    // Carol receives Bob's acceptance before answering
+    fetch_until(&mut carol_engine, &carol, &mut carol_events, |event| *event == EngineEvent::IntroductionUpdated { owned_identity: carol.clone(), introduction_id: carol_introduction_id }).await;
+    assert!(carol_engine.get_contacts(&carol).await.unwrap().iter().all(|contact| contact.get_contact_identity() != bob));
+
+    carol_engine.accept_introduction(carol_introduction_id).await.unwrap();
+    let carol_contacts = carol_engine.get_contacts(&carol).await.unwrap();
+    let bob_contact = carol_contacts.iter().find(|contact| contact.get_contact_identity() == bob).unwrap();
+    assert_eq!(bob_contact.get_display_name(), "Bob");
+    assert_eq!(bob_contact.get_trust_level().unwrap(), ContactTrustLevel::Introduced);
+
+    let finished = EngineEvent::ProtocolFinished { owned_identity: bob.clone(), protocol_instance_uid: bob_introductions[0].get_introduction_uid().to_vec() };
+    fetch_until(&mut bob_engine, &bob, &mut bob_events, |event| *event == finished).await;
+    assert_eq!(bob_engine.get_introductions(&bob).await.unwrap()[0].get_status().unwrap(), IntroductionStatus::Completed);
+    bob_engine.send_text_message(&bob, &carol, "Nice to meet you", None, &[]).await.unwrap();
+    let EngineEvent::MessageReceived { message_id, .. } = fetch_until(&mut carol_engine, &carol, &mut carol_events, |event| matches!(event, EngineEvent::MessageReceived { .. })).await else { unreachable!() };
+    assert_eq!(carol_engine.get_message(message_id).await.unwrap().unwrap().get_body(), Some("Nice to meet you"));
+}
+
+#[tokio::test]
+async fn rejected_introduction_adds_no_contact() {
+    let server = MockServer::start().await.unwrap();
+    let ((alice_engine, alice), (mut bob_engine, bob), (mut carol_engine, carol)) = alice_bob_and_carol(&server).await;
+    let mut bob_events = bob_engine.subscribe_to_events();
+    let mut carol_events = carol_engine.subscribe_to_events();
+
+    alice_engine.introduce_contacts(&alice, &bob, &carol).await.unwrap();
+    let EngineEvent::IntroductionUpdated { introduction_id: bob_introduction_id, .. } = fetch_until(&mut bob_engine, &bob, &mut bob_events, introduction_updated).await else { unreachable!() };
+    let EngineEvent::IntroductionUpdated { introduction_id: carol_introduction_id, .. } = fetch_until(&mut carol_engine, &carol, &mut carol_events, introduction_updated).await else { unreachable!() };
+
+    carol_engine.accept_introduction(carol_introduction_id).await.unwrap();
+    fetch_until(&mut bob_engine, &bob, &mut bob_events, introduction_updated).await;
+    bob_engine.reject_introduction(bob_introduction_id).await.unwrap();
+
+    assert_eq!(bob_engine.get_introductions(&bob).await.unwrap()[0].get_status().unwrap(), IntroductionStatus::Rejected);
+    assert_eq!(bob_engine.get_contacts(&bob).await.unwrap().len(), 1);
+    assert_eq!(carol_engine.get_introductions(&carol).await.unwrap()[0].get_status().unwrap(), IntroductionStatus::Accepted);
+    assert_eq!(carol_engine.get_contacts(&carol).await.unwrap().len(), 1);
+}
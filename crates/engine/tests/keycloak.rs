@@ -0,0 +1,105 @@
+mod common;
+
+use engine::{entities::identity::JsonIdentityDetails, keycloak::JsonKeycloakAuthState, Engine, EngineError};
+use jose_jwk::JwkSet;
+use mock_server::{keycloak::MockKeycloakState, MockKeycloakServer, MockKeycloakUser, MockServer};
+
+use common::{details, new_engine};
+
+fn user(id: &str, first_name: &str) -> MockKeycloakUser {
+    MockKeycloakUser { id: id.to_owned(), username: id.to_owned(), first_name: first_name.to_owned(), ..Default::default() }
+}
+
+/// Creates the identity the way the app does once the user logged in, with the details signed by the server
+async fn generate_keycloak_identity(engine: &mut Engine, keycloak: &MockKeycloakServer, user_id: &str, signer: &MockKeycloakState) -> Result<Vec<u8>, EngineError> {
+    let user = keycloak.state().data.lock().unwrap().users[user_id].clone();
+    let signed_user_details = signer.sign(&serde_json::json!({ "id": user.id, "firstName": user.first_name }));
+    let identity_details = JsonIdentityDetails::builder().first_name(user.first_name).signed_user_details(signed_user_details).build();
+
+    generate_identity_with_details(engine, keycloak, user_id, identity_details).await
+}
+
+/// Creates the identity bound to the keycloak user with the given details
+async fn generate_identity_with_details(engine: &mut Engine, keycloak: &MockKeycloakServer, user_id: &str, identity_details: JsonIdentityDetails) -> Result<Vec<u8>, EngineError> {
+    let (access_token, refresh_token) = keycloak.state().issue_tokens(user_id);
+    let auth_state = JsonKeycloakAuthState { access_token, refresh_token: Some(refresh_token) };
+    let jwks: JwkSet = serde_json::from_value(keycloak.state().jwks()).unwrap();
+    let signature_key = jwks.keys[0].key.clone();
+
+    let obv_identity = engine.generate_identity(
+        identity_details, "", "", &[], &[],
+        Some(keycloak.url()), "olvid", "", jwks, signature_key, Some(serde_json::to_string(&auth_state).unwrap()), false,
+    ).await?;
+    assert!(obv_identity.keycloak_managed);
+
+    Ok(obv_identity.identity.get_identity())
+}
+
+#[tokio::test]
+async fn keycloak_details_directory_and_revocations() {
+    let server = MockServer::start().await.unwrap();
+    let keycloak = MockKeycloakServer::start().await.unwrap();
+    keycloak.state().add_user(user("alice", "Alice"));
+    keycloak.state().add_user(user("bob", "Bob"));
+    let mut alice_engine = new_engine(&server).await;
+    let mut bob_engine = new_engine(&server).await;
+
+    let forger = MockKeycloakState::new();
+    let forged = generate_keycloak_identity(&mut alice_engine, &keycloak, "alice", &forger).await;
+    assert!(matches!(forged, Err(EngineError::InvalidKeycloakSignature)));
+    let unsigned = generate_identity_with_details(&mut alice_engine, &keycloak, "alice", details("Alice")).await;
+    assert!(matches!(unsigned, Err(EngineError::InvalidKeycloakSignature)));
+    // Only the signed details are kept
+    let signed_user_details = keycloak.state().sign(&serde_json::json!({ "id": "alice", "firstName": "Alice" }));
+    let edited_details = JsonIdentityDetails::builder().first_name("Mallory".to_owned()).signed_user_details(signed_user_details).build();
+    let mut edited_engine = new_engine(&server).await;
+    generate_identity_with_details(&mut edited_engine, &keycloak, "alice", edited_details).await.unwrap();
+    assert_eq!(edited_engine.get_all_owned_identities().await.unwrap()[0].display_name, "Alice");
+
+    let alice = generate_keycloak_identity(&mut alice_engine, &keycloak, "alice", keycloak.state()).await.unwrap();
+    let bob = generate_keycloak_identity(&mut bob_engine, &keycloak, "bob", keycloak.state()).await.unwrap();
+    alice_engine.upload_keycloak_identity(&alice).await.unwrap();
+    bob_engine.upload_keycloak_identity(&bob).await.unwrap();
+
+    // Details edited on the server are pulled, with a refreshed access token
+    keycloak.state().data.lock().unwrap().users.get_mut("alice").unwrap().last_name = Some("Smith".to_owned());
+    keycloak.state().expire_access_tokens();
+    alice_engine.synchronize_keycloak(&alice).await.unwrap();
+    assert_eq!(alice_engine.get_all_owned_identities().await.unwrap()[0].display_name, "Alice Smith");
+    assert_eq!(alice_engine.get_keycloak_server(&alice).await.unwrap().unwrap().get_keycloak_user_id(), Some("alice"));
+
+    let results = alice_engine.search_keycloak_directory(&alice, "bo").await.unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].identity.as_deref(), Some(bob.as_slice()));
+    let contact = alice_engine.add_keycloak_contact(&alice, "bob").await.unwrap();
+    assert!(contact.is_keycloak_managed());
+    assert_eq!(contact.get_display_name(), "Bob");
+
+    keycloak.state().revoke_user("bob");
+    alice_engine.synchronize_keycloak(&alice).await.unwrap();
+    assert!(!alice_engine.get_contacts(&alice).await.unwrap()[0].is_keycloak_managed());
+
+    // The revoked identity stays, unbound from the server
+    keycloak.state().revoke_user("alice");
+    alice_engine.synchronize_keycloak(&alice).await.unwrap();
+    assert!(!alice_engine.get_all_owned_identities().await.unwrap()[0].keycloak_managed);
+    assert!(alice_engine.get_keycloak_server(&alice).await.unwrap().is_none());
+    assert!(matches!(alice_engine.synchronize_keycloak(&alice).await, Err(EngineError::NotKeycloakManaged)));
+}
+
+#[tokio::test]
+async fn restricted_profiles_are_not_transferred() {
+    let server = MockServer::start().await.unwrap();
+    let keycloak = MockKeycloakServer::start().await.unwrap();
+    keycloak.state().add_user(user("alice", "Alice"));
+    let mut engine = new_engine(&server).await;
+    let alice = generate_keycloak_identity(&mut engine, &keycloak, "alice", keycloak.state()).await.unwrap();
+
+    keycloak.state().set_transfer_restricted(true);
+    engine.synchronize_keycloak(&alice).await.unwrap();
+    assert!(matches!(engine.start_profile_transfer(&alice).await, Err(EngineError::ProfileTransferRestricted)));
+
+    // Without a valid refresh token, the user must log in again
+    engine.set_keycloak_auth_state(&alice, r#"{"access_token":"expired","refresh_token":null}"#).await.unwrap();
+    assert!(matches!(engine.synchronize_keycloak(&alice).await, Err(EngineError::KeycloakAuthenticationRequired)));
+}
@@ -0,0 +1,183 @@
+mod common;
+
+use std::time::Duration;
+
+use engine::{entities::{discussion::Ephemerality, message::MessageReference, message_mention::UserMention}, events::EngineEvent, Engine, EngineError};
+use mock_server::MockServer;
+use tokio::{sync::broadcast, time::Instant};
+
+use common::{configuration, details, engines, wait_for_event, wait_until_sent};
+
+fn mention(identity: &[u8], start: usize, end: usize) -> UserMention {
+    UserMention { mentioned_identity: identity.to_vec(), range: start..end }
+}
+
+/// Fetches the messages of an owned identity until one is received, returns its id and whether it notifies
+async fn fetch_received(engine: &mut Engine, owned_identity: &[u8], events: &mut broadcast::Receiver<EngineEvent>) -> (i64, bool) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        assert!(Instant::now() < deadline, "no message received");
+        engine.fetch_messages(owned_identity).await.unwrap();
+        while let Ok(event) = events.try_recv() {
+            if let EngineEvent::MessageReceived { message_id, notify, .. } = event {
+                return (message_id, notify);
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+/// Sends a text message and returns whether receiving it notifies the recipient
+async fn notifies(sender_engine: &Engine, sender: &[u8], recipient_engine: &mut Engine, recipient: &[u8], body: &str, mentions: &[UserMention]) -> bool {
+    let mut sender_events = sender_engine.subscribe_to_events();
+    let mut recipient_events = recipient_engine.subscribe_to_events();
+    sender_engine.send_text_message(sender, recipient, body, None, mentions).await.unwrap();
+    wait_until_sent(&mut sender_events).await;
+    fetch_received(recipient_engine, recipient, &mut recipient_events).await.1
+}
+
+#[tokio::test]
+async fn mentions_and_replies_are_resolved() {
+    let server = MockServer::start().await.unwrap();
+    let directory = tempfile::tempdir().unwrap();
+    let mut alice_engine = Engine::init_with_configuration(configuration(&server, directory.path())).await.unwrap();
+    let alice = alice_engine.generate_simple_identity(details("Alice")).await.unwrap().identity.get_identity();
+    let mut alice_events = alice_engine.subscribe_to_events();
+
+    for mut bob_engine in engines(&server, directory.path()).await {
+        let bob = bob_engine.generate_simple_identity(details("Bob")).await.unwrap().identity.get_identity();
+        let carol = vec![7; 32];
+        alice_engine.add_contact(&alice, &bob, &details("Bob")).await.unwrap();
+        bob_engine.add_contact(&bob, &alice, &details("Alice")).await.unwrap();
+        let mut bob_events = bob_engine.subscribe_to_events();
+
+        let body = "@Bob, @Alice and @Carol are here";
+        assert!(matches!(alice_engine.send_text_message(&alice, &bob, body, None, &[mention(&bob, 0, 40)]).await, Err(EngineError::InvalidMention)));
+        let sent_message = alice_engine.send_text_message(&alice, &bob, body, None, &[mention(&alice, 6, 12), mention(&bob, 0, 4), mention(&carol, 17, 23)]).await.unwrap();
+        assert!(!sent_message.is_mentioned());
+        assert_eq!(alice_engine.get_message_mentions(sent_message.get_id().unwrap()).await.unwrap().len(), 3);
+        wait_until_sent(&mut alice_events).await;
+
+        let (message_id, notify) = fetch_received(&mut bob_engine, &bob, &mut bob_events).await;
+        assert!(notify);
+        assert!(bob_engine.get_message(message_id).await.unwrap().unwrap().is_mentioned());
+        let mentions = bob_engine.get_message_mentions(message_id).await.unwrap();
+        let resolved: Vec<_> = mentions.iter()
+            .map(|resolved| (resolved.mention.get_range(), resolved.mention.get_mentioned_identity().to_vec(), resolved.contact.as_ref().map(|contact| contact.get_display_name().to_owned())))
+            .collect();
+        assert_eq!(resolved, [(0..4, bob.clone(), None), (6..12, alice.clone(), Some("Alice".to_owned())), (17..23, carol, None)]);
+        let mentioning: Vec<_> = bob_engine.get_mentioning_messages(&bob, None, 10).await.unwrap().iter().map(|message| message.get_id().unwrap()).collect();
+        assert_eq!(mentioning, [message_id]);
+
+        // A reply to a message of Alice mentions her
+        let reply = bob_engine.send_text_message(&bob, &alice, "Hi", Some(message_id), &[]).await.unwrap();
+        wait_until_sent(&mut bob_events).await;
+        let (reply_id, _) = fetch_received(&mut alice_engine, &alice, &mut alice_events).await;
+        let received_reply = alice_engine.get_message(reply_id).await.unwrap().unwrap();
+        assert_eq!(received_reply.get_reply_to_message_id(), sent_message.get_id());
+        assert_eq!(received_reply.get_reply_to().unwrap(), Some(sent_message.get_reference().unwrap()));
+        assert_eq!(reply.get_reply_to().unwrap(), received_reply.get_reply_to().unwrap());
+        assert!(received_reply.is_mentioned());
+        assert!(alice_engine.get_mentioning_messages(&alice, None, 10).await.unwrap().iter().any(|message| message.get_id() == Some(reply_id)));
+        assert!(alice_engine.get_mentioning_messages(&alice, Some(reply_id), 10).await.unwrap().iter().all(|message| message.get_id() != Some(reply_id)));
+    }
+}
+
+#[tokio::test]
+async fn replies_received_before_their_message_are_linked_to_it() {
+    let server = MockServer::start().await.unwrap();
+    let directory = tempfile::tempdir().unwrap();
+    let mut alice_engine = Engine::init_with_configuration(configuration(&server, directory.path())).await.unwrap();
+    let alice = alice_engine.generate_simple_identity(details("Alice")).await.unwrap().identity.get_identity();
+    let mut alice_events = alice_engine.subscribe_to_events();
+
+    for mut bob_engine in engines(&server, directory.path()).await {
+        let bob = bob_engine.generate_simple_identity(details("Bob")).await.unwrap().identity.get_identity();
+        alice_engine.add_contact(&alice, &bob, &details("Bob")).await.unwrap();
+        bob_engine.add_contact(&bob, &alice, &details("Alice")).await.unwrap();
+        let mut bob_events = bob_engine.subscribe_to_events();
+
+        // The server holds the first message back until the reply was delivered
+        let question = alice_engine.send_text_message(&alice, &bob, "Lunch?", None, &[]).await.unwrap();
+        wait_until_sent(&mut alice_events).await;
+        let held_message = server.state().data.lock().unwrap().messages.pop().unwrap();
+        alice_engine.send_text_message(&alice, &bob, "At noon", question.get_id(), &[]).await.unwrap();
+        wait_until_sent(&mut alice_events).await;
+
+        let (reply_id, _) = fetch_received(&mut bob_engine, &bob, &mut bob_events).await;
+        let reply = bob_engine.get_message(reply_id).await.unwrap().unwrap();
+        assert_eq!(reply.get_reply_to_message_id(), None);
+        let reference = MessageReference { sender_identity: alice.clone(), sender_thread_identifier: question.get_sender_thread_identifier().unwrap(), sender_sequence_number: question.get_sender_sequence_number() };
+        assert_eq!(reply.get_reply_to().unwrap(), Some(reference));
+        assert!(!reply.is_mentioned());
+
+        server.state().data.lock().unwrap().messages.push(held_message);
+        let (question_id, _) = fetch_received(&mut bob_engine, &bob, &mut bob_events).await;
+        assert_eq!(bob_engine.get_message(reply_id).await.unwrap().unwrap().get_reply_to_message_id(), Some(question_id));
+    }
+}
+
+#[tokio::test]
+async fn muted_notifications_let_mentions_through() {
+    let server = MockServer::start().await.unwrap();
+    let directory = tempfile::tempdir().unwrap();
+    let mut alice_engine = Engine::init_with_configuration(configuration(&server, directory.path())).await.unwrap();
+    let mut bob_engine = Engine::init_with_configuration(configuration(&server, directory.path())).await.unwrap();
+    let alice = alice_engine.generate_simple_identity(details("Alice")).await.unwrap().identity.get_identity();
+    let bob = bob_engine.generate_simple_identity(details("Bob")).await.unwrap().identity.get_identity();
+    alice_engine.add_contact(&alice, &bob, &details("Bob")).await.unwrap();
+    bob_engine.add_contact(&bob, &alice, &details("Alice")).await.unwrap();
+
+    assert!(notifies(&alice_engine, &alice, &mut bob_engine, &bob, "Hello", &[]).await);
+
+    bob_engine.mute_notifications(&bob, None, true).await.unwrap();
+    assert!(!notifies(&alice_engine, &alice, &mut bob_engine, &bob, "Hello", &[]).await);
+    assert!(notifies(&alice_engine, &alice, &mut bob_engine, &bob, "Hello Bob", &[mention(&bob, 6, 9)]).await);
+    assert!(!notifies(&alice_engine, &alice, &mut bob_engine, &bob, "Hello Alice", &[mention(&alice, 6, 11)]).await);
+
+    bob_engine.mute_notifications(&bob, None, false).await.unwrap();
+    assert!(!notifies(&alice_engine, &alice, &mut bob_engine, &bob, "Hello Bob", &[mention(&bob, 6, 9)]).await);
+
+    // Muting ends by itself
+    bob_engine.mute_notifications(&bob, Some(1), false).await.unwrap();
+    assert!(notifies(&alice_engine, &alice, &mut bob_engine, &bob, "Hello", &[]).await);
+
+    bob_engine.mute_notifications(&bob, None, false).await.unwrap();
+    bob_engine.unmute_notifications(&bob).await.unwrap();
+    assert!(notifies(&alice_engine, &alice, &mut bob_engine, &bob, "Hello", &[]).await);
+}
+
+#[tokio::test]
+async fn wiped_messages_no_longer_mention() {
+    let server = MockServer::start().await.unwrap();
+    let directory = tempfile::tempdir().unwrap();
+    let mut alice_engine = Engine::init_with_configuration(configuration(&server, directory.path())).await.unwrap();
+    let alice = alice_engine.generate_simple_identity(details("Alice")).await.unwrap().identity.get_identity();
+    let mut alice_events = alice_engine.subscribe_to_events();
+
+    for mut bob_engine in engines(&server, directory.path()).await {
+        let bob = bob_engine.generate_simple_identity(details("Bob")).await.unwrap().identity.get_identity();
+        alice_engine.add_contact(&alice, &bob, &details("Bob")).await.unwrap();
+        bob_engine.add_contact(&bob, &alice, &details("Alice")).await.unwrap();
+        let mut bob_events = bob_engine.subscribe_to_events();
+
+        alice_engine.send_text_message(&alice, &bob, "Hello", None, &[]).await.unwrap();
+        wait_until_sent(&mut alice_events).await;
+        fetch_received(&mut bob_engine, &bob, &mut bob_events).await;
+        let discussion_id = alice_engine.get_discussions(&alice).await.unwrap().iter()
+            .find(|discussion| discussion.get_contact_identity() == Some(bob.as_slice())).unwrap().get_id().unwrap();
+        alice_engine.set_discussion_ephemerality(discussion_id, Ephemerality { read_once: true, ..Default::default() }).await.unwrap();
+        wait_until_sent(&mut alice_events).await;
+        alice_engine.send_text_message(&alice, &bob, "Hello Bob", None, &[mention(&bob, 6, 9)]).await.unwrap();
+        wait_until_sent(&mut alice_events).await;
+
+        let (message_id, _) = fetch_received(&mut bob_engine, &bob, &mut bob_events).await;
+        assert!(bob_engine.get_message(message_id).await.unwrap().unwrap().is_mentioned());
+        bob_engine.mark_message_read(message_id).await.unwrap();
+        wait_for_event(&mut bob_events, |event| *event == EngineEvent::MessageWiped { message_id }).await;
+
+        assert!(!bob_engine.get_message(message_id).await.unwrap().unwrap().is_mentioned());
+        assert!(bob_engine.get_message_mentions(message_id).await.unwrap().is_empty());
+        assert!(bob_engine.get_mentioning_messages(&bob, None, 10).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,232 @@
+mod common;
+
+use std::time::Duration;
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use engine::{entities::message_reaction::AggregatedReaction, events::EngineEvent, search::MessageSearchQuery, server::OutboundServerMessage, Engine, EngineError};
+use mock_server::MockServer;
+use olvid_core::{crypto::{auth_encryption::{AuthEnc, AES256CTRHMACSHA256}, prng::{PRNGHmacSHA256, PRNG}}, cryptographic_identity::{CryptographicIdentity, OwnedCryptographicIdentity}, encoding::Encoder, AES256CTRHMACSHA256Key, SymmetricKey};
+use tokio::time::Instant;
+
+use common::{configuration, details, engines, fetch_until, wait_until_sent};
+
+/// Fetches the messages of an owned identity until every member of a group joined
+async fn fetch_until_joined(engine: &mut Engine, owned_identity: &[u8], group_id: i64) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while engine.get_group_members(group_id).await.unwrap().iter().any(|member| member.is_pending()) {
+        assert!(Instant::now() < deadline, "group members did not join");
+        engine.fetch_messages(owned_identity).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+fn message_received(event: &EngineEvent) -> bool {
+    matches!(event, EngineEvent::MessageReceived { .. })
+}
+
+/// Message for `recipient` whose channel content claims to come from `claimed_sender` but is signed by `signer`
+fn forged_message(signer: &OwnedCryptographicIdentity, claimed_sender: &[u8], recipient: &[u8], payload: &serde_json::Value) -> OutboundServerMessage {
+    let mut prng = PRNGHmacSHA256::init(&[7; 32]).unwrap();
+    let payload = serde_json::to_vec(payload).unwrap();
+    let statement = vec![recipient.to_vec().encode().unwrap(), payload.encode().unwrap()].encode().unwrap();
+    let signature = signer.sign(b"channelMessage", &statement, &mut prng).unwrap();
+    let content = vec![claimed_sender.to_vec().encode().unwrap(), payload.encode().unwrap(), signature.encode().unwrap()].encode().unwrap();
+
+    let raw_key = prng.bytes(64).unwrap();
+    let key = AES256CTRHMACSHA256Key::init(&raw_key).unwrap();
+    OutboundServerMessage {
+        to_identity: recipient.to_vec(),
+        device_uids: vec![],
+        wrapped_key: CryptographicIdentity::from_raw(recipient).unwrap().encrypt(&raw_key, &mut prng).unwrap(),
+        encrypted_content: AES256CTRHMACSHA256::encrypt(&content, &key, &mut prng).unwrap(),
+        is_application_message: true,
+        attachment_chunk_counts: vec![],
+    }
+}
+
+fn reaction(emoji: &str, reactor_identities: &[&[u8]]) -> AggregatedReaction {
+    AggregatedReaction { reaction: emoji.to_owned(), reactor_identities: reactor_identities.iter().map(|identity| identity.to_vec()).collect() }
+}
+
+#[tokio::test]
+async fn edits_deletions_and_reactions_reach_the_contact() {
+    let server = MockServer::start().await.unwrap();
+    let directory = tempfile::tempdir().unwrap();
+    let mut alice_engine = Engine::init_with_configuration(configuration(&server, directory.path())).await.unwrap();
+    let alice = alice_engine.generate_simple_identity(details("Alice")).await.unwrap().identity.get_identity();
+    let mut alice_events = alice_engine.subscribe_to_events();
+
+    for mut bob_engine in engines(&server, directory.path()).await {
+        let bob = bob_engine.generate_simple_identity(details("Bob")).await.unwrap().identity.get_identity();
+        alice_engine.add_contact(&alice, &bob, &details("Bob")).await.unwrap();
+        bob_engine.add_contact(&bob, &alice, &details("Alice")).await.unwrap();
+        let mut bob_events = bob_engine.subscribe_to_events();
+
+        let sent_message = alice_engine.send_text_message(&alice, &bob, "See you at the beach", None, &[]).await.unwrap();
+        let sent_message_id = sent_message.get_id().unwrap();
+        wait_until_sent(&mut alice_events).await;
+        let EngineEvent::MessageReceived { message_id, .. } = fetch_until(&mut bob_engine, &bob, &mut bob_events, message_received).await else { unreachable!() };
+
+        // Edits
+        assert!(matches!(bob_engine.edit_message(message_id, "See you at the pool").await, Err(EngineError::NotMessageSender)));
+        let edited_message = alice_engine.edit_message(sent_message_id, "See you at the lake").await.unwrap();
+        assert_eq!(edited_message.get_body(), Some("See you at the lake"));
+        assert!(edited_message.is_edited());
+        wait_until_sent(&mut alice_events).await;
+        fetch_until(&mut bob_engine, &bob, &mut bob_events, |event| *event == EngineEvent::MessageEdited { message_id }).await;
+        let message = bob_engine.get_message(message_id).await.unwrap().unwrap();
+        assert_eq!(message.get_body(), Some("See you at the lake"));
+        assert!(message.is_edited());
+        let edits = bob_engine.get_message_edits(message_id).await.unwrap();
+        assert_eq!(edits.iter().map(|edit| edit.get_body()).collect::<Vec<_>>(), ["See you at the beach"]);
+        assert_eq!(alice_engine.get_message_edits(sent_message_id).await.unwrap().len(), 1);
+        let search = |text: &str| MessageSearchQuery::builder().text(text.to_owned()).build();
+        assert!(bob_engine.search_messages(&bob, &search("beach"), None, 10).await.unwrap().is_empty());
+        assert_eq!(bob_engine.search_messages(&bob, &search("lake"), None, 10).await.unwrap().len(), 1);
+
+        // Reactions
+        bob_engine.react_to_message(message_id, Some("👍")).await.unwrap();
+        wait_until_sent(&mut bob_events).await;
+        alice_engine.react_to_message(sent_message_id, Some("👍")).await.unwrap();
+        wait_until_sent(&mut alice_events).await;
+        alice_engine.fetch_messages(&alice).await.unwrap();
+        fetch_until(&mut bob_engine, &bob, &mut bob_events, |event| *event == EngineEvent::MessageReactionsChanged { message_id }).await;
+        for (engine, id) in [(&alice_engine, sent_message_id), (&bob_engine, message_id)] {
+            assert_eq!(engine.get_message_reactions(id).await.unwrap(), [reaction("👍", &[&bob, &alice])]);
+        }
+        alice_engine.react_to_message(sent_message_id, Some("😂")).await.unwrap();
+        wait_until_sent(&mut alice_events).await;
+        fetch_until(&mut bob_engine, &bob, &mut bob_events, |event| *event == EngineEvent::MessageReactionsChanged { message_id }).await;
+        assert_eq!(bob_engine.get_message_reactions(message_id).await.unwrap(), [reaction("👍", &[&bob]), reaction("😂", &[&alice])]);
+        bob_engine.react_to_message(message_id, None).await.unwrap();
+        assert_eq!(bob_engine.get_message_reactions(message_id).await.unwrap(), [reaction("😂", &[&alice])]);
+
+        // Deletion for everyone
+        assert!(matches!(bob_engine.delete_message_for_everyone(message_id).await, Err(EngineError::NotMessageSender)));
+        alice_engine.delete_message_for_everyone(sent_message_id).await.unwrap();
+        wait_until_sent(&mut alice_events).await;
+        fetch_until(&mut bob_engine, &bob, &mut bob_events, |event| *event == EngineEvent::MessageRemotelyDeleted { message_id }).await;
+        for (engine, id) in [(&alice_engine, sent_message_id), (&bob_engine, message_id)] {
+            let message = engine.get_message(id).await.unwrap().unwrap();
+            assert!(message.is_remotely_deleted());
+            assert_eq!(message.get_body(), None);
+            assert!(engine.get_message_edits(id).await.unwrap().is_empty());
+            assert!(engine.get_message_reactions(id).await.unwrap().is_empty());
+            assert!(matches!(engine.react_to_message(id, Some("👍")).await, Err(EngineError::UnknownMessage)));
+        }
+        assert!(bob_engine.search_messages(&bob, &search("lake"), None, 10).await.unwrap().is_empty());
+    }
+}
+
+#[tokio::test]
+async fn operations_forged_by_a_third_party_are_rejected() {
+    let server = MockServer::start().await.unwrap();
+    let directory = tempfile::tempdir().unwrap();
+    let mut alice_engine = Engine::init_with_configuration(configuration(&server, directory.path())).await.unwrap();
+    let mut bob_engine = Engine::init_with_configuration(configuration(&server, directory.path())).await.unwrap();
+    let mut carol_engine = Engine::init_with_configuration(configuration(&server, directory.path())).await.unwrap();
+    let alice = alice_engine.generate_simple_identity(details("Alice")).await.unwrap().identity.get_identity();
+    let bob = bob_engine.generate_simple_identity(details("Bob")).await.unwrap().identity.get_identity();
+    let carol = carol_engine.generate_simple_identity(details("Carol")).await.unwrap().identity.get_identity();
+    alice_engine.add_contact(&alice, &bob, &details("Bob")).await.unwrap();
+    bob_engine.add_contact(&bob, &alice, &details("Alice")).await.unwrap();
+    let mut alice_events = alice_engine.subscribe_to_events();
+    let mut bob_events = bob_engine.subscribe_to_events();
+    let mut carol_events = carol_engine.subscribe_to_events();
+
+    let sent_message = alice_engine.send_text_message(&alice, &bob, "See you at the beach", None, &[]).await.unwrap();
+    wait_until_sent(&mut alice_events).await;
+    let EngineEvent::MessageReceived { message_id, .. } = fetch_until(&mut bob_engine, &bob, &mut bob_events, message_received).await else { unreachable!() };
+
+    // Carol does not have the key of Alice, she signs the edit and the deletion with another one
+    let reference = serde_json::json!({
+        "ssn": sent_message.get_sender_sequence_number(),
+        "sti": sent_message.get_sender_thread_identifier().unwrap().to_string(),
+        "si": STANDARD.encode(&alice),
+    });
+    let payload = serde_json::json!({ "upm": { "body": "See you never", "ref": reference }, "delm": { "refs": [reference] } });
+    let mut prng = PRNGHmacSHA256::init(&[3; 32]).unwrap();
+    let carol_key = OwnedCryptographicIdentity::generate_owned_cryptographic_identity(&server.url(), &mut prng).unwrap();
+    carol_engine.queue_message(&carol, &forged_message(&carol_key, &alice, &bob, &payload)).await.unwrap();
+    wait_until_sent(&mut carol_events).await;
+
+    assert_eq!(bob_engine.fetch_messages(&bob).await.unwrap(), 1);
+    assert!(bob_engine.get_pending_inbox_messages(&bob).await.unwrap().is_empty());
+    let message = bob_engine.get_message(message_id).await.unwrap().unwrap();
+    assert_eq!(message.get_body(), Some("See you at the beach"));
+    assert!(!message.is_edited());
+    assert!(!message.is_remotely_deleted());
+}
+
+#[tokio::test]
+async fn operations_received_before_their_message_are_applied_when_it_arrives() {
+    let server = MockServer::start().await.unwrap();
+    let directory = tempfile::tempdir().unwrap();
+    let mut alice_engine = Engine::init_with_configuration(configuration(&server, directory.path())).await.unwrap();
+    let alice = alice_engine.generate_simple_identity(details("Alice")).await.unwrap().identity.get_identity();
+    let mut alice_events = alice_engine.subscribe_to_events();
+
+    for mut bob_engine in engines(&server, directory.path()).await {
+        let bob = bob_engine.generate_simple_identity(details("Bob")).await.unwrap().identity.get_identity();
+        alice_engine.add_contact(&alice, &bob, &details("Bob")).await.unwrap();
+        bob_engine.add_contact(&bob, &alice, &details("Alice")).await.unwrap();
+        let mut bob_events = bob_engine.subscribe_to_events();
+
+        // The server holds the message back until the operations on it were delivered
+        let sent_message_id = alice_engine.send_text_message(&alice, &bob, "Lunch at noon", None, &[]).await.unwrap().get_id().unwrap();
+        wait_until_sent(&mut alice_events).await;
+        let held_message = server.state().data.lock().unwrap().messages.pop().unwrap();
+        alice_engine.edit_message(sent_message_id, "Lunch at one").await.unwrap();
+        wait_until_sent(&mut alice_events).await;
+        alice_engine.edit_message(sent_message_id, "Lunch at two").await.unwrap();
+        wait_until_sent(&mut alice_events).await;
+        alice_engine.react_to_message(sent_message_id, Some("🍕")).await.unwrap();
+        wait_until_sent(&mut alice_events).await;
+        bob_engine.fetch_messages(&bob).await.unwrap();
+        while let Ok(event) = bob_events.try_recv() {
+            assert!(!message_received(&event));
+        }
+
+        server.state().data.lock().unwrap().messages.push(held_message);
+        let EngineEvent::MessageReceived { message_id, .. } = fetch_until(&mut bob_engine, &bob, &mut bob_events, message_received).await else { unreachable!() };
+        let message = bob_engine.get_message(message_id).await.unwrap().unwrap();
+        assert_eq!(message.get_body(), Some("Lunch at two"));
+        assert!(message.is_edited());
+        let edits = bob_engine.get_message_edits(message_id).await.unwrap();
+        assert_eq!(edits.iter().map(|edit| edit.get_body()).collect::<Vec<_>>(), ["Lunch at noon", "Lunch at one"]);
+        assert_eq!(bob_engine.get_message_reactions(message_id).await.unwrap(), [reaction("🍕", &[&alice])]);
+    }
+}
+
+#[tokio::test]
+async fn group_administrators_delete_messages_of_other_members() {
+    let server = MockServer::start().await.unwrap();
+    let directory = tempfile::tempdir().unwrap();
+    let mut alice_engine = Engine::init_with_configuration(configuration(&server, directory.path())).await.unwrap();
+    let mut bob_engine = Engine::init_with_configuration(configuration(&server, directory.path())).await.unwrap();
+    let alice = alice_engine.generate_simple_identity(details("Alice")).await.unwrap().identity.get_identity();
+    let bob = bob_engine.generate_simple_identity(details("Bob")).await.unwrap().identity.get_identity();
+    alice_engine.add_contact(&alice, &bob, &details("Bob")).await.unwrap();
+    bob_engine.add_contact(&bob, &alice, &details("Alice")).await.unwrap();
+    let mut alice_events = alice_engine.subscribe_to_events();
+    let mut bob_events = bob_engine.subscribe_to_events();
+
+    let alice_group_id = alice_engine.create_group(&alice, "Friends", std::slice::from_ref(&bob)).await.unwrap().get_id().unwrap();
+    let EngineEvent::GroupUpdated { group_id: bob_group_id, .. } = fetch_until(&mut bob_engine, &bob, &mut bob_events, |event| matches!(event, EngineEvent::GroupUpdated { .. })).await else { unreachable!() };
+    bob_engine.accept_group_invitation(bob_group_id).await.unwrap();
+    fetch_until_joined(&mut alice_engine, &alice, alice_group_id).await;
+    fetch_until_joined(&mut bob_engine, &bob, bob_group_id).await;
+
+    let alice_message_id = alice_engine.send_group_text_message(alice_group_id, "Welcome", None, &[]).await.unwrap().get_id().unwrap();
+    let EngineEvent::MessageReceived { message_id: bob_copy_id, .. } = fetch_until(&mut bob_engine, &bob, &mut bob_events, message_received).await else { unreachable!() };
+    assert!(matches!(bob_engine.delete_message_for_everyone(bob_copy_id).await, Err(EngineError::NotMessageSender)));
+    assert!(!alice_engine.get_message(alice_message_id).await.unwrap().unwrap().is_remotely_deleted());
+
+    let bob_message_id = bob_engine.send_group_text_message(bob_group_id, "Buy cheap watches", None, &[]).await.unwrap().get_id().unwrap();
+    let EngineEvent::MessageReceived { message_id: alice_copy_id, .. } = fetch_until(&mut alice_engine, &alice, &mut alice_events, message_received).await else { unreachable!() };
+    assert!(matches!(alice_engine.edit_message(alice_copy_id, "Buy nothing").await, Err(EngineError::NotMessageSender)));
+    alice_engine.delete_message_for_everyone(alice_copy_id).await.unwrap();
+    assert!(alice_engine.get_message(alice_copy_id).await.unwrap().unwrap().is_remotely_deleted());
+    fetch_until(&mut bob_engine, &bob, &mut bob_events, |event| *event == EngineEvent::MessageRemotelyDeleted { message_id: bob_message_id }).await;
+    assert_eq!(bob_engine.get_message(bob_message_id).await.unwrap().unwrap().get_body(), None);
+}
@@ -0,0 +1,89 @@
+mod common;
+
+use engine::{entities::{message::{MessageDirection, MessageStatus}, outbox_message::DeliveryState}, events::EngineEvent};
+use mock_server::MockServer;
+use tokio::sync::broadcast;
+
+use common::{alice_and_bob, details, new_engine, wait_for_event, wait_for_received};
+
+async fn wait_for_sent(events: &mut broadcast::Receiver<EngineEvent>, outbox_message_id: i64) {
+    wait_for_event(events, |event| *event == EngineEvent::MessageDeliveryStateChanged { outbox_message_id, delivery_state: DeliveryState::Sent }).await;
+}
+
+#[tokio::test]
+async fn text_message_and_reply_are_exchanged() {
+    let server = MockServer::start().await.unwrap();
+    let ((mut alice_engine, alice), (mut bob_engine, bob)) = alice_and_bob(&server).await;
+    let mut alice_events = alice_engine.subscribe_to_events();
+    let mut bob_events = bob_engine.subscribe_to_events();
+
+    let sent_message = alice_engine.send_text_message(&alice, &bob, "Hello Bob", None, &[]).await.unwrap();
+    assert_eq!(sent_message.get_status().unwrap(), MessageStatus::Queued);
+    wait_for_sent(&mut alice_events, sent_message.get_outbox_message_id().unwrap()).await;
+    assert_eq!(alice_engine.get_message(sent_message.get_id().unwrap()).await.unwrap().unwrap().get_status().unwrap(), MessageStatus::Sent);
+
+    bob_engine.fetch_messages(&bob).await.unwrap();
+    let received_message_id = wait_for_received(&mut bob_events).await;
+    let received_message = bob_engine.get_message(received_message_id).await.unwrap().unwrap();
+    assert_eq!(received_message.get_body(), Some("Hello Bob"));
+    assert_eq!(received_message.get_sender_identity(), alice);
+    assert_eq!(received_message.get_direction().unwrap(), MessageDirection::Inbound);
+    assert_eq!(received_message.get_status().unwrap(), MessageStatus::Unread);
+    assert!(received_message.get_server_timestamp().is_some());
+    assert!(bob_engine.get_pending_inbox_messages(&bob).await.unwrap().is_empty());
+
+    let bob_discussions = bob_engine.get_discussions(&bob).await.unwrap();
+    assert_eq!(bob_discussions.len(), 1);
+    assert_eq!(bob_discussions[0].get_title(), "Alice");
+    assert_eq!(bob_discussions[0].get_contact_identity(), Some(alice.as_slice()));
+
+    let reply = bob_engine.send_text_message(&bob, &alice, "Hi Alice", Some(received_message_id), &[]).await.unwrap();
+    wait_for_sent(&mut bob_events, reply.get_outbox_message_id().unwrap()).await;
+
+    alice_engine.fetch_messages(&alice).await.unwrap();
+    let received_reply = alice_engine.get_message(wait_for_received(&mut alice_events).await).await.unwrap().unwrap();
+    assert_eq!(received_reply.get_body(), Some("Hi Alice"));
+    assert_eq!(received_reply.get_reply_to_message_id(), sent_message.get_id());
+    assert_eq!(received_reply.get_discussion_id(), sent_message.get_discussion_id());
+}
+
+#[tokio::test]
+async fn discussion_history_is_paginated() {
+    let server = MockServer::start().await.unwrap();
+    let ((alice_engine, alice), (_, bob)) = alice_and_bob(&server).await;
+
+    let mut sent_message_ids = vec![];
+    for index in 0..5 {
+        let message = alice_engine.send_text_message(&alice, &bob, &format!("Message {}", index), None, &[]).await.unwrap();
+        assert_eq!(message.get_sender_sequence_number(), index + 1);
+        sent_message_ids.push(message.get_id().unwrap());
+    }
+    let discussion_id = alice_engine.get_discussions(&alice).await.unwrap()[0].get_id().unwrap();
+
+    let mut history = vec![];
+    let mut before_message_id = None;
+    loop {
+        let page = alice_engine.get_discussion_messages(discussion_id, before_message_id, 2).await.unwrap();
+        if page.is_empty() {
+            break;
+        }
+        assert!(page.len() <= 2);
+        before_message_id = page.last().unwrap().get_id();
+        history.extend(page.into_iter().map(|message| message.get_id().unwrap()));
+    }
+
+    sent_message_ids.reverse();
+    assert_eq!(history, sent_message_ids);
+}
+
+#[tokio::test]
+async fn sending_to_unknown_contact_fails() {
+    let server = MockServer::start().await.unwrap();
+    let mut alice_engine = new_engine(&server).await;
+    let mut bob_engine = new_engine(&server).await;
+
+    let alice = alice_engine.generate_simple_identity(details("Alice")).await.unwrap().identity.get_identity();
+    let bob = bob_engine.generate_simple_identity(details("Bob")).await.unwrap().identity.get_identity();
+
+    assert!(matches!(alice_engine.send_text_message(&alice, &bob, "Hello", None, &[]).await, Err(engine::EngineError::UnknownContact)));
+}
@@ -0,0 +1,236 @@
+mod common;
+
+use std::{sync::Arc, time::Duration};
+
+use engine::{entities::outbox_message::{DeliveryState, OutboxMessage}, events::EngineEvent, network::PushConnectionStatus, server::{OutboundServerMessage, ServerClient}, store::{MemoryStore, Store}, Engine};
+use mock_server::MockServer;
+use sqlx::SqlitePool;
+use tokio::{sync::{broadcast, watch}, time::Instant};
+
+use common::{configuration, details, new_engine, open_engine, wait_until_sent};
+
+#[tokio::test]
+async fn message_is_relayed_between_two_engines() {
+    let server = MockServer::start().await.unwrap();
+    let mut alice_engine = new_engine(&server).await;
+    let mut bob_engine = new_engine(&server).await;
+
+    let alice = alice_engine.generate_simple_identity(details("Alice")).await.unwrap().identity.get_identity();
+    let bob = bob_engine.generate_simple_identity(details("Bob")).await.unwrap().identity.get_identity();
+
+    let session = alice_engine.authenticate_owned_identity(&alice).await.unwrap();
+    assert!(!session.token.is_empty());
+
+    let message = OutboundServerMessage {
+        to_identity: bob.clone(),
+        device_uids: vec![],
+        wrapped_key: vec![1; 32],
+        encrypted_content: b"hello bob".to_vec(),
+        is_application_message: true,
+        attachment_chunk_counts: vec![],
+    };
+    let uploaded = alice_engine.upload_message(&message).await.unwrap();
+
+    let downloaded = bob_engine.download_messages(&bob).await.unwrap();
+    assert_eq!(downloaded.len(), 1);
+    assert_eq!(downloaded[0].message_uid, uploaded.message_uid);
+    assert_eq!(downloaded[0].encrypted_content, message.encrypted_content);
+
+    bob_engine.delete_message(&bob, &uploaded.message_uid).await.unwrap();
+    assert!(bob_engine.download_messages(&bob).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn message_uploaded_again_with_its_nonce_is_stored_once() {
+    let server = MockServer::start().await.unwrap();
+    let alice_engine = new_engine(&server).await;
+    let mut bob_engine = new_engine(&server).await;
+    let bob = bob_engine.generate_simple_identity(details("Bob")).await.unwrap().identity.get_identity();
+
+    let message = OutboundServerMessage {
+        to_identity: bob.clone(),
+        device_uids: vec![],
+        wrapped_key: vec![1; 32],
+        encrypted_content: b"uploaded twice".to_vec(),
+        is_application_message: true,
+        attachment_chunk_counts: vec![1],
+    };
+    // As after a crash between the upload and saving its answer
+    let server_client = alice_engine.get_server_client();
+    let uploaded = server_client.upload_message_and_get_uids(&message, Some(&[7; 32])).await.unwrap();
+    let uploaded_again = server_client.upload_message_and_get_uids(&message, Some(&[7; 32])).await.unwrap();
+    assert_eq!(uploaded_again.message_uid, uploaded.message_uid);
+    assert_eq!(uploaded_again.attachment_upload_urls, uploaded.attachment_upload_urls);
+
+    let other_upload = server_client.upload_message_and_get_uids(&message, Some(&[8; 32])).await.unwrap();
+    assert_ne!(other_upload.message_uid, uploaded.message_uid);
+    assert_eq!(bob_engine.download_messages(&bob).await.unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn message_uploaded_before_a_crash_is_delivered_once() {
+    let server = MockServer::start().await.unwrap();
+    let directory = tempfile::tempdir().unwrap();
+    let mut bob_engine = new_engine(&server).await;
+    let bob = bob_engine.generate_simple_identity(details("Bob")).await.unwrap().identity.get_identity();
+
+    let message = OutboundServerMessage {
+        to_identity: bob.clone(),
+        device_uids: vec![],
+        wrapped_key: vec![1; 32],
+        encrypted_content: b"uploaded before the crash".to_vec(),
+        is_application_message: true,
+        attachment_chunk_counts: vec![],
+    };
+    // The sender persisted the nonce and uploaded the message, but stopped before marking it sent
+    let store = Arc::new(MemoryStore::new());
+    let outbox_message_id = store.outbox_messages().insert(OutboxMessage::new(&[0; 32], &message).unwrap()).await.unwrap();
+    store.outbox_messages().set_upload_nonce(outbox_message_id, &[7; 32]).await.unwrap();
+    ServerClient::new(&server.url()).unwrap().upload_message_and_get_uids(&message, Some(&[7; 32])).await.unwrap();
+
+    let alice_engine = Engine::init_with_store(configuration(&server, directory.path()), store).await.unwrap();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while alice_engine.get_outbox_message(outbox_message_id).await.unwrap().unwrap().get_delivery_state().unwrap() != DeliveryState::Sent {
+        assert!(Instant::now() < deadline, "message not sent");
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    assert_eq!(bob_engine.fetch_messages(&bob).await.unwrap(), 1);
+}
+
+#[tokio::test]
+async fn queued_message_reaches_inbox_once() {
+    let server = MockServer::start().await.unwrap();
+    let mut alice_engine = new_engine(&server).await;
+    let mut bob_engine = new_engine(&server).await;
+
+    let mut events = alice_engine.subscribe_to_events();
+    let alice = alice_engine.generate_simple_identity(details("Alice")).await.unwrap().identity.get_identity();
+    let bob = bob_engine.generate_simple_identity(details("Bob")).await.unwrap().identity.get_identity();
+
+    let event = events.recv().await.unwrap();
+    assert_eq!(event, EngineEvent::OwnedIdentityCreated { owned_identity: alice.clone() });
+
+    let message = OutboundServerMessage {
+        to_identity: bob.clone(),
+        device_uids: vec![],
+        wrapped_key: vec![1; 32],
+        encrypted_content: b"queued".to_vec(),
+        is_application_message: true,
+        attachment_chunk_counts: vec![],
+    };
+    let outbox_message_id = alice_engine.queue_message(&alice, &message).await.unwrap();
+
+    let expected_states = [DeliveryState::Queued, DeliveryState::Sent];
+    for expected_state in expected_states {
+        let event = tokio::time::timeout(Duration::from_secs(10), events.recv()).await.unwrap().unwrap();
+        assert_eq!(event, EngineEvent::MessageDeliveryStateChanged { outbox_message_id, delivery_state: expected_state });
+    }
+
+    assert_eq!(bob_engine.fetch_messages(&bob).await.unwrap(), 1);
+    assert_eq!(bob_engine.fetch_messages(&bob).await.unwrap(), 0);
+
+    let pending = bob_engine.get_pending_inbox_messages(&bob).await.unwrap();
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].get_encrypted_content(), b"queued");
+
+    bob_engine.mark_inbox_message_processed(pending[0].get_id()).await.unwrap();
+    assert!(bob_engine.get_pending_inbox_messages(&bob).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn message_processed_before_a_crash_is_applied_once() {
+    let server = MockServer::start().await.unwrap();
+    let directory = tempfile::tempdir().unwrap();
+    let database_path = directory.path().join("bob.db");
+    let database_url = format!("sqlite://{}", database_path.display());
+    let mut alice_engine = new_engine(&server).await;
+    let mut bob_engine = open_engine(&server, &database_url, None).await.unwrap();
+    let alice = alice_engine.generate_simple_identity(details("Alice")).await.unwrap().identity.get_identity();
+    let bob = bob_engine.generate_simple_identity(details("Bob")).await.unwrap().identity.get_identity();
+    alice_engine.add_contact(&alice, &bob, &details("Bob")).await.unwrap();
+    bob_engine.add_contact(&bob, &alice, &details("Alice")).await.unwrap();
+
+    let mut events = alice_engine.subscribe_to_events();
+    alice_engine.send_text_message(&alice, &bob, "Only once", None, &[]).await.unwrap();
+    wait_until_sent(&mut events).await;
+    let server_message = server.state().data.lock().unwrap().messages[0].clone();
+
+    // Bob stops after processing the message, before marking it processed
+    let db = SqlitePool::connect(&database_url).await.unwrap();
+    sqlx::query("CREATE TRIGGER crash BEFORE UPDATE ON inbox_messages BEGIN SELECT RAISE(ABORT, 'crash'); END").execute(&db).await.unwrap();
+    assert!(bob_engine.fetch_messages(&bob).await.is_err());
+    assert_eq!(received_messages(&bob_engine, &bob).await, 0);
+    assert_eq!(bob_engine.get_pending_inbox_messages(&bob).await.unwrap().len(), 1);
+
+    // The message is processed again from the start, once it is downloaded again
+    sqlx::query("DROP TRIGGER crash").execute(&db).await.unwrap();
+    db.close().await;
+    server.state().data.lock().unwrap().messages.push(server_message);
+    bob_engine.fetch_messages(&bob).await.unwrap();
+    assert_eq!(received_messages(&bob_engine, &bob).await, 1);
+    assert!(bob_engine.get_pending_inbox_messages(&bob).await.unwrap().is_empty());
+}
+
+async fn received_messages(engine: &Engine, owned_identity: &[u8]) -> usize {
+    let mut count = 0;
+    for discussion in engine.get_discussions(owned_identity).await.unwrap() {
+        count += engine.get_discussion_messages(discussion.get_id().unwrap(), None, 10).await.unwrap().len();
+    }
+
+    count
+}
+
+async fn wait_for_status(status: &mut watch::Receiver<PushConnectionStatus>, expected_status: PushConnectionStatus) {
+    tokio::time::timeout(Duration::from_secs(10), status.wait_for(|status| *status == expected_status)).await.unwrap().unwrap();
+}
+
+async fn wait_for_stored_message(events: &mut broadcast::Receiver<EngineEvent>) -> Vec<u8> {
+    loop {
+        let event = tokio::time::timeout(Duration::from_secs(10), events.recv()).await.unwrap().unwrap();
+        if let EngineEvent::InboxMessageStored { message_uid, .. } = event {
+            return message_uid;
+        }
+    }
+}
+
+#[tokio::test]
+async fn push_notification_triggers_download_and_survives_disconnection() {
+    let server = MockServer::start().await.unwrap();
+    let mut alice_engine = new_engine(&server).await;
+    let mut bob_engine = new_engine(&server).await;
+
+    let alice = alice_engine.generate_simple_identity(details("Alice")).await.unwrap().identity.get_identity();
+    let bob = bob_engine.generate_simple_identity(details("Bob")).await.unwrap().identity.get_identity();
+    let mut bob_events = bob_engine.subscribe_to_events();
+
+    let mut status = bob_engine.start_push_listener(&bob).await.unwrap();
+    wait_for_status(&mut status, PushConnectionStatus::Connected).await;
+    let connected_event = EngineEvent::NetworkStatusChanged { owned_identity: bob.clone(), status: PushConnectionStatus::Connected };
+    loop {
+        if tokio::time::timeout(Duration::from_secs(10), bob_events.recv()).await.unwrap().unwrap() == connected_event {
+            break;
+        }
+    }
+
+    let message = OutboundServerMessage {
+        to_identity: bob.clone(),
+        device_uids: vec![],
+        wrapped_key: vec![1; 32],
+        encrypted_content: b"pushed".to_vec(),
+        is_application_message: true,
+        attachment_chunk_counts: vec![],
+    };
+    alice_engine.queue_message(&alice, &message).await.unwrap();
+    wait_for_stored_message(&mut bob_events).await;
+
+    server.state().disconnect_push_clients();
+    wait_for_status(&mut status, PushConnectionStatus::Disconnected).await;
+    wait_for_status(&mut status, PushConnectionStatus::Connected).await;
+
+    alice_engine.queue_message(&alice, &message).await.unwrap();
+    wait_for_stored_message(&mut bob_events).await;
+
+    assert_eq!(bob_engine.get_pending_inbox_messages(&bob).await.unwrap().len(), 2);
+    assert_eq!(bob_engine.get_push_connection_status(&bob), PushConnectionStatus::Connected);
+}
@@ -0,0 +1,68 @@
+mod common;
+
+use engine::{profile_transfers::ProfileTransferStatus, EngineError};
+use mock_server::MockServer;
+
+use common::{details, exchange_seeds, new_engine};
+
+#[tokio::test]
+async fn profile_is_transferred_to_a_new_device() {
+    let server = MockServer::start().await.unwrap();
+    let mut source = new_engine(&server).await;
+    let mut contacts_engine = new_engine(&server).await;
+    let alice = source.generate_simple_identity(details("Alice")).await.unwrap().identity.get_identity();
+    let bob = contacts_engine.generate_simple_identity(details("Bob")).await.unwrap().identity.get_identity();
+    source.add_contact(&alice, &bob, &details("Bob")).await.unwrap();
+    source.create_group(&alice, "Friends", std::slice::from_ref(&bob)).await.unwrap();
+
+    let mut target = new_engine(&server).await;
+    let session_number = source.start_profile_transfer(&alice).await.unwrap();
+    assert_eq!(source.poll_profile_transfer(session_number).await.unwrap(), ProfileTransferStatus::WaitingForTarget);
+    target.join_profile_transfer(session_number).await.unwrap();
+
+    let sas = exchange_seeds(&mut source, &mut target, session_number).await;
+    assert_eq!(target.confirm_profile_transfer(session_number, &sas).await.unwrap(), ProfileTransferStatus::WaitingForOtherDevice);
+    assert_eq!(source.confirm_profile_transfer(session_number, &sas).await.unwrap(), ProfileTransferStatus::WaitingForOtherDevice);
+
+    let finished = ProfileTransferStatus::Finished { owned_identity: alice.clone() };
+    assert_eq!(target.poll_profile_transfer(session_number).await.unwrap(), finished);
+    assert_eq!(source.poll_profile_transfer(session_number).await.unwrap(), finished);
+    assert!(matches!(source.poll_profile_transfer(session_number).await, Err(EngineError::UnknownProfileTransfer)));
+
+    let owned_identities = target.get_all_owned_identities().await.unwrap();
+    assert_eq!(owned_identities.len(), 1);
+    assert_eq!(owned_identities[0].identity.get_identity(), alice);
+    assert_eq!(target.get_contacts(&alice).await.unwrap().len(), 1);
+    let groups = target.get_groups(&alice).await.unwrap();
+    assert_eq!(groups.len(), 1);
+    assert_eq!(groups[0].get_name(), "Friends");
+
+    // Each device knows the other one
+    let source_devices = source.get_owned_devices(&alice).await.unwrap();
+    let target_devices = target.get_owned_devices(&alice).await.unwrap();
+    assert_eq!(source_devices.len(), 1);
+    assert_eq!(target_devices.len(), 1);
+    assert_ne!(source_devices[0].get_device_uid(), target_devices[0].get_device_uid());
+
+    target.fetch_messages(&alice).await.unwrap();
+}
+
+#[tokio::test]
+async fn wrong_code_is_rejected() {
+    let server = MockServer::start().await.unwrap();
+    let mut source = new_engine(&server).await;
+    let mut target = new_engine(&server).await;
+    let alice = source.generate_simple_identity(details("Alice")).await.unwrap().identity.get_identity();
+
+    let session_number = source.start_profile_transfer(&alice).await.unwrap();
+    assert!(matches!(source.confirm_profile_transfer(session_number, "0000").await, Err(EngineError::UnexpectedProfileTransferStep)));
+    target.join_profile_transfer(session_number).await.unwrap();
+    let sas = exchange_seeds(&mut source, &mut target, session_number).await;
+
+    let wrong_sas = if sas == "0000" { "1111" } else { "0000" };
+    assert!(matches!(source.confirm_profile_transfer(session_number, wrong_sas).await, Err(EngineError::InvalidSasCode)));
+    assert!(target.get_all_owned_identities().await.unwrap().is_empty());
+
+    source.cancel_profile_transfer(session_number).await.unwrap();
+    assert!(matches!(source.poll_profile_transfer(session_number).await, Err(EngineError::UnknownProfileTransfer)));
+}
@@ -0,0 +1,110 @@
+mod common;
+
+use std::time::Duration;
+
+use engine::{events::EngineEvent, network::PushConnectionStatus, Engine, EngineError};
+use mock_server::MockServer;
+
+use common::{details, new_engine};
+
+async fn listed_identities(engine: &Engine) -> Vec<Vec<u8>> {
+    engine.get_all_owned_identities().await.unwrap().iter().map(|owned_identity| owned_identity.identity.get_identity()).collect()
+}
+
+async fn current_identity(engine: &Engine) -> Option<Vec<u8>> {
+    engine.get_current_owned_identity().await.unwrap().map(|owned_identity| owned_identity.identity.get_identity())
+}
+
+#[tokio::test]
+async fn selected_profile_is_the_current_one() {
+    let server = MockServer::start().await.unwrap();
+    let mut engine = new_engine(&server).await;
+    assert_eq!(current_identity(&engine).await, None);
+
+    let alice = engine.generate_simple_identity(details("Alice")).await.unwrap().identity.get_identity();
+    tokio::time::sleep(Duration::from_millis(5)).await;
+    let work = engine.generate_simple_identity(details("Alice at work")).await.unwrap().identity.get_identity();
+    assert_eq!(current_identity(&engine).await, Some(work.clone()));
+
+    tokio::time::sleep(Duration::from_millis(5)).await;
+    engine.select_owned_identity(&alice).await.unwrap();
+    assert_eq!(current_identity(&engine).await, Some(alice));
+}
+
+#[tokio::test]
+async fn hidden_profiles_are_listed_once_unlocked() {
+    let server = MockServer::start().await.unwrap();
+    let mut engine = new_engine(&server).await;
+    let alice = engine.generate_simple_identity(details("Alice")).await.unwrap().identity.get_identity();
+    tokio::time::sleep(Duration::from_millis(5)).await;
+    let secret = engine.generate_simple_identity(details("Secret Alice")).await.unwrap().identity.get_identity();
+
+    assert!(matches!(engine.hide_owned_identity(&secret, "").await, Err(EngineError::InvalidUnlockPassword)));
+    engine.hide_owned_identity(&secret, "1234").await.unwrap();
+    assert!(matches!(engine.hide_owned_identity(&alice, "5678").await, Err(EngineError::LastVisibleProfile)));
+
+    // Still listed until locked
+    assert_eq!(listed_identities(&engine).await.len(), 2);
+    engine.lock_hidden_profiles();
+    assert_eq!(listed_identities(&engine).await, std::slice::from_ref(&alice));
+    assert_eq!(current_identity(&engine).await, Some(alice.clone()));
+    assert!(matches!(engine.select_owned_identity(&secret).await, Err(EngineError::UnknownOwnedIdentity)));
+
+    assert!(matches!(engine.unlock_hidden_profiles("0000").await, Err(EngineError::InvalidUnlockPassword)));
+    let unlocked = engine.unlock_hidden_profiles("1234").await.unwrap();
+    assert_eq!(unlocked.len(), 1);
+    assert!(unlocked[0].hidden);
+    assert_eq!(listed_identities(&engine).await.len(), 2);
+    engine.select_owned_identity(&secret).await.unwrap();
+    assert_eq!(current_identity(&engine).await, Some(secret.clone()));
+
+    engine.unhide_owned_identity(&secret).await.unwrap();
+    engine.lock_hidden_profiles();
+    assert_eq!(listed_identities(&engine).await.len(), 2);
+}
+
+#[tokio::test]
+async fn deactivated_profiles_do_not_fetch_messages() {
+    let server = MockServer::start().await.unwrap();
+    let mut engine = new_engine(&server).await;
+    let alice = engine.generate_simple_identity(details("Alice")).await.unwrap().identity.get_identity();
+
+    engine.set_owned_identity_active(&alice, false).await.unwrap();
+    assert!(matches!(engine.fetch_messages(&alice).await, Err(EngineError::ProfileDeactivated)));
+    assert!(matches!(engine.start_push_listener(&alice).await, Err(EngineError::ProfileDeactivated)));
+
+    engine.set_owned_identity_active(&alice, true).await.unwrap();
+    // Listening again
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while engine.get_push_connection_status(&alice) != PushConnectionStatus::Connected {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }).await.unwrap();
+    engine.fetch_messages(&alice).await.unwrap();
+}
+
+#[tokio::test]
+async fn deleted_profile_loses_its_contacts_and_messages() {
+    let server = MockServer::start().await.unwrap();
+    let mut engine = new_engine(&server).await;
+    let mut bob_engine = new_engine(&server).await;
+    let alice = engine.generate_simple_identity(details("Alice")).await.unwrap().identity.get_identity();
+    let other = engine.generate_simple_identity(details("Other")).await.unwrap().identity.get_identity();
+    let bob = bob_engine.generate_simple_identity(details("Bob")).await.unwrap().identity.get_identity();
+    engine.add_contact(&alice, &bob, &details("Bob")).await.unwrap();
+    engine.add_contact(&other, &bob, &details("Bob")).await.unwrap();
+    let message = engine.send_text_message(&alice, &bob, "Hello Bob", None, &[]).await.unwrap();
+    let mut events = engine.subscribe_to_events();
+
+    engine.delete_owned_identity(&alice).await.unwrap();
+    let deleted = EngineEvent::OwnedIdentityDeleted { owned_identity: alice.clone() };
+    assert!(std::iter::from_fn(|| events.try_recv().ok()).any(|event| event == deleted));
+    assert_eq!(listed_identities(&engine).await, std::slice::from_ref(&other));
+    assert!(engine.get_contacts(&alice).await.unwrap().is_empty());
+    assert!(engine.get_discussions(&alice).await.unwrap().is_empty());
+    assert!(engine.get_message(message.get_id().unwrap()).await.unwrap().is_none());
+    assert!(matches!(engine.fetch_messages(&alice).await, Err(EngineError::UnknownOwnedIdentity)));
+
+    // Other profiles are untouched
+    assert_eq!(engine.get_contacts(&other).await.unwrap().len(), 1);
+}
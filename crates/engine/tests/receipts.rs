@@ -0,0 +1,52 @@
+mod common;
+
+use std::time::Duration;
+
+use engine::{entities::message::MessageStatus, events::EngineEvent, network::PushConnectionStatus, EngineError};
+use mock_server::MockServer;
+use tokio::sync::broadcast;
+
+use common::{alice_and_bob, wait_for_event, wait_for_received};
+
+async fn wait_for_status(events: &mut broadcast::Receiver<EngineEvent>, message_id: i64, status: MessageStatus) {
+    wait_for_event(events, |event| *event == EngineEvent::MessageStatusChanged { message_id, status }).await;
+}
+
+#[tokio::test]
+async fn delivery_and_read_receipts_update_sent_message() {
+    let server = MockServer::start().await.unwrap();
+    let ((mut alice_engine, alice), (mut bob_engine, bob)) = alice_and_bob(&server).await;
+    let mut alice_events = alice_engine.subscribe_to_events();
+    let mut bob_events = bob_engine.subscribe_to_events();
+
+    let mut push_status = alice_engine.start_push_listener(&alice).await.unwrap();
+    tokio::time::timeout(Duration::from_secs(10), push_status.wait_for(|status| *status == PushConnectionStatus::Connected)).await.unwrap().unwrap();
+
+    let first_message_id = alice_engine.send_text_message(&alice, &bob, "First", None, &[]).await.unwrap().get_id().unwrap();
+    wait_for_status(&mut alice_events, first_message_id, MessageStatus::Sent).await;
+
+    bob_engine.fetch_messages(&bob).await.unwrap();
+    let first_received_id = wait_for_received(&mut bob_events).await;
+    wait_for_status(&mut alice_events, first_message_id, MessageStatus::Delivered).await;
+    assert_eq!(alice_engine.get_message(first_message_id).await.unwrap().unwrap().get_status().unwrap(), MessageStatus::Delivered);
+
+    // Read receipts are disabled by default, the message stays delivered on Alice's side
+    bob_engine.mark_message_read(first_received_id).await.unwrap();
+    assert_eq!(bob_engine.get_message(first_received_id).await.unwrap().unwrap().get_status().unwrap(), MessageStatus::Read);
+
+    let discussion_id = bob_engine.get_discussions(&bob).await.unwrap()[0].get_id().unwrap();
+    assert!(matches!(bob_engine.set_discussion_send_read_receipts(discussion_id + 1, Some(true)).await, Err(EngineError::UnknownDiscussion)));
+    bob_engine.set_discussion_send_read_receipts(discussion_id, Some(true)).await.unwrap();
+    wait_for_event(&mut bob_events, |event| *event == EngineEvent::DiscussionSettingsUpdated { discussion_id }).await;
+
+    let second_message_id = alice_engine.send_text_message(&alice, &bob, "Second", None, &[]).await.unwrap().get_id().unwrap();
+    wait_for_status(&mut alice_events, second_message_id, MessageStatus::Sent).await;
+
+    bob_engine.fetch_messages(&bob).await.unwrap();
+    let second_received_id = wait_for_received(&mut bob_events).await;
+    bob_engine.mark_message_read(second_received_id).await.unwrap();
+    wait_for_status(&mut alice_events, second_message_id, MessageStatus::Read).await;
+
+    assert_eq!(alice_engine.get_message(first_message_id).await.unwrap().unwrap().get_status().unwrap(), MessageStatus::Delivered);
+    assert_eq!(alice_engine.get_message(second_message_id).await.unwrap().unwrap().get_status().unwrap(), MessageStatus::Read);
+}
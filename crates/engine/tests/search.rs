@@ -0,0 +1,172 @@
+mod common;
+
+use std::{ops::Range, time::Duration};
+
+use engine::{configuration::EngineConfiguration, entities::{discussion::Ephemerality, outbox_message::DeliveryState}, events::EngineEvent, search::MessageSearchQuery, store::DatabaseSecret, Engine};
+use mock_server::MockServer;
+use sqlx::SqlitePool;
+
+use common::{configuration, details, engines, wait_for_event, wait_for_received};
+
+/// Highlighted byte range
+fn bytes(start: usize, end: usize) -> Range<usize> {
+    start..end
+}
+
+/// Sends a text message and returns its id on the recipient side
+async fn send_and_receive(sender_engine: &Engine, sender: &[u8], recipient_engine: &mut Engine, recipient: &[u8], body: &str) -> i64 {
+    let mut sender_events = sender_engine.subscribe_to_events();
+    let mut recipient_events = recipient_engine.subscribe_to_events();
+    let outbox_message_id = sender_engine.send_text_message(sender, recipient, body, None, &[]).await.unwrap().get_outbox_message_id().unwrap();
+    wait_for_event(&mut sender_events, |event| *event == EngineEvent::MessageDeliveryStateChanged { outbox_message_id, delivery_state: DeliveryState::Sent }).await;
+
+    recipient_engine.fetch_messages(recipient).await.unwrap();
+    wait_for_received(&mut recipient_events).await
+}
+
+async fn search(engine: &Engine, owned_identity: &[u8], query: MessageSearchQuery) -> Vec<i64> {
+    engine.search_messages(owned_identity, &query, None, 10).await.unwrap().iter().map(|hit| hit.message.get_id().unwrap()).collect()
+}
+
+#[tokio::test]
+async fn search_matches_bodies_and_attachment_file_names() {
+    let server = MockServer::start().await.unwrap();
+    let directory = tempfile::tempdir().unwrap();
+    let mut alice_engine = Engine::init_with_configuration(configuration(&server, directory.path())).await.unwrap();
+    let alice = alice_engine.generate_simple_identity(details("Alice")).await.unwrap().identity.get_identity();
+    let source_path = directory.path().join("Sunset-Beach.jpg");
+    std::fs::write(&source_path, b"jpeg").unwrap();
+    let metadata = alice_engine.prepare_outbound_attachment(&alice, &source_path, "image/jpeg").await.unwrap().get_metadata();
+
+    for mut bob_engine in engines(&server, directory.path()).await {
+        let bob = bob_engine.generate_simple_identity(details("Bob")).await.unwrap().identity.get_identity();
+        alice_engine.add_contact(&alice, &bob, &details("Bob")).await.unwrap();
+        bob_engine.add_contact(&bob, &alice, &details("Alice")).await.unwrap();
+
+        let photos_id = send_and_receive(&alice_engine, &alice, &mut bob_engine, &bob, "Holiday photos from the beach").await;
+        let tomorrow_id = send_and_receive(&alice_engine, &alice, &mut bob_engine, &bob, "See you at the Beach tomorrow").await;
+
+        let message_uid = bob_engine.get_message(photos_id).await.unwrap().unwrap().get_message_uid().unwrap().to_vec();
+        bob_engine.start_attachment_download(&bob, &message_uid, 0, metadata.clone()).await.unwrap();
+
+        let hits = bob_engine.search_messages(&bob, &MessageSearchQuery::builder().text("BEACH").build(), None, 10).await.unwrap();
+        let hit_ids: Vec<i64> = hits.iter().map(|hit| hit.message.get_id().unwrap()).collect();
+        assert_eq!(hit_ids, [tomorrow_id, photos_id]);
+        assert_eq!(hits[0].body_highlights, [bytes(15, 20)]);
+        assert_eq!(hits[1].body_highlights, [bytes(24, 29)]);
+        assert_eq!(hits[1].file_name_highlights, [("Sunset-Beach.jpg".to_owned(), vec![bytes(7, 12)])]);
+
+        let hits = bob_engine.search_messages(&bob, &MessageSearchQuery::builder().text("sunset").build(), None, 10).await.unwrap();
+        assert_eq!(hits.len(), 1);
+        assert!(hits[0].body_highlights.is_empty());
+        assert_eq!(hits[0].file_name_highlights, [("Sunset-Beach.jpg".to_owned(), vec![bytes(0, 6)])]);
+
+        assert_eq!(search(&bob_engine, &bob, MessageSearchQuery::builder().text("beach photos").build()).await, [photos_id]);
+        assert!(search(&bob_engine, &bob, MessageSearchQuery::builder().text("photo").build()).await.is_empty());
+        assert!(search(&bob_engine, &bob, MessageSearchQuery::builder().text("  ,!").build()).await.is_empty());
+    }
+}
+
+#[tokio::test]
+async fn search_filters_and_pages_hits() {
+    let server = MockServer::start().await.unwrap();
+    let directory = tempfile::tempdir().unwrap();
+    let mut carol_engine = Engine::init_with_configuration(configuration(&server, directory.path())).await.unwrap();
+    let carol = carol_engine.generate_simple_identity(details("Carol")).await.unwrap().identity.get_identity();
+
+    for mut alice_engine in engines(&server, directory.path()).await {
+        let alice = alice_engine.generate_simple_identity(details("Alice")).await.unwrap().identity.get_identity();
+        let bob = Engine::init_with_configuration(configuration(&server, directory.path())).await.unwrap()
+            .generate_simple_identity(details("Bob")).await.unwrap().identity.get_identity();
+        alice_engine.add_contact(&alice, &bob, &details("Bob")).await.unwrap();
+        alice_engine.add_contact(&alice, &carol, &details("Carol")).await.unwrap();
+        carol_engine.add_contact(&carol, &alice, &details("Alice")).await.unwrap();
+
+        let first_id = alice_engine.send_text_message(&alice, &bob, "Lunch on Monday?", None, &[]).await.unwrap().get_id().unwrap();
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        let second_id = alice_engine.send_text_message(&alice, &bob, "Lunch on Tuesday then", None, &[]).await.unwrap().get_id().unwrap();
+        let second_timestamp = alice_engine.get_message(second_id).await.unwrap().unwrap().get_local_timestamp();
+        let to_carol_id = alice_engine.send_text_message(&alice, &carol, "Lunch with Bob", None, &[]).await.unwrap().get_id().unwrap();
+
+        let from_carol_id = send_and_receive(&carol_engine, &carol, &mut alice_engine, &alice, "Lunch sounds good").await;
+
+        let lunch = || MessageSearchQuery::builder().text("lunch");
+        assert_eq!(search(&alice_engine, &alice, lunch().build()).await, [from_carol_id, to_carol_id, second_id, first_id]);
+
+        let bob_discussion_id = alice_engine.get_message(first_id).await.unwrap().unwrap().get_discussion_id();
+        assert_eq!(search(&alice_engine, &alice, lunch().discussion_id(bob_discussion_id).build()).await, [second_id, first_id]);
+        assert_eq!(search(&alice_engine, &alice, lunch().sender_identity(carol.clone()).build()).await, [from_carol_id]);
+        assert_eq!(search(&alice_engine, &alice, lunch().discussion_id(bob_discussion_id).from_timestamp(second_timestamp).build()).await, [second_id]);
+        assert_eq!(search(&alice_engine, &alice, lunch().discussion_id(bob_discussion_id).to_timestamp(second_timestamp).build()).await, [first_id]);
+        assert!(search(&carol_engine, &carol, MessageSearchQuery::builder().text("monday").build()).await.is_empty());
+
+        let first_page = alice_engine.search_messages(&alice, &lunch().build(), None, 3).await.unwrap();
+        let second_page = alice_engine.search_messages(&alice, &lunch().build(), first_page.last().unwrap().message.get_id(), 3).await.unwrap();
+        assert_eq!(first_page.len(), 3);
+        assert_eq!(second_page.iter().map(|hit| hit.message.get_id().unwrap()).collect::<Vec<_>>(), [first_id]);
+    }
+}
+
+#[tokio::test]
+async fn expired_messages_leave_the_index() {
+    let server = MockServer::start().await.unwrap();
+    let directory = tempfile::tempdir().unwrap();
+
+    for alice_engine in engines(&server, directory.path()).await {
+        let mut alice_engine = alice_engine;
+        let alice = alice_engine.generate_simple_identity(details("Alice")).await.unwrap().identity.get_identity();
+        let bob = Engine::init_with_configuration(configuration(&server, directory.path())).await.unwrap()
+            .generate_simple_identity(details("Bob")).await.unwrap().identity.get_identity();
+        alice_engine.add_contact(&alice, &bob, &details("Bob")).await.unwrap();
+
+        let kept_id = alice_engine.send_text_message(&alice, &bob, "The secret recipe is kept", None, &[]).await.unwrap().get_id().unwrap();
+        let discussion_id = alice_engine.get_message(kept_id).await.unwrap().unwrap().get_discussion_id();
+        alice_engine.set_discussion_ephemerality(discussion_id, Ephemerality { existence_duration: Some(1), ..Default::default() }).await.unwrap();
+        let expiring_id = alice_engine.send_text_message(&alice, &bob, "The secret code is 1234", None, &[]).await.unwrap().get_id().unwrap();
+        assert_eq!(search(&alice_engine, &alice, MessageSearchQuery::builder().text("secret").build()).await, [expiring_id, kept_id]);
+
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while alice_engine.get_message(expiring_id).await.unwrap().is_some() {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        }).await.unwrap();
+
+        assert_eq!(search(&alice_engine, &alice, MessageSearchQuery::builder().text("secret").build()).await, [kept_id]);
+        assert!(search(&alice_engine, &alice, MessageSearchQuery::builder().text("1234").build()).await.is_empty());
+    }
+}
+
+#[tokio::test]
+async fn encrypted_index_holds_no_words() {
+    let server = MockServer::start().await.unwrap();
+    let directory = tempfile::tempdir().unwrap();
+    let database_path = directory.path().join("alice.db");
+    let plain_configuration = EngineConfiguration {
+        database_url: format!("sqlite://{}", database_path.display()),
+        ..configuration(&server, directory.path())
+    };
+    let encrypted_configuration = EngineConfiguration {
+        database_secret: Some(DatabaseSecret::Passphrase("correct horse".to_owned())),
+        ..plain_configuration.clone()
+    };
+
+    let (alice, message_id) = {
+        let mut alice_engine = Engine::init_with_configuration(plain_configuration).await.unwrap();
+        let alice = alice_engine.generate_simple_identity(details("Alice")).await.unwrap().identity.get_identity();
+        let bob = Engine::init_with_configuration(configuration(&server, directory.path())).await.unwrap()
+            .generate_simple_identity(details("Bob")).await.unwrap().identity.get_identity();
+        alice_engine.add_contact(&alice, &bob, &details("Bob")).await.unwrap();
+        let message_id = alice_engine.send_text_message(&alice, &bob, "Meet at noon", None, &[]).await.unwrap().get_id().unwrap();
+        (alice, message_id)
+    };
+
+    // Encrypting the columns of an existing database indexes its messages again
+    let alice_engine = Engine::init_with_configuration(encrypted_configuration).await.unwrap();
+    assert_eq!(search(&alice_engine, &alice, MessageSearchQuery::builder().text("Noon").build()).await, [message_id]);
+    drop(alice_engine);
+
+    let db = SqlitePool::connect(&format!("sqlite://{}", database_path.display())).await.unwrap();
+    let indexed_bodies: Vec<String> = sqlx::query_scalar("SELECT body FROM message_search").fetch_all(&db).await.unwrap();
+    assert_eq!(indexed_bodies.len(), 1);
+    assert!(!indexed_bodies[0].contains("noon"));
+}
@@ -0,0 +1,106 @@
+mod common;
+
+use std::sync::Arc;
+
+use engine::{configuration::EngineConfiguration, entities::{contact_channel::ContactChannel, discussion::Discussion, identity::DisplayNameFormat, message::MessageStatus, outbox_message::DeliveryState, return_receipt::OutboxReturnReceipt}, events::EngineEvent, store::{MemoryStore, SqliteStore, Store}, Engine};
+use mock_server::MockServer;
+
+use common::{details, wait_for_event, wait_for_received};
+
+async fn stores() -> Vec<Box<dyn Store>> {
+    vec![Box::new(SqliteStore::connect("sqlite::memory:", None).await.unwrap()), Box::new(MemoryStore::new())]
+}
+
+#[tokio::test]
+async fn transaction_writes_are_applied_once_committed() {
+    for store in stores().await {
+        let mut transaction = store.begin().await.unwrap();
+        let discussion_id = transaction.discussions().insert(Discussion::new_one_to_one(b"alice", b"bob", "Bob")).await.unwrap();
+        transaction.settings().set_display_name_format(&DisplayNameFormat::builder().show_company_and_position(true).build()).await.unwrap();
+        transaction.commit().await.unwrap();
+
+        assert_eq!(store.discussions().get_by_id(discussion_id).await.unwrap().unwrap().get_title(), "Bob");
+        assert!(store.settings().get_display_name_format().await.unwrap().show_company_and_position);
+    }
+}
+
+#[tokio::test]
+async fn dropped_transaction_leaves_nothing() {
+    for store in stores().await {
+        let mut transaction = store.begin().await.unwrap();
+        let discussion_id = transaction.discussions().insert(Discussion::new_one_to_one(b"alice", b"bob", "Bob")).await.unwrap();
+        drop(transaction);
+
+        assert!(store.discussions().get_by_id(discussion_id).await.unwrap().is_none());
+        assert!(store.discussions().get_one_to_one(b"alice", b"bob").await.unwrap().is_none());
+    }
+}
+
+#[tokio::test]
+async fn backends_order_rows_the_same_way() {
+    for store in stores().await {
+        let first = store.discussions().insert(Discussion::new_one_to_one(b"alice", b"bob", "Bob")).await.unwrap();
+        let second = store.discussions().insert(Discussion::new_one_to_one(b"alice", b"carol", "Carol")).await.unwrap();
+        store.discussions().insert(Discussion::new_one_to_one(b"dave", b"bob", "Bob")).await.unwrap();
+        store.discussions().set_last_message_timestamp(first, i64::MAX).await.unwrap();
+
+        // A timestamp never moves back
+        store.discussions().set_last_message_timestamp(first, 0).await.unwrap();
+
+        let discussion_ids: Vec<_> = store.discussions().get_all(b"alice").await.unwrap().iter().filter_map(Discussion::get_id).collect();
+        assert_eq!(discussion_ids, [first, second]);
+    }
+}
+
+#[tokio::test]
+async fn return_receipts_are_deleted_with_their_owned_identity() {
+    for store in stores().await {
+        store.return_receipts().insert(OutboxReturnReceipt::new(b"alice", b"bob", &[1; 16], vec![1])).await.unwrap();
+        let kept = store.return_receipts().insert(OutboxReturnReceipt::new(b"dave", b"bob", &[2; 16], vec![2])).await.unwrap();
+        store.return_receipts().delete_by_owned_identity(b"alice").await.unwrap();
+
+        let return_receipt_ids: Vec<_> = store.return_receipts().get_due(i64::MAX).await.unwrap().iter().filter_map(OutboxReturnReceipt::get_id).collect();
+        assert_eq!(return_receipt_ids, [kept]);
+    }
+}
+
+#[tokio::test]
+async fn channels_are_kept_until_torn_down() {
+    for store in stores().await {
+        assert!(store.contact_channels().insert_if_absent(ContactChannel::new(b"alice", b"bob", vec![1; 64])).await.unwrap());
+        assert!(!store.contact_channels().insert_if_absent(ContactChannel::new(b"alice", b"bob", vec![2; 64])).await.unwrap());
+        store.contact_channels().insert_if_absent(ContactChannel::new(b"alice", b"carol", vec![3; 64])).await.unwrap();
+        assert_eq!(store.contact_channels().get(b"alice", b"bob").await.unwrap().unwrap().get_channel_key(), [1; 64]);
+
+        assert!(store.contact_channels().delete(b"alice", b"bob").await.unwrap());
+        assert!(!store.contact_channels().delete(b"alice", b"bob").await.unwrap());
+        assert!(store.contact_channels().get(b"alice", b"bob").await.unwrap().is_none());
+        store.contact_channels().delete_by_owned_identity(b"alice").await.unwrap();
+        assert!(store.contact_channels().get(b"alice", b"carol").await.unwrap().is_none());
+    }
+}
+
+#[tokio::test]
+async fn engines_exchange_messages_on_memory_stores() {
+    let server = MockServer::start().await.unwrap();
+    let configuration = EngineConfiguration::builder().server_url(server.url()).build();
+    let mut alice_engine = Engine::init_with_store(configuration.clone(), Arc::new(MemoryStore::new())).await.unwrap();
+    let mut bob_engine = Engine::init_with_store(configuration, Arc::new(MemoryStore::new())).await.unwrap();
+
+    let alice = alice_engine.generate_simple_identity(details("Alice")).await.unwrap().identity.get_identity();
+    let bob = bob_engine.generate_simple_identity(details("Bob")).await.unwrap().identity.get_identity();
+    alice_engine.add_contact(&alice, &bob, &details("Bob")).await.unwrap();
+    bob_engine.add_contact(&bob, &alice, &details("Alice")).await.unwrap();
+
+    let mut alice_events = alice_engine.subscribe_to_events();
+    let mut bob_events = bob_engine.subscribe_to_events();
+    let sent_message = alice_engine.send_text_message(&alice, &bob, "Hello Bob", None, &[]).await.unwrap();
+    let outbox_message_id = sent_message.get_outbox_message_id().unwrap();
+    wait_for_event(&mut alice_events, |event| *event == EngineEvent::MessageDeliveryStateChanged { outbox_message_id, delivery_state: DeliveryState::Sent }).await;
+    assert_eq!(alice_engine.get_message(sent_message.get_id().unwrap()).await.unwrap().unwrap().get_status().unwrap(), MessageStatus::Sent);
+
+    bob_engine.fetch_messages(&bob).await.unwrap();
+    let received_message_id = wait_for_received(&mut bob_events).await;
+    assert_eq!(bob_engine.get_message(received_message_id).await.unwrap().unwrap().get_body(), Some("Hello Bob"));
+    assert_eq!(bob_engine.get_discussions(&bob).await.unwrap()[0].get_title(), "Alice");
+}
@@ -0,0 +1,99 @@
+mod common;
+
+use std::time::Duration;
+
+use engine::{entities::{contact::ContactTrustLevel, trust_establishment::{TrustEstablishment, TrustEstablishmentStatus}}, events::EngineEvent, Engine, EngineError};
+use mock_server::MockServer;
+use tokio::time::Instant;
+
+use common::{details, fetch_until, new_engine};
+
+/// Fetches the messages of an owned identity until a trust establishment reaches the expected state
+async fn fetch_until_trust_establishment(engine: &mut Engine, owned_identity: &[u8], trust_establishment_id: i64, is_expected: impl Fn(&TrustEstablishment) -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        if is_expected(&engine.get_trust_establishment(trust_establishment_id).await.unwrap().unwrap()) {
+            return;
+        }
+        assert!(Instant::now() < deadline, "expected trust establishment state not reached");
+        engine.fetch_messages(owned_identity).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+fn has_status(status: TrustEstablishmentStatus) -> impl Fn(&TrustEstablishment) -> bool {
+    move |trust_establishment| trust_establishment.get_status().unwrap() == status
+}
+
+async fn alice_and_bob(server: &MockServer) -> ((Engine, Vec<u8>), (Engine, Vec<u8>)) {
+    let mut alice_engine = new_engine(server).await;
+    let mut bob_engine = new_engine(server).await;
+    let alice = alice_engine.generate_simple_identity(details("Alice")).await.unwrap().identity.get_identity();
+    let bob = bob_engine.generate_simple_identity(details("Bob")).await.unwrap().identity.get_identity();
+
+    ((alice_engine, alice), (bob_engine, bob))
+}
+
+#[tokio::test]
+async fn typing_both_codes_verifies_the_contacts() {
+    let server = MockServer::start().await.unwrap();
+    let ((mut alice_engine, alice), (mut bob_engine, bob)) = alice_and_bob(&server).await;
+    let mut bob_events = bob_engine.subscribe_to_events();
+
+    let alice_trust_establishment = alice_engine.start_trust_establishment(&alice, &bob).await.unwrap();
+    let alice_trust_establishment_id = alice_trust_establishment.get_id().unwrap();
+    assert_eq!(alice_trust_establishment.get_status().unwrap(), TrustEstablishmentStatus::InvitationSent);
+    assert_eq!(alice_engine.get_trust_establishment_code(alice_trust_establishment_id).await.unwrap(), None);
+
+    let EngineEvent::TrustEstablishmentUpdated { trust_establishment_id: bob_trust_establishment_id, .. } = fetch_until(&mut bob_engine, &bob, &mut bob_events, |event| matches!(event, EngineEvent::TrustEstablishmentUpdated { .. })).await else { unreachable!() };
+    let bob_trust_establishment = bob_engine.get_trust_establishment(bob_trust_establishment_id).await.unwrap().unwrap();
+    assert_eq!(bob_trust_establishment.get_status().unwrap(), TrustEstablishmentStatus::InvitationReceived);
+    assert_eq!(bob_trust_establishment.get_contact_identity(), alice);
+    assert_eq!(bob_trust_establishment.get_identity_details().unwrap(), Some(details("Alice")));
+
+    bob_engine.accept_trust_establishment(bob_trust_establishment_id).await.unwrap();
+    fetch_until_trust_establishment(&mut alice_engine, &alice, alice_trust_establishment_id, has_status(TrustEstablishmentStatus::CodeRequired)).await;
+    fetch_until_trust_establishment(&mut bob_engine, &bob, bob_trust_establishment_id, has_status(TrustEstablishmentStatus::CodeRequired)).await;
+
+    let alice_code = alice_engine.get_trust_establishment_code(alice_trust_establishment_id).await.unwrap().unwrap();
+    let bob_code = bob_engine.get_trust_establishment_code(bob_trust_establishment_id).await.unwrap().unwrap();
+    let wrong_code = format!("{:04}", (alice_code.parse::<u32>().unwrap() + 1) % 10_000);
+    assert!(matches!(bob_engine.enter_trust_establishment_code(bob_trust_establishment_id, &wrong_code).await, Err(EngineError::InvalidSasCode)));
+
+    // Bob typed the code of Alice, and waits for Alice to type his
+    bob_engine.enter_trust_establishment_code(bob_trust_establishment_id, &alice_code).await.unwrap();
+    assert_eq!(bob_engine.get_trust_establishment(bob_trust_establishment_id).await.unwrap().unwrap().get_status().unwrap(), TrustEstablishmentStatus::WaitingForContactCode);
+    fetch_until_trust_establishment(&mut alice_engine, &alice, alice_trust_establishment_id, TrustEstablishment::is_contact_confirmed).await;
+
+    let mut alice_events = alice_engine.subscribe_to_events();
+    alice_engine.enter_trust_establishment_code(alice_trust_establishment_id, &bob_code).await.unwrap();
+    assert_eq!(alice_engine.get_trust_establishment(alice_trust_establishment_id).await.unwrap().unwrap().get_status().unwrap(), TrustEstablishmentStatus::Finished);
+    let finished = EngineEvent::ProtocolFinished { owned_identity: alice.clone(), protocol_instance_uid: alice_trust_establishment.get_protocol_uid().to_vec() };
+    assert!(std::iter::from_fn(|| alice_events.try_recv().ok()).any(|event| event == finished));
+    fetch_until_trust_establishment(&mut bob_engine, &bob, bob_trust_establishment_id, has_status(TrustEstablishmentStatus::Finished)).await;
+
+    for (engine, owned_identity, contact_identity, first_name) in [(&alice_engine, &alice, &bob, "Bob"), (&bob_engine, &bob, &alice, "Alice")] {
+        let contacts = engine.get_contacts(owned_identity).await.unwrap();
+        assert_eq!(contacts.len(), 1);
+        assert_eq!(contacts[0].get_contact_identity(), contact_identity.as_slice());
+        assert_eq!(contacts[0].get_identity_details().unwrap(), details(first_name));
+        assert_eq!(contacts[0].get_trust_level().unwrap(), ContactTrustLevel::Verified);
+    }
+}
+
+#[tokio::test]
+async fn rejected_invitation_is_cancelled_on_both_sides() {
+    let server = MockServer::start().await.unwrap();
+    let ((mut alice_engine, alice), (mut bob_engine, bob)) = alice_and_bob(&server).await;
+    let mut bob_events = bob_engine.subscribe_to_events();
+
+    let alice_trust_establishment_id = alice_engine.start_trust_establishment(&alice, &bob).await.unwrap().get_id().unwrap();
+    let EngineEvent::TrustEstablishmentUpdated { trust_establishment_id: bob_trust_establishment_id, .. } = fetch_until(&mut bob_engine, &bob, &mut bob_events, |event| matches!(event, EngineEvent::TrustEstablishmentUpdated { .. })).await else { unreachable!() };
+
+    bob_engine.reject_trust_establishment(bob_trust_establishment_id).await.unwrap();
+    assert!(matches!(bob_engine.accept_trust_establishment(bob_trust_establishment_id).await, Err(EngineError::UnknownTrustEstablishment)));
+    fetch_until_trust_establishment(&mut alice_engine, &alice, alice_trust_establishment_id, has_status(TrustEstablishmentStatus::Cancelled)).await;
+
+    assert!(alice_engine.get_contacts(&alice).await.unwrap().is_empty());
+    assert!(bob_engine.get_contacts(&bob).await.unwrap().is_empty());
+}
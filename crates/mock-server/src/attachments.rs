@@ -0,0 +1,60 @@
+use std::collections::HashMap;
+
+use axum::{body::Bytes, extract::{Path, Query, State}, http::StatusCode};
+
+use crate::{state::StoredAttachment, ServerContext};
+
+/// Chunk URLs mimic the signed URLs of the real server, the signature being a random value known only to the server
+pub fn chunk_url(base_url: &str, message_uid: &[u8], attachment_number: usize, chunk_number: usize, url_signature: &[u8]) -> String {
+    format!("{}/attachments/{}/{}/{}?signature={}", base_url, hex::encode(message_uid), attachment_number, chunk_number, hex::encode(url_signature))
+}
+
+fn with_attachment<T>(context: &ServerContext, message_uid: &str, attachment_number: usize, signature: Option<&String>, f: impl FnOnce(&mut StoredAttachment) -> Result<T, StatusCode>) -> Result<T, StatusCode> {
+    let message_uid = hex::decode(message_uid).map_err(|_| StatusCode::NOT_FOUND)?;
+    let signature = hex::decode(signature.ok_or(StatusCode::FORBIDDEN)?).map_err(|_| StatusCode::FORBIDDEN)?;
+
+    let mut data = context.state.data.lock().unwrap();
+    match data.chunk_transfer_budget {
+        Some(0) => return Err(StatusCode::SERVICE_UNAVAILABLE),
+        Some(budget) => data.chunk_transfer_budget = Some(budget - 1),
+        None => {}
+    }
+
+    let attachment = data.messages.iter_mut()
+        .find(|message| message.message_uid == message_uid)
+        .and_then(|message| message.attachments.get_mut(attachment_number))
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    if attachment.url_signature != signature {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    f(attachment)
+}
+
+pub async fn upload_chunk(
+    State(context): State<ServerContext>,
+    Path((message_uid, attachment_number, chunk_number)): Path<(String, usize, usize)>,
+    Query(query): Query<HashMap<String, String>>,
+    body: Bytes,
+) -> StatusCode {
+    let result = with_attachment(&context, &message_uid, attachment_number, query.get("signature"), |attachment| {
+        if chunk_number >= attachment.chunk_count {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        attachment.chunks.insert(chunk_number, body.to_vec());
+        Ok(StatusCode::OK)
+    });
+
+    result.unwrap_or_else(|status| status)
+}
+
+pub async fn download_chunk(
+    State(context): State<ServerContext>,
+    Path((message_uid, attachment_number, chunk_number)): Path<(String, usize, usize)>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<Vec<u8>, StatusCode> {
+    with_attachment(&context, &message_uid, attachment_number, query.get("signature"), |attachment| {
+        attachment.chunks.get(&chunk_number).cloned().ok_or(StatusCode::NOT_FOUND)
+    })
+}
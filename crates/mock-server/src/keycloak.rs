@@ -0,0 +1,289 @@
+//! In-memory stand-in for a keycloak server with the Olvid plugin.
+//!
+//! It answers the OIDC discovery, refreshes tokens, publishes its JWKS and implements the `olvid-rest` methods used
+//! by the engine. User details and revocations are signed with an ES256 key generated at startup.
+
+use std::{collections::HashMap, io, net::SocketAddr, sync::{Arc, Mutex}};
+
+use axum::{body::Bytes, extract::{Path, State}, http::{header, HeaderMap, StatusCode}, routing::{get, post}, Form, Json, Router};
+use base64::{engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD}, Engine};
+use p256::ecdsa::{signature::Signer, Signature, SigningKey};
+use rand::rngs::OsRng;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::{net::TcpListener, sync::oneshot, task::JoinHandle};
+
+use crate::state::{now_timestamp, random_bytes, TOKEN_LENGTH};
+
+const KEY_ID: &str = "mock-keycloak";
+const REVOCATION_TYPE_COMPROMISED: i64 = 0;
+
+#[derive(Debug, Clone, Default)]
+pub struct MockKeycloakUser {
+    pub id: String,
+    pub username: String,
+    pub first_name: String,
+    pub last_name: Option<String>,
+    pub company: Option<String>,
+    pub position: Option<String>,
+    /// Olvid identity uploaded by the user
+    pub identity: Option<Vec<u8>>,
+}
+
+#[derive(Default)]
+pub struct MockKeycloakData {
+    pub users: HashMap<String, MockKeycloakUser>,
+    /// User id of each valid access token
+    pub access_tokens: HashMap<String, String>,
+    pub refresh_tokens: HashMap<String, String>,
+    /// Revoked identities and the time they were revoked at
+    pub revocations: Vec<(Vec<u8>, i64)>,
+    pub transfer_restricted: bool,
+}
+
+pub struct MockKeycloakState {
+    signing_key: SigningKey,
+    pub data: Mutex<MockKeycloakData>,
+}
+
+impl MockKeycloakState {
+    pub fn new() -> Self {
+        Self { signing_key: SigningKey::random(&mut OsRng), data: Mutex::new(MockKeycloakData::default()) }
+    }
+
+    pub fn add_user(&self, user: MockKeycloakUser) {
+        self.data.lock().unwrap().users.insert(user.id.clone(), user);
+    }
+
+    /// Logs a user in, returns its access and refresh tokens
+    pub fn issue_tokens(&self, user_id: &str) -> (String, String) {
+        let mut data = self.data.lock().unwrap();
+        let access_token = hex::encode(random_bytes(TOKEN_LENGTH));
+        let refresh_token = hex::encode(random_bytes(TOKEN_LENGTH));
+        data.access_tokens.insert(access_token.clone(), user_id.to_owned());
+        data.refresh_tokens.insert(refresh_token.clone(), user_id.to_owned());
+        (access_token, refresh_token)
+    }
+
+    /// Drops every access token, the refresh tokens stay valid
+    pub fn expire_access_tokens(&self) {
+        self.data.lock().unwrap().access_tokens.clear();
+    }
+
+    /// Revokes the identity uploaded by a user
+    pub fn revoke_user(&self, user_id: &str) {
+        let mut data = self.data.lock().unwrap();
+        if let Some(identity) = data.users.get(user_id).and_then(|user| user.identity.clone()) {
+            data.revocations.push((identity, now_timestamp()));
+        }
+    }
+
+    pub fn set_transfer_restricted(&self, transfer_restricted: bool) {
+        self.data.lock().unwrap().transfer_restricted = transfer_restricted;
+    }
+
+    /// Public key of the server, as a JWKS
+    pub fn jwks(&self) -> Value {
+        let point = self.signing_key.verifying_key().to_encoded_point(false);
+        json!({
+            "keys": [{
+                "kty": "EC",
+                "crv": "P-256",
+                "kid": KEY_ID,
+                "x": URL_SAFE_NO_PAD.encode(point.x().unwrap()),
+                "y": URL_SAFE_NO_PAD.encode(point.y().unwrap()),
+            }]
+        })
+    }
+
+    /// Compact JWS of a JSON payload
+    pub fn sign(&self, payload: &Value) -> String {
+        let header = URL_SAFE_NO_PAD.encode(json!({ "alg": "ES256", "kid": KEY_ID }).to_string());
+        let signing_input = format!("{}.{}", header, URL_SAFE_NO_PAD.encode(payload.to_string()));
+        let signature: Signature = self.signing_key.sign(signing_input.as_bytes());
+        format!("{}.{}", signing_input, URL_SAFE_NO_PAD.encode(signature.to_bytes()))
+    }
+
+    /// What the server signs about a user, for example to create a keycloak managed identity
+    pub fn signed_user_details(&self, user_id: &str) -> Option<String> {
+        let user = self.data.lock().unwrap().users.get(user_id).cloned()?;
+        Some(self.sign(&user_details(&user)))
+    }
+
+    fn authenticated_user(&self, headers: &HeaderMap) -> Option<MockKeycloakUser> {
+        let access_token = headers.get(header::AUTHORIZATION)?.to_str().ok()?.strip_prefix("Bearer ")?;
+        let data = self.data.lock().unwrap();
+        data.access_tokens.get(access_token).and_then(|user_id| data.users.get(user_id)).cloned()
+    }
+}
+
+impl Default for MockKeycloakState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn user_details(user: &MockKeycloakUser) -> Value {
+    json!({
+        "id": user.id,
+        "username": user.username,
+        "firstName": user.first_name,
+        "lastName": user.last_name,
+        "company": user.company,
+        "position": user.position,
+        "identity": user.identity.as_ref().map(|identity| STANDARD.encode(identity)),
+        "timestamp": now_timestamp(),
+    })
+}
+
+#[derive(Clone)]
+struct KeycloakContext {
+    state: Arc<MockKeycloakState>,
+    base_url: String,
+}
+
+async fn openid_configuration(State(context): State<KeycloakContext>) -> Json<Value> {
+    Json(json!({
+        "issuer": context.base_url,
+        "token_endpoint": format!("{}token", context.base_url),
+        "jwks_uri": format!("{}jwks", context.base_url),
+    }))
+}
+
+async fn jwks(State(context): State<KeycloakContext>) -> Json<Value> {
+    Json(context.state.jwks())
+}
+
+/// Only the refresh token grant, logging in is done with `MockKeycloakState::issue_tokens`
+async fn token(State(context): State<KeycloakContext>, Form(form): Form<HashMap<String, String>>) -> Result<Json<Value>, StatusCode> {
+    if form.get("grant_type").map(String::as_str) != Some("refresh_token") {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let refresh_token = form.get("refresh_token").ok_or(StatusCode::BAD_REQUEST)?;
+    let user_id = context.state.data.lock().unwrap().refresh_tokens.get(refresh_token).cloned().ok_or(StatusCode::BAD_REQUEST)?;
+
+    let access_token = hex::encode(random_bytes(TOKEN_LENGTH));
+    context.state.data.lock().unwrap().access_tokens.insert(access_token.clone(), user_id);
+    Ok(Json(json!({ "access_token": access_token, "refresh_token": refresh_token, "token_type": "Bearer" })))
+}
+
+#[derive(Deserialize)]
+struct MeRequest {
+    timestamp: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct PutKeyRequest {
+    identity: String,
+}
+
+#[derive(Deserialize)]
+struct SearchRequest {
+    filter: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct GetKeyRequest {
+    user: String,
+}
+
+async fn olvid_rest(State(context): State<KeycloakContext>, Path(method): Path<String>, headers: HeaderMap, body: Bytes) -> Result<Json<Value>, StatusCode> {
+    let user = context.state.authenticated_user(&headers).ok_or(StatusCode::UNAUTHORIZED)?;
+    let state = &context.state;
+
+    match method.as_str() {
+        "me" => {
+            let request: MeRequest = serde_json::from_slice(&body).map_err(|_| StatusCode::BAD_REQUEST)?;
+            let data = state.data.lock().unwrap();
+            let signed_revocations: Vec<String> = data.revocations.iter()
+                .filter(|(_, timestamp)| *timestamp >= request.timestamp.unwrap_or_default())
+                .map(|(identity, timestamp)| state.sign(&json!({ "identity": STANDARD.encode(identity), "timestamp": timestamp, "type": REVOCATION_TYPE_COMPROMISED })))
+                .collect();
+            Ok(Json(json!({
+                "signature": state.sign(&user_details(&user)),
+                "transferRestricted": data.transfer_restricted,
+                "signedRevocations": signed_revocations,
+                "currentServerTimestamp": now_timestamp(),
+            })))
+        }
+        "putKey" => {
+            let request: PutKeyRequest = serde_json::from_slice(&body).map_err(|_| StatusCode::BAD_REQUEST)?;
+            let identity = STANDARD.decode(request.identity).map_err(|_| StatusCode::BAD_REQUEST)?;
+            let mut data = state.data.lock().unwrap();
+            if let Some(stored_user) = data.users.get_mut(&user.id) {
+                stored_user.identity = Some(identity);
+            }
+            Ok(Json(json!({})))
+        }
+        "search" => {
+            let request: SearchRequest = serde_json::from_slice(&body).map_err(|_| StatusCode::BAD_REQUEST)?;
+            let filter = request.filter.unwrap_or_default().to_lowercase();
+            let data = state.data.lock().unwrap();
+            let mut results: Vec<&MockKeycloakUser> = data.users.values()
+                .filter(|user| [Some(&user.username), Some(&user.first_name), user.last_name.as_ref()].into_iter().flatten().any(|name| name.to_lowercase().contains(&filter)))
+                .collect();
+            results.sort_by(|a, b| a.id.cmp(&b.id));
+            Ok(Json(json!({ "results": results.into_iter().map(user_details).collect::<Vec<Value>>() })))
+        }
+        "getKey" => {
+            let request: GetKeyRequest = serde_json::from_slice(&body).map_err(|_| StatusCode::BAD_REQUEST)?;
+            let signature = state.signed_user_details(&request.user).ok_or(StatusCode::NOT_FOUND)?;
+            Ok(Json(json!({ "signature": signature })))
+        }
+        _ => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+fn router(context: KeycloakContext) -> Router {
+    Router::new()
+        .route("/.well-known/openid-configuration", get(openid_configuration))
+        .route("/jwks", get(jwks))
+        .route("/token", post(token))
+        .route("/olvid-rest/{method}", post(olvid_rest))
+        .with_state(context)
+}
+
+/// Running mock keycloak server, it is shut down when dropped
+pub struct MockKeycloakServer {
+    address: SocketAddr,
+    state: Arc<MockKeycloakState>,
+    shutdown_sender: Option<oneshot::Sender<()>>,
+    _server_task: JoinHandle<()>,
+}
+
+impl MockKeycloakServer {
+    /// Starts a server on a random local port
+    pub async fn start() -> io::Result<Self> {
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))).await?;
+        let address = listener.local_addr()?;
+
+        let state = Arc::new(MockKeycloakState::new());
+        let context = KeycloakContext { state: Arc::clone(&state), base_url: format!("http://{}/", address) };
+
+        let (shutdown_sender, shutdown_receiver) = oneshot::channel::<()>();
+        let server_task = tokio::spawn(async move {
+            let _ = axum::serve(listener, router(context))
+                .with_graceful_shutdown(async { let _ = shutdown_receiver.await; })
+                .await;
+        });
+
+        Ok(Self { address, state, shutdown_sender: Some(shutdown_sender), _server_task: server_task })
+    }
+
+    /// Ends with a slash, as the URL of a keycloak realm
+    pub fn url(&self) -> String {
+        format!("http://{}/", self.address)
+    }
+
+    pub fn state(&self) -> &Arc<MockKeycloakState> {
+        &self.state
+    }
+}
+
+impl Drop for MockKeycloakServer {
+    fn drop(&mut self) {
+        if let Some(shutdown_sender) = self.shutdown_sender.take() {
+            let _ = shutdown_sender.send(());
+        }
+    }
+}
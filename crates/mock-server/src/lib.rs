@@ -0,0 +1,233 @@
+//! In-memory implementation of the subset of the Olvid server API used by the engine.
+//!
+//! It lets integration tests run several engines against a real HTTP and WebSocket server without
+//! reaching `server.olvid.io`.
+
+use std::{io, net::SocketAddr, sync::Arc};
+
+use axum::{routing::{get, post}, Router};
+use tokio::{net::TcpListener, sync::oneshot, task::JoinHandle};
+
+pub mod attachments;
+pub mod keycloak;
+pub mod methods;
+pub mod push;
+pub mod state;
+
+pub use keycloak::{MockKeycloakServer, MockKeycloakUser};
+pub use state::{KeycloakConfiguration, MockServerState, StoredApiKey};
+
+#[derive(Clone)]
+pub struct ServerContext {
+    pub state: Arc<MockServerState>,
+    /// URL the server is reachable at, used to build attachment chunk URLs
+    pub base_url: String,
+}
+
+pub fn router(context: ServerContext) -> Router {
+    Router::new()
+        .route("/ws", get(push::handle_push_connection))
+        .route("/attachments/{message_uid}/{attachment_number}/{chunk_number}", get(attachments::download_chunk).put(attachments::upload_chunk))
+        .route("/{method}", post(methods::handle_method))
+        .with_state(context)
+}
+
+/// Running mock server, it is shut down when dropped
+pub struct MockServer {
+    address: SocketAddr,
+    state: Arc<MockServerState>,
+    shutdown_sender: Option<oneshot::Sender<()>>,
+    server_task: JoinHandle<()>,
+}
+
+impl MockServer {
+    /// Starts a server on a random local port
+    pub async fn start() -> io::Result<Self> {
+        Self::bind(SocketAddr::from(([127, 0, 0, 1], 0))).await
+    }
+
+    pub async fn bind(address: SocketAddr) -> io::Result<Self> {
+        let listener = TcpListener::bind(address).await?;
+        let address = listener.local_addr()?;
+
+        let state = Arc::new(MockServerState::new());
+        let context = ServerContext { state: Arc::clone(&state), base_url: format!("http://{}", address) };
+
+        let (shutdown_sender, shutdown_receiver) = oneshot::channel::<()>();
+        let server_task = tokio::spawn(async move {
+            let _ = axum::serve(listener, router(context))
+                .with_graceful_shutdown(async { let _ = shutdown_receiver.await; })
+                .await;
+        });
+
+        Ok(Self { address, state, shutdown_sender: Some(shutdown_sender), server_task })
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}", self.address)
+    }
+
+    pub fn push_url(&self) -> String {
+        format!("ws://{}/ws", self.address)
+    }
+
+    pub fn state(&self) -> &Arc<MockServerState> {
+        &self.state
+    }
+
+    /// Waits for the server to stop, it only stops once the handle is dropped
+    pub async fn wait(mut self) {
+        let server_task = std::mem::replace(&mut self.server_task, tokio::spawn(async {}));
+        let _ = server_task.await;
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        if let Some(shutdown_sender) = self.shutdown_sender.take() {
+            let _ = shutdown_sender.send(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{SinkExt, StreamExt};
+    use olvid_core::{crypto::prng::{PRNGHmacSHA256, PRNG}, cryptographic_identity::OwnedCryptographicIdentity, encoding::{BytesArray, Decoder, Encoder}};
+    use rand::random;
+    use tokio_tungstenite::tungstenite::Message;
+
+    use crate::methods::{STATUS_INVALID_SESSION, STATUS_OK};
+
+    use super::MockServer;
+
+    async fn call(server: &MockServer, method: &str, inputs: Vec<BytesArray>) -> (u8, Vec<BytesArray>) {
+        let body = reqwest::Client::new()
+            .post(format!("{}/{}", server.url(), method))
+            .body(inputs.encode().unwrap())
+            .send()
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+
+        let outputs = match body.len() > 1 {
+            true => Vec::<BytesArray>::decode(&body[1..]).unwrap(),
+            false => vec![],
+        };
+        (body[0], outputs)
+    }
+
+    async fn authenticate(server: &MockServer, owned_identity: &OwnedCryptographicIdentity, prng: &mut PRNGHmacSHA256) -> Vec<u8> {
+        let identity = owned_identity.get_crypto_identity().get_identity();
+        let nonce = prng.bytes(16).unwrap();
+
+        let (status, outputs) = call(server, "requestChallenge", vec![identity.encode().unwrap(), nonce.encode().unwrap()]).await;
+        assert_eq!(status, STATUS_OK);
+
+        let mut challenge = BytesArray::decode(&outputs[0]).unwrap();
+        challenge.extend_from_slice(&nonce);
+        challenge.append(&mut BytesArray::decode(&outputs[1]).unwrap());
+        let response = owned_identity.solve_challenge(&challenge, prng).unwrap();
+
+        let (status, outputs) = call(server, "getToken", vec![identity.encode().unwrap(), response.encode().unwrap(), nonce.encode().unwrap()]).await;
+        assert_eq!(status, STATUS_OK);
+        BytesArray::decode(&outputs[0]).unwrap()
+    }
+
+    fn upload_inputs(to_identity: &[u8], chunk_counts: &[i64]) -> Vec<BytesArray> {
+        let attachments = chunk_counts.iter().map(|chunk_count| chunk_count.encode().unwrap()).collect::<Vec<BytesArray>>();
+        vec![
+            to_identity.to_vec().encode().unwrap(),
+            Vec::<BytesArray>::new().encode().unwrap(),
+            vec![1u8; 32].encode().unwrap(),
+            vec![2u8; 64].encode().unwrap(),
+            true.encode().unwrap(),
+            attachments.encode().unwrap(),
+        ]
+    }
+
+    fn new_prng() -> PRNGHmacSHA256 {
+        let seed: [u8; 32] = random();
+        PRNGHmacSHA256::init(&seed).unwrap()
+    }
+
+    #[tokio::test]
+    async fn unauthenticated_download_is_rejected() {
+        let server = MockServer::start().await.unwrap();
+
+        let (status, _) = call(&server, "downloadMessagesAndListAttachments", vec![vec![1u8; 10].encode().unwrap(), vec![2u8; 32].encode().unwrap(), vec![3u8; 32].encode().unwrap()]).await;
+        assert_eq!(status, STATUS_INVALID_SESSION);
+    }
+
+    #[tokio::test]
+    async fn message_and_attachment_round_trip() {
+        let server = MockServer::start().await.unwrap();
+        let mut prng = new_prng();
+        let owned_identity = OwnedCryptographicIdentity::generate_owned_cryptographic_identity(&server.url(), &mut prng).unwrap();
+        let identity = owned_identity.get_crypto_identity().get_identity();
+        let device_uid = vec![7u8; 32];
+
+        let token = authenticate(&server, &owned_identity, &mut prng).await;
+
+        let (status, outputs) = call(&server, "uploadMessageAndGetUids", upload_inputs(&identity, &[2])).await;
+        assert_eq!(status, STATUS_OK);
+        let message_uid = BytesArray::decode(&outputs[0]).unwrap();
+        let upload_urls = Vec::<BytesArray>::decode(&Vec::<BytesArray>::decode(&outputs[1]).unwrap()[0]).unwrap();
+        assert_eq!(upload_urls.len(), 2);
+
+        let http_client = reqwest::Client::new();
+        for (chunk_number, encoded_url) in upload_urls.iter().enumerate() {
+            let url = String::decode(encoded_url).unwrap();
+            let response = http_client.put(url).body(vec![chunk_number as u8; 10]).send().await.unwrap();
+            assert!(response.status().is_success());
+        }
+
+        let session_inputs = || vec![identity.encode().unwrap(), token.encode().unwrap(), device_uid.encode().unwrap()];
+        let (status, outputs) = call(&server, "downloadMessagesAndListAttachments", session_inputs()).await;
+        assert_eq!(status, STATUS_OK);
+        let messages = Vec::<BytesArray>::decode(&outputs[0]).unwrap();
+        assert_eq!(messages.len(), 1);
+
+        let mut chunk_url_inputs = session_inputs();
+        chunk_url_inputs.push(message_uid.encode().unwrap());
+        chunk_url_inputs.push(0i64.encode().unwrap());
+        let (status, outputs) = call(&server, "getAttachmentChunkDownloadUrls", chunk_url_inputs).await;
+        assert_eq!(status, STATUS_OK);
+        let download_urls = Vec::<BytesArray>::decode(&outputs[0]).unwrap();
+        let second_chunk = http_client.get(String::decode(&download_urls[1]).unwrap()).send().await.unwrap().bytes().await.unwrap();
+        assert_eq!(second_chunk.to_vec(), vec![1u8; 10]);
+
+        let mut delete_inputs = session_inputs();
+        delete_inputs.push(message_uid.encode().unwrap());
+        let (status, _) = call(&server, "deleteMessageAndAttachments", delete_inputs).await;
+        assert_eq!(status, STATUS_OK);
+
+        let (_, outputs) = call(&server, "downloadMessagesAndListAttachments", session_inputs()).await;
+        assert!(Vec::<BytesArray>::decode(&outputs[0]).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn push_notification_on_upload() {
+        let server = MockServer::start().await.unwrap();
+        let mut prng = new_prng();
+        let owned_identity = OwnedCryptographicIdentity::generate_owned_cryptographic_identity(&server.url(), &mut prng).unwrap();
+        let identity = owned_identity.get_crypto_identity().get_identity();
+        let token = authenticate(&server, &owned_identity, &mut prng).await;
+
+        let (mut socket, _) = tokio_tungstenite::connect_async(server.push_url()).await.unwrap();
+        let register = serde_json::json!({ "action": "register", "identity": hex::encode(&identity), "token": hex::encode(&token), "deviceUid": hex::encode([7u8; 32]) });
+        socket.send(Message::Text(register.to_string().into())).await.unwrap();
+
+        let registered: serde_json::Value = serde_json::from_str(socket.next().await.unwrap().unwrap().to_text().unwrap()).unwrap();
+        assert_eq!(registered["action"], "registered");
+
+        let (_, outputs) = call(&server, "uploadMessageAndGetUids", upload_inputs(&identity, &[])).await;
+        let message_uid = BytesArray::decode(&outputs[0]).unwrap();
+
+        let notification: serde_json::Value = serde_json::from_str(socket.next().await.unwrap().unwrap().to_text().unwrap()).unwrap();
+        assert_eq!(notification["action"], "message");
+        assert_eq!(notification["messageUid"], hex::encode(&message_uid));
+    }
+}
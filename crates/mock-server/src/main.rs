@@ -0,0 +1,27 @@
+use std::net::{IpAddr, SocketAddr};
+
+use clap::Parser;
+use mock_server::MockServer;
+
+#[derive(Parser, Debug)]
+#[command(about = "Local stand-in for the Olvid server")]
+struct Cli {
+    /// Address to listen on
+    #[arg(long, default_value = "127.0.0.1")]
+    address: IpAddr,
+
+    /// Port to listen on, a random one is picked when 0
+    #[arg(short, long, default_value_t = 8080)]
+    port: u16,
+}
+
+#[tokio::main]
+async fn main() -> std::io::Result<()> {
+    let args = Cli::parse();
+
+    let server = MockServer::bind(SocketAddr::new(args.address, args.port)).await?;
+    println!("Mock Olvid server listening on {}", server.url());
+    server.wait().await;
+
+    Ok(())
+}
@@ -0,0 +1,419 @@
+use std::sync::Arc;
+
+use axum::{body::Bytes, extract::{Path, State}};
+use olvid_core::{cryptographic_identity::CryptographicIdentity, encoding::{BytesArray, Decoder, Encoder}};
+
+use crate::{attachments::chunk_url, state::{now_timestamp, random_bytes, MockServerState, PendingChallenge, PushNotification, StoredApiKey, StoredAttachment, StoredGroupBlob, StoredMessage, StoredReturnReceipt, StoredTransferSession, TOKEN_LENGTH, UID_LENGTH}, ServerContext};
+
+pub const STATUS_OK: u8 = 0x00;
+pub const STATUS_INVALID_SESSION: u8 = 0x04;
+pub const STATUS_DELETED_FROM_SERVER: u8 = 0x09;
+pub const STATUS_INVALID_API_KEY: u8 = 0x0b;
+pub const STATUS_GROUP_UID_ALREADY_USED: u8 = 0x13;
+pub const STATUS_GROUP_BLOB_OUTDATED: u8 = 0x14;
+pub const STATUS_GENERAL_ERROR: u8 = 0xff;
+
+const API_KEY_STATUS_VALID: i64 = 1;
+/// Status of the identities that did not register a key
+const DEFAULT_API_KEY: StoredApiKey = StoredApiKey { status: API_KEY_STATUS_VALID, permissions: 0, expiration_timestamp: 0 };
+/// Session numbers have this many digits
+const TRANSFER_SESSION_NUMBER_DIGITS: u32 = 8;
+
+/// Error answered to the client as a single status byte
+pub struct MethodError(u8);
+
+type MethodResult = Result<Vec<BytesArray>, MethodError>;
+
+fn general_error<T>(_: T) -> MethodError {
+    MethodError(STATUS_GENERAL_ERROR)
+}
+
+fn input<T: Decoder>(inputs: &[BytesArray], index: usize) -> Result<T, MethodError> {
+    T::decode(inputs.get(index).ok_or(MethodError(STATUS_GENERAL_ERROR))?).map_err(general_error)
+}
+
+fn encode(values: Vec<Result<BytesArray, olvid_core::encoding::DecodingParsingError>>) -> MethodResult {
+    values.into_iter().collect::<Result<Vec<BytesArray>, _>>().map_err(general_error)
+}
+
+/// Authenticated methods all start with the identity and its session token
+fn check_session(state: &MockServerState, inputs: &[BytesArray]) -> Result<Vec<u8>, MethodError> {
+    let identity: Vec<u8> = input(inputs, 0)?;
+    let token: Vec<u8> = input(inputs, 1)?;
+
+    if !state.is_session_valid(&identity, &token) {
+        return Err(MethodError(STATUS_INVALID_SESSION));
+    }
+
+    Ok(identity)
+}
+
+pub async fn handle_method(State(context): State<ServerContext>, Path(method): Path<String>, body: Bytes) -> Vec<u8> {
+    let result = match Vec::<BytesArray>::decode(&body) {
+        Ok(inputs) => dispatch(&context, &method, inputs).await,
+        Err(_) => Err(MethodError(STATUS_GENERAL_ERROR)),
+    };
+
+    match result.and_then(|outputs| outputs.encode().map_err(general_error)) {
+        Ok(mut encoded_outputs) => {
+            let mut response = vec![STATUS_OK];
+            response.append(&mut encoded_outputs);
+            response
+        }
+        Err(MethodError(status)) => vec![status],
+    }
+}
+
+async fn dispatch(context: &ServerContext, method: &str, inputs: Vec<BytesArray>) -> MethodResult {
+    let state = &context.state;
+    match method {
+        "requestChallenge" => request_challenge(state, inputs),
+        "getToken" => get_token(Arc::clone(state), inputs).await,
+        "registerApiKey" => register_api_key(state, inputs),
+        "queryApiKeyStatus" => query_api_key_status(state, inputs),
+        "uploadMessageAndGetUids" => upload_message_and_get_uids(context, inputs),
+        "uploadReturnReceipt" => upload_return_receipt(state, inputs),
+        "downloadMessagesAndListAttachments" => download_messages_and_list_attachments(state, inputs),
+        "downloadMessageAndListAttachments" => download_message_and_list_attachments(state, inputs),
+        "deleteMessageAndAttachments" => delete_message_and_attachments(state, inputs),
+        "getAttachmentChunkDownloadUrls" => get_attachment_chunk_download_urls(context, inputs),
+        "getKeycloakData" => get_keycloak_data(state, inputs),
+        "createGroupBlob" => create_group_blob(state, inputs),
+        "getGroupBlob" => get_group_blob(state, inputs),
+        "updateGroupBlob" => update_group_blob(state, inputs),
+        "transferOpenSession" => transfer_open_session(state, inputs),
+        "transferRelay" => transfer_relay(state, inputs),
+        "transferFetch" => transfer_fetch(state, inputs),
+        "transferCloseSession" => transfer_close_session(state, inputs),
+        _ => Err(MethodError(STATUS_GENERAL_ERROR)),
+    }
+}
+
+fn request_challenge(state: &MockServerState, inputs: Vec<BytesArray>) -> MethodResult {
+    let identity: Vec<u8> = input(&inputs, 0)?;
+    let nonce: Vec<u8> = input(&inputs, 1)?;
+
+    let pending_challenge = PendingChallenge { challenge: random_bytes(32), nonce, server_nonce: random_bytes(16) };
+    let outputs = encode(vec![pending_challenge.challenge.encode(), pending_challenge.server_nonce.encode()]);
+    state.data.lock().unwrap().challenges.insert(identity, pending_challenge);
+
+    outputs
+}
+
+async fn get_token(state: Arc<MockServerState>, inputs: Vec<BytesArray>) -> MethodResult {
+    let identity: Vec<u8> = input(&inputs, 0)?;
+    let response: Vec<u8> = input(&inputs, 1)?;
+    let nonce: Vec<u8> = input(&inputs, 2)?;
+
+    let pending_challenge = state.data.lock().unwrap().challenges.remove(&identity).ok_or(MethodError(STATUS_GENERAL_ERROR))?;
+    if pending_challenge.nonce != nonce {
+        return Err(MethodError(STATUS_GENERAL_ERROR));
+    }
+
+    let mut challenge = pending_challenge.challenge;
+    challenge.extend_from_slice(&pending_challenge.nonce);
+    challenge.extend_from_slice(&pending_challenge.server_nonce);
+
+    // Signature verification is CPU bound and slow with big integers
+    let identity_to_check = identity.clone();
+    let is_valid = tokio::task::spawn_blocking(move || {
+        let cryptographic_identity = CryptographicIdentity::from_raw(&identity_to_check).map_err(general_error)?;
+        cryptographic_identity.check_challenge_response(&challenge, &response).map_err(general_error)
+    }).await.map_err(general_error)??;
+
+    if !is_valid {
+        return Err(MethodError(STATUS_GENERAL_ERROR));
+    }
+
+    let token = random_bytes(TOKEN_LENGTH);
+    let mut data = state.data.lock().unwrap();
+    let api_key = data.registered_api_keys.get(&identity).and_then(|api_key| data.api_keys.get(api_key)).cloned().unwrap_or(DEFAULT_API_KEY);
+    data.sessions.insert(token.clone(), identity);
+
+    encode(vec![token.encode(), api_key.status.encode(), api_key.permissions.encode(), api_key.expiration_timestamp.encode()])
+}
+
+fn register_api_key(state: &MockServerState, inputs: Vec<BytesArray>) -> MethodResult {
+    let identity = check_session(state, &inputs)?;
+    let api_key: String = input(&inputs, 2)?;
+
+    let mut data = state.data.lock().unwrap();
+    if !data.api_keys.contains_key(&api_key) {
+        return Err(MethodError(STATUS_INVALID_API_KEY));
+    }
+    data.registered_api_keys.insert(identity, api_key);
+
+    encode(vec![])
+}
+
+fn query_api_key_status(state: &MockServerState, inputs: Vec<BytesArray>) -> MethodResult {
+    let api_key: String = input(&inputs, 1)?;
+
+    let data = state.data.lock().unwrap();
+    let api_key = data.api_keys.get(&api_key).ok_or(MethodError(STATUS_INVALID_API_KEY))?;
+
+    encode(vec![api_key.status.encode(), api_key.permissions.encode(), api_key.expiration_timestamp.encode()])
+}
+
+fn upload_message_and_get_uids(context: &ServerContext, inputs: Vec<BytesArray>) -> MethodResult {
+    let to_identity: Vec<u8> = input(&inputs, 0)?;
+    let encoded_device_uids: Vec<BytesArray> = input(&inputs, 1)?;
+    let device_uids = encoded_device_uids.iter().map(|encoded| BytesArray::decode(encoded)).collect::<Result<Vec<Vec<u8>>, _>>().map_err(general_error)?;
+
+    // Attachments are optional, each of them is described by its number of chunks
+    let attachments = match inputs.get(5) {
+        Some(_) => {
+            let encoded_attachments: Vec<BytesArray> = input(&inputs, 5)?;
+            encoded_attachments.iter()
+                .map(|encoded| i64::decode(encoded).map_err(general_error))
+                .map(|chunk_count| Ok(StoredAttachment { chunk_count: usize::try_from(chunk_count?).map_err(general_error)?, url_signature: random_bytes(16), chunks: Default::default() }))
+                .collect::<Result<Vec<StoredAttachment>, MethodError>>()?
+        }
+        None => vec![],
+    };
+
+    let message = StoredMessage {
+        message_uid: random_bytes(UID_LENGTH),
+        to_identity,
+        device_uids,
+        wrapped_key: input(&inputs, 2)?,
+        encrypted_content: input(&inputs, 3)?,
+        is_application_message: input(&inputs, 4)?,
+        server_timestamp: now_timestamp(),
+        attachments,
+        deleted_for_devices: Default::default(),
+    };
+
+    let mut encoded_upload_urls = vec![];
+    for (attachment_number, attachment) in message.attachments.iter().enumerate() {
+        let urls = (0..attachment.chunk_count)
+            .map(|chunk_number| chunk_url(&context.base_url, &message.message_uid, attachment_number, chunk_number, &attachment.url_signature).encode())
+            .collect::<Result<Vec<BytesArray>, _>>()
+            .map_err(general_error)?;
+        encoded_upload_urls.push(urls.encode().map_err(general_error)?);
+    }
+
+    let notification = PushNotification::NewMessage {
+        identity: message.to_identity.clone(),
+        device_uids: message.device_uids.clone(),
+        message_uid: message.message_uid.clone(),
+        server_timestamp: message.server_timestamp,
+    };
+    let outputs = encode(vec![message.message_uid.encode(), encoded_upload_urls.encode()])?;
+
+    // A message uploaded again with the same nonce is answered the uids of the stored one
+    let upload_nonce: Option<Vec<u8>> = inputs.get(6).map(|_| input(&inputs, 6)).transpose()?;
+    let mut data = context.state.data.lock().unwrap();
+    if let Some(upload_nonce) = upload_nonce {
+        if let Some(stored_outputs) = data.upload_outputs.get(&upload_nonce) {
+            return Ok(stored_outputs.clone());
+        }
+        data.upload_outputs.insert(upload_nonce, outputs.clone());
+    }
+    data.messages.push(message);
+    drop(data);
+    context.state.notify(notification);
+
+    Ok(outputs)
+}
+
+/// Return receipts are not stored with the messages, they are pushed to the identity they are for
+fn upload_return_receipt(state: &MockServerState, inputs: Vec<BytesArray>) -> MethodResult {
+    let return_receipt = StoredReturnReceipt {
+        to_identity: input(&inputs, 0)?,
+        nonce: input(&inputs, 2)?,
+        encrypted_payload: input(&inputs, 3)?,
+        server_timestamp: now_timestamp(),
+    };
+
+    state.data.lock().unwrap().return_receipts.push(return_receipt.clone());
+    state.notify(PushNotification::ReturnReceipt(return_receipt));
+
+    Ok(vec![])
+}
+
+fn encode_message(message: &StoredMessage) -> Result<BytesArray, MethodError> {
+    let attachment_count = i64::try_from(message.attachments.len()).map_err(general_error)?;
+    encode(vec![
+        message.message_uid.encode(),
+        message.server_timestamp.encode(),
+        message.wrapped_key.encode(),
+        message.encrypted_content.encode(),
+        attachment_count.encode(),
+    ])?.encode().map_err(general_error)
+}
+
+fn download_messages_and_list_attachments(state: &MockServerState, inputs: Vec<BytesArray>) -> MethodResult {
+    let identity = check_session(state, &inputs)?;
+    let device_uid: Vec<u8> = input(&inputs, 2)?;
+
+    let data = state.data.lock().unwrap();
+    let encoded_messages = data.messages.iter()
+        .filter(|message| message.to_identity == identity && message.is_available_for(&device_uid))
+        .map(encode_message)
+        .collect::<Result<Vec<BytesArray>, MethodError>>()?;
+
+    encode(vec![encoded_messages.encode()])
+}
+
+/// Targeted download of a single message, answers an empty list when it is not (or no longer) available
+fn download_message_and_list_attachments(state: &MockServerState, inputs: Vec<BytesArray>) -> MethodResult {
+    let identity = check_session(state, &inputs)?;
+    let device_uid: Vec<u8> = input(&inputs, 2)?;
+    let message_uid: Vec<u8> = input(&inputs, 3)?;
+
+    let data = state.data.lock().unwrap();
+    let encoded_messages = data.messages.iter()
+        .filter(|message| message.to_identity == identity && message.message_uid == message_uid && message.is_available_for(&device_uid))
+        .map(encode_message)
+        .collect::<Result<Vec<BytesArray>, MethodError>>()?;
+
+    encode(vec![encoded_messages.encode()])
+}
+
+fn delete_message_and_attachments(state: &MockServerState, inputs: Vec<BytesArray>) -> MethodResult {
+    let identity = check_session(state, &inputs)?;
+    let device_uid: Vec<u8> = input(&inputs, 2)?;
+    let message_uid: Vec<u8> = input(&inputs, 3)?;
+
+    let mut data = state.data.lock().unwrap();
+    if let Some(message) = data.messages.iter_mut().find(|message| message.to_identity == identity && message.message_uid == message_uid) {
+        message.deleted_for_devices.insert(device_uid);
+    }
+
+    Ok(vec![])
+}
+
+fn get_attachment_chunk_download_urls(context: &ServerContext, inputs: Vec<BytesArray>) -> MethodResult {
+    let identity = check_session(&context.state, &inputs)?;
+    let device_uid: Vec<u8> = input(&inputs, 2)?;
+    let message_uid: Vec<u8> = input(&inputs, 3)?;
+    let attachment_number = usize::try_from(input::<i64>(&inputs, 4)?).map_err(general_error)?;
+
+    let data = context.state.data.lock().unwrap();
+    let message = data.messages.iter()
+        .find(|message| message.to_identity == identity && message.message_uid == message_uid && message.is_available_for(&device_uid))
+        .ok_or(MethodError(STATUS_GENERAL_ERROR))?;
+    let attachment = message.attachments.get(attachment_number).ok_or(MethodError(STATUS_GENERAL_ERROR))?;
+
+    let urls = (0..attachment.chunk_count)
+        .map(|chunk_number| chunk_url(&context.base_url, &message_uid, attachment_number, chunk_number, &attachment.url_signature).encode())
+        .collect::<Result<Vec<BytesArray>, _>>()
+        .map_err(general_error)?;
+
+    encode(vec![urls.encode()])
+}
+
+fn get_keycloak_data(state: &MockServerState, inputs: Vec<BytesArray>) -> MethodResult {
+    check_session(state, &inputs)?;
+
+    let data = state.data.lock().unwrap();
+    let keycloak_configuration = data.keycloak_configuration.as_ref().ok_or(MethodError(STATUS_GENERAL_ERROR))?;
+
+    encode(vec![
+        keycloak_configuration.server_url.encode(),
+        keycloak_configuration.client_id.encode(),
+        keycloak_configuration.client_secret.clone().unwrap_or_default().encode(),
+    ])
+}
+
+fn create_group_blob(state: &MockServerState, inputs: Vec<BytesArray>) -> MethodResult {
+    check_session(state, &inputs)?;
+    let group_uid: Vec<u8> = input(&inputs, 2)?;
+    let encrypted_blob: Vec<u8> = input(&inputs, 3)?;
+
+    let mut data = state.data.lock().unwrap();
+    if data.group_blobs.contains_key(&group_uid) {
+        return Err(MethodError(STATUS_GROUP_UID_ALREADY_USED));
+    }
+    data.group_blobs.insert(group_uid, StoredGroupBlob { encrypted_blob, version: 0 });
+
+    Ok(vec![])
+}
+
+fn get_group_blob(state: &MockServerState, inputs: Vec<BytesArray>) -> MethodResult {
+    check_session(state, &inputs)?;
+    let group_uid: Vec<u8> = input(&inputs, 2)?;
+
+    let data = state.data.lock().unwrap();
+    let group_blob = data.group_blobs.get(&group_uid).ok_or(MethodError(STATUS_DELETED_FROM_SERVER))?;
+
+    encode(vec![group_blob.encrypted_blob.encode(), group_blob.version.encode()])
+}
+
+/// The real server also checks the blob is signed by a group administrator, here only the version is checked
+fn update_group_blob(state: &MockServerState, inputs: Vec<BytesArray>) -> MethodResult {
+    check_session(state, &inputs)?;
+    let group_uid: Vec<u8> = input(&inputs, 2)?;
+    let encrypted_blob: Vec<u8> = input(&inputs, 3)?;
+    let version: i64 = input(&inputs, 4)?;
+
+    let mut data = state.data.lock().unwrap();
+    let group_blob = data.group_blobs.get_mut(&group_uid).ok_or(MethodError(STATUS_DELETED_FROM_SERVER))?;
+    if version != group_blob.version + 1 {
+        return Err(MethodError(STATUS_GROUP_BLOB_OUTDATED));
+    }
+    *group_blob = StoredGroupBlob { encrypted_blob, version };
+
+    Ok(vec![])
+}
+
+/// Opened by the source device, the target device joins with the returned session number
+fn transfer_open_session(state: &MockServerState, inputs: Vec<BytesArray>) -> MethodResult {
+    let source_identity = check_session(state, &inputs)?;
+
+    let mut data = state.data.lock().unwrap();
+    let session_number = loop {
+        let session_number = rand::random::<u32>() as i64 % 10i64.pow(TRANSFER_SESSION_NUMBER_DIGITS);
+        if !data.transfer_sessions.contains_key(&session_number) {
+            break session_number;
+        }
+    };
+    data.transfer_sessions.insert(session_number, StoredTransferSession { source_identity, ..Default::default() });
+
+    encode(vec![session_number.encode()])
+}
+
+/// The target device is not authenticated, the devices authenticate each other by comparing a code
+fn transfer_relay(state: &MockServerState, inputs: Vec<BytesArray>) -> MethodResult {
+    let session_number: i64 = input(&inputs, 0)?;
+    let to_source: bool = input(&inputs, 1)?;
+    let payload: Vec<u8> = input(&inputs, 2)?;
+
+    let mut data = state.data.lock().unwrap();
+    let session = data.transfer_sessions.get_mut(&session_number).ok_or(MethodError(STATUS_DELETED_FROM_SERVER))?;
+    match to_source {
+        true => session.to_source.push(payload),
+        false => session.to_target.push(payload),
+    }
+
+    Ok(vec![])
+}
+
+/// Removes and returns the payloads relayed to one of the devices
+fn transfer_fetch(state: &MockServerState, inputs: Vec<BytesArray>) -> MethodResult {
+    let session_number: i64 = input(&inputs, 0)?;
+    let to_source: bool = input(&inputs, 1)?;
+
+    let mut data = state.data.lock().unwrap();
+    let session = data.transfer_sessions.get_mut(&session_number).ok_or(MethodError(STATUS_DELETED_FROM_SERVER))?;
+    let payloads = match to_source {
+        true => std::mem::take(&mut session.to_source),
+        false => std::mem::take(&mut session.to_target),
+    };
+    let encoded_payloads = payloads.into_iter().map(|payload| payload.encode()).collect::<Result<Vec<BytesArray>, _>>().map_err(general_error)?;
+
+    encode(vec![encoded_payloads.encode()])
+}
+
+fn transfer_close_session(state: &MockServerState, inputs: Vec<BytesArray>) -> MethodResult {
+    let source_identity = check_session(state, &inputs)?;
+    let session_number: i64 = input(&inputs, 2)?;
+
+    let mut data = state.data.lock().unwrap();
+    if data.transfer_sessions.get(&session_number).is_some_and(|session| session.source_identity == source_identity) {
+        data.transfer_sessions.remove(&session_number);
+    }
+
+    Ok(vec![])
+}
@@ -0,0 +1,106 @@
+use std::sync::Arc;
+
+use axum::{extract::{ws::{Message, WebSocket}, State, WebSocketUpgrade}, response::Response};
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{state::{MockServerState, PushNotification, StoredReturnReceipt}, ServerContext};
+
+/// Messages sent by clients on the push WebSocket, binary values are hex encoded
+#[derive(Debug, Deserialize)]
+#[serde(tag = "action", rename_all = "camelCase", rename_all_fields = "camelCase")]
+pub enum ClientPushMessage {
+    Register { identity: String, token: String, device_uid: String },
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "action", rename_all = "camelCase", rename_all_fields = "camelCase")]
+pub enum ServerPushMessage {
+    Registered { identity: String },
+    Error { reason: String },
+    Message { identity: String, message_uid: String, server_timestamp: i64 },
+    ReturnReceipt { identity: String, nonce: String, encrypted_payload: String, server_timestamp: i64 },
+}
+
+impl From<&StoredReturnReceipt> for ServerPushMessage {
+    fn from(return_receipt: &StoredReturnReceipt) -> Self {
+        ServerPushMessage::ReturnReceipt {
+            identity: hex::encode(&return_receipt.to_identity),
+            nonce: hex::encode(&return_receipt.nonce),
+            encrypted_payload: hex::encode(&return_receipt.encrypted_payload),
+            server_timestamp: return_receipt.server_timestamp,
+        }
+    }
+}
+
+pub async fn handle_push_connection(ws: WebSocketUpgrade, State(context): State<ServerContext>) -> Response {
+    ws.on_upgrade(move |socket| push_session(socket, context.state))
+}
+
+fn register(state: &MockServerState, message: &str) -> Result<(Vec<u8>, Vec<u8>), String> {
+    let ClientPushMessage::Register { identity, token, device_uid } = serde_json::from_str(message).map_err(|_| "malformedMessage".to_string())?;
+    let identity = hex::decode(identity).map_err(|_| "malformedMessage".to_string())?;
+    let token = hex::decode(token).map_err(|_| "malformedMessage".to_string())?;
+    let device_uid = hex::decode(device_uid).map_err(|_| "malformedMessage".to_string())?;
+
+    if !state.is_session_valid(&identity, &token) {
+        return Err("invalidSession".to_string());
+    }
+
+    Ok((identity, device_uid))
+}
+
+async fn push_session(socket: WebSocket, state: Arc<MockServerState>) {
+    let (mut sender, mut receiver) = socket.split();
+    let mut notifications = state.push_notifications.subscribe();
+    // Identities and devices registered on this connection
+    let mut registrations: Vec<(Vec<u8>, Vec<u8>)> = vec![];
+
+    loop {
+        let answers = tokio::select! {
+            message = receiver.next() => match message {
+                Some(Ok(Message::Text(text))) => match register(&state, text.as_str()) {
+                    Ok((identity, device_uid)) => {
+                        let mut answers = vec![ServerPushMessage::Registered { identity: hex::encode(&identity) }];
+                        answers.extend(state.take_return_receipts(&identity).iter().map(ServerPushMessage::from));
+                        registrations.push((identity, device_uid));
+                        answers
+                    }
+                    Err(reason) => vec![ServerPushMessage::Error { reason }],
+                },
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => vec![],
+            },
+            notification = notifications.recv() => match notification {
+                Ok(PushNotification::NewMessage { identity, device_uids, message_uid, server_timestamp }) => {
+                    let is_registered = registrations.iter().any(|(registered_identity, registered_device_uid)| {
+                        *registered_identity == identity && (device_uids.is_empty() || device_uids.contains(registered_device_uid))
+                    });
+                    is_registered.then(|| ServerPushMessage::Message {
+                        identity: hex::encode(&identity),
+                        message_uid: hex::encode(&message_uid),
+                        server_timestamp,
+                    }).into_iter().collect()
+                }
+                Ok(PushNotification::ReturnReceipt(return_receipt)) => {
+                    let is_registered = registrations.iter().any(|(registered_identity, _)| *registered_identity == return_receipt.to_identity);
+                    if is_registered {
+                        state.remove_return_receipt(&return_receipt);
+                    }
+                    is_registered.then(|| ServerPushMessage::from(&return_receipt)).into_iter().collect()
+                }
+                Ok(PushNotification::DisconnectAll) => break,
+                Err(RecvError::Lagged(_)) => vec![],
+                Err(RecvError::Closed) => break,
+            },
+        };
+
+        for answer in answers {
+            let Ok(serialized_answer) = serde_json::to_string(&answer) else { continue };
+            if sender.send(Message::Text(serialized_answer.into())).await.is_err() {
+                return;
+            }
+        }
+    }
+}
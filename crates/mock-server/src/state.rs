@@ -0,0 +1,190 @@
+use std::{collections::{HashMap, HashSet}, sync::Mutex, time::{SystemTime, UNIX_EPOCH}};
+
+use olvid_core::encoding::BytesArray;
+use rand::RngCore;
+use tokio::sync::broadcast;
+
+pub const UID_LENGTH: usize = 16;
+pub const TOKEN_LENGTH: usize = 32;
+
+#[derive(Debug, Clone)]
+pub struct PendingChallenge {
+    pub challenge: Vec<u8>,
+    pub nonce: Vec<u8>,
+    pub server_nonce: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct StoredAttachment {
+    pub chunk_count: usize,
+    /// Random value embedded in the chunk URLs, it plays the role of the signature of real signed URLs
+    pub url_signature: Vec<u8>,
+    pub chunks: HashMap<usize, Vec<u8>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct StoredMessage {
+    pub message_uid: Vec<u8>,
+    pub to_identity: Vec<u8>,
+    /// Empty when the message is for every device of `to_identity`
+    pub device_uids: Vec<Vec<u8>>,
+    pub wrapped_key: Vec<u8>,
+    pub encrypted_content: Vec<u8>,
+    pub is_application_message: bool,
+    pub server_timestamp: i64,
+    pub attachments: Vec<StoredAttachment>,
+    pub deleted_for_devices: HashSet<Vec<u8>>,
+}
+
+impl StoredMessage {
+    pub fn is_available_for(&self, device_uid: &[u8]) -> bool {
+        let is_recipient = self.device_uids.is_empty() || self.device_uids.iter().any(|uid| uid == device_uid);
+        is_recipient && !self.deleted_for_devices.contains(device_uid)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredReturnReceipt {
+    pub to_identity: Vec<u8>,
+    pub nonce: Vec<u8>,
+    pub encrypted_payload: Vec<u8>,
+    pub server_timestamp: i64,
+}
+
+/// Encrypted state of a group, only its version is readable by the server
+#[derive(Debug, Clone)]
+pub struct StoredGroupBlob {
+    pub encrypted_blob: Vec<u8>,
+    pub version: i64,
+}
+
+/// Relay between the two devices of a profile transfer, identified by the session number displayed on the source
+#[derive(Debug, Clone, Default)]
+pub struct StoredTransferSession {
+    pub source_identity: Vec<u8>,
+    pub to_source: Vec<Vec<u8>>,
+    pub to_target: Vec<Vec<u8>>,
+}
+
+/// API key known by the server, its status is one of the `API_KEY_STATUS_*` values of the engine
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredApiKey {
+    pub status: i64,
+    pub permissions: i64,
+    /// Zero when the key never expires
+    pub expiration_timestamp: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct KeycloakConfiguration {
+    pub server_url: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PushNotification {
+    NewMessage { identity: Vec<u8>, device_uids: Vec<Vec<u8>>, message_uid: Vec<u8>, server_timestamp: i64 },
+    ReturnReceipt(StoredReturnReceipt),
+    /// Closes every push connection, simulating a network failure
+    DisconnectAll,
+}
+
+#[derive(Default)]
+pub struct MockServerData {
+    pub challenges: HashMap<Vec<u8>, PendingChallenge>,
+    /// Session tokens and the identity they were issued to
+    pub sessions: HashMap<Vec<u8>, Vec<u8>>,
+    pub messages: Vec<StoredMessage>,
+    /// Answer to the upload of each message uploaded with a nonce, by nonce
+    pub upload_outputs: HashMap<Vec<u8>, Vec<BytesArray>>,
+    /// Return receipts not pushed yet, they are pushed as soon as their recipient registers
+    pub return_receipts: Vec<StoredReturnReceipt>,
+    /// Group blobs by group uid
+    pub group_blobs: HashMap<Vec<u8>, StoredGroupBlob>,
+    /// Profile transfer sessions by session number
+    pub transfer_sessions: HashMap<i64, StoredTransferSession>,
+    pub keycloak_configuration: Option<KeycloakConfiguration>,
+    pub api_keys: HashMap<String, StoredApiKey>,
+    /// API key registered by each identity, identities without one get a valid status
+    pub registered_api_keys: HashMap<Vec<u8>, String>,
+    /// Number of chunk uploads and downloads still accepted, unlimited when `None`
+    pub chunk_transfer_budget: Option<usize>,
+}
+
+/// Whole state of the mock server, everything is kept in memory and lost on shutdown
+pub struct MockServerState {
+    pub data: Mutex<MockServerData>,
+    pub push_notifications: broadcast::Sender<PushNotification>,
+}
+
+impl MockServerState {
+    pub fn new() -> Self {
+        let (push_notifications, _) = broadcast::channel(256);
+        Self {
+            data: Mutex::new(MockServerData::default()),
+            push_notifications,
+        }
+    }
+
+    pub fn is_session_valid(&self, identity: &[u8], token: &[u8]) -> bool {
+        let data = self.data.lock().unwrap();
+        data.sessions.get(token).is_some_and(|session_identity| session_identity == identity)
+    }
+
+    /// Drops every session token, simulating their expiration
+    pub fn invalidate_sessions(&self) {
+        self.data.lock().unwrap().sessions.clear();
+    }
+
+    pub fn disconnect_push_clients(&self) {
+        self.notify(PushNotification::DisconnectAll);
+    }
+
+    /// Makes chunk transfers fail once `budget` more of them went through, simulating a lost connection
+    pub fn limit_chunk_transfers(&self, budget: Option<usize>) {
+        self.data.lock().unwrap().chunk_transfer_budget = budget;
+    }
+
+    /// Removes and returns the return receipts waiting for `identity`
+    pub fn take_return_receipts(&self, identity: &[u8]) -> Vec<StoredReturnReceipt> {
+        let mut data = self.data.lock().unwrap();
+        let (taken, kept) = std::mem::take(&mut data.return_receipts).into_iter().partition(|return_receipt| return_receipt.to_identity == identity);
+        data.return_receipts = kept;
+        taken
+    }
+
+    pub fn remove_return_receipt(&self, return_receipt: &StoredReturnReceipt) {
+        self.data.lock().unwrap().return_receipts.retain(|stored_return_receipt| stored_return_receipt != return_receipt);
+    }
+
+    pub fn set_keycloak_configuration(&self, keycloak_configuration: KeycloakConfiguration) {
+        self.data.lock().unwrap().keycloak_configuration = Some(keycloak_configuration);
+    }
+
+    /// Adds or replaces an API key, sessions opened afterwards get its new status
+    pub fn set_api_key(&self, api_key: &str, stored_api_key: StoredApiKey) {
+        self.data.lock().unwrap().api_keys.insert(api_key.to_owned(), stored_api_key);
+    }
+
+    pub fn notify(&self, notification: PushNotification) {
+        // Nobody listening is not an error
+        let _ = self.push_notifications.send(notification);
+    }
+}
+
+impl Default for MockServerState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn random_bytes(length: usize) -> Vec<u8> {
+    let mut bytes = vec![0; length];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes
+}
+
+pub fn now_timestamp() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|duration| duration.as_millis() as i64).unwrap_or_default()
+}
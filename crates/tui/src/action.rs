@@ -20,7 +20,9 @@ pub enum Action {
     FocusPrev,
     Focus,
     Unfocus,
-    Navigation(NavigationAction)
+    Navigation(NavigationAction),
+    /// Push connection of the current identity went up (true) or down (false)
+    NetworkStatusChanged(bool),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Display, Serialize, Deserialize)]
@@ -1,9 +1,9 @@
 use color_eyre::Result;
 use crossterm::event::KeyEvent;
-use engine::Engine;
+use engine::{events::EngineEvent, network::PushConnectionStatus, Engine};
 use ratatui::prelude::Rect;
 use serde::{Deserialize, Serialize};
-use tokio::sync::mpsc;
+use tokio::sync::{broadcast::{self, error::RecvError}, mpsc};
 use tracing::{debug, info};
 
 use crate::{
@@ -32,6 +32,7 @@ pub struct App {
     action_tx: mpsc::UnboundedSender<Action>,
     action_rx: mpsc::UnboundedReceiver<Action>,
     state: State,
+    engine_events: broadcast::Receiver<EngineEvent>,
 }
 
 #[derive(Default, Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -48,6 +49,7 @@ impl App {
             .await
             .expect("Failed to init olvid engine");
 
+        let engine_events = olvid_engine.subscribe_to_events();
         let state = State::init(olvid_engine).await?;
 
         Ok(Self {
@@ -62,6 +64,7 @@ impl App {
             action_tx,
             action_rx,
             state,
+            engine_events,
         })
     }
 
@@ -124,7 +127,11 @@ impl App {
     }
 
     async fn handle_events(&mut self, tui: &mut Tui) -> Result<()> {
-        let Some(event) = tui.next_event().await else {
+        let event = tokio::select! {
+            event = tui.next_event() => event,
+            engine_event = self.engine_events.recv() => return self.handle_engine_event(engine_event).await,
+        };
+        let Some(event) = event else {
             return Ok(());
         };
         let action_tx = self.action_tx.clone();
@@ -145,6 +152,59 @@ impl App {
         Ok(())
     }
 
+    async fn handle_engine_event(&mut self, engine_event: Result<EngineEvent, RecvError>) -> Result<()> {
+        let engine_event = match engine_event {
+            Ok(engine_event) => engine_event,
+            // Missed events may have changed anything, reload what the state caches
+            Err(RecvError::Lagged(_)) => {
+                self.state.refresh_owned_identities().await?;
+                self.action_tx.send(Action::Update)?;
+                return Ok(());
+            }
+            Err(RecvError::Closed) => return Ok(()),
+        };
+
+        match engine_event {
+            EngineEvent::OwnedIdentityCreated { .. } | EngineEvent::OwnedIdentityUpdated { .. } | EngineEvent::OwnedIdentityDeleted { .. } => {
+                self.state.refresh_owned_identities().await?;
+                self.action_tx.send(Action::Update)?;
+            }
+            EngineEvent::NetworkStatusChanged { owned_identity, status } => {
+                let is_current_identity = self.state.current_identity.as_ref()
+                    .is_some_and(|current_identity| current_identity.identity.get_identity() == owned_identity);
+                if is_current_identity {
+                    self.action_tx.send(Action::NetworkStatusChanged(status == PushConnectionStatus::Connected))?;
+                }
+            }
+            // The status itself comes with the owned identity update
+            EngineEvent::ApiKeyExpired { .. } | EngineEvent::ApiKeyLicenseExhausted { .. } => self.action_tx.send(Action::Update)?,
+            EngineEvent::ContactAdded { .. }
+            | EngineEvent::ContactUpdated { .. }
+            | EngineEvent::ContactDeleted { .. }
+            | EngineEvent::ContactBlocked { .. }
+            | EngineEvent::ContactUnblocked { .. }
+            | EngineEvent::IntroductionUpdated { .. }
+            | EngineEvent::TrustEstablishmentUpdated { .. }
+            | EngineEvent::MessageReceived { .. }
+            | EngineEvent::MessageDeliveryStateChanged { .. }
+            | EngineEvent::MessageStatusChanged { .. }
+            | EngineEvent::MessageWiped { .. }
+            | EngineEvent::MessageDeleted { .. }
+            | EngineEvent::MessageEdited { .. }
+            | EngineEvent::MessageRemotelyDeleted { .. }
+            | EngineEvent::MessageReactionsChanged { .. }
+            | EngineEvent::DiscussionSettingsUpdated { .. }
+            | EngineEvent::DiscussionDeleted { .. }
+            | EngineEvent::GroupUpdated { .. }
+            | EngineEvent::GroupRemoved { .. }
+            | EngineEvent::AttachmentProgress { .. }
+            | EngineEvent::AttachmentStatusChanged { .. }
+            | EngineEvent::ProtocolFinished { .. } => self.action_tx.send(Action::Update)?,
+            EngineEvent::InboxMessageStored { .. } => {}
+        }
+        Ok(())
+    }
+
     fn handle_key_event(&mut self, key: KeyEvent) -> Result<()> {
         let action_tx = self.action_tx.clone();
         let Some(keymap) = self.config.keybindings.get(&self.mode) else {
@@ -186,6 +246,7 @@ impl App {
                 Action::Resize(w, h) => self.handle_resize(tui, w, h)?,
                 Action::Render => self.render(tui)?,
                 Action::Navigation(ref nav_action) => self.handle_navigation(nav_action.clone())?,
+                Action::NetworkStatusChanged(connected) => self.state.network_connected = connected,
                 _ => {}
             }
 
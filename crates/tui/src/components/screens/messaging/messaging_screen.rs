@@ -49,7 +49,7 @@ impl Component for MessagingScreen {
         let horizontal = Layout::horizontal([Constraint::Length(50)]).flex(Flex::SpaceAround).split(area);
         let test = state.current_identity.as_ref().unwrap();
         
-        frame.render_widget(Paragraph::new(test.identity_details.format_display_name()), horizontal[0]);
+        frame.render_widget(Paragraph::new(test.display_name.as_str()), horizontal[0]);
 
         Ok(())
     }
@@ -5,18 +5,26 @@ pub struct State {
     // pub input_mode: InputMode,
     pub current_identity: Option<ObvIdentity>,
     pub owned_identities: Vec<ObvIdentity>,
-    pub olvid_engine: Engine
+    pub olvid_engine: Engine,
+    pub network_connected: bool,
 }
 
 impl State {
     pub async fn init(olvid_engine: Engine) -> Result<Self> {
         let all_owned_identities = olvid_engine.get_all_owned_identities().await?;
         Ok(Self {
+            current_identity: olvid_engine.get_current_owned_identity().await?,
             olvid_engine,
-            current_identity: all_owned_identities.get(0).map(|owned_identity| owned_identity.to_owned()),
             owned_identities: all_owned_identities,
+            network_connected: false,
         })
     }
+
+    pub async fn refresh_owned_identities(&mut self) -> Result<()> {
+        self.owned_identities = self.olvid_engine.get_all_owned_identities().await?;
+        self.current_identity = self.olvid_engine.get_current_owned_identity().await?;
+        Ok(())
+    }
 }
 
 #[derive(Default, PartialEq)]